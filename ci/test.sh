@@ -3,4 +3,21 @@ set -e
 
 cargo fmt --manifest-path=kernel-rs/Cargo.toml -- --check -l
 cargo clippy --manifest-path=kernel-rs/Cargo.toml
+cargo fmt --manifest-path=kernel-rs-lib/Cargo.toml -- --check -l
+# kernel-rs-lib runs on the host, not on the kernel target of .cargo/config.toml.
+HOST=$(rustc -vV | sed -n 's/^host: //p')
+cargo clippy --manifest-path=kernel-rs-lib/Cargo.toml --all-targets --all-features --target "$HOST"
+cargo test --manifest-path=kernel-rs-lib/Cargo.toml --all-features --target "$HOST"
+cargo miri test --manifest-path=kernel-rs-lib/Cargo.toml --target "$HOST"
+cargo fmt --manifest-path=mkfs/Cargo.toml -- --check -l
+cargo clippy --manifest-path=mkfs/Cargo.toml --all-targets --target "$HOST"
+cargo test --manifest-path=mkfs/Cargo.toml --target "$HOST"
+cargo fmt --manifest-path=usys/Cargo.toml -- --check -l
+cargo clippy --manifest-path=usys/Cargo.toml --target "$HOST"
+# Run each fuzz target briefly on the host. cargo-fuzz needs a nightly toolchain, as pinned.
+for dir in kernel-rs-lib mkfs; do
+  for target in $(cd "$dir" && cargo fuzz list); do
+    (cd "$dir" && cargo fuzz run --target "$HOST" "$target" -- -max_total_time=30)
+  done
+done
 make qemu USERTEST=yes RUST_MODE=release
@@ -0,0 +1,188 @@
+//! Runs random sequences of lookups, clones, and frees on each arena, and checks them against a
+//! model that counts the handles of each key.
+//!
+//! Run with `cargo fuzz run arena` in the `kernel-rs-lib` directory.
+
+#![no_main]
+#![allow(incomplete_features)]
+#![feature(generic_associated_types)]
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use arbitrary::Arbitrary;
+use kernel_rs_lib::arena::{
+    Arena, ArenaKey, ArenaObject, ArenaRc, ArrayArena, MruArena, ShardedArena,
+};
+use kernel_rs_lib::lock::RawLock;
+use kernel_rs_lib::strong_pin::StrongPin;
+use libfuzzer_sys::fuzz_target;
+
+/// A lock for a single thread, which checks that the arena never acquires it twice.
+struct RawFlagLock {
+    locked: AtomicBool,
+}
+
+impl RawFlagLock {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+        }
+    }
+}
+
+impl RawLock for RawFlagLock {
+    fn acquire(&self) {
+        assert!(!self.locked.swap(true, Ordering::Acquire), "locked twice");
+    }
+
+    fn release(&self) {
+        assert!(self.locked.swap(false, Ordering::Release), "not locked");
+    }
+}
+
+#[derive(Default)]
+struct Obj {
+    key: usize,
+}
+
+impl ArenaObject for Obj {
+    type Ctx<'a, 'b: 'a> = &'a Cell<usize>;
+
+    fn finalize<'a, 'b: 'a>(&mut self, finalized: Self::Ctx<'a, 'b>) {
+        finalized.set(finalized.get() + 1);
+    }
+}
+
+impl ArenaKey for Obj {
+    fn key_hash(&self) -> usize {
+        self.key
+    }
+}
+
+/// Few entries and twice as many keys, so that the arenas often run full and reuse entries.
+const CAPACITY: usize = 4;
+const SHARDS: usize = 2;
+const KEYS: usize = 2 * CAPACITY;
+
+type Array = ArrayArena<Obj, RawFlagLock, CAPACITY>;
+type Mru = MruArena<Obj, RawFlagLock, CAPACITY>;
+type Sharded = ShardedArena<Obj, RawFlagLock, CAPACITY, SHARDS>;
+
+#[derive(Arbitrary, Clone, Copy, Debug)]
+enum Op {
+    /// Looks up the object of a key, or allocates one.
+    Get(u8),
+    /// Clones a handle, chosen by its index among the live ones.
+    Clone(u8),
+    /// Frees a handle, chosen by its index among the live ones.
+    Free(u8),
+}
+
+/// Runs `ops` on `arena`, looking up objects with `get`.
+fn run<A: Arena<Data = Obj>>(
+    arena: StrongPin<'_, A>,
+    ops: &[Op],
+    get: impl Fn(StrongPin<'_, A>, usize) -> Option<ArenaRc<A>>,
+) {
+    let finalized = Cell::new(0);
+    let mut rcs = Vec::new();
+    // The number of handles of each key, and the address of its object.
+    let mut model = HashMap::new();
+
+    for &op in ops {
+        match op {
+            Op::Get(key) => {
+                let key = key as usize % KEYS;
+                match get(arena, key) {
+                    Some(rc) => {
+                        assert_eq!(rc.key, key);
+                        let addr = &*rc as *const Obj;
+                        let (count, old) = model.entry(key).or_insert((0, addr));
+                        assert_eq!(*old, addr, "two objects of one key");
+                        *count += 1;
+                        rcs.push(rc);
+                    }
+                    None => {
+                        assert!(!model.contains_key(&key), "live object not found");
+                        assert_eq!(model.len(), CAPACITY, "free entry not found");
+                    }
+                }
+            }
+            Op::Clone(i) if !rcs.is_empty() => {
+                let rc = rcs[i as usize % rcs.len()].clone();
+                model.get_mut(&rc.key).unwrap().0 += 1;
+                rcs.push(rc);
+            }
+            Op::Free(i) if !rcs.is_empty() => {
+                let i = i as usize % rcs.len();
+                free(&mut rcs, &mut model, i, &finalized);
+            }
+            _ => (),
+        }
+
+        // Handles of one key share its object, and objects of different keys do not.
+        for rc in &rcs {
+            assert_eq!(model[&rc.key].1, &**rc as *const Obj);
+        }
+    }
+
+    while !rcs.is_empty() {
+        free(&mut rcs, &mut model, 0, &finalized);
+    }
+}
+
+/// Frees the `i`th handle, which finalizes its object if it is the last handle of its key.
+fn free<A: Arena<Data = Obj>>(
+    rcs: &mut Vec<ArenaRc<A>>,
+    model: &mut HashMap<usize, (usize, *const Obj)>,
+    i: usize,
+    finalized: &Cell<usize>,
+) {
+    let rc = rcs.swap_remove(i);
+    let key = rc.key;
+    let before = finalized.get();
+    rc.free(finalized);
+    let count = &mut model.get_mut(&key).unwrap().0;
+    *count -= 1;
+    let last = *count == 0;
+    if last {
+        let _ = model.remove(&key);
+    }
+    assert_eq!(
+        finalized.get() - before,
+        last as usize,
+        "wrong finalization"
+    );
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let array = Box::pin(Array::new(RawFlagLock::new()));
+    // SAFETY: the arena is not mutated until it is dropped.
+    run(
+        unsafe { StrongPin::new_unchecked(array.as_ref().get_ref()) },
+        &ops,
+        |arena, key| arena.find_or_alloc(|obj| obj.key == key, |obj| obj.key = key),
+    );
+
+    let mut mru = Box::pin(unsafe { Mru::new(RawFlagLock::new()) });
+    mru.as_mut().init();
+    run(
+        unsafe { StrongPin::new_unchecked(mru.as_ref().get_ref()) },
+        &ops,
+        |arena, key| arena.find_or_alloc(|obj| obj.key == key, |obj| obj.key = key),
+    );
+
+    let mut sharded: Pin<Box<Sharded>> =
+        Box::pin(unsafe { Sharded::new([RawFlagLock::new(), RawFlagLock::new()]) });
+    sharded.as_mut().init();
+    let sharded = unsafe { StrongPin::new_unchecked(sharded.as_ref().get_ref()) };
+    run(sharded, &ops, |arena, key| {
+        arena.find_or_alloc(|obj| obj.key == key, |obj| obj.key = key)
+    });
+    run(sharded, &ops, |arena, key| {
+        arena.find_or_alloc_hashed(key, |obj| obj.key == key, |obj| obj.key = key)
+    });
+});
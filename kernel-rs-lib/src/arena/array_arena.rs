@@ -5,17 +5,15 @@ use core::{marker::PhantomPinned, ptr::NonNull};
 use array_macro::array;
 use pin_project::pin_project;
 
-use super::{Arena, ArenaObject, ArenaRc};
+use super::{Arena, ArenaObject, ArenaRc, Locked};
 use crate::{
-    lock::{SpinLock, SpinLockGuard},
-    util::{
-        static_arc::StaticArc,
-        strong_pin::{StrongPin, StrongPinMut},
-    },
+    lock::RawLock,
+    static_arc::StaticArc,
+    strong_pin::{StrongPin, StrongPinMut},
 };
 
-pub struct ArrayArena<T, const CAPACITY: usize> {
-    inner: SpinLock<ArrayArenaInner<T, CAPACITY>>,
+pub struct ArrayArena<T, R, const CAPACITY: usize> {
+    inner: Locked<R, ArrayArenaInner<T, CAPACITY>>,
 }
 
 /// A homogeneous memory allocator equipped with reference counts.
@@ -27,22 +25,23 @@ pub struct ArrayArenaInner<T, const CAPACITY: usize> {
     _marker: PhantomPinned,
 }
 
-impl<T, const CAPACITY: usize> ArrayArena<T, CAPACITY> {
+impl<T, R, const CAPACITY: usize> ArrayArena<T, R, CAPACITY> {
+    /// Returns an arena of default values, protected by `lock`.
     #[allow(clippy::new_ret_no_self)]
-    pub const fn new<D: Default>(name: &'static str) -> ArrayArena<D, CAPACITY> {
+    pub const fn new<D: Default>(lock: R) -> ArrayArena<D, R, CAPACITY> {
         let inner: ArrayArenaInner<D, CAPACITY> = ArrayArenaInner {
             entries: array![_ => StaticArc::new(Default::default()); CAPACITY],
             _marker: PhantomPinned,
         };
         ArrayArena {
-            inner: SpinLock::new(name, inner),
+            inner: Locked::new(lock, inner),
         }
     }
 
     #[allow(clippy::needless_lifetimes)]
     fn inner<'s>(
         self: StrongPin<'s, Self>,
-    ) -> StrongPin<'s, SpinLock<ArrayArenaInner<T, CAPACITY>>> {
+    ) -> StrongPin<'s, Locked<R, ArrayArenaInner<T, CAPACITY>>> {
         unsafe { StrongPin::new_unchecked(&(*self.ptr()).inner) }
     }
 }
@@ -55,18 +54,17 @@ impl<T, const CAPACITY: usize> ArrayArenaInner<T, CAPACITY> {
     }
 }
 
-impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
-    for ArrayArena<T, CAPACITY>
+impl<T: 'static + ArenaObject + Unpin + Send, R: RawLock, const CAPACITY: usize> Arena
+    for ArrayArena<T, R, CAPACITY>
 {
     type Data = T;
-    type Guard<'s> = SpinLockGuard<'s, ArrayArenaInner<T, CAPACITY>>;
 
     fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
         self: StrongPin<'_, Self>,
         c: C,
         n: N,
     ) -> Option<ArenaRc<Self>> {
-        let mut guard = self.inner().strong_pinned_lock();
+        let mut guard = self.inner().lock();
         let this = guard.get_strong_pinned_mut();
 
         let mut empty: Option<NonNull<StaticArc<T>>> = None;
@@ -91,7 +89,7 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
     }
 
     fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>> {
-        let mut guard = self.inner().strong_pinned_lock();
+        let mut guard = self.inner().lock();
         let this = guard.get_strong_pinned_mut();
 
         for mut entry in this.entries().iter_mut() {
@@ -3,26 +3,31 @@
 //! Includes the `Arena` trait, which represents a type that can be used as an arena.
 //! For types that `impl Arena`, you can allocate a thread safe `Rc` (reference counted pointer) from it.
 //!
-//! This module also includes pre-built arenas, such as `ArrayArena`(array based arena) or `MruArena`(list based arena).
+//! This module also includes pre-built arenas, such as `ArrayArena`(array based arena), `MruArena`(list based arena),
+//! or `ShardedArena`(hash-sharded lists based arena).
+//! They are generic over the `RawLock` that protects their entries.
 
+use core::cell::UnsafeCell;
 use core::mem::ManuallyDrop;
 use core::ops::Deref;
+use core::pin::Pin;
 
-use crate::util::static_arc::Ref;
-use crate::util::strong_pin::StrongPin;
+use crate::lock::RawLock;
+use crate::static_arc::Ref;
+use crate::strong_pin::{StrongPin, StrongPinMut};
 
 mod array_arena;
 mod mru_arena;
+mod sharded_arena;
 
 pub use array_arena::ArrayArena;
 pub use mru_arena::MruArena;
+pub use sharded_arena::{ArenaKey, ShardedArena};
 
 /// A homogeneous memory allocator. Provides `Rc<Arena>` to the outside.
 pub trait Arena: Sized + Sync {
     /// The value type of the allocator.
     type Data: ArenaObject;
-    /// The guard type for arena.
-    type Guard<'s>;
 
     /// Looks for an `Rc` that already contains the data, and clone it if exists. Otherwise, we allocate a new `Rc`.
     /// * Uses `c` to check if the data is the one we are looking for.
@@ -122,3 +127,54 @@ impl<A: Arena> Drop for ArenaRc<A> {
         panic!();
     }
 }
+
+/// The entries of an arena, protected by a raw lock of type `R`.
+struct Locked<R, T> {
+    lock: R,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: `lock` serializes the accesses to `data`.
+unsafe impl<R: RawLock, T: Send> Sync for Locked<R, T> {}
+
+/// Holds the lock of a `Locked` until it drops.
+struct LockedGuard<'s, R: RawLock, T> {
+    locked: &'s Locked<R, T>,
+}
+
+impl<R, T> Locked<R, T> {
+    const fn new(lock: R, data: T) -> Self {
+        Self {
+            lock,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut T> {
+        // SAFETY: for `T: !Unpin`, we only provide pinned references and don't move `T`.
+        unsafe { Pin::new_unchecked(&mut *self.data.get()) }
+    }
+}
+
+impl<R: RawLock, T> Locked<R, T> {
+    #[allow(clippy::needless_lifetimes)]
+    fn lock<'s>(self: StrongPin<'s, Self>) -> LockedGuard<'s, R, T> {
+        self.lock.acquire();
+        LockedGuard {
+            locked: self.as_pin().get_ref(),
+        }
+    }
+}
+
+impl<R: RawLock, T> LockedGuard<'_, R, T> {
+    fn get_strong_pinned_mut(&mut self) -> StrongPinMut<'_, T> {
+        // SAFETY: the pointer is valid, and the lock makes the `StrongPinMut` unique.
+        unsafe { StrongPinMut::new_unchecked(self.locked.data.get()) }
+    }
+}
+
+impl<R: RawLock, T> Drop for LockedGuard<'_, R, T> {
+    fn drop(&mut self) {
+        self.locked.lock.release();
+    }
+}
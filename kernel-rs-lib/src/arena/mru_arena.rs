@@ -8,17 +8,17 @@ use core::ptr::NonNull;
 use array_macro::array;
 use pin_project::pin_project;
 
-use super::{Arena, ArenaObject, ArenaRc};
-use crate::util::strong_pin::StrongPin;
+use super::{Arena, ArenaObject, ArenaRc, Locked};
 use crate::{
-    lock::{SpinLock, SpinLockGuard},
-    util::intrusive_list::{List, ListEntry, ListNode},
-    util::pinned_array::IterPinMut,
-    util::{static_arc::StaticArc, strong_pin::StrongPinMut},
+    intrusive_list::{List, ListEntry, ListNode},
+    lock::RawLock,
+    pinned_array::IterPinMut,
+    static_arc::StaticArc,
+    strong_pin::{StrongPin, StrongPinMut},
 };
 
-pub struct MruArena<T, const CAPACITY: usize> {
-    inner: SpinLock<MruArenaInner<T, CAPACITY>>,
+pub struct MruArena<T, R, const CAPACITY: usize> {
+    inner: Locked<R, MruArenaInner<T, CAPACITY>>,
 }
 
 #[pin_project]
@@ -31,6 +31,12 @@ pub struct MruEntry<T> {
 }
 
 /// A homogeneous memory allocator equipped with reference counts.
+///
+/// # Safety
+///
+/// `list` links exactly the entries of `entries`. The list only orders the entries: the pointers
+/// that it yields are used only to find the index of an entry, and the entry itself is accessed
+/// through `entries`, so that the access is derived from the `StrongPinMut` of the arena.
 #[pin_project]
 pub struct MruArenaInner<T, const CAPACITY: usize> {
     #[pin]
@@ -45,12 +51,9 @@ unsafe impl<T: Send, const CAPACITY: usize> Send for MruArenaInner<T, CAPACITY>
 impl<T> MruEntry<T> {
     // TODO(https://github.com/kaist-cp/rv6/issues/369)
     // A workarond for https://github.com/Gilnaa/memoffset/issues/49.
-    // Assumes `list_entry` is located at the beginning of `MruEntry`
-    // and `data` is located at `mem::size_of::<ListEntry>()`.
-    const DATA_OFFSET: usize = mem::size_of::<ListEntry>();
+    // Assumes `list_entry` is located at the beginning of `MruEntry`.
     const LIST_ENTRY_OFFSET: usize = 0;
 
-    // const DATA_OFFSET: usize = offset_of!(MruEntry<T>, data);
     // const LIST_ENTRY_OFFSET: usize = offset_of!(MruEntry<T>, list_entry);
 
     pub const fn new(data: T) -> Self {
@@ -59,12 +62,6 @@ impl<T> MruEntry<T> {
             data: StaticArc::new(data),
         }
     }
-
-    #[allow(clippy::needless_lifetimes)]
-    fn data<'s>(self: StrongPinMut<'s, Self>) -> StrongPinMut<'s, StaticArc<T>> {
-        // SAFETY: the pointer is valid, and it creates a unique `StrongPinMut`.
-        unsafe { StrongPinMut::new_unchecked(&raw mut (*self.ptr().as_ptr()).data) }
-    }
 }
 
 // SAFETY: `MruEntry` owns a `ListEntry`.
@@ -74,19 +71,24 @@ unsafe impl<T> ListNode for MruEntry<T> {
     }
 
     fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
-        (list_entry as *const _ as usize - Self::LIST_ENTRY_OFFSET) as *const Self
+        (list_entry as *const u8).wrapping_sub(Self::LIST_ENTRY_OFFSET) as *const Self
     }
 }
 
-impl<T, const CAPACITY: usize> MruArena<T, CAPACITY> {
+impl<T, R, const CAPACITY: usize> MruArena<T, R, CAPACITY> {
+    /// Returns an arena of default values, protected by `lock`.
+    ///
+    /// # Safety
+    ///
+    /// Must be used only after initializing it with `MruArena::init`.
     #[allow(clippy::new_ret_no_self)]
-    pub const unsafe fn new<D: Default>(name: &'static str) -> MruArena<D, CAPACITY> {
+    pub const unsafe fn new<D: Default>(lock: R) -> MruArena<D, R, CAPACITY> {
         let inner: MruArenaInner<D, CAPACITY> = MruArenaInner {
             entries: array![_ => MruEntry::new(Default::default()); CAPACITY],
             list: unsafe { List::new() },
         };
         MruArena {
-            inner: SpinLock::new(name, inner),
+            inner: Locked::new(lock, inner),
         }
     }
 
@@ -97,7 +99,9 @@ impl<T, const CAPACITY: usize> MruArena<T, CAPACITY> {
     }
 
     #[allow(clippy::needless_lifetimes)]
-    fn inner<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, SpinLock<MruArenaInner<T, CAPACITY>>> {
+    fn inner<'s>(
+        self: StrongPin<'s, Self>,
+    ) -> StrongPin<'s, Locked<R, MruArenaInner<T, CAPACITY>>> {
         unsafe { StrongPin::new_unchecked(&(*self.ptr()).inner) }
     }
 }
@@ -112,31 +116,66 @@ impl<T, const CAPACITY: usize> MruArenaInner<T, CAPACITY> {
         }
     }
 
+    /// Returns a pointer to the entry at the address `addr`, derived from `this`.
+    ///
+    /// # Safety
+    ///
+    /// `this` is valid, and `addr` is inside one of its entries.
+    unsafe fn entry_at(this: *mut Self, addr: usize) -> *mut MruEntry<T> {
+        let entries = unsafe { &raw mut (*this).entries } as *mut MruEntry<T>;
+        let index = (addr - entries as usize) / mem::size_of::<MruEntry<T>>();
+        assert!(index < CAPACITY, "MruArenaInner::entry_at: not an entry");
+        entries.wrapping_add(index)
+    }
+
+    /// Returns an iterator over the data of the entries, in the order of the list, whose back is the
+    /// most recently freed entry.
     #[allow(clippy::needless_lifetimes)]
-    fn list<'s>(self: StrongPinMut<'s, Self>) -> StrongPinMut<'s, List<MruEntry<T>>> {
-        // SAFETY: the pointer is valid, and it creates a unique `StrongPinMut`.
-        unsafe { StrongPinMut::new_unchecked(&raw mut (*self.ptr().as_ptr()).list) }
+    fn iter_data<'s>(
+        self: StrongPinMut<'s, Self>,
+    ) -> impl 's + DoubleEndedIterator<Item = StrongPinMut<'s, StaticArc<T>>>
+    where
+        T: 's,
+    {
+        let this = self.ptr().as_ptr();
+        // SAFETY: `list` is pinned since `self` is.
+        let list = unsafe { Pin::new_unchecked(&(*this).list) };
+        list.iter_ptr().map(move |entry| {
+            // SAFETY: `list` links the entries of `this`, each of them once, and `self` gives the
+            // unique access to them for `'s`.
+            unsafe {
+                let entry = Self::entry_at(this, entry as usize);
+                StrongPinMut::new_unchecked(&raw mut (*entry).data)
+            }
+        })
+    }
+
+    /// Moves the entry that contains `data` to the back of the list.
+    fn move_to_back(self: StrongPinMut<'_, Self>, data: *const StaticArc<T>) {
+        let this = self.ptr().as_ptr();
+        // SAFETY: `data` is the data of an entry of `this`, and entries are pinned.
+        unsafe {
+            let entry = Self::entry_at(this, data as usize);
+            Pin::new_unchecked(&(*this).list).push_back(Pin::new_unchecked(&*entry));
+        }
     }
 }
 
-impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
-    for MruArena<T, CAPACITY>
+impl<T: 'static + ArenaObject + Unpin + Send, R: RawLock, const CAPACITY: usize> Arena
+    for MruArena<T, R, CAPACITY>
 {
     type Data = T;
-    type Guard<'s> = SpinLockGuard<'s, MruArenaInner<T, CAPACITY>>;
 
     fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
         self: StrongPin<'_, Self>,
         c: C,
         n: N,
     ) -> Option<ArenaRc<Self>> {
-        let mut guard = self.inner().strong_pinned_lock();
+        let mut guard = self.inner().lock();
         let this = guard.get_strong_pinned_mut();
 
         let mut empty: Option<NonNull<StaticArc<T>>> = None;
-        for entry in this.list().iter_shared_mut() {
-            let mut entry = entry.data();
-
+        for mut entry in this.iter_data() {
             if let Some(entry) = entry.as_mut().try_borrow() {
                 // The entry is not under finalization. Check its data.
                 if c(&entry) {
@@ -158,11 +197,10 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
     }
 
     fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>> {
-        let mut guard = self.inner().strong_pinned_lock();
+        let mut guard = self.inner().lock();
         let this = guard.get_strong_pinned_mut();
 
-        for entry in this.list().iter_shared_mut().rev() {
-            let mut entry = entry.data();
+        for mut entry in this.iter_data().rev() {
             if let Some(data) = entry.as_mut().get_mut() {
                 *data = f();
                 return Some(ArenaRc::new(self, entry.borrow()));
@@ -178,17 +216,9 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
             rm.finalize(ctx);
 
             // Move this entry to the back of the list.
-            let ptr: *const MruEntry<Self::Data> =
-                (rm.cell() as usize - MruEntry::<T>::DATA_OFFSET) as _;
-            // SAFETY:
-            // * `rm.cell()` is an `RcCell` inside an `MruEntry`.
-            // * The value of `DATA_OFFSET` is proper.
-            let ptr = unsafe { Pin::new_unchecked(&*ptr) };
-
             let arena = unsafe { StrongPin::new_unchecked(&*rc.arena) };
-            let mut this = arena.inner().strong_pinned_lock();
-            let this = this.get_strong_pinned_mut().as_ref().as_pin().get_ref();
-            unsafe { Pin::new_unchecked(&this.list) }.push_back(ptr);
+            let mut guard = arena.inner().lock();
+            guard.get_strong_pinned_mut().move_to_back(rm.cell());
         }
         core::mem::forget(rc);
     }
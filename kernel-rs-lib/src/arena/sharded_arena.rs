@@ -0,0 +1,414 @@
+//! Hash-sharded, list based arena.
+//!
+//! Like `MruArena`, it keeps the data of freed entries, so that looking them up again finds them
+//! as long as they have not been reused. Instead of one list under one lock, it has `SHARDS`
+//! lists, each under its own lock, and an object lives in the shard that the hash of its key
+//! picks. Hence, looking up an object by `ShardedArena::find_or_alloc_hashed` locks and scans only
+//! one shard.
+//!
+//! Each shard keeps its entries in the order they were freed, so its front-most free entry is its
+//! least recently freed one. When a shard has no free entry, the arena takes the least recently
+//! freed entry over all shards, by comparing the front-most free entries of the shards one shard
+//! at a time. That is, the global LRU order is maintained lazily, only when a shard runs out of
+//! free entries.
+//!
+//! A lookup holds at most one shard lock at a time. Only `Arena::find_or_alloc`, which does not
+//! know the key it looks for, locks all shards, in the order of their indices.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomPinned;
+use core::mem::{self, ManuallyDrop};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use super::{Arena, ArenaObject, ArenaRc};
+use crate::{
+    intrusive_list::{List, ListEntry, ListNode},
+    lock::RawLock,
+    static_arc::{Ref, StaticArc},
+    strong_pin::{StrongPin, StrongPinMut},
+};
+
+/// Arena objects whose key decides their shard in a `ShardedArena`.
+pub trait ArenaKey {
+    /// Returns the hash of the key of `self`. Objects with the same key must have the same hash.
+    fn key_hash(&self) -> usize;
+}
+
+/// A homogeneous memory allocator equipped with reference counts, whose entries are sharded by
+/// the hashes of their keys.
+///
+/// # Safety
+///
+/// * Each entry of `entries` is linked in the list of exactly one shard, namely `shard`, except
+///   while `steal` has taken it out, when no other one can reach it.
+/// * The list of a shard, and `shard`, `freed_at`, and `live` of its entries, are accessed only
+///   while holding the lock of the shard.
+/// * The lists only order the entries: the pointers that they yield are used only to find the
+///   index of an entry, and the entry itself is accessed through `entries`.
+pub struct ShardedArena<T, R, const CAPACITY: usize, const SHARDS: usize> {
+    entries: UnsafeCell<[ShardedEntry<T>; CAPACITY]>,
+    locks: [R; SHARDS],
+    lists: [List<ShardedEntry<T>>; SHARDS],
+    /// Counts the deallocations, to order the freed entries.
+    clock: AtomicUsize,
+    _marker: PhantomPinned,
+}
+
+#[repr(C)]
+pub struct ShardedEntry<T> {
+    list_entry: ListEntry,
+    /// The shard whose list links this entry.
+    shard: usize,
+    /// The value of `clock` when this entry was freed last time.
+    freed_at: usize,
+    /// Whether `data` has ever been initialized, i.e., it is not the default value but an object,
+    /// although possibly a freed one.
+    live: bool,
+    data: StaticArc<T>,
+}
+
+/// Holds the locks of some shards until it drops.
+struct ShardsGuard<'s, R: RawLock> {
+    locks: &'s [R],
+}
+
+// SAFETY: the locks of the shards serialize the accesses to the entries.
+unsafe impl<T: Send, R: RawLock, const CAPACITY: usize, const SHARDS: usize> Sync
+    for ShardedArena<T, R, CAPACITY, SHARDS>
+{
+}
+
+impl<T> ShardedEntry<T> {
+    // TODO(https://github.com/kaist-cp/rv6/issues/369)
+    // A workarond for https://github.com/Gilnaa/memoffset/issues/49.
+    // Assumes `list_entry` is located at the beginning of `ShardedEntry`.
+    const LIST_ENTRY_OFFSET: usize = 0;
+
+    pub const fn new(data: T) -> Self {
+        Self {
+            list_entry: unsafe { ListEntry::new() },
+            shard: 0,
+            freed_at: 0,
+            live: false,
+            data: StaticArc::new(data),
+        }
+    }
+}
+
+// SAFETY: `ShardedEntry` owns a `ListEntry`.
+unsafe impl<T> ListNode for ShardedEntry<T> {
+    fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+        unsafe { Pin::new_unchecked(&self.get_ref().list_entry) }
+    }
+
+    fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+        (list_entry as *const u8).wrapping_sub(Self::LIST_ENTRY_OFFSET) as *const Self
+    }
+}
+
+impl<R: RawLock> ShardsGuard<'_, R> {
+    fn new(locks: &[R]) -> ShardsGuard<'_, R> {
+        for lock in locks {
+            lock.acquire();
+        }
+        ShardsGuard { locks }
+    }
+}
+
+impl<R: RawLock> Drop for ShardsGuard<'_, R> {
+    fn drop(&mut self) {
+        for lock in self.locks.iter().rev() {
+            lock.release();
+        }
+    }
+}
+
+impl<T, R, const CAPACITY: usize, const SHARDS: usize> ShardedArena<T, R, CAPACITY, SHARDS> {
+    /// Returns an arena of default values, whose shards are protected by `locks`.
+    ///
+    /// # Safety
+    ///
+    /// Must be used only after initializing it with `ShardedArena::init`.
+    #[allow(clippy::new_ret_no_self)]
+    pub const unsafe fn new<D: Default>(
+        locks: [R; SHARDS],
+    ) -> ShardedArena<D, R, CAPACITY, SHARDS> {
+        ShardedArena {
+            entries: UnsafeCell::new(array![_ => ShardedEntry::new(Default::default()); CAPACITY]),
+            locks,
+            lists: array![_ => unsafe { List::new() }; SHARDS],
+            clock: AtomicUsize::new(0),
+            _marker: PhantomPinned,
+        }
+    }
+
+    /// Distributes the entries to the shards evenly.
+    pub fn init(self: Pin<&mut Self>) {
+        // SAFETY: nothing is moved out of `this`.
+        let this = unsafe { self.get_unchecked_mut() };
+        for list in this.lists.iter_mut() {
+            unsafe { Pin::new_unchecked(list) }.init();
+        }
+
+        let this: &Self = this;
+        for index in 0..CAPACITY {
+            let shard = index % SHARDS;
+            // SAFETY: `index` is in bounds, and no one else accesses the entries yet.
+            unsafe {
+                let entry = (this.entries.get() as *mut ShardedEntry<T>).add(index);
+                Pin::new_unchecked(&mut (*entry).list_entry).init();
+                (*entry).shard = shard;
+                this.list(shard).push_back(Pin::new_unchecked(&*entry));
+            }
+        }
+    }
+
+    fn lock(&self, shard: usize) -> ShardsGuard<'_, R>
+    where
+        R: RawLock,
+    {
+        ShardsGuard::new(&self.locks[shard..=shard])
+    }
+
+    /// Returns the list of `shard`, whose lock should be held.
+    fn list(&self, shard: usize) -> Pin<&List<ShardedEntry<T>>> {
+        // SAFETY: `self` is pinned.
+        unsafe { Pin::new_unchecked(&self.lists[shard]) }
+    }
+
+    /// Returns a pointer to the entry at the address `addr`, derived from `entries`.
+    fn entry_at(&self, addr: *const ShardedEntry<T>) -> *mut ShardedEntry<T> {
+        let entries = self.entries.get() as *mut ShardedEntry<T>;
+        let index = (addr as usize - entries as usize) / mem::size_of::<ShardedEntry<T>>();
+        assert!(index < CAPACITY, "ShardedArena::entry_at: not an entry");
+        entries.wrapping_add(index)
+    }
+
+    /// Returns the entries of `shard`, from the least recently freed one.
+    /// The lock of `shard` should be held.
+    fn iter(&self, shard: usize) -> impl '_ + Iterator<Item = *mut ShardedEntry<T>> {
+        self.list(shard)
+            .iter_ptr()
+            .map(move |entry| self.entry_at(entry))
+    }
+
+    /// # Safety
+    ///
+    /// The lock of the shard of `entry` is held, or `steal` has taken `entry` out.
+    #[allow(clippy::needless_lifetimes)]
+    unsafe fn data<'s>(&'s self, entry: *mut ShardedEntry<T>) -> StrongPinMut<'s, StaticArc<T>> {
+        unsafe { StrongPinMut::new_unchecked(&raw mut (*entry).data) }
+    }
+
+    /// Returns the first entry of `shard` that satisfies `c`, which can be free but not one that
+    /// has never been initialized. The lock of `shard` should be held.
+    fn find<C: Fn(&T) -> bool>(&self, shard: usize, c: &C) -> Option<Ref<T>> {
+        for entry in self.iter(shard) {
+            // SAFETY: the lock of `shard` is held.
+            if !unsafe { (*entry).live } {
+                continue;
+            }
+            // SAFETY: the lock of `shard` is held.
+            if let Some(data) = unsafe { self.data(entry) }.try_borrow() {
+                // The entry is not under finalization. Check its data.
+                if c(&data) {
+                    return Some(data);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the least recently freed entry of `shard`.
+    /// The lock of `shard` should be held.
+    fn first_free(&self, shard: usize) -> Option<*mut ShardedEntry<T>> {
+        // SAFETY: the lock of `shard` is held.
+        self.iter(shard)
+            .find(|&entry| !unsafe { self.data(entry) }.is_borrowed())
+    }
+
+    /// Moves `entry` to the back of the list of `shard`, whose lock should be held.
+    ///
+    /// # Safety
+    ///
+    /// The lock of the shard of `entry` is held, or `steal` has taken `entry` out.
+    unsafe fn move_to(&self, entry: *mut ShardedEntry<T>, shard: usize) {
+        unsafe {
+            (*entry).shard = shard;
+            self.list(shard).push_back(Pin::new_unchecked(&*entry));
+        }
+    }
+
+    /// Initializes the free `entry` with `n`, and borrows it.
+    ///
+    /// # Safety
+    ///
+    /// The lock of the shard of `entry` is held, or `steal` has taken `entry` out.
+    unsafe fn init_entry<N: FnOnce(&mut T)>(&self, entry: *mut ShardedEntry<T>, n: N) -> Ref<T> {
+        unsafe { (*entry).live = true };
+        let mut data = unsafe { self.data(entry) };
+        n(data.as_mut().get_mut().expect("ShardedArena: entry in use"));
+        data.borrow()
+    }
+}
+
+impl<
+        T: 'static + ArenaObject + ArenaKey + Unpin + Send,
+        R: RawLock,
+        const CAPACITY: usize,
+        const SHARDS: usize,
+    > ShardedArena<T, R, CAPACITY, SHARDS>
+{
+    /// Takes the least recently freed entry over all shards out of its shard.
+    /// Returns `None` if no entry is free.
+    fn steal(&self) -> Option<*mut ShardedEntry<T>> {
+        loop {
+            let mut victim: Option<(usize, usize)> = None;
+            for shard in 0..SHARDS {
+                let _guard = self.lock(shard);
+                if let Some(entry) = self.first_free(shard) {
+                    // SAFETY: the lock of `shard` is held.
+                    let freed_at = unsafe { (*entry).freed_at };
+                    if victim.map_or(true, |(_, oldest)| freed_at < oldest) {
+                        victim = Some((shard, freed_at));
+                    }
+                }
+            }
+
+            let (shard, _) = victim?;
+            let _guard = self.lock(shard);
+            // The shard may have changed after the scan. If it has no free entry anymore, retry.
+            if let Some(entry) = self.first_free(shard) {
+                // SAFETY: the lock of `shard` is held.
+                unsafe { Pin::new_unchecked(&(*entry).list_entry) }.remove();
+                return Some(entry);
+            }
+        }
+    }
+
+    /// Looks for the object that satisfies `c` in the shard of `hash`, the hash of the key of the
+    /// object, without allocating one if there is none. It may find a freed object, but never an
+    /// entry that has not held one yet.
+    pub fn find_hashed<C: Fn(&T) -> bool>(
+        self: StrongPin<'_, Self>,
+        hash: usize,
+        c: C,
+    ) -> Option<ArenaRc<Self>> {
+        let this = self.as_pin().get_ref();
+        let shard = hash % SHARDS;
+        let _guard = this.lock(shard);
+        this.find(shard, &c).map(|data| ArenaRc::new(self, data))
+    }
+
+    /// Looks for the object that satisfies `c`, like `Arena::find_or_alloc`, but only in the shard
+    /// of `hash`, the hash of the key of the object. `n` should set the key accordingly.
+    pub fn find_or_alloc_hashed<C: Fn(&T) -> bool, N: FnOnce(&mut T)>(
+        self: StrongPin<'_, Self>,
+        hash: usize,
+        c: C,
+        n: N,
+    ) -> Option<ArenaRc<Self>> {
+        let this = self.as_pin().get_ref();
+        let shard = hash % SHARDS;
+        let n = |data: &mut T| {
+            n(data);
+            debug_assert_eq!(data.key_hash(), hash, "ShardedArena: wrong hash");
+        };
+
+        {
+            let _guard = this.lock(shard);
+            if let Some(data) = this.find(shard, &c) {
+                return Some(ArenaRc::new(self, data));
+            }
+            if let Some(entry) = this.first_free(shard) {
+                // SAFETY: the lock of `shard` is held.
+                let data = unsafe { this.init_entry(entry, n) };
+                return Some(ArenaRc::new(self, data));
+            }
+        }
+
+        // `shard` has no free entry. Take one from the other shards.
+        let entry = this.steal()?;
+        let _guard = this.lock(shard);
+        // SAFETY: `steal` has taken `entry` out, and the lock of `shard` is held.
+        unsafe { this.move_to(entry, shard) };
+        // Another one may have allocated the object in the meantime.
+        if let Some(data) = this.find(shard, &c) {
+            return Some(ArenaRc::new(self, data));
+        }
+        // SAFETY: the lock of `shard` is held.
+        let data = unsafe { this.init_entry(entry, n) };
+        Some(ArenaRc::new(self, data))
+    }
+}
+
+impl<
+        T: 'static + ArenaObject + ArenaKey + Unpin + Send,
+        R: RawLock,
+        const CAPACITY: usize,
+        const SHARDS: usize,
+    > Arena for ShardedArena<T, R, CAPACITY, SHARDS>
+{
+    type Data = T;
+
+    fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
+        self: StrongPin<'_, Self>,
+        c: C,
+        n: N,
+    ) -> Option<ArenaRc<Self>> {
+        let this = self.as_pin().get_ref();
+        let _guard = ShardsGuard::new(&this.locks);
+
+        let mut victim: Option<(*mut ShardedEntry<T>, usize)> = None;
+        for shard in 0..SHARDS {
+            if let Some(data) = this.find(shard, &c) {
+                return Some(ArenaRc::new(self, data));
+            }
+            if let Some(entry) = this.first_free(shard) {
+                // SAFETY: all locks are held.
+                let freed_at = unsafe { (*entry).freed_at };
+                if victim.map_or(true, |(_, oldest)| freed_at < oldest) {
+                    victim = Some((entry, freed_at));
+                }
+            }
+        }
+
+        let (entry, _) = victim?;
+        // SAFETY: all locks are held.
+        unsafe {
+            let data = this.init_entry(entry, n);
+            this.move_to(entry, data.key_hash() % SHARDS);
+            Some(ArenaRc::new(self, data))
+        }
+    }
+
+    fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>> {
+        let data = f();
+        let hash = data.key_hash();
+        self.find_or_alloc_hashed(hash, |_| false, |entry| *entry = data)
+    }
+
+    fn dealloc(mut rc: ArenaRc<Self>, ctx: <Self::Data as ArenaObject>::Ctx<'_, '_>) {
+        let inner = unsafe { ManuallyDrop::take(&mut rc.inner) };
+        if let Ok(mut rm) = inner.into_mut() {
+            // Finalize the arena object.
+            rm.finalize(ctx);
+
+            // Move this entry to the back of its shard.
+            let this = unsafe { &*rc.arena };
+            let entry = this.entry_at(rm.cell() as *const ShardedEntry<T>);
+            // SAFETY: `shard` does not change while the entry is borrowed.
+            let shard = unsafe { (*entry).shard };
+            let _guard = this.lock(shard);
+            // SAFETY: the lock of `shard` is held.
+            unsafe {
+                (*entry).freed_at = this.clock.fetch_add(1, Ordering::Relaxed);
+                this.move_to(entry, shard);
+            }
+        }
+        core::mem::forget(rc);
+    }
+}
@@ -14,6 +14,10 @@
 //! Instead, a [`List`] or [`ListEntry`]'s methods never returns a reference to a node or [`ListEntry`], and always
 //! returns a raw pointer instead. This is because a node could get mutated or dropped at any time, and hence,
 //! the caller should make sure the node is not under mutation or already dropped when dereferencing the raw pointer.
+//!
+//! The pointers in a [`List`] are derived from the whole nodes passed to [`List::push_back`] and
+//! [`List::push_front`], not from their [`ListEntry`]s, so that [`ListNode::from_list_entry`]
+//! can turn them into pointers to the nodes.
 // TODO: Also allow move.
 
 use core::cell::Cell;
@@ -23,8 +27,6 @@ use core::ptr;
 
 use pin_project::{pin_project, pinned_drop};
 
-use super::strong_pin::StrongPinMut;
-
 /// A doubly linked list.
 /// Can only contain types that implement the `ListNode` trait.
 /// Use only after initialization.
@@ -43,15 +45,16 @@ pub struct List<T: ListNode> {
 
 /// An iterator over the elements of `List`.
 pub struct Iter<'s, T: ListNode> {
-    last: &'s ListEntry,
-    curr: &'s ListEntry,
-    _marker: PhantomData<T>,
+    last: *const ListEntry,
+    curr: *const ListEntry,
+    _marker: PhantomData<&'s T>,
 }
 
-pub struct IterStrongPinMut<'s, T> {
+/// An iterator over raw pointers to the elements of `List`.
+pub struct IterPtr<'s, T: ListNode> {
     last: *const ListEntry,
     curr: *const ListEntry,
-    _marker: PhantomData<&'s mut T>,
+    _marker: PhantomData<&'s T>,
 }
 
 /// A pinned mutable iterator over the elements of `List`.
@@ -61,6 +64,18 @@ pub struct IterPinMut<'s, T: ListNode> {
     _marker: PhantomData<&'s mut T>,
 }
 
+/// A cursor over `List` that can remove nodes and splice other lists at its position.
+///
+/// A cursor points to either a node or the "ghost" position, i.e., the head of the list between
+/// its back and front. Like [`List::iter_ptr`], it gives raw pointers to the nodes, so that nodes can
+/// be removed while iterating without any reference to them, and the caller derives the access to
+/// a node from its owner.
+pub struct CursorMut<'s, T: ListNode> {
+    head: *const ListEntry,
+    curr: *const ListEntry,
+    _marker: PhantomData<&'s mut T>,
+}
+
 /// Intrusive linked list nodes that can be inserted into a `List`.
 ///
 /// # Safety
@@ -73,6 +88,7 @@ pub unsafe trait ListNode: Sized {
 
     /// Returns a raw pointer which points to the struct that owns the given `list_entry`.
     /// You may want to use `offset_of!` to implement this.
+    /// Do not round-trip the pointer through `usize`, which loses the provenance of the pointer.
     fn from_list_entry(list_entry: *const ListEntry) -> *const Self;
 }
 
@@ -137,14 +153,22 @@ impl<T: ListNode> List<T> {
         }
     }
 
+    /// Returns a pointer to the `ListEntry` of `elt`. Unlike `elt.get_list_entry()`, the pointer is
+    /// derived from the whole `elt`, so that `T::from_list_entry` gives back a pointer to `elt`.
+    fn entry_of(elt: Pin<&T>) -> *const ListEntry {
+        let node = elt.get_ref() as *const T;
+        let offset = elt.get_list_entry().get_ref() as *const ListEntry as usize - node as usize;
+        (node as *const u8).wrapping_add(offset) as *const ListEntry
+    }
+
     /// Push `elt` at the back of the list after unlinking it.
     pub fn push_back(self: Pin<&Self>, elt: Pin<&T>) {
-        self.head().push_back(elt.get_list_entry());
+        self.head().push_back(Self::entry_of(elt));
     }
 
     /// Push `elt` at the front of the list after unlinking it.
     pub fn push_front(self: Pin<&Self>, elt: Pin<&T>) {
-        self.head().push_front(elt.get_list_entry());
+        self.head().push_front(Self::entry_of(elt));
     }
 
     /// Removes the last node from the list and returns a raw pointer to it,
@@ -154,22 +178,20 @@ impl<T: ListNode> List<T> {
         if ptr::eq(ptr, &self.head) {
             None
         } else {
-            let ptr = unsafe { Pin::new_unchecked(&*ptr) };
-            ptr.remove();
-            Some(T::from_list_entry(ptr.get_ref()))
+            unsafe { Pin::new_unchecked(&*ptr) }.remove();
+            Some(T::from_list_entry(ptr))
         }
     }
 
-    /// Removes the last node from the list and returns a raw pointer to it,
+    /// Removes the first node from the list and returns a raw pointer to it,
     /// or `None` if the list is empty.
     pub fn pop_front(self: Pin<&Self>) -> Option<*const T> {
         let ptr = self.head().next();
         if ptr::eq(ptr, &self.head) {
             None
         } else {
-            let ptr = unsafe { Pin::new_unchecked(&*ptr) };
-            ptr.remove();
-            Some(T::from_list_entry(ptr.get_ref()))
+            unsafe { Pin::new_unchecked(&*ptr) }.remove();
+            Some(T::from_list_entry(ptr))
         }
     }
 
@@ -194,7 +216,7 @@ impl<T: ListNode> List<T> {
     ///
     /// *Incorrect* usage of this method.
     ///
-    /// ```rust,no_run
+    /// ```rust,ignore
     /// # #[pin_project]
     /// # struct Node {
     /// #     data: usize,
@@ -238,26 +260,57 @@ impl<T: ListNode> List<T> {
     pub unsafe fn iter_unchecked(self: Pin<&Self>) -> Iter<'_, T> {
         Iter {
             last: self.head().get_ref(),
-            curr: unsafe { &*self.head().next() },
+            curr: self.head().next(),
             _marker: PhantomData,
         }
     }
 
-    #[allow(clippy::needless_lifetimes)]
-    pub fn iter_shared_mut<'s>(self: StrongPinMut<'s, Self>) -> IterStrongPinMut<'s, T> {
-        let last = unsafe { &(*self.ptr().as_ptr()).head };
-        let curr = unsafe { &*Pin::new_unchecked(last).next() };
-        IterStrongPinMut {
-            last,
-            curr,
+    /// Provides a forward iterator over raw pointers to the nodes.
+    ///
+    /// The iterator never dereferences the nodes, but only their `ListEntry`s, which must not be
+    /// unlinked or dropped while iterating. To access a node mutably, the caller should derive
+    /// the access from the owner of the node, and use the pointer only to find the node.
+    pub fn iter_ptr(self: Pin<&Self>) -> IterPtr<'_, T> {
+        IterPtr {
+            last: self.head().get_ref(),
+            curr: self.head().next(),
             _marker: PhantomData,
         }
     }
 
+    /// Provides a forward iterator that gives pinned mutable references to the nodes.
+    ///
+    /// # Safety
+    ///
+    /// The caller must have the unique access to all nodes in the list while iterating.
     pub unsafe fn iter_pin_mut_unchecked(self: Pin<&mut Self>) -> IterPinMut<'_, T> {
         IterPinMut {
             last: &self.head,
-            curr: unsafe { &*self.as_ref().head().next() },
+            curr: self.as_ref().head().next(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Provides a cursor at the front node, or at the ghost position if the list is empty.
+    ///
+    /// The nodes in the list must not be dropped while the cursor points to them.
+    pub fn cursor_front_mut(self: Pin<&mut Self>) -> CursorMut<'_, T> {
+        let head = self.as_ref().head();
+        CursorMut {
+            head: head.get_ref(),
+            curr: head.next(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Provides a cursor at the back node, or at the ghost position if the list is empty.
+    ///
+    /// The nodes in the list must not be dropped while the cursor points to them.
+    pub fn cursor_back_mut(self: Pin<&mut Self>) -> CursorMut<'_, T> {
+        let head = self.as_ref().head();
+        CursorMut {
+            head: head.get_ref(),
+            curr: head.prev(),
             _marker: PhantomData,
         }
     }
@@ -279,9 +332,9 @@ impl<'s, T: 's + ListNode> Iterator for Iter<'s, T> {
         } else {
             // Safe since `self.curr` is a `ListEntry` contained inside a `T`.
             let res = Some(unsafe { &*T::from_list_entry(self.curr) });
-            let curr = unsafe { Pin::new_unchecked(self.curr) };
-            debug_assert_ne!(self.curr as *const _, curr.next(), "loops forever");
-            self.curr = unsafe { &*curr.next() };
+            let curr = unsafe { Pin::new_unchecked(&*self.curr) };
+            debug_assert_ne!(self.curr, curr.next(), "loops forever");
+            self.curr = curr.next();
             res
         }
     }
@@ -292,25 +345,24 @@ impl<'s, T: 's + ListNode> DoubleEndedIterator for Iter<'s, T> {
         if ptr::eq(self.last, self.curr) {
             None
         } else {
-            let last = unsafe { Pin::new_unchecked(self.last) };
-            debug_assert_ne!(self.last as *const _, last.prev(), "loops forever");
-            self.last = unsafe { &*last.prev() };
+            let last = unsafe { Pin::new_unchecked(&*self.last) };
+            debug_assert_ne!(self.last, last.prev(), "loops forever");
+            self.last = last.prev();
             // Safe since `self.last` is a `ListEntry` contained inside a `T`.
             Some(unsafe { &*T::from_list_entry(self.last) })
         }
     }
 }
 
-impl<'s, T: 's + ListNode> Iterator for IterStrongPinMut<'s, T> {
-    type Item = StrongPinMut<'s, T>;
+impl<'s, T: 's + ListNode> Iterator for IterPtr<'s, T> {
+    type Item = *const T;
 
     fn next(&mut self) -> Option<Self::Item> {
         if ptr::eq(self.last, self.curr) {
             None
         } else {
-            // Safe since `self.curr` is a `ListEntry` contained inside a `T`.
-            let ptr = T::from_list_entry(self.curr) as *mut T;
-            let res = Some(unsafe { StrongPinMut::new_unchecked(ptr) });
+            let res = Some(T::from_list_entry(self.curr));
+            // Safe since the `ListEntry`s in the list are valid while the list is borrowed.
             let curr = unsafe { Pin::new_unchecked(&*self.curr) };
             debug_assert_ne!(self.curr, curr.next(), "loops forever");
             self.curr = curr.next();
@@ -319,17 +371,16 @@ impl<'s, T: 's + ListNode> Iterator for IterStrongPinMut<'s, T> {
     }
 }
 
-impl<'s, T: 's + ListNode> DoubleEndedIterator for IterStrongPinMut<'s, T> {
+impl<'s, T: 's + ListNode> DoubleEndedIterator for IterPtr<'s, T> {
     fn next_back(&mut self) -> Option<Self::Item> {
         if ptr::eq(self.last, self.curr) {
             None
         } else {
+            // Safe since the `ListEntry`s in the list are valid while the list is borrowed.
             let last = unsafe { Pin::new_unchecked(&*self.last) };
             debug_assert_ne!(self.last, last.prev(), "loops forever");
             self.last = last.prev();
-            // Safe since `self.last` is a `ListEntry` contained inside a `T`.
-            let ptr = T::from_list_entry(self.last) as *mut T;
-            Some(unsafe { StrongPinMut::new_unchecked(ptr) })
+            Some(T::from_list_entry(self.last))
         }
     }
 }
@@ -367,6 +418,74 @@ impl<'s, T: 's + ListNode> DoubleEndedIterator for IterPinMut<'s, T> {
     }
 }
 
+impl<'s, T: 's + ListNode> CursorMut<'s, T> {
+    fn entry(&self, ptr: *const ListEntry) -> Pin<&ListEntry> {
+        // Safe since the `ListEntry`s in the list are valid while the list is borrowed.
+        unsafe { Pin::new_unchecked(&*ptr) }
+    }
+
+    /// Returns a raw pointer to the current node, or `None` at the ghost position.
+    pub fn current(&self) -> Option<*const T> {
+        if ptr::eq(self.curr, self.head) {
+            None
+        } else {
+            Some(T::from_list_entry(self.curr))
+        }
+    }
+
+    /// Moves to the next node. Moves to the front node after the ghost position, and to the ghost
+    /// position after the back node.
+    pub fn move_next(&mut self) {
+        self.curr = self.entry(self.curr).next();
+    }
+
+    /// Moves to the previous node. Moves to the back node before the ghost position, and to the
+    /// ghost position before the front node.
+    pub fn move_prev(&mut self) {
+        self.curr = self.entry(self.curr).prev();
+    }
+
+    /// Removes the current node from the list, moves to the next node, and returns a raw pointer
+    /// to the removed node. Returns `None` and does nothing at the ghost position.
+    pub fn remove_current(&mut self) -> Option<*const T> {
+        let node = self.current()?;
+        let removed = self.curr;
+        self.move_next();
+        self.entry(removed).remove();
+        Some(node)
+    }
+
+    /// Moves all nodes of `list` after the current node, keeping their order. At the ghost
+    /// position, moves them to the front of the list. The cursor does not move.
+    pub fn splice_after(&mut self, list: Pin<&List<T>>) {
+        let next = self.entry(self.curr).next();
+        self.splice_between(self.curr, next, list);
+    }
+
+    /// Moves all nodes of `list` before the current node, keeping their order. At the ghost
+    /// position, moves them to the back of the list. The cursor does not move.
+    pub fn splice_before(&mut self, list: Pin<&List<T>>) {
+        let prev = self.entry(self.curr).prev();
+        self.splice_between(prev, self.curr, list);
+    }
+
+    /// Links the nodes of `list` between the adjacent `prev` and `next`, and empties `list`.
+    fn splice_between(&self, prev: *const ListEntry, next: *const ListEntry, list: Pin<&List<T>>) {
+        if list.is_empty() {
+            return;
+        }
+        let head = list.head();
+        let (first, last) = (head.next(), head.prev());
+        // The pointers stored in `list` are derived from the whole nodes, so that they stay so.
+        self.entry(prev).next.set(first);
+        self.entry(first).prev.set(prev);
+        self.entry(last).next.set(next);
+        self.entry(next).prev.set(last);
+        head.prev.set(head.get_ref());
+        head.next.set(head.get_ref());
+    }
+}
+
 impl ListEntry {
     /// Returns an uninitialized `ListEntry`,
     ///
@@ -415,30 +534,34 @@ impl ListEntry {
     }
 
     /// Inserts `elt` at the back of this `ListEntry` after unlinking `elt`.
-    fn push_back(self: Pin<&Self>, elt: Pin<&Self>) {
-        if !elt.is_unlinked() {
-            elt.remove();
+    /// The neighbors of `elt` store `elt` itself, not a pointer derived from a reference to it.
+    fn push_back(self: Pin<&Self>, elt: *const Self) {
+        let entry = unsafe { Pin::new_unchecked(&*elt) };
+        if !entry.is_unlinked() {
+            entry.remove();
         }
 
-        elt.next.set(self.get_ref());
-        elt.prev.set(self.prev());
+        entry.next.set(self.get_ref());
+        entry.prev.set(self.prev());
         unsafe {
-            (*elt.next()).prev.set(elt.get_ref());
-            (*elt.prev()).next.set(elt.get_ref());
+            (*entry.next()).prev.set(elt);
+            (*entry.prev()).next.set(elt);
         }
     }
 
     /// Inserts `elt` in front of this `ListEntry` after unlinking `elt`.
-    fn push_front(self: Pin<&Self>, elt: Pin<&Self>) {
-        if !elt.is_unlinked() {
-            elt.remove();
+    /// The neighbors of `elt` store `elt` itself, not a pointer derived from a reference to it.
+    fn push_front(self: Pin<&Self>, elt: *const Self) {
+        let entry = unsafe { Pin::new_unchecked(&*elt) };
+        if !entry.is_unlinked() {
+            entry.remove();
         }
 
-        elt.next.set(self.next());
-        elt.prev.set(self.get_ref());
+        entry.next.set(self.next());
+        entry.prev.set(self.get_ref());
         unsafe {
-            (*elt.next()).prev.set(elt.get_ref());
-            (*elt.prev()).next.set(elt.get_ref());
+            (*entry.next()).prev.set(elt);
+            (*entry.prev()).next.set(elt);
         }
     }
 
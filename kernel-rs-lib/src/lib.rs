@@ -0,0 +1,32 @@
+//! Data structures of the rv6 kernel that do not depend on the rest of the kernel.
+//!
+//! The kernel uses the modules of this crate as they are, and this crate is their only copy.
+//! Since nothing here touches the hardware, the integration tests in `tests/` run them on the
+//! host, also under miri (`cargo miri test`), which checks the aliasing of their raw pointers.
+//!
+//! Locking is left to the user: the arenas are generic over a `RawLock`, which the kernel
+//! implements with its spin locks and the tests with a plain atomic flag.
+
+#![no_std]
+#![deny(rust_2018_idioms)]
+#![deny(unsafe_op_in_unsafe_fn)]
+#![deny(unused_qualifications)]
+#![deny(unused_results)]
+#![deny(warnings)]
+#![allow(incomplete_features)]
+#![feature(arbitrary_self_types)]
+#![feature(const_fn_trait_bound)]
+#![feature(const_fn_union)]
+#![feature(const_mut_refs)]
+#![feature(const_precise_live_drops)]
+#![feature(const_trait_impl)]
+#![feature(generic_associated_types)]
+#![feature(raw_ref_op)]
+
+pub mod arena;
+pub mod intrusive_list;
+pub mod lock;
+pub mod model;
+pub mod pinned_array;
+pub mod static_arc;
+pub mod strong_pin;
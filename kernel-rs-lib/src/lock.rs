@@ -0,0 +1,8 @@
+//! The interface of the locks that protect the data structures of this crate.
+
+pub trait RawLock {
+    /// Acquires the lock.
+    fn acquire(&self);
+    /// Releases the lock.
+    fn release(&self);
+}
@@ -0,0 +1,156 @@
+//! Runtime checks of the invariants that the types of the kernel rely on but cannot express,
+//! enabled by the `model-check` feature. Without it, every check returns at once, and costs
+//! nothing.
+//!
+//! * Lock order: locks fall into classes by their names. Whenever a CPU acquires a lock while
+//!   holding another one, the order of the two classes is recorded, and acquiring them in the
+//!   opposite order later panics, even if the two acquisitions never actually deadlock. So does
+//!   closing a cycle through other classes, such as acquiring a under c after b under a and c
+//!   under b.
+//! * Linear guards: a transaction and an inode guard must end explicitly, and dropping them
+//!   panics. Counting the live ones also catches forgetting one by `mem::forget`, when the system
+//!   call that took it returns.
+
+use core::cell::Cell;
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+use spin::Mutex;
+
+/// Maximum number of locks that a CPU holds at once.
+const MAX_HELD: usize = 16;
+
+/// Maximum number of recorded orders between lock classes.
+const MAX_ORDERS: usize = 256;
+
+/// The order of the lock classes, learned from the locks that `NCPU` CPUs acquire.
+pub struct LockOrder<const NCPU: usize> {
+    /// The classes of the locks that each CPU holds, in the order of acquisition. Indexed by CPU.
+    held: [Mutex<ArrayVec<&'static str, MAX_HELD>>; NCPU],
+
+    /// (a, b) means that a lock of class b has been acquired while holding one of class a.
+    orders: Mutex<ArrayVec<(&'static str, &'static str), MAX_ORDERS>>,
+}
+
+impl<const NCPU: usize> LockOrder<NCPU> {
+    pub const fn new() -> Self {
+        Self {
+            held: array![_ => Mutex::new(ArrayVec::new_const()); NCPU],
+            orders: Mutex::new(ArrayVec::new_const()),
+        }
+    }
+
+    /// Records that `cpu` is acquiring a lock of class `name`, and checks its order against the
+    /// locks that `cpu` holds. Called by `cpu` with interrupts disabled, before it spins.
+    pub fn acquire(&self, cpu: usize, name: &'static str) {
+        if !cfg!(feature = "model-check") {
+            return;
+        }
+        let mut held = self.held[cpu].lock();
+        let mut orders = self.orders.lock();
+        for &outer in held.iter().filter(|&&outer| outer != name) {
+            if orders.contains(&(outer, name)) {
+                continue;
+            }
+            assert!(
+                !reaches(&orders, name, outer),
+                "lock order: {} acquired while holding {}, but the other way elsewhere",
+                name,
+                outer
+            );
+            // Stop learning new orders once the table is full.
+            let _ = orders.try_push((outer, name));
+        }
+        held.try_push(name)
+            .expect("LockOrder::acquire: too many locks held");
+    }
+
+    /// Records that `cpu` has released a lock of class `name`, not necessarily the last one it
+    /// acquired. Called by `cpu` with interrupts disabled.
+    pub fn release(&self, cpu: usize, name: &'static str) {
+        if !cfg!(feature = "model-check") {
+            return;
+        }
+        let mut held = self.held[cpu].lock();
+        let i = held
+            .iter()
+            .rposition(|&class| class == name)
+            .expect("LockOrder::release: not held");
+        let _ = held.remove(i);
+    }
+}
+
+/// Returns true if the recorded `orders` lead from class `from` to class `to`.
+fn reaches(orders: &[(&'static str, &'static str)], from: &str, to: &str) -> bool {
+    // reached[i] means that the orders lead from `from` to the end of orders[i].
+    let mut reached = [false; MAX_ORDERS];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, &(a, b)) in orders.iter().enumerate() {
+            if reached[i] {
+                continue;
+            }
+            if a == from || orders.iter().zip(&reached).any(|(&(_, c), &r)| r && c == a) {
+                if b == to {
+                    return true;
+                }
+                reached[i] = true;
+                changed = true;
+            }
+        }
+    }
+    false
+}
+
+/// The transactions and the inode guards that a process holds.
+pub struct LiveGuards {
+    txs: Cell<usize>,
+    inodes: Cell<usize>,
+}
+
+impl LiveGuards {
+    pub const fn new() -> Self {
+        Self {
+            txs: Cell::new(0),
+            inodes: Cell::new(0),
+        }
+    }
+
+    pub fn begin_tx(&self) {
+        if cfg!(feature = "model-check") {
+            self.txs.set(self.txs.get() + 1);
+        }
+    }
+
+    pub fn end_tx(&self) {
+        if cfg!(feature = "model-check") {
+            self.txs.set(self.txs.get() - 1);
+        }
+    }
+
+    pub fn lock_inode(&self) {
+        if cfg!(feature = "model-check") {
+            self.inodes.set(self.inodes.get() + 1);
+        }
+    }
+
+    pub fn unlock_inode(&self) {
+        if cfg!(feature = "model-check") {
+            self.inodes.set(self.inodes.get() - 1);
+        }
+    }
+
+    /// Checks that no transaction or inode guard outlives the system call `num`.
+    pub fn check_none(&self, num: i32) {
+        if cfg!(feature = "model-check") {
+            assert!(
+                self.txs.get() == 0 && self.inodes.get() == 0,
+                "system call {} leaked {} transactions and {} inode guards",
+                num,
+                self.txs.get(),
+                self.inodes.get()
+            );
+        }
+    }
+}
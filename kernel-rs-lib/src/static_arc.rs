@@ -4,7 +4,7 @@ use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use super::strong_pin::StrongPinMut;
+use crate::strong_pin::StrongPinMut;
 
 const BORROWED_MUT: usize = usize::MAX;
 
@@ -59,6 +59,9 @@ impl<T> StaticArc<T> {
         }
     }
 
+    /// # Safety
+    ///
+    /// No `Ref` nor `RefMut` points to `self`.
     #[allow(clippy::needless_lifetimes)]
     pub unsafe fn get_mut_unchecked<'s>(self: StrongPinMut<'s, Self>) -> &'s mut T {
         // SAFETY: no `Ref` nor `RefMut` points to `self`.
@@ -88,6 +91,9 @@ impl<T> StaticArc<T> {
         self.try_borrow().expect("already mutably borrowed")
     }
 
+    /// # Safety
+    ///
+    /// No `RefMut` points to `self`.
     pub unsafe fn borrow_unchecked(mut self: StrongPinMut<'_, Self>) -> Ref<T> {
         let _ = self.as_mut().rc().fetch_add(1, Ordering::Relaxed);
         Ref(self.ptr())
@@ -111,9 +117,11 @@ impl<T> Ref<T> {
     }
 
     pub fn into_mut(self) -> Result<RefMut<T>, Self> {
+        // Acquire, so that the reads through the other `Ref`s, which have released `refcnt` when
+        // they dropped, happen before the writes through the `RefMut`.
         if self
             .rc()
-            .compare_exchange(1, BORROWED_MUT, Ordering::Relaxed, Ordering::Relaxed)
+            .compare_exchange(1, BORROWED_MUT, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
             return Err(self);
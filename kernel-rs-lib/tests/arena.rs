@@ -0,0 +1,356 @@
+//! Tests of `ArrayArena`, `MruArena`, and `ShardedArena`.
+
+#![allow(incomplete_features)]
+#![feature(generic_associated_types)]
+
+use std::cell::Cell;
+use std::hint;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use kernel_rs_lib::arena::{
+    Arena, ArenaKey, ArenaObject, ArenaRc, ArrayArena, MruArena, ShardedArena,
+};
+use kernel_rs_lib::lock::RawLock;
+use kernel_rs_lib::strong_pin::StrongPin;
+
+struct RawFlagLock {
+    locked: AtomicBool,
+}
+
+impl RawFlagLock {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+        }
+    }
+}
+
+impl RawLock for RawFlagLock {
+    fn acquire(&self) {
+        while self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+    }
+
+    fn release(&self) {
+        assert!(self.locked.swap(false, Ordering::Release), "not locked");
+    }
+}
+
+#[derive(Default)]
+struct Obj {
+    key: usize,
+}
+
+impl ArenaObject for Obj {
+    type Ctx<'a, 'b: 'a> = &'a Cell<usize>;
+
+    fn finalize<'a, 'b: 'a>(&mut self, finalized: Self::Ctx<'a, 'b>) {
+        finalized.set(finalized.get() + 1);
+    }
+}
+
+impl ArenaKey for Obj {
+    fn key_hash(&self) -> usize {
+        self.key
+    }
+}
+
+const CAPACITY: usize = 4;
+const SHARDS: usize = 2;
+
+type Array = ArrayArena<Obj, RawFlagLock, CAPACITY>;
+type Mru = MruArena<Obj, RawFlagLock, CAPACITY>;
+type Sharded = ShardedArena<Obj, RawFlagLock, CAPACITY, SHARDS>;
+
+fn array_arena() -> Pin<Box<Array>> {
+    Box::pin(Array::new(RawFlagLock::new()))
+}
+
+fn mru_arena() -> Pin<Box<Mru>> {
+    let mut arena = Box::pin(unsafe { Mru::new(RawFlagLock::new()) });
+    arena.as_mut().init();
+    arena
+}
+
+fn sharded_arena() -> Pin<Box<Sharded>> {
+    let mut arena = Box::pin(unsafe { Sharded::new([RawFlagLock::new(), RawFlagLock::new()]) });
+    arena.as_mut().init();
+    arena
+}
+
+fn pin<A: Arena>(arena: &Pin<Box<A>>) -> StrongPin<'_, A> {
+    // SAFETY: the arena is not mutated until it is dropped.
+    unsafe { StrongPin::new_unchecked(arena.as_ref().get_ref()) }
+}
+
+fn get<A: Arena<Data = Obj>>(arena: StrongPin<'_, A>, key: usize) -> Option<ArenaRc<A>> {
+    arena.find_or_alloc(|obj| obj.key == key, |obj| obj.key = key)
+}
+
+fn get_hashed(arena: StrongPin<'_, Sharded>, key: usize) -> Option<ArenaRc<Sharded>> {
+    arena.find_or_alloc_hashed(key, |obj| obj.key == key, |obj| obj.key = key)
+}
+
+fn free_all<A: Arena<Data = Obj>>(rcs: Vec<ArenaRc<A>>, finalized: &Cell<usize>) {
+    for rc in rcs {
+        rc.free(finalized);
+    }
+}
+
+fn alloc_until_full<A: Arena<Data = Obj>>(arena: StrongPin<'_, A>) {
+    let finalized = Cell::new(0);
+    let rcs: Vec<_> = (0..CAPACITY)
+        .map(|key| arena.alloc(|| Obj { key }).unwrap())
+        .collect();
+    assert!(arena.alloc(Obj::default).is_none());
+    assert!(get(arena, CAPACITY).is_none());
+
+    let mut keys: Vec<_> = rcs.iter().map(|rc| rc.key).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, (0..CAPACITY).collect::<Vec<_>>());
+
+    free_all(rcs, &finalized);
+    assert_eq!(finalized.get(), CAPACITY);
+    free_all(vec![arena.alloc(Obj::default).unwrap()], &finalized);
+}
+
+fn find_shares_entry<A: Arena<Data = Obj>>(arena: StrongPin<'_, A>) {
+    let finalized = Cell::new(0);
+    let a = get(arena, 7).unwrap();
+    let b = get(arena, 7).unwrap();
+    let c = a.clone();
+    assert!(std::ptr::eq(&*a, &*b));
+    assert!(std::ptr::eq(&*a, &*c));
+
+    // Only the last handle finalizes the object.
+    free_all(vec![a, b], &finalized);
+    assert_eq!(finalized.get(), 0);
+    free_all(vec![c], &finalized);
+    assert_eq!(finalized.get(), 1);
+}
+
+/// A reference into a live entry stays valid while the arena searches and initializes the other
+/// entries. Under miri, this fails if the arena accesses all entries mutably.
+fn reference_survives_search<A: Arena<Data = Obj>>(arena: StrongPin<'_, A>) {
+    let finalized = Cell::new(0);
+    let a = get(arena, 1).unwrap();
+    let key: &usize = &a.key;
+
+    let b = get(arena, 2).unwrap();
+    let c = arena.alloc(|| Obj { key: 3 }).unwrap();
+    let d = get(arena, 1).unwrap();
+    assert_eq!(*key, 1);
+    assert_eq!((b.key, c.key, d.key), (2, 3, 1));
+
+    free_all(vec![a, b, c, d], &finalized);
+    assert_eq!(finalized.get(), 3);
+}
+
+#[test]
+fn array_alloc_until_full() {
+    alloc_until_full(pin(&array_arena()));
+}
+
+#[test]
+fn array_find_shares_entry() {
+    find_shares_entry(pin(&array_arena()));
+}
+
+#[test]
+fn array_reference_survives_search() {
+    reference_survives_search(pin(&array_arena()));
+}
+
+#[test]
+fn mru_alloc_until_full() {
+    alloc_until_full(pin(&mru_arena()));
+}
+
+#[test]
+fn mru_find_shares_entry() {
+    find_shares_entry(pin(&mru_arena()));
+}
+
+#[test]
+fn mru_reference_survives_search() {
+    reference_survives_search(pin(&mru_arena()));
+}
+
+/// A freed entry keeps its data, so that looking it up again finds it, as long as it has not been
+/// reused.
+#[test]
+fn mru_keeps_freed_entries() {
+    let arena = mru_arena();
+    let arena = pin(&arena);
+    let finalized = Cell::new(0);
+
+    let rcs: Vec<_> = (1..=CAPACITY).map(|key| get(arena, key).unwrap()).collect();
+    let addrs: Vec<*const Obj> = rcs.iter().map(|rc| &**rc as *const Obj).collect();
+    free_all(rcs, &finalized);
+    assert_eq!(finalized.get(), CAPACITY);
+
+    let rcs: Vec<_> = (1..=CAPACITY).map(|key| get(arena, key).unwrap()).collect();
+    for (rc, addr) in rcs.iter().zip(addrs) {
+        assert!(std::ptr::eq(&**rc, addr));
+    }
+    free_all(rcs, &finalized);
+}
+
+#[test]
+fn sharded_alloc_until_full() {
+    alloc_until_full(pin(&sharded_arena()));
+}
+
+#[test]
+fn sharded_find_shares_entry() {
+    find_shares_entry(pin(&sharded_arena()));
+}
+
+#[test]
+fn sharded_reference_survives_search() {
+    reference_survives_search(pin(&sharded_arena()));
+}
+
+/// A shard without free entries takes ones from the other shards, and keeps them.
+#[test]
+fn sharded_shard_takes_entries() {
+    let arena = sharded_arena();
+    let arena = pin(&arena);
+    let finalized = Cell::new(0);
+
+    // All keys fall into the shard 0, which has only half of the entries.
+    let keys: Vec<_> = (0..CAPACITY).map(|i| i * SHARDS).collect();
+    let rcs: Vec<_> = keys
+        .iter()
+        .map(|&key| get_hashed(arena, key).unwrap())
+        .collect();
+    assert!(get_hashed(arena, CAPACITY * SHARDS).is_none());
+    assert!(get_hashed(arena, 1).is_none());
+    let addrs: Vec<*const Obj> = rcs.iter().map(|rc| &**rc as *const Obj).collect();
+    free_all(rcs, &finalized);
+
+    let rcs: Vec<_> = keys
+        .iter()
+        .map(|&key| get_hashed(arena, key).unwrap())
+        .collect();
+    for (rc, addr) in rcs.iter().zip(addrs) {
+        assert!(std::ptr::eq(&**rc, addr));
+    }
+    free_all(rcs, &finalized);
+    assert_eq!(finalized.get(), 2 * CAPACITY);
+}
+
+/// A new object takes the least recently freed entry of its shard, or of all shards if its shard
+/// has none.
+#[test]
+fn sharded_evicts_least_recently_freed() {
+    let arena = sharded_arena();
+    let arena = pin(&arena);
+    let finalized = Cell::new(0);
+
+    let rcs: Vec<_> = (0..CAPACITY)
+        .map(|key| get_hashed(arena, key).unwrap())
+        .collect();
+    let addrs: Vec<*const Obj> = rcs.iter().map(|rc| &**rc as *const Obj).collect();
+    let mut rcs: Vec<_> = rcs.into_iter().map(Some).collect();
+    for key in [2, 0, 3, 1] {
+        rcs[key].take().unwrap().free(&finalized);
+    }
+
+    let reuses = |key, old_key: usize| {
+        let rc = get_hashed(arena, key).unwrap();
+        assert!(std::ptr::eq(&*rc, addrs[old_key]));
+        rc
+    };
+    let rcs = vec![
+        // From the shard 0.
+        reuses(4, 2),
+        // From the shard 1.
+        reuses(5, 3),
+        // From the shard 0.
+        reuses(6, 0),
+        // From the shard 1, since the shard 0 has no free entries.
+        reuses(8, 1),
+    ];
+    assert!(get_hashed(arena, 10).is_none());
+    free_all(rcs, &finalized);
+    assert_eq!(finalized.get(), 2 * CAPACITY);
+}
+
+/// `find_hashed` finds an object even after it is freed, but never allocates one.
+#[test]
+fn sharded_find_hashed_does_not_alloc() {
+    let arena = sharded_arena();
+    let arena = pin(&arena);
+    let finalized = Cell::new(0);
+    let find = |key| arena.find_hashed(key, |obj: &Obj| obj.key == key);
+
+    assert!(find(0).is_none());
+    let rc = get_hashed(arena, 0).unwrap();
+    let addr = &*rc as *const Obj;
+    free_all(vec![rc], &finalized);
+
+    let rc = find(0).unwrap();
+    assert!(std::ptr::eq(&*rc, addr));
+    assert!(find(1).is_none());
+    free_all(vec![rc], &finalized);
+    assert_eq!(finalized.get(), 2);
+}
+
+/// Threads looking up and freeing objects concurrently never get wrong or duplicate objects.
+#[test]
+fn sharded_concurrent_lookups() {
+    const THREADS: usize = 4;
+    const KEYS: usize = 6;
+
+    let ptr = Box::into_raw(Box::new(unsafe {
+        Sharded::new([RawFlagLock::new(), RawFlagLock::new()])
+    }));
+    unsafe { Pin::new_unchecked(&mut *ptr) }.init();
+    // SAFETY: `ptr` is freed only after the threads finish.
+    let arena: &'static Sharded = unsafe { &*ptr };
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            thread::spawn(move || {
+                let arena = unsafe { StrongPin::new_unchecked(arena) };
+                let finalized = Cell::new(0);
+                for i in 0..10 {
+                    let key = (t + i) % KEYS;
+                    // Another thread may hold the free entry that the lookup is looking for.
+                    if let Some(rc) = get_hashed(arena, key) {
+                        assert_eq!(rc.key, key);
+                        free_all(vec![rc], &finalized);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let arena = unsafe { StrongPin::new_unchecked(arena) };
+    let rcs: Vec<_> = (0..CAPACITY)
+        .map(|key| get_hashed(arena, key).unwrap())
+        .collect();
+    for (key, rc) in rcs.iter().enumerate() {
+        assert_eq!(rc.key, key);
+        assert_eq!(
+            rcs.iter()
+                .filter(|other| std::ptr::eq(&***other, &**rc))
+                .count(),
+            1
+        );
+    }
+    free_all(rcs, &Cell::new(0));
+
+    drop(unsafe { Box::from_raw(ptr) });
+}
@@ -0,0 +1,297 @@
+//! Tests of `List`, including the provenance of the pointers that it returns.
+
+use std::cell::Cell;
+use std::pin::Pin;
+
+use kernel_rs_lib::intrusive_list::{List, ListEntry, ListNode};
+
+// `entry` must be the first field. See `from_list_entry`.
+#[repr(C)]
+struct Node {
+    entry: ListEntry,
+    value: Cell<usize>,
+}
+
+// SAFETY: `Node` owns a `ListEntry`.
+unsafe impl ListNode for Node {
+    fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+        unsafe { Pin::new_unchecked(&self.get_ref().entry) }
+    }
+
+    fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+        list_entry as _
+    }
+}
+
+fn node(value: usize) -> Pin<Box<Node>> {
+    let mut node = Box::pin(Node {
+        entry: unsafe { ListEntry::new() },
+        value: Cell::new(value),
+    });
+    unsafe { node.as_mut().map_unchecked_mut(|n| &mut n.entry) }.init();
+    node
+}
+
+fn list() -> Pin<Box<List<Node>>> {
+    let mut list = Box::pin(unsafe { List::new() });
+    list.as_mut().init();
+    list
+}
+
+fn values(list: Pin<&List<Node>>) -> Vec<usize> {
+    list.iter_ptr()
+        .map(|node| unsafe { (*node).value.get() })
+        .collect()
+}
+
+#[test]
+fn push_and_pop() {
+    let list = list();
+    let nodes: Vec<_> = (0..4).map(node).collect();
+    assert!(list.as_ref().is_empty());
+
+    list.as_ref().push_back(nodes[1].as_ref());
+    list.as_ref().push_back(nodes[2].as_ref());
+    list.as_ref().push_front(nodes[0].as_ref());
+    list.as_ref().push_back(nodes[3].as_ref());
+    assert_eq!(values(list.as_ref()), [0, 1, 2, 3]);
+
+    let front = list.as_ref().pop_front().unwrap();
+    assert!(std::ptr::eq(front, &*nodes[0]));
+    let back = list.as_ref().pop_back().unwrap();
+    assert!(std::ptr::eq(back, &*nodes[3]));
+    assert_eq!(values(list.as_ref()), [1, 2]);
+
+    list.as_ref().clear();
+    assert!(list.as_ref().is_empty());
+    assert!(list.as_ref().pop_front().is_none());
+    assert!(list.as_ref().pop_back().is_none());
+}
+
+#[test]
+fn push_moves_linked_node() {
+    let list = list();
+    let nodes: Vec<_> = (0..3).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+
+    list.as_ref().push_back(nodes[0].as_ref());
+    assert_eq!(values(list.as_ref()), [1, 2, 0]);
+    list.as_ref().push_front(nodes[2].as_ref());
+    assert_eq!(values(list.as_ref()), [2, 1, 0]);
+}
+
+#[test]
+fn dropped_node_is_unlinked() {
+    let list = list();
+    let mut nodes: Vec<_> = (0..3).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+
+    drop(nodes.remove(1));
+    assert_eq!(values(list.as_ref()), [0, 2]);
+    drop(nodes);
+    assert!(list.as_ref().is_empty());
+}
+
+#[test]
+fn dropped_list_unlinks_nodes() {
+    let list = list();
+    let nodes: Vec<_> = (0..2).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+
+    drop(list);
+    for node in &nodes {
+        assert!(node.as_ref().get_list_entry().is_unlinked());
+    }
+}
+
+#[test]
+fn iterate_both_ends() {
+    let list = list();
+    let nodes: Vec<_> = (0..4).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+
+    let rev: Vec<_> = list
+        .as_ref()
+        .iter_ptr()
+        .rev()
+        .map(|node| unsafe { (*node).value.get() })
+        .collect();
+    assert_eq!(rev, [3, 2, 1, 0]);
+
+    let mut iter = unsafe { list.as_ref().iter_unchecked() };
+    assert_eq!(iter.next().unwrap().value.get(), 0);
+    assert_eq!(iter.next_back().unwrap().value.get(), 3);
+    assert_eq!(iter.next().unwrap().value.get(), 1);
+    assert_eq!(iter.next_back().unwrap().value.get(), 2);
+    assert!(iter.next().is_none());
+    assert!(iter.next_back().is_none());
+}
+
+/// The pointers from the list cover the whole nodes, not only their `ListEntry`s, so that the
+/// nodes can be accessed through them. Under miri, this fails if they are derived from the
+/// `ListEntry`s.
+#[test]
+fn pointers_cover_nodes() {
+    let list = list();
+    let nodes: Vec<_> = (0..3).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_front(node.as_ref());
+    }
+
+    for node in list.as_ref().iter_ptr() {
+        let node = unsafe { &*node };
+        node.value.set(node.value.get() * 10);
+    }
+    assert_eq!(values(list.as_ref()), [20, 10, 0]);
+
+    let back = list.as_ref().back().unwrap();
+    assert_eq!(unsafe { (*back).value.get() }, 0);
+    let front = list.as_ref().pop_front().unwrap();
+    assert_eq!(unsafe { (*front).value.get() }, 20);
+    assert_eq!(nodes[2].value.get(), 20);
+}
+
+/// The nodes can be accessed through their owner while they are linked, and the list still
+/// works afterwards.
+#[test]
+fn owner_access_while_linked() {
+    let list = list();
+    let nodes: Vec<_> = (0..3).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+
+    for node in &nodes {
+        node.value.set(node.value.get() + 1);
+    }
+    assert_eq!(values(list.as_ref()), [1, 2, 3]);
+
+    list.as_ref().push_back(nodes[0].as_ref());
+    assert_eq!(values(list.as_ref()), [2, 3, 1]);
+}
+
+#[test]
+fn cursor_removes_while_iterating() {
+    let mut list = list();
+    let nodes: Vec<_> = (0..6).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+
+    let mut cursor = list.as_mut().cursor_front_mut();
+    while let Some(node) = cursor.current() {
+        if unsafe { (*node).value.get() } % 2 == 0 {
+            let removed = cursor.remove_current().unwrap();
+            assert!(std::ptr::eq(removed, node));
+        } else {
+            cursor.move_next();
+        }
+    }
+    assert!(cursor.remove_current().is_none());
+    assert_eq!(values(list.as_ref()), [1, 3, 5]);
+    for node in nodes.iter().step_by(2) {
+        assert!(node.as_ref().get_list_entry().is_unlinked());
+    }
+
+    // Removing the back node moves the cursor to the ghost position.
+    let mut cursor = list.as_mut().cursor_back_mut();
+    assert!(std::ptr::eq(cursor.remove_current().unwrap(), &*nodes[5]));
+    assert!(cursor.current().is_none());
+    cursor.move_next();
+    assert!(std::ptr::eq(cursor.current().unwrap(), &*nodes[1]));
+    assert_eq!(values(list.as_ref()), [1, 3]);
+}
+
+#[test]
+fn cursor_moves_through_ghost() {
+    let mut list = list();
+    assert!(list.as_mut().cursor_front_mut().current().is_none());
+
+    let nodes: Vec<_> = (0..2).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+    let mut cursor = list.as_mut().cursor_front_mut();
+    cursor.move_prev();
+    assert!(cursor.current().is_none());
+    cursor.move_prev();
+    assert!(std::ptr::eq(cursor.current().unwrap(), &*nodes[1]));
+    cursor.move_next();
+    cursor.move_next();
+    assert!(std::ptr::eq(cursor.current().unwrap(), &*nodes[0]));
+}
+
+#[test]
+fn cursor_splices() {
+    let other = list();
+    let mut list = list();
+    let nodes: Vec<_> = (0..6).map(node).collect();
+    list.as_ref().push_back(nodes[0].as_ref());
+    list.as_ref().push_back(nodes[1].as_ref());
+
+    let mut cursor = list.as_mut().cursor_front_mut();
+    other.as_ref().push_back(nodes[2].as_ref());
+    other.as_ref().push_back(nodes[3].as_ref());
+    cursor.splice_after(other.as_ref());
+    assert!(other.as_ref().is_empty());
+    assert!(std::ptr::eq(cursor.current().unwrap(), &*nodes[0]));
+
+    other.as_ref().push_back(nodes[4].as_ref());
+    cursor.splice_before(other.as_ref());
+    cursor.splice_after(other.as_ref());
+    assert!(other.as_ref().is_empty());
+
+    // At the ghost position, splicing after goes to the front and splicing before to the back.
+    cursor.move_prev();
+    cursor.move_prev();
+    assert!(cursor.current().is_none());
+    other.as_ref().push_back(nodes[5].as_ref());
+    cursor.splice_before(other.as_ref());
+    assert_eq!(values(list.as_ref()), [4, 0, 2, 3, 1, 5]);
+
+    // The spliced nodes can be removed and dropped as usual.
+    let mut cursor = list.as_mut().cursor_front_mut();
+    cursor.move_next();
+    cursor.move_next();
+    assert!(std::ptr::eq(cursor.remove_current().unwrap(), &*nodes[2]));
+    drop(nodes);
+    assert!(list.as_ref().is_empty());
+}
+
+/// A cursor never creates a reference to a node, so that references to the nodes from their owner
+/// stay valid while the cursor removes and splices them. Under miri, this fails if the cursor
+/// accesses the nodes through references.
+#[test]
+fn cursor_keeps_owner_references() {
+    let other = list();
+    let mut list = list();
+    let nodes: Vec<_> = (0..4).map(node).collect();
+    for node in &nodes {
+        list.as_ref().push_back(node.as_ref());
+    }
+    let values_of: Vec<&Cell<usize>> = nodes.iter().map(|node| &node.value).collect();
+
+    let mut cursor = list.as_mut().cursor_front_mut();
+    cursor.move_next();
+    let removed = cursor.remove_current().unwrap();
+    values_of[1].set(10);
+    assert_eq!(unsafe { (*removed).value.get() }, 10);
+
+    other
+        .as_ref()
+        .push_back(unsafe { Pin::new_unchecked(&*removed) });
+    cursor.splice_after(other.as_ref());
+    values_of[2].set(20);
+    cursor.move_next();
+    assert_eq!(unsafe { (*cursor.current().unwrap()).value.get() }, 10);
+    assert_eq!(values(list.as_ref()), [0, 20, 10, 3]);
+    assert_eq!(values_of.iter().map(|v| v.get()).sum::<usize>(), 33);
+}
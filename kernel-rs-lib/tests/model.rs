@@ -0,0 +1,219 @@
+//! Tests of `LockOrder` and `LiveGuards`, with the `model-check` feature.
+//!
+//! As loom does, the lock order tests run a few CPUs through every interleaving of their lock
+//! operations, and check that the outcome does not depend on the schedule: an inconsistent order
+//! panics in every interleaving, and a consistent one in none.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use kernel_rs_lib::model::{LiveGuards, LockOrder};
+
+const NCPU: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Acquire(&'static str),
+    Release(&'static str),
+}
+
+/// Returns every interleaving of the operations of the CPUs, as the indices of the CPUs that run
+/// them in order.
+fn interleavings(lens: &[usize]) -> Vec<Vec<usize>> {
+    fn go(left: &mut [usize], schedule: &mut Vec<usize>, out: &mut Vec<Vec<usize>>) {
+        if left.iter().all(|&n| n == 0) {
+            out.push(schedule.clone());
+            return;
+        }
+        for cpu in 0..left.len() {
+            if left[cpu] > 0 {
+                left[cpu] -= 1;
+                schedule.push(cpu);
+                go(left, schedule, out);
+                let _ = schedule.pop();
+                left[cpu] += 1;
+            }
+        }
+    }
+    let mut out = Vec::new();
+    go(&mut lens.to_vec(), &mut Vec::new(), &mut out);
+    out
+}
+
+/// Runs the operations of each CPU on a fresh `LockOrder` in the order of `schedule`.
+/// Returns true if it panicked.
+fn run(cpus: &[&[Op]], schedule: &[usize]) -> bool {
+    let order = LockOrder::<NCPU>::new();
+    let mut next = vec![0; cpus.len()];
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        for &cpu in schedule {
+            match cpus[cpu][next[cpu]] {
+                Op::Acquire(name) => order.acquire(cpu, name),
+                Op::Release(name) => order.release(cpu, name),
+            }
+            next[cpu] += 1;
+        }
+    }))
+    .is_err()
+}
+
+/// Returns the number of the interleavings of the operations of the CPUs, and of those that
+/// panicked.
+fn explore(cpus: &[&[Op]]) -> (usize, usize) {
+    let lens = cpus.iter().map(|ops| ops.len()).collect::<Vec<_>>();
+    let schedules = interleavings(&lens);
+    let panicked = schedules
+        .iter()
+        .filter(|schedule| run(cpus, schedule))
+        .count();
+    (schedules.len(), panicked)
+}
+
+/// Silences the expected panics.
+fn quiet<R>(f: impl FnOnce() -> R) -> R {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| ()));
+    let res = f();
+    panic::set_hook(hook);
+    res
+}
+
+use Op::{Acquire, Release};
+
+#[test]
+fn opposite_orders_panic_in_every_interleaving() {
+    let ab = [Acquire("a"), Acquire("b"), Release("b"), Release("a")];
+    let ba = [Acquire("b"), Acquire("a"), Release("a"), Release("b")];
+    let (total, panicked) = quiet(|| explore(&[&ab, &ba]));
+    assert_eq!(total, 70);
+    assert_eq!(panicked, total);
+}
+
+#[test]
+fn cycle_of_three_panics_in_every_interleaving() {
+    let ab = [Acquire("a"), Acquire("b"), Release("b"), Release("a")];
+    let bc = [Acquire("b"), Acquire("c"), Release("c"), Release("b")];
+    let ca = [Acquire("c"), Acquire("a"), Release("a"), Release("c")];
+    let (total, panicked) = quiet(|| explore(&[&ab, &bc, &ca]));
+    assert_eq!(total, 34650);
+    assert_eq!(panicked, total);
+}
+
+#[test]
+fn consistent_orders_never_panic() {
+    let abc = [
+        Acquire("a"),
+        Acquire("b"),
+        Acquire("c"),
+        Release("c"),
+        Release("b"),
+        Release("a"),
+    ];
+    let ac = [Acquire("a"), Acquire("c"), Release("a"), Release("c")];
+    let bc = [Acquire("b"), Release("b"), Acquire("c"), Release("c")];
+    let (total, panicked) = quiet(|| explore(&[&abc, &ac, &bc]));
+    assert_eq!(total, 210210);
+    assert_eq!(panicked, 0);
+}
+
+#[test]
+fn nesting_the_same_class() {
+    let order = LockOrder::<NCPU>::new();
+    order.acquire(0, "a");
+    order.acquire(0, "a");
+    order.acquire(0, "b");
+    order.release(0, "a");
+    order.release(0, "b");
+    order.release(0, "a");
+    order.acquire(1, "a");
+    order.acquire(1, "b");
+    order.release(1, "b");
+    order.release(1, "a");
+}
+
+#[test]
+#[should_panic(expected = "lock order: a acquired while holding b")]
+fn released_out_of_order() {
+    let order = LockOrder::<NCPU>::new();
+    order.acquire(0, "a");
+    order.acquire(0, "b");
+    // Releasing "a" first still leaves "b" held, under which "a" is acquired again.
+    order.release(0, "a");
+    order.acquire(0, "a");
+}
+
+#[test]
+#[should_panic(expected = "LockOrder::release: not held")]
+fn release_not_held() {
+    let order = LockOrder::<NCPU>::new();
+    order.acquire(0, "a");
+    order.release(1, "a");
+}
+
+#[test]
+fn concurrent_cpus_learn_one_order() {
+    let order = Arc::new(LockOrder::<NCPU>::new());
+    let barrier = Arc::new(Barrier::new(NCPU - 1));
+    let threads = (0..NCPU - 1)
+        .map(|cpu| {
+            let order = order.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let _ = barrier.wait();
+                for _ in 0..1000 {
+                    order.acquire(cpu, "outer");
+                    order.acquire(cpu, "inner");
+                    order.release(cpu, "inner");
+                    order.release(cpu, "outer");
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // The CPUs have recorded the order, and hold nothing any longer.
+    order.acquire(NCPU - 1, "outer");
+    order.acquire(NCPU - 1, "inner");
+    order.release(NCPU - 1, "inner");
+    order.release(NCPU - 1, "outer");
+    let reversed = quiet(|| {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            order.acquire(0, "inner");
+            order.acquire(0, "outer");
+        }))
+    });
+    assert!(reversed.is_err());
+}
+
+#[test]
+fn balanced_guards() {
+    let guards = LiveGuards::new();
+    guards.begin_tx();
+    guards.lock_inode();
+    guards.lock_inode();
+    guards.unlock_inode();
+    guards.unlock_inode();
+    guards.end_tx();
+    guards.check_none(1);
+}
+
+#[test]
+#[should_panic(expected = "system call 7 leaked 1 transactions and 0 inode guards")]
+fn leaked_tx() {
+    let guards = LiveGuards::new();
+    guards.begin_tx();
+    guards.check_none(7);
+}
+
+#[test]
+#[should_panic(expected = "system call 3 leaked 0 transactions and 1 inode guards")]
+fn leaked_inode_guard() {
+    let guards = LiveGuards::new();
+    guards.lock_inode();
+    guards.lock_inode();
+    guards.unlock_inode();
+    guards.check_none(3);
+}
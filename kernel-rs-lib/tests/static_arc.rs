@@ -0,0 +1,111 @@
+//! Tests of `StaticArc` and the `StrongPin`s that it is accessed through.
+
+use std::mem;
+
+use kernel_rs_lib::pinned_array;
+use kernel_rs_lib::static_arc::StaticArc;
+use kernel_rs_lib::strong_pin::StrongPinMut;
+
+fn pin_mut<T>(data: &mut T) -> StrongPinMut<'_, T> {
+    // SAFETY: `data` is not moved or accessed otherwise while the `StrongPinMut` lives.
+    unsafe { StrongPinMut::new_unchecked(data) }
+}
+
+#[test]
+fn borrow_and_get_mut() {
+    let mut arc = StaticArc::new(1);
+    let mut arc = pin_mut(&mut arc);
+    assert!(!arc.as_mut().is_borrowed());
+    *arc.as_mut().get_mut().unwrap() += 1;
+
+    let a = arc.as_mut().borrow();
+    let b = a.clone();
+    assert!(arc.as_mut().is_borrowed());
+    assert!(arc.as_mut().get_mut().is_none());
+    assert_eq!((*a, *b), (2, 2));
+
+    drop(a);
+    assert!(arc.as_mut().is_borrowed());
+    drop(b);
+    assert!(!arc.as_mut().is_borrowed());
+    assert_eq!(*arc.as_mut().get_mut().unwrap(), 2);
+}
+
+#[test]
+fn into_mut_only_from_last_ref() {
+    let mut arc = StaticArc::new(0);
+    let mut arc = pin_mut(&mut arc);
+
+    let a = arc.as_mut().borrow();
+    let b = arc.as_mut().borrow();
+    let a = a.into_mut().err().unwrap();
+    drop(b);
+
+    let mut m = a.into_mut().ok().unwrap();
+    *m = 3;
+    assert!(arc.as_mut().try_borrow().is_none());
+    assert!(arc.as_mut().is_borrowed());
+    drop(m);
+
+    assert_eq!(*arc.as_mut().borrow(), 3);
+    assert!(!arc.as_mut().is_borrowed());
+}
+
+/// A `Ref` stays valid while another `Ref` to the same data is taken and dropped through the
+/// `StrongPinMut`. Under miri, this fails if the `StrongPinMut` accesses the data.
+#[test]
+fn refs_alias_data() {
+    let mut arc = StaticArc::new([1, 2, 3]);
+    let mut arc = pin_mut(&mut arc);
+
+    let a = arc.as_mut().borrow();
+    let first: &i32 = &a[0];
+    for _ in 0..3 {
+        let b = arc.as_mut().try_borrow().unwrap();
+        assert_eq!(b[2], 3);
+    }
+    assert_eq!(*first, 1);
+}
+
+#[test]
+#[should_panic(expected = "dropped while borrowed")]
+fn drop_while_borrowed() {
+    let mut arc = StaticArc::new(0);
+    mem::forget(pin_mut(&mut arc).borrow());
+    drop(arc);
+}
+
+#[test]
+#[should_panic(expected = "already mutably borrowed")]
+fn borrow_while_borrowed_mut() {
+    let mut arc = StaticArc::new(0);
+    let mut arc = pin_mut(&mut arc);
+    let _m = arc.as_mut().borrow().into_mut().ok().unwrap();
+    let _ = arc.as_mut().borrow();
+}
+
+#[test]
+fn array_iter_mut() {
+    let mut arcs = [StaticArc::new(0), StaticArc::new(1), StaticArc::new(2)];
+    let mut arcs = pin_mut(&mut arcs);
+    let refs: Vec<_> = arcs.as_mut().iter_mut().map(|arc| arc.borrow()).collect();
+    assert_eq!(refs.iter().map(|r| **r).collect::<Vec<_>>(), [0, 1, 2]);
+
+    // Taking the elements again does not invalidate the `Ref`s.
+    for mut arc in arcs.as_mut().iter_mut() {
+        assert!(arc.as_mut().is_borrowed());
+    }
+    assert_eq!(*refs[1], 1);
+    drop(refs);
+}
+
+#[test]
+fn pinned_array_bounds() {
+    let mut arr = Box::pin([0, 1, 2]);
+    *pinned_array::get_pin_mut(arr.as_mut(), 2).unwrap() = 5;
+    assert!(pinned_array::get_pin_mut(arr.as_mut(), 3).is_none());
+    let values: Vec<_> = pinned_array::IterPinMut::from(arr.as_mut())
+        .map(|x| *x)
+        .collect();
+    assert_eq!(values, [0, 1, 5]);
+}
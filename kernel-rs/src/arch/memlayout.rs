@@ -4,6 +4,7 @@
 //! based on qemu's hw/riscv/virt.c:
 //!
 //! 00001000 -- boot ROM, provided by qemu
+//! 00101000 -- goldfish RTC
 //! 02000000 -- CLINT
 //! 0C000000 -- PLIC
 //! 10000000 -- uart0
@@ -19,11 +20,17 @@
 // Dead code is allowed in this file because not all components are used in the kernel.
 #![allow(dead_code)]
 
+use static_assertions::const_assert;
+
 use crate::arch::addr::{MAXVA, PGSIZE};
+use crate::param::{NPROC, NTHREAD};
 
 /// SiFive Test Finisher. (virt device only)
 pub const FINISHER: usize = 0x100000;
 
+/// goldfish real-time clock.
+pub const RTC: usize = 0x101000;
+
 /// qemu puts UART registers here in physical memory.
 pub const UART0: usize = 0x10000000;
 pub const UART0_IRQ: usize = 10;
@@ -40,6 +47,10 @@ pub const fn clint_mtimecmp(hartid: usize) -> usize {
         .wrapping_add(hartid.wrapping_mul(8))
 }
 
+pub const fn clint_msip(hartid: usize) -> usize {
+    CLINT.wrapping_add(hartid.wrapping_mul(4))
+}
+
 /// cycles since boot.
 pub const CLINT_MTIME: usize = CLINT.wrapping_add(0xbff8);
 
@@ -77,6 +88,17 @@ pub const fn kstack(p: usize) -> usize {
     TRAMPOLINE - ((p + 1) * 2 * PGSIZE)
 }
 
+// The kernel stacks of all processes fit above the RAM, which the kernel maps at its physical
+// address.
+const_assert!(NPROC < (TRAMPOLINE - PHYSTOP) / (2 * PGSIZE));
+
+/// the kernel maps device registers and pages at run time
+/// (see KernelMemory::ioremap) at the addresses between
+/// the RAM and the kernel stacks, leaving a guard page below
+/// the lowest stack.
+pub const VMAP_BASE: usize = PHYSTOP;
+pub const VMAP_END: usize = kstack(NPROC - 1) - PGSIZE;
+
 /// User memory layout.
 /// Address zero first:
 ///   text
@@ -84,6 +106,17 @@ pub const fn kstack(p: usize) -> usize {
 ///   fixed-size stack
 ///   expandable heap
 ///   ...
+///   mmap()ed pages, placed downward from TRAPFRAMES
+///   TRAPFRAMES (trap frames of the threads sharing the memory, see trap_frame)
 ///   TRAPFRAME (p->trapframe, used by the trampoline)
 ///   TRAMPOLINE (the same page as in the kernel)
 pub const TRAPFRAME: usize = TRAMPOLINE.wrapping_sub(PGSIZE);
+
+/// map the trap frame of each thread sharing a user memory
+/// at its own slot, downward from TRAPFRAME, which is slot 0.
+pub const fn trap_frame(slot: usize) -> usize {
+    TRAPFRAME - slot * PGSIZE
+}
+
+/// the lowest trap frame slot. user pages lie below it.
+pub const TRAPFRAMES: usize = trap_frame(NTHREAD - 1);
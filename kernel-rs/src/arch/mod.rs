@@ -5,3 +5,53 @@ pub mod memlayout;
 pub mod plic;
 pub mod poweroff;
 pub mod riscv;
+pub mod timer;
+pub mod tlb;
+
+/// Cache and TLB maintenance, which the virtual memory calls through `TargetArch`, and power
+/// management.
+pub trait Arch {
+    /// Flushes the translations of the page at `va` from the TLBs of every CPU that may have
+    /// cached them for the page table in `satp`.
+    fn flush_tlb_page(satp: usize, va: usize);
+
+    /// Flushes every translation from the TLBs of every CPU that may have cached them for the
+    /// page table in `satp`.
+    fn flush_tlb_all(satp: usize);
+
+    /// Flushes the translations of the page at `va` of the kernel page table from the TLBs of
+    /// every CPU, and waits for them. The caller must not hold a spin lock, which another CPU may
+    /// be waiting for with interrupts disabled.
+    fn flush_kernel_tlb_page(va: usize);
+
+    /// Makes every CPU fetch the instructions stored to [start, end) since the last call before
+    /// it executes them.
+    fn sync_icache_range(start: usize, end: usize);
+
+    /// Waits until an interrupt is pending, to save power while there is nothing to run. Called
+    /// with interrupts disabled, which does not keep a pending interrupt from ending the wait.
+    fn wait_for_interrupt();
+
+    /// Powers off the machine with the exit code `code`, discarding all unsaved data.
+    fn power_off(code: u16) -> !;
+
+    /// Resets the machine, discarding all unsaved data.
+    fn reboot() -> !;
+}
+
+/// The timer of each CPU, which the timer wheel, the one-shot timer and the clocks use through
+/// `TargetArch`. Time is counted in timer cycles.
+pub trait TimeManager {
+    /// Returns the frequency of the timer, in cycles per second.
+    fn freq() -> u64;
+
+    /// Returns the current time, in cycles since the boot.
+    fn now() -> u64;
+
+    /// Raises a timer interrupt on the current CPU `delta` cycles from now, unless one is already
+    /// due earlier. Interrupts must be disabled, so that the CPU does not change.
+    fn set_next_event(delta: u64);
+}
+
+/// The architecture the kernel runs on.
+pub use tlb::RiscV as TargetArch;
@@ -1,10 +1,19 @@
 //! the riscv Platform Level Interrupt Controller (PLIC).
-use crate::arch::{
-    memlayout::{plic_sclaim, plic_senable, plic_spriority, PLIC, UART0_IRQ, VIRTIO0_IRQ},
-    riscv::r_tp,
+use core::pin::Pin;
+
+use crate::{
+    arch::{
+        memlayout::{plic_sclaim, plic_senable, plic_spriority, PLIC, UART0_IRQ, VIRTIO0_IRQ},
+        riscv::r_tp,
+    },
+    init_call,
+    initcall::InitPhase,
+    kernel::Kernel,
 };
 
-pub unsafe fn plicinit() {
+init_call!(InitPhase::Device, plicinit);
+
+unsafe fn plicinit(_: Pin<&mut Kernel>) {
     // set desired IRQ priorities non-zero (otherwise disabled).
     unsafe { *((PLIC.wrapping_add(UART0_IRQ.wrapping_mul(4))) as *mut u32) = 1 };
     unsafe { *((PLIC + VIRTIO0_IRQ * 4) as *mut u32) = 1 };
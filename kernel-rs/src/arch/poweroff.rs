@@ -7,7 +7,21 @@ use crate::arch::memlayout;
 /// This function uses SiFive Test Finalizer, which provides power management for QEMU virt device.
 pub fn machine_poweroff(exitcode: u16) -> ! {
     const BASE_CODE: u32 = 0x3333;
-    let code = ((exitcode as u32) << 16) | BASE_CODE;
+    finish(((exitcode as u32) << 16) | BASE_CODE);
+    unreachable!("Power off failed");
+}
+
+/// Resets this machine, discarding all unsaved data. It boots again from the start.
+///
+/// Like `machine_poweroff`, this function uses SiFive Test Finalizer.
+pub fn machine_reboot() -> ! {
+    const RESET_CODE: u32 = 0x7777;
+    finish(RESET_CODE);
+    unreachable!("Reboot failed");
+}
+
+/// Writes `code` to SiFive Test Finalizer.
+fn finish(code: u32) {
     // SAFETY:
     // - FINISHER is identically mapped from physical address.
     // - FINISHER is for MMIO. Though this is not specified as document, see the implementation:
@@ -16,6 +30,4 @@ pub fn machine_poweroff(exitcode: u16) -> ! {
     unsafe {
         ptr::write_volatile(memlayout::FINISHER as *mut u32, code);
     }
-
-    unreachable!("Power off failed");
 }
@@ -260,8 +260,12 @@ pub unsafe fn w_mtvec(x: usize) {
 /// Use riscv's sv39 page table scheme.
 pub const SATP_SV39: usize = (8) << 60;
 
-pub const fn make_satp(pagetable: usize) -> usize {
-    SATP_SV39 | pagetable >> 12
+/// The ASID field of satp starts at bit 44, and is at most 16 bits wide.
+pub const SATP_ASID_SHIFT: usize = 44;
+pub const SATP_ASID_MASK: usize = 0xffff << SATP_ASID_SHIFT;
+
+pub const fn make_satp(pagetable: usize, asid: usize) -> usize {
+    SATP_SV39 | asid << SATP_ASID_SHIFT | pagetable >> 12
 }
 
 /// Supervisor address translation and protection;
@@ -411,3 +415,36 @@ pub unsafe fn sfence_vma() {
         asm!("sfence.vma zero, zero");
     }
 }
+
+/// Flush the TLB entries of the page at `va`.
+#[inline]
+pub unsafe fn sfence_vma_page(va: usize) {
+    unsafe {
+        asm!("sfence.vma {}, zero", in(reg) va);
+    }
+}
+
+/// Flush the TLB entries of the address space `asid`, except the global ones.
+#[inline]
+pub unsafe fn sfence_vma_asid(asid: usize) {
+    unsafe {
+        asm!("sfence.vma zero, {}", in(reg) asid);
+    }
+}
+
+/// Wait for an interrupt. Returns once an interrupt enabled in sie is pending, even if sstatus
+/// disables interrupts, or possibly earlier.
+#[inline]
+pub fn wfi() {
+    unsafe {
+        asm!("wfi");
+    }
+}
+
+/// Synchronize the instruction cache with the stores to memory.
+#[inline]
+pub unsafe fn fence_i() {
+    unsafe {
+        asm!("fence.i");
+    }
+}
@@ -0,0 +1,179 @@
+//! The timer of each CPU.
+//!
+//! A CPU has a comparator in the CLINT, which raises a machine-mode timer interrupt once the time
+//! reaches it. `timervec` in kernelvec.S handles the interrupt and forwards it to the kernel as a
+//! supervisor software interrupt. The comparator serves two timers: the periodic tick, and a
+//! one-shot timer that the kernel arms for deadlines finer than a tick. `timervec` programs the
+//! comparator with the earlier of the two, and flags which of them expired for `take_expired`.
+//!
+//! `timervec` also handles the machine-mode software interrupts, by which a CPU interrupts
+//! another with `send_ipi`, and forwards them the same way, flagged for `take_ipi`.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+
+use super::{riscv::r_time, tlb::RiscV, TimeManager};
+use crate::{
+    arch::memlayout::{clint_msip, clint_mtimecmp, CLINT_MTIME},
+    cpu::cpuid,
+    param::NCPU,
+};
+
+/// Timer cycles per second, i.e., the 10MHz timer of qemu's virt machine.
+pub const CYCLES_PER_SEC: u64 = 10_000_000;
+
+/// Cycles between ticks; about 1/10th second in qemu.
+pub const TICK_INTERVAL: u64 = 1_000_000;
+
+/// Microseconds per tick.
+pub const US_PER_TICK: u64 = TICK_INTERVAL * 1_000_000 / CYCLES_PER_SEC;
+
+/// The scratch area of `timervec` for a CPU. kernelvec.S knows the layout, and reads the fields
+/// that the kernel only writes.
+#[allow(dead_code)]
+#[repr(C)]
+struct TimerScratch {
+    /// Space for `timervec` to save registers.
+    save: [usize; 3],
+
+    /// Address of the CLINT MTIMECMP register of the CPU.
+    mtimecmp: usize,
+
+    /// Cycles between ticks.
+    interval: usize,
+
+    /// Time of the next tick.
+    next_tick: usize,
+
+    /// Deadline of the one-shot timer, or `usize::MAX` if it is not armed.
+    oneshot: AtomicUsize,
+
+    /// Set to 1 by `timervec` when a tick has passed.
+    ticked: AtomicUsize,
+
+    /// Set to 1 by `timervec` when the one-shot timer has expired, which disarms it.
+    expired: AtomicUsize,
+
+    /// Address of the CLINT MSIP register of the CPU.
+    msip: usize,
+
+    /// Set to 1 by `timervec` when another CPU has interrupted this one.
+    ipi: AtomicUsize,
+}
+
+impl TimerScratch {
+    const fn new() -> Self {
+        Self {
+            save: [0; 3],
+            mtimecmp: 0,
+            interval: 0,
+            next_tick: 0,
+            oneshot: AtomicUsize::new(usize::MAX),
+            ticked: AtomicUsize::new(0),
+            expired: AtomicUsize::new(0),
+            msip: 0,
+            ipi: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// A scratch area per CPU for machine-mode timer interrupts.
+static mut TIMER_SCRATCH: [TimerScratch; NCPU] = array![_ => TimerScratch::new(); NCPU];
+
+/// Asks the CLINT for the first tick of the CPU `id`.
+/// Returns the address of its scratch area, for mscratch.
+///
+/// # Safety
+///
+/// Must be called once by the CPU `id` in machine mode, before it enables timer interrupts.
+pub unsafe fn init(id: usize) -> usize {
+    // SAFETY: timervec does not run on this CPU yet, and the other CPUs use their own areas.
+    let scratch = unsafe { &mut TIMER_SCRATCH[id] };
+    scratch.mtimecmp = clint_mtimecmp(id);
+    scratch.msip = clint_msip(id);
+    scratch.interval = TICK_INTERVAL as usize;
+    scratch.next_tick =
+        unsafe { ptr::read_volatile(CLINT_MTIME as *const usize) } + TICK_INTERVAL as usize;
+    unsafe { ptr::write_volatile(scratch.mtimecmp as *mut usize, scratch.next_tick) };
+    scratch as *mut _ as usize
+}
+
+fn current_scratch() -> &'static TimerScratch {
+    // SAFETY: only `init` takes a mutable reference, before the CPU runs the kernel.
+    unsafe { &TIMER_SCRATCH[cpuid()] }
+}
+
+/// Arms the one-shot timer of the current CPU to expire at `deadline`, unless it is already
+/// armed to expire earlier. Interrupts must be disabled, so that the CPU does not change.
+pub fn arm_oneshot(deadline: u64) {
+    let scratch = current_scratch();
+    // Store the deadline before programming the comparator, so that `timervec` keeps it even if
+    // the comparator fires in between.
+    let _ = scratch
+        .oneshot
+        .fetch_min(deadline as usize, Ordering::Relaxed);
+    let mtimecmp = scratch.mtimecmp as *mut usize;
+    // SAFETY: the CLINT is mapped in the kernel page table, and each CPU writes only its own
+    // comparator. If `timervec` fires between the read and the write, the comparator may be
+    // set in the past, but then `timervec` fires again at once and programs it anew.
+    unsafe {
+        let oneshot = scratch.oneshot.load(Ordering::Relaxed);
+        if oneshot < ptr::read_volatile(mtimecmp) {
+            ptr::write_volatile(mtimecmp, oneshot);
+        }
+    }
+}
+
+impl TimeManager for RiscV {
+    fn freq() -> u64 {
+        CYCLES_PER_SEC
+    }
+
+    fn now() -> u64 {
+        r_time()
+    }
+
+    fn set_next_event(delta: u64) {
+        arm_oneshot(r_time().saturating_add(delta));
+    }
+}
+
+/// Takes the expirations that `timervec` flagged on the current CPU.
+/// Returns (whether a tick has passed, whether the one-shot timer has expired).
+/// Interrupts must be disabled, so that the CPU does not change.
+pub fn take_expired() -> (bool, bool) {
+    let scratch = current_scratch();
+    (
+        scratch.ticked.swap(0, Ordering::Relaxed) != 0,
+        scratch.expired.swap(0, Ordering::Relaxed) != 0,
+    )
+}
+
+/// Interrupts the CPU `id`, which finds `take_ipi` true in its interrupt handler.
+pub fn send_ipi(id: usize) {
+    // SAFETY: the CLINT is mapped in the kernel page table.
+    unsafe { ptr::write_volatile(clint_msip(id) as *mut u32, 1) };
+}
+
+/// Takes the interrupt from another CPU that `timervec` flagged on the current CPU.
+/// Returns whether there was one. Interrupts must be disabled, so that the CPU does not change.
+pub fn take_ipi() -> bool {
+    current_scratch().ipi.swap(0, Ordering::Relaxed) != 0
+}
+
+/// Converts timer cycles to (seconds, nanoseconds).
+pub fn cycles_to_timespec(cycles: u64) -> (u64, u64) {
+    let freq = RiscV::freq();
+    (cycles / freq, (cycles % freq) * 1_000_000_000 / freq)
+}
+
+/// Converts seconds and nanoseconds to timer cycles, rounding up.
+pub fn timespec_to_cycles(sec: u64, nsec: u64) -> u64 {
+    let freq = RiscV::freq();
+    sec.saturating_mul(freq)
+        .saturating_add((nsec * freq + 999_999_999) / 1_000_000_000)
+}
@@ -0,0 +1,348 @@
+//! TLB and instruction cache maintenance on RISC-V.
+//!
+//! Each user memory runs with its own address-space ID (ASID), so that the TLB of a CPU keeps
+//! the translations of a user page table across traps and context switches, tagged with its ASID,
+//! and the trampoline switches `satp` without flushing (see trampoline.S). The kernel page table
+//! uses ASID 0. A CPU without ASIDs runs every user memory with ASID 0 too, and then the trampoline
+//! flushes its TLB whenever it switches `satp`, as before.
+//!
+//! ASIDs are allocated in generations. When a generation runs out of them, a new one begins, and
+//! each CPU flushes its whole TLB before it runs user code with an ASID of the new generation. A
+//! memory whose ASID belongs to an old generation gets a new one when it next runs, so an ASID is
+//! reused only after every CPU has forgotten its old translations.
+//!
+//! Hence, changing a valid entry of a user page table requires flushing the translations from the
+//! TLBs of every CPU that has run the memory. A CPU running in user mode with the page table, i.e.,
+//! a thread sharing the memory, is flushed at once: the CPU making the change records the flush in
+//! the `Shootdown` of the CPU, interrupts it with an IPI, and waits until it has flushed or has
+//! left user mode. The other CPUs are flushed lazily: the ASID is marked stale on each of them, and
+//! a CPU flushes the translations of a stale ASID before it runs user code with it.
+//!
+//! The instruction cache, on the other hand, is synchronized lazily. A store to a page that will
+//! be executed bumps a generation, and each CPU executes `fence.i` before returning to user mode
+//! if it has not seen the current generation yet.
+
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use super::{
+    poweroff::{machine_poweroff, machine_reboot},
+    riscv::{
+        fence_i, make_satp, r_satp, sfence_vma, sfence_vma_asid, sfence_vma_page, w_satp, wfi,
+        SATP_ASID_MASK, SATP_ASID_SHIFT,
+    },
+    timer::send_ipi,
+    Arch,
+};
+use crate::{cpu::cpuid, param::NCPU};
+
+/// No flush is pending.
+const FLUSH_NONE: usize = 0;
+/// A flush of every page is pending. A flush of a page is pending as its address plus 1.
+const FLUSH_ALL: usize = usize::MAX;
+
+/// Largest number of ASIDs to use, including 0 for the kernel, even if the CPUs support more.
+const MAX_ASIDS: usize = 256;
+
+/// An ASID tagged with its generation is `generation << GENERATION_SHIFT | asid`.
+const GENERATION_SHIFT: usize = 16;
+const ASID_MASK: usize = (1 << GENERATION_SHIFT) - 1;
+
+/// Number of ASIDs that the CPUs support, up to `MAX_ASIDS`. Set by `init_asids`.
+static NASID: AtomicUsize = AtomicUsize::new(MAX_ASIDS);
+
+/// The tagged ASID to allocate next. Its generation is the current one. Generations start at 1,
+/// so that a tagged ASID is never 0.
+static NEXT_ASID: AtomicUsize = AtomicUsize::new(1 << GENERATION_SHIFT | 1);
+
+struct Shootdown {
+    /// The `satp` the CPU runs user code with, or 0 while in the kernel.
+    user_satp: AtomicUsize,
+
+    /// The flush pending on the CPU.
+    pending: AtomicUsize,
+
+    /// Whether the CPU has executed `fence.i` since the last store to executable pages.
+    icache_synced: AtomicBool,
+
+    /// The last generation of ASIDs the CPU has flushed its TLB for.
+    generation: AtomicUsize,
+
+    /// Bitmap of the ASIDs whose translations the CPU must flush before it uses them.
+    stale: [AtomicU64; MAX_ASIDS / 64],
+}
+
+impl Shootdown {
+    const fn new() -> Self {
+        Self {
+            user_satp: AtomicUsize::new(0),
+            pending: AtomicUsize::new(FLUSH_NONE),
+            icache_synced: AtomicBool::new(true),
+            generation: AtomicUsize::new(0),
+            stale: array![_ => AtomicU64::new(0); MAX_ASIDS / 64],
+        }
+    }
+
+    fn mark_stale(&self, asid: usize) {
+        let _ = self.stale[asid / 64].fetch_or(1 << (asid % 64), Ordering::SeqCst);
+    }
+
+    /// Clears the stale mark of `asid`, and returns whether it was marked.
+    fn take_stale(&self, asid: usize) -> bool {
+        let bit = 1 << (asid % 64);
+        self.stale[asid / 64].fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+}
+
+static SHOOTDOWNS: [Shootdown; NCPU] = array![_ => Shootdown::new(); NCPU];
+
+/// The ASID of a user memory, tagged with its generation, or 0 if it has none yet.
+pub struct Asid {
+    tag: AtomicUsize,
+}
+
+impl Asid {
+    pub const fn new() -> Self {
+        Self {
+            tag: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the ASID, which may belong to an old generation, or 0 if there is none.
+    pub fn get(&self) -> usize {
+        self.tag.load(Ordering::SeqCst) & ASID_MASK
+    }
+}
+
+/// Returns the page table that `satp` refers to, without its ASID.
+fn satp_root(satp: usize) -> usize {
+    satp & !SATP_ASID_MASK
+}
+
+/// Returns the ASID in `satp`.
+fn satp_asid(satp: usize) -> usize {
+    (satp & SATP_ASID_MASK) >> SATP_ASID_SHIFT
+}
+
+/// Allocates a tagged ASID of the current generation, beginning a new one if it has run out.
+fn alloc_asid() -> usize {
+    let nasid = NASID.load(Ordering::Relaxed);
+    let mut next = NEXT_ASID.load(Ordering::SeqCst);
+    loop {
+        let tag = if next & ASID_MASK < nasid {
+            next
+        } else {
+            ((next >> GENERATION_SHIFT) + 1) << GENERATION_SHIFT | 1
+        };
+        match NEXT_ASID.compare_exchange_weak(next, tag + 1, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => return tag,
+            Err(current) => next = current,
+        }
+    }
+}
+
+/// Finds out how many ASIDs the current CPU supports, by writing every bit of the ASID field of
+/// `satp` and reading them back. The CPUs are assumed to agree.
+///
+/// # Safety
+///
+/// `satp` must hold the kernel page table, with ASID 0.
+pub unsafe fn init_asids() {
+    let satp = r_satp();
+    let bits = unsafe {
+        w_satp(satp | SATP_ASID_MASK);
+        let bits = satp_asid(r_satp()).count_ones();
+        w_satp(satp);
+        sfence_vma();
+        bits
+    };
+    let _ = NASID.fetch_min(1 << bits, Ordering::Relaxed);
+}
+
+/// RISC-V, which the kernel runs on.
+pub struct RiscV;
+
+impl RiscV {
+    /// Flushes the TLBs of the other CPUs that may have cached translations of the user page
+    /// table in `satp`, with `flush`.
+    fn shootdown(satp: usize, flush: usize) {
+        let me = cpuid();
+        let asid = satp_asid(satp);
+        if asid != 0 {
+            for (id, shootdown) in SHOOTDOWNS.iter().enumerate() {
+                if id != me {
+                    shootdown.mark_stale(asid);
+                }
+            }
+        }
+
+        // Order the stores to the page table and the stale marks before reading `user_satp`. A
+        // CPU that stores `satp` to it after this reads it finds the mark afterwards, or flushes
+        // its TLB in the trampoline if it has no ASIDs.
+        fence(Ordering::SeqCst);
+        let mut targets = [false; NCPU];
+        for (id, shootdown) in SHOOTDOWNS.iter().enumerate() {
+            if id == me || satp_root(shootdown.user_satp.load(Ordering::SeqCst)) != satp_root(satp)
+            {
+                continue;
+            }
+            let _ = shootdown
+                .pending
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                    Some(if pending == FLUSH_NONE || pending == flush {
+                        flush
+                    } else {
+                        FLUSH_ALL
+                    })
+                });
+            send_ipi(id);
+            targets[id] = true;
+        }
+        for (id, shootdown) in SHOOTDOWNS.iter().enumerate() {
+            while targets[id]
+                && shootdown.pending.load(Ordering::SeqCst) != FLUSH_NONE
+                && satp_root(shootdown.user_satp.load(Ordering::SeqCst)) == satp_root(satp)
+            {
+                ::core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Prepares the current CPU to run user code with the page table at `root`, whose memory has
+    /// `asid`, and returns the `satp` to run it with. Gives the memory an ASID of the current
+    /// generation if it has none, and flushes the stale translations from the TLB. The CPU runs
+    /// with `satp` from now on, until `leave_user`.
+    /// Called with interrupts disabled right before switching to user mode.
+    pub fn enter_user(asid: &Asid, root: usize) -> usize {
+        let shootdown = &SHOOTDOWNS[cpuid()];
+        let satp = if NASID.load(Ordering::Relaxed) < 2 {
+            make_satp(root, 0)
+        } else {
+            loop {
+                let generation = NEXT_ASID.load(Ordering::SeqCst) >> GENERATION_SHIFT;
+                if shootdown.generation.swap(generation, Ordering::SeqCst) != generation {
+                    // The ASIDs of the old generations may be reused from now on.
+                    for stale in &shootdown.stale {
+                        stale.store(0, Ordering::SeqCst);
+                    }
+                    // SAFETY: flushing the TLB only makes the CPU walk the page tables again.
+                    unsafe { sfence_vma() };
+                }
+                let mut tag = asid.tag.load(Ordering::SeqCst);
+                if tag >> GENERATION_SHIFT != generation {
+                    // Another thread of the memory may be doing the same on another CPU.
+                    let new = alloc_asid();
+                    tag = match asid.tag.compare_exchange(
+                        tag,
+                        new,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    ) {
+                        Ok(_) => new,
+                        Err(current) => current,
+                    };
+                }
+                // Otherwise, a new generation has begun meanwhile, which this CPU must flush for.
+                if tag >> GENERATION_SHIFT == generation {
+                    break make_satp(root, tag & ASID_MASK);
+                }
+            }
+        };
+
+        shootdown.user_satp.store(satp, Ordering::SeqCst);
+        let asid = satp_asid(satp);
+        if asid != 0 && shootdown.take_stale(asid) {
+            // SAFETY: flushing the TLB only makes the CPU walk the page tables again.
+            unsafe { sfence_vma_asid(asid) };
+        }
+        if !shootdown.icache_synced.swap(true, Ordering::SeqCst) {
+            // SAFETY: fence.i has no effect other than synchronizing the instruction cache.
+            unsafe { fence_i() };
+        }
+        satp
+    }
+
+    /// Records that the current CPU has trapped into the kernel, where it does not use the
+    /// translations of user page tables.
+    pub fn leave_user() {
+        SHOOTDOWNS[cpuid()].user_satp.store(0, Ordering::SeqCst);
+    }
+
+    /// Performs the flush that another CPU has requested from the current CPU.
+    /// Called by the interrupt handler of an IPI.
+    pub fn handle_shootdown() {
+        let flush = SHOOTDOWNS[cpuid()]
+            .pending
+            .swap(FLUSH_NONE, Ordering::SeqCst);
+        // SAFETY: flushing the TLB only makes the CPU walk the page tables again.
+        match flush {
+            FLUSH_NONE => (),
+            FLUSH_ALL => unsafe { sfence_vma() },
+            page => unsafe { sfence_vma_page(page - 1) },
+        }
+    }
+}
+
+impl Arch for RiscV {
+    fn flush_tlb_page(satp: usize, va: usize) {
+        // SAFETY: flushing the TLB only makes the CPU walk the page tables again. This flushes
+        // the page for every ASID.
+        unsafe { sfence_vma_page(va) };
+        Self::shootdown(satp, va + 1);
+    }
+
+    fn flush_tlb_all(satp: usize) {
+        // SAFETY: flushing the TLB only makes the CPU walk the page tables again.
+        unsafe { sfence_vma() };
+        Self::shootdown(satp, FLUSH_ALL);
+    }
+
+    fn flush_kernel_tlb_page(va: usize) {
+        // SAFETY: flushing the TLB only makes the CPU walk the page tables again.
+        unsafe { sfence_vma_page(va) };
+        // We may move to another CPU meanwhile, and then interrupt ourselves, which is harmless.
+        let me = cpuid();
+        for (id, shootdown) in SHOOTDOWNS.iter().enumerate() {
+            if id == me {
+                continue;
+            }
+            let _ = shootdown
+                .pending
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                    Some(if pending == FLUSH_NONE || pending == va + 1 {
+                        va + 1
+                    } else {
+                        FLUSH_ALL
+                    })
+                });
+            send_ipi(id);
+        }
+        for shootdown in SHOOTDOWNS.iter() {
+            while shootdown.pending.load(Ordering::SeqCst) != FLUSH_NONE {
+                // Another CPU may be waiting for us likewise, with interrupts disabled.
+                Self::handle_shootdown();
+                ::core::hint::spin_loop();
+            }
+        }
+    }
+
+    fn sync_icache_range(_start: usize, _end: usize) {
+        // RISC-V synchronizes the whole instruction cache at once.
+        for shootdown in SHOOTDOWNS.iter() {
+            shootdown.icache_synced.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn wait_for_interrupt() {
+        wfi();
+    }
+
+    fn power_off(code: u16) -> ! {
+        machine_poweroff(code)
+    }
+
+    fn reboot() -> ! {
+        machine_reboot()
+    }
+}
@@ -0,0 +1,11 @@
+//! The arenas of the kernel, i.e., those of `kernel_rs_lib::arena` protected by spin locks.
+
+pub use kernel_rs_lib::arena::{Arena, ArenaKey, ArenaObject, ArenaRc};
+
+use crate::lock::RawSpinLock;
+
+pub type ArrayArena<T, const CAPACITY: usize> =
+    kernel_rs_lib::arena::ArrayArena<T, RawSpinLock, CAPACITY>;
+
+pub type ShardedArena<T, const CAPACITY: usize, const SHARDS: usize> =
+    kernel_rs_lib::arena::ShardedArena<T, RawSpinLock, CAPACITY, SHARDS>;
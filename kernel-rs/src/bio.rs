@@ -1,7 +1,8 @@
 //! Buffer cache.
 //!
-//! The buffer cache is a linked list of buf structures holding cached copies of disk block
-//! contents.  Caching disk blocks in memory reduces the number of disk reads and also provides a
+//! The buffer cache is a hash table of linked lists of buf structures holding cached copies of
+//! disk block contents. Each bucket has its own lock, so that looking up different blocks rarely
+//! contends.  Caching disk blocks in memory reduces the number of disk reads and also provides a
 //! synchronization point for disk blocks used by multiple processes.
 //!
 //! Interface:
@@ -13,13 +14,17 @@
 
 use core::mem::{self, ManuallyDrop};
 use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+
+use array_macro::array;
 
 use crate::arena::ArenaRc;
 use crate::util::strong_pin::StrongPin;
 use crate::{
-    arena::{Arena, ArenaObject, MruArena},
-    lock::SleepLock,
-    param::{BSIZE, NBUF},
+    arena::{ArenaKey, ArenaObject, ShardedArena},
+    fcount,
+    lock::{RawSpinLock, SleepLock},
+    param::{BSIZE, NBUCKET, NBUF},
     proc::{KernelCtx, WaitChannel},
 };
 
@@ -42,6 +47,19 @@ impl BufEntry {
             inner: SleepLock::new("buffer", BufInner::new()),
         }
     }
+
+    /// Returns the hash of the block blockno on device dev.
+    fn hash(dev: u32, blockno: u32) -> usize {
+        (dev as usize)
+            .wrapping_mul(31)
+            .wrapping_add(blockno as usize)
+    }
+}
+
+impl ArenaKey for BufEntry {
+    fn key_hash(&self) -> usize {
+        Self::hash(self.dev, self.blockno)
+    }
 }
 
 impl const Default for BufEntry {
@@ -100,10 +118,12 @@ impl BufInner {
     }
 }
 
-pub type Bcache = MruArena<BufEntry, NBUF>;
+pub struct Bcache {
+    arena: ShardedArena<BufEntry, NBUF, NBUCKET>,
+}
 
 /// A reference counted smart pointer to a `BufEntry`.
-pub struct BufUnlocked(ManuallyDrop<ArenaRc<Bcache>>);
+pub struct BufUnlocked(ManuallyDrop<ArenaRc<ShardedArena<BufEntry, NBUF, NBUCKET>>>);
 
 /// A locked `BufEntry`.
 ///
@@ -184,15 +204,40 @@ impl Drop for Buf {
 impl Bcache {
     /// # Safety
     ///
-    /// Must be used only after initializing it with `MruArena::init`.
+    /// Must be used only after initializing it with `Bcache::init`.
     pub const unsafe fn new_bcache() -> Self {
-        unsafe { MruArena::<BufEntry, NBUF>::new("BCACHE") }
+        Self {
+            arena: unsafe {
+                ShardedArena::<BufEntry, NBUF, NBUCKET>::new(
+                    array![_ => RawSpinLock::new("BCACHE"); NBUCKET],
+                )
+            },
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
+        unsafe { Pin::new_unchecked(&mut self.get_unchecked_mut().arena) }.init();
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn arena<'s>(
+        self: StrongPin<'s, Self>,
+    ) -> StrongPin<'s, ShardedArena<BufEntry, NBUF, NBUCKET>> {
+        unsafe { StrongPin::new_unchecked(&self.ptr().arena) }
     }
 
     /// Return a unlocked buf with the contents of the indicated block.
     pub fn get_buf(self: StrongPin<'_, Self>, dev: u32, blockno: u32) -> BufUnlocked {
-        BufUnlocked(ManuallyDrop::new(
-            self.find_or_alloc(
+        fcount!(Bcache::get_buf);
+        self.try_get_buf(dev, blockno)
+            .expect("[BufGuard::new] no buffers")
+    }
+
+    /// Like `get_buf`, but returns None instead of panicking if every buf is in use.
+    pub fn try_get_buf(self: StrongPin<'_, Self>, dev: u32, blockno: u32) -> Option<BufUnlocked> {
+        self.arena()
+            .find_or_alloc_hashed(
+                BufEntry::hash(dev, blockno),
                 |buf| buf.dev == dev && buf.blockno == blockno,
                 |buf| {
                     buf.dev = dev;
@@ -200,7 +245,29 @@ impl Bcache {
                     buf.inner.get_mut().valid = false;
                 },
             )
-            .expect("[BufGuard::new] no buffers"),
-        ))
+            .map(|rc| BufUnlocked(ManuallyDrop::new(rc)))
+    }
+
+    /// Return the locked buf of the indicated block if it holds the contents of the block, without
+    /// evicting another.
+    pub fn lookup(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        blockno: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<Buf> {
+        let buf = self
+            .arena()
+            .find_hashed(BufEntry::hash(dev, blockno), |buf| {
+                buf.dev == dev && buf.blockno == blockno
+            })
+            .map(|rc| BufUnlocked(ManuallyDrop::new(rc)))?
+            .lock(ctx);
+        if buf.deref_inner().valid {
+            Some(buf)
+        } else {
+            buf.free(ctx);
+            None
+        }
     }
 }
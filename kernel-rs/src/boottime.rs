@@ -0,0 +1,109 @@
+//! Boot time breakdown.
+//!
+//! The boot stamps the timer at the end of each `BootPhase`, and prints how long each phase took
+//! once the first process has exec'ed `/init`, so that a regression in the boot time, which every
+//! CI run pays, shows up in the console log. A phase is stamped only the first time it ends, e.g.,
+//! only the first `exec` ends `BootPhase::FirstExec`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use array_macro::array;
+
+use crate::arch::riscv::r_time;
+
+/// Timer cycles per millisecond, i.e., the 10MHz timer of qemu's virt machine.
+const CYCLES_PER_MS: u64 = 10_000;
+
+#[derive(Clone, Copy)]
+pub enum BootPhase {
+    /// `hal_init`, i.e., the physical page allocator and the printer.
+    HalInit,
+
+    /// Creating the kernel page table and turning on paging.
+    Kvminit,
+
+    /// Initializing the process system.
+    ProcsInit,
+
+    /// Mounting the root file system, in the context of the first process. Includes the trap
+    /// vector, the device drivers and the buffer cache, which are initialized in between.
+    FsMount,
+
+    /// Loading `/init`.
+    FirstExec,
+}
+
+const NPHASE: usize = 5;
+
+impl BootPhase {
+    const ALL: [BootPhase; NPHASE] = [
+        BootPhase::HalInit,
+        BootPhase::Kvminit,
+        BootPhase::ProcsInit,
+        BootPhase::FsMount,
+        BootPhase::FirstExec,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            BootPhase::HalInit => "hal_init",
+            BootPhase::Kvminit => "kvminit",
+            BootPhase::ProcsInit => "procs init",
+            BootPhase::FsMount => "fs mount",
+            BootPhase::FirstExec => "first exec",
+        }
+    }
+}
+
+pub struct BootTimes {
+    /// Time the boot started at.
+    start: AtomicU64,
+
+    /// Time each phase ended at, or 0 if it has not ended yet.
+    ends: [AtomicU64; NPHASE],
+}
+
+impl BootTimes {
+    pub const fn new() -> Self {
+        Self {
+            start: AtomicU64::new(0),
+            ends: array![_ => AtomicU64::new(0); NPHASE],
+        }
+    }
+
+    /// Stamps the start of the boot. Called by the hart 0 before anything else.
+    pub fn start(&self) {
+        self.start.store(r_time(), Ordering::Relaxed);
+    }
+
+    /// Stamps the end of `phase` unless it has already ended.
+    /// Returns true if this call stamped it.
+    pub fn end(&self, phase: BootPhase) -> bool {
+        let end = &self.ends[phase as usize];
+        // Cheap check first, as `exec` calls this every time.
+        end.load(Ordering::Relaxed) == 0
+            && end
+                .compare_exchange(0, r_time(), Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    /// Calls `f` with the name of each phase that has ended, the cycles it took and the cycles
+    /// since the boot started, in order.
+    pub fn for_each<F: FnMut(&'static str, u64, u64)>(&self, mut f: F) {
+        let start = self.start.load(Ordering::Relaxed);
+        let mut prev = start;
+        for phase in BootPhase::ALL.iter() {
+            let end = self.ends[*phase as usize].load(Ordering::Relaxed);
+            if end == 0 {
+                continue;
+            }
+            f(phase.name(), end - prev, end - start);
+            prev = end;
+        }
+    }
+}
+
+/// Converts timer cycles to milliseconds.
+pub fn cycles_to_ms(cycles: u64) -> u64 {
+    cycles / CYCLES_PER_MS
+}
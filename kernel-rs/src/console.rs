@@ -6,23 +6,48 @@
 //! * control-u -- kill line
 //! * control-d -- end of file
 //! * control-p -- print process list
+//! * control-t -- print status line
+//!
+//! The UART interrupt handler only moves the received bytes into a lock-free queue, and raises a
+//! softirq. Echo and line editing run later in the softirq, with interrupts enabled, so that a long
+//! paste cannot keep the hart in the interrupt handler.
+
+use core::{
+    fmt,
+    pin::Pin,
+    sync::atomic::{AtomicI32, AtomicU8, AtomicUsize, Ordering},
+};
 
-use core::{fmt, pin::Pin};
+use array_macro::array;
 
 use crate::{
     arch::addr::UVAddr,
+    error::{Errno, KernelError},
+    file::Devsw,
     hal::hal,
+    init_call,
+    initcall::InitPhase,
     kernel::{Kernel, KernelRef},
     lock::{SleepableLock, SleepableLockGuard, SpinLock, SpinLockGuard},
     proc::KernelCtx,
+    softirq::Softirq,
+    some_or,
     uart::Uart,
     util::spin_loop,
 };
 
+/// Major device number of the console.
+const CONSOLE_MAJOR: usize = 1;
+
+/// Major device number of the terminal of the processes, which is the console.
+const TTY_MAJOR: usize = 4;
+
 /// Size of console input buffer.
 const INPUT_BUF: usize = 128;
 /// Size of console output buffer.
 const OUTPUT_BUF: usize = 32;
+/// Size of the queue of bytes received but not processed yet.
+const RX_BUF: usize = 256;
 
 struct OutputBuffer {
     buf: [u8; OUTPUT_BUF],
@@ -63,8 +88,55 @@ impl InputBuffer {
     }
 }
 
+/// Bytes received by the UART, waiting for `Console::process_input`.
+///
+/// A single-producer single-consumer ring. The producer is `Console::intr`, which the PLIC never
+/// runs on two harts at once. The consumer holds the lock of the input buffer, as `pop` demands.
+struct RxQueue {
+    buf: [AtomicU8; RX_BUF],
+    /// Read index.
+    r: AtomicUsize,
+    /// Write index.
+    w: AtomicUsize,
+}
+
+impl RxQueue {
+    pub const fn new() -> Self {
+        Self {
+            buf: array![_ => AtomicU8::new(0); RX_BUF],
+            r: AtomicUsize::new(0),
+            w: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends `c`. Returns Err(()) if the queue is full.
+    fn push(&self, c: u8) -> Result<(), ()> {
+        let w = self.w.load(Ordering::Relaxed);
+        if w.wrapping_sub(self.r.load(Ordering::Acquire)) == RX_BUF {
+            return Err(());
+        }
+        self.buf[w % RX_BUF].store(c, Ordering::Relaxed);
+        self.w.store(w.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the oldest byte, if any.
+    fn pop(&self, _guard: &SleepableLockGuard<'_, InputBuffer>) -> Option<u8> {
+        let r = self.r.load(Ordering::Relaxed);
+        if r == self.w.load(Ordering::Acquire) {
+            return None;
+        }
+        let c = self.buf[r % RX_BUF].load(Ordering::Relaxed);
+        self.r.store(r.wrapping_add(1), Ordering::Release);
+        Some(c)
+    }
+}
+
 pub struct Console {
     uart: Uart,
+    rx: RxQueue,
+    /// Pid of the process that last read from the console, or 0 if none did.
+    foreground: AtomicI32,
     input_buffer: SleepableLock<InputBuffer>,
     output_buffer: SleepableLock<OutputBuffer>,
 }
@@ -76,6 +148,8 @@ impl Console {
     pub const unsafe fn new(uart: usize) -> Self {
         Self {
             uart: unsafe { Uart::new(uart) },
+            rx: RxQueue::new(),
+            foreground: AtomicI32::new(0),
             input_buffer: SleepableLock::new("console_input", InputBuffer::new()),
             output_buffer: SleepableLock::new("console_output", OutputBuffer::new()),
         }
@@ -163,8 +237,8 @@ impl Console {
         for i in 0..n {
             let mut c = [0u8];
             if ctx
-                .proc_mut()
-                .memory_mut()
+                .proc()
+                .memory()
                 .copy_in_bytes(&mut c, src + i as usize)
                 .is_err()
             {
@@ -175,15 +249,29 @@ impl Console {
         n
     }
 
-    fn read(&self, mut dst: UVAddr, mut n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    fn read(
+        &self,
+        mut dst: UVAddr,
+        mut n: i32,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        self.foreground.store(ctx.proc().pid(), Ordering::Relaxed);
         let mut guard = self.input_buffer.lock();
         let target = n;
         while n > 0 {
             // Wait until interrupt handler has put some
             // input into CONS.buffer.
             while guard.r == guard.w {
+                // Without blocking, return what has arrived, but never an end-of-file.
+                if nonblock {
+                    if n < target {
+                        return Ok((target - n) as usize);
+                    }
+                    return Err(Errno::EAGAIN.into());
+                }
                 if ctx.proc().killed() {
-                    return -1;
+                    return Err(().into());
                 }
                 guard.sleep(ctx);
             }
@@ -201,12 +289,7 @@ impl Console {
             } else {
                 // Copy the input byte to the user-space buffer.
                 let cbuf = [cin as u8];
-                if ctx
-                    .proc_mut()
-                    .memory_mut()
-                    .copy_out_bytes(dst, &cbuf)
-                    .is_err()
-                {
+                if ctx.proc().memory().copy_out_bytes(dst, &cbuf).is_err() {
                     break;
                 }
                 dst = dst + 1;
@@ -218,26 +301,47 @@ impl Console {
                 }
             }
         }
-        target - n
+        Ok((target - n) as usize)
     }
 
     /// Handle a uart interrupt, raised because input has arrived, or the uart is ready for more
-    /// output, or both. Called from trap.c. Do erase/kill processing, append to the input buffer,
-    /// and wake up read() if a whole line has arrived.
+    /// output, or both. Called from trap.rs. Queues the input for `process_input`, and raises the
+    /// console softirq to run it. Bytes arriving while the queue is full are dropped.
     ///
     /// # Note
     ///
-    /// When `self.uart.getc()` is `Ok(ctrl('P'))`, this method is unsafe.
+    /// When `self.uart.getc()` is `Ok(ctrl('P'))` or `Ok(ctrl('T'))`, this method is unsafe.
     pub unsafe fn intr(&self, kernel: KernelRef<'_, '_>) {
-        // Read and process incoming characters.
+        // Read incoming characters.
+        let mut received = false;
         while let Ok(c) = self.uart.getc() {
+            if c == ctrl('P') {
+                // Print process list right away, even if the softirqs are stuck.
+                unsafe { kernel.dump() };
+            } else if c == ctrl('T') {
+                // Likewise for the status line.
+                unsafe { kernel.status(self.foreground.load(Ordering::Relaxed)) };
+            } else {
+                let _ = self.rx.push(c as u8);
+                received = true;
+            }
+        }
+        if received {
+            kernel.softirqs().raise(Softirq::Console);
+        }
+
+        // Write buffered characters.
+        self.flush_output_buffer(self.output_buffer.lock(), kernel);
+    }
+
+    /// Do erase/kill processing of the queued input, append it to the input buffer, and wake up
+    /// read() if a whole line has arrived. Runs in the console softirq.
+    pub fn process_input(&self, kernel: KernelRef<'_, '_>) {
+        // Take the lock for each byte, so that interrupts are enabled in between.
+        loop {
             let mut guard = self.input_buffer.lock();
+            let c = some_or!(self.rx.pop(&guard), break) as i32;
             match c {
-                // Print process list.
-                m if m == ctrl('P') => {
-                    unsafe { kernel.dump() };
-                }
-
                 // Kill line.
                 m if m == ctrl('U') => {
                     while guard.e != guard.w
@@ -279,9 +383,6 @@ impl Console {
                 }
             }
         }
-
-        // Write buffered characters.
-        self.flush_output_buffer(self.output_buffer.lock(), kernel);
     }
 }
 
@@ -334,6 +435,32 @@ pub fn console_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
 /// User read()s from the console go here.
 /// Copy (up to) a whole input line to dst.
 /// User_dist indicates whether dst is a user or kernel address.
-pub fn console_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
-    hal().console().read(dst, n, ctx)
+/// Returns Err(EAGAIN) instead of waiting for input if nonblock.
+pub fn console_read(
+    dst: UVAddr,
+    n: i32,
+    nonblock: bool,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, KernelError> {
+    hal().console().read(dst, n, nonblock, ctx)
 }
+
+/// Connect read and write system calls to console_read and console_write, through both
+/// `/dev/console` and `/dev/tty`.
+unsafe fn console_init(mut kernel: Pin<&mut Kernel>) {
+    for (major, name) in [(CONSOLE_MAJOR, "console"), (TTY_MAJOR, "tty")] {
+        kernel
+            .as_mut()
+            .register_devsw(
+                major,
+                Devsw {
+                    name,
+                    read: Some(console_read),
+                    write: Some(console_write),
+                },
+            )
+            .expect("console_init: register_devsw");
+    }
+}
+
+init_call!(InitPhase::Device, console_init);
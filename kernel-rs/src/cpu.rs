@@ -1,10 +1,13 @@
-use core::{cell::Cell, ptr};
+use core::{cell::Cell, panic::Location, ptr};
 
 use array_macro::array;
+use rv6_abi::IrqoffRecord;
 
 use crate::{
     arch::riscv::r_tp,
-    arch::riscv::{intr_get, intr_off, intr_on},
+    arch::riscv::{intr_get, intr_off, intr_on, r_time},
+    irqoff::{IrqOffRecords, IrqOffSite},
+    model::LockOrder,
     param::NCPU,
     proc::{Context, Proc},
 };
@@ -12,7 +15,15 @@ use crate::{
 // The `Cpu` struct of the current cpu can be mutated. To do so, we need to
 // obtain mutable pointers to `Cpu`s from a shared reference of a `Cpus`.
 // It requires interior mutability, so we use `UnsafeCell`.
-pub struct Cpus([Cell<Cpu>; NCPU]);
+pub struct Cpus {
+    cpus: [Cell<Cpu>; NCPU],
+
+    /// The longest interrupts-disabled window of each CPU, with the `irqoff` feature.
+    irqoff: IrqOffRecords,
+
+    /// The order of the lock classes, with the `model-check` feature.
+    lock_order: LockOrder,
+}
 
 /// # Safety
 ///
@@ -33,7 +44,11 @@ unsafe impl Sync for Cpus {}
 
 impl Cpus {
     pub const fn new() -> Self {
-        Self(array![_ => Cell::new(Cpu::new()); NCPU])
+        Self {
+            cpus: array![_ => Cell::new(Cpu::new()); NCPU],
+            irqoff: IrqOffRecords::new(),
+            lock_order: LockOrder::new(),
+        }
     }
 }
 
@@ -44,7 +59,7 @@ impl Cpus {
     /// current CPU since the scheduler can move the process to another CPU on time interrupt.
     pub fn current_raw(&self) -> *mut Cpu {
         let id: usize = cpuid();
-        self.0[id].as_ptr()
+        self.cpus[id].as_ptr()
     }
 
     /// Returns a `CpuMut` to the current CPU.
@@ -57,7 +72,7 @@ impl Cpus {
         // SAFETY:
         // * safety condition of this method.
         // * `ptr` refers to the current CPU.
-        unsafe { CpuMut::new_unchecked(&self.0[id]) }
+        unsafe { CpuMut::new_unchecked(&self.cpus[id]) }
     }
 
     /// Returns a `CpuMut` to the current CPU. Since the returned `CpuMut` cannot outlive a given
@@ -72,14 +87,34 @@ impl Cpus {
     /// push_off/pop_off are like intr_off()/intr_on() except that they are matched:
     /// It takes two pop_off()s to undo two push_off()s. Also, if interrupts
     /// are initially off, then push_off, pop_off leaves them off.
+    #[track_caller]
     pub fn push_off(&self) -> HeldInterrupts {
+        self.push_off_at(IrqOffSite::Caller(Location::caller()))
+    }
+
+    /// push_off() for acquiring the spin lock named `name`, which the `irqoff` feature blames
+    /// for the window.
+    pub fn push_off_lock(&self, name: &'static str) -> HeldInterrupts {
+        self.push_off_at(IrqOffSite::Lock(name))
+    }
+
+    fn push_off_at(&self, site: IrqOffSite) -> HeldInterrupts {
         let old = intr_get();
         let intr = HeldInterrupts::new();
         let cpu = self.current(&intr);
-        cpu.push_off(old);
+        cpu.push_off(old, site);
         intr
     }
 
+    /// Returns the record of the longest interrupts-disabled window of the CPU with the given id.
+    pub fn irqoff_record(&self, id: usize) -> IrqoffRecord {
+        self.irqoff.get(id)
+    }
+
+    pub fn lock_order(&self) -> &LockOrder {
+        &self.lock_order
+    }
+
     /// pop_off() should be paired with push_off().
     /// See push_off() for more details.
     ///
@@ -90,6 +125,10 @@ impl Cpus {
     pub unsafe fn pop_off(&self, intr: HeldInterrupts) {
         assert!(!intr_get(), "pop_off: interruptible");
         let cpu = self.current(&intr);
+        if cfg!(feature = "irqoff") && cpu.get_noff() == 1 && cpu.get_interrupt() {
+            let (start, site) = cpu.irqoff_window();
+            self.irqoff.record(cpuid(), r_time() - start, site);
+        }
         // SAFETY: safety condition of this method.
         unsafe {
             cpu.pop_off();
@@ -111,6 +150,10 @@ pub struct Cpu {
 
     /// Were interrupts enabled before push_off()?
     interrupt_enabled: bool,
+
+    /// When and where the outermost push_off() disabled interrupts, with the `irqoff` feature.
+    irqoff_start: u64,
+    irqoff_site: IrqOffSite,
 }
 
 impl Cpu {
@@ -120,6 +163,8 @@ impl Cpu {
             context: Context::new(),
             noff: 0,
             interrupt_enabled: false,
+            irqoff_start: 0,
+            irqoff_site: IrqOffSite::Lock(""),
         }
     }
 }
@@ -177,14 +222,26 @@ impl<'s> CpuMut<'s> {
         self.ptr.set(cpu);
     }
 
-    fn push_off(&self, old: bool) {
+    fn push_off(&self, old: bool, site: IrqOffSite) {
         let noff = self.get_noff();
         if noff == 0 {
             self.set_interrupt(old);
+            if cfg!(feature = "irqoff") && old {
+                let mut cpu = self.ptr.get();
+                cpu.irqoff_start = r_time();
+                cpu.irqoff_site = site;
+                self.ptr.set(cpu);
+            }
         }
         self.set_noff(noff + 1);
     }
 
+    /// Returns when and where the outermost push_off() disabled interrupts.
+    fn irqoff_window(&self) -> (u64, IrqOffSite) {
+        let cpu = self.ptr.get();
+        (cpu.irqoff_start, cpu.irqoff_site)
+    }
+
     /// # Safety
     ///
     /// It may turn on interrupt, so callers must ensure that calling this method does not incur
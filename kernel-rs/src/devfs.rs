@@ -0,0 +1,164 @@
+//! Device nodes under `/dev`.
+//!
+//! devfs is a pseudo file system (see `mount`), which the kernel boots with mounted on `/dev`. Its
+//! root directory and its device nodes are inodes of the pseudo device `DEVFS_DEV`, which `dinode`
+//! and `dirent` synthesize. A driver registers its read and write functions by
+//! `Kernel::register_devsw`, together with the name of its device node, and `/dev/<name>` then
+//! refers to it. Hence, the fs image needs no `mknod` for them, a new driver appears under `/dev`
+//! by registering itself, and nothing is left on the disk. Changes to the nodes, e.g., by `chmod`,
+//! last only while they are in memory.
+//!
+//! This module also provides the drivers of `/dev/null` and `/dev/zero`.
+
+use core::{cmp, mem, pin::Pin};
+
+use rv6_abi::{Dinode, Dirent, ROOTINO, T_DEVICE, T_DIR};
+
+use crate::{
+    arch::addr::UVAddr,
+    error::{Errno, KernelError},
+    file::Devsw,
+    init_call,
+    initcall::InitPhase,
+    kernel::Kernel,
+    mount::{PseudoFile, PseudoFs},
+    proc::KernelCtx,
+};
+
+/// The pseudo device of the inodes under `/dev`. No disk has this number.
+pub const DEVFS_DEV: u32 = u32::MAX;
+
+/// Inode number of `/dev`. The device node of major number `major` has inode number
+/// `DEVFS_ROOTINO + 1 + major`.
+const DEVFS_ROOTINO: u32 = ROOTINO;
+
+/// devfs, which the kernel boots with mounted on `/dev`.
+pub const DEVFS: PseudoFs = PseudoFs {
+    name: "devfs",
+    dev: DEVFS_DEV,
+    dinode,
+    dirent,
+    read,
+    read_only: false,
+};
+
+/// Major device number of `/dev/null`.
+const NULL_MAJOR: usize = 2;
+
+/// Major device number of `/dev/zero`.
+const ZERO_MAJOR: usize = 3;
+
+static ZEROS: [u8; 256] = [0; 256];
+
+/// Returns the major device numbers of the drivers that have a device node, with their names.
+fn nodes<'s>(ctx: &KernelCtx<'_, 's>) -> impl Iterator<Item = (usize, &'s str)> {
+    ctx.kernel()
+        .devsw()
+        .iter()
+        .map(|devsw| devsw.name)
+        .enumerate()
+        .filter(|(_, name)| !name.is_empty())
+}
+
+/// Returns the `i`th entry of `/dev`: ".", "..", and then the device nodes in the order of their
+/// major numbers. Returns None past the last entry, or if `inum` is not `/dev`.
+fn dirent(inum: u32, _: u32, i: u32, ctx: &KernelCtx<'_, '_>) -> Option<Dirent> {
+    if inum != DEVFS_ROOTINO {
+        return None;
+    }
+    let (name, inum) = match i {
+        0 => (&b"."[..], DEVFS_ROOTINO),
+        1 => (&b".."[..], 0),
+        _ => {
+            let (major, name) = nodes(ctx).nth(i as usize - 2)?;
+            (name.as_bytes(), DEVFS_ROOTINO + 1 + major as u32)
+        }
+    };
+    let mut de = Dirent {
+        inum: inum as u16,
+        ..Default::default()
+    };
+    de.set_name(name);
+    Some(de)
+}
+
+/// Returns the inode `inum` of `DEVFS_DEV` as if it were read from the disk, or a free one if
+/// there is no such inode.
+fn dinode(inum: u32, ctx: &KernelCtx<'_, '_>) -> Dinode {
+    let mut dip = Dinode::default();
+    if inum == DEVFS_ROOTINO {
+        dip.typ = T_DIR;
+        dip.nlink = 1;
+        dip.size = ((2 + nodes(ctx).count()) * mem::size_of::<Dirent>()) as u32;
+        dip.mode = 0o755;
+    } else if let Some((major, _)) =
+        nodes(ctx).find(|(major, _)| DEVFS_ROOTINO + 1 + *major as u32 == inum)
+    {
+        dip.typ = T_DEVICE;
+        dip.major = major as u16;
+        dip.nlink = 1;
+        dip.mode = 0o666;
+    }
+    dip.update_checksum();
+    dip
+}
+
+/// The device nodes have no content of their own, but their drivers read and write them.
+fn read(_: u32, _: u32, _: &mut PseudoFile, _: &KernelCtx<'_, '_>) {}
+
+/// Reads of `/dev/null` find the end of file.
+fn null_read(_: UVAddr, _: i32, _: bool, _: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    Ok(0)
+}
+
+/// Writes to `/dev/null` and `/dev/zero` are discarded.
+fn null_write(_: UVAddr, n: i32, _: &mut KernelCtx<'_, '_>) -> i32 {
+    n
+}
+
+/// Reads of `/dev/zero` fill the buffer with zeros.
+fn zero_read(
+    dst: UVAddr,
+    n: i32,
+    _: bool,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, KernelError> {
+    let n = cmp::max(n, 0) as usize;
+    let mut tot = 0;
+    while tot < n {
+        let m = cmp::min(n - tot, ZEROS.len());
+        ctx.proc()
+            .memory()
+            .copy_out_bytes(dst + tot, &ZEROS[..m])
+            .map_err(|_| Errno::EFAULT)?;
+        tot += m;
+    }
+    Ok(n)
+}
+
+/// Registers the drivers of `/dev/null` and `/dev/zero`.
+unsafe fn devfs_init(mut kernel: Pin<&mut Kernel>) {
+    kernel
+        .as_mut()
+        .register_devsw(
+            NULL_MAJOR,
+            Devsw {
+                name: "null",
+                read: Some(null_read),
+                write: Some(null_write),
+            },
+        )
+        .expect("devfs_init: register_devsw");
+    kernel
+        .register_devsw(
+            ZERO_MAJOR,
+            Devsw {
+                name: "zero",
+                read: Some(zero_read),
+                write: Some(null_write),
+            },
+        )
+        .expect("devfs_init: register_devsw");
+}
+
+init_call!(InitPhase::Device, devfs_init);
@@ -0,0 +1,39 @@
+//! Errors of system calls.
+
+pub use rv6_abi::Errno;
+
+/// An error of a system call, which is reported to the user program as `-errno` in `a0`.
+///
+/// Most kernel functions still fail with `Err(())`. `?` converts such an error into `EINVAL`, so
+/// a system call should map it to a more specific error number where one is known, e.g.,
+/// `.map_err(|_| Errno::EBADF)?`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KernelError(Errno);
+
+impl KernelError {
+    pub const fn errno(self) -> Errno {
+        self.0
+    }
+
+    /// Returns `errno` in place of this error, unless this is `EIO`, which reports that the file
+    /// system is corrupted, and is never hidden behind another error.
+    pub fn or(self, errno: Errno) -> Self {
+        if self.0 == Errno::EIO {
+            self
+        } else {
+            Self(errno)
+        }
+    }
+}
+
+impl From<Errno> for KernelError {
+    fn from(errno: Errno) -> Self {
+        Self(errno)
+    }
+}
+
+impl From<()> for KernelError {
+    fn from(_: ()) -> Self {
+        Self(Errno::EINVAL)
+    }
+}
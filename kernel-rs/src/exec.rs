@@ -4,23 +4,38 @@ use core::{cmp, mem};
 
 use bitflags::bitflags;
 use itertools::*;
+use rv6_abi::{AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
     arch::addr::{pgroundup, PAddr, PGSIZE},
-    fs::{FileSystem, Path},
+    arch::{Arch, TargetArch},
+    boottime::BootPhase,
+    error::{Errno, KernelError},
+    fs::{Access, FileSystem, Path},
     hal::hal,
+    model::DETERMINISTIC,
     page::Page,
-    param::MAXARG,
-    proc::KernelCtx,
+    param::{MAXARG, NOFILE},
+    proc::{pages_of, KernelCtx, TrapFrame},
     vm::UserMemory,
 };
 
+/// Number of random bytes pushed for `AT_RANDOM`.
+const RANDOM_BYTES: usize = 16;
+
+/// The user stack starts below the top of its page by a random multiple of 16 bytes less than this.
+const STACK_RANDOM: usize = 512;
+
+/// mmap places mappings below the trap frames by a random number of pages less than this.
+const MMAP_RANDOM_PAGES: usize = 1 << 16;
+
 /// "\x7FELF" in little endian
 const ELF_MAGIC: u32 = 0x464c457f;
 
 /// Values for Proghdr type
 const ELF_PROG_LOAD: u32 = 1;
+const ELF_PROG_TLS: u32 = 7;
 
 /// File header
 #[derive(Default, Clone)]
@@ -83,6 +98,41 @@ struct ProgHdr {
     align: usize,
 }
 
+/// The system call ABI of a program, which decides how its system calls are numbered and how its
+/// initial stack is laid out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Abi {
+    /// The programs of rv6. They are linked at address 0, and start at `main(argc, argv)` with
+    /// the array of argv[] pointers on the stack.
+    Rv6,
+
+    /// The programs linked against a Linux libc, e.g., a static musl. They use the system call
+    /// numbers of `rv6_abi::linux`, and find argc, argv[], an empty envp[] and the auxiliary
+    /// vector on the stack. They are told apart by not being linked at address 0, since Linux
+    /// does not let programs map the first page. Still, rv6 maps the pages below the program.
+    Linux,
+}
+
+/// A program image loaded by `KernelCtx::load_image`, which has not been installed in a process
+/// yet. Its `memory` must be either installed or freed.
+pub struct Image {
+    pub memory: UserMemory,
+
+    /// Initial program counter.
+    entry: usize,
+
+    /// Initial stack pointer, which points to the array of argv[] pointers, followed by the
+    /// auxiliary vector.
+    sp: usize,
+
+    argc: usize,
+
+    /// Thread pointer of the main thread, or 0 if the program has no TLS.
+    tp: usize,
+
+    pub abi: Abi,
+}
+
 impl ElfHdr {
     pub fn is_valid(&self) -> bool {
         self.magic == ELF_MAGIC
@@ -93,12 +143,51 @@ impl ProgHdr {
     pub fn is_prog_load(&self) -> bool {
         self.typ == ELF_PROG_LOAD
     }
+
+    pub fn is_prog_tls(&self) -> bool {
+        self.typ == ELF_PROG_TLS
+    }
+}
+
+impl Image {
+    /// Sets up `trap_frame` to start the program at `main(argc, argv)`. A Linux program reads them
+    /// from the stack instead.
+    pub fn start(&self, trap_frame: &mut TrapFrame) {
+        trap_frame.a0 = self.argc;
+        trap_frame.a1 = self.sp;
+        trap_frame.epc = self.entry;
+        trap_frame.sp = self.sp;
+        trap_frame.tp = self.tp;
+    }
+}
+
+/// Sets `proc_name` to the last element of `path`, for debugging.
+pub fn set_proc_name(proc_name: &mut [u8], path: &Path) {
+    let path_str = path.as_bytes();
+    let name = path_str
+        .iter()
+        .rposition(|c| *c == b'/')
+        .map(|i| &path_str[(i + 1)..])
+        .unwrap_or(path_str);
+    let len = cmp::min(proc_name.len(), name.len());
+    proc_name[..len].copy_from_slice(&name[..len]);
+    if len < proc_name.len() {
+        proc_name[len] = 0;
+    }
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, ()> {
+    /// Loads the program at `path` into a new user memory whose trap frame is `trap_frame`, and
+    /// pushes `args` on its stack. The current process is not changed, but must be permitted to
+    /// execute the program.
+    pub fn load_image(
+        &mut self,
+        path: &Path,
+        args: &[Page],
+        trap_frame: PAddr,
+    ) -> Result<Image, KernelError> {
         if args.len() > MAXARG {
-            return Err(());
+            return Err(Errno::ENOEXEC.into());
         }
 
         let allocator = hal().kmem();
@@ -107,38 +196,66 @@ impl KernelCtx<'_, '_> {
         let tx = scopeguard::guard(tx, |t| t.end(self));
         let ptr = self.kernel().fs().namei(path, &tx, self)?;
         let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
-        let ip = ptr.lock(self);
+        let ip = ptr.lock(self)?;
         let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
+        ip.deref_inner().check_access(Access::EXEC, self)?;
 
         // Check ELF header
         let mut elf: ElfHdr = Default::default();
         ip.read_kernel(&mut elf, 0, self)?;
         if !elf.is_valid() {
-            return Err(());
+            return Err(Errno::ENOEXEC.into());
         }
 
-        let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
         let mem = UserMemory::new(trap_frame, None, allocator).ok_or(())?;
         let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
 
         // Load program into memory.
+        let mut tls = None;
+        let mut abi = Abi::Rv6;
+        // Where the program headers are loaded, if they are.
+        let mut phdr = None;
         for i in 0..elf.phnum as usize {
             let off = elf.phoff + i * mem::size_of::<ProgHdr>();
 
             let mut ph: ProgHdr = Default::default();
             ip.read_kernel(&mut ph, off as _, self)?;
             if ph.is_prog_load() {
-                if ph.memsz < ph.filesz || ph.vaddr % PGSIZE != 0 {
-                    return Err(());
+                if ph.memsz < ph.filesz {
+                    return Err(Errno::ENOEXEC.into());
+                }
+                if mem.size() == 0 && ph.vaddr >= PGSIZE {
+                    abi = Abi::Linux;
+                }
+                if (ph.off..ph.off + ph.filesz).contains(&elf.phoff) {
+                    phdr = Some(ph.vaddr + (elf.phoff - ph.off));
                 }
                 let _ = mem.alloc(ph.vaddr.checked_add(ph.memsz).ok_or(())?, allocator)?;
                 mem.load_file(ph.vaddr.into(), &mut ip, ph.off as _, ph.filesz as _, self)?;
+            } else if ph.is_prog_tls() {
+                tls = Some(ph);
             }
         }
+
+        // Allocate the TLS block of the main thread at the next page boundary, and initialize it
+        // with the TLS image. The riscv ABI has tp point to the start of the block.
+        let mut tp = 0;
+        if let Some(ph) = tls {
+            if ph.memsz < ph.filesz || ph.align > PGSIZE {
+                return Err(Errno::ENOEXEC.into());
+            }
+            tp = pgroundup(mem.size());
+            let _ = mem.alloc(tp.checked_add(ph.memsz).ok_or(())?, allocator)?;
+            // The rest of the block, i.e., .tbss, is zero-filled by alloc.
+            mem.load_file(tp.into(), &mut ip, ph.off as _, ph.filesz as _, self)?;
+        }
         drop(ip);
         drop(ptr);
         drop(tx);
 
+        // Make the CPUs fetch the program loaded rather than stale instructions.
+        TargetArch::sync_icache_range(0, mem.size());
+
         // Allocate two pages at the next page boundary.
         // Use the second as the user stack.
         let mut sz = pgroundup(mem.size());
@@ -147,20 +264,31 @@ impl KernelCtx<'_, '_> {
         let mut sp: usize = sz;
         let stackbase: usize = sp - PGSIZE;
 
+        // Randomize the top of the stack and the base of the mappings, so that their addresses
+        // are hard to guess.
+        if !DETERMINISTIC {
+            sp -= 16 * self.kernel().entropy().below(STACK_RANDOM / 16);
+            mem.set_mmap_gap(PGSIZE * self.kernel().entropy().below(MMAP_RANDOM_PAGES));
+        }
+
+        // Push random bytes for AT_RANDOM.
+        let mut bytes = [0u8; RANDOM_BYTES];
+        self.kernel().entropy().fill_bytes(&mut bytes);
+        sp -= RANDOM_BYTES;
+        mem.copy_out_bytes(sp.into(), &bytes)?;
+        let random_addr = sp;
+
         // Push argument strings, prepare rest of stack in ustack.
         let mut ustack = [0usize; MAXARG + 1];
         for (arg, stack) in izip!(args, &mut ustack) {
-            let null_idx = arg
-                .iter()
-                .position(|c| *c == 0)
-                .expect("exec: no null char found");
+            let null_idx = arg.iter().position(|c| *c == 0).ok_or(())?;
             let bytes = &arg[..null_idx + 1];
             sp -= bytes.len();
 
             // riscv sp must be 16-byte aligned
             sp &= !0xf;
             if sp < stackbase {
-                return Err(());
+                return Err(Errno::ENOEXEC.into());
             }
 
             mem.copy_out_bytes(sp.into(), bytes)?;
@@ -169,50 +297,147 @@ impl KernelCtx<'_, '_> {
         let argc: usize = args.len();
         ustack[argc] = 0;
 
-        // push the array of argv[] pointers.
-        let argv_size = (argc + 1) * mem::size_of::<usize>();
-        sp -= argv_size;
+        // push the array of argv[] pointers, followed by the auxiliary vector. A Linux program
+        // also takes argc before, and an empty envp[] in between.
+        let auxv = [
+            AT_PHDR,
+            phdr.unwrap_or(0),
+            AT_PHENT,
+            mem::size_of::<ProgHdr>(),
+            AT_PHNUM,
+            if phdr.is_some() { elf.phnum as _ } else { 0 },
+            AT_PAGESZ,
+            PGSIZE,
+            AT_ENTRY,
+            elf.entry,
+            AT_RANDOM,
+            random_addr,
+            AT_NULL,
+            0,
+        ];
+        let word = mem::size_of::<usize>();
+        let extra = if abi == Abi::Linux { 2 * word } else { 0 };
+        let argv_size = (argc + 1) * word;
+        sp -= extra + argv_size + mem::size_of_val(&auxv);
         sp &= !0xf;
         if sp < stackbase {
-            return Err(());
+            return Err(Errno::ENOEXEC.into());
+        }
+        let mut p = sp;
+        if abi == Abi::Linux {
+            mem.copy_out(p.into(), &argc)?;
+            p += word;
         }
         // SAFETY: any byte can be considered as a valid u8.
         let (_, ustack, _) = unsafe { ustack.align_to::<u8>() };
-        mem.copy_out_bytes(sp.into(), &ustack[..argv_size])?;
+        mem.copy_out_bytes(p.into(), &ustack[..argv_size])?;
+        p += argv_size;
+        if abi == Abi::Linux {
+            mem.copy_out(p.into(), &0usize)?;
+            p += word;
+        }
+        mem.copy_out(p.into(), &auxv)?;
 
-        // Save program name for debugging.
-        let path_str = path.as_bytes();
-        let name = path_str
-            .iter()
-            .rposition(|c| *c == b'/')
-            .map(|i| &path_str[(i + 1)..])
-            .unwrap_or(path_str);
-        let proc_name = &mut self.proc_mut().deref_mut_data().name;
-        let len = cmp::min(proc_name.len(), name.len());
-        proc_name[..len].copy_from_slice(&name[..len]);
-        if len < proc_name.len() {
-            proc_name[len] = 0;
+        Ok(Image {
+            memory: scopeguard::ScopeGuard::into_inner(mem),
+            entry: elf.entry,
+            sp,
+            argc,
+            tp,
+            abi,
+        })
+    }
+
+    /// Replaces the user image of the current process with the program at `path`. The other
+    /// threads sharing the old image keep it.
+    /// Returns Ok(argc) on success, Err(error) on error.
+    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, KernelError> {
+        let allocator = hal().kmem();
+        let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
+        let image = self.load_image(path, args, trap_frame)?;
+
+        // Set up the registers, before the memory moves into a slot.
+        let mut regs = *self.proc().trap_frame();
+        image.start(&mut regs);
+        let footprint = image.memory.footprint();
+        let kernel = self.kernel();
+        let memory = kernel
+            .procs()
+            .memories()
+            .alloc(image.memory)
+            .map_err(|memory| memory.free(allocator))?;
+
+        // Leave the open file table to the other threads sharing it, with a copy for this one.
+        let files = if self.proc().shared_files().is_shared() {
+            let table = self.proc().files().inherit(true);
+            match kernel.procs().file_tables().alloc(table) {
+                Ok(files) => Some(files),
+                Err(table) => {
+                    table.free(self);
+                    memory.release().expect("exec").0.free(allocator);
+                    return Err(Errno::ENOMEM.into());
+                }
+            }
+        } else {
+            None
+        };
+
+        // Charge the new image to the resource group. It takes over the charge of the old one
+        // unless other threads keep using that.
+        let charged = if self.proc().shared_memory().is_shared() {
+            let group = self.proc().deref_data().group;
+            kernel.procs().group(group).try_charge(pages_of(footprint))
+        } else {
+            self.charge_memory(footprint)
+                .map(|_| self.proc().shared_memory().set_charged_pages(0))
+        };
+        if charged.is_err() {
+            if let Some(files) = files {
+                files.release().expect("exec").free(self);
+            }
+            memory.release().expect("exec").0.free(allocator);
+            return Err(Errno::ENOMEM.into());
         }
+        memory.set_charged_pages(pages_of(footprint));
+
+        // Save program name for debugging.
+        set_proc_name(&mut self.proc_mut().deref_mut_data().name, path);
 
         // Commit to the user image.
-        mem::replace(
-            self.proc_mut().memory_mut(),
-            scopeguard::ScopeGuard::into_inner(mem),
-        )
-        .free(allocator);
+        *self.proc_mut().trap_frame_mut() = regs;
+        self.replace_memory(memory);
+        self.proc_mut().deref_mut_data().abi = image.abi;
 
-        // arguments to user main(argc, argv)
-        // argc is returned via the system call return
-        // value, which goes in a0.
-        self.proc_mut().trap_frame_mut().a1 = sp;
+        // The robust list lived in the old image.
+        self.proc_mut().deref_mut_data().robust_list = 0.into();
+        // So did the signal handlers.
+        self.reset_signal_handlers();
 
-        // initial program counter = main
-        self.proc_mut().trap_frame_mut().epc = elf.entry;
+        // Close the files marked close-on-exec, which the copy has left out already.
+        if let Some(files) = files {
+            self.replace_files(files);
+        } else {
+            for fd in 0..NOFILE {
+                let f = {
+                    let mut files = self.proc().files();
+                    if mem::take(&mut files.cloexec[fd]) {
+                        files.open_files[fd].take()
+                    } else {
+                        None
+                    }
+                };
+                if let Some(f) = f {
+                    f.free(self);
+                }
+            }
+        }
 
-        // initial stack pointer
-        self.proc_mut().trap_frame_mut().sp = sp;
+        if self.kernel().boot_times().end(BootPhase::FirstExec) {
+            self.kernel().as_ref().print_boot_times();
+        }
 
-        // this ends up in a0, the first argument to main(argc, argv)
-        Ok(argc)
+        // argc is returned via the system call return value, which goes in a0, the first
+        // argument to main(argc, argv).
+        Ok(image.argc)
     }
 }
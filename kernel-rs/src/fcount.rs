@@ -0,0 +1,84 @@
+//! Function entry counters, enabled by the `fcount` feature.
+//!
+//! `fcount!(name)` at the beginning of function `name`, e.g., `fcount!(Kmem::alloc)`, counts the
+//! calls to the function in a `FuncCounter`, which it places in the `.fcount` linker section. The
+//! `fcount` system call copies every counter out, so that hot paths can be found from inside the
+//! machine, where host-side profilers cannot see.
+//!
+//! A call costs an atomic increment with the feature, and nothing without it.
+
+use core::{
+    cmp, mem, slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rv6_abi::{FcountRecord, FCOUNT_NAME_LEN};
+
+extern "C" {
+    // kernel.ld
+    static mut __fcount_start: [u8; 0];
+    static mut __fcount_end: [u8; 0];
+}
+
+/// A counter placed by `fcount!`.
+#[repr(C)]
+pub struct FuncCounter {
+    /// Path of the function, starting with the crate name.
+    name: &'static str,
+
+    count: AtomicUsize,
+}
+
+impl FuncCounter {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn hit(&self) {
+        let _ = self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the record of this counter, with the name truncated to fit in.
+    pub fn record(&self) -> FcountRecord {
+        // Drop the crate name, which is the same for every counter.
+        let name = self.name.splitn(2, "::").nth(1).unwrap_or(self.name);
+        let mut record = FcountRecord {
+            count: self.count.load(Ordering::Relaxed) as u64,
+            name: [0; FCOUNT_NAME_LEN],
+        };
+        // Leave room for the terminating NUL.
+        let len = cmp::min(name.len(), FCOUNT_NAME_LEN - 1);
+        record.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        record
+    }
+}
+
+/// Counts the calls to the enclosing function `$name` if the `fcount` feature is enabled.
+#[macro_export]
+macro_rules! fcount {
+    ($name:path) => {
+        #[cfg(feature = "fcount")]
+        {
+            #[used]
+            #[link_section = ".fcount"]
+            static COUNTER: $crate::fcount::FuncCounter =
+                $crate::fcount::FuncCounter::new(concat!(module_path!(), "::", stringify!($name)));
+            COUNTER.hit();
+        }
+    };
+}
+
+/// Returns the counters placed by `fcount!`.
+pub fn counters() -> &'static [FuncCounter] {
+    // SAFETY: the linker places only `FuncCounter`s between `__fcount_start` and `__fcount_end`,
+    // aligned properly.
+    unsafe {
+        let start = __fcount_start.as_ptr();
+        let end = __fcount_end.as_ptr();
+        let len = (end as usize - start as usize) / mem::size_of::<FuncCounter>();
+        slice::from_raw_parts(start as *const FuncCounter, len)
+    }
+}
@@ -6,16 +6,25 @@ use core::{
     mem::{self, ManuallyDrop},
     ops::Deref,
     ops::DerefMut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use arrayvec::ArrayVec;
+use rv6_abi::{
+    Dirent, DirentPlus, FcntlFlags, POSIX_FADV_DONTNEED, POSIX_FADV_NORMAL, POSIX_FADV_RANDOM,
+    POSIX_FADV_SEQUENTIAL, POSIX_FADV_WILLNEED, SEEK_CUR, SEEK_END, SEEK_SET,
 };
 
 use crate::{
     arch::addr::UVAddr,
     arena::{Arena, ArenaObject, ArenaRc, ArrayArena},
-    fs::{FileSystem, InodeGuard, RcInode, Ufs},
+    error::{Errno, KernelError},
+    fs::{FileSystem, InodeGuard, InodeType, RcInode, SyncFileRangeFlags, Ufs},
     hal::hal,
-    param::{BSIZE, MAXOPBLOCKS, NFILE},
+    lock::RawSpinLock,
+    param::{BSIZE, MAXOPBLOCKS, NFILE, READAHEAD},
     pipe::AllocatedPipe,
-    proc::KernelCtx,
+    proc::{KernelCtx, Pid},
     util::strong_pin::StrongPin,
 };
 
@@ -31,6 +40,16 @@ pub enum FileType {
         ip: RcInode<<Ufs as FileSystem>::InodeInner>,
         major: u16,
     },
+    /// Refers to a process. Reading it blocks until the process exits, and then returns 0.
+    Pidfd {
+        pid: Pid,
+    },
+    /// Refers to the watch in `slot` of the events on `ip`, which it keeps from being freed.
+    /// Reading it returns the events (see `fswatch`).
+    Watch {
+        ip: RcInode<<Ufs as FileSystem>::InodeInner>,
+        slot: usize,
+    },
 }
 
 /// It has an inode and an offset.
@@ -42,6 +61,27 @@ pub struct InodeFileType {
     pub ip: RcInode<<Ufs as FileSystem>::InodeInner>,
     // It should be accessed only when `ip` is locked.
     pub off: UnsafeCell<u32>,
+    /// Whether every write moves the offset to the end of file first, i.e., opened with
+    /// `O_APPEND`.
+    pub append: bool,
+    /// Whether reads and writes move data between the disk and the user pages directly, instead
+    /// of through the buffer cache, i.e., opened with `O_DIRECT`.
+    pub direct: bool,
+    // It should be accessed only when `ip` is locked.
+    pub ra: UnsafeCell<ReadAhead>,
+}
+
+/// The state of reading ahead a file, which reads the blocks following a sequential read into the
+/// buffer cache before they are read.
+#[derive(Default)]
+pub struct ReadAhead {
+    /// The advice of `fadvise` on the access pattern, `POSIX_FADV_NORMAL`, `POSIX_FADV_RANDOM`
+    /// or `POSIX_FADV_SEQUENTIAL`.
+    advice: i32,
+    /// The offset where the last read ended. A read from there is sequential.
+    next: u32,
+    /// The offset up to which the file has been read ahead.
+    end: u32,
 }
 
 /// It can be acquired when the inode of `InodeFileType` is locked. `ip` is the guard of the locked
@@ -50,25 +90,38 @@ pub struct InodeFileType {
 struct InodeFileTypeGuard<'a, I> {
     ip: ManuallyDrop<InodeGuard<'a, I>>,
     off: &'a mut u32,
+    ra: &'a mut ReadAhead,
 }
 
 pub struct File {
     pub typ: FileType,
     readable: bool,
     writable: bool,
+    /// Whether reads return `EAGAIN` instead of waiting for input, i.e., `O_NONBLOCK`. It is
+    /// shared by the descriptors duplicated from the same `open`.
+    nonblock: AtomicBool,
 }
 
-pub type FileTable = ArrayArena<File, NFILE>;
+pub struct FileTable {
+    arena: ArrayArena<File, NFILE>,
+}
 
 /// map major device number to device functions.
 #[derive(Copy, Clone)]
 pub struct Devsw {
-    pub read: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
+    /// The name of the device node under `/dev` (see `devfs`), or empty if there is none.
+    pub name: &'static str,
+    /// Reads into the user virtual address. Returns Err(EAGAIN) instead of waiting for input if
+    /// the bool, i.e., nonblock, is true.
+    pub read: Option<fn(UVAddr, i32, bool, &mut KernelCtx<'_, '_>) -> Result<usize, KernelError>>,
     pub write: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
 }
 
+/// Maximum number of entries read by a `getdents_plus`.
+const NDIRENTPLUS: usize = 16;
+
 /// A reference counted smart pointer to a `File`.
-pub type RcFile = ArenaRc<FileTable>;
+pub type RcFile = ArenaRc<ArrayArena<File, NFILE>>;
 
 impl Default for FileType {
     fn default() -> Self {
@@ -81,13 +134,392 @@ impl InodeFileType {
         &self,
         ctx: &KernelCtx<'_, '_>,
     ) -> InodeFileTypeGuard<'_, <Ufs as FileSystem>::InodeInner> {
-        let ip = self.ip.lock(ctx);
-        // SAFETY: `ip` is locked and `off` can be exclusively accessed.
+        let ip = self.ip.lock(ctx).expect("InodeFileType::lock");
+        // SAFETY: `ip` is locked and `off` and `ra` can be exclusively accessed.
         let off = unsafe { &mut *self.off.get() };
+        let ra = unsafe { &mut *self.ra.get() };
         InodeFileTypeGuard {
             ip: ManuallyDrop::new(ip),
             off,
+            ra,
+        }
+    }
+
+    /// Moves the offset to `off` from the origin `whence`, one of `SEEK_SET`, `SEEK_CUR`, and
+    /// `SEEK_END`. The offset may go past the end of file, where reads return 0 bytes.
+    /// Returns Ok(new offset) on success, Err(()) if `whence` is invalid or the offset would be
+    /// negative or too large.
+    pub fn seek(&self, off: i32, whence: i32, ctx: &KernelCtx<'_, '_>) -> Result<u32, ()> {
+        let mut ip = self.lock(ctx);
+        let base = match whence {
+            SEEK_SET => Ok(0),
+            SEEK_CUR => Ok(*ip.off),
+            SEEK_END => Ok(ip.deref_inner().size),
+            _ => Err(()),
+        };
+        let new = base.and_then(|base| {
+            let new = base as i64 + off as i64;
+            if new < 0 || new > u32::MAX as i64 {
+                Err(())
+            } else {
+                Ok(new as u32)
+            }
+        });
+        if let Ok(new) = new {
+            *ip.off = new;
         }
+        ip.free(ctx);
+        new
+    }
+
+    /// Read n bytes into the user virtual address addr, at offset off if given. Otherwise, reads
+    /// at the offset of the file and advances it. A read that continues the previous one reads
+    /// ahead the following blocks (see `readahead_blocks`).
+    /// Returns Ok(number read) on success, Err(()) on error.
+    fn read(
+        &self,
+        addr: UVAddr,
+        n: u32,
+        off: Option<u32>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        if self.direct {
+            return self.read_direct(addr, n, off, ctx);
+        }
+        let mut ip = self.lock(ctx);
+        let curr_off = off.unwrap_or(*ip.off);
+        let ret = ip.read_user(addr, curr_off, n, ctx);
+        if let (Ok(v), None) = (ret, off) {
+            *ip.off += v as u32;
+        }
+        let blocks = match ret {
+            Ok(v) => Self::readahead_blocks(&mut ip, curr_off, v as u32, ctx),
+            Err(_) => ArrayVec::new(),
+        };
+        ip.free(ctx);
+        if !blocks.is_empty() {
+            hal().disk().read_ahead(self.ip.dev, &blocks, ctx);
+        }
+        ret
+    }
+
+    /// Returns the blocks to read ahead after a read of n bytes at offset off. If the read is
+    /// sequential, it keeps the window of `READAHEAD` blocks, doubled by `POSIX_FADV_SEQUENTIAL`
+    /// and none by `POSIX_FADV_RANDOM`, read ahead of it, topping it up once half of it has been
+    /// read.
+    fn readahead_blocks(
+        ip: &mut InodeFileTypeGuard<'_, <Ufs as FileSystem>::InodeInner>,
+        off: u32,
+        n: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> ArrayVec<u32, { 2 * READAHEAD }> {
+        let blocks = match ip.ra.advice {
+            POSIX_FADV_RANDOM => 0,
+            POSIX_FADV_SEQUENTIAL => 2 * READAHEAD,
+            _ => READAHEAD,
+        };
+        let window = (blocks * BSIZE) as u32;
+        let end = off + n;
+        let sequential = off == ip.ra.next;
+        ip.ra.next = end;
+        if !sequential {
+            ip.ra.end = 0;
+        }
+        if !sequential || window == 0 || n == 0 || ip.ra.end >= end.saturating_add(window / 2) {
+            return ArrayVec::new();
+        }
+        let from = cmp::max(ip.ra.end, end);
+        ip.ra.end = end.saturating_add(window);
+        let to = ip.ra.end;
+        ip.blocks_ahead(from, to, ctx)
+    }
+
+    /// Advises how the file will be read: `POSIX_FADV_NORMAL`, `POSIX_FADV_RANDOM` and
+    /// `POSIX_FADV_SEQUENTIAL` set how much is read ahead of sequential reads, while
+    /// `POSIX_FADV_WILLNEED` reads the `len` bytes at offset off, up to `2 * READAHEAD` blocks of
+    /// them, into the buffer cache, and `POSIX_FADV_DONTNEED` commits the logged blocks among them
+    /// and drops them from the caches. `len == 0` means up to the end of file.
+    /// Returns Ok(()) on success, Err(()) if advice is invalid.
+    pub fn fadvise(
+        &self,
+        off: u32,
+        len: u32,
+        advice: i32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        match advice {
+            POSIX_FADV_NORMAL | POSIX_FADV_RANDOM | POSIX_FADV_SEQUENTIAL => {
+                let mut ip = self.lock(ctx);
+                ip.ra.advice = advice;
+                ip.free(ctx);
+            }
+            POSIX_FADV_WILLNEED => {
+                let mut ip = self.lock(ctx);
+                let to = if len == 0 {
+                    u32::MAX
+                } else {
+                    off.saturating_add(len)
+                };
+                // Read from the block holding off.
+                let from = off / BSIZE as u32 * BSIZE as u32;
+                let blocks = ip.blocks_ahead::<{ 2 * READAHEAD }>(from, to, ctx);
+                ip.free(ctx);
+                hal().disk().read_ahead(self.ip.dev, &blocks, ctx);
+            }
+            POSIX_FADV_DONTNEED => {
+                // The bytes staged by the writes are not cached in blocks, so they stay.
+                ctx.kernel().fs().sync_range(&self.ip, off, len, ctx);
+                let mut ip = self.lock(ctx);
+                ip.drop_cached(off, len, ctx);
+                ip.free(ctx);
+            }
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    /// Reads like `read`, but with the disk writing into the user pages directly. addr, n and the
+    /// offset must be multiples of `BSIZE`.
+    /// Returns Ok(number read) on success, Err(()) on error, or if any of them is not aligned.
+    fn read_direct(
+        &self,
+        addr: UVAddr,
+        n: u32,
+        off: Option<u32>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        if addr.into_usize() % BSIZE != 0 || n as usize % BSIZE != 0 {
+            return Err(());
+        }
+        let mut ip = self.lock(ctx);
+        let curr_off = off.unwrap_or(*ip.off);
+        let ret = if curr_off as usize % BSIZE != 0 {
+            Err(())
+        } else {
+            ip.read_direct(addr, curr_off, n, ctx)
+        };
+        if let (Ok(v), None) = (ret, off) {
+            *ip.off += v as u32;
+        }
+        ip.free(ctx);
+        ret
+    }
+
+    /// Writes like `write`, but with the disk reading the user pages directly. addr, n and the
+    /// offset must be multiples of `BSIZE`.
+    /// Returns Ok(n) on success, Err(()) on error, or if any of them is not aligned.
+    fn write_direct(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        off: Option<u32>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        if addr.into_usize() % BSIZE != 0 || n % BSIZE != 0 {
+            return Err(());
+        }
+        // Allocate a few blocks at a time, as `write` does.
+        let max = (MAXOPBLOCKS - 1 - 3 - 2) / 2 * BSIZE;
+
+        let mut bytes_written: usize = 0;
+        while bytes_written < n {
+            let len = cmp::min(n - bytes_written, max) as u32;
+            let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+            let mut ip = self.lock(ctx);
+            if !ip.deref_inner().staged.is_empty() {
+                let r = ip.flush(&tx, ctx);
+                tx.end(ctx);
+                ip.free(ctx);
+                r?;
+                continue;
+            }
+            let curr_off = self.write_offset(&mut ip, off, bytes_written);
+            let r = if curr_off as usize % BSIZE != 0 {
+                Err(())
+            } else {
+                ip.prepare_direct(curr_off, len, &tx, ctx)
+            };
+            tx.end(ctx);
+            ip.free(ctx);
+            r?;
+
+            // The newly allocated blocks are zeroed through the log, which would overwrite what
+            // the disk writes if it committed later.
+            ctx.kernel().fs().sync_range(&self.ip, curr_off, len, ctx);
+
+            let mut ip = self.lock(ctx);
+            let r = ip.write_direct(addr + bytes_written, curr_off, len, ctx);
+            if let (Ok(r), None) = (r, off) {
+                *ip.off = curr_off + r as u32;
+            }
+            ip.free(ctx);
+            // Blocks that another write has logged meanwhile are tried again after a commit.
+            bytes_written += r?;
+        }
+        Ok(n)
+    }
+
+    /// Writes the bytes staged by the writes to the file to the disk, if any.
+    /// Returns Err(()) if the disk is full.
+    pub fn flush(&self, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let ip = self.lock(ctx);
+        let staged = !ip.deref_inner().staged.is_empty();
+        ip.free(ctx);
+        if !staged {
+            return Ok(());
+        }
+        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+        let mut ip = self.lock(ctx);
+        let res = ip.flush(&tx, ctx);
+        tx.end(ctx);
+        ip.free(ctx);
+        res
+    }
+
+    /// Reads the entries in use of the directory from the offset into user virtual address `addr`,
+    /// each with the status of its inode, as many as fit in `n` bytes up to `NDIRENTPLUS`, and
+    /// moves the offset past them. The directory is unlocked before the inodes are locked, so
+    /// that locking ".." cannot deadlock.
+    /// Returns Ok(number of bytes read) on success, Err(error) on error.
+    pub fn getdents_plus(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let max = n / mem::size_of::<DirentPlus>();
+        if max == 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut des = ArrayVec::<Dirent, NDIRENTPLUS>::new();
+        let mut ip = self.lock(ctx);
+        if ip.deref_inner().typ != InodeType::Dir {
+            ip.free(ctx);
+            return Err(Errno::ENOTDIR.into());
+        }
+        let off = *ip.off;
+        *ip.off = ip.read_dirents(off, max, &mut des, ctx);
+        ip.free(ctx);
+
+        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+        let res = try {
+            let count = des.len();
+            for (i, dirent) in des.into_iter().enumerate() {
+                let stat = ctx
+                    .kernel()
+                    .fs()
+                    .stat_inode(self.ip.dev, dirent.inum as u32, &tx, ctx)
+                    .map_err(|_| Errno::EIO)?;
+                let record = DirentPlus { stat, dirent };
+                ctx.proc()
+                    .memory()
+                    .copy_out(addr + i * mem::size_of::<DirentPlus>(), &record)
+                    .map_err(|_| Errno::EFAULT)?;
+            }
+            count * mem::size_of::<DirentPlus>()
+        };
+        tx.end(ctx);
+        res
+    }
+
+    /// Returns the offset to write at after `written` bytes of a write, which is at offset off if
+    /// given. Otherwise, it is at the offset of the file, which is moved to the end of file first
+    /// if opened with `O_APPEND`.
+    fn write_offset(
+        &self,
+        ip: &mut InodeFileTypeGuard<'_, <Ufs as FileSystem>::InodeInner>,
+        off: Option<u32>,
+        written: usize,
+    ) -> u32 {
+        match off {
+            Some(off) => off + written as u32,
+            None => {
+                if self.append {
+                    // Position at the end of file under the inode lock, so that concurrent
+                    // appenders do not overwrite each other.
+                    *ip.off = ip.deref_inner().size;
+                }
+                *ip.off
+            }
+        }
+    }
+
+    /// Write n bytes from the user virtual address addr, at offset off if given. Otherwise, writes
+    /// at the offset of the file, or at the end of file if opened with `O_APPEND`, and advances
+    /// the offset.
+    /// A write within a block is staged in memory with the previous ones, if it can be (see
+    /// `InodeGuard::stage_user`), so that a run of small writes costs a transaction per block
+    /// rather than per write.
+    /// Returns Ok(n) on success, Err(()) on error.
+    fn write(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        off: Option<u32>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        if self.direct {
+            return self.write_direct(addr, n, off, ctx);
+        }
+        if n < BSIZE {
+            let mut ip = self.lock(ctx);
+            let curr_off = self.write_offset(&mut ip, off, 0);
+            let staged = ip.stage_user(addr, curr_off, n as u32, ctx);
+            if let (Ok(()), None) = (staged, off) {
+                *ip.off += n as u32;
+            }
+            ip.free(ctx);
+            if staged.is_ok() {
+                return Ok(n);
+            }
+        }
+
+        // write a few blocks at a time to avoid exceeding
+        // the maximum log transaction size, including
+        // i-node, up to three indirect blocks (the double-indirect
+        // block and two of its indirect blocks), allocation blocks,
+        // and 2 blocks of slop for non-aligned writes.
+        // this really belongs lower down, since write()
+        // might be writing a device like the console.
+        let max = (MAXOPBLOCKS - 1 - 3 - 2) / 2 * BSIZE;
+
+        let mut bytes_written: usize = 0;
+        while bytes_written < n {
+            let bytes_to_write = cmp::min(n - bytes_written, max);
+            let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+            let mut ip = self.lock(ctx);
+            if !ip.deref_inner().staged.is_empty() {
+                // Write the staged bytes first, in a transaction of their own.
+                let r = ip.flush(&tx, ctx);
+                tx.end(ctx);
+                ip.free(ctx);
+                r?;
+                continue;
+            }
+            let curr_off = self.write_offset(&mut ip, off, bytes_written);
+            let r = ip.write_user(
+                addr + bytes_written,
+                curr_off,
+                bytes_to_write as u32,
+                ctx,
+                &tx,
+            );
+            if let (Ok(r), None) = (r, off) {
+                *ip.off += r as u32;
+            }
+            tx.end(ctx);
+            ip.free(ctx);
+            let r = r?;
+            if r != bytes_to_write {
+                // error from write_user
+                break;
+            }
+            bytes_written += r;
+        }
+        if bytes_written != n {
+            return Err(());
+        }
+        Ok(n)
     }
 }
 
@@ -127,6 +559,48 @@ impl File {
             typ,
             readable,
             writable,
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.readable
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Returns the status flags of file self, i.e., its access mode, `O_APPEND`, `O_DIRECT` and
+    /// `O_NONBLOCK`.
+    pub fn status_flags(&self) -> FcntlFlags {
+        let mut flags = match (self.readable, self.writable) {
+            (true, true) => FcntlFlags::O_RDWR,
+            (false, true) => FcntlFlags::O_WRONLY,
+            _ => FcntlFlags::O_RDONLY,
+        };
+        if matches!(&self.typ, FileType::Inode { inner } if inner.append) {
+            flags |= FcntlFlags::O_APPEND;
+        }
+        if matches!(&self.typ, FileType::Inode { inner } if inner.direct) {
+            flags |= FcntlFlags::O_DIRECT;
+        }
+        if self.nonblock.load(Ordering::Relaxed) {
+            flags |= FcntlFlags::O_NONBLOCK;
+        }
+        flags
+    }
+
+    /// Sets whether reads from file self return `EAGAIN` instead of waiting for input.
+    pub fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+
+    /// Returns the inode of file self if it is a regular file, which can be mmap()ed.
+    pub fn mappable_inode(&self) -> Option<&RcInode<<Ufs as FileSystem>::InodeInner>> {
+        match &self.typ {
+            FileType::Inode { inner } => Some(&inner.ip),
+            _ => None,
         }
     }
 
@@ -139,7 +613,7 @@ impl File {
             }
             | FileType::Device { ip, .. } => {
                 let st = ip.stat(ctx);
-                ctx.proc_mut().memory_mut().copy_out(addr, &st)
+                ctx.proc().memory().copy_out(addr, &st)
             }
             _ => Err(()),
         }
@@ -147,27 +621,35 @@ impl File {
 
     /// Read from file self.
     /// addr is a user virtual address.
-    pub fn read(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// Returns Err(EAGAIN) if self is nonblocking and a pipe, a device or a watch has no input
+    /// waiting.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         if !self.readable {
-            return Err(());
+            return Err(().into());
         }
 
+        let nonblock = self.nonblock.load(Ordering::Relaxed);
         match &self.typ {
-            FileType::Pipe { pipe } => pipe.read(addr, n as usize, ctx),
-            FileType::Inode { inner } => {
-                let mut ip = inner.lock(ctx);
-                let curr_off = *ip.off;
-                let ret = ip.read_user(addr, curr_off, n as u32, ctx);
-                if let Ok(v) = ret {
-                    *ip.off += v as u32;
-                }
-                ip.free(ctx);
-                ret
-            }
+            FileType::Pipe { pipe } => pipe.read(addr, n as usize, nonblock, ctx),
+            FileType::Inode { inner } => Ok(inner.read(addr, n as u32, None, ctx)?),
             FileType::Device { major, .. } => {
                 let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
                 let read = major.read.ok_or(())?;
-                Ok(read(addr, n, ctx) as usize)
+                read(addr, n, nonblock, ctx)
+            }
+            FileType::Pidfd { pid } => {
+                ctx.kernel().procs().wait_exit(*pid, ctx)?;
+                Ok(0)
+            }
+            FileType::Watch { slot, .. } => {
+                ctx.kernel()
+                    .fs_watches()
+                    .read(*slot, addr, n as usize, nonblock, ctx)
             }
             FileType::None => panic!("File::read"),
         }
@@ -182,55 +664,182 @@ impl File {
 
         match &self.typ {
             FileType::Pipe { pipe } => pipe.write(addr, n as usize, ctx),
-            FileType::Inode { inner } => {
-                let n = n as usize;
-
-                // write a few blocks at a time to avoid exceeding
-                // the maximum log transaction size, including
-                // i-node, indirect block, allocation blocks,
-                // and 2 blocks of slop for non-aligned writes.
-                // this really belongs lower down, since write()
-                // might be writing a device like the console.
-                let max = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
-
-                let mut bytes_written: usize = 0;
-                while bytes_written < n {
-                    let bytes_to_write = cmp::min(n - bytes_written, max);
-                    let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                    let mut ip = inner.lock(ctx);
-                    let curr_off = *ip.off;
-                    let r = ip.write_user(
-                        addr + bytes_written,
-                        curr_off,
-                        bytes_to_write as u32,
-                        ctx,
-                        &tx,
-                    );
-                    if let Ok(r) = r {
-                        *ip.off += r as u32;
-                    }
-                    tx.end(ctx);
-                    ip.free(ctx);
-                    let r = r?;
-                    if r != bytes_to_write {
-                        // error from write_user
-                        break;
-                    }
-                    bytes_written += r;
-                }
-                if bytes_written != n {
-                    return Err(());
-                }
-                Ok(n)
-            }
+            FileType::Inode { inner } => inner.write(addr, n as usize, None, ctx),
             FileType::Device { major, .. } => {
                 let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
                 let write = major.write.ok_or(())?;
                 Ok(write(addr, n, ctx) as usize)
             }
+            FileType::Pidfd { .. } | FileType::Watch { .. } => Err(()),
             FileType::None => panic!("File::read"),
         }
     }
+
+    /// Read from file self at offset off, without moving the offset of the file.
+    /// addr is a user virtual address.
+    /// Returns Ok(number read) on success, Err(()) if self is not readable or not a regular file.
+    pub fn pread(
+        &self,
+        addr: UVAddr,
+        n: u32,
+        off: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        match &self.typ {
+            FileType::Inode { inner } if self.readable => inner.read(addr, n, Some(off), ctx),
+            _ => Err(()),
+        }
+    }
+
+    /// Write to file self at offset off, without moving the offset of the file, even if it was
+    /// opened with `O_APPEND`.
+    /// addr is a user virtual address.
+    /// Returns Ok(n) on success, Err(()) if self is not writable or not a regular file.
+    pub fn pwrite(
+        &self,
+        addr: UVAddr,
+        n: u32,
+        off: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        match &self.typ {
+            FileType::Inode { inner } if self.writable => {
+                inner.write(addr, n as usize, Some(off), ctx)
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Read the entries of directory self with the status of their inodes.
+    /// addr is a user virtual address, pointing to an array of struct direntplus.
+    /// Returns Ok(number read) on success, Err(error) on error.
+    pub fn getdents_plus(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        match &self.typ {
+            FileType::Inode { inner } if self.readable => inner.getdents_plus(addr, n, ctx),
+            FileType::Inode { .. } => Err(Errno::EBADF.into()),
+            _ => Err(Errno::ENOTDIR.into()),
+        }
+    }
+
+    /// Advise how file self will be read (see `InodeFileType::fadvise`).
+    /// Returns Ok(()) on success, Err(ESPIPE) if self is a pipe, or Err(EINVAL) if advice is
+    /// invalid.
+    pub fn fadvise(
+        &self,
+        off: u32,
+        len: u32,
+        advice: i32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        match &self.typ {
+            FileType::Inode { inner } => {
+                inner
+                    .fadvise(off, len, advice, ctx)
+                    .map_err(|_| Errno::EINVAL.into())
+            }
+            FileType::Pipe { .. } => Err(Errno::ESPIPE.into()),
+            _ if advice < POSIX_FADV_NORMAL || advice > POSIX_FADV_DONTNEED => {
+                Err(Errno::EINVAL.into())
+            }
+            // Devices and the others have nothing to read ahead or to cache.
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the bytes staged by the writes to file self to the disk, if it is a regular file.
+    /// Returns Err(()) if the disk is full.
+    pub fn flush(&self, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        match &self.typ {
+            FileType::Inode { inner } => inner.flush(ctx),
+            _ => Ok(()),
+        }
+    }
+
+    /// Force the dirty blocks of file self in the byte range [off, off + len) to disk.
+    pub fn sync_range(
+        &self,
+        off: u32,
+        len: u32,
+        flags: SyncFileRangeFlags,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        match &self.typ {
+            FileType::Inode { inner } => {
+                inner.flush(ctx)?;
+                // Log commits are synchronous, so starting the write-out and waiting for it are
+                // the same thing. Without any flag, sync_file_range() is a no-op.
+                if !flags.is_empty() {
+                    ctx.kernel().fs().sync_range(&inner.ip, off, len, ctx);
+                }
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Force file self to disk. Since the log holds the updates of every file together, it commits
+    /// all of them.
+    /// Returns Err(EINVAL) if self is a pipe, or Err(ENOSPC) if the bytes staged by the writes to
+    /// self do not fit on the disk.
+    pub fn fsync(&self, ctx: &KernelCtx<'_, '_>) -> Result<(), KernelError> {
+        match &self.typ {
+            FileType::Inode { inner } => {
+                inner.flush(ctx).map_err(|_| Errno::ENOSPC)?;
+                ctx.kernel().fs().sync(ctx);
+                Ok(())
+            }
+            // Devices write through.
+            FileType::Device { .. } => Ok(()),
+            _ => Err(Errno::EINVAL.into()),
+        }
+    }
+
+    /// Change the size of file self to len, freeing the blocks past len or extending it with
+    /// zeros.
+    /// Returns Err(()) if self is not a writable regular file, or if len is too large.
+    pub fn truncate(&self, len: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        match &self.typ {
+            FileType::Inode { inner } if self.writable => {
+                let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+                let mut ip = inner.lock(ctx);
+                let ret = ip.truncate(len, &tx, ctx);
+                tx.end(ctx);
+                ip.free(ctx);
+                ret
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Sets the access time and the modification time of the inode of file self to `atime` and
+    /// `mtime`, unless they are None.
+    /// Returns Ok(()) on success, Err(()) if self is not an inode.
+    pub fn set_times(
+        &self,
+        atime: Option<u32>,
+        mtime: Option<u32>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let ip = match &self.typ {
+            FileType::Inode {
+                inner: InodeFileType { ip, .. },
+            }
+            | FileType::Device { ip, .. } => ip,
+            _ => return Err(()),
+        };
+        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+        let ret = ip.lock(ctx).map(|mut ip| {
+            ip.set_times(atime, mtime, &tx, ctx);
+            ip.free(ctx);
+        });
+        tx.end(ctx);
+        ret.map_err(|_| ())
+    }
 }
 
 impl const Default for File {
@@ -243,6 +852,10 @@ impl ArenaObject for File {
     type Ctx<'a, 'id: 'a> = &'a KernelCtx<'id, 'a>;
 
     fn finalize<'a, 'id: 'a>(&mut self, ctx: Self::Ctx<'a, 'id>) {
+        if self.writable {
+            // A full disk cannot be reported on close, and drops the staged bytes.
+            let _ = self.flush(ctx);
+        }
         let typ = mem::replace(&mut self.typ, FileType::None);
         match typ {
             FileType::Pipe { pipe } => {
@@ -258,6 +871,12 @@ impl ArenaObject for File {
                 ip.free((&tx, ctx));
                 tx.end(ctx);
             }
+            FileType::Watch { ip, slot } => {
+                ctx.kernel().fs_watches().remove(slot);
+                let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+                ip.free((&tx, ctx));
+                tx.end(ctx);
+            }
             _ => (),
         }
     }
@@ -265,7 +884,14 @@ impl ArenaObject for File {
 
 impl FileTable {
     pub const fn new_ftable() -> Self {
-        ArrayArena::<File, NFILE>::new("FTABLE")
+        Self {
+            arena: ArrayArena::<File, NFILE>::new(RawSpinLock::new("FTABLE")),
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn arena<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, ArrayArena<File, NFILE>> {
+        unsafe { StrongPin::new_unchecked(&self.ptr().arena) }
     }
 
     /// Allocate a file structure.
@@ -275,22 +901,24 @@ impl FileTable {
         readable: bool,
         writable: bool,
     ) -> Result<RcFile, ()> {
-        self.alloc(|| File::new(typ, readable, writable)).ok_or(())
+        self.arena()
+            .alloc(|| File::new(typ, readable, writable))
+            .ok_or(())
     }
 }
 
-impl RcFile {
-    /// Allocate a file descriptor for the given file.
+impl KernelCtx<'_, '_> {
+    /// Allocate a file descriptor for the given file, with the close-on-exec flag cloexec.
     /// Takes over file reference from caller on success.
-    pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, ()> {
-        let proc_data = ctx.proc_mut().deref_mut_data();
-        for (fd, f) in proc_data.open_files.iter_mut().enumerate() {
-            if f.is_none() {
-                *f = Some(self);
-                return Ok(fd as i32);
-            }
+    pub fn fdalloc(&mut self, file: RcFile, cloexec: bool) -> Result<i32, ()> {
+        let mut files = self.proc().files();
+        if let Some(fd) = files.open_files.iter().position(|f| f.is_none()) {
+            files.open_files[fd] = Some(file);
+            files.cloexec[fd] = cloexec;
+            return Ok(fd as i32);
         }
-        self.free(ctx);
+        drop(files);
+        file.free(self);
         Err(())
     }
 }
@@ -1,8 +1,13 @@
 // TODO: remove it
 #![allow(unused_variables)]
 
-use super::{FcntlFlags, FileSystem, Inode, InodeGuard, InodeType, Path, RcInode};
-use crate::{arena::ArenaObject, proc::KernelCtx, util::strong_pin::StrongPin};
+use super::{FcntlFlags, FileSystem, FswMask, Inode, InodeGuard, InodeType, Path, RcInode};
+use crate::{
+    arena::ArenaObject,
+    error::KernelError,
+    proc::{Gid, KernelCtx, Uid},
+    util::strong_pin::StrongPin,
+};
 
 pub struct InodeInner {}
 
@@ -20,7 +25,7 @@ impl FileSystem for Lfs {
     type InodeInner = InodeInner;
     type Tx<'s> = &'s ();
 
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
+    unsafe fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
         todo!()
     }
 
@@ -37,7 +42,7 @@ impl FileSystem for Lfs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self::InodeInner>, ()> {
+    ) -> Result<RcInode<Self::InodeInner>, KernelError> {
         todo!()
     }
 
@@ -47,7 +52,7 @@ impl FileSystem for Lfs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         todo!()
     }
 
@@ -56,7 +61,17 @@ impl FileSystem for Lfs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
+        todo!()
+    }
+
+    fn rename(
+        self: StrongPin<'_, Self>,
+        old: &Path,
+        new: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
         todo!()
     }
 
@@ -67,7 +82,7 @@ impl FileSystem for Lfs {
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self::InodeInner>, T), ()>
+    ) -> Result<(RcInode<Self::InodeInner>, T), KernelError>
     where
         F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>) -> T,
     {
@@ -80,7 +95,17 @@ impl FileSystem for Lfs {
         omode: FcntlFlags,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
+        todo!()
+    }
+
+    fn watch(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        mask: FswMask,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         todo!()
     }
 
@@ -89,7 +114,58 @@ impl FileSystem for Lfs {
         inode: RcInode<Self::InodeInner>,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
+        todo!()
+    }
+
+    fn set_times(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        atime: Option<u32>,
+        mtime: Option<u32>,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        todo!()
+    }
+
+    fn chmod(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        mode: u32,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        todo!()
+    }
+
+    fn chown(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        uid: Option<Uid>,
+        gid: Option<Gid>,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        todo!()
+    }
+
+    fn mount(
+        self: StrongPin<'_, Self>,
+        fstype: &[u8],
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        todo!()
+    }
+
+    fn umount(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
         todo!()
     }
 }
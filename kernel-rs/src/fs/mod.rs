@@ -1,35 +1,26 @@
 use core::ops::Deref;
 
 use bitflags::bitflags;
+use rv6_abi::{S_IROTH, S_IWOTH, S_IXOTH};
 
 use crate::{
     arena::{ArenaObject, ArenaRc, ArrayArena},
+    error::KernelError,
     lock::SleepLock,
     param::NINODE,
-    proc::KernelCtx,
+    proc::{Gid, KernelCtx, Uid},
     util::strong_pin::StrongPin,
 };
 
 mod lfs;
 mod path;
-mod stat;
 mod ufs;
 
 pub use lfs::Lfs;
 pub use path::{FileName, Path};
-pub use stat::Stat;
+pub use rv6_abi::{FcntlFlags, FswMask, Stat, SyncFileRangeFlags};
 pub use ufs::Ufs;
 
-bitflags! {
-    pub struct FcntlFlags: i32 {
-        const O_RDONLY = 0;
-        const O_WRONLY = 0x1;
-        const O_RDWR = 0x2;
-        const O_CREATE = 0x200;
-        const O_TRUNC = 0x400;
-    }
-}
-
 #[derive(Copy, Clone, PartialEq, Debug)]
 #[repr(i16)]
 pub enum InodeType {
@@ -39,6 +30,16 @@ pub enum InodeType {
     Device { major: u16, minor: u16 },
 }
 
+bitflags! {
+    /// Accesses to an inode that the permission bits allow or deny, as the bits of the class of
+    /// the others. Executing a directory means searching it.
+    pub struct Access: u32 {
+        const READ = S_IROTH;
+        const WRITE = S_IWOTH;
+        const EXEC = S_IXOTH;
+    }
+}
+
 /// InodeGuard implies that `SleepLock<InodeInner>` is held by current thread.
 ///
 /// # Safety
@@ -75,6 +76,7 @@ impl<I> InodeGuard<'_, I> {
     }
 
     pub fn free(self, ctx: &KernelCtx<'_, '_>) {
+        ctx.proc().deref_data().guards.unlock_inode();
         // SAFETY: self will be dropped.
         unsafe { self.inner.unlock(ctx) };
         core::mem::forget(self);
@@ -101,10 +103,19 @@ pub struct Inode<I> {
     pub inner: SleepLock<I>,
 }
 
-pub type Itable<I> = ArrayArena<Inode<I>, NINODE>;
+pub struct Itable<I> {
+    arena: ArrayArena<Inode<I>, NINODE>,
+}
 
 /// A reference counted smart pointer to an `Inode`.
-pub type RcInode<I> = ArenaRc<Itable<I>>;
+pub type RcInode<I> = ArenaRc<ArrayArena<Inode<I>, NINODE>>;
+
+impl<I> Itable<I> {
+    #[allow(clippy::needless_lifetimes)]
+    fn arena<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, ArrayArena<Inode<I>, NINODE>> {
+        unsafe { StrongPin::new_unchecked(&self.ptr().arena) }
+    }
+}
 
 pub trait FileSystem
 where
@@ -116,7 +127,13 @@ where
     type Tx<'s>;
 
     /// Initializes the file system (loading from the disk).
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>);
+    /// Returns Err(()) if the disk does not hold a valid file system.
+    ///
+    /// # Safety
+    ///
+    /// It must be called only once, by the first process before it returns to user space. If it
+    /// fails, no process may return to user space.
+    unsafe fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()>;
 
     /// Called for each FS system call.
     fn begin_tx(&self, ctx: &KernelCtx<'_, '_>) -> Self::Tx<'_>;
@@ -130,29 +147,39 @@ where
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self::InodeInner>, ()>;
+    ) -> Result<RcInode<Self::InodeInner>, KernelError>;
 
     /// Create another name(newname) for the file oldname.
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Returns Ok(()) on success, Err(error) on error.
     fn link(
         self: StrongPin<'_, Self>,
         inode: RcInode<Self::InodeInner>,
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), KernelError>;
 
     /// Remove a file(filename).
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Returns Ok(()) on success, Err(error) on error.
     fn unlink(
         self: StrongPin<'_, Self>,
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), KernelError>;
+
+    /// Atomically rename the file or directory `old` to `new`, replacing `new` if it exists.
+    /// Returns Ok(()) on success, Err(error) on error.
+    fn rename(
+        self: StrongPin<'_, Self>,
+        old: &Path,
+        new: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
 
     /// Create an inode with given type.
-    /// Returns Ok(created inode, result of given function f) on success, Err(()) on error.
+    /// Returns Ok(created inode, result of given function f) on success, Err(error) on error.
     fn create<F, T>(
         self: StrongPin<'_, Self>,
         path: &Path,
@@ -160,26 +187,90 @@ where
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self::InodeInner>, T), ()>
+    ) -> Result<(RcInode<Self::InodeInner>, T), KernelError>
     where
         F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>) -> T;
 
-    /// Open a file; omode indicate read/write.
-    /// Returns Ok(file descriptor) on success, Err(()) on error.
+    /// Open a file; omode indicate read/write, and whether the file is nonblocking and the
+    /// descriptor close-on-exec.
+    /// Returns Ok(file descriptor) on success, Err(error) on error.
     fn open(
         self: StrongPin<'_, Self>,
         path: &Path,
         omode: FcntlFlags,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()>;
+    ) -> Result<usize, KernelError>;
+
+    /// Watch the events in mask on the file at path, which the current process must be able to
+    /// read.
+    /// Returns Ok(file descriptor reading the events) on success, Err(error) on error.
+    fn watch(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        mask: FswMask,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError>;
 
     /// Change the current directory.
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Returns Ok(()) on success, Err(error) on error.
     fn chdir(
         self: StrongPin<'_, Self>,
         inode: RcInode<Self::InodeInner>,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), KernelError>;
+
+    /// Set the access time and the modification time of an inode, unless they are None.
+    /// Returns Ok(()) on success, Err(error) on error.
+    fn set_times(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        atime: Option<u32>,
+        mtime: Option<u32>,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
+
+    /// Set the permission bits of an inode to `mode`. Only its owner or the superuser may.
+    /// Returns Ok(()) on success, Err(error) on error.
+    fn chmod(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        mode: u32,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
+
+    /// Set the owner and the group of an inode, unless they are None. Only the superuser may.
+    /// Returns Ok(()) on success, Err(error) on error.
+    fn chown(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        uid: Option<Uid>,
+        gid: Option<Gid>,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
+
+    /// Mount the pseudo file system `fstype` on `path`, whose parent must be a directory of this
+    /// file system (see `mount`).
+    /// Returns Ok(()) on success, Err(error) on error.
+    fn mount(
+        self: StrongPin<'_, Self>,
+        fstype: &[u8],
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
+
+    /// Unmount the pseudo file system mounted on `path`.
+    /// Returns Ok(()) on success, Err(error) on error.
+    fn umount(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
 }
@@ -0,0 +1,150 @@
+//! In-memory hash indexes of big directories.
+//!
+//! Looking up a name in a directory reads every `Dirent` of the directory, which makes path
+//! resolution, and the check that a name is not taken yet, slow in big directories. A big
+//! directory gets a `DirHash` in its in-memory inode instead, built from its entries on the first
+//! lookup. It maps the hashes of the names to the indices of the entries, so that a lookup reads
+//! only the entries whose names have the same hash, which usually means one or none.
+//!
+//! An index is a table of `NSLOT` slots, with open addressing and linear probing. A slot holds
+//! the upper 16 bits of the hash of a name, and the index of the entry plus one, so that most
+//! mismatches are told apart without reading the entry. The index is never written to the disk.
+//!
+//! The tables are a fixed pool rather than pages of the allocator, so that the indexes never take
+//! memory away from processes. A directory goes without an index if the pool is exhausted.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use array_macro::array;
+
+use crate::param::BSIZE;
+
+/// Directories smaller than this (in bytes) are scanned instead, since their entries fit in a
+/// block.
+pub const DIRHASH_MIN_SIZE: u32 = BSIZE as u32;
+
+/// Number of slots of an index.
+const NSLOT: usize = 1024;
+
+/// Maximum number of used slots, including removed ones, so that probing stays short.
+const MAX_USED: usize = NSLOT * 3 / 4;
+
+/// Number of indexes, i.e., of big directories that can have one at the same time.
+const NDIRHASH: usize = 8;
+
+/// A slot that has never been used. Probing stops at it.
+const EMPTY: u32 = 0;
+
+/// A slot whose entry has been removed. Probing goes past it.
+const REMOVED: u32 = u32::MAX;
+
+/// Largest index of an entry that fits in a slot.
+const MAX_INDEX: u32 = 0xfffe - 1;
+
+/// The tables of the indexes. `IN_USE[i]` tells whether a `DirHash` owns `TABLES[i]`.
+static mut TABLES: [[u32; NSLOT]; NDIRHASH] = [[EMPTY; NSLOT]; NDIRHASH];
+static IN_USE: [AtomicBool; NDIRHASH] = array![_ => AtomicBool::new(false); NDIRHASH];
+
+pub struct DirHash {
+    /// Index of the table in `TABLES`.
+    table: usize,
+
+    /// Number of slots in use, including removed ones.
+    used: usize,
+
+    /// Every entry before this one is in use, so that looking for an empty entry can start here.
+    pub free: u32,
+}
+
+/// Returns the FNV-1a hash of `name`.
+fn hash(name: &[u8]) -> u32 {
+    name.iter().fold(0x811c_9dc5, |h, c| {
+        (h ^ *c as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+impl DirHash {
+    /// Returns an empty index, or None if every table is in use.
+    pub fn new() -> Option<Self> {
+        let table = IN_USE.iter().position(|in_use| {
+            in_use
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        let mut hash = Self {
+            table,
+            used: 0,
+            free: 0,
+        };
+        hash.slots_mut().fill(EMPTY);
+        Some(hash)
+    }
+
+    fn slots(&self) -> &[u32; NSLOT] {
+        // SAFETY: `self` owns the table, since `IN_USE[self.table]` is set.
+        unsafe { &TABLES[self.table] }
+    }
+
+    fn slots_mut(&mut self) -> &mut [u32; NSLOT] {
+        // SAFETY: `self` owns the table, since `IN_USE[self.table]` is set.
+        unsafe { &mut TABLES[self.table] }
+    }
+
+    /// Returns the tag of `name`, and the slots to probe for it, in order.
+    fn probe(name: &[u8]) -> (u32, impl Iterator<Item = usize>) {
+        let h = hash(name);
+        let start = h as usize % NSLOT;
+        (h >> 16, (0..NSLOT).map(move |i| (start + i) % NSLOT))
+    }
+
+    /// Adds the entry of `name` at `index`.
+    /// Returns Err(()) if the index is full, or `index` is too large. Then, the index must be
+    /// dropped, since it misses the entry. Building it again drops the removed slots too.
+    pub fn insert(&mut self, name: &[u8], index: u32) -> Result<(), ()> {
+        if self.used >= MAX_USED || index > MAX_INDEX {
+            return Err(());
+        }
+        let (tag, mut probe) = Self::probe(name);
+        let i = probe
+            .find(|i| matches!(self.slots()[*i], EMPTY | REMOVED))
+            .expect("DirHash::insert");
+        if self.slots()[i] == EMPTY {
+            self.used += 1;
+        }
+        self.slots_mut()[i] = tag << 16 | (index + 1);
+        Ok(())
+    }
+
+    /// Removes the entry of `name` at `index`.
+    pub fn remove(&mut self, name: &[u8], index: u32) {
+        let (tag, probe) = Self::probe(name);
+        let target = tag << 16 | (index + 1);
+        for i in probe {
+            match self.slots()[i] {
+                EMPTY => return,
+                slot if slot == target => {
+                    self.slots_mut()[i] = REMOVED;
+                    return;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Returns the indices of the entries that may be of `name`, i.e., whose names have the same
+    /// tag. The caller must compare the names.
+    pub fn candidates<'s>(&'s self, name: &[u8]) -> impl Iterator<Item = u32> + 's {
+        let (tag, probe) = Self::probe(name);
+        probe
+            .map(move |i| self.slots()[i])
+            .take_while(|slot| *slot != EMPTY)
+            .filter(move |slot| *slot != REMOVED && *slot >> 16 == tag)
+            .map(|slot| (slot & 0xffff) - 1)
+    }
+}
+
+impl Drop for DirHash {
+    fn drop(&mut self) {
+        IN_USE[self.table].store(false, Ordering::Release);
+    }
+}
@@ -6,7 +6,7 @@
 //! list of blocks holding the file's content.
 //!
 //! The inodes are laid out sequentially on disk at
-//! kernel.fs().superblock.startinode. Each inode has a number, indicating its
+//! kernel.fs().mounted().superblock().inodestart. Each inode has a number, indicating its
 //! position on the disk.
 //!
 //! The kernel keeps a table of in-use inodes in memory
@@ -68,42 +68,73 @@
 //! read or write that inode's ip->valid, ip->size, ip->type, &c.
 
 use core::{
+    cmp,
+    convert::TryFrom,
     iter::StepBy,
     mem,
     ops::{Deref, Range},
-    ptr,
+    slice,
 };
 
-use static_assertions::const_assert;
+use arrayvec::ArrayVec;
+use rv6_abi::extent::{ExtentMap, EXTENT_BLOCK, NEXTENT_INODE};
+pub use rv6_abi::{Dirent, DIRSIZ};
+use rv6_abi::{FswMask, NSEC_PER_SEC, T_DEVICE, T_DIR, T_FILE};
 use zerocopy::{AsBytes, FromBytes};
 
-use super::{FileName, Path, Stat, UfsTx, IPB, MAXFILE, NDIRECT, NINDIRECT, ROOTINO};
+use super::{
+    dinodes, dinodes_mut,
+    dirhash::{DirHash, DIRHASH_MIN_SIZE},
+    Dinode, FileName, Path, Stat, UfsTx, IPB, MAXFILE, NDINDIRECT, NDIRECT, NINDIRECT, ROOTINO,
+};
 use crate::{
-    arch::addr::UVAddr,
+    arch::addr::{UVAddr, PGSIZE},
     arena::{Arena, ArenaObject, ArrayArena},
-    bio::BufData,
-    fs::{Inode, InodeGuard, InodeType, Itable, RcInode},
+    error::{Errno, KernelError},
+    fs::{Access, Inode, InodeGuard, InodeType, Itable, RcInode},
     hal::hal,
-    lock::SleepLock,
+    lock::{RawSpinLock, SleepLock},
+    mount::{pseudo_fs, PseudoFile},
+    ok_or,
     param::ROOTDEV,
-    param::{BSIZE, NINODE},
-    proc::KernelCtx,
+    param::{BSIZE, LOGSIZE, NINODE},
+    proc::{Gid, KernelCtx, Uid},
     util::strong_pin::StrongPin,
 };
 
-/// Directory is a file containing a sequence of Dirent structures.
-pub const DIRSIZ: usize = 14;
-
 /// dirent size
 pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
 
+/// Contents of a hole in a file, i.e., a block that has not been allocated.
+static ZEROS: [u8; BSIZE] = [0; BSIZE];
+
+/// Returns the wall-clock time in seconds since the epoch, the unit of the timestamps of inodes.
+fn now() -> u32 {
+    (hal().rtc().now() / NSEC_PER_SEC as u64) as u32
+}
+
+/// Type of an on-disk inode, i.e., the valid values of `Dinode::typ`.
 #[derive(Copy, Clone, PartialEq, Debug)]
-#[repr(i16)]
+#[repr(u16)]
 pub enum DInodeType {
-    None,
-    Dir,
-    File,
-    Device,
+    None = 0,
+    Dir = T_DIR,
+    File = T_FILE,
+    Device = T_DEVICE,
+}
+
+impl TryFrom<u16> for DInodeType {
+    type Error = ();
+
+    fn try_from(typ: u16) -> Result<Self, ()> {
+        match typ {
+            0 => Ok(Self::None),
+            T_DIR => Ok(Self::Dir),
+            T_FILE => Ok(Self::File),
+            T_DEVICE => Ok(Self::Device),
+            _ => Err(()),
+        }
+    }
 }
 
 pub struct InodeInner {
@@ -113,78 +144,158 @@ pub struct InodeInner {
     pub typ: InodeType,
     pub nlink: i16,
     pub size: u32,
-    pub addr_direct: [u32; NDIRECT],
-    pub addr_indirect: u32,
+    /// The addresses of the direct blocks, the indirect block, and the double-indirect block,
+    /// or the extents of the file if the file system has `FS_EXTENTS` set (see
+    /// `rv6_abi::extent`).
+    pub addrs: [u32; NDIRECT + 2],
+    pub gen: u32,
+    /// Timestamps, in seconds since the epoch. A read updates `atime` only in memory, and it
+    /// reaches the disk with the next update of the inode.
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+    /// Permission bits, and the owner and the group that they are for.
+    pub mode: u32,
+    pub uid: Uid,
+    pub gid: Gid,
+    /// The run of contiguous blocks that was looked up or allocated last, so that sequential
+    /// accesses need not read the indirect blocks again and again.
+    pub extent: Extent,
+    /// Bytes that small writes to the file have left in memory, to be written to the disk
+    /// together.
+    pub staged: Staged,
+    /// Hash index of the names in the directory, if it is big enough to deserve one.
+    pub dirhash: Option<DirHash>,
+    /// Building the index failed since the directory has too many entries. It is tried again once
+    /// an entry is removed.
+    pub dirhash_full: bool,
 }
 
-/// On-disk inode structure
-/// Both the kernel and user programs use this header file.
-// It needs repr(C) because it's struct for in-disk representation
-// which should follow C(=machine) representation
-// https://github.com/kaist-cp/rv6/issues/52
-#[repr(C)]
-pub struct Dinode {
-    /// File type
-    typ: DInodeType,
-
-    /// Major device number (T_DEVICE only)
-    major: u16,
+impl InodeInner {
+    /// Returns Ok(()) if the current process may access the inode as `access`, by the bits of the
+    /// owner if its effective user ID owns the inode, by those of the group if its group does,
+    /// and by those of the others otherwise. The superuser may do anything but execute a file
+    /// that no one may execute.
+    pub fn check_access(&self, access: Access, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let euid = ctx.proc().euid();
+        let allowed = if euid == 0 {
+            if self.typ == InodeType::Dir || self.mode & 0o111 != 0 {
+                Access::all()
+            } else {
+                Access::READ | Access::WRITE
+            }
+        } else if euid == self.uid {
+            Access::from_bits_truncate(self.mode >> 6)
+        } else if ctx.proc().gid() == self.gid {
+            Access::from_bits_truncate(self.mode >> 3)
+        } else {
+            Access::from_bits_truncate(self.mode)
+        };
+        if allowed.contains(access) {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+}
 
-    /// Minor device number (T_DEVICE only)
-    minor: u16,
+/// The bytes `start..end` of a file, which lie in a block and small writes have coalesced in
+/// memory instead of writing each through a transaction (see `InodeGuard::stage_user`). They are
+/// written to the disk by `InodeGuard::flush`, once a write reaches the end of the block or goes
+/// elsewhere, or the file is synced or closed. Meanwhile, reads see them, and the size of the
+/// file covers them in memory but not on the disk.
+pub struct Staged {
+    start: u32,
+    end: u32,
+    /// Size of the file on the disk.
+    disk_size: u32,
+    /// Content of the block, of which the bytes of `start..end` are valid.
+    data: [u8; BSIZE],
+}
 
-    /// Number of links to inode in file system
-    nlink: i16,
+impl Staged {
+    const fn new() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            disk_size: 0,
+            data: [0; BSIZE],
+        }
+    }
 
-    /// Size of file (bytes)
-    size: u32,
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
 
-    /// Direct data block addresses
-    addr_direct: [u32; NDIRECT],
+    fn clear(&mut self) {
+        self.start = 0;
+        self.end = 0;
+    }
 
-    /// Indirect data block address
-    addr_indirect: u32,
+    /// Returns the range of the staged bytes within `begin..end` of block `bn`, by the offsets in
+    /// the block. The range is empty if there is none.
+    fn range_in(&self, bn: usize, begin: usize, end: usize) -> Range<usize> {
+        if self.is_empty() || self.start as usize / BSIZE != bn {
+            return begin..begin;
+        }
+        let lo = cmp::max(begin, self.start as usize % BSIZE);
+        let hi = cmp::min(end, self.end as usize - bn * BSIZE);
+        lo..cmp::max(lo, hi)
+    }
 }
 
-#[repr(C)]
-#[derive(Default, AsBytes, FromBytes)]
-pub struct Dirent {
-    pub inum: u16,
-    name: [u8; DIRSIZ],
+/// A run of blocks of an inode that are contiguous on the disk: the `len` blocks from the `fbn`th
+/// one are at `start..start + len`.
+#[derive(Clone, Copy, Default)]
+pub struct Extent {
+    fbn: u32,
+    start: u32,
+    len: u32,
 }
 
-impl Dirent {
-    fn new(
-        ip: &mut InodeGuard<'_, InodeInner>,
-        off: u32,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<Dirent, ()> {
-        let mut dirent = Dirent::default();
-        ip.read_kernel(&mut dirent, off, ctx)?;
-        Ok(dirent)
+impl Extent {
+    /// Returns the run of contiguous blocks at the beginning of `addrs`, which lists the addresses
+    /// of the blocks from the `fbn`th one.
+    fn from_addrs(fbn: usize, addrs: &[u32]) -> Self {
+        let start = addrs.first().copied().unwrap_or(0);
+        let len = if start == 0 {
+            0
+        } else {
+            addrs
+                .iter()
+                .zip(start..)
+                .take_while(|(addr, expected)| **addr == *expected)
+                .count()
+        };
+        Self {
+            fbn: fbn as u32,
+            start,
+            len: len as u32,
+        }
     }
 
-    /// Fill in name. If name is shorter than DIRSIZ, NUL character is appended as
-    /// terminator.
-    ///
-    /// `name` must not contain NUL characters, but this is not a safety invariant.
-    fn set_name(&mut self, name: &FileName<{ DIRSIZ }>) {
-        let name = name.as_bytes();
-        if name.len() == DIRSIZ {
-            self.name.copy_from_slice(name);
+    /// Returns the address of the `bn`th block if the extent contains it.
+    fn lookup(&self, bn: usize) -> Option<u32> {
+        let off = (bn as u32).checked_sub(self.fbn)?;
+        if off < self.len {
+            Some(self.start + off)
         } else {
-            self.name[..name.len()].copy_from_slice(name);
-            self.name[name.len()] = 0;
+            None
         }
     }
 
-    /// Returns slice which exactly contains `name`.
-    ///
-    /// It contains no NUL characters.
-    fn get_name(&self) -> &FileName<{ DIRSIZ }> {
-        let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
-        // SAFETY: self.name[..len] doesn't contain '\0', and len must be <= DIRSIZ.
-        unsafe { FileName::from_bytes(&self.name[..len]) }
+    /// Adds the `bn`th block at `addr` to the extent if it continues the extent. Otherwise, the
+    /// extent restarts from the block.
+    fn push(&mut self, bn: usize, addr: u32) {
+        if self.len != 0 && self.fbn + self.len == bn as u32 && self.start + self.len == addr {
+            self.len += 1;
+        } else {
+            *self = Self {
+                fbn: bn as u32,
+                start: addr,
+                len: 1,
+            };
+        }
     }
 }
 
@@ -199,14 +310,22 @@ impl Iterator for DirentIter<'_, '_, '_> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let off = self.iter.next()?;
-        let dirent = Dirent::new(self.guard, off, self.ctx).expect("DirentIter");
+        let mut dirent = Dirent::default();
+        self.guard
+            .read_kernel(&mut dirent, off, self.ctx)
+            .expect("DirentIter");
         Some((dirent, off))
     }
 }
 
 impl<'t> InodeGuard<'t, InodeInner> {
-    fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
-        let iter = (0..self.deref_inner().size).step_by(DIRENT_SIZE);
+    /// Iterates over the directory entries from the `from`th one.
+    fn iter_dirents<'id, 's>(
+        &'s mut self,
+        from: u32,
+        ctx: &'s KernelCtx<'id, 's>,
+    ) -> DirentIter<'id, 's, 't> {
+        let iter = (from * DIRENT_SIZE as u32..self.deref_inner().size).step_by(DIRENT_SIZE);
         DirentIter {
             guard: self,
             iter,
@@ -231,15 +350,19 @@ impl InodeGuard<'_, InodeInner> {
             return Err(());
         };
 
-        // Look for an empty Dirent.
+        // Look for an empty Dirent, past the entries that the index knows to be in use.
+        let from = self
+            .deref_inner()
+            .dirhash
+            .as_ref()
+            .map_or(0, |hash| hash.free);
         let (mut de, off) = self
-            .iter_dirents(ctx)
+            .iter_dirents(from, ctx)
             .find(|(de, _)| de.inum == 0)
             .unwrap_or((Default::default(), self.deref_inner().size));
         de.inum = inum as _;
-        de.set_name(name);
-        self.write_kernel(&de, off, tx, ctx).expect("dirlink");
-        Ok(())
+        de.set_name(name.as_bytes());
+        self.write_dirent(&de, off, tx, ctx)
     }
 
     /// Look for a directory entry in a directory.
@@ -251,104 +374,395 @@ impl InodeGuard<'_, InodeInner> {
     ) -> Result<(RcInode<InodeInner>, u32), ()> {
         assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
 
-        self.iter_dirents(ctx)
-            .find(|(de, _)| de.inum != 0 && de.get_name() == name)
-            .map(|(de, off)| {
-                (
-                    ctx.kernel()
-                        .fs()
-                        .itable()
-                        .get_inode(self.dev, de.inum as u32),
-                    off,
-                )
-            })
-            .ok_or(())
+        let is_name = |de: &Dirent| de.inum != 0 && de.name() == name.as_bytes();
+        let (de, off) = match self.take_dirhash(ctx) {
+            Some(hash) => {
+                let found = hash
+                    .candidates(name.as_bytes())
+                    .map(|index| {
+                        let off = index * DIRENT_SIZE as u32;
+                        (self.dirent_at(off, ctx), off)
+                    })
+                    .find(|(de, _)| is_name(de));
+                self.deref_inner_mut().dirhash = Some(hash);
+                found
+            }
+            None => self.iter_dirents(0, ctx).find(|(de, _)| is_name(de)),
+        }
+        .ok_or(())?;
+        // ".." of the root of a pseudo file system is the directory it is mounted in.
+        let (dev, inum) = if de.name() == b".." && self.inum == ROOTINO && self.is_pseudo() {
+            ctx.kernel().mounts().parent(self.dev).ok_or(())?
+        } else {
+            (self.dev, de.inum as u32)
+        };
+        let ip = ctx.kernel().fs().itable().get_inode(dev, inum)?;
+        Ok((ip, off))
+    }
+
+    /// Reads up to `max` entries in use of the directory from the one at offset `off` into `des`.
+    /// Returns the offset past the last entry read, or where the next entry in use begins.
+    pub fn read_dirents<const N: usize>(
+        &mut self,
+        off: u32,
+        max: usize,
+        des: &mut ArrayVec<Dirent, N>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> u32 {
+        let from = off / DIRENT_SIZE as u32;
+        let mut end = from * DIRENT_SIZE as u32;
+        for (de, off) in self.iter_dirents(from, ctx) {
+            if de.inum != 0 {
+                if des.len() == cmp::min(max, N) {
+                    break;
+                }
+                des.push(de);
+            }
+            end = off + DIRENT_SIZE as u32;
+        }
+        end
+    }
+
+    /// Write the directory entry `de` at offset `off` of the directory,
+    /// keeping the index of the directory up to date.
+    pub fn write_dirent(
+        &mut self,
+        de: &Dirent,
+        off: u32,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let index = off / DIRENT_SIZE as u32;
+        // The index and the watches of the directory need the entry being overwritten.
+        let inner = self.deref_inner();
+        let old = if off < inner.size
+            && (inner.dirhash.is_some() || ctx.kernel().fs_watches().is_active())
+        {
+            self.dirent_at(off, ctx)
+        } else {
+            Dirent::default()
+        };
+        let mut dirhash = self.deref_inner_mut().dirhash.take();
+        if let Some(hash) = &mut dirhash {
+            if old.inum != 0 {
+                hash.remove(old.name(), index);
+            }
+        }
+        if de.inum == 0 {
+            self.deref_inner_mut().dirhash_full = false;
+        }
+
+        self.write_kernel(de, off, tx, ctx)?;
+
+        // Drops the index if it cannot take the entry.
+        self.deref_inner_mut().dirhash = dirhash.and_then(|mut hash| {
+            if de.inum == 0 {
+                hash.free = core::cmp::min(hash.free, index);
+            } else {
+                hash.insert(de.name(), index).ok()?;
+                if hash.free == index {
+                    hash.free += 1;
+                }
+            }
+            Some(hash)
+        });
+
+        // Report the entries removed and added, but not "." and "..".
+        for &(de, event) in &[(&old, FswMask::FSW_UNLINK), (de, FswMask::FSW_CREATE)] {
+            if de.inum != 0 && de.name() != b"." && de.name() != b".." {
+                let kernel = ctx.kernel();
+                let watches = kernel.fs_watches();
+                watches.notify(self.dev, self.inum, event, de.inum as _, de.name(), kernel);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the directory entry at offset `off`.
+    fn dirent_at(&mut self, off: u32, ctx: &KernelCtx<'_, '_>) -> Dirent {
+        let mut de = Dirent::default();
+        self.read_kernel(&mut de, off, ctx).expect("dirent_at");
+        de
+    }
+
+    /// Takes the index out of the directory, building it first if the directory is big enough
+    /// to deserve one. The caller should put it back.
+    /// Returns None if the directory goes without an index.
+    fn take_dirhash(&mut self, ctx: &KernelCtx<'_, '_>) -> Option<DirHash> {
+        // The entries of a pseudo file system come and go behind the back of an index.
+        if self.is_pseudo() {
+            return None;
+        }
+        let inner = self.deref_inner_mut();
+        if inner.dirhash.is_some() || inner.size < DIRHASH_MIN_SIZE || inner.dirhash_full {
+            return inner.dirhash.take();
+        }
+
+        let mut hash = DirHash::new()?;
+        let mut free = None;
+        let full = self.iter_dirents(0, ctx).any(|(de, off)| {
+            let index = off / DIRENT_SIZE as u32;
+            if de.inum == 0 {
+                let _ = free.get_or_insert(index);
+                false
+            } else {
+                hash.insert(de.name(), index).is_err()
+            }
+        });
+        if full {
+            self.deref_inner_mut().dirhash_full = true;
+            return None;
+        }
+        hash.free = free.unwrap_or(self.deref_inner().size / DIRENT_SIZE as u32);
+        Some(hash)
     }
 }
 
 impl InodeGuard<'_, InodeInner> {
     /// Copy a modified in-memory inode to disk.
     /// Must be called after every change to an ip->xxx field
-    /// that lives on disk.
-    pub fn update(&self, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
-        let mut bp = hal().disk().read(
-            self.dev,
-            ctx.kernel().fs().superblock().iblock(self.inum),
-            ctx,
-        );
-
-        const_assert!(IPB <= mem::size_of::<BufData>() / mem::size_of::<Dinode>());
-        const_assert!(mem::align_of::<BufData>() % mem::align_of::<Dinode>() == 0);
-        // SAFETY:
-        // * dip is aligned properly.
-        // * dip is inside bp.data.
-        // * dip will not be read.
-        let dip = unsafe {
-            &mut *(bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode)
-                .add(self.inum as usize % IPB)
-        };
+    /// that lives on disk. Sets the change time of the inode.
+    pub fn update(&mut self, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        self.deref_inner_mut().ctime = now();
+        if self.is_pseudo() {
+            // A pseudo file system is not on the disk.
+            return;
+        }
+        let mut bp = hal()
+            .disk()
+            .read(self.dev, tx.mounted.superblock().iblock(self.inum), ctx);
+        let mut dinodes = dinodes_mut(&mut bp);
+        let dip = &mut dinodes[self.inum as usize % IPB];
 
         let inner = self.deref_inner();
         match inner.typ {
             InodeType::Device { major, minor } => {
-                dip.typ = DInodeType::Device;
+                dip.typ = DInodeType::Device as u16;
                 dip.major = major;
                 dip.minor = minor;
             }
             InodeType::None => {
-                dip.typ = DInodeType::None;
+                dip.typ = DInodeType::None as u16;
                 dip.major = 0;
                 dip.minor = 0;
             }
             InodeType::Dir => {
-                dip.typ = DInodeType::Dir;
+                dip.typ = DInodeType::Dir as u16;
                 dip.major = 0;
                 dip.minor = 0;
             }
             InodeType::File => {
-                dip.typ = DInodeType::File;
+                dip.typ = DInodeType::File as u16;
                 dip.major = 0;
                 dip.minor = 0;
             }
         }
 
-        (*dip).nlink = inner.nlink;
-        (*dip).size = inner.size;
-        (*dip).addr_direct.copy_from_slice(&inner.addr_direct);
-        (*dip).addr_indirect = inner.addr_indirect;
+        dip.nlink = inner.nlink;
+        dip.size = if inner.staged.is_empty() {
+            inner.size
+        } else {
+            inner.staged.disk_size
+        };
+        dip.addrs = inner.addrs;
+        dip.gen = inner.gen;
+        dip.atime = inner.atime;
+        dip.mtime = inner.mtime;
+        dip.ctime = inner.ctime;
+        dip.mode = inner.mode;
+        dip.uid = inner.uid;
+        dip.gid = inner.gid;
+        if tx.mounted.checksums {
+            dip.update_checksum();
+        }
         tx.write(bp, ctx);
     }
 
+    /// Sets the access time and the modification time of the inode to `atime` and `mtime`,
+    /// unless they are None.
+    pub fn set_times(
+        &mut self,
+        atime: Option<u32>,
+        mtime: Option<u32>,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let inner = self.deref_inner_mut();
+        inner.atime = atime.unwrap_or(inner.atime);
+        inner.mtime = mtime.unwrap_or(inner.mtime);
+        self.update(tx, ctx);
+    }
+
+    /// Sets the permission bits of the inode to `mode`.
+    pub fn set_mode(&mut self, mode: u32, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        self.deref_inner_mut().mode = mode;
+        self.update(tx, ctx);
+    }
+
+    /// Sets the owner and the group of the inode to `uid` and `gid`, unless they are None.
+    pub fn set_owner(
+        &mut self,
+        uid: Option<Uid>,
+        gid: Option<Gid>,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let inner = self.deref_inner_mut();
+        inner.uid = uid.unwrap_or(inner.uid);
+        inner.gid = gid.unwrap_or(inner.gid);
+        self.update(tx, ctx);
+    }
+
     /// Truncate inode (discard contents).
     /// This function is called with Inode's lock is held.
     pub fn itrunc(&mut self, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        self.deref_inner_mut().staged.clear();
+        ctx.kernel()
+            .page_cache()
+            .invalidate(self.dev, self.inum, 0..u32::MAX);
+        self.free_blocks(0, tx, ctx);
+        self.deref_inner_mut().size = 0;
+        self.deref_inner_mut().mtime = now();
+        self.update(tx, ctx);
+    }
+
+    /// Change the size of inode to len, which may shrink or grow it.
+    /// Shrinking frees the blocks past len. Growing allocates no block, and the new
+    /// bytes read as zeros since they are in a hole.
+    /// This function is called with Inode's lock is held.
+    /// Returns Err(()) if len is larger than the maximum file size.
+    pub fn truncate(
+        &mut self,
+        len: u32,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if len as usize > MAXFILE * BSIZE {
+            return Err(());
+        }
+        self.flush(tx, ctx)?;
+        if len < self.deref_inner().size {
+            ctx.kernel().page_cache().invalidate(
+                self.dev,
+                self.inum,
+                len / PGSIZE as u32..u32::MAX,
+            );
+            self.free_blocks((len as usize + BSIZE - 1) / BSIZE, tx, ctx);
+
+            // Zero the rest of the last block, so that growing the inode later does not bring
+            // the old content back.
+            let begin = len as usize % BSIZE;
+            if begin != 0 {
+                let addr = self.bmap_lookup(len as usize / BSIZE, ctx);
+                if addr != 0 {
+                    let mut bp = hal().disk().read(self.dev, addr, ctx);
+                    bp.deref_inner_mut().data[begin..].fill(0);
+                    tx.write(bp, ctx);
+                }
+            }
+        }
+        self.deref_inner_mut().size = len;
+        self.deref_inner_mut().mtime = now();
+        self.update(tx, ctx);
+        Ok(())
+    }
+
+    /// Free the blocks of inode from the from-th one to the end.
+    /// Frees the indirect blocks too if none of their blocks remains, and the extent block if
+    /// the remaining extents fit in the inode.
+    fn free_blocks(&mut self, from: usize, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
         let dev = self.dev;
-        for addr in &mut self.deref_inner_mut().addr_direct {
+        self.deref_inner_mut().extent = Extent::default();
+        if tx.mounted.extents {
+            let inner = self.deref_inner_mut();
+            let block = inner.addrs[EXTENT_BLOCK];
+            let mut bp = if block != 0 {
+                Some(hal().disk().read(dev, block, ctx))
+            } else {
+                None
+            };
+            let mut map = ExtentMap::new(
+                &mut inner.addrs[..],
+                bp.as_mut().map(|bp| &mut bp.deref_inner_mut().data[..]),
+            );
+            map.truncate(from as u32, |blocks| {
+                for b in blocks {
+                    tx.bfree(dev, b, ctx);
+                }
+            });
+            let len = map.len();
+            if let Some(bp) = bp {
+                if len <= NEXTENT_INODE {
+                    bp.free(ctx);
+                    tx.bfree(dev, block, ctx);
+                    inner.addrs[EXTENT_BLOCK] = 0;
+                } else {
+                    tx.write(bp, ctx);
+                }
+            }
+            return;
+        }
+
+        for addr in self.deref_inner_mut().addrs[..NDIRECT]
+            .iter_mut()
+            .skip(from)
+        {
             if *addr != 0 {
                 tx.bfree(dev, *addr, ctx);
                 *addr = 0;
             }
         }
 
-        if self.deref_inner().addr_indirect != 0 {
-            let mut bp = hal()
-                .disk()
-                .read(dev, self.deref_inner().addr_indirect, ctx);
-            // SAFETY: u32 does not have internal structure.
-            let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
-            debug_assert_eq!(prefix.len(), 0, "itrunc: Buf data unaligned");
-            for a in data {
-                if *a != 0 {
-                    tx.bfree(dev, *a, ctx);
-                }
+        let indirect = self.deref_inner().addrs[NDIRECT];
+        if indirect != 0 && self.free_indirect(indirect, from.saturating_sub(NDIRECT), 1, tx, ctx) {
+            self.deref_inner_mut().addrs[NDIRECT] = 0;
+        }
+
+        let dindirect = self.deref_inner().addrs[NDIRECT + 1];
+        let from = from.saturating_sub(NDIRECT + NINDIRECT);
+        if dindirect != 0 && self.free_indirect(dindirect, from, 2, tx, ctx) {
+            self.deref_inner_mut().addrs[NDIRECT + 1] = 0;
+        }
+    }
+
+    /// Free the blocks that the indirect block addr maps, from the from-th one to the end.
+    /// If depth is 2, addr is a double-indirect block, whose entries are indirect blocks.
+    /// Frees addr itself and returns true if from is 0.
+    fn free_indirect(
+        &self,
+        addr: u32,
+        from: usize,
+        depth: u32,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> bool {
+        let span = if depth == 1 { 1 } else { NINDIRECT };
+        let mut bp = hal().disk().read(self.dev, addr, ctx);
+        // SAFETY: u32 does not have internal structure.
+        let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
+        debug_assert_eq!(prefix.len(), 0, "free_indirect: Buf data unaligned");
+        for (i, a) in data.iter_mut().enumerate().skip(from / span) {
+            if *a == 0 {
+                continue;
+            }
+            let freed = if depth == 1 {
+                tx.bfree(self.dev, *a, ctx);
+                true
+            } else {
+                self.free_indirect(*a, from.saturating_sub(i * span), depth - 1, tx, ctx)
+            };
+            if freed {
+                *a = 0;
             }
+        }
+        if from == 0 {
             bp.free(ctx);
-            tx.bfree(dev, self.deref_inner().addr_indirect, ctx);
-            self.deref_inner_mut().addr_indirect = 0
+            tx.bfree(self.dev, addr, ctx);
+            true
+        } else {
+            tx.write(bp, ctx);
+            false
         }
-
-        self.deref_inner_mut().size = 0;
-        self.update(tx, ctx);
     }
 
     /// Copy data into `dst` from the content of inode at offset `off`.
@@ -388,7 +802,8 @@ impl InodeGuard<'_, InodeInner> {
     }
 
     /// Copy data into virtual address `dst` of the current process by `n` bytes
-    /// from the content of inode at offset `off`.
+    /// from the content of inode at offset `off`. The whole pages are mapped
+    /// instead of copied if `dst` and `off` are page-aligned (see `lend_pages`).
     /// Returns Ok(number of bytes copied) on success, Err(()) on failure due to
     /// accessing an invalid virtual address.
     pub fn read_user(
@@ -398,16 +813,53 @@ impl InodeGuard<'_, InodeInner> {
         n: u32,
         ctx: &mut KernelCtx<'_, '_>,
     ) -> Result<usize, ()> {
-        self.read_internal(
-            off,
-            n,
+        let lent = self.lend_pages(dst, off, n, ctx);
+        let res = self.read_internal(
+            off + lent,
+            n - lent,
             |off, src, ctx| {
-                ctx.proc_mut()
-                    .memory_mut()
-                    .copy_out_bytes(dst + off as usize, src)
+                ctx.proc()
+                    .memory()
+                    .copy_out_bytes(dst + (lent + off) as usize, src)
             },
-            ctx,
-        )
+            &mut *ctx,
+        );
+        let res = match res {
+            Err(()) if lent > 0 => Ok(lent as usize),
+            res => res.map(|bytes| lent as usize + bytes),
+        };
+        if let Ok(bytes) = res {
+            ctx.proc().io().charge_read(bytes);
+        }
+        res
+    }
+
+    /// Maps the whole pages of the content of inode from offset `off` into
+    /// virtual address `dst` of the current process by up to `n` bytes, if both
+    /// are page-aligned. The pages are lent by the page cache instead of copied,
+    /// and the process copies a page on the first write to it.
+    /// Returns the number of bytes mapped, which falls short of `n` from the
+    /// first page that cannot be lent, e.g., the last page of the file if it is
+    /// not whole. The rest is left to be copied.
+    fn lend_pages(&mut self, dst: UVAddr, off: u32, n: u32, ctx: &mut KernelCtx<'_, '_>) -> u32 {
+        if dst.into_usize() % PGSIZE != 0 || off as usize % PGSIZE != 0 {
+            return 0;
+        }
+        let size = self.deref_inner().size;
+        let cache = ctx.kernel().page_cache();
+        let mut tot: u32 = 0;
+        while n - tot >= PGSIZE as u32 && size.saturating_sub(off + tot) >= PGSIZE as u32 {
+            let page = ok_or!(cache.lend(self, (off + tot) / PGSIZE as u32, ctx), break);
+            let va = dst.into_usize() + tot as usize;
+            if ctx.map_lent(va, page).is_err() {
+                break;
+            }
+            tot += PGSIZE as u32;
+        }
+        if tot > 0 {
+            self.deref_inner_mut().atime = now();
+        }
+        tot
     }
 
     /// Read data from inode.
@@ -434,23 +886,83 @@ impl InodeGuard<'_, InodeInner> {
         mut f: F,
         mut k: K,
     ) -> Result<usize, ()> {
+        // The content of a file of a pseudo file system is not on the disk, and its size does not
+        // bound it.
+        let mut content = PseudoFile::new();
+        let fs = pseudo_fs(self.dev);
         let inner = self.deref_inner();
-        if off > inner.size || off.wrapping_add(n) < off {
+        let size = match fs {
+            Some(fs) if inner.typ != InodeType::Dir => {
+                (fs.read)(self.inum, inner.gen, &mut content, &k);
+                content.len() as u32
+            }
+            _ => inner.size,
+        };
+        if off > size || off.wrapping_add(n) < off {
             return Ok(0);
         }
-        if off + n > inner.size {
-            n = inner.size - off;
+        if off + n > size {
+            n = size - off;
+        }
+        if n > 0 {
+            self.deref_inner_mut().atime = now();
         }
         let mut tot: u32 = 0;
+        if let Some(fs) = fs {
+            if self.deref_inner().typ != InodeType::Dir {
+                f(
+                    0,
+                    &content.as_bytes()[off as usize..(off + n) as usize],
+                    &mut k,
+                )?;
+                return Ok(n as usize);
+            }
+            // Nor are the entries of its directories.
+            let gen = self.deref_inner().gen;
+            while tot < n {
+                let i = off / DIRENT_SIZE as u32;
+                let begin = (off % DIRENT_SIZE as u32) as usize;
+                let m = cmp::min(n - tot, (DIRENT_SIZE - begin) as u32);
+                let de = k
+                    .kernel()
+                    .mounts()
+                    .dirent(fs, self.inum, gen, i, &k)
+                    .unwrap_or_default();
+                f(tot, &de.as_bytes()[begin..begin + m as usize], &mut k)?;
+                tot += m;
+                off += m;
+            }
+            return Ok(tot as usize);
+        }
         while tot < n {
-            let bp = hal()
-                .disk()
-                .read(self.dev, self.bmap(off as usize / BSIZE, &k), &k);
+            let bn = off as usize / BSIZE;
+            let addr = self.bmap_lookup(bn, &k);
             let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
             let begin = (off % BSIZE as u32) as usize;
             let end = begin + m as usize;
-            let res = f(tot, &bp.deref_inner().data[begin..end], &mut k);
-            bp.free(&k);
+            // A hole reads as zeros, without reading the disk.
+            let bp = if addr == 0 {
+                None
+            } else {
+                Some(hal().disk().read(self.dev, addr, &k))
+            };
+            let block = bp
+                .as_ref()
+                .map_or(&ZEROS[..], |bp| &bp.deref_inner().data[..]);
+            // The staged bytes are newer than those on the disk.
+            let staged = &self.deref_inner().staged;
+            let Range { start: lo, end: hi } = staged.range_in(bn, begin, end);
+            let res = if lo == hi {
+                f(tot, &block[begin..end], &mut k)
+            } else {
+                let at = |i: usize| tot + (i - begin) as u32;
+                f(tot, &block[begin..lo], &mut k)
+                    .and_then(|_| f(at(lo), &staged.data[lo..hi], &mut k))
+                    .and_then(|_| f(at(hi), &block[hi..end], &mut k))
+            };
+            if let Some(bp) = bp {
+                bp.free(&k);
+            }
             res?;
             tot += m;
             off += m;
@@ -507,17 +1019,268 @@ impl InodeGuard<'_, InodeInner> {
         ctx: &mut KernelCtx<'_, '_>,
         tx: &UfsTx<'_>,
     ) -> Result<usize, ()> {
-        self.write_internal(
+        let res = self.write_internal(
             off,
             n,
-            |off, dst, ctx| {
-                ctx.proc_mut()
-                    .memory_mut()
-                    .copy_in_bytes(dst, src + off as usize)
-            },
+            |off, dst, ctx| ctx.proc().memory().copy_in_bytes(dst, src + off as usize),
             tx,
-            ctx,
-        )
+            &mut *ctx,
+        );
+        if let Ok(bytes) = res {
+            ctx.proc().io().charge_write(bytes);
+            self.notify_write(ctx);
+        }
+        res
+    }
+
+    /// Coalesces the write of `n` bytes from virtual address `src` of the current process at
+    /// offset `off` of the file with the staged bytes in memory, instead of writing them to the
+    /// disk. Only a write that lies in the block of the staged bytes, if any, next to or over
+    /// them, and does not reach the end of the block can be staged.
+    /// Returns Ok(()) if staged, Err(()) if the write must be written to the disk instead, or
+    /// reading `src` fails.
+    pub fn stage_user(
+        &mut self,
+        src: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let (dev, inum) = (self.dev, self.inum);
+        let inner = self.deref_inner_mut();
+        let begin = off as usize % BSIZE;
+        if inner.typ != InodeType::File
+            || n == 0
+            || off > inner.size
+            || off as usize / BSIZE >= MAXFILE
+            || begin + n as usize >= BSIZE
+        {
+            return Err(());
+        }
+        let staged = &mut inner.staged;
+        if staged.is_empty() {
+            staged.start = off;
+            staged.end = off;
+            staged.disk_size = inner.size;
+        } else if off < staged.start
+            || off > staged.end
+            || off as usize / BSIZE != staged.start as usize / BSIZE
+        {
+            return Err(());
+        }
+        let dst = &mut staged.data[begin..begin + n as usize];
+        if ctx.proc().memory().copy_in_bytes(dst, src).is_err() {
+            if staged.start == staged.end {
+                staged.clear();
+            }
+            return Err(());
+        }
+        staged.end = cmp::max(staged.end, off + n);
+        inner.size = cmp::max(inner.size, staged.end);
+        inner.mtime = now();
+        let cache = ctx.kernel().page_cache();
+        cache.write(dev, inum, off, &staged.data[begin..begin + n as usize]);
+        let page = off / PGSIZE as u32;
+        cache.invalidate(dev, inum, page..page + 1);
+        ctx.proc().io().charge_write(n as usize);
+        self.notify_write(ctx);
+        Ok(())
+    }
+
+    /// Reports a write of user data to the watches of the file.
+    fn notify_write(&self, ctx: &KernelCtx<'_, '_>) {
+        let kernel = ctx.kernel();
+        let watches = kernel.fs_watches();
+        watches.notify(
+            self.dev,
+            self.inum,
+            FswMask::FSW_WRITE,
+            self.inum,
+            &[],
+            kernel,
+        );
+    }
+
+    /// Reads up to `n` bytes of the file at offset `off` into virtual address `dst` of the current
+    /// process, with the disk writing into the pages of the process directly instead of through
+    /// the buffer cache (see `O_DIRECT`). `dst` and `off` must be multiples of `BSIZE`. Holes,
+    /// the block at the end of file, and blocks whose newer content the log holds are read
+    /// through the buffer cache instead, as is everything if bytes are staged or the file is of
+    /// a pseudo file system.
+    /// Returns Ok(number of bytes read) on success, Err(()) on error.
+    pub fn read_direct(
+        &mut self,
+        dst: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let inner = self.deref_inner();
+        if !inner.staged.is_empty() || self.is_pseudo() {
+            return self.read_user(dst, off, n, ctx);
+        }
+        if off >= inner.size {
+            return Ok(0);
+        }
+        let n = cmp::min(n, inner.size - off);
+        // Only those holding the inode lock log the blocks of the file.
+        let pending = ctx.kernel().fs().pending_blocks();
+        let mut tot: u32 = 0;
+        let mut res = Ok(());
+        while tot < n && res.is_ok() {
+            let m = cmp::min(n - tot, BSIZE as u32);
+            let addr = self.bmap_lookup((off + tot) as usize / BSIZE, ctx);
+            let dst = dst + tot as usize;
+            res = if addr == 0 {
+                ctx.proc()
+                    .memory()
+                    .copy_out_bytes(dst, &ZEROS[..m as usize])
+            } else if m < BSIZE as u32 || pending.contains(&addr) {
+                let bp = hal().disk().read(self.dev, addr, ctx);
+                let res = ctx
+                    .proc()
+                    .memory()
+                    .copy_out_bytes(dst, &bp.deref_inner().data[..m as usize]);
+                bp.free(ctx);
+                res
+            } else {
+                ctx.pin_user_page(dst, true).and_then(|pa| {
+                    let res = hal().disk().rw_direct(addr, pa, false, ctx);
+                    ctx.unpin_user_page();
+                    res
+                })
+            };
+            if res.is_ok() {
+                tot += m;
+            }
+        }
+        if tot == 0 {
+            res?;
+        }
+        self.deref_inner_mut().atime = now();
+        ctx.proc().io().charge_read(tot as usize);
+        Ok(tot as usize)
+    }
+
+    /// Allocates the blocks of the `n` bytes at offset `off` of the file, which are zeroed, and
+    /// extends the file over them, so that `write_direct` can write them after the transaction
+    /// commits. There must be no staged bytes.
+    /// Returns Ok(()) on success, Err(()) if `off` is past the end of file, or the disk is full.
+    pub fn prepare_direct(
+        &mut self,
+        off: u32,
+        n: u32,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        assert!(
+            self.deref_inner().staged.is_empty(),
+            "prepare_direct: staged"
+        );
+        let end = off.checked_add(n).ok_or(())?;
+        if off > self.deref_inner().size || end as usize > MAXFILE * BSIZE {
+            return Err(());
+        }
+        for bn in off as usize / BSIZE..(end as usize + BSIZE - 1) / BSIZE {
+            let _ = self.bmap_or_alloc(bn, tx, ctx)?;
+        }
+        let inner = self.deref_inner_mut();
+        inner.size = cmp::max(inner.size, end);
+        inner.mtime = now();
+        self.update(tx, ctx);
+        Ok(())
+    }
+
+    /// Writes up to `n` bytes from virtual address `src` of the current process into the file at
+    /// offset `off`, with the disk reading the pages of the process directly instead of through
+    /// the buffer cache (see `O_DIRECT`). `src`, `off` and `n` must be multiples of `BSIZE`, and
+    /// `prepare_direct` must have allocated the blocks in a committed transaction. It stops at a
+    /// block that is missing, or that the log holds, since committing the log would overwrite it.
+    /// Returns Ok(number of bytes written) on success, Err(()) on error.
+    pub fn write_direct(
+        &mut self,
+        src: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let pending = ctx.kernel().fs().pending_blocks();
+        let (dev, inum) = (self.dev, self.inum);
+        let mut tot: u32 = 0;
+        let mut res = Ok(());
+        while tot < n && off + tot < self.deref_inner().size {
+            let addr = self.bmap_lookup((off + tot) as usize / BSIZE, ctx);
+            if addr == 0 || pending.contains(&addr) {
+                break;
+            }
+            res = ctx.pin_user_page(src + tot as usize, false).and_then(|pa| {
+                // Map the page before writing it to the disk, so that the page cache can be
+                // updated once the write succeeds.
+                let memory = ctx.kernel().memory();
+                let res = memory.kmap_page(pa, hal().kmem()).and_then(|va| {
+                    let res = hal().disk().rw_direct(addr, pa, true, ctx);
+                    if res.is_ok() {
+                        // SAFETY: va maps the pinned page until `kunmap_page`, and the page holds
+                        // the whole block as `src` is aligned.
+                        let data = unsafe { slice::from_raw_parts(va as *const u8, BSIZE) };
+                        ctx.kernel().page_cache().write(dev, inum, off + tot, data);
+                    }
+                    memory.kunmap_page(va);
+                    res
+                });
+                ctx.unpin_user_page();
+                res
+            });
+            if res.is_err() {
+                break;
+            }
+            // The cached copy of the block, if any, is stale now.
+            let mut bp = ctx.kernel().bcache().get_buf(self.dev, addr).lock(ctx);
+            bp.deref_inner_mut().valid = false;
+            bp.free(ctx);
+            tot += BSIZE as u32;
+        }
+        if tot == 0 {
+            res?;
+        }
+        if tot > 0 {
+            let pages = off / PGSIZE as u32..(off + tot - 1) / PGSIZE as u32 + 1;
+            ctx.kernel()
+                .page_cache()
+                .invalidate(self.dev, self.inum, pages);
+            ctx.proc().io().charge_write(tot as usize);
+            self.notify_write(ctx);
+        }
+        Ok(tot as usize)
+    }
+
+    /// Writes the staged bytes of the file, if any, to the disk.
+    /// Returns Err(()) if the disk is full, in which case the bytes are dropped.
+    pub fn flush(&mut self, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let staged = &self.deref_inner().staged;
+        if staged.is_empty() {
+            return Ok(());
+        }
+        let bn = staged.start as usize / BSIZE;
+        let range = staged.range_in(bn, 0, BSIZE);
+        let res = self.bmap_or_alloc(bn, tx, ctx).map(|addr| {
+            let mut bp = hal().disk().read(self.dev, addr, ctx);
+            let staged = &self.deref_inner().staged;
+            let data = &staged.data[range.clone()];
+            bp.deref_inner_mut().data[range.clone()].copy_from_slice(data);
+            tx.write(bp, ctx);
+            let off = (bn * BSIZE + range.start) as u32;
+            ctx.kernel()
+                .page_cache()
+                .write(self.dev, self.inum, off, data);
+        });
+        let inner = self.deref_inner_mut();
+        if res.is_err() {
+            inner.size = inner.staged.disk_size;
+        }
+        inner.staged.clear();
+        self.update(tx, ctx);
+        res
     }
 
     /// Write data to inode. Returns the number of bytes successfully written.
@@ -553,17 +1316,24 @@ impl InodeGuard<'_, InodeInner> {
         if off.checked_add(n).ok_or(())? as usize > MAXFILE * BSIZE {
             return Err(());
         }
+        // The staged bytes are older than those written now.
+        self.flush(tx, &k)?;
+        let start = off;
         let mut tot: u32 = 0;
         while tot < n {
-            let mut bp = hal().disk().read(
-                self.dev,
-                self.bmap_or_alloc(off as usize / BSIZE, tx, &k),
-                &k,
-            );
+            // Stop writing if the disk is full.
+            let addr = ok_or!(self.bmap_or_alloc(off as usize / BSIZE, tx, &k), break);
+            let mut bp = hal().disk().read(self.dev, addr, &k);
             let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
             let begin = (off % BSIZE as u32) as usize;
             let end = begin + m as usize;
             if f(tot, &mut bp.deref_inner_mut().data[begin..end], &mut k).is_ok() {
+                k.kernel().page_cache().write(
+                    self.dev,
+                    self.inum,
+                    off,
+                    &bp.deref_inner().data[begin..end],
+                );
                 tx.write(bp, &k);
             } else {
                 bp.free(&k);
@@ -576,9 +1346,16 @@ impl InodeGuard<'_, InodeInner> {
         if off > self.deref_inner().size {
             self.deref_inner_mut().size = off;
         }
+        if tot > 0 {
+            self.deref_inner_mut().mtime = now();
+            let pages = start / PGSIZE as u32..(off - 1) / PGSIZE as u32 + 1;
+            k.kernel()
+                .page_cache()
+                .invalidate(self.dev, self.inum, pages);
+        }
 
         // Write the i-node back to disk even if the size didn't change
-        // because the loop above might have called bmap() and added a new
+        // because the loop above might have called bmap_or_alloc() and added a new
         // block to self->addrs[].
         self.update(tx, &k);
         Ok(tot as usize)
@@ -589,56 +1366,312 @@ impl InodeGuard<'_, InodeInner> {
     /// The content (data) associated with each inode is stored
     /// in blocks on the disk. The first NDIRECT block numbers
     /// are listed in self->addrs[].  The next NINDIRECT blocks are
-    /// listed in block self->addr_indirect.
+    /// listed in block self->addrs[NDIRECT].  The next NDINDIRECT
+    /// blocks are listed in the NINDIRECT indirect blocks that
+    /// block self->addrs[NDIRECT + 1] lists. If the file system
+    /// has FS_EXTENTS set, self->addrs[] lists extents instead.
     /// Return the disk block address of the nth block in inode self.
-    /// If there is no such block, bmap allocates one.
-    fn bmap_or_alloc(&mut self, bn: usize, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) -> u32 {
-        self.bmap_internal(bn, Some(tx), ctx)
-    }
+    /// If there is no such block, bmap allocates one, right after
+    /// the previous block of the file if it is free, so that files
+    /// are laid out in contiguous extents.
+    /// Returns Err(()) if the disk is full, or the file has too
+    /// many extents to map another block.
+    fn bmap_or_alloc(
+        &mut self,
+        bn: usize,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<u32, ()> {
+        if let Some(addr) = self.deref_inner().extent.lookup(bn) {
+            return Ok(addr);
+        }
+        // Appending to the file, which is the common case, allocates a block for sure. With
+        // extents, a failed write may have left blocks past the end, which must not be mapped
+        // twice.
+        let extents = tx.mounted.extents;
+        if extents || bn < (self.deref_inner().size as usize + BSIZE - 1) / BSIZE {
+            let addr = self.bmap_lookup(bn, ctx);
+            if addr != 0 {
+                return Ok(addr);
+            }
+        }
+        let goal = match bn.checked_sub(1).map(|prev| self.bmap_lookup(prev, ctx)) {
+            Some(prev) if prev != 0 => prev + 1,
+            _ => 0,
+        };
 
-    fn bmap(&mut self, bn: usize, ctx: &KernelCtx<'_, '_>) -> u32 {
-        self.bmap_internal(bn, None, ctx)
+        let inner = self.deref_inner();
+        let addr = if extents {
+            self.extent_alloc(bn, goal, tx, ctx)?
+        } else if bn < NDIRECT {
+            let mut addr = inner.addrs[bn];
+            if addr == 0 {
+                addr = tx.balloc(self.dev, goal, ctx)?;
+                self.deref_inner_mut().addrs[bn] = addr;
+            }
+            addr
+        } else if bn < NDIRECT + NINDIRECT {
+            let mut indirect = inner.addrs[NDIRECT];
+            if indirect == 0 {
+                indirect = tx.balloc(self.dev, 0, ctx)?;
+                self.deref_inner_mut().addrs[NDIRECT] = indirect;
+            }
+            self.indirect_or_alloc(indirect, bn - NDIRECT, goal, tx, ctx)?
+        } else {
+            let index = bn - NDIRECT - NINDIRECT;
+            assert!(index < NDINDIRECT, "bmap: out of range");
+
+            let mut dindirect = inner.addrs[NDIRECT + 1];
+            if dindirect == 0 {
+                dindirect = tx.balloc(self.dev, 0, ctx)?;
+                self.deref_inner_mut().addrs[NDIRECT + 1] = dindirect;
+            }
+            let indirect = self.indirect_or_alloc(dindirect, index / NINDIRECT, 0, tx, ctx)?;
+            self.indirect_or_alloc(indirect, index % NINDIRECT, goal, tx, ctx)?
+        };
+        self.deref_inner_mut().extent.push(bn, addr);
+        Ok(addr)
     }
 
-    fn bmap_internal(
+    /// Maps the `bn`th block of the inode, which lists extents and has the block in a hole, to a
+    /// new block, at `goal` if it is free. Allocates the extent block first if the extents in
+    /// the inode may run out.
+    /// Returns Err(()) if the disk is full, or the file has too many extents.
+    fn extent_alloc(
         &mut self,
         bn: usize,
-        tx_opt: Option<&UfsTx<'_>>,
+        goal: u32,
+        tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> u32 {
+    ) -> Result<u32, ()> {
+        let dev = self.dev;
+        let inner = self.deref_inner_mut();
+        if inner.addrs[EXTENT_BLOCK] == 0 && !ExtentMap::new(&inner.addrs[..], None::<&[u8]>).fits()
+        {
+            inner.addrs[EXTENT_BLOCK] = tx.balloc(dev, 0, ctx)?;
+        }
+
+        let block = inner.addrs[EXTENT_BLOCK];
+        let mut bp = if block != 0 {
+            Some(hal().disk().read(dev, block, ctx))
+        } else {
+            None
+        };
+        let mut map = ExtentMap::new(
+            &mut inner.addrs[..],
+            bp.as_mut().map(|bp| &mut bp.deref_inner_mut().data[..]),
+        );
+        let res = if map.fits() {
+            tx.balloc(dev, goal, ctx)
+        } else {
+            Err(())
+        };
+        if let Ok(addr) = res {
+            map.map(bn as u32, addr).expect("extent_alloc");
+        }
+        match bp {
+            Some(bp) if res.is_ok() => tx.write(bp, ctx),
+            Some(bp) => bp.free(ctx),
+            None => (),
+        }
+        res
+    }
+
+    /// Return the index-th block address listed in the indirect block `indirect`.
+    /// If there is no such block, allocates one, at `goal` if it is free.
+    /// Returns Err(()) if the disk is full.
+    fn indirect_or_alloc(
+        &self,
+        indirect: u32,
+        index: usize,
+        goal: u32,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<u32, ()> {
+        let mut bp = hal().disk().read(self.dev, indirect, ctx);
+        // SAFETY: u32 does not have internal structure.
+        let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
+        debug_assert_eq!(prefix.len(), 0, "bmap: Buf data unaligned");
+        let mut addr = data[index];
+        if addr == 0 {
+            addr = match tx.balloc(self.dev, goal, ctx) {
+                Ok(addr) => addr,
+                Err(()) => {
+                    bp.free(ctx);
+                    return Err(());
+                }
+            };
+            data[index] = addr;
+            tx.write(bp, ctx);
+        } else {
+            bp.free(ctx);
+        }
+        Ok(addr)
+    }
+
+    /// Return the disk block address of the nth block in inode self, or 0 if
+    /// the block has not been allocated. Unlike bmap_or_alloc(), it never allocates.
+    /// Remembers the extent of the block, so that looking up the following
+    /// blocks reads no indirect block.
+    fn bmap_lookup(&mut self, bn: usize, ctx: &KernelCtx<'_, '_>) -> u32 {
         let inner = self.deref_inner();
+        if let Some(addr) = inner.extent.lookup(bn) {
+            return addr;
+        }
 
-        if bn < NDIRECT {
-            let mut addr = inner.addr_direct[bn];
-            if addr == 0 {
-                addr = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
-                self.deref_inner_mut().addr_direct[bn] = addr;
+        let extent = if ctx.kernel().fs().as_pin().get_ref().mounted().extents {
+            self.find_extent(bn, ctx)
+        } else if bn < NDIRECT {
+            Extent::from_addrs(bn, &inner.addrs[bn..NDIRECT])
+        } else if bn < NDIRECT + NINDIRECT {
+            self.indirect_extent(inner.addrs[NDIRECT], bn - NDIRECT, bn, ctx)
+        } else if bn < MAXFILE {
+            let index = bn - NDIRECT - NINDIRECT;
+            let indirect = self
+                .indirect_extent(inner.addrs[NDIRECT + 1], index / NINDIRECT, 0, ctx)
+                .start;
+            self.indirect_extent(indirect, index % NINDIRECT, bn, ctx)
+        } else {
+            return 0;
+        };
+        if extent.len != 0 {
+            self.deref_inner_mut().extent = extent;
+        }
+        extent.start
+    }
+
+    /// Return the extent of the `bn`th block of the inode, which lists extents, from the block
+    /// to the end of its extent on the disk. The extent is empty if the block is in a hole.
+    fn find_extent(&self, bn: usize, ctx: &KernelCtx<'_, '_>) -> Extent {
+        let addrs = &self.deref_inner().addrs;
+        let block = addrs[EXTENT_BLOCK];
+        let bp = if block != 0 {
+            Some(hal().disk().read(self.dev, block, ctx))
+        } else {
+            None
+        };
+        let found = ExtentMap::new(&addrs[..], bp.as_ref().map(|bp| &bp.deref_inner().data[..]))
+            .find(bn as u32);
+        if let Some(bp) = bp {
+            bp.free(ctx);
+        }
+        match found {
+            Some((fbn, e)) if !e.is_hole() => {
+                let off = bn as u32 - fbn;
+                Extent {
+                    fbn: bn as u32,
+                    start: e.start + off,
+                    len: e.len - off,
+                }
             }
-            addr
+            _ => Extent::default(),
+        }
+    }
+
+    /// Return the extent of the `fbn`th block of the inode, whose address is the index-th
+    /// one listed in the indirect block `indirect`. The extent is empty if either of them
+    /// has not been allocated.
+    fn indirect_extent(
+        &self,
+        indirect: u32,
+        index: usize,
+        fbn: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Extent {
+        if indirect == 0 {
+            return Extent::default();
+        }
+        let mut bp = hal().disk().read(self.dev, indirect, ctx);
+        // SAFETY: u32 does not have internal structure.
+        let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
+        debug_assert_eq!(prefix.len(), 0, "bmap_lookup: Buf data unaligned");
+        let extent = Extent::from_addrs(fbn, &data[index..]);
+        bp.free(ctx);
+        extent
+    }
+
+    /// Returns the blocks among `pending` that hold the content of inode self
+    /// in the byte range [off, off + len). `len == 0` means up to the end of file.
+    pub fn blocks_in_range(
+        &mut self,
+        off: u32,
+        len: u32,
+        pending: &[u32],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> ArrayVec<u32, LOGSIZE> {
+        let mut blocks = ArrayVec::new();
+        let size = self.deref_inner().size;
+        let end = if len == 0 {
+            size
         } else {
-            let bn = bn - NDIRECT;
-            assert!(bn < NINDIRECT, "bmap: out of range");
+            core::cmp::min(off.saturating_add(len), size)
+        };
+        if off >= end {
+            return blocks;
+        }
 
-            let mut indirect = inner.addr_indirect;
-            if indirect == 0 {
-                indirect = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
-                self.deref_inner_mut().addr_indirect = indirect;
+        for bn in (off as usize / BSIZE)..=((end - 1) as usize / BSIZE) {
+            let addr = self.bmap_lookup(bn, ctx);
+            if addr != 0 && pending.contains(&addr) && !blocks.contains(&addr) {
+                blocks.push(addr);
             }
+        }
+        blocks
+    }
 
-            let mut bp = hal().disk().read(self.dev, indirect, ctx);
-            let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
-            debug_assert_eq!(prefix.len(), 0, "bmap: Buf data unaligned");
-            let mut addr = data[bn];
-            if addr == 0 {
-                let tx = tx_opt.expect("bmap: out of range");
-                addr = tx.balloc(self.dev, ctx);
-                data[bn] = addr;
-                tx.write(bp, ctx);
-            } else {
+    /// Returns the addresses of the blocks of inode self from the first one at or after offset
+    /// `from` up to the one holding offset `to - 1`, up to `N` of them. Holes and the blocks past
+    /// the end of file are skipped.
+    pub fn blocks_ahead<const N: usize>(
+        &mut self,
+        from: u32,
+        to: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> ArrayVec<u32, N> {
+        let mut blocks = ArrayVec::new();
+        let to = cmp::min(to, self.deref_inner().size) as usize;
+        for bn in (from as usize + BSIZE - 1) / BSIZE..(to + BSIZE - 1) / BSIZE {
+            if blocks.is_full() {
+                break;
+            }
+            let addr = self.bmap_lookup(bn, ctx);
+            if addr != 0 {
+                blocks.push(addr);
+            }
+        }
+        blocks
+    }
+
+    /// Drops the blocks of inode self in the byte range [off, off + len) from the buffer cache,
+    /// and its pages there from the page cache, unless they are in use. `len == 0` means up to
+    /// the end of file. The blocks that the log holds are kept, as the disk does not have their
+    /// content yet.
+    pub fn drop_cached(&mut self, off: u32, len: u32, ctx: &KernelCtx<'_, '_>) {
+        let size = self.deref_inner().size;
+        let end = if len == 0 {
+            size
+        } else {
+            cmp::min(off.saturating_add(len), size)
+        };
+        if off >= end {
+            return;
+        }
+
+        let pending = ctx.kernel().fs().pending_blocks();
+        for bn in (off as usize / BSIZE)..=((end - 1) as usize / BSIZE) {
+            let addr = self.bmap_lookup(bn, ctx);
+            if addr == 0 || pending.contains(&addr) {
+                continue;
+            }
+            if let Some(mut bp) = ctx.kernel().bcache().lookup(self.dev, addr, ctx) {
+                bp.deref_inner_mut().valid = false;
                 bp.free(ctx);
             }
-            addr
         }
+        let pages = off / PGSIZE as u32..(end - 1) / PGSIZE as u32 + 1;
+        ctx.kernel()
+            .page_cache()
+            .drop_idle(self.dev, self.inum, pages);
     }
 
     /// Is the directory dp empty except for "." and ".." ?
@@ -673,12 +1706,14 @@ impl ArenaObject for Inode<InodeInner> {
     /// case it has to free the inode.
     fn finalize<'a, 'id: 'a>(&mut self, ctx: Self::Ctx<'a, 'id>) {
         let (tx, ctx) = ctx;
+        // Give the index back to the pool, for the directories in use.
+        self.inner.get_mut().dirhash = None;
         if self.inner.get_mut().valid && self.inner.get_mut().nlink == 0 {
             // inode has no links and no other references: truncate and free.
 
             // self->ref == 1 means no other process can have self locked,
             // so this acquiresleep() won't block (or deadlock).
-            let mut ip = self.lock(ctx);
+            let mut ip = self.lock(ctx).expect("finalize: lock");
 
             ip.itrunc(tx, ctx);
             ip.deref_inner_mut().typ = InodeType::None;
@@ -691,30 +1726,44 @@ impl ArenaObject for Inode<InodeInner> {
 }
 
 impl Inode<InodeInner> {
+    /// Returns true if the inode is of a pseudo file system (see `mount`).
+    pub fn is_pseudo(&self) -> bool {
+        pseudo_fs(self.dev).is_some()
+    }
+
+    /// Returns true if the inode is of a read-only pseudo file system, e.g., procfs.
+    pub fn is_read_only(&self) -> bool {
+        pseudo_fs(self.dev).map_or(false, |fs| fs.read_only)
+    }
+
     /// Lock the given inode.
     /// Reads the inode from disk if necessary.
-    pub fn lock(&self, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'_, InodeInner> {
+    /// Returns Err(EIO) if the inode read from disk is free or corrupted, i.e., it has an unknown
+    /// type, or the file system is checksummed and the inode does not match its checksum, and
+    /// Err(ENOENT) if the inode of a pseudo file system is free. Locking an inode that has been
+    /// locked before while the caller held a reference to it never fails.
+    pub fn lock(&self, ctx: &KernelCtx<'_, '_>) -> Result<InodeGuard<'_, InodeInner>, KernelError> {
         let mut guard = self.inner.lock(ctx);
         if !guard.valid {
-            let mut bp = hal().disk().read(
-                self.dev,
-                ctx.kernel().fs().superblock().iblock(self.inum),
-                ctx,
-            );
-
-            // SAFETY: dip is inside bp.data.
-            let dip = unsafe {
-                (bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode)
-                    .add(self.inum as usize % IPB)
+            let mounted = ctx.kernel().fs().as_pin().get_ref().mounted();
+            let dip = if let Some(fs) = pseudo_fs(self.dev) {
+                // A pseudo file system is not on the disk.
+                (fs.dinode)(self.inum, ctx)
+            } else {
+                let bp = hal()
+                    .disk()
+                    .read(self.dev, mounted.superblock().iblock(self.inum), ctx);
+                let dip = dinodes(&bp)[self.inum as usize % IPB];
+                bp.free(ctx);
+                dip
             };
-            // SAFETY: i16 does not have internal structure.
-            let t = unsafe { *(dip as *const i16) };
-            // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
-            assert!(t < core::mem::variant_count::<DInodeType>() as i16);
-            // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
-            let dip = unsafe { &mut *dip };
-
-            match dip.typ {
+            if let Err(errno) = dip.check(mounted.checksums) {
+                guard.free(ctx);
+                return Err(errno.into());
+            }
+            let typ = DInodeType::try_from(dip.typ).expect("Inode::lock");
+
+            match typ {
                 DInodeType::None => guard.typ = InodeType::None,
                 DInodeType::Dir => guard.typ = InodeType::Dir,
                 DInodeType::File => guard.typ = InodeType::File,
@@ -727,14 +1776,33 @@ impl Inode<InodeInner> {
             }
             guard.nlink = dip.nlink;
             guard.size = dip.size;
-            guard.addr_direct.copy_from_slice(&dip.addr_direct);
-            guard.addr_indirect = dip.addr_indirect;
-            bp.free(ctx);
+            guard.addrs = dip.addrs;
+            guard.extent = Extent::default();
+            guard.staged.clear();
+            guard.dirhash = None;
+            guard.dirhash_full = false;
+            guard.gen = dip.gen;
+            guard.atime = dip.atime;
+            guard.mtime = dip.mtime;
+            guard.ctime = dip.ctime;
+            guard.mode = dip.mode;
+            guard.uid = dip.uid;
+            guard.gid = dip.gid;
+            if guard.typ == InodeType::None {
+                guard.free(ctx);
+                // What an inode of a pseudo file system shows, e.g., a process, may be gone.
+                let errno = if self.is_pseudo() {
+                    Errno::ENOENT
+                } else {
+                    Errno::EIO
+                };
+                return Err(errno.into());
+            }
             guard.valid = true;
-            assert_ne!(guard.typ, InodeType::None, "Inode::lock: no type");
         };
         mem::forget(guard);
-        InodeGuard { inode: self }
+        ctx.proc().deref_data().guards.lock_inode();
+        Ok(InodeGuard { inode: self })
     }
 
     pub const fn new() -> Self {
@@ -748,8 +1816,18 @@ impl Inode<InodeInner> {
                     typ: InodeType::None,
                     nlink: 0,
                     size: 0,
-                    addr_direct: [0; NDIRECT],
-                    addr_indirect: 0,
+                    addrs: [0; NDIRECT + 2],
+                    extent: Extent::default(),
+                    staged: Staged::new(),
+                    dirhash: None,
+                    dirhash_full: false,
+                    gen: 0,
+                    atime: 0,
+                    mtime: 0,
+                    ctime: 0,
+                    mode: 0,
+                    uid: 0,
+                    gid: 0,
                 },
             ),
         }
@@ -763,13 +1841,19 @@ impl Inode<InodeInner> {
             ino: self.inum,
             typ: match inner.typ {
                 InodeType::None => 0,
-                InodeType::Dir => 1,
-                InodeType::File => 2,
-                InodeType::Device { .. } => 3,
+                InodeType::Dir => T_DIR,
+                InodeType::File => T_FILE,
+                InodeType::Device { .. } => T_DEVICE,
             },
             nlink: inner.nlink,
-            _padding: 0,
+            gen: inner.gen,
             size: inner.size as usize,
+            atime: inner.atime as u64,
+            mtime: inner.mtime as u64,
+            ctime: inner.ctime as u64,
+            mode: inner.mode,
+            uid: inner.uid,
+            gid: inner.gid,
         };
         inner.free(ctx);
         st
@@ -778,78 +1862,116 @@ impl Inode<InodeInner> {
 
 impl Itable<InodeInner> {
     pub const fn new_itable() -> Self {
-        ArrayArena::<Inode<InodeInner>, NINODE>::new("ITABLE")
+        Self {
+            arena: ArrayArena::<Inode<InodeInner>, NINODE>::new(RawSpinLock::new("ITABLE")),
+        }
     }
 
     /// Find the inode with number inum on device dev
     /// and return the in-memory copy. Does not lock
     /// the inode and does not read it from disk.
-    pub fn get_inode(self: StrongPin<'_, Self>, dev: u32, inum: u32) -> RcInode<InodeInner> {
-        self.find_or_alloc(
-            |inode| inode.dev == dev && inode.inum == inum,
-            |inode| {
-                inode.dev = dev;
-                inode.inum = inum;
-                inode.inner.get_mut().valid = false;
-            },
-        )
-        .expect("[Itable::get_inode] no inodes")
+    /// Returns Err(()) if the inode table is full.
+    pub fn get_inode(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        inum: u32,
+    ) -> Result<RcInode<InodeInner>, ()> {
+        self.arena()
+            .find_or_alloc(
+                |inode| inode.dev == dev && inode.inum == inum,
+                |inode| {
+                    inode.dev = dev;
+                    inode.inum = inum;
+                    inode.inner.get_mut().valid = false;
+                },
+            )
+            .ok_or(())
+    }
+
+    /// Returns the inode with number `inum` on device `dev`, whose generation number on the disk
+    /// is `gen`. A cached copy of another generation is stale, i.e., it was read before the inode
+    /// was freed and reallocated, and is read again from the disk on the next lock.
+    fn get_inode_gen(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        inum: u32,
+        gen: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<InodeInner>, ()> {
+        let ptr = self.get_inode(dev, inum)?;
+        if let Ok(mut ip) = ptr.lock(ctx) {
+            if ip.deref_inner().gen != gen {
+                ip.deref_inner_mut().valid = false;
+            }
+            ip.free(ctx);
+        }
+        Ok(ptr)
     }
 
     /// Allocate an inode on device dev.
-    /// Mark it as allocated by giving it type.
-    /// Returns an unlocked but allocated and referenced inode.
+    /// Mark it as allocated by giving it type, and the current process as its owner.
+    /// Returns an unlocked but allocated and referenced inode, or Err(()) if there is no free
+    /// inode.
     pub fn alloc_inode(
         self: StrongPin<'_, Self>,
         dev: u32,
         typ: InodeType,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> RcInode<InodeInner> {
-        for inum in 1..ctx.kernel().fs().superblock().ninodes {
-            let mut bp = hal()
-                .disk()
-                .read(dev, ctx.kernel().fs().superblock().iblock(inum), ctx);
-
-            const_assert!(IPB <= mem::size_of::<BufData>() / mem::size_of::<Dinode>());
-            const_assert!(mem::align_of::<BufData>() % mem::align_of::<Dinode>() == 0);
-            // SAFETY: dip is inside bp.data.
-            let dip = unsafe {
-                (bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode).add(inum as usize % IPB)
-            };
-            // SAFETY: i16 does not have internal structure.
-            let t = unsafe { *(dip as *const i16) };
-            // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
-            assert!(t < core::mem::variant_count::<DInodeType>() as i16);
-            // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
-            let dip = unsafe { &mut *dip };
-
-            // a free inode
-            if dip.typ == DInodeType::None {
-                unsafe { ptr::write_bytes(dip as _, 0, 1) };
+    ) -> Result<RcInode<InodeInner>, ()> {
+        let superblock = tx.mounted.superblock();
+        for inum in 1..superblock.ninodes {
+            let mut bp = hal().disk().read(dev, superblock.iblock(inum), ctx);
+
+            let mut dinodes = dinodes_mut(&mut bp);
+            let dip = &mut dinodes[inum as usize % IPB];
+
+            // a free inode, unless it is corrupted
+            if dip.typ == DInodeType::None as u16
+                && (!tx.mounted.checksums || dip.is_checksum_valid())
+            {
+                let gen = dip.gen.wrapping_add(1);
+                let time = now();
+                *dip = Dinode::default();
+                dip.gen = gen;
+                dip.atime = time;
+                dip.mtime = time;
+                dip.ctime = time;
+                dip.uid = ctx.proc().euid();
+                dip.gid = ctx.proc().gid();
                 match typ {
-                    InodeType::None => dip.typ = DInodeType::None,
-                    InodeType::Dir => dip.typ = DInodeType::Dir,
-                    InodeType::File => dip.typ = DInodeType::File,
+                    InodeType::None => dip.typ = DInodeType::None as u16,
+                    InodeType::Dir => {
+                        dip.typ = DInodeType::Dir as u16;
+                        dip.mode = 0o755;
+                    }
+                    InodeType::File => {
+                        dip.typ = DInodeType::File as u16;
+                        dip.mode = 0o644;
+                    }
                     InodeType::Device { major, minor } => {
-                        dip.typ = DInodeType::Device;
+                        dip.typ = DInodeType::Device as u16;
                         dip.major = major;
-                        dip.minor = minor
+                        dip.minor = minor;
+                        dip.mode = 0o666;
                     }
                 }
+                if tx.mounted.checksums {
+                    dip.update_checksum();
+                }
 
                 // mark it allocated on the disk
                 tx.write(bp, ctx);
-                return self.get_inode(dev, inum);
+                return self.get_inode_gen(dev, inum, gen, ctx);
             } else {
                 bp.free(ctx);
             }
         }
-        panic!("[Itable::alloc_inode] no inodes");
+        Err(())
     }
 
     pub fn root(self: StrongPin<'_, Self>) -> RcInode<InodeInner> {
-        self.get_inode(ROOTDEV, ROOTINO)
+        self.get_inode(ROOTDEV, ROOTINO).expect("root")
     }
 
     pub fn namei(
@@ -857,7 +1979,7 @@ impl Itable<InodeInner> {
         path: &Path,
         tx: &UfsTx<'_>,
         proc: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<InodeInner>, ()> {
+    ) -> Result<RcInode<InodeInner>, KernelError> {
         Ok(self.namex(path, false, tx, proc)?.0)
     }
 
@@ -866,7 +1988,7 @@ impl Itable<InodeInner> {
         path: &'s Path,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<InodeInner>, &'s FileName<{ DIRSIZ }>), ()> {
+    ) -> Result<(RcInode<InodeInner>, &'s FileName<{ DIRSIZ }>), KernelError> {
         let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
         let name_in_path = name_in_path.ok_or(())?;
         Ok((ip, name_in_path))
@@ -878,35 +2000,49 @@ impl Itable<InodeInner> {
         parent: bool,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<InodeInner>, Option<&'s FileName<{ DIRSIZ }>>), ()> {
+    ) -> Result<(RcInode<InodeInner>, Option<&'s FileName<{ DIRSIZ }>>), KernelError> {
         let mut ptr = if path.is_absolute() {
-            self.root()
+            self.get_inode(ROOTDEV, ROOTINO)?
         } else {
-            ctx.proc().cwd().clone()
+            ctx.proc().cwd()
         };
 
         while let Some((new_path, name)) = path.skipelem() {
             path = new_path;
 
-            let mut ip = ptr.lock(ctx);
+            let mut ip = match ptr.lock(ctx) {
+                Ok(ip) => ip,
+                Err(e) => {
+                    ptr.free((tx, ctx));
+                    return Err(e);
+                }
+            };
             if ip.deref_inner().typ != InodeType::Dir {
                 ip.free(ctx);
                 ptr.free((tx, ctx));
-                return Err(());
+                return Err(Errno::EINVAL.into());
             }
             if parent && path.is_empty_string() {
                 // Stop one level early.
                 ip.free(ctx);
                 return Ok((ptr, Some(name)));
             }
-            let next = ip.dirlookup(name, ctx);
+            let mounted = ctx
+                .kernel()
+                .mounts()
+                .lookup(ptr.dev, ptr.inum, name.as_bytes());
+            let next = if let Some(dev) = mounted {
+                self.get_inode(dev, ROOTINO)
+            } else {
+                ip.dirlookup(name, ctx).map(|(ip, _)| ip)
+            };
             ip.free(ctx);
             ptr.free((tx, ctx));
-            ptr = next?.0
+            ptr = next?
         }
         if parent {
             ptr.free((tx, ctx));
-            return Err(());
+            return Err(Errno::EINVAL.into());
         }
         Ok((ptr, None))
     }
@@ -7,10 +7,17 @@
 //! write an uncommitted system call's updates to disk.
 //!
 //! A system call should call begin_op()/end_op() to mark
-//! its start and end. Usually begin_op() just increments
-//! the count of in-progress FS system calls and returns.
-//! But if it thinks the LOG is close to running out, it
-//! sleeps until the last outstanding end_op() commits.
+//! its start and end. Usually begin_op() just reserves
+//! MAXOPBLOCKS blocks of the LOG for the system call and returns.
+//! But if the LOG has no room for the reservation, it
+//! sleeps until end_op() hands it one.
+//!
+//! A reservation shrinks by a block whenever the system call
+//! logs a new block, and end_op() returns what is left of it,
+//! so the room of the LOG is known exactly. Waiters are admitted
+//! in the order they arrived, and only as many as fit are woken
+//! up, each with its reservation already made, instead of waking
+//! all of them to race for the room and mostly sleep again.
 //!
 //! The LOG is a physical re-do LOG containing disk blocks.
 //! The on-disk LOG format:
@@ -20,172 +27,180 @@
 //!   block C
 //!   ...
 //! Log appends are synchronous.
-use core::mem;
-
+//!
+//! The format, and how a transaction is committed and recovered, are in `rv6_abi::log`, whose
+//! crash consistency `mkfs` checks on the host.
 use arrayvec::ArrayVec;
-use itertools::*;
+use rv6_abi::{
+    log::{self, LogDisk, MAXLOGGED},
+    Superblock,
+};
 use static_assertions::const_assert;
 
 use crate::{
-    bio::{Buf, BufData, BufUnlocked},
+    bio::{Buf, BufUnlocked},
     hal::hal,
-    lock::SleepableLock,
-    param::{BSIZE, LOGSIZE, MAXOPBLOCKS},
-    proc::KernelCtx,
+    kernel::KernelRef,
+    lock::{SleepableLock, SleepableLockGuard},
+    param::{LOGSIZE, MAXOPBLOCKS},
+    proc::{KernelCtx, WaitChannel},
+    some_or,
 };
 
 pub struct Log {
     dev: u32,
-    start: i32,
-    size: i32,
+
+    /// The superblock at mount, which locates the log.
+    superblock: Superblock,
 
     /// How many FS sys calls are executing?
     outstanding: i32,
 
+    /// Blocks reserved by the executing FS sys calls that they have not logged yet.
+    reserved: i32,
+
+    /// How many FS sys calls are waiting in begin_op() to be admitted.
+    waiting: i32,
+
+    /// How many waiting FS sys calls have been admitted but have not woken up yet.
+    granted: i32,
+
     /// In commit(), please wait.
     committing: bool,
 
     /// Contents of the header block, used to keep track in memory of logged block# before commit.
     bufs: ArrayVec<BufUnlocked, LOGSIZE>,
-}
 
-/// Contents of the header block, used for the on-disk header block.
-struct LogHeader {
-    n: u32,
-    block: [u32; LOGSIZE],
+    /// Ranges of blocks freed in this transaction, as (start, length). They are discarded after
+    /// commit, since discarding them earlier would lose their contents if the transaction does not
+    /// commit.
+    discards: ArrayVec<(u32, u32), NDISCARD>,
 }
 
-impl Log {
-    pub fn new(dev: u32, start: i32, size: i32, ctx: &KernelCtx<'_, '_>) -> Self {
-        let mut log = Self {
-            dev,
-            start,
-            size,
-            outstanding: 0,
-            committing: false,
-            bufs: ArrayVec::new(),
-        };
-        log.recover_from_log(ctx);
-        log
-    }
-
-    /// Copy committed blocks from log to their home location.
-    fn install_trans(&mut self, ctx: &KernelCtx<'_, '_>) {
-        let dev = self.dev;
-        let start = self.start;
-
-        for (tail, dbuf) in self.bufs.drain(..).enumerate() {
-            // Read log block.
-            let lbuf = hal()
-                .disk()
-                .read(dev, (start + tail as i32 + 1) as u32, ctx);
-
-            // Read dst.
-            let mut dbuf = dbuf.lock(ctx);
+/// Maximum number of discarded ranges per transaction. Further ranges are not discarded.
+const NDISCARD: usize = 16;
 
-            // Copy block to dst.
-            dbuf.deref_inner_mut()
-                .data
-                .copy_from_slice(&lbuf.deref_inner().data[..]);
+const_assert!(LOGSIZE <= MAXLOGGED);
 
-            // Write dst to disk.
-            hal().disk().write(&mut dbuf, ctx);
+/// The disk under the log, seen through the buffer cache.
+struct BufDisk<'a, 'id, 'p> {
+    dev: u32,
+    ctx: &'a KernelCtx<'id, 'p>,
+}
 
-            lbuf.free(ctx);
-            dbuf.free(ctx);
-        }
+impl LogDisk for BufDisk<'_, '_, '_> {
+    fn copy(&mut self, from: u32, to: u32) {
+        let from = hal().disk().read(self.dev, from, self.ctx);
+        let mut to = hal().disk().read(self.dev, to, self.ctx);
+        to.deref_inner_mut()
+            .data
+            .copy_from_slice(&from.deref_inner().data[..]);
+        hal().disk().write(&mut to, self.ctx);
+        from.free(self.ctx);
+        to.free(self.ctx);
     }
 
-    /// Read the log header from disk into the in-memory log header.
-    fn read_head(&mut self, ctx: &KernelCtx<'_, '_>) {
-        let mut buf = hal().disk().read(self.dev, self.start as u32, ctx);
-
-        const_assert!(mem::size_of::<LogHeader>() <= BSIZE);
-        const_assert!(mem::align_of::<BufData>() % mem::align_of::<LogHeader>() == 0);
-        // SAFETY:
-        // * buf.data is larger than LogHeader
-        // * buf.data is aligned properly.
-        // * LogHeader contains only u32's, so does not have any requirements.
-        // * buf is locked, so we can access it exclusively.
-        let lh = unsafe { &mut *(buf.deref_inner_mut().data.as_mut_ptr() as *mut LogHeader) };
-        buf.free(ctx);
-
-        for b in &lh.block[0..lh.n as usize] {
-            let buf = hal().disk().read(self.dev, *b, ctx).unlock(ctx);
-            self.bufs.push(buf);
-        }
+    fn read(&mut self, bn: u32, f: impl FnOnce(&[u8])) {
+        let buf = hal().disk().read(self.dev, bn, self.ctx);
+        f(&buf.deref_inner().data[..]);
+        buf.free(self.ctx);
     }
 
-    /// Write in-memory log header to disk.
-    /// This is the true point at which the
-    /// current transaction commits.
-    fn write_head(&mut self, ctx: &KernelCtx<'_, '_>) {
-        let mut buf = hal().disk().read(self.dev, self.start as u32, ctx);
-
-        const_assert!(mem::size_of::<LogHeader>() <= BSIZE);
-        const_assert!(mem::align_of::<BufData>() % mem::align_of::<LogHeader>() == 0);
-        // SAFETY:
-        // * buf.data is larger than LogHeader
-        // * buf.data is aligned properly.
-        // * LogHeader contains only u32's, so does not have any requirements.
-        // * buf is locked, so we can access it exclusively.
-        let mut lh = unsafe { &mut *(buf.deref_inner_mut().data.as_mut_ptr() as *mut LogHeader) };
-
-        lh.n = self.bufs.len() as u32;
-        for (db, b) in izip!(&mut lh.block, &self.bufs) {
-            *db = b.blockno;
-        }
-        hal().disk().write(&mut buf, ctx);
-        buf.free(ctx);
+    fn update(&mut self, bn: u32, f: impl FnOnce(&mut [u8])) {
+        let mut buf = hal().disk().read(self.dev, bn, self.ctx);
+        f(&mut buf.deref_inner_mut().data[..]);
+        hal().disk().write(&mut buf, self.ctx);
+        buf.free(self.ctx);
     }
 
-    fn recover_from_log(&mut self, ctx: &KernelCtx<'_, '_>) {
-        self.read_head(ctx);
-
-        // If committed, copy from log to disk.
-        self.install_trans(ctx);
-
-        // Clear the log.
-        self.write_head(ctx);
+    fn flush(&mut self) {
+        // Disk writes are synchronous.
     }
+}
 
-    /// Copy modified blocks from cache to self.
-    fn write_log(&mut self, ctx: &KernelCtx<'_, '_>) {
-        for (tail, from) in self.bufs.iter().enumerate() {
-            // Log block.
-            let mut to = hal()
-                .disk()
-                .read(self.dev, (self.start + tail as i32 + 1) as u32, ctx);
-
-            // Cache block.
-            let from = hal().disk().read(self.dev, from.blockno, ctx);
-
-            to.deref_inner_mut()
-                .data
-                .copy_from_slice(&from.deref_inner().data[..]);
+impl Log {
+    /// Recovers the log of the file system of `superblock` on `dev`.
+    /// Returns Err(()) if the log is corrupted, in which case nothing has been written.
+    pub fn new(dev: u32, superblock: Superblock, ctx: &KernelCtx<'_, '_>) -> Result<Self, ()> {
+        log::recover::<_, LOGSIZE>(&mut BufDisk { dev, ctx }, &superblock).map_err(|_| ())?;
+        Ok(Self {
+            dev,
+            superblock,
+            outstanding: 0,
+            reserved: 0,
+            waiting: 0,
+            granted: 0,
+            committing: false,
+            bufs: ArrayVec::new(),
+            discards: ArrayVec::new(),
+        })
+    }
 
-            // Write the log.
-            hal().disk().write(&mut to, ctx);
+    /// Returns true if the log has room for one more FS sys call.
+    fn fits(&self) -> bool {
+        !self.committing
+            && self.bufs.len() as i32 + self.reserved + MAXOPBLOCKS as i32 <= LOGSIZE as i32
+    }
 
-            to.free(ctx);
-            from.free(ctx);
-        }
+    /// Starts an FS sys call, reserving `MAXOPBLOCKS` blocks for it.
+    fn admit(&mut self) {
+        self.outstanding += 1;
+        self.reserved += MAXOPBLOCKS as i32;
     }
 
     fn commit(&mut self, ctx: &KernelCtx<'_, '_>) {
         if !self.bufs.is_empty() {
-            // Write modified blocks from cache to self.
-            self.write_log(ctx);
+            let blocks = self.pending_blocks();
+            log::commit(
+                &mut BufDisk { dev: self.dev, ctx },
+                &self.superblock,
+                &blocks,
+            );
+            // The blocks are installed, so they need not stay in the cache.
+            self.bufs.clear();
+
+            for (start, len) in self.discards.drain(..) {
+                hal().disk().discard(start, len, ctx);
+            }
+        };
+    }
 
-            // Write header to disk -- the real commit.
-            self.write_head(ctx);
+    /// Records that block `b` is freed in this transaction, to discard it after commit.
+    pub fn discard(&mut self, b: u32) {
+        for (start, len) in &mut self.discards {
+            if *start + *len == b {
+                *len += 1;
+                return;
+            } else if b + 1 == *start {
+                *start = b;
+                *len += 1;
+                return;
+            }
+        }
+        // Discards are only hints, so the block is not discarded if there is no room.
+        let _ = self.discards.try_push((b, 1));
+    }
 
-            // Now install writes to home locations.
-            self.install_trans(ctx);
+    /// Cancels the discard of block `b`, which is allocated again in this transaction.
+    pub fn undiscard(&mut self, b: u32) {
+        let i = some_or!(
+            self.discards
+                .iter()
+                .position(|(start, len)| (*start..*start + *len).contains(&b)),
+            return
+        );
+        let (start, len) = self.discards.swap_remove(i);
+        if start < b {
+            self.discards.push((start, b - start));
+        }
+        if b + 1 < start + len {
+            let _ = self.discards.try_push((b + 1, start + len - (b + 1)));
+        }
+    }
 
-            // Erase the transaction from the self.
-            self.write_head(ctx);
-        };
+    /// Returns the block numbers that are logged but not yet committed.
+    pub fn pending_blocks(&self) -> ArrayVec<u32, LOGSIZE> {
+        self.bufs.iter().map(|buf| buf.blockno).collect()
     }
 
     /// Caller has modified b->data and is done with the buffer.
@@ -196,9 +211,12 @@ impl Log {
     ///   bp = Disk::read(...)
     ///   modify bp->data[]
     ///   write(bp)
-    pub fn write(&mut self, b: Buf, ctx: &KernelCtx<'_, '_>) {
+    ///
+    /// `logged` is the number of blocks that the calling FS sys call has logged so far.
+    /// Returns true if b is newly logged, in which case it uses up a block of the reservation.
+    pub fn write(&mut self, b: Buf, logged: u32, ctx: &KernelCtx<'_, '_>) -> bool {
         assert!(
-            !(self.bufs.len() >= LOGSIZE || self.bufs.len() as i32 >= self.size - 1),
+            !(self.bufs.len() >= LOGSIZE || self.bufs.len() as u32 >= self.superblock.nlog - 1),
             "too big a transaction"
         );
         assert!(self.outstanding >= 1, "write outside of trans");
@@ -206,34 +224,75 @@ impl Log {
         if self.bufs.iter().all(|buf| buf.blockno != b.blockno) {
             // Add new block to log
             self.bufs.push(b.unlock(ctx));
+            if (logged as usize) < MAXOPBLOCKS {
+                self.reserved -= 1;
+            }
+            true
         } else {
             b.free(ctx);
+            false
         }
     }
 }
 
-impl SleepableLock<Log> {
+/// The log, and the FS sys calls waiting for room in it.
+pub struct LogLock {
+    log: SleepableLock<Log>,
+    /// Where begin_op() waits to be admitted. It is apart from the wait channel of `log`, on
+    /// which sync_blocks() waits for commits, so that waking up one waiter never picks the wrong
+    /// kind of waiter.
+    admission: WaitChannel,
+}
+
+impl LogLock {
+    pub const fn new(log: Log) -> Self {
+        Self {
+            log: SleepableLock::new("LOG", log),
+            admission: WaitChannel::new(),
+        }
+    }
+
+    pub fn lock(&self) -> SleepableLockGuard<'_, Log> {
+        self.log.lock()
+    }
+
     /// Called at the start of each FS system call.
     pub fn begin_op(&self, ctx: &KernelCtx<'_, '_>) {
         let mut guard = self.lock();
-        loop {
-            if guard.committing ||
-            // This op might exhaust log space; wait for commit.
-            guard.bufs.len() as i32 + (guard.outstanding + 1) * MAXOPBLOCKS as i32 > LOGSIZE as i32
-            {
-                guard.sleep(ctx);
-            } else {
-                guard.outstanding += 1;
-                break;
-            }
+        // Do not overtake the waiters.
+        if guard.waiting == 0 && guard.fits() {
+            guard.admit();
+            return;
+        }
+
+        // This op might exhaust log space; wait until end_op() or sync_blocks() admits us.
+        guard.waiting += 1;
+        while guard.granted == 0 {
+            self.admission.sleep(&mut guard, ctx);
+        }
+        guard.granted -= 1;
+    }
+
+    /// Admits as many waiters of begin_op() as the log has room for, oldest first, and wakes
+    /// them up.
+    fn admit_waiters(&self, guard: &mut SleepableLockGuard<'_, Log>, kernel: KernelRef<'_, '_>) {
+        while guard.waiting > 0 && guard.fits() {
+            guard.waiting -= 1;
+            guard.granted += 1;
+            guard.admit();
+            // Whoever of the waiters wakes up takes the grant, so none is lost even if another
+            // waiter than this one does.
+            self.admission.wakeup_one(kernel);
         }
     }
 
-    /// Called at the end of each FS system call.
+    /// Called at the end of each FS system call, which has logged `logged` blocks.
     /// Commits if this was the last outstanding operation.
-    pub fn end_op(&self, ctx: &KernelCtx<'_, '_>) {
+    pub fn end_op(&self, logged: u32, ctx: &KernelCtx<'_, '_>) {
         let mut guard = self.lock();
         guard.outstanding -= 1;
+        // Return the unused part of the reservation.
+        guard.reserved -= (MAXOPBLOCKS as u32).saturating_sub(logged) as i32;
         assert!(!guard.committing, "guard.committing");
 
         if guard.outstanding == 0 {
@@ -245,13 +304,38 @@ impl SleepableLock<Log> {
             // Call commit w/o holding locks, since not allowed to sleep with locks.
             guard.reacquire_after(||
                 // SAFETY: there is no another transaction, so `inner` cannot be read or written.
-                unsafe { &mut *self.get_mut_raw() }.commit(ctx));
+                unsafe { &mut *self.log.get_mut_raw() }.commit(ctx));
 
             guard.committing = false;
+            // sync_blocks() may be waiting for the commit.
+            guard.wakeup(ctx.kernel());
         }
 
-        // begin_op() may be waiting for LOG space, and decrementing log.outstanding has decreased
-        // the amount of reserved space.
-        guard.wakeup(ctx.kernel());
+        // begin_op() may be waiting for LOG space, and ending this op has freed some.
+        self.admit_waiters(&mut guard, ctx.kernel());
+    }
+
+    /// Waits until none of `blocks` is pending in the log.
+    /// If no FS system call is executing, commits the log right away instead of waiting for the
+    /// last end_op(). Must not be called inside a transaction.
+    pub fn sync_blocks(&self, blocks: &[u32], ctx: &KernelCtx<'_, '_>) {
+        let mut guard = self.lock();
+        while guard.committing || guard.bufs.iter().any(|buf| blocks.contains(&buf.blockno)) {
+            if guard.committing || guard.outstanding > 0 {
+                // end_op() wakes us up after the commit.
+                guard.sleep(ctx);
+                continue;
+            }
+
+            guard.committing = true;
+            guard.reacquire_after(||
+                // SAFETY: there is no another transaction, so `inner` cannot be read or written.
+                unsafe { &mut *self.log.get_mut_raw() }.commit(ctx));
+            guard.committing = false;
+
+            // Others may be waiting for the commit to finish.
+            guard.wakeup(ctx.kernel());
+            self.admit_waiters(&mut guard, ctx.kernel());
+        }
     }
 }
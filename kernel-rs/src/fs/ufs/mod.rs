@@ -11,46 +11,129 @@
 //!
 //! On-disk file system format used for both kernel and user programs are also included here.
 
-use core::cell::UnsafeCell;
-use core::{cmp, mem};
+use core::cell::{Cell, UnsafeCell};
+use core::cmp;
+use core::mem::{self, MaybeUninit};
 
+use arrayvec::ArrayVec;
 use pin_project::pin_project;
-use spin::Once;
+use rv6_abi::{MAXFILE, NDINDIRECT, NDIRECT, NINDIRECT, ROOTINO};
 
-use self::log::Log;
-use super::{FcntlFlags, FileName, FileSystem, InodeGuard, InodeType, Itable, Path, RcInode, Stat};
+use self::log::{Log, LogLock};
+use super::{
+    Access, FcntlFlags, FileName, FileSystem, FswMask, Inode, InodeGuard, InodeType, Itable, Path,
+    RcInode, Stat,
+};
 use crate::util::strong_pin::StrongPin;
 use crate::{
     bio::Buf,
-    file::{FileType, InodeFileType},
+    error::{Errno, KernelError},
+    file::{FileType, InodeFileType, ReadAhead},
     hal::hal,
-    lock::SleepableLock,
-    param::BSIZE,
-    proc::KernelCtx,
+    lock::{SleepLock, SpinLock},
+    param::{LOGSIZE, MAXOPBLOCKS},
+    proc::{Gid, KernelCtx, Uid},
 };
 
+mod dirhash;
 mod inode;
 mod log;
 mod superblock;
 
-pub use inode::{Dinode, Dirent, InodeInner, DIRENT_SIZE, DIRSIZ};
-pub use superblock::{Superblock, BPB, IPB};
-
-/// root i-number
-const ROOTINO: u32 = 1;
-
-const NDIRECT: usize = 12;
-const NINDIRECT: usize = BSIZE.wrapping_div(mem::size_of::<u32>());
-const MAXFILE: usize = NDIRECT.wrapping_add(NINDIRECT);
+pub use inode::{Dirent, InodeInner, DIRENT_SIZE, DIRSIZ};
+use superblock::{dinodes, dinodes_mut, read_superblock, write_superblock};
+pub use superblock::{Dinode, Superblock, BPB, IPB};
 
 #[pin_project]
 pub struct Ufs {
-    /// Initializing superblock should run only once because forkret() calls FileSystem::init().
+    /// Written once by `DiskReady::mount`, and read only through `Ufs::mounted` afterwards.
     /// There should be one superblock per disk device, but we run with only one device.
-    superblock: Once<Superblock>,
-    log: Once<SleepableLock<Log>>,
+    mounted: UnsafeCell<MaybeUninit<Mounted>>,
     #[pin]
     itable: Itable<InodeInner>,
+    /// Serializes renames, so that no directory moves while a rename checks that it does not
+    /// move a directory into itself.
+    rename_lock: SleepLock<()>,
+}
+
+/// Initialization phases of `Ufs`:
+///
+/// * `Unmounted`: the handle that `Ufs::unmounted()` returns, only once. Nothing has been read
+///   from the disk.
+/// * `DiskReady`: the superblock has been read, so the layout of the disk is known.
+/// * `Mounted`: the log has been recovered, so transactions can begin.
+///
+/// The superblock and the log are reachable only through these types, and each phase can be
+/// constructed only by consuming the previous one. Hence, touching them too early is a type
+/// error, and the file system is mounted at most once.
+struct Unmounted<'s> {
+    ufs: &'s Ufs,
+    dev: u32,
+}
+
+struct DiskReady<'s> {
+    ufs: &'s Ufs,
+    dev: u32,
+    superblock: Superblock,
+}
+
+pub struct Mounted {
+    dev: u32,
+    /// The in-memory copy of the superblock. It may change after mounting, but only within a
+    /// transaction, which also writes it back to the disk.
+    superblock: SpinLock<Superblock>,
+    log: LogLock,
+    /// Whether freed blocks are discarded, i.e., the superblock has `FS_DISCARD` set.
+    discard: bool,
+    /// Whether the metadata is checksummed, i.e., the superblock has `FS_CHECKSUM` set.
+    checksums: bool,
+    /// Whether inodes list extents instead of blocks, i.e., the superblock has `FS_EXTENTS` set.
+    extents: bool,
+}
+
+impl<'s> Unmounted<'s> {
+    /// Reads the superblock.
+    /// Returns Err(()) if it is corrupted.
+    fn read(self, ctx: &KernelCtx<'_, '_>) -> Result<DiskReady<'s>, ()> {
+        let buf = hal().disk().read(self.dev, 1, ctx);
+        let superblock = read_superblock(&buf);
+        buf.free(ctx);
+        Ok(DiskReady {
+            ufs: self.ufs,
+            dev: self.dev,
+            superblock: superblock?,
+        })
+    }
+}
+
+impl<'s> DiskReady<'s> {
+    /// Recovers the log and finishes mounting.
+    /// Returns Err(()) if the log is corrupted.
+    fn mount(self, ctx: &KernelCtx<'_, '_>) -> Result<&'s Mounted, ()> {
+        let log = Log::new(self.dev, self.superblock, ctx)?;
+        let mounted = Mounted {
+            dev: self.dev,
+            superblock: SpinLock::new("SUPERBLOCK", self.superblock),
+            log: LogLock::new(log),
+            discard: self.superblock.has_discard(),
+            checksums: self.superblock.has_checksums(),
+            extents: self.superblock.has_extents(),
+        };
+        // SAFETY: `Ufs::unmounted` returns only one `Unmounted`, so this is the only write, and no
+        // one reads `ufs.mounted` before the first process returns to user space.
+        Ok(unsafe { (*self.ufs.mounted.get()).write(mounted) })
+    }
+}
+
+impl Mounted {
+    /// Returns a snapshot of the superblock.
+    pub fn superblock(&self) -> Superblock {
+        *self.superblock.lock()
+    }
+
+    fn log(&self) -> &LogLock {
+        &self.log
+    }
 }
 
 impl FileSystem for Ufs {
@@ -58,23 +141,22 @@ impl FileSystem for Ufs {
     type InodeInner = InodeInner;
     type Tx<'s> = UfsTx<'s>;
 
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
-        if !self.superblock.is_completed() {
-            let buf = hal().disk().read(dev, 1, ctx);
-            let superblock = self.superblock.call_once(|| Superblock::new(&buf));
-            buf.free(ctx);
-            let _ = self.log.call_once(|| {
-                SleepableLock::new(
-                    "LOG",
-                    Log::new(dev, superblock.logstart as i32, superblock.nlog as i32, ctx),
-                )
-            });
-        }
+    unsafe fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        // SAFETY: the caller calls this only once, before any process returns to user space.
+        unsafe { self.unmounted(dev) }
+            .read(ctx)?
+            .mount(ctx)
+            .map(|_| ())
     }
 
     fn begin_tx(&self, ctx: &KernelCtx<'_, '_>) -> Self::Tx<'_> {
-        self.log().begin_op(ctx);
-        UfsTx { fs: self }
+        let mounted = self.mounted();
+        mounted.log().begin_op(ctx);
+        ctx.proc().deref_data().guards.begin_tx();
+        UfsTx {
+            mounted,
+            logged: Cell::new(0),
+        }
     }
 
     fn root(self: StrongPin<'_, Self>) -> RcInode<Self::InodeInner> {
@@ -86,7 +168,7 @@ impl FileSystem for Ufs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self::InodeInner>, ()> {
+    ) -> Result<RcInode<Self::InodeInner>, KernelError> {
         self.itable().namei(path, tx, ctx)
     }
 
@@ -96,31 +178,47 @@ impl FileSystem for Ufs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
-        let ip = inode.lock(ctx);
+        let ip = inode.lock(ctx)?;
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         if ip.deref_inner().typ == InodeType::Dir {
-            return Err(());
+            return Err(Errno::EINVAL.into());
         }
         ip.deref_inner_mut().nlink += 1;
         ip.update(tx, ctx);
         drop(ip);
 
-        if let Ok((ptr2, name)) = self.itable().nameiparent(path, tx, ctx) {
-            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
-            let dp = ptr2.lock(ctx);
-            let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
-            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, tx, ctx).is_ok() {
-                return Ok(());
+        let err = match self.itable().nameiparent(path, tx, ctx) {
+            Ok((ptr2, name)) => {
+                let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+                match ptr2.lock(ctx) {
+                    Ok(dp) => {
+                        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+                        let allowed = dp
+                            .deref_inner()
+                            .check_access(Access::WRITE | Access::EXEC, ctx);
+                        if dp.dev == inode.dev
+                            && !dp.is_pseudo()
+                            && !is_mount_point(&dp, name, ctx)
+                            && allowed.is_ok()
+                            && dp.dirlink(name, inode.inum, tx, ctx).is_ok()
+                        {
+                            return Ok(());
+                        }
+                        Errno::EINVAL.into()
+                    }
+                    Err(e) => e,
+                }
             }
-        }
+            Err(e) => e,
+        };
 
-        let ip = inode.lock(ctx);
+        let ip = inode.lock(ctx).expect("link: lock");
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink -= 1;
         ip.update(tx, ctx);
-        Err(())
+        Err(err)
     }
 
     fn unlink(
@@ -128,28 +226,34 @@ impl FileSystem for Ufs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
         let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+        let dp = ptr.lock(ctx)?;
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
 
-        // Cannot unlink "." or "..".
-        if name.as_bytes() == b"." || name.as_bytes() == b".." {
-            return Err(());
+        // Cannot unlink "." or "..", nor a mount point, nor anything in a pseudo file system.
+        if name.as_bytes() == b"."
+            || name.as_bytes() == b".."
+            || dp.is_pseudo()
+            || is_mount_point(&dp, name, ctx)
+        {
+            return Err(Errno::EINVAL.into());
         }
+        dp.deref_inner()
+            .check_access(Access::WRITE | Access::EXEC, ctx)?;
 
         let (ptr2, off) = dp.dirlookup(name, ctx)?;
         let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
-        let ip = ptr2.lock(ctx);
+        let ip = ptr2.lock(ctx)?;
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
 
         if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
-            return Err(());
+            return Err(Errno::EINVAL.into());
         }
 
-        dp.write_kernel(&Dirent::default(), off, tx, ctx)
+        dp.write_dirent(&Dirent::default(), off, tx, ctx)
             .expect("unlink: writei");
         if ip.deref_inner().typ == InodeType::Dir {
             dp.deref_inner_mut().nlink -= 1;
@@ -162,6 +266,180 @@ impl FileSystem for Ufs {
         Ok(())
     }
 
+    fn rename(
+        self: StrongPin<'_, Self>,
+        old: &Path,
+        new: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let (optr, oname) = self.itable().nameiparent(old, tx, ctx)?;
+        let optr = scopeguard::guard(optr, |ptr| ptr.free((tx, ctx)));
+        let (nptr, nname) = self.itable().nameiparent(new, tx, ctx)?;
+        let nptr = scopeguard::guard(nptr, |ptr| ptr.free((tx, ctx)));
+
+        // Cannot rename "." or "..".
+        for name in [oname, nname] {
+            if name.as_bytes() == b"." || name.as_bytes() == b".." {
+                return Err(Errno::EINVAL.into());
+            }
+        }
+        // Nothing moves into or out of a pseudo file system, nor onto or off a mount point.
+        if optr.dev != nptr.dev
+            || optr.is_pseudo()
+            || is_mount_point(&optr, oname, ctx)
+            || is_mount_point(&nptr, nname, ctx)
+        {
+            return Err(Errno::EINVAL.into());
+        }
+
+        let rename_lock = self.as_pin().get_ref().rename_lock.lock(ctx);
+        let _rename_lock = scopeguard::guard(rename_lock, |guard| guard.free(ctx));
+
+        // Find the source, and refuse to move a directory into itself.
+        let (inum, is_dir) = {
+            let odp = optr.lock(ctx)?;
+            let mut odp = scopeguard::guard(odp, |ip| ip.free(ctx));
+            let (ptr, _) = odp.dirlookup(oname, ctx)?;
+            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+            drop(odp);
+            let ip = ptr.lock(ctx)?;
+            let is_dir = ip.deref_inner().typ == InodeType::Dir;
+            ip.free(ctx);
+            (ptr.inum, is_dir)
+        };
+        if is_dir && self.is_ancestor(inum, &nptr, tx, ctx)? {
+            return Err(Errno::EINVAL.into());
+        }
+
+        // Lock the parents, the ancestor first if one is an ancestor of the other, as the other
+        // operations lock a directory before its entries. Otherwise, no operation but a rename
+        // holds both, so the order does not matter.
+        let same_dir = optr.inum == nptr.inum;
+        let new_first = !same_dir && self.is_ancestor(nptr.inum, &optr, tx, ctx)?;
+        let (odp, ndp) = if same_dir {
+            (optr.lock(ctx)?, None)
+        } else if new_first {
+            let ndp = nptr.lock(ctx)?;
+            match optr.lock(ctx) {
+                Ok(odp) => (odp, Some(ndp)),
+                Err(e) => {
+                    ndp.free(ctx);
+                    return Err(e);
+                }
+            }
+        } else {
+            let odp = optr.lock(ctx)?;
+            match nptr.lock(ctx) {
+                Ok(ndp) => (odp, Some(ndp)),
+                Err(e) => {
+                    odp.free(ctx);
+                    return Err(e);
+                }
+            }
+        };
+        let mut odp = scopeguard::guard(odp, |ip| ip.free(ctx));
+        let mut ndp = scopeguard::guard(ndp, |ip| {
+            if let Some(ip) = ip {
+                ip.free(ctx);
+            }
+        });
+        odp.deref_inner()
+            .check_access(Access::WRITE | Access::EXEC, ctx)?;
+        if let Some(ndp) = &*ndp {
+            ndp.deref_inner()
+                .check_access(Access::WRITE | Access::EXEC, ctx)?;
+        }
+
+        // The source may have been unlinked since, or the new parent removed.
+        let (sptr, soff) = odp.dirlookup(oname, ctx)?;
+        let sptr = scopeguard::guard(sptr, |ptr| ptr.free((tx, ctx)));
+        if sptr.inum != inum {
+            return Err(Errno::EINVAL.into());
+        }
+        let ndp_ref = match &mut *ndp {
+            Some(ndp) => ndp,
+            None => &mut *odp,
+        };
+        if ndp_ref.deref_inner().nlink == 0 {
+            return Err(Errno::EINVAL.into());
+        }
+
+        let target = ndp_ref.dirlookup(nname, ctx);
+        let target = scopeguard::guard(target, |target| {
+            if let Ok((ptr, _)) = target {
+                ptr.free((tx, ctx));
+            }
+        });
+        let ip = sptr.lock(ctx)?;
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+
+        if let Ok((tptr, toff)) = &*target {
+            if tptr.inum == inum {
+                // Both names refer to the same file.
+                return Ok(());
+            }
+            // The target is not empty if it is the old parent.
+            if tptr.inum == odp.inum {
+                return Err(Errno::EINVAL.into());
+            }
+            let tp = tptr.lock(ctx)?;
+            let mut tp = scopeguard::guard(tp, |ip| ip.free(ctx));
+            let target_is_dir = tp.deref_inner().typ == InodeType::Dir;
+            if target_is_dir != is_dir || (target_is_dir && !tp.is_dir_empty(ctx)) {
+                return Err(Errno::EINVAL.into());
+            }
+
+            let ndp_ref = match &mut *ndp {
+                Some(ndp) => ndp,
+                None => &mut *odp,
+            };
+            let mut de = Dirent::default();
+            de.inum = inum as _;
+            de.set_name(nname.as_bytes());
+            ndp_ref
+                .write_dirent(&de, *toff, tx, ctx)
+                .expect("rename: writei");
+            if target_is_dir {
+                // for the ".." of the target
+                ndp_ref.deref_inner_mut().nlink -= 1;
+                ndp_ref.update(tx, ctx);
+                tp.deref_inner_mut().nlink -= 1;
+            }
+            tp.deref_inner_mut().nlink -= 1;
+            tp.update(tx, ctx);
+        } else {
+            // Fails only if the disk is full, before changing anything.
+            let ndp_ref = match &mut *ndp {
+                Some(ndp) => ndp,
+                None => &mut *odp,
+            };
+            ndp_ref.dirlink(nname, inum, tx, ctx)?;
+        }
+
+        odp.write_dirent(&Dirent::default(), soff, tx, ctx)
+            .expect("rename: writei");
+
+        if let Some(ndp) = &mut *ndp {
+            if is_dir {
+                // Point ".." of the directory to the new parent.
+                // SAFETY: b".." does not contain any NUL characters.
+                let dotdot = unsafe { FileName::from_bytes(b"..") };
+                let (ptr, off) = ip.dirlookup(dotdot, ctx).expect("rename: no ..");
+                ptr.free((tx, ctx));
+                let mut de = Dirent::default();
+                de.inum = ndp.inum as _;
+                de.set_name(b"..");
+                ip.write_dirent(&de, off, tx, ctx).expect("rename: writei");
+                odp.deref_inner_mut().nlink -= 1;
+                odp.update(tx, ctx);
+                ndp.deref_inner_mut().nlink += 1;
+                ndp.update(tx, ctx);
+            }
+        }
+        Ok(())
+    }
+
     fn create<F, T>(
         self: StrongPin<'_, Self>,
         path: &Path,
@@ -169,53 +447,70 @@ impl FileSystem for Ufs {
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self::InodeInner>, T), ()>
+    ) -> Result<(RcInode<Self::InodeInner>, T), KernelError>
     where
         F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>) -> T,
     {
         let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
         let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+        let dp = ptr.lock(ctx)?;
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
         if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
             let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
             drop(dp);
             if typ != InodeType::File {
-                return Err(());
+                return Err(Errno::EINVAL.into());
             }
-            let ip = ptr2.lock(ctx);
+            let ip = ptr2.lock(ctx)?;
             let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
             if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
-                return Err(());
+                return Err(Errno::EINVAL.into());
             }
             let ret = f(&mut ip);
             drop(ip);
             return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
         }
-        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx);
-        let ip = ptr2.lock(ctx);
+        // The inodes of a pseudo file system are those that it synthesizes only.
+        if dp.is_pseudo() || is_mount_point(&dp, name, ctx) {
+            return Err(Errno::EINVAL.into());
+        }
+        dp.deref_inner()
+            .check_access(Access::WRITE | Access::EXEC, ctx)?;
+        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr2.lock(ctx)?;
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink = 1;
         ip.update(tx, ctx);
 
         // Create . and .. entries.
-        if typ == InodeType::Dir {
-            // for ".."
-            dp.deref_inner_mut().nlink += 1;
-            dp.update(tx, ctx);
-
-            let inum = ip.inum;
+        let inum = ip.inum;
+        let dots = if typ == InodeType::Dir {
             // No ip->nlink++ for ".": avoid cyclic ref count.
             // SAFETY: b"." does not contain any NUL characters.
             ip.dirlink(unsafe { FileName::from_bytes(b".") }, inum, tx, ctx)
                 // SAFETY: b".." does not contain any NUL characters.
                 .and_then(|_| ip.dirlink(unsafe { FileName::from_bytes(b"..") }, dp.inum, tx, ctx))
-                .expect("create dots");
+        } else {
+            Ok(())
+        };
+
+        // Writing the directory entries fails only if the disk is full.
+        if dots.and_then(|_| dp.dirlink(name, inum, tx, ctx)).is_err() {
+            // De-allocate ip when ptr2 is freed.
+            ip.deref_inner_mut().nlink = 0;
+            ip.update(tx, ctx);
+            return Err(Errno::EINVAL.into());
+        }
+
+        if typ == InodeType::Dir {
+            // for ".."
+            dp.deref_inner_mut().nlink += 1;
+            dp.update(tx, ctx);
         }
-        dp.dirlink(name, ip.inum, tx, ctx).expect("create: dirlink");
         let ret = f(&mut ip);
         drop(ip);
-        Ok((ptr2, ret))
+        Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret))
     }
 
     fn open(
@@ -224,22 +519,44 @@ impl FileSystem for Ufs {
         omode: FcntlFlags,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
+        let mut access = Access::empty();
+        if !omode.intersects(FcntlFlags::O_WRONLY) {
+            access |= Access::READ;
+        }
+        if omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR | FcntlFlags::O_TRUNC) {
+            access |= Access::WRITE;
+        }
+
         let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
-            self.create(path, InodeType::File, tx, ctx, |ip| ip.deref_inner().typ)?
+            let (ip, (typ, allowed)) = self.create(path, InodeType::File, tx, ctx, |ip| {
+                let inner = ip.deref_inner();
+                (inner.typ, inner.check_access(access, ctx))
+            })?;
+            if allowed.is_err() {
+                ip.free((tx, ctx));
+                return Err(Errno::EINVAL.into());
+            }
+            (ip, typ)
         } else {
             let ptr = self.itable().namei(path, tx, ctx)?;
             let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-            let ip = ptr.lock(ctx);
+            let ip = ptr.lock(ctx)?;
             let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
             let typ = ip.deref_inner().typ;
 
             if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
-                return Err(());
+                return Err(Errno::EINVAL.into());
             }
+            ip.deref_inner().check_access(access, ctx)?;
             drop(ip);
             (scopeguard::ScopeGuard::into_inner(ptr), typ)
         };
+        // Nothing is written to a read-only pseudo file system, even by the superuser.
+        if access.contains(Access::WRITE) && ip.is_read_only() {
+            ip.free((tx, ctx));
+            return Err(Errno::EROFS.into());
+        }
 
         let filetype = match typ {
             InodeType::Device { major, .. } => FileType::Device { ip, major },
@@ -248,6 +565,9 @@ impl FileSystem for Ufs {
                     inner: InodeFileType {
                         ip,
                         off: UnsafeCell::new(0),
+                        append: omode.contains(FcntlFlags::O_APPEND),
+                        direct: omode.contains(FcntlFlags::O_DIRECT),
+                        ra: UnsafeCell::new(ReadAhead::default()),
                     },
                 }
             }
@@ -266,14 +586,43 @@ impl FileSystem for Ufs {
                 | FileType::Inode {
                     inner: InodeFileType { ip, .. },
                 } => {
-                    let mut ip = ip.lock(ctx);
+                    let mut ip = ip.lock(ctx).expect("open: lock");
                     ip.itrunc(tx, ctx);
                     ip.free(ctx);
                 }
                 _ => panic!("sys_open : Not reach"),
             };
         }
-        let fd = f.fdalloc(ctx)?;
+        if omode.contains(FcntlFlags::O_NONBLOCK) {
+            f.set_nonblock(true);
+        }
+        let fd = ctx.fdalloc(f, omode.contains(FcntlFlags::O_CLOEXEC))?;
+        Ok(fd as usize)
+    }
+
+    fn watch(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        mask: FswMask,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let ptr = self.itable().namei(path, tx, ctx)?;
+        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr.lock(ctx)?;
+        let allowed = ip.deref_inner().check_access(Access::READ, ctx);
+        ip.free(ctx);
+        allowed?;
+        let watches = ctx.kernel().fs_watches();
+        let slot = watches.add(ptr.dev, ptr.inum, mask)?;
+        let ip = scopeguard::ScopeGuard::into_inner(ptr);
+
+        let f = ctx
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Watch { ip, slot }, true, false)
+            .map_err(|_| watches.remove(slot))?;
+        let fd = ctx.fdalloc(f, false)?;
         Ok(fd as usize)
     }
 
@@ -282,38 +631,261 @@ impl FileSystem for Ufs {
         inode: RcInode<InodeInner>,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        let ip = inode.lock(ctx);
-        let typ = ip.deref_inner().typ;
-        ip.free(ctx);
-        if typ != InodeType::Dir {
+    ) -> Result<(), KernelError> {
+        let is_dir = inode.lock(ctx).and_then(|ip| {
+            let is_dir = ip.deref_inner().typ == InodeType::Dir;
+            ip.free(ctx);
+            if is_dir {
+                Ok(())
+            } else {
+                Err(Errno::EINVAL.into())
+            }
+        });
+        if let Err(e) = is_dir {
             inode.free((tx, ctx));
-            return Err(());
+            return Err(e);
         }
-        mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
+        let cwd = ctx.proc().files().set_cwd(inode);
+        cwd.free((tx, ctx));
         Ok(())
     }
+
+    fn set_times(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<InodeInner>,
+        atime: Option<u32>,
+        mtime: Option<u32>,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        if inode.is_read_only() {
+            inode.free((tx, ctx));
+            return Err(Errno::EROFS.into());
+        }
+        let ret = inode.lock(ctx).map(|mut ip| {
+            ip.set_times(atime, mtime, tx, ctx);
+            ip.free(ctx);
+        });
+        inode.free((tx, ctx));
+        ret
+    }
+
+    fn chmod(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<InodeInner>,
+        mode: u32,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        if inode.is_read_only() {
+            inode.free((tx, ctx));
+            return Err(Errno::EROFS.into());
+        }
+        let ret = inode.lock(ctx).and_then(|mut ip| {
+            let euid = ctx.proc().euid();
+            let ret = if euid == 0 || euid == ip.deref_inner().uid {
+                ip.set_mode(mode, tx, ctx);
+                Ok(())
+            } else {
+                Err(Errno::EPERM.into())
+            };
+            ip.free(ctx);
+            ret
+        });
+        inode.free((tx, ctx));
+        ret
+    }
+
+    fn chown(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<InodeInner>,
+        uid: Option<Uid>,
+        gid: Option<Gid>,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let ret = if ctx.proc().euid() != 0 {
+            Err(Errno::EPERM.into())
+        } else if inode.is_read_only() {
+            Err(Errno::EROFS.into())
+        } else {
+            inode.lock(ctx).map(|mut ip| {
+                ip.set_owner(uid, gid, tx, ctx);
+                ip.free(ctx);
+            })
+        };
+        inode.free((tx, ctx));
+        ret
+    }
+
+    fn mount(
+        self: StrongPin<'_, Self>,
+        fstype: &[u8],
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        if ctx.proc().euid() != 0 {
+            return Err(Errno::EPERM.into());
+        }
+        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
+        // Pseudo file systems are mounted in directories on the disk only.
+        let ret = if name.as_bytes() == b"." || name.as_bytes() == b".." || ptr.is_pseudo() {
+            Err(Errno::EINVAL.into())
+        } else {
+            ctx.kernel()
+                .mounts()
+                .mount(fstype, ptr.dev, ptr.inum, name.as_bytes())
+        };
+        ptr.free((tx, ctx));
+        ret
+    }
+
+    fn umount(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        if ctx.proc().euid() != 0 {
+            return Err(Errno::EPERM.into());
+        }
+        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
+        let ret = ctx
+            .kernel()
+            .mounts()
+            .umount(ptr.dev, ptr.inum, name.as_bytes());
+        ptr.free((tx, ctx));
+        ret
+    }
 }
 
 pub struct UfsTx<'s> {
-    fs: &'s Ufs,
+    mounted: &'s Mounted,
+    /// How many blocks this transaction has newly logged, which have used up its reservation.
+    logged: Cell<u32>,
 }
 
 impl Ufs {
     pub const fn new() -> Self {
         Self {
-            superblock: Once::new(),
-            log: Once::new(),
+            mounted: UnsafeCell::new(MaybeUninit::uninit()),
             itable: Itable::new_itable(),
+            rename_lock: SleepLock::new("rename", ()),
         }
     }
 
-    fn log(&self) -> &SleepableLock<Log> {
-        self.log.get().expect("log")
+    /// Commits the logged blocks holding the byte range [off, off + len) of `inode`.
+    /// `len == 0` means up to the end of file. Must not be called inside a transaction.
+    pub fn sync_range(
+        &self,
+        inode: &RcInode<InodeInner>,
+        off: u32,
+        len: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let log = self.mounted().log();
+        let pending = log.lock().pending_blocks();
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut ip = inode.lock(ctx).expect("sync_range: lock");
+        let blocks = ip.blocks_in_range(off, len, &pending, ctx);
+        ip.free(ctx);
+        log.sync_blocks(&blocks, ctx);
+    }
+
+    /// Returns the blocks that are logged but not yet committed.
+    pub fn pending_blocks(&self) -> ArrayVec<u32, LOGSIZE> {
+        self.mounted().log().lock().pending_blocks()
+    }
+
+    /// Commits every logged block. Must not be called inside a transaction.
+    pub fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        let log = self.mounted().log();
+        let pending = log.lock().pending_blocks();
+        log.sync_blocks(&pending, ctx);
     }
 
-    fn superblock(&self) -> &Superblock {
-        self.superblock.get().expect("superblock")
+    /// Grows the file system to `size` blocks.
+    ///
+    /// Returns Err(()) if `size` is not larger than the current size, or the bitmap cannot be
+    /// extended to track the new blocks. The caller must make sure that the disk holds `size`
+    /// blocks.
+    pub fn resize(&self, size: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let tx = self.begin_tx(ctx);
+        let result = tx.grow(size, ctx);
+        tx.end(ctx);
+        result
+    }
+
+    /// Returns the handle that mounts the file system on `dev`.
+    ///
+    /// # Safety
+    ///
+    /// It must be called only once, and the file system must be mounted with the handle before
+    /// any process returns to user space. If mounting fails, no process may.
+    unsafe fn unmounted(&self, dev: u32) -> Unmounted<'_> {
+        Unmounted { ufs: self, dev }
+    }
+
+    /// Returns the mounted state of the file system.
+    pub fn mounted(&self) -> &Mounted {
+        // SAFETY: the first process mounts the file system in forkret() before it returns to user
+        // space, and every other process descends from it. Hence, the file system has been
+        // mounted whenever a process gets here.
+        unsafe { (*self.mounted.get()).assume_init_ref() }
+    }
+
+    /// Returns true if the directory `inum` is `dir` or one of its ancestors. The caller must hold
+    /// `rename_lock`, so that no directory moves meanwhile.
+    fn is_ancestor(
+        self: StrongPin<'_, Self>,
+        inum: u32,
+        dir: &RcInode<InodeInner>,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<bool, KernelError> {
+        let mut ptr = dir.clone();
+        loop {
+            if ptr.inum == inum || ptr.inum == ROOTINO {
+                let found = ptr.inum == inum;
+                ptr.free((tx, ctx));
+                return Ok(found);
+            }
+            let next = match ptr.lock(ctx) {
+                Ok(mut ip) => {
+                    // SAFETY: b".." does not contain any NUL characters.
+                    let next = ip.dirlookup(unsafe { FileName::from_bytes(b"..") }, ctx);
+                    ip.free(ctx);
+                    next.map_err(KernelError::from)
+                }
+                Err(e) => Err(e),
+            };
+            ptr.free((tx, ctx));
+            ptr = next?.0;
+        }
+    }
+
+    /// Returns the status of the inode `inum` of the device `dev`, which is read from the disk
+    /// unless the inode table has it.
+    /// Returns Err(()) if the inode table is full or the inode is corrupted.
+    pub fn stat_inode(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        inum: u32,
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Stat, ()> {
+        let ptr = self.itable().get_inode(dev, inum)?;
+        let st = ptr
+            .lock(ctx)
+            .map(|ip| ip.free(ctx))
+            .map(|_| ptr.stat(ctx))
+            .map_err(|_| ());
+        ptr.free((tx, ctx));
+        st
     }
 
     #[allow(clippy::needless_lifetimes)]
@@ -322,6 +894,18 @@ impl Ufs {
     }
 }
 
+/// Returns true if a pseudo file system is mounted on the entry `name` of the directory `dir`.
+fn is_mount_point(
+    dir: &Inode<InodeInner>,
+    name: &FileName<DIRSIZ>,
+    ctx: &KernelCtx<'_, '_>,
+) -> bool {
+    ctx.kernel()
+        .mounts()
+        .lookup(dir.dev, dir.inum, name.as_bytes())
+        .is_some()
+}
+
 impl Drop for UfsTx<'_> {
     fn drop(&mut self) {
         // HACK(@efenniht): we really need linear type here:
@@ -340,7 +924,9 @@ impl UfsTx<'_> {
     ///   modify bp->data[]
     ///   write(bp)
     fn write(&self, b: Buf, ctx: &KernelCtx<'_, '_>) {
-        self.fs.log().lock().write(b, ctx);
+        if self.mounted.log().lock().write(b, self.logged.get(), ctx) {
+            self.logged.set(self.logged.get() + 1);
+        }
     }
 
     /// Zero a block.
@@ -352,29 +938,53 @@ impl UfsTx<'_> {
     }
 
     /// Blocks.
-    /// Allocate a zeroed disk block.
-    fn balloc(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
-        for b in num_iter::range_step(0, self.fs.superblock().size, BPB as u32) {
-            let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
-            for bi in 0..cmp::min(BPB as u32, self.fs.superblock().size - b) {
+    /// Allocate a zeroed disk block, `goal` if it is a free block, so that the caller can lay
+    /// out a file contiguously. Otherwise, the first free block.
+    /// Returns Err(()) if the disk is full.
+    fn balloc(&self, dev: u32, goal: u32, ctx: &KernelCtx<'_, '_>) -> Result<u32, ()> {
+        // The file system only grows, so the snapshot never covers a block beyond the end.
+        let superblock = self.mounted.superblock();
+        if goal != 0 && goal < superblock.size {
+            let mut bp = hal().disk().read(dev, superblock.bblock(goal), ctx);
+            let bi = goal as usize % BPB;
+            if bp.deref_inner_mut().data[bi / 8] & (1 << (bi % 8)) == 0 {
+                return Ok(self.bclaim(bp, dev, goal, ctx));
+            }
+            bp.free(ctx);
+        }
+
+        for b in num_iter::range_step(0, superblock.size, BPB as u32) {
+            let mut bp = hal().disk().read(dev, superblock.bblock(b), ctx);
+            for bi in 0..cmp::min(BPB as u32, superblock.size - b) {
                 let m = 1 << (bi % 8);
                 if bp.deref_inner_mut().data[(bi / 8) as usize] & m == 0 {
                     // Is block free?
-                    bp.deref_inner_mut().data[(bi / 8) as usize] |= m; // Mark block in use.
-                    self.write(bp, ctx);
-                    self.bzero(dev, b + bi, ctx);
-                    return b + bi;
+                    return Ok(self.bclaim(bp, dev, b + bi, ctx));
                 }
             }
             bp.free(ctx);
         }
 
-        panic!("balloc: out of blocks");
+        Err(())
+    }
+
+    /// Marks the free block `b` in use in `bp`, the bitmap block of `b`, and zeroes it.
+    fn bclaim(&self, mut bp: Buf, dev: u32, b: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
+        let bi = b as usize % BPB;
+        bp.deref_inner_mut().data[bi / 8] |= 1 << (bi % 8); // Mark block in use.
+        self.write(bp, ctx);
+        if self.mounted.discard {
+            self.mounted.log().lock().undiscard(b);
+        }
+        self.bzero(dev, b, ctx);
+        b
     }
 
     /// Free a disk block.
     fn bfree(&self, dev: u32, b: u32, ctx: &KernelCtx<'_, '_>) {
-        let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
+        let mut bp = hal()
+            .disk()
+            .read(dev, self.mounted.superblock().bblock(b), ctx);
         let bi = b as usize % BPB;
         let m = 1u8 << (bi % 8);
         assert_ne!(
@@ -384,12 +994,76 @@ impl UfsTx<'_> {
         );
         bp.deref_inner_mut().data[bi / 8] &= !m;
         self.write(bp, ctx);
+        if self.mounted.discard {
+            self.mounted.log().lock().discard(b);
+        }
+    }
+
+    /// Grows the file system to `size` blocks, and writes back the superblock.
+    ///
+    /// The new blocks are already free in the bitmap, since mkfs clears the bits beyond the end.
+    /// If the bitmap needs more blocks to track them, the data blocks right after the bitmap
+    /// become bitmap blocks, which fails unless they are free.
+    fn grow(&self, size: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let dev = self.mounted.dev;
+        // The buffer lock serializes resizing, so the superblocks are written back in order.
+        let mut bp = hal().disk().read(dev, 1, ctx);
+        let mut superblock = self.mounted.superblock();
+        let datastart = superblock.size - superblock.nblocks;
+        let nbitmap = datastart - superblock.bmapstart;
+        let extra =
+            (size.saturating_sub(1) as usize / BPB + 1).saturating_sub(nbitmap as usize) as u32;
+        if size <= superblock.size
+            || extra as usize > MAXOPBLOCKS - 2
+            || self.reserve(datastart, extra, ctx).is_err()
+        {
+            bp.free(ctx);
+            return Err(());
+        }
+        for b in datastart..datastart + extra {
+            self.bzero(dev, b, ctx);
+        }
+
+        superblock.nblocks = superblock.nblocks - extra + (size - superblock.size);
+        superblock.size = size;
+        *self.mounted.superblock.lock() = superblock;
+        write_superblock(&mut bp, &superblock);
+        self.write(bp, ctx);
+        Ok(())
+    }
+
+    /// Marks the `n` blocks from `start` in use, if all of them are free.
+    /// The blocks must be tracked by a single bitmap block.
+    fn reserve(&self, start: u32, n: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        if n == 0 {
+            return Ok(());
+        }
+        let superblock = self.mounted.superblock();
+        let end = start + n;
+        if end > superblock.size || superblock.bblock(start) != superblock.bblock(end - 1) {
+            return Err(());
+        }
+        let mut bp = hal()
+            .disk()
+            .read(self.mounted.dev, superblock.bblock(start), ctx);
+        let data = &mut bp.deref_inner_mut().data;
+        let is_free = |b: u32| data[b as usize % BPB / 8] & (1 << (b % 8)) == 0;
+        if !(start..end).all(is_free) {
+            bp.free(ctx);
+            return Err(());
+        }
+        for b in start..end {
+            data[b as usize % BPB / 8] |= 1 << (b % 8);
+        }
+        self.write(bp, ctx);
+        Ok(())
     }
 
     /// Called at the end of each FS system call.
     /// Commits if this was the last outstanding operation.
     pub fn end(self, ctx: &KernelCtx<'_, '_>) {
-        self.fs.log().end_op(ctx);
+        ctx.proc().deref_data().guards.end_tx();
+        self.mounted.log().end_op(self.logged.get(), ctx);
         mem::forget(self);
     }
 }
@@ -0,0 +1,209 @@
+//! Watches of file system events.
+//!
+//! `fswatch` watches a directory or a file, and returns a descriptor that reads the events on it:
+//! an entry added to or removed from the directory, or a write to the file. The file system
+//! reports them from `InodeGuard::write_dirent`, through which `dirlink`, `unlink` and `rename`
+//! change a directory, and from the writes of user data. Each watch queues up to `NWATCHEVENT`
+//! events until they are read, and drops the rest, which the next read reports by an
+//! `FSW_OVERFLOW` event.
+
+use core::{
+    cmp, mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+use rv6_abi::{FsEvent, FswMask, DIRSIZ};
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::UVAddr,
+    error::{Errno, KernelError},
+    kernel::KernelRef,
+    lock::SleepableLock,
+    param::{NWATCH, NWATCHEVENT},
+    proc::KernelCtx,
+};
+
+/// The watches of file system events.
+pub struct FsWatches {
+    /// Number of watches in use, so that the file system skips the table if nothing is watched.
+    active: AtomicUsize,
+    watches: SleepableLock<[Watch; NWATCH]>,
+}
+
+/// A watch of the inode `inum` of the device `dev`, or a free slot if `mask` is empty.
+struct Watch {
+    dev: u32,
+    inum: u32,
+    mask: FswMask,
+    /// The queued events, `len` of them from `head`.
+    events: [FsEvent; NWATCHEVENT],
+    head: usize,
+    len: usize,
+    /// Whether an event was dropped since the queue was full.
+    overflow: bool,
+}
+
+impl Watch {
+    const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            mask: FswMask::empty(),
+            events: [FsEvent {
+                mask: 0,
+                inum: 0,
+                name: [0; DIRSIZ],
+            }; NWATCHEVENT],
+            head: 0,
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    fn push(&mut self, event: FsEvent) {
+        if self.len == NWATCHEVENT {
+            self.overflow = true;
+        } else {
+            self.events[(self.head + self.len) % NWATCHEVENT] = event;
+            self.len += 1;
+        }
+    }
+
+    /// Moves the queued events to `dst`, the overflow first if any, as many as fit.
+    /// Returns the number of events moved.
+    fn pop(&mut self, dst: &mut [FsEvent]) -> usize {
+        let mut count = 0;
+        if self.overflow && !dst.is_empty() {
+            dst[0] = FsEvent {
+                mask: FswMask::FSW_OVERFLOW.bits(),
+                ..Default::default()
+            };
+            self.overflow = false;
+            count = 1;
+        }
+        while count < dst.len() && self.len > 0 {
+            dst[count] = self.events[self.head];
+            self.head = (self.head + 1) % NWATCHEVENT;
+            self.len -= 1;
+            count += 1;
+        }
+        count
+    }
+}
+
+impl FsWatches {
+    pub const fn new() -> Self {
+        Self {
+            active: AtomicUsize::new(0),
+            watches: SleepableLock::new("fswatch", array![_ => Watch::new(); NWATCH]),
+        }
+    }
+
+    /// Returns true if any inode is watched.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed) > 0
+    }
+
+    /// Watches the events in `mask` on the inode `inum` of the device `dev`.
+    /// Returns Ok(slot of the watch) on success, Err(()) if `mask` is empty or every slot is in
+    /// use.
+    pub fn add(&self, dev: u32, inum: u32, mask: FswMask) -> Result<usize, ()> {
+        let mask = mask - FswMask::FSW_OVERFLOW;
+        if mask.is_empty() {
+            return Err(());
+        }
+        let mut watches = self.watches.lock();
+        let slot = watches.iter().position(|w| w.mask.is_empty()).ok_or(())?;
+        watches[slot] = Watch {
+            dev,
+            inum,
+            mask,
+            ..Watch::new()
+        };
+        let _ = self.active.fetch_add(1, Ordering::Relaxed);
+        Ok(slot)
+    }
+
+    /// Stops the watch in `slot`, dropping its queued events.
+    pub fn remove(&self, slot: usize) {
+        self.watches.lock()[slot].mask = FswMask::empty();
+        let _ = self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Reports `event` on the inode `inum` of the device `dev` with the entry `child` named `name`,
+    /// or with the inode itself and an empty name for `FSW_WRITE`.
+    pub fn notify(
+        &self,
+        dev: u32,
+        inum: u32,
+        event: FswMask,
+        child: u32,
+        name: &[u8],
+        kernel: KernelRef<'_, '_>,
+    ) {
+        if !self.is_active() {
+            return;
+        }
+        let mut ev = FsEvent {
+            mask: event.bits(),
+            inum: child as u16,
+            ..Default::default()
+        };
+        ev.name[..name.len()].copy_from_slice(name);
+
+        let mut watches = self.watches.lock();
+        let mut queued = false;
+        for w in watches
+            .iter_mut()
+            .filter(|w| w.dev == dev && w.inum == inum && w.mask.intersects(event))
+        {
+            w.push(ev);
+            queued = true;
+        }
+        if queued {
+            watches.wakeup(kernel);
+        }
+    }
+
+    /// Reads the queued events of the watch in `slot` into user virtual address `addr`, as many
+    /// whole events as fit in `n` bytes. Waits for an event if none is queued, unless `nonblock`.
+    /// Returns Ok(number of bytes read) on success, Err(error) on error.
+    pub fn read(
+        &self,
+        slot: usize,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let max = cmp::min(n / mem::size_of::<FsEvent>(), NWATCHEVENT);
+        if max == 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut events = [FsEvent::default(); NWATCHEVENT];
+        let mut watches = self.watches.lock();
+        let count = loop {
+            let count = watches[slot].pop(&mut events[..max]);
+            if count > 0 {
+                break count;
+            }
+            if nonblock {
+                return Err(Errno::EAGAIN.into());
+            }
+            if ctx.proc().killed() {
+                return Err(Errno::EINTR.into());
+            }
+            watches.sleep(ctx);
+        };
+        drop(watches);
+
+        let bytes = events[..count].as_bytes();
+        ctx.proc()
+            .memory()
+            .copy_out_bytes(addr, bytes)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(bytes.len())
+    }
+}
@@ -0,0 +1,182 @@
+//! Fast user-space mutexes.
+//!
+//! A futex is an aligned 32-bit word in user memory. User programs manipulate it with atomic
+//! instructions, and ask the kernel only to sleep until the word changes (`FUTEX_WAIT`) or to wake
+//! up the sleepers (`FUTEX_WAKE`). Futexes are identified by their physical addresses, so a futex
+//! in memory shared by several address spaces works as well.
+//!
+//! A thread can register a robust list, a user-space list of the futexes it holds. When the thread
+//! exits, the kernel marks each of them `FUTEX_OWNER_DIED` and wakes up its waiters, so that they
+//! do not wait forever for a dead owner.
+
+use core::{
+    mem,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use rv6_abi::{RobustListHead, FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS};
+
+use crate::{
+    arch::addr::{Addr, PAddr, UVAddr},
+    error::{Errno, KernelError},
+    exit_hook, ok_or,
+    param::{NFUTEX, ROBUST_LIST_LIMIT},
+    proc::{KernelCtx, WaitQueue},
+};
+
+/// Wait queues of futexes, keyed by their physical addresses.
+pub struct Futexes {
+    queue: WaitQueue<NFUTEX>,
+}
+
+impl Futexes {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `Futexes::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            queue: unsafe { WaitQueue::new("futex") },
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
+        unsafe { Pin::new_unchecked(&mut self.get_unchecked_mut().queue) }.init();
+    }
+
+    fn queue(self: Pin<&Self>) -> Pin<&WaitQueue<NFUTEX>> {
+        unsafe { Pin::new_unchecked(&self.get_ref().queue) }
+    }
+}
+
+/// Returns the futex word at `pa`.
+///
+/// # Safety
+///
+/// `pa` must be an aligned physical address of a user page.
+unsafe fn futex_word<'a>(pa: PAddr) -> &'a AtomicU32 {
+    // SAFETY: the kernel maps physical memory directly, and `pa` is aligned.
+    unsafe { &*(pa.into_usize() as *const AtomicU32) }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Returns the physical address of the futex word at `uaddr`.
+    fn futex_pa(&mut self, uaddr: UVAddr) -> Result<PAddr, KernelError> {
+        if uaddr.into_usize() % mem::size_of::<u32>() != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        Ok(self.proc().memory().translate(uaddr).ok_or(Errno::EFAULT)?)
+    }
+
+    /// Sleeps if the futex word at `uaddr` still holds `val`.
+    /// Returns Err(EAGAIN) without sleeping if it does not.
+    pub fn futex_wait(&mut self, uaddr: UVAddr, val: u32) -> Result<(), KernelError> {
+        let pa = self.futex_pa(uaddr)?;
+        self.kernel().futexes().queue().wait(
+            pa.into_usize(),
+            || {
+                // Read the word with the memory locked, so that no other thread frees its page
+                // meanwhile. A word that has moved to another page counts as changed.
+                let memory = self.proc().memory();
+                if memory.translate(uaddr).map(|pa| pa.into_usize()) != Some(pa.into_usize())
+                    // SAFETY: `pa` is an aligned address of a user page, which is mapped.
+                    || unsafe { futex_word(pa) }.load(Ordering::SeqCst) != val
+                {
+                    return Err(Errno::EAGAIN.into());
+                }
+                drop(memory);
+                if self.proc().killed() {
+                    return Err(Errno::EINTR.into());
+                }
+                Ok(())
+            },
+            self,
+        )
+    }
+
+    /// Wakes up at most `n` threads sleeping on the futex at `uaddr`.
+    /// Returns the number of threads woken up.
+    pub fn futex_wake(&mut self, uaddr: UVAddr, n: usize) -> Result<usize, KernelError> {
+        let pa = self.futex_pa(uaddr)?;
+        Ok(self
+            .kernel()
+            .futexes()
+            .queue()
+            .wake(pa.into_usize(), n, self.kernel()))
+    }
+
+    /// Registers the robust list of the current thread.
+    pub fn set_robust_list(&mut self, head: UVAddr) {
+        self.proc_mut().deref_mut_data().robust_list = head;
+    }
+
+    /// Releases the futexes on the robust list of the current thread, which is exiting, so that
+    /// their waiters do not hang. A malformed list is walked only as far as it is readable.
+    fn exit_robust_list(&mut self) {
+        let head = mem::replace(
+            &mut self.proc_mut().deref_mut_data().robust_list,
+            UVAddr::from(0),
+        );
+        if head.into_usize() == 0 {
+            return;
+        }
+
+        let mut list = RobustListHead::default();
+        // SAFETY: RobustListHead does not have any internal structure.
+        if unsafe { self.proc().memory().copy_in(&mut list, head) }.is_err() {
+            return;
+        }
+        let offset = list.futex_offset as usize;
+
+        // Walk the list until it comes back to the head. The limit protects against cycles.
+        let mut entry = list.next;
+        for _ in 0..ROBUST_LIST_LIMIT {
+            if entry == head.into_usize() {
+                break;
+            }
+            let mut next = 0usize;
+            // SAFETY: usize does not have any internal structure.
+            if unsafe { self.proc().memory().copy_in(&mut next, entry.into()) }.is_err() {
+                break;
+            }
+            // The pending entry is handled below.
+            if entry != list.list_op_pending {
+                self.futex_owner_died(entry.wrapping_add(offset).into());
+            }
+            entry = next;
+        }
+
+        // The thread might have died while acquiring or releasing this one.
+        if list.list_op_pending != 0 {
+            self.futex_owner_died(list.list_op_pending.wrapping_add(offset).into());
+        }
+    }
+
+    /// Marks the futex at `uaddr` `FUTEX_OWNER_DIED` if the current thread holds it, and wakes up a
+    /// waiter if there is one.
+    fn futex_owner_died(&mut self, uaddr: UVAddr) {
+        let pa = ok_or!(self.futex_pa(uaddr), return);
+        let tid = self.proc().pid() as u32;
+        // SAFETY: `pa` is an aligned address of a user page.
+        let word = unsafe { futex_word(pa) };
+        let old = ok_or!(
+            word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |old| {
+                if old & FUTEX_TID_MASK == tid {
+                    Some((old & FUTEX_WAITERS) | FUTEX_OWNER_DIED)
+                } else {
+                    None
+                }
+            }),
+            return
+        );
+        if old & FUTEX_WAITERS != 0 {
+            let _ = self.futex_wake(uaddr, 1);
+        }
+    }
+}
+
+fn robust_list_exit_hook(ctx: &mut KernelCtx<'_, '_>) {
+    ctx.exit_robust_list();
+}
+
+exit_hook!(robust_list_exit_hook);
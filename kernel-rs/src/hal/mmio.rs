@@ -0,0 +1,95 @@
+//! Typed access to memory-mapped device registers.
+//!
+//! A driver describes the registers of a device as a `#[repr(C)]` register block, whose fields are
+//! `ReadOnly`, `WriteOnly`, or `ReadWrite` registers at their offsets, with `Reserved` gaps in
+//! between, and reaches the block through an `MmioRegion`. Creating the region is the only unsafe
+//! step: the registers can then be accessed safely, and only in the ways the block declares.
+//!
+//! Writing a register may still make the device access memory, e.g., a virtio queue notification.
+//! The driver keeps such writes behind unsafe methods of its own.
+
+use core::{cell::UnsafeCell, marker::PhantomData, ops::Deref, ptr};
+
+/// A register that can only be read.
+#[repr(transparent)]
+pub struct ReadOnly<T: Copy> {
+    value: UnsafeCell<T>,
+}
+
+/// A register that can only be written.
+#[repr(transparent)]
+pub struct WriteOnly<T: Copy> {
+    value: UnsafeCell<T>,
+}
+
+/// A register that can be read and written.
+#[repr(transparent)]
+pub struct ReadWrite<T: Copy> {
+    value: UnsafeCell<T>,
+}
+
+/// A gap of `N` bytes between registers, which cannot be accessed.
+#[repr(transparent)]
+pub struct Reserved<const N: usize> {
+    _bytes: [u8; N],
+}
+
+// SAFETY: volatile concurrent accesses are safe.
+// (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
+unsafe impl<T: Copy + Send> Sync for ReadOnly<T> {}
+unsafe impl<T: Copy + Send> Sync for WriteOnly<T> {}
+unsafe impl<T: Copy + Send> Sync for ReadWrite<T> {}
+
+impl<T: Copy> ReadOnly<T> {
+    pub fn get(&self) -> T {
+        // SAFETY: the register is valid by the safety condition of `MmioRegion::new`.
+        unsafe { ptr::read_volatile(self.value.get()) }
+    }
+}
+
+impl<T: Copy> WriteOnly<T> {
+    pub fn set(&self, value: T) {
+        // SAFETY: the register is valid by the safety condition of `MmioRegion::new`.
+        unsafe { ptr::write_volatile(self.value.get(), value) }
+    }
+}
+
+impl<T: Copy> ReadWrite<T> {
+    pub fn get(&self) -> T {
+        // SAFETY: the register is valid by the safety condition of `MmioRegion::new`.
+        unsafe { ptr::read_volatile(self.value.get()) }
+    }
+
+    pub fn set(&self, value: T) {
+        // SAFETY: the register is valid by the safety condition of `MmioRegion::new`.
+        unsafe { ptr::write_volatile(self.value.get(), value) }
+    }
+}
+
+/// The registers of a device, laid out as `T`, which dereferences to them.
+pub struct MmioRegion<T> {
+    base: usize,
+    _marker: PhantomData<&'static T>,
+}
+
+impl<T> MmioRegion<T> {
+    /// # Safety
+    ///
+    /// `base` must be the address of the registers of a device laid out as `T`, aligned for `T`,
+    /// and they must stay accessible at `base` while the region is used.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            base,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for MmioRegion<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: `base` is the address of the registers by the safety condition of `new`.
+        unsafe { &*(self.base as *const T) }
+    }
+}
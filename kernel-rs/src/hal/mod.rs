@@ -3,14 +3,18 @@ use core::pin::Pin;
 use pin_project::pin_project;
 
 use crate::{
-    arch::memlayout::UART0,
+    arch::memlayout::{RTC, UART0},
     console::{Console, Printer},
     cpu::Cpus,
     kalloc::Kmem,
     lock::{SleepableLock, SpinLock},
+    rtc::Rtc,
+    trace::Tracer,
     virtio::VirtioDisk,
 };
 
+pub mod mmio;
+
 static mut HAL: Hal = unsafe { Hal::new() };
 
 pub fn hal<'s>() -> Pin<&'s Hal> {
@@ -47,8 +51,14 @@ pub struct Hal {
 
     cpus: Cpus,
 
+    /// The real-time clock.
+    rtc: Rtc,
+
     #[pin]
     disk: SleepableLock<VirtioDisk>,
+
+    /// Lives here rather than in `Kernel` so that the spinlocks can record their contention.
+    tracer: Tracer,
 }
 
 impl Hal {
@@ -61,7 +71,9 @@ impl Hal {
             printer: Printer::new(),
             kmem: SpinLock::new("KMEM", unsafe { Kmem::new() }),
             cpus: Cpus::new(),
+            rtc: unsafe { Rtc::new(RTC) },
             disk: SleepableLock::new("DISK", unsafe { VirtioDisk::new() }),
+            tracer: Tracer::new(),
         }
     }
 
@@ -79,7 +91,10 @@ impl Hal {
         // Physical page allocator.
         unsafe { this.kmem.get_pin_mut().init() };
 
-        this.disk.get_pin_mut().as_ref().init();
+        // Real-time clock, read before paging is on, as the kernel does not map it.
+        this.rtc.init();
+
+        this.disk.get_pin_mut().init();
     }
 
     pub fn console(&self) -> &Console {
@@ -99,8 +114,16 @@ impl Hal {
         &self.cpus
     }
 
+    pub fn rtc(&self) -> &Rtc {
+        &self.rtc
+    }
+
     pub fn disk(self: Pin<&Self>) -> Pin<&SleepableLock<VirtioDisk>> {
         // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
         unsafe { Pin::new_unchecked(&self.get_ref().disk) }
     }
+
+    pub fn tracer(&self) -> &Tracer {
+        &self.tracer
+    }
 }
@@ -0,0 +1,84 @@
+//! Boot-time registration of initialization functions.
+//!
+//! `init_call!(phase, func)` places a descriptor of `func` in the `.initcall` linker section.
+//! While booting, `Kernel::init` calls `run_initcalls` for each `InitPhase` in order, which runs
+//! every function registered for that phase. Hence, a driver can hook itself into the boot
+//! sequence without touching `Kernel::init`.
+//!
+//! Functions registered for the same phase run in link order, so they must not depend on each
+//! other. If a function depends on another, register it for a later phase.
+
+use core::{mem, pin::Pin, slice};
+
+use crate::kernel::Kernel;
+
+extern "C" {
+    // kernel.ld
+    static mut __initcall_start: [u8; 0];
+    static mut __initcall_end: [u8; 0];
+}
+
+/// Boot phases, in the order they run.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InitPhase {
+    /// Right after the kernel starts booting, before paging is turned on.
+    Arch,
+    /// After the kernel page table and the process system are ready.
+    /// Device drivers register themselves here.
+    Device,
+}
+
+/// A function registered by `init_call!`.
+#[repr(C)]
+pub struct InitCall {
+    phase: InitPhase,
+    func: unsafe fn(Pin<&mut Kernel>),
+}
+
+impl InitCall {
+    pub const fn new(phase: InitPhase, func: unsafe fn(Pin<&mut Kernel>)) -> Self {
+        Self { phase, func }
+    }
+}
+
+/// Registers `func: unsafe fn(Pin<&mut Kernel>)` to be called at the given `InitPhase`.
+///
+/// # Safety
+///
+/// `func` is called only once by the hart 0, while no other reference to the `Kernel` is alive.
+#[macro_export]
+macro_rules! init_call {
+    ($phase:expr, $func:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".initcall"]
+            static INIT_CALL: $crate::initcall::InitCall =
+                $crate::initcall::InitCall::new($phase, $func);
+        };
+    };
+}
+
+/// Returns the functions registered by `init_call!`.
+fn initcalls() -> &'static [InitCall] {
+    // SAFETY: the linker places only `InitCall`s between `__initcall_start` and `__initcall_end`,
+    // aligned properly.
+    unsafe {
+        let start = __initcall_start.as_ptr();
+        let end = __initcall_end.as_ptr();
+        let len = (end as usize - start as usize) / mem::size_of::<InitCall>();
+        slice::from_raw_parts(start as *const InitCall, len)
+    }
+}
+
+/// Runs every function registered for `phase`.
+///
+/// # Safety
+///
+/// It must be called only once for each phase, by the hart 0.
+pub unsafe fn run_initcalls(phase: InitPhase, mut kernel: Pin<&mut Kernel>) {
+    for call in initcalls().iter().filter(|call| call.phase == phase) {
+        // SAFETY: `kernel` is the only reference to the `Kernel` while booting.
+        unsafe { (call.func)(kernel.as_mut()) };
+    }
+}
@@ -0,0 +1,61 @@
+//! I/O statistics of block devices and processes.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub use rv6_abi::IoStat;
+
+/// I/O counters that can be charged without holding any lock.
+pub struct IoCounters {
+    read_ops: AtomicUsize,
+    write_ops: AtomicUsize,
+    read_bytes: AtomicUsize,
+    write_bytes: AtomicUsize,
+    wait_time: AtomicUsize,
+}
+
+impl IoCounters {
+    pub const fn new() -> Self {
+        Self {
+            read_ops: AtomicUsize::new(0),
+            write_ops: AtomicUsize::new(0),
+            read_bytes: AtomicUsize::new(0),
+            write_bytes: AtomicUsize::new(0),
+            wait_time: AtomicUsize::new(0),
+        }
+    }
+
+    /// Charges a read of `bytes` bytes.
+    pub fn charge_read(&self, bytes: usize) {
+        let _ = self.read_ops.fetch_add(1, Ordering::Relaxed);
+        let _ = self.read_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Charges a write of `bytes` bytes.
+    pub fn charge_write(&self, bytes: usize) {
+        let _ = self.write_ops.fetch_add(1, Ordering::Relaxed);
+        let _ = self.write_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Charges `time` cycles spent waiting for the disk.
+    pub fn charge_wait(&self, time: usize) {
+        let _ = self.wait_time.fetch_add(time, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.read_ops.store(0, Ordering::Relaxed);
+        self.write_ops.store(0, Ordering::Relaxed);
+        self.read_bytes.store(0, Ordering::Relaxed);
+        self.write_bytes.store(0, Ordering::Relaxed);
+        self.wait_time.store(0, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> IoStat {
+        IoStat {
+            read_ops: self.read_ops.load(Ordering::Relaxed),
+            write_ops: self.write_ops.load(Ordering::Relaxed),
+            read_bytes: self.read_bytes.load(Ordering::Relaxed),
+            write_bytes: self.write_bytes.load(Ordering::Relaxed),
+            wait_time: self.wait_time.load(Ordering::Relaxed),
+        }
+    }
+}
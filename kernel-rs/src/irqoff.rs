@@ -0,0 +1,85 @@
+//! Auditing of the interrupts-disabled windows, enabled by the `irqoff` feature.
+//!
+//! Each CPU times the window from the outermost `push_off` that disables interrupts to the
+//! `pop_off` that enables them again, and keeps the longest one along with its site: the name of
+//! the spin lock that was acquired, or the caller of `push_off`. While interrupts are disabled, the
+//! timer and the devices wait, so these windows bound the latency of the whole system. The `irqoff`
+//! system call copies the records out.
+//!
+//! A window costs reading the timer twice with the feature, and nothing without it.
+
+use core::{cmp, panic::Location};
+
+use array_macro::array;
+use rv6_abi::{IrqoffRecord, IRQOFF_SITE_LEN};
+use spin::Mutex;
+
+use crate::param::NCPU;
+
+/// What disabled interrupts.
+#[derive(Clone, Copy)]
+pub enum IrqOffSite {
+    /// Acquiring the spin lock with the name.
+    Lock(&'static str),
+
+    /// A direct call to `push_off`.
+    Caller(&'static Location<'static>),
+}
+
+/// The longest window of a CPU.
+#[derive(Clone, Copy)]
+struct Window {
+    cycles: u64,
+    site: Option<IrqOffSite>,
+}
+
+pub struct IrqOffRecords {
+    /// Indexed by CPU. `spin::Mutex` does not disable interrupts, so that recording a window does
+    /// not open another one.
+    windows: [Mutex<Window>; NCPU],
+}
+
+impl IrqOffRecords {
+    pub const fn new() -> Self {
+        Self {
+            windows: array![_ => Mutex::new(Window { cycles: 0, site: None }); NCPU],
+        }
+    }
+
+    /// Records a window of `cycles` opened at `site` on `cpu`, if it is the longest one so far.
+    /// Called by `cpu` with interrupts disabled. Skips the window if the record is being read, as
+    /// spinning here could wait for a reader interrupted on this CPU.
+    pub fn record(&self, cpu: usize, cycles: u64, site: IrqOffSite) {
+        if let Some(mut window) = self.windows[cpu].try_lock() {
+            if cycles > window.cycles {
+                *window = Window {
+                    cycles,
+                    site: Some(site),
+                };
+            }
+        }
+    }
+
+    /// Returns the record of the longest window of `cpu`, with the site truncated to fit in.
+    pub fn get(&self, cpu: usize) -> IrqoffRecord {
+        let window = *self.windows[cpu].lock();
+        let mut record = IrqoffRecord {
+            cycles: window.cycles,
+            line: 0,
+            site: [0; IRQOFF_SITE_LEN],
+        };
+        let site = match window.site {
+            None => "",
+            Some(IrqOffSite::Lock(name)) => name,
+            Some(IrqOffSite::Caller(location)) => {
+                record.line = location.line() as u64;
+                location.file()
+            }
+        };
+        // Keep the end of a long path, and leave room for the terminating NUL.
+        let start = site.len() - cmp::min(site.len(), IRQOFF_SITE_LEN - 1);
+        let site = &site.as_bytes()[start..];
+        record.site[..site.len()].copy_from_slice(site);
+        record
+    }
+}
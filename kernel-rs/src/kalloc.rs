@@ -1,18 +1,108 @@
 //! Physical memory allocator, for user processes,
 //! kernel stacks, page-table pages,
 //! and pipe buffers. Allocates whole 4096-byte pages.
-use core::{mem, pin::Pin};
+//!
+//! Every allocated page is tagged with its owner, the subsystem that allocated it, and the pages
+//! of each owner are counted, so that running out of pages can be attributed to the subsystem
+//! that holds them, e.g., one leaking pipe buffers. An owner whose pages are bounded by a table,
+//! e.g., of the open files, has a budget of as many pages, and its allocations beyond the budget
+//! fail, so that a leak of the owner cannot starve the others. The `meminfo` system call reports
+//! the counts and the budgets.
+//!
+//! A page may be shared, e.g., when the page cache lends a page to a process that reads a file.
+//! Each `share` takes another reference to the page, which `free` drops, and the page returns to
+//! the free list only when the last reference is freed. The holders of a shared page must not
+//! write to it.
+use core::{
+    mem,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use array_macro::array;
 use pin_project::pin_project;
+use rv6_abi::{
+    NPAGEOWNER, PAGE_OWNER_EXEC, PAGE_OWNER_KSTACK, PAGE_OWNER_PAGECACHE, PAGE_OWNER_PAGETABLE,
+    PAGE_OWNER_PIPE, PAGE_OWNER_TRAPFRAME, PAGE_OWNER_USER,
+};
 
 use crate::{
-    arch::addr::{pgrounddown, pgroundup, PGSIZE},
-    arch::memlayout::PHYSTOP,
+    arch::addr::{pgrounddown, pgroundup, Addr, PAddr, PGSIZE},
+    arch::memlayout::{KERNBASE, PHYSTOP},
+    fcount,
     lock::SpinLock,
     page::Page,
+    param::{NFILE, NPAGECACHE, NPROC},
     util::intrusive_list::{List, ListEntry, ListNode},
 };
 
+/// Number of the physical pages, including those of the kernel image.
+const NPAGE: usize = (PHYSTOP - KERNBASE) / PGSIZE;
+
+/// The subsystem that has allocated a page.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageOwner {
+    /// User memory, including the pages of the programs and their arguments.
+    User,
+
+    /// Page-table pages, of both the kernel and the users.
+    PageTable,
+
+    /// Kernel stacks of the processes.
+    KernelStack,
+
+    /// Trap frames of the processes.
+    TrapFrame,
+
+    /// Pipe buffers.
+    Pipe,
+
+    /// Pages of the page cache, i.e., of the files mapped with MAP_SHARED, and of those read().
+    PageCache,
+
+    /// Arguments that `exec` copies in from the user.
+    Exec,
+}
+
+impl PageOwner {
+    pub const ALL: [PageOwner; NPAGEOWNER] = [
+        PageOwner::User,
+        PageOwner::PageTable,
+        PageOwner::KernelStack,
+        PageOwner::TrapFrame,
+        PageOwner::Pipe,
+        PageOwner::PageCache,
+        PageOwner::Exec,
+    ];
+
+    /// Returns the index of the owner in `Meminfo`.
+    pub fn index(self) -> usize {
+        match self {
+            PageOwner::User => PAGE_OWNER_USER,
+            PageOwner::PageTable => PAGE_OWNER_PAGETABLE,
+            PageOwner::KernelStack => PAGE_OWNER_KSTACK,
+            PageOwner::TrapFrame => PAGE_OWNER_TRAPFRAME,
+            PageOwner::Pipe => PAGE_OWNER_PIPE,
+            PageOwner::PageCache => PAGE_OWNER_PAGECACHE,
+            PageOwner::Exec => PAGE_OWNER_EXEC,
+        }
+    }
+
+    /// Returns the number of pages the owner may hold, or None if it is not bounded. User pages
+    /// are bounded by the resource groups instead.
+    pub fn budget(self) -> Option<usize> {
+        match self {
+            PageOwner::KernelStack => Some(NPROC),
+            // A pipe holds a page, and at least one open file.
+            PageOwner::Pipe => Some(NFILE),
+            // Each process may read a page outside of the cache, which it drops if another process
+            // has cached the page meanwhile.
+            PageOwner::PageCache => Some(NPAGECACHE + NPROC),
+            _ => None,
+        }
+    }
+}
+
 extern "C" {
     // first address after kernel.
     // defined by kernel.ld.
@@ -65,6 +155,19 @@ unsafe impl ListNode for Run {
 pub struct Kmem {
     #[pin]
     runs: List<Run>,
+
+    /// Number of pages in `runs`. Atomic so that it can be read without the lock.
+    free_pages: AtomicUsize,
+
+    /// The owner of each allocated page, indexed by the page number from `KERNBASE`.
+    owners: [Option<PageOwner>; NPAGE],
+
+    /// Number of the pages of each owner, indexed by `PageOwner::index`. Atomic so that they can
+    /// be read without the lock.
+    owned: [AtomicUsize; NPAGEOWNER],
+
+    /// Number of the references to each allocated page taken by `share`, indexed as `owners`.
+    shares: [u16; NPAGE],
 }
 
 impl Kmem {
@@ -74,6 +177,10 @@ impl Kmem {
     pub const unsafe fn new() -> Self {
         Self {
             runs: unsafe { List::new() },
+            free_pages: AtomicUsize::new(0),
+            owners: [None; NPAGE],
+            owned: array![_ => AtomicUsize::new(0); NPAGEOWNER],
+            shares: [0; NPAGE],
         }
     }
 
@@ -95,11 +202,62 @@ impl Kmem {
             // * end <= pa < PHYSTOP
             // * the safety condition of this method guarantees that the
             //   created page does not overlap with existing pages
-            self.as_ref().free(unsafe { Page::from_usize(pa) });
+            self.as_ref().push(unsafe { Page::from_usize(pa) });
+        }
+    }
+
+    /// Frees an allocated page, and uncounts it from its owner. If the page is shared, drops a
+    /// reference to it instead.
+    pub fn free(mut self: Pin<&mut Self>, page: Page) {
+        fcount!(Kmem::free);
+        let this = self.as_mut().project();
+        let shares = &mut this.shares[page_index(&page)];
+        if *shares > 0 {
+            *shares -= 1;
+            mem::forget(page);
+            return;
         }
+        let owner = this.owners[page_index(&page)]
+            .take()
+            .expect("Kmem::free: page not allocated");
+        let _ = this.owned[owner.index()].fetch_sub(1, Ordering::Relaxed);
+        self.as_ref().push(page);
     }
 
-    pub fn free(self: Pin<&Self>, mut page: Page) {
+    /// Allocates a page for owner.
+    /// Returns None if there are no free pages, or the owner has used up its budget.
+    pub fn alloc(self: Pin<&mut Self>, owner: PageOwner) -> Option<Page> {
+        fcount!(Kmem::alloc);
+        let owned = &self.owned[owner.index()];
+        if matches!(owner.budget(), Some(budget) if owned.load(Ordering::Relaxed) >= budget) {
+            return None;
+        }
+        let run = self.as_ref().runs().pop_front()?;
+        let _ = self.free_pages.fetch_sub(1, Ordering::Relaxed);
+        // SAFETY: the invariant of `Kmem`.
+        let mut page = unsafe { Page::from_usize(run as _) };
+        // fill with junk
+        page.write_bytes(5);
+        let _ = owned.fetch_add(1, Ordering::Relaxed);
+        self.project().owners[page_index(&page)] = Some(owner);
+        Some(page)
+    }
+
+    /// Takes another reference to the allocated page at pa.
+    /// Returns Ok(()) on success, Err(()) if the page has too many references.
+    pub fn share(self: Pin<&mut Self>, pa: PAddr) -> Result<(), ()> {
+        let index = (pa.into_usize() - KERNBASE) / PGSIZE;
+        assert!(
+            self.owners[index].is_some(),
+            "Kmem::share: page not allocated"
+        );
+        let shares = &mut self.project().shares[index];
+        *shares = shares.checked_add(1).ok_or(())?;
+        Ok(())
+    }
+
+    /// Returns a page to the free list.
+    fn push(self: Pin<&Self>, mut page: Page) {
         // Fill with junk to catch dangling refs.
         page.write_bytes(1);
 
@@ -109,31 +267,50 @@ impl Kmem {
         let mut run = unsafe { Pin::new_unchecked(run) };
         run.as_mut().init();
         self.runs().push_front(run.as_ref());
+        let _ = self.free_pages.fetch_add(1, Ordering::Relaxed);
 
         // Since the page has returned to the list, forget the page.
         mem::forget(page);
     }
 
-    pub fn alloc(self: Pin<&Self>) -> Option<Page> {
-        let run = self.runs().pop_front()?;
-        // SAFETY: the invariant of `Kmem`.
-        let mut page = unsafe { Page::from_usize(run as _) };
-        // fill with junk
-        page.write_bytes(5);
-        Some(page)
-    }
-
     fn runs(self: Pin<&Self>) -> Pin<&List<Run>> {
         unsafe { Pin::new_unchecked(&self.get_ref().runs) }
     }
 }
 
+/// Returns the index of page in `Kmem::owners`.
+fn page_index(page: &Page) -> usize {
+    (page.addr().into_usize() - KERNBASE) / PGSIZE
+}
+
 impl SpinLock<Kmem> {
     pub fn free(self: Pin<&Self>, page: Page) {
-        self.pinned_lock().get_pin_mut().as_ref().free(page);
+        self.pinned_lock().get_pin_mut().free(page);
+    }
+
+    pub fn alloc(self: Pin<&Self>, owner: PageOwner) -> Option<Page> {
+        self.pinned_lock().get_pin_mut().alloc(owner)
+    }
+
+    pub fn share(self: Pin<&Self>, pa: PAddr) -> Result<(), ()> {
+        self.pinned_lock().get_pin_mut().share(pa)
+    }
+
+    /// Returns whether the page at pa has more than one reference.
+    pub fn is_shared(self: Pin<&Self>, pa: PAddr) -> bool {
+        self.pinned_lock().shares[(pa.into_usize() - KERNBASE) / PGSIZE] > 0
+    }
+
+    /// Returns the number of free pages. Doesn't acquire the lock, so that it can be used for
+    /// debugging a stuck machine, and the result may be stale.
+    pub fn free_pages(&self) -> usize {
+        // SAFETY: `free_pages` is only atomically accessed, so it can be read concurrently.
+        unsafe { (*self.get_mut_raw()).free_pages.load(Ordering::Relaxed) }
     }
 
-    pub fn alloc(self: Pin<&Self>) -> Option<Page> {
-        self.pinned_lock().get_pin_mut().as_ref().alloc()
+    /// Returns the number of the pages of owner, without acquiring the lock as `free_pages` does.
+    pub fn owned_pages(&self, owner: PageOwner) -> usize {
+        // SAFETY: `owned` is only atomically accessed, so it can be read concurrently.
+        unsafe { (*self.get_mut_raw()).owned[owner.index()].load(Ordering::Relaxed) }
     }
 }
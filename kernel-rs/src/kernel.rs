@@ -1,34 +1,46 @@
 use core::fmt::{self, Write};
-use core::mem::MaybeUninit;
+use core::mem::{self, MaybeUninit};
 use core::ops::Deref;
 use core::pin::Pin;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use pin_project::pin_project;
+use static_assertions::const_assert;
 
 use crate::util::strong_pin::StrongPin;
 use crate::{
-    arch::plic::{plicinit, plicinithart},
+    arch::memlayout::{KERNBASE, PHYSTOP},
+    arch::plic::plicinithart,
     bio::Bcache,
-    console::{console_read, console_write},
+    boottime::{cycles_to_ms, BootPhase, BootTimes},
     cpu::cpuid,
     file::{Devsw, FileTable},
     fs::{FileSystem, Ufs},
+    fswatch::FsWatches,
+    futex::Futexes,
     hal::{hal, hal_init},
+    initcall::{run_initcalls, InitPhase},
     kalloc::Kmem,
+    latency::SyscallLatency,
+    loadavg::LoadAvg,
     lock::{SleepableLock, SpinLock},
+    mount::Mounts,
+    pagecache::PageCache,
     param::NDEV,
-    proc::Procs,
-    trap::{trapinit, trapinithart},
+    proc::{Procs, SleepQueue},
+    random::EntropyPool,
+    softirq::Softirqs,
+    trap::{earlytrapinithart, trapinithart},
     util::{branded::Branded, spin_loop},
     vm::KernelMemory,
 };
 
-const CONSOLE_IN_DEVSW: usize = 1;
-
 /// The kernel.
 static mut KERNEL: Kernel = unsafe { Kernel::new() };
 
+// The kernel, which grows with the `param::Config`, leaves most of the RAM to the page allocator.
+const_assert!(mem::size_of::<Kernel>() < (PHYSTOP - KERNBASE) / 2);
+
 /// Returns a shared reference to the `KERNEL`.
 #[inline]
 fn kernel<'s>() -> StrongPin<'s, Kernel> {
@@ -69,11 +81,33 @@ unsafe fn kernel_mut_unchecked<'s>() -> Pin<&'s mut Kernel> {
 pub struct Kernel {
     panicked: AtomicBool,
 
+    /// When each phase of the boot ended.
+    boot_times: BootTimes,
+
     /// The kernel's memory manager.
     memory: MaybeUninit<KernelMemory>,
 
     ticks: SleepableLock<u32>,
 
+    /// Load averages, updated by the clock interrupt.
+    loadavg: LoadAvg,
+
+    /// Processes sleeping until a tick count.
+    sleep_queue: SleepQueue,
+
+    /// Wait queues of futexes.
+    #[pin]
+    futexes: Futexes,
+
+    /// Pending softirqs of each CPU.
+    softirqs: Softirqs,
+
+    /// Source of random numbers.
+    entropy: EntropyPool,
+
+    /// Latency histograms of system calls, recorded with the `syscall-latency` feature.
+    syscall_latency: SyscallLatency,
+
     /// Current process system.
     #[pin]
     procs: Procs,
@@ -81,6 +115,9 @@ pub struct Kernel {
     #[pin]
     bcache: Bcache,
 
+    /// Pages of the files mapped with MAP_SHARED.
+    page_cache: PageCache,
+
     devsw: [Devsw; NDEV],
 
     #[pin]
@@ -88,6 +125,12 @@ pub struct Kernel {
 
     #[pin]
     file_system: Ufs,
+
+    /// Watches of file system events.
+    fs_watches: FsWatches,
+
+    /// Where the pseudo file systems are mounted.
+    mounts: Mounts,
 }
 
 /// A branded reference to a `Kernel`.
@@ -123,6 +166,47 @@ impl<'id, 's> KernelRef<'id, 's> {
         &self.0.as_pin().get_ref().ticks
     }
 
+    /// Returns a reference to the times the phases of the boot ended at.
+    pub fn boot_times(&self) -> &'s BootTimes {
+        &self.0.as_pin().get_ref().boot_times
+    }
+
+    /// Returns a reference to the kernel's load averages.
+    pub fn loadavg(&self) -> &'s LoadAvg {
+        &self.0.as_pin().get_ref().loadavg
+    }
+
+    /// Returns a reference to the kernel's memory manager.
+    pub fn memory(&self) -> &'s KernelMemory {
+        // SAFETY: `init` has initialized it before making a `KernelRef` available to the others.
+        unsafe { self.0.as_pin().get_ref().memory.assume_init_ref() }
+    }
+
+    /// Returns a reference to the queue of processes sleeping until a tick count.
+    pub fn sleep_queue(&self) -> &'s SleepQueue {
+        &self.0.as_pin().get_ref().sleep_queue
+    }
+
+    /// Returns a reference to the kernel's futex wait queues.
+    pub fn futexes(&self) -> Pin<&'s Futexes> {
+        unsafe { Pin::new_unchecked(&self.0.as_pin().get_ref().futexes) }
+    }
+
+    /// Returns a reference to the softirqs pending on each CPU.
+    pub fn softirqs(&self) -> &'s Softirqs {
+        &self.0.as_pin().get_ref().softirqs
+    }
+
+    /// Returns a reference to the kernel's entropy pool.
+    pub fn entropy(&self) -> &'s EntropyPool {
+        &self.0.as_pin().get_ref().entropy
+    }
+
+    /// Returns a reference to the latency histograms of system calls.
+    pub fn syscall_latency(&self) -> &'s SyscallLatency {
+        &self.0.as_pin().get_ref().syscall_latency
+    }
+
     pub fn ps(&self) -> Pin<&'s Procs> {
         unsafe { Pin::new_unchecked(&self.0.as_pin().get_ref().procs) }
     }
@@ -131,6 +215,11 @@ impl<'id, 's> KernelRef<'id, 's> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().bcache) }
     }
 
+    /// Returns a reference to the page cache of shared file mappings.
+    pub fn page_cache(&self) -> &'s PageCache {
+        &self.0.as_pin().get_ref().page_cache
+    }
+
     /// Returns a reference to the kernel's `Devsw` array.
     pub fn devsw(&self) -> &'s [Devsw; NDEV] {
         &self.0.as_pin().get_ref().devsw
@@ -144,6 +233,16 @@ impl<'id, 's> KernelRef<'id, 's> {
     pub fn ftable(&self) -> StrongPin<'s, FileTable> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().ftable) }
     }
+
+    /// Returns a reference to the watches of file system events.
+    pub fn fs_watches(&self) -> &'s FsWatches {
+        &self.0.as_pin().get_ref().fs_watches
+    }
+
+    /// Returns a reference to the mount table of the pseudo file systems.
+    pub fn mounts(&self) -> &'s Mounts {
+        &self.0.as_pin().get_ref().mounts
+    }
 }
 
 impl<'id, 's> Deref for KernelRef<'id, 's> {
@@ -161,16 +260,27 @@ impl Kernel {
     const unsafe fn new() -> Self {
         Self {
             panicked: AtomicBool::new(false),
+            boot_times: BootTimes::new(),
             memory: MaybeUninit::uninit(),
             ticks: SleepableLock::new("time", 0),
+            loadavg: LoadAvg::new(),
+            sleep_queue: SleepQueue::new(),
+            futexes: unsafe { Futexes::new() },
+            softirqs: Softirqs::new(),
+            entropy: EntropyPool::new(),
+            syscall_latency: SyscallLatency::new(),
             procs: Procs::new(),
             bcache: unsafe { Bcache::new_bcache() },
+            page_cache: PageCache::new(),
             devsw: [Devsw {
+                name: "",
                 read: None,
                 write: None,
             }; NDEV],
             ftable: FileTable::new_ftable(),
             file_system: Ufs::new(),
+            fs_watches: FsWatches::new(),
+            mounts: Mounts::new(),
         }
     }
 
@@ -179,46 +289,56 @@ impl Kernel {
     /// # Safety
     ///
     /// This method should be called only once by the hart 0.
-    unsafe fn init(self: Pin<&mut Self>, allocator: Pin<&SpinLock<Kmem>>) {
+    unsafe fn init(mut self: Pin<&mut Self>, allocator: Pin<&SpinLock<Kmem>>) {
         self.as_ref().write_str("\nrv6 kernel is booting\n\n");
 
-        let mut this = self.project();
+        // Architecture-specific setup registered with `init_call!`.
+        unsafe { run_initcalls(InitPhase::Arch, self.as_mut()) };
 
-        // Connect read and write system calls to consoleread and consolewrite.
-        this.devsw[CONSOLE_IN_DEVSW] = Devsw {
-            read: Some(console_read),
-            write: Some(console_write),
-        };
+        let mut this = self.as_mut().project();
 
         // Create kernel memory manager.
         let memory = KernelMemory::new(allocator).expect("PageTable::new failed");
 
         // Turn on paging.
         unsafe { this.memory.write(memory).init_hart() };
+        let _ = this.boot_times.end(BootPhase::Kvminit);
 
         // Process system.
         this.procs.as_mut().init();
-
-        // Trap vectors.
-        trapinit();
+        let _ = this.boot_times.end(BootPhase::ProcsInit);
 
         // Install kernel trap vector.
         unsafe { trapinithart() };
 
-        // Set up interrupt controller.
-        unsafe { plicinit() };
+        // Device drivers registered with `init_call!`, e.g., the interrupt controller and the
+        // console.
+        unsafe { run_initcalls(InitPhase::Device, self.as_mut()) };
 
         // Ask PLIC for device interrupts.
         unsafe { plicinithart() };
 
+        let mut this = self.project();
+
         // Buffer cache.
         this.bcache.init();
 
+        // Futexes.
+        this.futexes.init();
+
         // First user process.
         let fs = unsafe { StrongPin::new_unchecked(this.file_system.as_ref().get_ref()) };
         this.procs.user_proc_init(fs.root(), allocator);
     }
 
+    /// Registers the read and write functions of the device with the given major number. The
+    /// device appears under `/dev` by its name at once.
+    /// Returns Err(()) if the major number is out of range.
+    pub fn register_devsw(self: Pin<&mut Self>, major: usize, devsw: Devsw) -> Result<(), ()> {
+        *self.project().devsw.get_mut(major).ok_or(())? = devsw;
+        Ok(())
+    }
+
     /// Initializes the kernel for a hart.
     ///
     /// # Safety
@@ -237,6 +357,19 @@ impl Kernel {
         unsafe { plicinithart() };
     }
 
+    /// Prints how long each phase of the boot took.
+    pub fn print_boot_times(self: Pin<&Self>) {
+        self.boot_times.for_each(|name, cycles, since_start| {
+            self.write_fmt(format_args!(
+                "boot: {:<10} {:>4}ms, {:>4}ms since start ({} cycles)\n",
+                name,
+                cycles_to_ms(cycles),
+                cycles_to_ms(since_start),
+                cycles
+            ))
+        });
+    }
+
     fn panic(self: Pin<&Self>) {
         self.panicked.store(true, Ordering::Release);
     }
@@ -275,10 +408,15 @@ fn panic_handler(info: &core::panic::PanicInfo<'_>) -> ! {
 pub unsafe fn main() -> ! {
     static INITED: AtomicBool = AtomicBool::new(false);
 
+    // Report faults until the kernel trap vector is installed, instead of wedging silently.
+    unsafe { earlytrapinithart() };
+
     if cpuid() == 0 {
+        kernel().as_pin().boot_times.start();
         unsafe {
             hal_init();
         }
+        let _ = kernel().as_pin().boot_times.end(BootPhase::HalInit);
         unsafe {
             kernel_mut_unchecked().init(hal().kmem());
         }
@@ -0,0 +1,44 @@
+//! Latency histograms of system calls.
+//!
+//! With the `syscall-latency` feature, `KernelCtx::syscall` records how many timer cycles each
+//! system call took in a log2 histogram of its number, which `sys_syscall_latency` copies out.
+//! Bucket `i` of a histogram counts the calls that took less than 2^i cycles, but not less than
+//! 2^(i-1); the last bucket also counts every longer call.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+use rv6_abi::LATENCY_BUCKETS;
+
+/// System call numbers below this have a histogram.
+const NSYSCALL: usize = 64;
+
+pub struct SyscallLatency {
+    buckets: [[AtomicUsize; LATENCY_BUCKETS]; NSYSCALL],
+}
+
+impl SyscallLatency {
+    pub const fn new() -> Self {
+        Self {
+            buckets: array![_ => array![_ => AtomicUsize::new(0); LATENCY_BUCKETS]; NSYSCALL],
+        }
+    }
+
+    /// Records that system call `num` took `cycles` cycles.
+    pub fn record(&self, num: i32, cycles: u64) {
+        if let Some(buckets) = self.buckets.get(num as usize) {
+            let bucket = (64 - cycles.leading_zeros()) as usize;
+            let _ = buckets[bucket.min(LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the histogram of system call `num`, or Err(()) if it has none.
+    pub fn histogram(&self, num: i32) -> Result<[usize; LATENCY_BUCKETS], ()> {
+        let buckets = self.buckets.get(num as usize).ok_or(())?;
+        let mut histogram = [0; LATENCY_BUCKETS];
+        for (h, b) in histogram.iter_mut().zip(buckets.iter()) {
+            *h = b.load(Ordering::Relaxed);
+        }
+        Ok(histogram)
+    }
+}
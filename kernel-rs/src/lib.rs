@@ -50,26 +50,45 @@
 #![feature(maybe_uninit_extra)]
 #![feature(raw_ref_op)]
 #![feature(try_blocks)]
-#![feature(variant_count)]
 
 mod arch;
 mod arena;
 mod bio;
+mod boottime;
 mod console;
 mod cpu;
+mod devfs;
+mod error;
 mod exec;
+mod fcount;
 mod file;
 mod fs;
+mod fswatch;
+mod futex;
 mod hal;
+mod initcall;
+mod iostat;
+mod irqoff;
 mod kalloc;
 mod kernel;
+mod latency;
+mod linux;
+mod loadavg;
 mod lock;
+mod model;
+mod mount;
 mod page;
+mod pagecache;
 mod param;
 mod pipe;
 mod proc;
+mod procfs;
+mod random;
+mod rtc;
+mod softirq;
 mod start;
 mod syscall;
+mod trace;
 mod trap;
 mod uart;
 mod util;
@@ -0,0 +1,150 @@
+//! System calls of Linux programs, e.g., those linked against a static musl.
+//!
+//! A Linux program numbers its system calls as `rv6_abi::linux` does. Most of them take the same
+//! arguments as their rv6 counterparts, and are dispatched to the same functions. The rest are
+//! those that a libc needs to start and exit a program, and to write to the console.
+//!
+//! The error numbers, the clocks, and the flags of `mmap`, `madvise` and `futex` are the same as
+//! those of Linux already, so the results need no translation either.
+
+use core::{convert::TryFrom, mem};
+
+use rv6_abi::{linux::*, Iovec, IOV_MAX, SIGKILL};
+
+use crate::{
+    error::{Errno, KernelError},
+    file::RcFile,
+    proc::KernelCtx,
+};
+
+impl KernelCtx<'_, '_> {
+    /// Dispatches the system call `num` of a Linux program.
+    pub fn dispatch_linux(&mut self, num: i32) -> Result<usize, KernelError> {
+        match num {
+            SYS_IOCTL => self.sys_ioctl(),
+            SYS_CLOSE => self.sys_close(),
+            SYS_LSEEK => self.sys_lseek(),
+            SYS_READ => self.sys_read(),
+            SYS_WRITE => self.sys_write(),
+            SYS_READV => self.sys_readv(),
+            SYS_WRITEV => self.sys_writev(),
+            SYS_PREAD64 => self.sys_pread(),
+            SYS_PWRITE64 => self.sys_pwrite(),
+            SYS_FSYNC => self.sys_fsync(),
+            SYS_FADVISE64 => self.sys_fadvise(),
+            SYS_EXIT => self.sys_exit(),
+            SYS_EXIT_GROUP => self.sys_exit_group(),
+            SYS_SET_TID_ADDRESS => self.sys_set_tid_address(),
+            SYS_FUTEX => self.sys_futex(),
+            SYS_SET_ROBUST_LIST => self.sys_set_robust_list(),
+            SYS_NANOSLEEP => self.sys_nanosleep(),
+            SYS_CLOCK_GETTIME => self.sys_clock_gettime(),
+            SYS_KILL => self.sys_kill(),
+            SYS_TGKILL => self.sys_tgkill(),
+            SYS_GETPID => self.sys_getpid(),
+            SYS_GETPPID => self.sys_getppid(),
+            SYS_GETUID => self.sys_getuid(),
+            SYS_GETEUID => self.sys_geteuid(),
+            SYS_GETGID => self.sys_getgid(),
+            SYS_SETUID => self.sys_setuid(),
+            SYS_SETGID => self.sys_setgid(),
+            SYS_GETTID => self.sys_gettid(),
+            SYS_BRK => self.sys_brk(),
+            SYS_MUNMAP => self.sys_munmap(),
+            SYS_MMAP => self.sys_mmap(),
+            SYS_MADVISE => self.sys_madvise(),
+            _ => self.unknown_syscall(num),
+        }
+    }
+
+    /// Terminate every thread of the current process; status reported to wait(). No return.
+    pub fn sys_exit_group(&mut self) -> Result<usize, KernelError> {
+        let n = self.proc().argint(0)?;
+        let tgid = self.proc().tgid();
+        // The other threads exit as soon as they return to user space.
+        let _ = self.kernel().procs().kill(tgid, SIGKILL);
+        self.kernel().procs().exit_current(n, self);
+    }
+
+    /// Set the address to clear when the current thread exits. It is ignored, since rv6 does not
+    /// wake up the joiners of a thread through it, but through the robust list.
+    /// Returns Ok(current thread’s TID).
+    pub fn sys_set_tid_address(&self) -> Result<usize, KernelError> {
+        self.sys_gettid()
+    }
+
+    /// Control the device of file descriptor fd. No device takes a request, so a libc finds that
+    /// fd is not a terminal.
+    /// Returns Err(error).
+    pub fn sys_ioctl(&self) -> Result<usize, KernelError> {
+        let (_, f) = self.proc().argfd(0)?;
+        f.free(self);
+        Err(Errno::ENOTTY.into())
+    }
+
+    /// Read into the iovcnt buffers of the iovec array at iov in order.
+    /// Returns Ok(number read) on success, Err(error) on error.
+    pub fn sys_readv(&mut self) -> Result<usize, KernelError> {
+        self.iov(false)
+    }
+
+    /// Write the iovcnt buffers of the iovec array at iov in order.
+    /// Returns Ok(number written) on success, Err(error) on error.
+    pub fn sys_writev(&mut self) -> Result<usize, KernelError> {
+        self.iov(true)
+    }
+
+    /// The vectored I/O of readv and writev, whose arguments are fd, iov, and iovcnt. It stops at
+    /// the first buffer that is not filled or written in full.
+    fn iov(&mut self, write: bool) -> Result<usize, KernelError> {
+        let iov = self.proc().argaddr(1)?;
+        let iovcnt = usize::try_from(self.proc().argint(2)?).map_err(|_| Errno::EINVAL)?;
+        if iovcnt > IOV_MAX {
+            return Err(Errno::EINVAL.into());
+        }
+        let (_, f) = self.proc().argfd(0)?;
+        let res = self.iov_file(&f, write, iov, iovcnt);
+        f.free(self);
+        res
+    }
+
+    /// The vectored I/O of `iov` on the file `f`.
+    fn iov_file(
+        &mut self,
+        f: &RcFile,
+        write: bool,
+        iov: usize,
+        iovcnt: usize,
+    ) -> Result<usize, KernelError> {
+        let mut total = 0;
+        for i in 0..iovcnt {
+            let mut v = Iovec::default();
+            let addr = iov + i * mem::size_of::<Iovec>();
+            // SAFETY: Iovec does not have any internal structure.
+            unsafe { self.proc().memory().copy_in(&mut v, addr.into()) }
+                .map_err(|_| Errno::EFAULT)?;
+            let n = i32::try_from(v.iov_len).map_err(|_| Errno::EINVAL)?;
+            if n == 0 {
+                continue;
+            }
+            let ret = if write {
+                f.write(v.iov_base.into(), n, self)
+                    .map_err(KernelError::from)
+            } else {
+                f.read(v.iov_base.into(), n, self)
+            };
+            match ret {
+                Ok(m) => {
+                    total += m;
+                    if m < v.iov_len {
+                        break;
+                    }
+                }
+                // Report what was done before the error.
+                Err(_) if total > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(total)
+    }
+}
@@ -0,0 +1,59 @@
+//! Load averages.
+//!
+//! As in Unix, the load average is an exponentially decaying average of the number of processes
+//! that are running or runnable. The clock interrupt samples that number every `LOAD_FREQ` ticks,
+//! and folds it into averages over 1, 5 and 15 minutes.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rv6_abi::FSHIFT;
+
+/// Ticks between samples, i.e., 5 seconds, since timer interrupts come about every 1/10th second.
+pub const LOAD_FREQ: u32 = 50;
+
+/// 1.0 in fixed point.
+const FIXED_1: usize = 1 << FSHIFT;
+
+/// exp(-5s/1min), exp(-5s/5min) and exp(-5s/15min) in fixed point.
+const EXP: [usize; 3] = [1884, 2014, 2037];
+
+pub struct LoadAvg {
+    /// The 1, 5 and 15 minute averages in fixed point.
+    avenrun: [AtomicUsize; 3],
+}
+
+impl LoadAvg {
+    pub const fn new() -> Self {
+        Self {
+            avenrun: [
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            ],
+        }
+    }
+
+    /// Folds a sample of `active` running or runnable processes into the averages. Called only
+    /// by the clock interrupt of CPU 0, so the updates do not race with each other.
+    pub fn update(&self, active: usize) {
+        let active = active * FIXED_1;
+        for (avg, exp) in self.avenrun.iter().zip(EXP.iter()) {
+            let load = avg.load(Ordering::Relaxed);
+            let mut new = load * exp + active * (FIXED_1 - *exp);
+            if active >= load {
+                // Round up so that a constant load converges to itself.
+                new += FIXED_1 - 1;
+            }
+            avg.store(new / FIXED_1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the 1, 5 and 15 minute averages in fixed point with `FSHIFT` fractional bits.
+    pub fn get(&self) -> [usize; 3] {
+        [
+            self.avenrun[0].load(Ordering::Relaxed),
+            self.avenrun[1].load(Ordering::Relaxed),
+            self.avenrun[2].load(Ordering::Relaxed),
+        ]
+    }
+}
@@ -22,12 +22,12 @@
 //!     you can make multiple data be protected by a single [`SpinLock`], and hence,
 //!     implement global locks. In this case, you may want to use an [`SpinLock<()>`]
 //!     if the [`SpinLock`] doesn't need to hold data.
-//! * When you want a lifetime-less smart pointer (such as [`Ref`](crate::util::rc_cell::Ref) or `std::rc::Rc`)
+//! * When you want a lifetime-less smart pointer (such as [`Ref`](crate::util::static_arc::Ref) or `std::rc::Rc`)
 //!   that points to the *inside* of a lock protected data.
-//!   * e.g. Suppose a [`Lock`] holds a [`RcCell`](crate::util::rc_cell::RcCell). Suppose you want to provide a
-//!     [`Ref`](crate::util::rc_cell::Ref) that borrows this [`RcCell`](crate::util::rc_cell::RcCell) to the outside, but still want
-//!     accesses to the [`RcCell`](crate::util::rc_cell::RcCell)'s inner data to be synchronized.
-//!     Then, instead of providing a [`Ref`](crate::util::rc_cell::Ref), you should provide a [`Ref`](crate::util::rc_cell::Ref) wrapped by a `RemoteLock`.
+//!   * e.g. Suppose a [`Lock`] holds a [`StaticArc`](crate::util::static_arc::StaticArc). Suppose you want to provide a
+//!     [`Ref`](crate::util::static_arc::Ref) that borrows this [`StaticArc`](crate::util::static_arc::StaticArc) to the outside, but still want
+//!     accesses to the [`StaticArc`](crate::util::static_arc::StaticArc)'s inner data to be synchronized.
+//!     Then, instead of providing a [`Ref`](crate::util::static_arc::Ref), you should provide a [`Ref`](crate::util::static_arc::Ref) wrapped by a `RemoteLock`.
 //!     to the outside.
 
 // Dead code is allowed in this file because not all components are used in the kernel.
@@ -42,6 +42,7 @@ mod sleepablelock;
 mod sleeplock;
 mod spinlock;
 
+pub use kernel_rs_lib::lock::RawLock;
 pub use sleepablelock::{SleepableLock, SleepableLockGuard};
 pub use sleeplock::{SleepLock, SleepLockGuard};
 pub use spinlock::{RawSpinLock, SpinLock, SpinLockGuard};
@@ -49,13 +50,6 @@ pub use spinlock::{RawSpinLock, SpinLock, SpinLockGuard};
 use crate::util::strong_pin::StrongPin;
 use crate::util::strong_pin::StrongPinMut;
 
-pub trait RawLock {
-    /// Acquires the lock.
-    fn acquire(&self);
-    /// Releases the lock.
-    fn release(&self);
-}
-
 /// Locks that provide mutual exclusion and has its own `RawLock`.
 pub struct Lock<R: RawLock, T> {
     lock: R,
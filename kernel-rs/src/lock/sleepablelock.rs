@@ -57,4 +57,8 @@ impl<T> SleepableLockGuard<'_, T> {
     pub fn wakeup(&self, kernel: KernelRef<'_, '_>) {
         self.lock.lock.waitchannel.wakeup(kernel);
     }
+
+    pub fn wakeup_one(&self, kernel: KernelRef<'_, '_>) {
+        self.lock.lock.waitchannel.wakeup_one(kernel);
+    }
 }
@@ -48,7 +48,8 @@ impl RawSleepLock {
     fn release(&self, ctx: &KernelCtx<'_, '_>) {
         let mut guard = self.inner.lock();
         *guard = -1;
-        guard.wakeup(ctx.kernel());
+        // Hand the lock over to the longest waiter. Waiters do not give up, so it will take it.
+        guard.wakeup_one(ctx.kernel());
     }
 }
 
@@ -4,9 +4,11 @@ use core::mem::MaybeUninit;
 use core::ptr;
 use core::sync::atomic::{AtomicPtr, Ordering};
 
+use rv6_abi::TRACE_LOCK_CONTEND;
+
 use super::{Guard, Lock, RawLock};
 use crate::{
-    cpu::{Cpu, HeldInterrupts},
+    cpu::{cpuid, Cpu, HeldInterrupts},
     hal::hal,
 };
 
@@ -62,8 +64,9 @@ impl RawLock for RawSpinLock {
     /// Additionally, note that an additional fence is unneccessary due to the pair of `Acquire`/`Release` orderings.
     fn acquire(&self) {
         // Disable interrupts to avoid deadlock.
-        let intr = hal().cpus().push_off();
+        let intr = hal().cpus().push_off_lock(self.name);
         assert!(!self.holding(), "acquire {}", self.name);
+        hal().cpus().lock_order().acquire(cpuid(), self.name);
 
         // RISC-V supports two forms of atomic instructions, 1) load-reserved/store-conditional and 2) atomic fetch-and-op,
         // and we use the former here.
@@ -73,6 +76,7 @@ impl RawLock for RawSpinLock {
         // 0x80000fe2 | sc.d    a3,a1,(a0)      (store-conditional, dword)
         // 0x80000fe6 | bnez    a3,0x80000fdc   (go back to start of loop)
         // 0x80000fe8 | snez    a0,a2           (set if not zero)
+        let mut contended = false;
         while self
             .locked
             .compare_exchange(
@@ -85,6 +89,12 @@ impl RawLock for RawSpinLock {
             )
             .is_err()
         {
+            if !contended {
+                contended = true;
+                hal()
+                    .tracer()
+                    .record(TRACE_LOCK_CONTEND, [self as *const _ as u64, 0]);
+            }
             ::core::hint::spin_loop();
         }
 
@@ -104,6 +114,7 @@ impl RawLock for RawSpinLock {
         // 0x80000f5c | fence   rw,w            (Enforces `Release` memory ordering)
         self.locked.store(ptr::null_mut(), Ordering::Release);
         let intr = unsafe { self.intr.replace(MaybeUninit::uninit()).assume_init_read() };
+        hal().cpus().lock_order().release(cpuid(), self.name);
         unsafe { hal().cpus().pop_off(intr) };
     }
 }
@@ -0,0 +1,28 @@
+//! Runtime checks of the invariants that the types of the kernel rely on but cannot express,
+//! enabled by the `model-check` feature.
+//!
+//! * Lock order: spin locks fall into classes by their names. Whenever a CPU acquires a lock while
+//!   holding another one, the order of the two classes is recorded, and acquiring them in the
+//!   opposite order later, directly or through other classes, panics, even if the acquisitions
+//!   never actually deadlock.
+//! * Linear guards: a transaction and an `InodeGuard` must end by `end` and `free`. Dropping them
+//!   already panics, and with the feature, each process also counts the live ones, so that
+//!   forgetting one by `mem::forget` panics when the system call returns.
+//! * Deterministic scheduling: a process runs on the lowest CPU that its affinity allows, no CPU
+//!   steals another's processes, and the timer never preempts a process. Hence, a process runs
+//!   until it blocks or yields, and the processes interleave in the same way in every run,
+//!   unless a device interrupt wakes up one of them.
+//!
+//! The checks depend only on `cfg!`, so they cost nothing without the feature. The lock order and
+//! the linear guards live in `kernel_rs_lib::model`, which the tests on the host run against the
+//! same invariants.
+
+pub use kernel_rs_lib::model::LiveGuards;
+
+use crate::param::NCPU;
+
+/// Whether the scheduler runs in the deterministic mode.
+pub const DETERMINISTIC: bool = cfg!(feature = "model-check");
+
+/// The order of the spin lock classes of the CPUs.
+pub type LockOrder = kernel_rs_lib::model::LockOrder<NCPU>;
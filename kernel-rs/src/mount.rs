@@ -0,0 +1,192 @@
+//! The mount table of the pseudo file systems.
+//!
+//! A pseudo file system, e.g., devfs, has no disk. Its inodes are those of a pseudo device, which
+//! the file system synthesizes in memory by the `dinode`, `dirent`, and `read` of its `PseudoFs`
+//! instead of reading them from the disk. Its root directory is the inode `ROOTINO` of the pseudo
+//! device.
+//!
+//! Mounting a pseudo file system on the entry `name` of a directory on the disk makes path
+//! resolution enter its root from there, hiding the entry of that name on the disk, if any. The
+//! mount point is thus keyed by the device and the inode number of the directory, together with
+//! the name, and needs not exist on the disk, so that the fs image needs no `/dev`. Each pseudo
+//! file system is mounted at most once, so that ".." of its root is the directory it is mounted
+//! in. Unmounting it leaves the inodes in use valid, but ".." of its root then leads nowhere until
+//! it is mounted again. The kernel boots with devfs mounted on `/dev` and procfs on `/proc`, and
+//! `mount` and `umount` move them. Nothing is created in a pseudo file system, nor removed or renamed, and nothing is
+//! linked into it.
+
+use arrayvec::ArrayString;
+use rv6_abi::{Dinode, Dirent, DIRSIZ, ROOTINO};
+
+use crate::{
+    devfs,
+    error::{Errno, KernelError},
+    lock::SpinLock,
+    param::ROOTDEV,
+    proc::KernelCtx,
+    procfs,
+};
+
+/// The content of a file of a pseudo file system.
+pub type PseudoFile = ArrayString<256>;
+
+/// A pseudo file system.
+pub struct PseudoFs {
+    /// The name by which `mount` refers to it.
+    pub name: &'static str,
+
+    /// The pseudo device of its inodes. No disk has this number.
+    pub dev: u32,
+
+    /// Returns the inode `inum` as if it were read from the disk, or a free one if there is no
+    /// such inode.
+    pub dinode: fn(u32, &KernelCtx<'_, '_>) -> Dinode,
+
+    /// Returns the `i`th entry of the directory `inum` of generation `gen`, or None past the last
+    /// entry. The inode number of ".." of the root is left for `Mounts::dirent` to fill in.
+    pub dirent: fn(u32, u32, u32, &KernelCtx<'_, '_>) -> Option<Dirent>,
+
+    /// Writes the content of the file `inum` of generation `gen` into the buffer. The content is
+    /// generated on each read, and the size of the inode does not bound it.
+    pub read: fn(u32, u32, &mut PseudoFile, &KernelCtx<'_, '_>),
+
+    /// Nothing is written to the file system, even by the superuser.
+    pub read_only: bool,
+}
+
+/// Number of the pseudo file systems.
+const NPSEUDOFS: usize = 2;
+
+/// The pseudo file systems, in the order of the slots of `Mounts`.
+static PSEUDO_FS: [PseudoFs; NPSEUDOFS] = [devfs::DEVFS, procfs::PROCFS];
+
+/// Returns the pseudo file system of the device `dev`, or None if `dev` is a disk.
+pub fn pseudo_fs(dev: u32) -> Option<&'static PseudoFs> {
+    PSEUDO_FS.iter().find(|fs| fs.dev == dev)
+}
+
+/// The entry `name` of the directory `inum` of `dev`, on which a pseudo file system is mounted.
+#[derive(Clone, Copy)]
+struct MountPoint {
+    dev: u32,
+    inum: u32,
+    name: [u8; DIRSIZ],
+}
+
+impl MountPoint {
+    const fn new(dev: u32, inum: u32, name: &[u8]) -> Self {
+        let mut point = Self {
+            dev,
+            inum,
+            name: [0; DIRSIZ],
+        };
+        let mut i = 0;
+        while i < name.len() {
+            point.name[i] = name[i];
+            i += 1;
+        }
+        point
+    }
+
+    fn is(&self, dev: u32, inum: u32, name: &[u8]) -> bool {
+        self.dev == dev
+            && self.inum == inum
+            && self.name[..name.len()] == *name
+            && self.name.get(name.len()).map_or(true, |&c| c == 0)
+    }
+}
+
+/// Where each pseudo file system is mounted, if anywhere.
+pub struct Mounts {
+    points: SpinLock<[Option<MountPoint>; NPSEUDOFS]>,
+}
+
+impl Mounts {
+    pub const fn new() -> Self {
+        Self {
+            points: SpinLock::new(
+                "mounts",
+                [
+                    Some(MountPoint::new(ROOTDEV, ROOTINO, b"dev")),
+                    Some(MountPoint::new(ROOTDEV, ROOTINO, b"proc")),
+                ],
+            ),
+        }
+    }
+
+    /// Returns the device of the pseudo file system mounted on the entry `name` of the directory
+    /// `inum` of `dev`, or None if nothing is mounted there.
+    pub fn lookup(&self, dev: u32, inum: u32, name: &[u8]) -> Option<u32> {
+        let points = self.points.lock();
+        let i = points
+            .iter()
+            .position(|point| point.map_or(false, |point| point.is(dev, inum, name)))?;
+        Some(PSEUDO_FS[i].dev)
+    }
+
+    /// Returns the device and the inode number of the directory that the pseudo file system of
+    /// `dev` is mounted in, or None if it is not mounted.
+    pub fn parent(&self, dev: u32) -> Option<(u32, u32)> {
+        let i = PSEUDO_FS.iter().position(|fs| fs.dev == dev)?;
+        let point = self.points.lock()[i]?;
+        Some((point.dev, point.inum))
+    }
+
+    /// Returns the `i`th entry of the directory `inum` of generation `gen` of the pseudo file
+    /// system `fs`, or None past the last entry.
+    pub fn dirent(
+        &self,
+        fs: &PseudoFs,
+        inum: u32,
+        gen: u32,
+        i: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<Dirent> {
+        let mut de = (fs.dirent)(inum, gen, i, ctx)?;
+        if inum == ROOTINO && de.name() == b".." {
+            // An entry that refers to no inode, if unmounted.
+            de.inum = self.parent(fs.dev).map_or(0, |(_, inum)| inum as u16);
+        }
+        Some(de)
+    }
+
+    /// Mounts the pseudo file system named `fstype` on the entry `name` of the directory `inum`
+    /// of `dev`, which must be on a disk.
+    /// Returns Ok(()) on success, Err(ENODEV) if there is no such pseudo file system, and
+    /// Err(EBUSY) if it is mounted already, or something else is mounted there.
+    pub fn mount(
+        &self,
+        fstype: &[u8],
+        dev: u32,
+        inum: u32,
+        name: &[u8],
+    ) -> Result<(), KernelError> {
+        let i = PSEUDO_FS
+            .iter()
+            .position(|fs| fs.name.as_bytes() == fstype)
+            .ok_or(Errno::ENODEV)?;
+        let mut points = self.points.lock();
+        if points[i].is_some()
+            || points
+                .iter()
+                .any(|point| point.map_or(false, |point| point.is(dev, inum, name)))
+        {
+            return Err(Errno::EBUSY.into());
+        }
+        points[i] = Some(MountPoint::new(dev, inum, name));
+        Ok(())
+    }
+
+    /// Unmounts the pseudo file system mounted on the entry `name` of the directory `inum` of
+    /// `dev`.
+    /// Returns Ok(()) on success, Err(EINVAL) if nothing is mounted there.
+    pub fn umount(&self, dev: u32, inum: u32, name: &[u8]) -> Result<(), KernelError> {
+        let mut points = self.points.lock();
+        let point = points
+            .iter_mut()
+            .find(|point| point.map_or(false, |point| point.is(dev, inum, name)))
+            .ok_or(Errno::EINVAL)?;
+        *point = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,423 @@
+//! Page cache of the files mapped with MAP_SHARED, and of the pages that `read()` lends.
+//!
+//! A page of a shared file mapping is cached here, keyed by the inode of the file and the offset
+//! of the page in it, so that every process mapping the page maps the same physical page. The
+//! cache counts the mappings of each page. A mapping that goes away reports whether it has written
+//! to the page, and once a page is no longer mapped, `sync` writes it back to the file through
+//! transactions if it is dirty, and drops it from the cache.
+//!
+//! `read()` lends the whole pages it reads into a page-aligned buffer instead of copying them
+//! (see `lend`). The reading process maps a lent page without W, and copies it on the first write
+//! to it, so that a lent page never changes. Hence, a page that a mapping may write to is never
+//! lent, and mapping a page that has been lent reads the page again. The cache keeps a lent page
+//! after the processes let go of it, so that reading it again lends the same page, until the file
+//! changes there, another page needs the entry, or the next `sync`.
+//!
+//! `write()` writes through the cache: it writes to the file, and copies what it writes into the
+//! page if a mapping may write it back (see `write`). Hence, the mappings see the write, and the
+//! write-back of the page does not undo it. Otherwise, `read()` and `write()` bypass the cache.
+
+use core::{cmp, ops::Range, slice};
+
+use array_macro::array;
+
+use crate::{
+    arch::addr::{PAddr, PGSIZE},
+    fs::{FileSystem, InodeGuard, RcInode, Ufs},
+    hal::hal,
+    kalloc::PageOwner,
+    lock::SpinLock,
+    page::Page,
+    param::{BSIZE, MAXOPBLOCKS, NPAGECACHE},
+    proc::KernelCtx,
+};
+
+type Inode = RcInode<<Ufs as FileSystem>::InodeInner>;
+
+struct CachedPage {
+    /// The file the page belongs to, while the page is mapped or has yet to be written back.
+    inode: Option<Inode>,
+
+    /// Device number of the file the page belongs to.
+    dev: u32,
+
+    /// Inode number of the file the page belongs to.
+    inum: u32,
+
+    /// Offset of the page in the file, in pages.
+    pgoff: u32,
+
+    /// The page, or `None` if the entry is unused.
+    page: Option<Page>,
+
+    /// Number of the mappings of the page.
+    maps: usize,
+
+    /// Whether a mapping has written to the page since it was last written back.
+    dirty: bool,
+
+    /// Whether the page holds the current content of the file, and no mapping may write to it,
+    /// so that it may be lent.
+    uptodate: bool,
+
+    /// Whether the page no longer belongs to the file, and is kept only while it is lent.
+    stale: bool,
+}
+
+impl CachedPage {
+    const fn new() -> Self {
+        Self {
+            inode: None,
+            dev: 0,
+            inum: 0,
+            pgoff: 0,
+            page: None,
+            maps: 0,
+            dirty: false,
+            uptodate: false,
+            stale: false,
+        }
+    }
+
+    fn is_page_of(&self, dev: u32, inum: u32, pgoff: u32) -> bool {
+        self.page.is_some()
+            && !self.stale
+            && self.dev == dev
+            && self.inum == inum
+            && self.pgoff == pgoff
+    }
+
+    fn addr(&self) -> Option<PAddr> {
+        self.page.as_ref().map(Page::addr)
+    }
+
+    /// Returns whether a process holds the page that the cache has lent.
+    fn is_lent(&self) -> bool {
+        matches!(self.addr(), Some(pa) if hal().kmem().is_shared(pa))
+    }
+
+    /// Returns whether the page can be dropped right away, i.e., it is neither mapped, to be
+    /// written back, nor lent.
+    fn is_idle(&self) -> bool {
+        self.page.is_some() && self.inode.is_none() && !self.is_lent()
+    }
+
+    /// Frees the page, which must not be mapped, and makes the entry unused.
+    fn clear(&mut self) {
+        if let Some(page) = self.page.take() {
+            hal().kmem().free(page);
+        }
+        *self = Self::new();
+    }
+}
+
+pub struct PageCache {
+    entries: SpinLock<[CachedPage; NPAGECACHE]>,
+}
+
+impl PageCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: SpinLock::new("page_cache", array![_ => CachedPage::new(); NPAGECACHE]),
+        }
+    }
+
+    /// Adds a mapping of page pgoff of the file ip, reading the page into the cache unless it is
+    /// already there. The part of the page past the end of the file is zero-filled.
+    /// Returns Ok(address of the page) on success, Err(()) if the cache or the memory is full.
+    pub fn map(&self, ip: &Inode, pgoff: u32, ctx: &KernelCtx<'_, '_>) -> Result<PAddr, ()> {
+        if let Some(pa) = map_cached(&mut self.entries.lock()[..], ip, pgoff) {
+            return Ok(pa);
+        }
+
+        // Read the page without holding the lock.
+        let allocator = hal().kmem();
+        let mut page = allocator.alloc(PageOwner::PageCache).ok_or(())?;
+        page.write_bytes(0);
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+        let mut guard = ip.lock(ctx).map_err(|_| ())?;
+        let _ = guard.read_bytes_kernel(&mut page[..], pgoff * PGSIZE as u32, ctx);
+        guard.free(ctx);
+
+        let mut entries = self.entries.lock();
+        // Another process may have read the page meanwhile.
+        if let Some(pa) = map_cached(&mut entries[..], ip, pgoff) {
+            return Ok(pa);
+        }
+        let entry = unused_entry(&mut entries[..]).ok_or(())?;
+        let page = scopeguard::ScopeGuard::into_inner(page);
+        let pa = page.addr();
+        *entry = CachedPage {
+            inode: Some(ip.clone()),
+            dev: ip.dev,
+            inum: ip.inum,
+            pgoff,
+            page: Some(page),
+            maps: 1,
+            dirty: false,
+            uptodate: false,
+            stale: false,
+        };
+        Ok(pa)
+    }
+
+    /// Lends page pgoff of the file ip, which the caller has locked, reading the page into the
+    /// cache unless it is already there. The page must lie within the file in whole. The borrower
+    /// must not write to the page, and frees it when done with it.
+    /// Returns Ok(a reference to the page) on success, Err(()) if a mapping may write to the page,
+    /// or the cache or the memory is full.
+    pub fn lend(
+        &self,
+        ip: &mut InodeGuard<'_, <Ufs as FileSystem>::InodeInner>,
+        pgoff: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Page, ()> {
+        let (dev, inum) = (ip.dev, ip.inum);
+        if let Some(res) = lend_cached(&self.entries.lock()[..], dev, inum, pgoff) {
+            return res;
+        }
+
+        // Read the page without holding the lock. No one writes to the file meanwhile, as the
+        // caller has locked it.
+        let allocator = hal().kmem();
+        let page = allocator.alloc(PageOwner::PageCache).ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+        if ip.read_bytes_kernel(&mut page[..], pgoff * PGSIZE as u32, ctx) != PGSIZE {
+            return Err(());
+        }
+
+        let mut entries = self.entries.lock();
+        // A mapping may have read the page meanwhile.
+        if let Some(res) = lend_cached(&entries[..], dev, inum, pgoff) {
+            return res;
+        }
+        // Leave half of the cache to the mappings.
+        if entries.iter().filter(|entry| entry.is_lent()).count() >= NPAGECACHE / 2 {
+            return Err(());
+        }
+        let entry = unused_entry(&mut entries[..]).ok_or(())?;
+        let page = scopeguard::ScopeGuard::into_inner(page);
+        let pa = page.addr();
+        allocator.share(pa).expect("PageCache::lend");
+        *entry = CachedPage {
+            inode: None,
+            dev,
+            inum,
+            pgoff,
+            page: Some(page),
+            maps: 0,
+            dirty: false,
+            uptodate: true,
+            stale: false,
+        };
+        // SAFETY: the reference has been taken by `share`.
+        Ok(unsafe { Page::from_usize(pa.into_usize()) })
+    }
+
+    /// Adds a mapping of the cached page at pa, e.g., when a process forks.
+    pub fn share(&self, pa: PAddr) {
+        let mut entries = self.entries.lock();
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.addr().map(PAddr::into_usize) == Some(pa.into_usize()))
+            .expect("PageCache::share");
+        entry.maps += 1;
+    }
+
+    /// Removes a mapping of the cached page at pa. If dirty, the mapping has written to the page.
+    /// The page is written back and freed by the next `sync` if it is no longer mapped.
+    pub fn unmap(&self, pa: PAddr, dirty: bool) {
+        let mut entries = self.entries.lock();
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.addr().map(PAddr::into_usize) == Some(pa.into_usize()))
+            .expect("PageCache::unmap");
+        assert!(entry.maps > 0, "PageCache::unmap");
+        entry.maps -= 1;
+        entry.dirty |= dirty;
+    }
+
+    /// Copies `src`, which has just been written to the file dev/inum at offset off, into the
+    /// cached pages of the file that a mapping may write back. The caller holds the lock of the
+    /// file, as `write_back` does, so that a write-back sees either both the file and the page
+    /// before the write, or both after it.
+    pub fn write(&self, dev: u32, inum: u32, off: u32, src: &[u8]) {
+        let (off, end) = (off as usize, off as usize + src.len());
+        let mut entries = self.entries.lock();
+        for entry in entries.iter_mut() {
+            if entry.inode.is_none() || !entry.is_page_of(dev, inum, entry.pgoff) {
+                continue;
+            }
+            let start = entry.pgoff as usize * PGSIZE;
+            let (lo, hi) = (cmp::max(off, start), cmp::min(end, start + PGSIZE));
+            if let Some(page) = entry.page.as_mut().filter(|_| lo < hi) {
+                page[lo - start..hi - start].copy_from_slice(&src[lo - off..hi - off]);
+            }
+        }
+    }
+
+    /// Drops the pages of the file dev/inum in pages, which are not mapped, from the cache, as the
+    /// file has changed there. The processes keep the pages lent to them.
+    pub fn invalidate(&self, dev: u32, inum: u32, pages: Range<u32>) {
+        let mut entries = self.entries.lock();
+        for entry in entries.iter_mut() {
+            if !pages.contains(&entry.pgoff) || !entry.is_page_of(dev, inum, entry.pgoff) {
+                continue;
+            }
+            entry.uptodate = false;
+            if entry.is_idle() {
+                entry.clear();
+            } else if entry.inode.is_none() {
+                entry.stale = true;
+            }
+        }
+    }
+
+    /// Drops the pages of the file dev/inum in pages from the cache if they are idle, i.e.,
+    /// neither mapped, to be written back, nor lent, as the file will not be read there soon.
+    pub fn drop_idle(&self, dev: u32, inum: u32, pages: Range<u32>) {
+        let mut entries = self.entries.lock();
+        for entry in entries.iter_mut() {
+            if pages.contains(&entry.pgoff)
+                && entry.is_page_of(dev, inum, entry.pgoff)
+                && entry.is_idle()
+            {
+                entry.clear();
+            }
+        }
+    }
+
+    /// Writes back the dirty pages that are no longer mapped, and drops every such page from the
+    /// cache. Drops the pages that are no longer lent, too.
+    pub fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        for i in 0..NPAGECACHE {
+            loop {
+                let mut entries = self.entries.lock();
+                let entry = &mut entries[i];
+                if entry.inode.is_none() {
+                    if entry.is_idle() {
+                        entry.clear();
+                    }
+                    break;
+                }
+                if entry.maps > 0 {
+                    break;
+                }
+
+                if !entry.dirty {
+                    let ip = entry.inode.take().expect("sync");
+                    entry.clear();
+                    drop(entries);
+                    let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+                    ip.free((&tx, ctx));
+                    tx.end(ctx);
+                    break;
+                }
+
+                // Keep the page in the cache while writing it back, as if it were mapped.
+                entry.dirty = false;
+                entry.maps += 1;
+                let ip = entry.inode.clone().expect("sync");
+                let (pgoff, pa) = (entry.pgoff, entry.addr().expect("sync"));
+                drop(entries);
+                write_back(ip, pgoff, pa, ctx);
+                self.entries.lock()[i].maps -= 1;
+                // Drop the page unless it has been mapped or written to meanwhile.
+            }
+        }
+    }
+}
+
+/// Adds a mapping of page pgoff of the file ip if it is in entries. A page that has been lent is
+/// left to the processes holding it instead, as the mapping may write to it.
+/// Returns Some(address of the page) if it is cached, None otherwise.
+fn map_cached(entries: &mut [CachedPage], ip: &Inode, pgoff: u32) -> Option<PAddr> {
+    let entry = entries
+        .iter_mut()
+        .find(|entry| entry.is_page_of(ip.dev, ip.inum, pgoff))?;
+    if entry.is_lent() {
+        entry.stale = true;
+        return None;
+    }
+    if entry.inode.is_none() {
+        entry.inode = Some(ip.clone());
+    }
+    entry.maps += 1;
+    entry.uptodate = false;
+    entry.addr()
+}
+
+/// Takes a reference to page pgoff of the file dev/inum if it is in entries.
+/// Returns Some(Ok(the reference)) if it is cached and may be lent, Some(Err(())) if it is cached
+/// but a mapping may write to it, and None if it is not cached.
+fn lend_cached(
+    entries: &[CachedPage],
+    dev: u32,
+    inum: u32,
+    pgoff: u32,
+) -> Option<Result<Page, ()>> {
+    let entry = entries
+        .iter()
+        .find(|entry| entry.is_page_of(dev, inum, pgoff))?;
+    let pa = entry.addr()?;
+    if !entry.uptodate || hal().kmem().share(pa).is_err() {
+        return Some(Err(()));
+    }
+    // SAFETY: the reference has been taken by `share`.
+    Some(Ok(unsafe { Page::from_usize(pa.into_usize()) }))
+}
+
+/// Returns an unused entry, dropping an idle page to make one if there is none.
+fn unused_entry(entries: &mut [CachedPage]) -> Option<&mut CachedPage> {
+    let index = entries
+        .iter()
+        .position(|entry| entry.page.is_none())
+        .or_else(|| entries.iter().position(CachedPage::is_idle))?;
+    let entry = &mut entries[index];
+    entry.clear();
+    Some(entry)
+}
+
+/// Writes the page at pa back to page pgoff of the file ip, without extending the file, and
+/// releases ip.
+fn write_back(ip: Inode, pgoff: u32, pa: PAddr, ctx: &KernelCtx<'_, '_>) {
+    // SAFETY: the page stays in the cache while it is written back.
+    let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
+    let off = pgoff as usize * PGSIZE;
+    // Write a few blocks at a time to avoid exceeding the maximum log transaction size,
+    // as `File::write` does.
+    let max = (MAXOPBLOCKS - 1 - 3 - 2) / 2 * BSIZE;
+    let mut written = 0;
+    loop {
+        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+        let mut done = true;
+        if let Ok(mut guard) = ip.lock(ctx) {
+            if !guard.deref_inner().staged.is_empty() {
+                // Write the bytes staged by `write` first, in a transaction of their own.
+                let _ = guard.flush(&tx, ctx);
+                done = false;
+            } else {
+                let size = guard.deref_inner().size as usize;
+                let end = cmp::min(PGSIZE, size.saturating_sub(off));
+                if written < end {
+                    let n = cmp::min(end - written, max);
+                    let src = &src[written..written + n];
+                    if guard
+                        .write_bytes_kernel(src, (off + written) as u32, &tx, ctx)
+                        .is_ok()
+                    {
+                        written += n;
+                        done = written >= end;
+                    }
+                }
+            }
+            guard.free(ctx);
+        }
+        if done {
+            ip.free((&tx, ctx));
+            tx.end(ctx);
+            return;
+        }
+        tx.end(ctx);
+    }
+}
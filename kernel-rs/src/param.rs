@@ -1,17 +1,82 @@
+//! Compile-time configuration of the kernel.
+//!
+//! The resource limits that are worth tuning, i.e., the numbers of processes, open files, active
+//! i-nodes and buffers, come from a `Config` chosen by a cargo feature: `config-small` for a
+//! machine with little memory, `config-large` for heavy workloads, or the default otherwise.
+//! `kernel/param.h` must agree with it, which the Makefile ensures by defining `CONFIG_SMALL` or
+//! `CONFIG_LARGE` for the C code as well. The assertions here and in `arch::memlayout` reject a
+//! `Config` that breaks a layout depending on it at compile time.
+
+use static_assertions::const_assert;
+
+/// The tunable resource limits.
+pub struct Config {
+    /// Maximum number of processes.
+    pub nproc: usize,
+
+    /// Open files per process.
+    pub nofile: usize,
+
+    /// Open files per system.
+    pub nfile: usize,
+
+    /// Maximum number of active i-nodes.
+    pub ninode: usize,
+
+    /// Size of disk block cache.
+    pub nbuf: usize,
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        nproc: 64,
+        nofile: 16,
+        nfile: 100,
+        ninode: 50,
+        nbuf: LOGSIZE,
+    };
+    pub const LARGE: Self = Self {
+        nproc: 256,
+        nofile: 64,
+        nfile: 400,
+        ninode: 200,
+        nbuf: LOGSIZE * 4,
+    };
+    pub const SMALL: Self = Self {
+        nproc: 16,
+        nofile: 8,
+        nfile: 32,
+        ninode: 24,
+        nbuf: LOGSIZE,
+    };
+}
+
+#[cfg(all(feature = "config-small", feature = "config-large"))]
+compile_error!("config-small and config-large are exclusive");
+
+/// The configuration the kernel is built with.
+pub const CONFIG: Config = if cfg!(feature = "config-small") {
+    Config::SMALL
+} else if cfg!(feature = "config-large") {
+    Config::LARGE
+} else {
+    Config::DEFAULT
+};
+
 /// Maximum number of processes.
-pub const NPROC: usize = 64;
+pub const NPROC: usize = CONFIG.nproc;
 
 /// Maximum number of CPUs.
 pub const NCPU: usize = 8;
 
 /// Open files per process.
-pub const NOFILE: usize = 16;
+pub const NOFILE: usize = CONFIG.nofile;
 
 /// Open files per system.
-pub const NFILE: usize = 100;
+pub const NFILE: usize = CONFIG.nfile;
 
 /// Maximum number of active i-nodes.
-pub const NINODE: usize = 50;
+pub const NINODE: usize = CONFIG.ninode;
 
 /// Maximum major device number.
 pub const NDEV: usize = 10;
@@ -23,7 +88,7 @@ pub const ROOTDEV: u32 = 1;
 pub const MAXARG: usize = 32;
 
 /// Block Size.
-pub const BSIZE: usize = 1024;
+pub const BSIZE: usize = rv6_abi::BSIZE;
 
 /// Max # of blocks any FS op writes.
 /// Will be handled in #31.
@@ -33,10 +98,57 @@ pub const MAXOPBLOCKS: usize = 10;
 pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
 
 /// Size of disk block cache.
-pub const NBUF: usize = MAXOPBLOCKS * 3;
+pub const NBUF: usize = CONFIG.nbuf;
+
+/// Number of hash buckets of disk block cache, each with its own lock.
+pub const NBUCKET: usize = 7;
+
+/// Number of blocks read ahead of a sequential read of a file, doubled if `fadvise` tells that
+/// the file is read sequentially.
+pub const READAHEAD: usize = 4;
 
 /// Maximum file path name.
 pub const MAXPATH: usize = 128;
 
 /// Maximum length of process name.
 pub const MAXPROCNAME: usize = 16;
+
+/// Maximum number of resource groups.
+pub const NGROUP: usize = 8;
+
+/// Maximum number of threads sharing a user memory.
+pub const NTHREAD: usize = 16;
+
+/// Maximum number of mmap()ed regions per process.
+pub const NVMA: usize = 16;
+
+/// Maximum number of areas the kernel maps at run time by `ioremap` and `kmap_page` at a time.
+pub const NKMAP: usize = 32;
+
+/// Maximum number of pages in the page cache of shared file mappings and of the pages read() lends.
+pub const NPAGECACHE: usize = 256;
+
+/// Number of records in the trace buffer of each CPU.
+pub const NTRACE: usize = 256;
+
+/// Number of slots in the timer wheel of sleeping processes.
+pub const NSLEEPSLOT: usize = 64;
+
+/// Number of futex wait queues.
+pub const NFUTEX: usize = 16;
+
+/// Maximum number of entries walked in a robust list.
+pub const ROBUST_LIST_LIMIT: usize = 2048;
+
+/// Number of file system watches.
+pub const NWATCH: usize = 16;
+
+/// Number of events each file system watch queues until they are read.
+pub const NWATCHEVENT: usize = 16;
+
+// A process's open files fit in the file table.
+const_assert!(0 < NOFILE && NOFILE <= NFILE);
+// A committing transaction pins a buffer for each block in the log.
+const_assert!(NBUF >= LOGSIZE);
+// A process and the threads sharing its memory are processes.
+const_assert!(0 < NTHREAD && NTHREAD <= NPROC);
@@ -2,8 +2,10 @@ use core::{mem, ops::Deref, ptr::NonNull};
 
 use crate::{
     arch::addr::UVAddr,
+    error::{Errno, KernelError},
     file::{FileType, RcFile},
     hal::hal,
+    kalloc::PageOwner,
     lock::SpinLock,
     page::Page,
     proc::{KernelCtx, WaitChannel},
@@ -40,22 +42,31 @@ pub struct Pipe {
 impl Pipe {
     /// Tries to read up to `n` bytes using `Pipe::try_read()`.
     /// If successfully read i > 0 bytes, wakeups the `write_waitchannel` and returns `Ok(i: usize)`.
-    /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
-    pub fn read(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup, or returns
+    /// `Err(EAGAIN)` if `nonblock`.
+    /// If an error happened, returns `Err(EINVAL)`.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         let mut inner = self.inner.lock();
         loop {
             match inner.try_read(addr, n, ctx) {
                 Ok(r) => {
                     //DOC: piperead-wakeup
-                    self.write_waitchannel.wakeup(ctx.kernel());
+                    // Only one writer can fill the freed space at a time. It wakes up the next.
+                    self.write_waitchannel.wakeup_one(ctx.kernel());
                     return Ok(r);
                 }
+                Err(PipeError::WaitForIO) if nonblock => return Err(Errno::EAGAIN.into()),
                 Err(PipeError::WaitForIO) => {
                     //DOC: piperead-sleep
                     self.read_waitchannel.sleep(&mut inner, ctx);
                 }
-                _ => return Err(()),
+                _ => return Err(().into()),
             }
         }
     }
@@ -69,7 +80,7 @@ impl Pipe {
     pub fn write(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
         let mut written = 0;
         let mut inner = self.inner.lock();
-        loop {
+        let ret = loop {
             match inner.try_write(addr + written, n - written, ctx) {
                 Ok(r) => {
                     written += r;
@@ -77,16 +88,19 @@ impl Pipe {
                     if written < n {
                         self.write_waitchannel.sleep(&mut inner, ctx);
                     } else {
-                        return Ok(written);
+                        break Ok(written);
                     }
                 }
                 Err(PipeError::InvalidCopyin(i)) => {
                     self.read_waitchannel.wakeup(ctx.kernel());
-                    return Ok(written + i);
+                    break Ok(written + i);
                 }
-                _ => return Err(()),
+                _ => break Err(()),
             }
-        }
+        };
+        // Pass the wakeup on to the next writer, in case there is still space left.
+        self.write_waitchannel.wakeup_one(ctx.kernel());
+        ret
     }
 
     fn close(&self, writable: bool, ctx: &KernelCtx<'_, '_>) -> bool {
@@ -132,7 +146,7 @@ impl Deref for AllocatedPipe {
 impl KernelCtx<'_, '_> {
     pub fn allocate_pipe(&self) -> Result<(RcFile, RcFile), ()> {
         let allocator = hal().kmem();
-        let page = allocator.alloc().ok_or(())?;
+        let page = allocator.alloc(PageOwner::Pipe).ok_or(())?;
         let mut page = scopeguard::guard(page, |page| allocator.free(page));
         let ptr = page.as_uninit_mut();
 
@@ -215,8 +229,8 @@ impl PipeInner {
                 return Ok(i);
             }
             if ctx
-                .proc_mut()
-                .memory_mut()
+                .proc()
+                .memory()
                 .copy_in_bytes(&mut ch, addr + i)
                 .is_err()
             {
@@ -253,12 +267,7 @@ impl PipeInner {
             }
             let ch = [self.data[self.nread as usize % PIPESIZE]];
             self.nread = self.nread.wrapping_add(1);
-            if ctx
-                .proc_mut()
-                .memory_mut()
-                .copy_out_bytes(addr + i, &ch)
-                .is_err()
-            {
+            if ctx.proc().memory().copy_out_bytes(addr + i, &ch).is_err() {
                 return Ok(i);
             }
         }
@@ -272,23 +281,24 @@ impl KernelCtx<'_, '_> {
     pub fn pipe(&mut self, fdarray: UVAddr) -> Result<(), ()> {
         let (pipereader, pipewriter) = self.allocate_pipe()?;
 
-        let fd1 = if let Ok(fd) = pipereader.fdalloc(self) {
+        let fd1 = if let Ok(fd) = self.fdalloc(pipereader, false) {
             fd
         } else {
             pipewriter.free(self);
             return Err(());
         };
 
-        let fd2 = if let Ok(fd) = pipewriter.fdalloc(self) {
+        let fd2 = if let Ok(fd) = self.fdalloc(pipewriter, false) {
             fd
         } else {
-            self.proc_mut().deref_mut_data().open_files[fd1 as usize]
-                .take()
-                .unwrap()
-                .free(self);
+            // Another thread may have closed fd1 already.
+            let f = self.proc().files().take(fd1);
+            if let Some(f) = f {
+                f.free(self);
+            }
             return Err(());
         };
 
-        self.proc_mut().memory_mut().copy_out(fdarray, &[fd1, fd2])
+        self.proc().memory().copy_out(fdarray, &[fd1, fd2])
     }
 }
@@ -0,0 +1,65 @@
+//! Release of per-process kernel resources on exit.
+//!
+//! A subsystem that attaches resources to processes registers a hook with
+//! `exit_hook!(func)`, which places a descriptor of `func` in the `.exithook` linker section.
+//! `exit_current` runs every hook in the context of the exiting process, before it closes the
+//! open files and drops the current directory, and before the process becomes a zombie.
+//! A hook runs for every exiting process, so it should return immediately if the process does
+//! not hold the resource.
+//!
+//! Hooks run in link order, so they must not depend on each other.
+
+use core::{mem, slice};
+
+use super::KernelCtx;
+
+extern "C" {
+    // kernel.ld
+    static mut __exithook_start: [u8; 0];
+    static mut __exithook_end: [u8; 0];
+}
+
+/// A function registered by `exit_hook!`.
+#[repr(C)]
+pub struct ExitHook {
+    func: fn(&mut KernelCtx<'_, '_>),
+}
+
+impl ExitHook {
+    pub const fn new(func: fn(&mut KernelCtx<'_, '_>)) -> Self {
+        Self { func }
+    }
+}
+
+/// Registers `func: fn(&mut KernelCtx<'_, '_>)` to be called when a process exits.
+#[macro_export]
+macro_rules! exit_hook {
+    ($func:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".exithook"]
+            static EXIT_HOOK: $crate::proc::ExitHook = $crate::proc::ExitHook::new($func);
+        };
+    };
+}
+
+/// Returns the functions registered by `exit_hook!`.
+fn exit_hooks() -> &'static [ExitHook] {
+    // SAFETY: the linker places only `ExitHook`s between `__exithook_start` and
+    // `__exithook_end`, aligned properly.
+    unsafe {
+        let start = __exithook_start.as_ptr();
+        let end = __exithook_end.as_ptr();
+        let len = (end as usize - start as usize) / mem::size_of::<ExitHook>();
+        slice::from_raw_parts(start as *const ExitHook, len)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Runs every exit hook for the current process, which is exiting.
+    pub fn run_exit_hooks(&mut self) {
+        for hook in exit_hooks() {
+            (hook.func)(self);
+        }
+    }
+}
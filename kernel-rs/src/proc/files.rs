@@ -0,0 +1,207 @@
+//! Open file tables, which the threads made by `clone` share together with the current directory.
+//!
+//! A process refers to its table in a `SharedFiles` slot of `Procs`, which counts the processes
+//! using it, as `SharedMemory` does for the user memory. Every access locks the table, and a file
+//! is cloned out of it before use, so that a thread may close a descriptor while another one still
+//! reads or writes the file: the file lives until the last of them frees its reference. The files
+//! and the directory are freed when the last user exits or execs.
+
+use core::{mem, ptr};
+
+use array_macro::array;
+
+use super::KernelCtx;
+use crate::{
+    file::RcFile,
+    fs::{FileSystem, RcInode, Ufs},
+    lock::{SpinLock, SpinLockGuard},
+    param::{NCPU, NOFILE, NPROC},
+};
+
+/// Number of `SharedFiles` slots. Every process uses one, and a fork, a spawn, or an exec in
+/// progress on each CPU may hold another one before it gives up the old one or fails.
+const NFILES: usize = NPROC + NCPU;
+
+pub struct FileTable {
+    /// Open files.
+    pub open_files: [Option<RcFile>; NOFILE],
+
+    /// Close-on-exec flags of the open files, indexed by descriptor.
+    pub cloexec: [bool; NOFILE],
+
+    /// Current directory, which is `None` only in a free slot.
+    cwd: Option<RcInode<<Ufs as FileSystem>::InodeInner>>,
+}
+
+impl FileTable {
+    const fn new() -> Self {
+        Self {
+            open_files: array![_ => None; NOFILE],
+            cloexec: [false; NOFILE],
+            cwd: None,
+        }
+    }
+
+    /// Returns a table with no open files, whose current directory is `cwd`.
+    pub fn with_cwd(cwd: RcInode<<Ufs as FileSystem>::InodeInner>) -> Self {
+        Self {
+            cwd: Some(cwd),
+            ..Self::new()
+        }
+    }
+
+    /// Returns the file of descriptor `fd`, or None if it is not open.
+    pub fn get(&self, fd: i32) -> Option<&RcFile> {
+        self.open_files.get(fd as usize)?.as_ref()
+    }
+
+    /// Closes descriptor `fd`, and returns its file, which the caller must free.
+    pub fn take(&mut self, fd: i32) -> Option<RcFile> {
+        self.open_files.get_mut(fd as usize)?.take()
+    }
+
+    /// Returns a new table with the same files and current directory. If `exec`, the
+    /// close-on-exec files are left out.
+    pub fn inherit(&self, exec: bool) -> Self {
+        let mut table = Self::new();
+        for (fd, f) in self.open_files.iter().enumerate() {
+            if let Some(file) = f {
+                if !(exec && self.cloexec[fd]) {
+                    table.open_files[fd] = Some(file.clone());
+                    table.cloexec[fd] = self.cloexec[fd];
+                }
+            }
+        }
+        table.cwd = Some(self.cwd().clone());
+        table
+    }
+
+    pub fn cwd(&self) -> &RcInode<<Ufs as FileSystem>::InodeInner> {
+        self.cwd.as_ref().expect("FileTable::cwd")
+    }
+
+    /// Makes `cwd` the current directory, and returns the old one, which the caller must free.
+    pub fn set_cwd(
+        &mut self,
+        cwd: RcInode<<Ufs as FileSystem>::InodeInner>,
+    ) -> RcInode<<Ufs as FileSystem>::InodeInner> {
+        self.cwd.replace(cwd).expect("FileTable::set_cwd")
+    }
+
+    /// Frees the open files and the current directory.
+    pub fn free(mut self, ctx: &KernelCtx<'_, '_>) {
+        for f in self.open_files.iter_mut() {
+            if let Some(f) = f.take() {
+                f.free(ctx);
+            }
+        }
+        if let Some(cwd) = self.cwd.take() {
+            let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+            cwd.free((&tx, ctx));
+            tx.end(ctx);
+        }
+    }
+}
+
+/// `table` has a current directory iff `users` > 0.
+pub struct SharedFiles {
+    /// Number of the processes using the table.
+    users: SpinLock<usize>,
+
+    table: SpinLock<FileTable>,
+}
+
+impl SharedFiles {
+    const fn new() -> Self {
+        Self {
+            users: SpinLock::new("files", 0),
+            table: SpinLock::new("file_table", FileTable::new()),
+        }
+    }
+
+    /// Locks the table. The caller must be a user, and must not sleep or free a file until it
+    /// drops the guard.
+    pub fn lock(&self) -> SpinLockGuard<'_, FileTable> {
+        self.table.lock()
+    }
+
+    /// Returns the number of the open files in the table. Unlike `lock`, the caller need not be a
+    /// user, and finds none if the table is unused.
+    pub fn open_count(&self) -> usize {
+        self.table
+            .lock()
+            .open_files
+            .iter()
+            .filter(|f| f.is_some())
+            .count()
+    }
+
+    /// Adds a user of the table. The caller must be a user.
+    pub fn share(&self) {
+        let mut users = self.users.lock();
+        assert!(*users > 0, "SharedFiles::share");
+        *users += 1;
+    }
+
+    /// Returns true if another process uses the table. The caller must be a user.
+    pub fn is_shared(&self) -> bool {
+        *self.users.lock() > 1
+    }
+
+    /// Removes a user of the table. The caller must be a user, and must not use the table any
+    /// longer. Returns Some(table) if it was the last user, which must free the table then.
+    pub fn release(&self) -> Option<FileTable> {
+        let mut users = self.users.lock();
+        assert!(*users > 0, "SharedFiles::release");
+        *users -= 1;
+        if *users > 0 {
+            return None;
+        }
+        Some(mem::replace(&mut *self.table.lock(), FileTable::new()))
+    }
+}
+
+pub struct FileTables {
+    slots: [SharedFiles; NFILES],
+}
+
+impl FileTables {
+    pub const fn new() -> Self {
+        Self {
+            slots: array![_ => SharedFiles::new(); NFILES],
+        }
+    }
+
+    /// Puts `table` in a free slot, whose only user is the caller.
+    /// Returns Ok(slot) on success, Err(table) if every slot is in use.
+    pub fn alloc(&self, table: FileTable) -> Result<&SharedFiles, FileTable> {
+        for slot in &self.slots {
+            let mut users = slot.users.lock();
+            if *users == 0 {
+                let _ = mem::replace(&mut *slot.table.lock(), table);
+                *users = 1;
+                return Ok(slot);
+            }
+        }
+        Err(table)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Stops the current process from using its file table, which must not be accessed until it
+    /// gets a new one. Frees the open files and the current directory if no other thread uses
+    /// them.
+    pub fn release_files(&mut self) {
+        if let Some(table) = self.proc().shared_files().release() {
+            table.free(self);
+        }
+        self.proc_mut().deref_mut_data().files = ptr::null();
+    }
+
+    /// Makes the current process use `files` in place of its file table, which is released by
+    /// `release_files`.
+    pub fn replace_files(&mut self, files: &SharedFiles) {
+        self.release_files();
+        self.proc_mut().deref_mut_data().files = files;
+    }
+}
@@ -0,0 +1,141 @@
+//! Resource groups: a minimal resource-control mechanism.
+//!
+//! Every process belongs to a resource group, which is inherited on fork.
+//! A group has
+//! * a CPU weight: a process runs for as many timer ticks as its group's weight
+//!   before it gives up the CPU, and
+//! * a page cap: the total number of user pages held by the processes in the
+//!   group cannot exceed it.
+//!
+//! Only the superuser may set the limits of a group or move a process into one.
+//!
+//! Groups are numbered from 0 to `NGROUP - 1`, rather than keyed by process group. A process group
+//! is gone once its last member changes groups with `setpgid` or exits, and its pgid may then be
+//! reused by an unrelated job, which would inherit the limits and the charged pages left behind.
+//! Job control also moves processes between process groups freely, which would move their
+//! charges without the superuser asking.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::KernelCtx;
+use crate::arch::addr::{pgroundup, PGSIZE};
+
+/// CPU weight of a newly created group.
+const DEFAULT_CPU_WEIGHT: usize = 1;
+
+/// Maximum CPU weight, so that a process of any group is preempted within a bounded number of
+/// ticks.
+const MAX_CPU_WEIGHT: usize = 16;
+
+/// Page cap of a group without any limit.
+const NO_PAGE_CAP: usize = usize::MAX;
+
+pub struct ResourceGroup {
+    cpu_weight: AtomicUsize,
+    page_cap: AtomicUsize,
+    /// Number of user pages charged to this group.
+    pages: AtomicUsize,
+}
+
+impl ResourceGroup {
+    pub const fn new() -> Self {
+        Self {
+            cpu_weight: AtomicUsize::new(DEFAULT_CPU_WEIGHT),
+            page_cap: AtomicUsize::new(NO_PAGE_CAP),
+            pages: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn cpu_weight(&self) -> usize {
+        self.cpu_weight.load(Ordering::Relaxed)
+    }
+
+    /// Sets the CPU weight, clamped to `MAX_CPU_WEIGHT`, and the page cap of this group.
+    /// `page_cap == 0` means that the group has no page cap.
+    /// Lowering the cap below the number of charged pages only prevents further growth.
+    pub fn set_limits(&self, cpu_weight: usize, page_cap: usize) -> Result<(), ()> {
+        if cpu_weight == 0 {
+            return Err(());
+        }
+        let page_cap = if page_cap == 0 { NO_PAGE_CAP } else { page_cap };
+        self.cpu_weight
+            .store(cpu_weight.min(MAX_CPU_WEIGHT), Ordering::Relaxed);
+        self.page_cap.store(page_cap, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Charges `pages` pages to this group.
+    /// Returns Err(()) without charging anything if it would exceed the page cap.
+    pub fn try_charge(&self, pages: usize) -> Result<(), ()> {
+        let cap = self.page_cap.load(Ordering::Relaxed);
+        self.pages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |charged| {
+                charged.checked_add(pages).filter(|new| *new <= cap)
+            })
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+
+    pub fn uncharge(&self, pages: usize) {
+        let _ = self.pages.fetch_sub(pages, Ordering::Relaxed);
+    }
+}
+
+/// Number of pages needed to hold `size` bytes of user memory.
+pub fn pages_of(size: usize) -> usize {
+    pgroundup(size) / PGSIZE
+}
+
+impl KernelCtx<'_, '_> {
+    /// Charges the current process's group for its user memory being `size` bytes,
+    /// replacing what has been charged for the memory so far.
+    /// Returns Err(()) without charging anything if it would exceed the page cap.
+    pub fn charge_memory(&self, size: usize) -> Result<(), ()> {
+        let pages = pages_of(size);
+        let group = self.proc().deref_data().group;
+        let charged = self.proc().shared_memory().charged_pages();
+        if pages > charged {
+            self.kernel()
+                .procs()
+                .group(group)
+                .try_charge(pages - charged)?;
+        } else {
+            self.kernel().procs().group(group).uncharge(charged - pages);
+        }
+        self.proc().shared_memory().set_charged_pages(pages);
+        Ok(())
+    }
+
+    /// Moves the current process into the given group, along with the pages charged for its
+    /// memory. Fails if other threads share the memory, as it is charged to a single group.
+    pub fn join_group(&mut self, group: usize) -> Result<(), ()> {
+        if self.proc().shared_memory().is_shared() {
+            return Err(());
+        }
+        let old = self.proc().deref_data().group;
+        let charged = self.proc().shared_memory().charged_pages();
+        self.kernel()
+            .procs()
+            .get_group(group)?
+            .try_charge(charged)?;
+        self.kernel().procs().group(old).uncharge(charged);
+        self.proc_mut().deref_mut_data().group = group;
+        Ok(())
+    }
+
+    /// Charges a timer tick to the current process.
+    /// Returns true if the process has run as many ticks as its group's CPU weight
+    /// and should give up the CPU.
+    pub fn tick_expired(&mut self) -> bool {
+        let group = self.proc().deref_data().group;
+        let weight = self.kernel().procs().group(group).cpu_weight();
+        let data = self.proc_mut().deref_mut_data();
+        data.ticks += 1;
+        if data.ticks >= weight {
+            data.ticks = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -2,9 +2,9 @@ use core::ops::Deref;
 
 use super::*;
 use crate::{
-    fs::{FileSystem, Ufs},
+    fs::{FileSystem, RcInode, Ufs},
     kernel::{kernel_ref, KernelRef},
-    vm::UserMemory,
+    lock::SpinLockGuard,
 };
 
 /// Type that stores the context of the current thread. Consists of
@@ -56,7 +56,7 @@ impl<'id, 'p> KernelCtx<'id, 'p> {
     // Its name cannot be `yield` because `yield` is a reserved keyword.
     pub fn yield_cpu(&self) {
         let mut guard = self.proc.lock();
-        guard.deref_mut_info().state = Procstate::RUNNABLE;
+        self.kernel.procs().make_runnable(&mut guard);
         unsafe { guard.sched() };
     }
 }
@@ -94,6 +94,11 @@ impl<'id, 'p> CurrentProc<'id, 'p> {
         unsafe { (*self.info.get_mut_raw()).pid }
     }
 
+    pub fn tgid(&self) -> Pid {
+        // SAFETY: tgid is not modified while CurrentProc exists.
+        unsafe { (*self.info.get_mut_raw()).tgid }
+    }
+
     pub fn trap_frame(&self) -> &TrapFrame {
         // SAFETY: trap_frame is a valid pointer according to the invariants
         // of Proc and CurrentProc.
@@ -106,28 +111,81 @@ impl<'id, 'p> CurrentProc<'id, 'p> {
         unsafe { &mut *self.deref_mut_data().trap_frame }
     }
 
-    pub fn memory(&self) -> &UserMemory {
-        // SAFETY: memory has been initialized according to the invariants
-        // of Proc and CurrentProc.
-        unsafe { self.deref_data().memory.assume_init_ref() }
+    /// Returns where the trap frame is mapped in the user memory.
+    pub fn trap_frame_va(&self) -> usize {
+        self.deref_data().trap_frame_va
     }
 
-    pub fn memory_mut(&mut self) -> &mut UserMemory {
-        // SAFETY: memory has been initialized according to the invariants
+    /// Returns the slot of the user memory, which other threads may share.
+    pub fn shared_memory(&self) -> &SharedMemory {
+        // SAFETY: memory is a valid pointer according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_mut_data().memory.assume_init_mut() }
+        unsafe { &*self.deref_data().memory }
     }
 
-    pub fn cwd(&self) -> &RcInode<<Ufs as FileSystem>::InodeInner> {
-        // SAFETY: cwd has been initialized according to the invariants
-        // of Proc and CurrentProc.
-        unsafe { self.deref_data().cwd.assume_init_ref() }
+    /// Locks the user memory, which other threads may share. The guard must not be held while
+    /// sleeping, nor while calling this again.
+    pub fn memory(&self) -> MemoryGuard<'_> {
+        // SAFETY: the current process uses the memory.
+        unsafe { self.shared_memory().lock() }
     }
 
-    pub fn cwd_mut(&mut self) -> &mut RcInode<<Ufs as FileSystem>::InodeInner> {
-        // SAFETY: cwd has been initialized according to the invariants
+    /// Returns the slot of the open file table, which other threads may share.
+    pub fn shared_files(&self) -> &SharedFiles {
+        // SAFETY: files is a valid pointer according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_mut_data().cwd.assume_init_mut() }
+        unsafe { &*self.deref_data().files }
+    }
+
+    /// Locks the open file table. The guard must not be held while sleeping, nor while calling
+    /// this again.
+    pub fn files(&self) -> SpinLockGuard<'_, FileTable> {
+        self.shared_files().lock()
+    }
+
+    /// Returns the real user ID.
+    pub fn uid(&self) -> Uid {
+        self.deref_data().uid
+    }
+
+    /// Returns the effective user ID.
+    pub fn euid(&self) -> Uid {
+        self.deref_data().euid
+    }
+
+    /// Returns the group ID.
+    pub fn gid(&self) -> Gid {
+        self.deref_data().gid
+    }
+
+    /// Sets the real and the effective user IDs to `uid`, if the process is the superuser.
+    /// Otherwise, only the effective user ID can be set, and only to the real user ID.
+    /// Returns Ok(()) on success, Err(()) if not permitted.
+    pub fn set_uid(&mut self, uid: Uid) -> Result<(), ()> {
+        let data = self.deref_mut_data();
+        if data.euid == 0 {
+            data.uid = uid;
+        } else if uid != data.uid {
+            return Err(());
+        }
+        data.euid = uid;
+        Ok(())
+    }
+
+    /// Sets the group ID to `gid`, if the process is the superuser.
+    /// Returns Ok(()) on success, Err(()) if not permitted.
+    pub fn set_gid(&mut self, gid: Gid) -> Result<(), ()> {
+        let data = self.deref_mut_data();
+        if data.euid != 0 && gid != data.gid {
+            return Err(());
+        }
+        data.gid = gid;
+        Ok(())
+    }
+
+    /// Returns a new reference to the current directory.
+    pub fn cwd(&self) -> RcInode<<Ufs as FileSystem>::InodeInner> {
+        self.files().cwd().clone()
     }
 }
 
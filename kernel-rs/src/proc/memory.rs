@@ -0,0 +1,382 @@
+//! User memories, which the threads made by `clone` share.
+//!
+//! A process refers to its user memory in a `SharedMemory` slot of `Procs`, which counts the
+//! processes using it. Each of them has its own trap frame, mapped at its own trap frame slot of the
+//! memory (see `memlayout::trap_frame`). The memory is freed when the last of them exits or execs.
+//!
+//! The users lock the memory on every access, e.g., to copy in or out, or to handle a page fault,
+//! and never sleep while holding the lock. A change of the layout, i.e., of the regions mapped by
+//! mmap or of the program break, also holds a sleeping lock from start to end, as mapping a file
+//! reads it between the locked steps. Hence, whatever the threads do to each other's memory, the
+//! kernel only sees a copy fail with EFAULT.
+//!
+//! The only access outside the lock is the disk reading or writing a page directly for
+//! `O_DIRECT`, which sleeps. The thread pins the page by `pin_user_page` for the transfer, and
+//! whatever frees or replaces user pages, i.e., `munmap_pages`, `set_break`, `discard_pages` and
+//! `map_lent`, waits for the pins in its range to go away, or fails.
+
+use core::{
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+
+use super::{KernelCtx, Pid};
+use crate::{
+    arch::addr::{pgrounddown, pgroundup, Addr, PAddr, UVAddr, PGSIZE},
+    arch::memlayout::TRAPFRAME,
+    hal::hal,
+    lock::{SleepLock, SleepLockGuard, SleepableLock, SleepableLockGuard, SpinLock, SpinLockGuard},
+    page::Page,
+    param::{NCPU, NPROC, NTHREAD},
+    vm::UserMemory,
+};
+
+/// Number of `SharedMemory` slots. Every process uses one, and a fork or an exec in progress on
+/// each CPU may hold another one before it gives up the old one or fails.
+const NMEMORY: usize = NPROC + NCPU;
+
+/// # Safety
+///
+/// `memory` has been initialized iff `users` > 0.
+pub struct SharedMemory {
+    /// Number of the processes using the memory.
+    users: SpinLock<usize>,
+
+    memory: SpinLock<MaybeUninit<UserMemory>>,
+
+    /// Held while changing the layout of the memory.
+    layout: SleepLock<()>,
+
+    /// Number of user pages charged to the resource group of the users.
+    charged_pages: AtomicUsize,
+
+    /// The ranges that the users are reading or writing.
+    io: SleepableLock<IoRanges>,
+}
+
+/// A page of user memory that the disk is reading or writing for a thread, from start to end.
+#[derive(Clone, Copy)]
+struct IoRange {
+    tid: Pid,
+    start: usize,
+    end: usize,
+}
+
+struct IoRanges {
+    /// At most one for each user.
+    ranges: [Option<IoRange>; NTHREAD],
+
+    /// Number of the users waiting for a pin to go away.
+    waiters: usize,
+}
+
+impl IoRanges {
+    const fn new() -> Self {
+        Self {
+            ranges: [None; NTHREAD],
+            waiters: 0,
+        }
+    }
+
+    /// Returns true if a range overlaps [start, end).
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.ranges
+            .iter()
+            .flatten()
+            .any(|range| range.start < end && start < range.end)
+    }
+}
+
+/// A locked user memory, which no other thread accesses until it drops.
+pub struct MemoryGuard<'s> {
+    guard: SpinLockGuard<'s, MaybeUninit<UserMemory>>,
+}
+
+impl Deref for MemoryGuard<'_> {
+    type Target = UserMemory;
+
+    fn deref(&self) -> &UserMemory {
+        // SAFETY: the memory has been initialized since the locker uses it.
+        unsafe { self.guard.assume_init_ref() }
+    }
+}
+
+impl DerefMut for MemoryGuard<'_> {
+    fn deref_mut(&mut self) -> &mut UserMemory {
+        // SAFETY: the memory has been initialized since the locker uses it.
+        unsafe { self.guard.assume_init_mut() }
+    }
+}
+
+// SAFETY: `memory` is accessed only by its users, under the lock.
+unsafe impl Sync for SharedMemory {}
+
+impl SharedMemory {
+    const fn new() -> Self {
+        Self {
+            users: SpinLock::new("memory", 0),
+            memory: SpinLock::new("user_memory", MaybeUninit::uninit()),
+            layout: SleepLock::new("memory_layout", ()),
+            charged_pages: AtomicUsize::new(0),
+            io: SleepableLock::new("memory_io", IoRanges::new()),
+        }
+    }
+
+    /// Adds a user of the memory. The caller must be a user.
+    pub fn share(&self) {
+        let mut users = self.users.lock();
+        assert!(*users > 0, "SharedMemory::share");
+        *users += 1;
+    }
+
+    /// Returns true if another process uses the memory. The caller must be a user.
+    pub fn is_shared(&self) -> bool {
+        *self.users.lock() > 1
+    }
+
+    /// Removes a user of the memory. The caller must be a user, and must not use the memory any
+    /// longer. Returns Some((memory, charged pages)) if it was the last user, which must free the
+    /// memory and return the pages to the resource group then.
+    pub fn release(&self) -> Option<(UserMemory, usize)> {
+        let mut users = self.users.lock();
+        assert!(*users > 0, "SharedMemory::release");
+        *users -= 1;
+        if *users > 0 {
+            return None;
+        }
+        let charged_pages = self.charged_pages.swap(0, Ordering::Relaxed);
+        // SAFETY: memory has been initialized, and the slot is free from now on.
+        Some((
+            unsafe { self.memory.lock().assume_init_read() },
+            charged_pages,
+        ))
+    }
+
+    /// Locks the memory. The guard must not be held while sleeping.
+    ///
+    /// # Safety
+    ///
+    /// The caller must be a user of the memory.
+    pub unsafe fn lock(&self) -> MemoryGuard<'_> {
+        MemoryGuard {
+            guard: self.memory.lock(),
+        }
+    }
+
+    /// Locks the layout of the memory, i.e., keeps the other users from changing it, until the
+    /// guard is freed. It must be taken before locking the memory or an inode.
+    pub fn lock_layout(&self, ctx: &KernelCtx<'_, '_>) -> SleepLockGuard<'_, ()> {
+        self.layout.lock(ctx)
+    }
+
+    /// Returns the number of user pages charged for the memory.
+    pub fn charged_pages(&self) -> usize {
+        self.charged_pages.load(Ordering::Relaxed)
+    }
+
+    pub fn set_charged_pages(&self, pages: usize) {
+        self.charged_pages.store(pages, Ordering::Relaxed);
+    }
+}
+
+pub struct Memories {
+    slots: [SharedMemory; NMEMORY],
+}
+
+impl Memories {
+    pub const fn new() -> Self {
+        Self {
+            slots: array![_ => SharedMemory::new(); NMEMORY],
+        }
+    }
+
+    /// Puts `memory` in a free slot, whose only user is the caller.
+    /// Returns Ok(slot) on success, Err(memory) if every slot is in use.
+    pub fn alloc(&self, memory: UserMemory) -> Result<&SharedMemory, UserMemory> {
+        for slot in &self.slots {
+            let mut users = slot.users.lock();
+            if *users == 0 {
+                let _ = slot.memory.lock().write(memory);
+                slot.charged_pages.store(0, Ordering::Relaxed);
+                *users = 1;
+                return Ok(slot);
+            }
+        }
+        Err(memory)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Stops the current process from using its memory, which must not be accessed until it gets
+    /// a new one. Unmaps its trap frame from the memory, or frees the memory if no other thread
+    /// uses it, after writing back the shared mappings of files. The pages charged for the freed
+    /// memory return to the resource group.
+    pub fn release_memory(&mut self) {
+        let va = self.proc().deref_data().trap_frame_va;
+        self.proc().memory().unmap_trap_frame(va);
+        if let Some((mut memory, charged_pages)) = self.proc().shared_memory().release() {
+            let cache = self.kernel().page_cache();
+            memory.unmap_shared(cache);
+            memory.free(hal().kmem());
+            cache.sync(self);
+            let group = self.proc().deref_data().group;
+            self.kernel().procs().group(group).uncharge(charged_pages);
+        }
+        self.proc_mut().deref_mut_data().memory = ptr::null();
+    }
+
+    /// Calls `f` with the layout of the current process's memory locked, so that no other thread
+    /// changes it meanwhile, and returns its result.
+    pub fn change_layout<R, F: FnOnce(&Self) -> R>(&self, f: F) -> R {
+        let layout = self.proc().shared_memory().lock_layout(self);
+        let res = f(self);
+        layout.free(self);
+        res
+    }
+
+    /// Pins the page of the current process at `va` until it calls `unpin_user_page`, so that
+    /// the disk may read or write it directly meanwhile. A page that is shared copy-on-write is
+    /// copied first if `write`, as the disk will write into it.
+    /// Returns Ok(the physical address of `va`) on success, Err(()) if `va` is not mapped for the
+    /// user, or not writable if `write`.
+    pub fn pin_user_page(&self, va: UVAddr, write: bool) -> Result<PAddr, ()> {
+        let start = pgrounddown(va.into_usize());
+        let range = IoRange {
+            tid: self.proc().pid(),
+            start,
+            end: start + PGSIZE,
+        };
+        let mut io = self.proc().shared_memory().io.lock();
+        // Translate while holding the lock, so that the page is not freed before it is pinned.
+        let pa = {
+            let mut memory = self.proc().memory();
+            if write {
+                memory.translate_mut(va)
+            } else {
+                memory.translate(va)
+            }
+        }
+        .ok_or(())?;
+        let slot = io
+            .ranges
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("pin_user_page");
+        *slot = Some(range);
+        Ok(pa)
+    }
+
+    /// Unpins the page pinned by `pin_user_page`.
+    pub fn unpin_user_page(&self) {
+        let tid = self.proc().pid();
+        let mut io = self.proc().shared_memory().io.lock();
+        for slot in io.ranges.iter_mut() {
+            if matches!(slot, Some(range) if range.tid == tid) {
+                *slot = None;
+            }
+        }
+        if io.waiters > 0 {
+            io.wakeup(self.kernel());
+        }
+    }
+
+    /// Locks the pins of the current process's memory once no page from `start` to `end` is
+    /// pinned. The caller may free the pages while holding the guard, as no pin begins in them
+    /// before it drops.
+    fn wait_unpinned(&self, start: usize, end: usize) -> SleepableLockGuard<'_, IoRanges> {
+        let mut io = self.proc().shared_memory().io.lock();
+        while io.overlaps(start, end) {
+            io.waiters += 1;
+            io.sleep(self);
+            io.waiters -= 1;
+        }
+        io
+    }
+
+    /// Moves the program break of the current process to `brk`, and charges the resource group
+    /// for the change. Waits for the pins in the pages above `brk` to go away before freeing
+    /// them, or above the old break before giving back the pages of a failed growth. The caller
+    /// must hold the layout lock.
+    /// Returns Ok(()) on success, Err(()) if the memory cannot grow to `brk`.
+    pub fn set_break(&self, brk: usize) -> Result<(), ()> {
+        let allocator = hal().kmem();
+        let size = self.proc().memory().size();
+        if brk >= size {
+            let _ = self.proc().memory().alloc(brk, allocator)?;
+            let footprint = self.proc().memory().footprint();
+            if self.charge_memory(footprint).is_err() {
+                // Exceeded the page cap of the resource group.
+                let io = self.wait_unpinned(pgroundup(size), pgroundup(brk));
+                let _ = self.proc().memory().dealloc(size, allocator);
+                drop(io);
+                return Err(());
+            }
+            return Ok(());
+        }
+
+        let io = self.wait_unpinned(pgroundup(brk), pgroundup(size));
+        let _ = self.proc().memory().dealloc(brk, allocator);
+        drop(io);
+
+        let footprint = self.proc().memory().footprint();
+        // Shrinking always fits in the page cap.
+        let _ = self.charge_memory(footprint);
+        Ok(())
+    }
+
+    /// Unmaps the mmap()ed pages of the current process from `addr` to `addr + len`, as
+    /// `UserMemory::munmap` does, once no page of them is pinned. The caller must hold the layout
+    /// lock.
+    /// Returns Ok(()) on success, Err(()) if it would split a region but no more can be added.
+    pub fn munmap_pages(&self, addr: usize, len: usize) -> Result<(), ()> {
+        let end = addr.checked_add(pgroundup(len)).ok_or(())?;
+        let io = self.wait_unpinned(addr, end);
+        let res = self
+            .proc()
+            .memory()
+            .munmap(addr, len, self.kernel().page_cache(), hal().kmem());
+        drop(io);
+        res
+    }
+
+    /// Frees the anonymous pages of the current process from start to end, which must be
+    /// page-aligned and lie within the regions mapped by mmap, as `UserMemory::discard` does,
+    /// once no page of them is pinned. The pages stay charged to the resource group, as they come
+    /// back on the next access. The caller must hold the layout lock.
+    /// Returns Ok(()) on success, Err(()) if the range has private pages of a file.
+    pub fn discard_pages(&self, start: usize, end: usize) -> Result<(), ()> {
+        let io = self.wait_unpinned(start, end);
+        let res = self.proc().memory().discard(start, end, hal().kmem());
+        drop(io);
+        res
+    }
+
+    /// Maps `page`, which the page cache lends, at `va` of the current process in place of the
+    /// page there, as `UserMemory::map_lent` does, unless that page is pinned.
+    /// Returns Ok(()) on success, Err(()) on failure, in which case `page` is freed.
+    pub fn map_lent(&self, va: usize, page: Page) -> Result<(), ()> {
+        let io = self.proc().shared_memory().io.lock();
+        let res = if io.overlaps(va, va.saturating_add(PGSIZE)) {
+            hal().kmem().free(page);
+            Err(())
+        } else {
+            // Replace the page while holding the lock, so that no pin begins in it before.
+            self.proc().memory().map_lent(va, page, hal().kmem())
+        };
+        drop(io);
+        res
+    }
+
+    /// Makes the current process use `memory`, which maps its trap frame at `TRAPFRAME`, in place
+    /// of the old one, which is released by `release_memory`.
+    pub fn replace_memory(&mut self, memory: &SharedMemory) {
+        self.release_memory();
+        let data = self.proc_mut().deref_mut_data();
+        data.memory = memory;
+        data.trap_frame_va = TRAPFRAME;
+    }
+}
@@ -1,32 +1,46 @@
-use core::{
-    cell::UnsafeCell,
-    mem::{self, MaybeUninit},
-    ops::Deref,
-    ptr, str,
-    sync::atomic::{AtomicBool, Ordering},
-};
+use core::{cell::UnsafeCell, mem, ops::Deref, pin::Pin, ptr, str};
 
-use array_macro::array;
+use rv6_abi::{SigAction, NSIG, SIGKILL};
 
 use crate::{
     arch::riscv::intr_get,
-    file::RcFile,
-    fs::{FileSystem, RcInode, Ufs},
+    exec::Abi,
+    fcount,
     hal::hal,
+    iostat::IoCounters,
     lock::SpinLock,
+    model::LiveGuards,
     page::Page,
-    param::{MAXPROCNAME, NOFILE},
-    util::branded::Branded,
-    vm::UserMemory,
+    param::MAXPROCNAME,
+    util::{
+        branded::Branded,
+        intrusive_list::{List, ListEntry, ListNode},
+    },
 };
 
+mod exit_hook;
+mod files;
+mod group;
 mod kernel_ctx;
+mod memory;
 mod procs;
+mod runqueue;
+mod signal;
+mod sleep_queue;
 mod wait_channel;
+mod wait_queue;
 
+pub use exit_hook::*;
+pub use files::*;
+pub use group::*;
 pub use kernel_ctx::*;
+pub use memory::*;
 pub use procs::*;
+pub use runqueue::*;
+pub use signal::*;
+pub use sleep_queue::*;
 pub use wait_channel::*;
+pub use wait_queue::*;
 
 extern "C" {
     // swtch.S
@@ -68,7 +82,7 @@ pub struct Context {
 /// The trapframe includes callee-saved user registers like s0-s11 because the
 /// return-to-user path via usertrapret() doesn't return through
 /// the entire kernel call stack.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct TrapFrame {
     /// 0 - kernel page table (satp: Supervisor Address Translation and Protection)
     pub kernel_satp: usize,
@@ -189,7 +203,13 @@ pub enum Procstate {
     USED,
 }
 
-type Pid = i32;
+pub type Pid = i32;
+
+/// User ID. 0 is the superuser.
+pub type Uid = u16;
+
+/// Group ID.
+pub type Gid = u16;
 
 /// Proc::info's spinlock must be held when using these.
 pub struct ProcInfo {
@@ -199,11 +219,46 @@ pub struct ProcInfo {
     /// If non-zero, sleeping on waitchannel.
     waitchannel: *const WaitChannel,
 
+    /// Ticket taken from waitchannel when the process went to sleep.
+    wait_ticket: usize,
+
     /// Exit status to be returned to parent's wait.
     xstate: i32,
 
-    /// Process ID.
+    /// Thread ID. Unique among all threads, and used as the PID of a single-threaded process.
     pid: Pid,
+
+    /// Thread group ID, i.e., the PID of the process this thread belongs to.
+    /// The same as `pid` for the main thread of a process.
+    tgid: Pid,
+
+    /// Process group ID, shared by every thread of the process. Written while holding both the
+    /// `wait_lock` and this lock.
+    pgid: Pid,
+
+    /// Session ID, shared by every thread of the process. Written while holding both the
+    /// `wait_lock` and this lock.
+    sid: Pid,
+
+    /// The CPU the process last ran on, whose run queue it joins when it becomes runnable, if
+    /// `affinity` allows it.
+    cpu: usize,
+
+    /// The CPUs the process may run on, as a bitmask. Inherited by the children.
+    affinity: usize,
+
+    /// The real-time interval timer of the process, armed by `setitimer` or `alarm`.
+    /// Used only by the main thread.
+    alarm: Option<Alarm>,
+}
+
+/// An armed interval timer, which sends `SIGALRM` when the tick count reaches `deadline`.
+#[derive(Clone, Copy)]
+pub struct Alarm {
+    deadline: u32,
+
+    /// Ticks between the later expirations, or 0 if it expires only once.
+    interval: u32,
 }
 
 /// Proc::data are private to the process, so lock need not be held.
@@ -214,20 +269,47 @@ pub struct ProcData {
     /// Data page for trampoline.S.
     trap_frame: *mut TrapFrame,
 
-    /// User memory manager
-    memory: MaybeUninit<UserMemory>,
+    /// Where `trap_frame` is mapped in the user memory.
+    trap_frame_va: usize,
+
+    /// User memory manager, which may be shared with other threads.
+    memory: *const SharedMemory,
 
     /// swtch() here to run process.
     context: Context,
 
-    /// Open files.
-    pub open_files: [Option<RcFile>; NOFILE],
-
-    /// Current directory.
-    cwd: MaybeUninit<RcInode<<Ufs as FileSystem>::InodeInner>>,
+    /// Open files and current directory, which may be shared with other threads.
+    files: *const SharedFiles,
 
     /// Process name (debugging).
     pub name: [u8; MAXPROCNAME],
+
+    /// Resource group.
+    group: usize,
+
+    /// Timer ticks run since the process got the CPU.
+    ticks: usize,
+
+    /// Head of the robust futex list registered by `set_robust_list`, or 0 if there is none.
+    pub robust_list: UVAddr,
+
+    /// Actions taken on the signals, indexed by signal number.
+    sigactions: [SigAction; NSIG],
+
+    /// Real user ID, i.e., the user who owns the process.
+    uid: Uid,
+
+    /// Effective user ID, which the permission checks use.
+    euid: Uid,
+
+    /// Group ID, which the permission checks also use.
+    gid: Gid,
+
+    /// System call ABI of the program running.
+    pub abi: Abi,
+
+    /// The transactions and the inode guards held, counted with the `model-check` feature.
+    pub guards: LiveGuards,
 }
 
 /// Per-process state.
@@ -236,12 +318,25 @@ pub struct ProcData {
 ///
 /// * If `info.state` ≠ `UNUSED`, then
 ///   - `data.trap_frame` is a valid pointer, and `Page::from_usize(data.trap_frame)` is safe.
-///   - `data.memory` has been initialized.
+/// * If `info.state` ∉ { `UNUSED`, `ZOMBIE` }, then
+///   - `data.memory` is a valid pointer to a `SharedMemory` that `self` uses, which maps
+///     `data.trap_frame` at `data.trap_frame_va`.
+/// * If `info.state` ∉ { `UNUSED`, `USED`, `ZOMBIE` }, then
+///   - `data.files` is a valid pointer to a `SharedFiles` that `self` uses.
 /// * If `info.state` ∉ { `UNUSED`, `USED` }, then
-///   - `data.cwd` has been initialized.
 ///   - `parent` contains null or a valid pointer. `parent` can be null only when `self` is the same
 ///     as `initial_proc` of `Procs` that contains `self`.
+/// * If `parent` is not null, `sibling` is linked in the `children` list of `parent`. Otherwise,
+///   `sibling` is unlinked. Both are modified only while holding the `wait_lock`.
+// `sibling` must be the first field. See `from_list_entry`.
+#[repr(C)]
 pub struct Proc {
+    /// Entry in the `children` list of the parent.
+    sibling: ListEntry,
+
+    /// Children of this process, linked through their `sibling` entries.
+    children: List<Proc>,
+
     /// Parent process.
     parent: UnsafeCell<*const Proc>,
 
@@ -252,8 +347,11 @@ pub struct Proc {
     /// Waitchannel saying child proc is dead.
     child_waitchannel: WaitChannel,
 
-    /// If true, the process have been killed.
-    killed: AtomicBool,
+    /// Signals sent to the process.
+    signals: SignalSets,
+
+    /// I/O statistics of this process.
+    io: IoCounters,
 }
 
 /// A branded reference to a `Proc`.
@@ -291,7 +389,7 @@ impl Context {
 }
 
 impl Procstate {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             Procstate::USED => "used",
             Procstate::UNUSED => "unused",
@@ -308,11 +406,25 @@ impl ProcData {
         Self {
             kstack: 0,
             trap_frame: ptr::null_mut(),
-            memory: MaybeUninit::uninit(),
+            trap_frame_va: 0,
+            memory: ptr::null(),
             context: Context::new(),
-            open_files: array![_ => None; NOFILE],
-            cwd: MaybeUninit::uninit(),
+            files: ptr::null(),
             name: [0; MAXPROCNAME],
+            group: 0,
+            ticks: 0,
+            robust_list: UVAddr::from(0),
+            sigactions: [SigAction {
+                handler: 0,
+                mask: 0,
+                flags: 0,
+                restorer: 0,
+            }; NSIG],
+            uid: 0,
+            euid: 0,
+            gid: 0,
+            abi: Abi::Rv6,
+            guards: LiveGuards::new(),
         }
     }
 }
@@ -320,31 +432,64 @@ impl ProcData {
 impl Proc {
     const fn new() -> Self {
         Self {
+            sibling: unsafe { ListEntry::new() },
+            children: unsafe { List::new() },
             parent: UnsafeCell::new(ptr::null()),
             info: SpinLock::new(
                 "proc",
                 ProcInfo {
                     state: Procstate::UNUSED,
                     waitchannel: ptr::null(),
+                    wait_ticket: 0,
                     xstate: 0,
                     pid: 0,
+                    tgid: 0,
+                    pgid: 0,
+                    sid: 0,
+                    cpu: 0,
+                    affinity: ALL_CPUS,
+                    alarm: None,
                 },
             ),
             data: UnsafeCell::new(ProcData::new()),
             child_waitchannel: WaitChannel::new(),
-            killed: AtomicBool::new(false),
+            signals: SignalSets::new(),
+            io: IoCounters::new(),
         }
     }
 }
 
+// SAFETY: `Proc` owns a `ListEntry`.
+unsafe impl ListNode for Proc {
+    fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+        unsafe { Pin::new_unchecked(&self.get_ref().sibling) }
+    }
+
+    fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+        // `sibling` is the first field of the `#[repr(C)]` struct.
+        list_entry as _
+    }
+}
+
 impl Proc {
-    /// Kill and wake the process up.
+    /// Kill the process by sending `SIGKILL`. The caller should wake it up.
     pub fn kill(&self) {
-        self.killed.store(true, Ordering::Release);
+        let _ = self.signals.send(SIGKILL);
     }
 
+    /// Returns true if the process has been killed, or has any other signal to take.
+    /// Interruptible waits return early then, so that the signal is taken soon.
     pub fn killed(&self) -> bool {
-        self.killed.load(Ordering::Acquire)
+        self.signals.has_deliverable()
+    }
+
+    /// Returns the I/O counters charged to this process.
+    pub fn io(&self) -> &IoCounters {
+        &self.io
+    }
+
+    fn children_list(self: Pin<&Self>) -> Pin<&List<Proc>> {
+        unsafe { Pin::new_unchecked(&self.get_ref().children) }
     }
 }
 
@@ -358,6 +503,35 @@ impl<'id, 's> ProcRef<'id, 's> {
         unsafe { &mut *self.parent.get() }
     }
 
+    fn pinned(&self) -> Pin<&'s Proc> {
+        // SAFETY: `Proc`s are pinned in their `Procs`.
+        unsafe { Pin::new_unchecked(self.0.into_inner()) }
+    }
+
+    /// Makes this process a child of `parent`, or an orphan if `parent` is null.
+    fn set_parent(&self, parent: *const Proc, guard: &mut WaitGuard<'id, '_>) {
+        *self.get_mut_parent(guard) = parent;
+        if parent.is_null() {
+            self.pinned().get_list_entry().remove();
+        } else {
+            // SAFETY: `parent` is a valid pointer to a `Proc` of the same `Procs`.
+            let parent = unsafe { Pin::new_unchecked(&*parent) };
+            parent.children_list().push_back(self.pinned());
+        }
+    }
+
+    /// Returns an iterator over the children of this process.
+    /// The `WaitGuard` keeps the children from changing during the iteration.
+    pub fn children<'a>(
+        &'a self,
+        _guard: &'a WaitGuard<'id, '_>,
+    ) -> impl Iterator<Item = ProcRef<'id, 's>> + 'a {
+        // SAFETY: `Proc`s are never dropped, and the lists are modified only while holding the
+        // `wait_lock`, which `_guard` holds.
+        let iter = unsafe { self.pinned().children_list().iter_unchecked() };
+        iter.map(move |child| ProcRef(self.0.brand(child)))
+    }
+
     pub fn lock(&self) -> ProcGuard<'id, 's> {
         mem::forget(self.info.lock());
         ProcGuard { proc: *self }
@@ -405,6 +579,7 @@ impl<'id> ProcGuard<'id, '_> {
     /// break in the few places where a lock is held but
     /// there's no process.
     unsafe fn sched(&mut self) {
+        fcount!(ProcGuard::sched);
         assert!(!intr_get(), "sched interruptible");
         assert_ne!(self.state(), Procstate::RUNNING, "sched running");
 
@@ -421,13 +596,13 @@ impl<'id> ProcGuard<'id, '_> {
         cpu.set_interrupt(interrupt_enabled);
     }
 
-    /// Frees a `Proc` structure and the data hanging from it, including user pages.
-    /// Also, clears `p`'s parent field into `ptr::null_mut()`.
+    /// Frees a `Proc` structure and the data hanging from it. The user pages have been freed by
+    /// `exit_current` already. Also, clears `p`'s parent field into `ptr::null_mut()`.
     /// The caller must provide a `ProcGuard`.
     ///
     /// # Safety
     ///
-    /// `self.info.state` ≠ `UNUSED`
+    /// `self.info.state` = `ZOMBIE`
     unsafe fn clear(&mut self, mut parent_guard: WaitGuard<'id, '_>) {
         // SAFETY: this process cannot be the current process any longer.
         let data = unsafe { self.deref_mut_data() };
@@ -435,43 +610,56 @@ impl<'id> ProcGuard<'id, '_> {
         let allocator = hal().kmem();
         // SAFETY: trap_frame uniquely refers to a valid page.
         allocator.free(unsafe { Page::from_usize(trap_frame as _) });
-        // SAFETY:
-        // * ok to assume_init() because memory has been initialized according to the invariant.
-        // * ok to replace memory with uninit() because state will become UNUSED.
-        unsafe {
-            mem::replace(&mut data.memory, MaybeUninit::uninit())
-                .assume_init()
-                .free(allocator)
-        };
 
         // Clear the name.
         data.name[0] = 0;
+        data.robust_list = UVAddr::from(0);
+        data.sigactions = [SigAction::default(); NSIG];
+        data.abi = Abi::Rv6;
 
         // Clear the process's parent field.
-        *self.get_mut_parent(&mut parent_guard) = ptr::null_mut();
+        self.set_parent(ptr::null(), &mut parent_guard);
         drop(parent_guard);
 
         // Clear the `ProcInfo`.
         let info = self.deref_mut_info();
         info.waitchannel = ptr::null();
         info.pid = 0;
+        info.tgid = 0;
+        info.pgid = 0;
+        info.sid = 0;
+        info.alarm = None;
         info.xstate = 0;
         info.state = Procstate::UNUSED;
 
-        self.killed.store(false, Ordering::Release);
-    }
-
-    /// Wake process from sleep().
-    fn wakeup(&mut self) {
-        if self.state() == Procstate::SLEEPING {
-            self.deref_mut_info().state = Procstate::RUNNABLE;
-        }
+        self.signals.reset();
+        self.io.reset();
     }
 
     pub fn state(&self) -> Procstate {
         self.deref_info().state
     }
 
+    /// Returns the thread ID. Meaningful only if the state is not `UNUSED`.
+    pub fn pid(&self) -> Pid {
+        self.deref_info().pid
+    }
+
+    /// Returns the thread group ID. Meaningful only if the state is not `UNUSED`.
+    pub fn tgid(&self) -> Pid {
+        self.deref_info().tgid
+    }
+
+    /// Returns the process group ID. Meaningful only if the state is not `UNUSED`.
+    pub fn pgid(&self) -> Pid {
+        self.deref_info().pgid
+    }
+
+    /// Returns the session ID. Meaningful only if the state is not `UNUSED`.
+    pub fn sid(&self) -> Pid {
+        self.deref_info().sid
+    }
+
     fn reacquire_after<F, U>(&mut self, f: F) -> U
     where
         F: FnOnce(ProcRef<'id, '_>) -> U,
@@ -1,27 +1,38 @@
 use core::{
+    cmp,
     marker::PhantomPinned,
+    mem,
     ops::Deref,
     pin::Pin,
     ptr, str,
-    sync::atomic::{AtomicI32, Ordering},
+    sync::atomic::{AtomicI32, AtomicUsize, Ordering},
 };
 
 use array_macro::array;
-use itertools::izip;
 use pin_project::pin_project;
+use rv6_abi::{WaitFlags, SIGALRM, SIGCHLD, SIGKILL, TRACE_SCHED_SWITCH};
 
 use super::*;
 use crate::{
     arch::addr::{Addr, UVAddr, PGSIZE},
-    arch::memlayout::kstack,
-    arch::riscv::intr_on,
-    fs::FileSystem,
+    arch::memlayout::{kstack, TRAPFRAME},
+    arch::riscv::{intr_off, intr_on},
+    arch::{Arch, TargetArch},
+    boottime::BootPhase,
+    cpu::cpuid,
+    error::{Errno, KernelError},
+    exec::set_proc_name,
+    fcount,
+    fs::{FileSystem, Path, RcInode, Ufs},
     hal::hal,
-    kalloc::Kmem,
+    iostat::IoStat,
+    kalloc::{Kmem, PageOwner},
     kernel::KernelRef,
     lock::{SpinLock, SpinLockGuard},
+    model::DETERMINISTIC,
     page::Page,
-    param::{NPROC, ROOTDEV},
+    param::{MAXPROCNAME, NGROUP, NPROC, ROOTDEV},
+    some_or,
     util::branded::Branded,
     vm::UserMemory,
 };
@@ -51,6 +62,18 @@ pub struct Procs {
     // memory model when using p->parent.
     // Must be acquired before any p->lock.
     wait_lock: SpinLock<()>,
+    /// Woken up whenever a process exits, for those waiting in `wait_exit`.
+    /// Sleepers hold the `wait_lock`.
+    exit_waitchannel: WaitChannel,
+    groups: [ResourceGroup; NGROUP],
+    /// Number of context switches from the schedulers to processes, shown by `dump`.
+    switches: AtomicUsize,
+    /// The `RUNNABLE` processes, queued on each CPU.
+    runqueues: RunQueues,
+    /// The user memories of the processes.
+    memories: Memories,
+    /// The open file tables of the processes.
+    file_tables: FileTables,
     #[pin]
     _marker: PhantomPinned,
 }
@@ -64,7 +87,9 @@ pub struct Procs {
 /// A `ProcsRef<'id, 's>` can be created only from a `KernelRef<'id, 's>` that has the same `'id` tag.
 pub struct ProcsRef<'id, 's>(Branded<'id, Pin<&'s Procs>>);
 
-struct ProcIter<'id, 'a>(Branded<'id, core::slice::Iter<'a, Proc>>);
+/// An iterator over the `Proc`s of a `Procs`, created by `ProcsRef::process_pool`.
+/// The yielded `ProcRef`s carry the same `'id` tag as the `ProcsRef`.
+pub struct ProcIter<'id, 'a>(Branded<'id, core::slice::Iter<'a, Proc>>);
 
 /// A branded type that holds the guard of a `Procs::wait_lock`.
 ///
@@ -75,6 +100,19 @@ struct ProcIter<'id, 'a>(Branded<'id, core::slice::Iter<'a, Proc>>);
 /// with the same `'id` tag.
 pub struct WaitGuard<'id, 's>(Branded<'id, SpinLockGuard<'s, ()>>);
 
+/// The status of a process, which `/proc/<pid>/status` shows.
+pub struct ProcStatus {
+    pub pid: Pid,
+    pub state: Procstate,
+    pub name: [u8; MAXPROCNAME],
+
+    /// Number of user pages charged for the memory, or None if unknown.
+    pub pages: Option<usize>,
+
+    /// Number of the open files, or None if unknown.
+    pub fds: Option<usize>,
+}
+
 impl Procs {
     pub const fn new() -> Self {
         Self {
@@ -82,6 +120,12 @@ impl Procs {
             process_pool: array![_ => Proc::new(); NPROC],
             initial_proc: ptr::null(),
             wait_lock: SpinLock::new("wait_lock", ()),
+            exit_waitchannel: WaitChannel::new(),
+            groups: array![_ => ResourceGroup::new(); NGROUP],
+            switches: AtomicUsize::new(0),
+            runqueues: RunQueues::new(),
+            memories: Memories::new(),
+            file_tables: FileTables::new(),
             _marker: PhantomPinned,
         }
     }
@@ -92,6 +136,9 @@ impl Procs {
         let this = unsafe { self.get_unchecked_mut() };
         for (i, p) in this.process_pool.iter_mut().enumerate() {
             p.data.get_mut().kstack = kstack(i);
+            // SAFETY: we don't move the `Proc`s.
+            unsafe { Pin::new_unchecked(&mut p.sibling) }.init();
+            unsafe { Pin::new_unchecked(&mut p.children) }.init();
         }
     }
 
@@ -105,19 +152,36 @@ impl Procs {
             let procs = ProcsRef(procs);
 
             // Allocate trap frame.
-            let trap_frame =
-                scopeguard::guard(allocator.alloc().expect("user_proc_init: alloc"), |page| {
-                    allocator.free(page)
-                });
+            let trap_frame = scopeguard::guard(
+                allocator
+                    .alloc(PageOwner::TrapFrame)
+                    .expect("user_proc_init: alloc"),
+                |page| allocator.free(page),
+            );
 
             // Allocate one user page and copy init's instructions
             // and data into it.
             let memory = UserMemory::new(trap_frame.addr(), Some(&INITCODE), allocator)
                 .expect("user_proc_init: UserMemory::new");
+            let pages = pages_of(memory.footprint());
+            procs
+                .group(0)
+                .try_charge(pages)
+                .expect("user_proc_init: try_charge");
+
+            let memory = procs
+                .memories()
+                .alloc(memory)
+                .unwrap_or_else(|_memory| panic!("user_proc_init: Memories::alloc"));
+            memory.set_charged_pages(pages);
 
             let mut guard = procs
-                .alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)
-                .expect("user_proc_init: Procs::alloc");
+                .alloc(
+                    scopeguard::ScopeGuard::into_inner(trap_frame),
+                    memory,
+                    TRAPFRAME,
+                )
+                .unwrap_or_else(|_| panic!("user_proc_init: Procs::alloc"));
 
             // SAFETY: this process cannot be the current process yet.
             let data = unsafe { guard.deref_mut_data() };
@@ -132,11 +196,16 @@ impl Procs {
             // SAFETY: trap_frame has been initialized by alloc.
             unsafe { (*data.trap_frame).sp = PGSIZE };
 
+            data.group = 0;
+
             let name = b"initcode\x00";
             (&mut data.name[..name.len()]).copy_from_slice(name);
-            let _ = data.cwd.write(cwd);
-            // It's safe because cwd now has been initialized.
-            guard.deref_mut_info().state = Procstate::RUNNABLE;
+            data.files = procs
+                .file_tables()
+                .alloc(FileTable::with_cwd(cwd))
+                .unwrap_or_else(|_table| panic!("user_proc_init: FileTables::alloc"));
+            // It's safe because files now has been initialized.
+            procs.make_runnable(&mut guard);
 
             guard.deref().deref() as *const _
         });
@@ -156,13 +225,72 @@ impl Procs {
     fn allocpid(self: Pin<&Self>) -> Pid {
         self.nextpid.fetch_add(1, Ordering::Relaxed)
     }
+
+    /// Returns the index of the process of `guard` in the process pool.
+    fn index_of(&self, guard: &ProcGuard<'_, '_>) -> usize {
+        (&***guard as *const Proc as usize - self.process_pool.as_ptr() as usize)
+            / mem::size_of::<Proc>()
+    }
+
+    /// Marks the process of `guard` runnable, and puts it on the run queue of the CPU it last ran
+    /// on, or of the first CPU its affinity mask allows if that one is not allowed.
+    pub fn make_runnable(&self, guard: &mut ProcGuard<'_, '_>) {
+        let index = self.index_of(guard);
+        let info = guard.deref_mut_info();
+        info.state = Procstate::RUNNABLE;
+        let cpu = if !DETERMINISTIC && info.affinity & (1 << info.cpu) != 0 {
+            info.cpu
+        } else {
+            info.affinity.trailing_zeros() as usize
+        };
+        self.runqueues.push(cpu, index, info.affinity);
+    }
+
+    /// Wakes up the process of `guard` if it is sleeping.
+    fn wakeup_proc(&self, guard: &mut ProcGuard<'_, '_>) {
+        if guard.state() == Procstate::SLEEPING {
+            self.make_runnable(guard);
+        }
+    }
+
+    /// Returns the resource group with the given id, which must be less than `NGROUP`.
+    pub fn group(&self, id: usize) -> &ResourceGroup {
+        &self.groups[id]
+    }
+
+    /// Returns the resource group with the given id.
+    /// Returns Err(()) if there is no such group.
+    pub fn get_group(&self, id: usize) -> Result<&ResourceGroup, ()> {
+        self.groups.get(id).ok_or(())
+    }
 }
 
 impl<'id, 's> ProcsRef<'id, 's> {
-    fn process_pool(&self) -> ProcIter<'id, 's> {
+    /// Returns an iterator over every `Proc` of this `Procs`, including `UNUSED` ones.
+    ///
+    /// The iterator itself does not lock anything. To read the state of a yielded `ProcRef`, lock
+    /// it with `ProcRef::lock`, and check `ProcGuard::state` first since the process may be
+    /// `UNUSED`. Do not hold two `ProcGuard`s at the same time, and acquire the `wait_lock`, if
+    /// needed, before any `ProcGuard`.
+    pub fn process_pool(&self) -> ProcIter<'id, 's> {
         ProcIter::new(self)
     }
 
+    /// Returns the `Proc` at `index` of the process pool.
+    fn proc_at(&self, index: usize) -> ProcRef<'id, 's> {
+        ProcRef(self.0.brand(&self.0.get_ref().process_pool[index]))
+    }
+
+    /// Returns the slots of the user memories.
+    pub fn memories(&self) -> &'s Memories {
+        &self.0.get_ref().memories
+    }
+
+    /// Returns the slots of the open file tables.
+    pub fn file_tables(&self) -> &'s FileTables {
+        &self.0.get_ref().file_tables
+    }
+
     /// Acquires the wait_lock of this `Procs` and returns the `WaitGuard`.
     /// You can access any of this `Procs`'s `Proc::parent` field only after acquiring the `WaitGuard`.
     fn wait_guard(&self) -> WaitGuard<'id, 's> {
@@ -171,9 +299,15 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
     /// Look into process system for an UNUSED proc.
     /// If found, initialize state required to run in the kernel,
-    /// and return with p->lock held.
-    /// If there are no free procs, or a memory allocation fails, return Err.
-    fn alloc(&self, trap_frame: Page, memory: UserMemory) -> Result<ProcGuard<'id, '_>, ()> {
+    /// and return with p->lock held. The new process uses `memory`, which maps `trap_frame` at
+    /// `trap_frame_va`, in place of the caller.
+    /// If there are no free procs, free trap_frame and return Err(()).
+    fn alloc(
+        &self,
+        trap_frame: Page,
+        memory: &SharedMemory,
+        trap_frame_va: usize,
+    ) -> Result<ProcGuard<'id, '_>, ()> {
         for p in self.process_pool() {
             let mut guard = p.lock();
             if guard.deref_info().state == Procstate::UNUSED {
@@ -182,7 +316,8 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
                 // Initialize trap frame and page table.
                 data.trap_frame = trap_frame.into_usize() as _;
-                let _ = data.memory.write(memory);
+                data.trap_frame_va = trap_frame_va;
+                data.memory = memory;
 
                 // Set up new context to start executing at forkret,
                 // which returns to user space.
@@ -192,6 +327,10 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
                 let info = guard.deref_mut_info();
                 info.pid = self.0.allocpid();
+                info.tgid = info.pid;
+                info.pgid = info.pid;
+                info.sid = info.pid;
+                info.affinity = ALL_CPUS;
                 // It's safe because trap_frame and memory now have been initialized.
                 info.state = Procstate::USED;
 
@@ -199,23 +338,57 @@ impl<'id, 's> ProcsRef<'id, 's> {
             }
         }
 
-        let allocator = hal().kmem();
-        allocator.free(trap_frame);
-        memory.free(allocator);
+        hal().kmem().free(trap_frame);
         Err(())
     }
 
     /// Wake up all processes in the pool sleeping on waitchannel.
     /// Must be called without any p->lock.
     pub fn wakeup_pool(&self, target: &WaitChannel, kernel: KernelRef<'_, '_>) {
+        fcount!(ProcsRef::wakeup_pool);
         let current_proc = kernel.current_proc();
         for p in self.process_pool() {
             if p.deref() as *const _ != current_proc {
                 let mut guard = p.lock();
                 if guard.deref_info().waitchannel == target as _ {
-                    guard.wakeup()
+                    self.wakeup_proc(&mut guard)
+                }
+            }
+        }
+    }
+
+    /// Wake up the process that has slept on waitchannel the longest, if any.
+    /// Must be called without any p->lock.
+    pub fn wakeup_one_pool(&self, target: &WaitChannel, kernel: KernelRef<'_, '_>) {
+        let current_proc = kernel.current_proc();
+        loop {
+            // Find the sleeper with the smallest ticket.
+            let mut first = None;
+            for p in self.process_pool() {
+                if p.deref() as *const _ != current_proc {
+                    let guard = p.lock();
+                    let info = guard.deref_info();
+                    if info.state == Procstate::SLEEPING && info.waitchannel == target as _ {
+                        let ticket = info.wait_ticket;
+                        drop(guard);
+                        if first.as_ref().map_or(true, |(_, t)| ticket < *t) {
+                            first = Some((p, ticket));
+                        }
+                    }
                 }
             }
+
+            let (p, ticket) = some_or!(first, return);
+            let mut guard = p.lock();
+            let info = guard.deref_info();
+            if info.state == Procstate::SLEEPING
+                && info.waitchannel == target as _
+                && info.wait_ticket == ticket
+            {
+                self.wakeup_proc(&mut guard);
+                return;
+            }
+            // It has been woken up by someone else in the meantime, e.g., by kill(). Try again.
         }
     }
 
@@ -227,12 +400,16 @@ impl<'id, 's> ProcsRef<'id, 's> {
         parent_guard: &'b mut WaitGuard<'id, '_>,
         kernel: KernelRef<'_, '_>,
     ) {
-        for pp in self.process_pool() {
-            let parent = pp.get_mut_parent(parent_guard);
-            if *parent == proc {
-                *parent = self.0.initial_proc();
-                self.0.initial_proc().child_waitchannel.wakeup(kernel);
-            }
+        // SAFETY: `proc` is a valid pointer to a `Proc` of this `Procs`.
+        let proc = ProcRef(self.0.brand(unsafe { &*proc }));
+        let mut found = false;
+        loop {
+            let child = some_or!(proc.children(parent_guard).next(), break);
+            child.set_parent(self.0.initial_proc(), parent_guard);
+            found = true;
+        }
+        if found {
+            self.0.initial_proc().child_waitchannel.wakeup(kernel);
         }
     }
 
@@ -246,23 +423,65 @@ impl<'id, 's> ProcsRef<'id, 's> {
     /// Otherwise, UB may happen if the new `Proc` tries to read its `parent` field
     /// that points to a `Proc` that already dropped.
     pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+        // Charge the child's memory to the parent's resource group.
+        let group = ctx.proc().deref_data().group;
+        let charged_pages = ctx.proc().shared_memory().charged_pages();
+        self.group(group).try_charge(charged_pages)?;
+        let charge = scopeguard::guard((), |_| self.group(group).uncharge(charged_pages));
+
         let allocator = hal().kmem();
         // Allocate trap frame.
         let trap_frame =
-            scopeguard::guard(allocator.alloc().ok_or(())?, |page| allocator.free(page));
+            scopeguard::guard(allocator.alloc(PageOwner::TrapFrame).ok_or(())?, |page| {
+                allocator.free(page)
+            });
 
         // Copy user memory from parent to child.
+        let cache = ctx.kernel().page_cache();
         let memory = ctx
-            .proc_mut()
-            .memory_mut()
-            .clone(trap_frame.addr(), allocator)
+            .proc()
+            .memory()
+            .clone(trap_frame.addr(), cache, allocator)
             .ok_or(())?;
 
         // Allocate process.
-        let mut np = self.alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)?;
+        let memory = self.memories().alloc(memory).map_err(|mut memory| {
+            memory.unmap_shared(cache);
+            memory.free(allocator)
+        })?;
+        let free_memory = || {
+            let (mut memory, _) = memory.release().expect("fork");
+            memory.unmap_shared(cache);
+            memory.free(allocator)
+        };
+        let table = ctx.proc().files().inherit(false);
+        let files = self.file_tables().alloc(table).map_err(|table| {
+            table.free(ctx);
+            free_memory()
+        })?;
+        let mut np = self
+            .alloc(
+                scopeguard::ScopeGuard::into_inner(trap_frame),
+                memory,
+                TRAPFRAME,
+            )
+            .map_err(|_| {
+                files.release().expect("fork").free(ctx);
+                free_memory()
+            })?;
         // SAFETY: this process cannot be the current process yet.
         let npdata = unsafe { np.deref_mut_data() };
 
+        // The child now owns the charge.
+        scopeguard::ScopeGuard::into_inner(charge);
+        memory.set_charged_pages(charged_pages);
+        npdata.group = group;
+        npdata.ticks = 0;
+        npdata.robust_list = UVAddr::from(0);
+        npdata.sigactions = ctx.proc().deref_data().sigactions;
+        npdata.abi = ctx.proc().deref_data().abi;
+        np.signals.inherit(&ctx.proc().signals);
+
         // Copy saved user registers.
         // SAFETY: trap_frame has been initialized by alloc.
         unsafe { *npdata.trap_frame = *ctx.proc().trap_frame() };
@@ -271,74 +490,259 @@ impl<'id, 's> ProcsRef<'id, 's> {
         // SAFETY: trap_frame has been initialized by alloc.
         unsafe { (*npdata.trap_frame).a0 = 0 };
 
-        // Increment reference counts on open file descriptors.
-        for (nf, f) in izip!(
-            npdata.open_files.iter_mut(),
-            ctx.proc().deref_data().open_files.iter()
-        ) {
-            if let Some(file) = f {
-                *nf = Some(file.clone());
-            }
+        Ok(self.start_child(np, files, ctx))
+    }
+
+    /// Create a new process running the program at `path` with `args`, as if the current
+    /// process forked and the child called exec(). Unlike fork(), the parent's memory is not
+    /// copied at all.
+    /// Returns Ok(new process id) on success, Err(error) on error.
+    pub fn spawn(
+        &self,
+        path: &Path,
+        args: &[Page],
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, KernelError> {
+        let allocator = hal().kmem();
+        // Allocate trap frame.
+        let trap_frame =
+            scopeguard::guard(allocator.alloc(PageOwner::TrapFrame).ok_or(())?, |page| {
+                allocator.free(page)
+            });
+
+        // Build the child's user memory directly from the program.
+        let image = ctx.load_image(path, args, trap_frame.addr())?;
+        let image = scopeguard::guard(image, |image| image.memory.free(allocator));
+
+        // Charge the child's memory to the parent's resource group.
+        let group = ctx.proc().deref_data().group;
+        let charged_pages = pages_of(image.memory.footprint());
+        self.group(group).try_charge(charged_pages)?;
+        let charge = scopeguard::guard((), |_| self.group(group).uncharge(charged_pages));
+
+        // Set up the registers to start the program, before the memory moves into a slot.
+        let image = scopeguard::ScopeGuard::into_inner(image);
+        let mut regs = TrapFrame::default();
+        image.start(&mut regs);
+
+        // Allocate process.
+        let memory = self
+            .memories()
+            .alloc(image.memory)
+            .map_err(|memory| memory.free(allocator))?;
+        let table = ctx.proc().files().inherit(true);
+        let files = self.file_tables().alloc(table).map_err(|table| {
+            table.free(ctx);
+            memory.release().expect("spawn").0.free(allocator)
+        })?;
+        let mut np = self
+            .alloc(
+                scopeguard::ScopeGuard::into_inner(trap_frame),
+                memory,
+                TRAPFRAME,
+            )
+            .map_err(|_| {
+                files.release().expect("spawn").free(ctx);
+                memory.release().expect("spawn").0.free(allocator)
+            })?;
+        // SAFETY: this process cannot be the current process yet.
+        let npdata = unsafe { np.deref_mut_data() };
+
+        // The child now owns the charge.
+        scopeguard::ScopeGuard::into_inner(charge);
+        memory.set_charged_pages(charged_pages);
+        npdata.group = group;
+        npdata.ticks = 0;
+        npdata.robust_list = UVAddr::from(0);
+        npdata.abi = image.abi;
+
+        // Start the program in the child.
+        // SAFETY: trap_frame has been initialized by alloc.
+        unsafe { *npdata.trap_frame = regs };
+
+        set_proc_name(&mut npdata.name, path);
+
+        Ok(self.start_child(np, files, ctx))
+    }
+
+    /// Create a new thread of the current process, which shares its memory, its open files, and
+    /// its current directory, and starts running at `entry` with `arg` in a0 and `stack` as the
+    /// stack pointer. If `tls` is `Some`, it is the thread pointer of the new thread.
+    /// Returns Ok(new thread id) on success, Err(()) on error.
+    pub fn clone(
+        &self,
+        entry: usize,
+        arg: usize,
+        stack: usize,
+        tls: Option<usize>,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, ()> {
+        let allocator = hal().kmem();
+        // Allocate trap frame.
+        let trap_frame =
+            scopeguard::guard(allocator.alloc(PageOwner::TrapFrame).ok_or(())?, |page| {
+                allocator.free(page)
+            });
+
+        // Share the memory, with the trap frame at a slot of its own.
+        let memory = ctx.proc().shared_memory();
+        let trap_frame_va = ctx
+            .proc()
+            .memory()
+            .map_trap_frame(trap_frame.addr(), allocator)?;
+        memory.share();
+
+        // Allocate process.
+        let mut np = self
+            .alloc(
+                scopeguard::ScopeGuard::into_inner(trap_frame),
+                memory,
+                trap_frame_va,
+            )
+            .map_err(|_| {
+                ctx.proc().memory().unmap_trap_frame(trap_frame_va);
+                // The current process still uses the memory.
+                let _ = memory.release();
+            })?;
+        // SAFETY: this process cannot be the current process yet.
+        let npdata = unsafe { np.deref_mut_data() };
+
+        npdata.group = ctx.proc().deref_data().group;
+        npdata.ticks = 0;
+        npdata.robust_list = UVAddr::from(0);
+        npdata.sigactions = ctx.proc().deref_data().sigactions;
+        npdata.abi = ctx.proc().deref_data().abi;
+        np.signals.inherit(&ctx.proc().signals);
+
+        // Start at entry, on the given stack.
+        let mut regs = *ctx.proc().trap_frame();
+        regs.epc = entry;
+        regs.sp = stack;
+        regs.a0 = arg;
+        if let Some(tls) = tls {
+            regs.tp = tls;
         }
-        let _ = npdata.cwd.write(ctx.proc().cwd().clone());
+        // SAFETY: trap_frame has been initialized by alloc.
+        unsafe { *npdata.trap_frame = regs };
+
+        // Join the thread group of the current process.
+        np.deref_mut_info().tgid = ctx.proc().tgid();
+
+        // Share the open files and the current directory.
+        let files = ctx.proc().shared_files();
+        files.share();
+
+        Ok(self.start_child(np, files, ctx))
+    }
+
+    /// Lets the new process `np` use the open file table `files`, makes it a child of the
+    /// current process, and marks it runnable.
+    /// Returns the pid of `np`.
+    fn start_child(
+        &self,
+        mut np: ProcGuard<'id, '_>,
+        files: &SharedFiles,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Pid {
+        // SAFETY: this process cannot be the current process yet.
+        let npdata = unsafe { np.deref_mut_data() };
+
+        npdata.files = files;
 
         npdata.name.copy_from_slice(&ctx.proc().deref_data().name);
+        npdata.uid = ctx.proc().uid();
+        npdata.euid = ctx.proc().euid();
+        npdata.gid = ctx.proc().gid();
 
         let pid = np.deref_mut_info().pid;
 
         // Now drop the guard before we acquire the `wait_lock`.
         // This is because the lock order must be `wait_lock` -> `Proc::info`.
-        np.reacquire_after(|np| {
+        let (parent_guard, pgid, sid, affinity) = np.reacquire_after(|np| {
             // Acquire the `wait_lock`, and write the parent field.
             let mut parent_guard = self.wait_guard();
-            *np.get_mut_parent(&mut parent_guard) = ctx.proc().deref().deref();
+            np.set_parent(ctx.proc().deref().deref(), &mut parent_guard);
+            let guard = ctx.proc().lock();
+            (
+                parent_guard,
+                guard.pgid(),
+                guard.sid(),
+                guard.deref_info().affinity,
+            )
         });
 
-        // Set the process's state to RUNNABLE.
-        // It does not break the invariant because cwd now has been initialized.
-        np.deref_mut_info().state = Procstate::RUNNABLE;
+        // Join the process group and the session of the current process. The `wait_lock` keeps
+        // them from changing meanwhile.
+        let info = np.deref_mut_info();
+        info.pgid = pgid;
+        info.sid = sid;
+        info.affinity = affinity;
+        drop(parent_guard);
+
+        // Set the process's state to RUNNABLE, on the run queue of this CPU.
+        // It does not break the invariant because files now has been initialized.
+        // The guard keeps interrupts off, so cpuid() is this CPU.
+        np.deref_mut_info().cpu = cpuid();
+        self.make_runnable(&mut np);
 
-        Ok(pid)
+        pid
     }
 
-    /// Wait for a child process to exit and return its pid.
-    /// Return Err(()) if this process has no children.
-    pub fn wait(&self, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    /// Wait for a child process to exit and return its pid. If `pid` is positive, waits for the
+    /// child with the pid, and for any child otherwise. With `WNOHANG`, returns 0 instead of
+    /// waiting if no such child has exited yet.
+    /// Return Err(()) if this process has no such children.
+    pub fn waitpid(
+        &self,
+        pid: Pid,
+        addr: UVAddr,
+        options: WaitFlags,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, ()> {
         let mut parent_guard = self.wait_guard();
+        let proc = *ctx.proc().deref();
 
         loop {
-            // Scan through pool looking for exited children.
+            // Scan through the children looking for an exited one.
             let mut havekids = false;
-            for np in self.process_pool() {
-                if *np.get_mut_parent(&mut parent_guard) == ctx.proc().deref().deref() {
-                    // Found a child.
-                    // Make sure the child isn't still in exit() or swtch().
-                    let mut np = np.lock();
-
-                    havekids = true;
-                    if np.state() == Procstate::ZOMBIE {
-                        let pid = np.deref_mut_info().pid;
-                        if !addr.is_null()
-                            && ctx
-                                .proc_mut()
-                                .memory_mut()
-                                .copy_out(addr, &np.deref_info().xstate)
-                                .is_err()
-                        {
-                            return Err(());
-                        }
-                        // Reap the zombie child process.
-                        // SAFETY: np.state() equals ZOMBIE.
-                        unsafe { np.clear(parent_guard) };
-                        return Ok(pid);
-                    }
+            let mut zombie = None;
+            for np in proc.children(&parent_guard) {
+                if pid > 0 && np.pid() != pid {
+                    continue;
                 }
+                havekids = true;
+                // Make sure the child isn't still in exit() or swtch().
+                let np = np.lock();
+                if np.state() == Procstate::ZOMBIE {
+                    zombie = Some(np);
+                    break;
+                }
+            }
+
+            if let Some(mut np) = zombie {
+                let pid = np.deref_mut_info().pid;
+                if !addr.is_null()
+                    && ctx
+                        .proc()
+                        .memory()
+                        .copy_out(addr, &np.deref_info().xstate)
+                        .is_err()
+                {
+                    return Err(());
+                }
+                // Reap the zombie child process.
+                // SAFETY: np.state() equals ZOMBIE.
+                unsafe { np.clear(parent_guard) };
+                return Ok(pid);
             }
 
             // No point waiting if we don't have any children.
             if !havekids || ctx.proc().killed() {
                 return Err(());
             }
+            if options.contains(WaitFlags::WNOHANG) {
+                return Ok(0);
+            }
 
             // Wait for a child to exit.
             //DOC: wait-sleep
@@ -346,46 +750,432 @@ impl<'id, 's> ProcsRef<'id, 's> {
         }
     }
 
-    /// Kill the process with the given pid.
-    /// The victim won't exit until it tries to return
+    /// Returns true if a thread of the process with the given pid has not exited yet.
+    pub fn is_running(&self, pid: Pid) -> bool {
+        self.process_pool().any(|p| {
+            let guard = p.lock();
+            !matches!(guard.state(), Procstate::UNUSED | Procstate::ZOMBIE) && guard.tgid() == pid
+        })
+    }
+
+    /// Wait for the process with the given pid to exit. Unlike wait(), the process need not be
+    /// a child of the current process, and it is not reaped.
+    /// Returns Ok(()) on success, Err(()) if the current process is killed while waiting.
+    pub fn wait_exit(&self, pid: Pid, ctx: &mut KernelCtx<'id, '_>) -> Result<(), ()> {
+        let mut wait_guard = self.wait_guard();
+        // A process becomes a zombie while holding the wait_lock, so the wakeup cannot be lost.
+        while self.is_running(pid) {
+            if ctx.proc().killed() {
+                return Err(());
+            }
+            self.exit_waitchannel.sleep(&mut wait_guard.0, ctx);
+        }
+        Ok(())
+    }
+
+    /// Returns true if `p` is the initial process, i.e., init.
+    pub fn is_initial(&self, p: &Proc) -> bool {
+        ptr::eq(p, self.0.initial_proc())
+    }
+
+    /// Send signal sig to the process with the given pid, or to every process in the process group
+    /// -pid if pid is negative, or only check that the processes exist if sig is 0. `SIGKILL` goes
+    /// to every thread of a process, and any other signal to the first thread found, or to the main
+    /// thread for a group. The initial process discards `SIGKILL`, since the kernel cannot run
+    /// without it.
+    /// The victim won't take the signal until it tries to return
     /// to user space (see usertrap() in trap.c).
     /// Returns Ok(()) on success, Err(()) on error.
-    pub fn kill(&self, pid: Pid) -> Result<(), ()> {
+    pub fn kill(&self, pid: Pid, sig: i32) -> Result<(), ()> {
+        let mut found = false;
         for p in self.process_pool() {
             let mut guard = p.lock();
-            if guard.deref_info().pid == pid {
-                p.kill();
-                guard.wakeup();
+            if guard.state() == Procstate::UNUSED {
+                continue;
+            }
+            let is_target = if pid < 0 {
+                guard.pgid() == pid.wrapping_neg()
+                    && (sig == SIGKILL || guard.pid() == guard.tgid())
+            } else {
+                guard.tgid() == pid
+            };
+            if is_target {
+                found = true;
+                let discarded = sig == 0 || (sig == SIGKILL && self.is_initial(&p));
+                if !discarded && p.signals.send(sig) {
+                    self.wakeup_proc(&mut guard);
+                }
+                if pid > 0 && sig != SIGKILL {
+                    break;
+                }
+            }
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Arms the real-time interval timer of the process `tgid` to expire after `value` ticks and
+    /// then every `interval` ticks, or disarms it if `value` is 0. `now` is the tick count.
+    /// Returns Ok((ticks left, interval)) of the previous setting, or Ok((0, 0)) if it was not
+    /// armed. Returns Err(()) if there is no such process.
+    pub fn set_alarm(
+        &self,
+        tgid: Pid,
+        value: u32,
+        interval: u32,
+        now: u32,
+    ) -> Result<(u32, u32), ()> {
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.state() == Procstate::UNUSED || guard.pid() != tgid {
+                continue;
+            }
+            let info = guard.deref_mut_info();
+            let old = info.alarm.map_or((0, 0), |alarm| {
+                // An expired timer still armed is about to be handled.
+                let left = cmp::max(alarm.deadline.wrapping_sub(now) as i32, 1);
+                (left as u32, alarm.interval)
+            });
+            info.alarm = if value == 0 {
+                None
+            } else {
+                // The next tick may come at once, so count one more to wait at least `value`.
+                Some(Alarm {
+                    deadline: now.wrapping_add(value).wrapping_add(1),
+                    interval,
+                })
+            };
+            return Ok(old);
+        }
+        Err(())
+    }
+
+    /// Sends `SIGALRM` to the processes whose interval timer has expired by the tick count
+    /// `ticks`, and rearms the periodic ones. Called by the clock interrupt handler.
+    pub fn expire_alarms(&self, ticks: u32) {
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            let alarm = match guard.deref_info().alarm {
+                Some(alarm) if ticks.wrapping_sub(alarm.deadline) as i32 >= 0 => alarm,
+                _ => continue,
+            };
+            guard.deref_mut_info().alarm = if alarm.interval == 0 {
+                None
+            } else {
+                Some(Alarm {
+                    deadline: ticks.wrapping_add(alarm.interval),
+                    interval: alarm.interval,
+                })
+            };
+            if p.signals.send(SIGALRM) {
+                self.wakeup_proc(&mut guard);
+            }
+        }
+    }
+
+    /// Returns the pid of the parent of the current process, or 0 if it has none, i.e., it is
+    /// the initial process. The parent of a thread made by `clone` is the thread that made it, so
+    /// follows the parents up to a thread of another process.
+    pub fn getppid(&self, ctx: &KernelCtx<'id, '_>) -> Pid {
+        let tgid = ctx.proc().tgid();
+        let mut wait_guard = self.wait_guard();
+        let mut proc = *ctx.proc().deref();
+        loop {
+            let parent = *proc.get_mut_parent(&mut wait_guard);
+            if parent.is_null() {
+                return 0;
+            }
+            // SAFETY: `parent` is a valid pointer to a `Proc` of this `Procs`.
+            proc = ProcRef(self.0.brand(unsafe { &*parent }));
+            let ptgid = proc.lock().tgid();
+            if ptgid != tgid {
+                return ptgid;
+            }
+        }
+    }
+
+    /// Returns the process group ID of the process with the given pid.
+    /// Returns Err(()) if there is no such process.
+    pub fn getpgid(&self, pid: Pid) -> Result<Pid, ()> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.state() != Procstate::UNUSED && guard.tgid() == pid {
+                return Ok(guard.pgid());
+            }
+        }
+        Err(())
+    }
+
+    /// Moves the process with the given pid, which must be the current process or its child, to
+    /// the process group pgid of the session of the current process. The process either starts a
+    /// group of its own, i.e., pgid = pid, or joins an existing group. A session leader cannot
+    /// move.
+    /// Returns Ok(()) on success, Err(ESRCH) if there is no such process, and Err(EPERM) if it
+    /// cannot move to the group.
+    pub fn setpgid(
+        &self,
+        pid: Pid,
+        pgid: Pid,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), KernelError> {
+        let tgid = ctx.proc().tgid();
+        // The `wait_lock` keeps the parents, the groups and the sessions from changing.
+        let mut wait_guard = self.wait_guard();
+        let sid = ctx.proc().lock().sid();
+
+        // The parent of a process is that of its main thread.
+        let target = self
+            .process_pool()
+            .find(|p| {
+                let guard = p.lock();
+                !matches!(guard.state(), Procstate::UNUSED | Procstate::ZOMBIE)
+                    && guard.pid() == pid
+                    && guard.tgid() == pid
+            })
+            .ok_or(Errno::ESRCH)?;
+        if pid != tgid {
+            let parent = *target.get_mut_parent(&mut wait_guard);
+            if parent.is_null() {
+                return Err(Errno::ESRCH.into());
+            }
+            // SAFETY: `parent` is a valid pointer to a `Proc` of this `Procs`.
+            let parent = ProcRef(self.0.brand(unsafe { &*parent }));
+            if parent.lock().tgid() != tgid {
+                return Err(Errno::ESRCH.into());
+            }
+        }
+        {
+            let guard = target.lock();
+            if guard.sid() == pid || guard.sid() != sid {
+                return Err(Errno::EPERM.into());
+            }
+        }
+        if pgid != pid
+            && !self.process_pool().any(|p| {
+                let guard = p.lock();
+                guard.state() != Procstate::UNUSED && guard.pgid() == pgid && guard.sid() == sid
+            })
+        {
+            return Err(Errno::EPERM.into());
+        }
+
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.state() != Procstate::UNUSED && guard.tgid() == pid {
+                guard.deref_mut_info().pgid = pgid;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the current process the leader of a new session and of a new process group in it,
+    /// whose IDs are its pid.
+    /// Returns Ok(pid) on success, Err(()) if the current process already leads a process group,
+    /// or if a process group with its pid exists.
+    pub fn setsid(&self, ctx: &KernelCtx<'id, '_>) -> Result<Pid, ()> {
+        let tgid = ctx.proc().tgid();
+        let _wait_guard = self.wait_guard();
+        if self.process_pool().any(|p| {
+            let guard = p.lock();
+            guard.state() != Procstate::UNUSED && guard.pgid() == tgid
+        }) {
+            return Err(());
+        }
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.state() != Procstate::UNUSED && guard.tgid() == tgid {
+                let info = guard.deref_mut_info();
+                info.pgid = tgid;
+                info.sid = tgid;
+            }
+        }
+        Ok(tgid)
+    }
+
+    /// Sends `SIGKILL` to every process but the initial process and the current one, and waits
+    /// until they have exited, e.g., to shut down the machine. Stops waiting if the current process
+    /// is killed meanwhile.
+    pub fn kill_all(&self, ctx: &mut KernelCtx<'id, '_>) {
+        let tgid = ctx.proc().tgid();
+        let is_victim = |p: &ProcRef<'id, 's>, guard: &ProcGuard<'id, 's>| {
+            !matches!(guard.state(), Procstate::UNUSED | Procstate::ZOMBIE)
+                && guard.tgid() != tgid
+                && !self.is_initial(p)
+        };
+
+        let mut wait_guard = self.wait_guard();
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if is_victim(&p, &guard) && p.signals.send(SIGKILL) {
+                self.wakeup_proc(&mut guard);
+            }
+        }
+        // A process becomes a zombie while holding the wait_lock, so the wakeup cannot be lost.
+        while self.process_pool().any(|p| {
+            let guard = p.lock();
+            is_victim(&p, &guard)
+        }) {
+            if ctx.proc().killed() {
+                return;
+            }
+            self.exit_waitchannel.sleep(&mut wait_guard.0, ctx);
+        }
+    }
+
+    /// Kill the thread tid of the process tgid, unless it is the initial process.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn tgkill(&self, tgid: Pid, tid: Pid) -> Result<(), ()> {
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.state() != Procstate::UNUSED && guard.pid() == tid && guard.tgid() == tgid {
+                if !self.is_initial(&p) {
+                    p.kill();
+                    self.wakeup_proc(&mut guard);
+                }
                 return Ok(());
             }
         }
         Err(())
     }
 
+    /// Lets the thread `pid` run only on the CPUs in the bitmask `affinity`, which must allow
+    /// one. A runnable thread moves to an allowed CPU before it runs next, and a running one when
+    /// it next gives up its CPU.
+    /// Returns Ok(()) on success, Err(()) if there is no such thread.
+    pub fn set_affinity(&self, pid: Pid, affinity: usize) -> Result<(), ()> {
+        assert!(affinity & ALL_CPUS != 0, "set_affinity");
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.state() == Procstate::UNUSED || guard.pid() != pid {
+                continue;
+            }
+            guard.deref_mut_info().affinity = affinity;
+            if guard.state() == Procstate::RUNNABLE {
+                self.runqueues.set_affinity(self.index_of(&guard), affinity);
+            }
+            return Ok(());
+        }
+        Err(())
+    }
+
+    /// Returns the affinity mask of the thread `pid`.
+    /// Returns Ok(mask) on success, Err(()) if there is no such thread.
+    pub fn get_affinity(&self, pid: Pid) -> Result<usize, ()> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.state() != Procstate::UNUSED && guard.pid() == pid {
+                return Ok(guard.deref_info().affinity);
+            }
+        }
+        Err(())
+    }
+
+    /// Returns the number of processes whose state satisfies `pred`.
+    pub fn count(&self, pred: impl Fn(Procstate) -> bool) -> usize {
+        self.process_pool()
+            .filter(|p| pred(p.lock().state()))
+            .count()
+    }
+
+    /// Returns the I/O statistics of the process with the given pid.
+    /// Returns Ok(statistics) on success, Err(()) on error.
+    pub fn iostat(&self, pid: Pid) -> Result<IoStat, ()> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.state() != Procstate::UNUSED && guard.pid() == pid {
+                return Ok(p.io().snapshot());
+            }
+        }
+        Err(())
+    }
+
+    /// Returns the PID of the process whose main thread is in the `index`th slot of the process
+    /// pool, or None if there is no such slot, or it is unused or holds another thread.
+    pub fn pid_at(&self, index: usize) -> Option<Pid> {
+        if index >= NPROC {
+            return None;
+        }
+        let guard = self.proc_at(index).lock();
+        if guard.state() != Procstate::UNUSED && guard.pid() == guard.tgid() {
+            Some(guard.pid())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the status of the process `pid` in the `index`th slot of the process pool, or None
+    /// if the slot holds no such process. The memory and the open files of a process running on
+    /// another CPU are unknown, since it may replace or release them meanwhile.
+    pub fn status_at(
+        &self,
+        index: usize,
+        pid: Pid,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Option<ProcStatus> {
+        if index >= NPROC {
+            return None;
+        }
+        let p = self.proc_at(index);
+        let guard = p.lock();
+        let state = guard.state();
+        if state == Procstate::UNUSED || guard.pid() != pid {
+            return None;
+        }
+        // SAFETY: we only read the data, which the process does not change while it is off the
+        // CPU, and it cannot get on the CPU while we hold its lock. The current process is on
+        // the CPU, but it is us.
+        let data = unsafe { &*p.data.get() };
+        let off_cpu = state != Procstate::RUNNING || pid == ctx.proc().pid();
+        let memory = if off_cpu && state != Procstate::ZOMBIE {
+            data.memory
+        } else {
+            ptr::null()
+        };
+        let files = if off_cpu && !matches!(state, Procstate::USED | Procstate::ZOMBIE) {
+            data.files
+        } else {
+            ptr::null()
+        };
+        let name = data.name;
+        drop(guard);
+
+        // SAFETY: the process used them while we held its lock, and they are slots of `Procs`,
+        // which outlive it. They may be used by another process by now, which makes the numbers
+        // stale but no less safe to read.
+        let pages = unsafe { memory.as_ref() }.map(|memory| memory.charged_pages());
+        let fds = unsafe { files.as_ref() }.map(|files| files.open_count());
+        Some(ProcStatus {
+            pid,
+            state,
+            name,
+            pages,
+            fds,
+        })
+    }
+
+    /// Returns the number of context switches from the schedulers to processes.
+    pub fn switches(&self) -> usize {
+        self.switches.load(Ordering::Relaxed)
+    }
+
     /// Exit the current process.  Does not return.
     /// An exited process remains in the zombie state
     /// until its parent calls wait().
     pub fn exit_current(&self, status: i32, ctx: &mut KernelCtx<'id, '_>) -> ! {
-        assert_ne!(
-            ctx.proc().deref().deref() as *const _,
-            self.0.initial_proc() as _,
-            "init exiting"
-        );
-
-        for i in 0..NOFILE {
-            let files = &mut ctx.proc_mut().deref_mut_data().open_files;
-            if let Some(f) = unsafe { files.get_unchecked_mut(i) }.take() {
-                f.free(ctx);
-            }
+        // The kernel cannot run without init, which reaps the orphans.
+        if self.is_initial(ctx.proc()) {
+            panic!("init exiting with status {}", status);
         }
 
-        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-        // SAFETY:
-        // * CurrentProc's cwd has been initialized.
-        // * It's ok to take cwd because proc will not be used any longer.
-        let cwd = unsafe { ctx.proc_mut().deref_mut_data().cwd.assume_init_read() };
-        cwd.free((&tx, ctx));
-        tx.end(ctx);
+        // Release the resources that subsystems have attached to the process.
+        ctx.run_exit_hooks();
+
+        // Close the files, and free the memory, unless other threads use them.
+        ctx.release_files();
+        ctx.release_memory();
 
         // Give all children to init.
         let mut parent_guard = self.wait_guard();
@@ -398,6 +1188,16 @@ impl<'id, 's> ProcsRef<'id, 's> {
         // * `parent` is a valid pointer according to the invariants of
         //   `Proc` and `CurrentProc`.
         unsafe { (*parent).child_waitchannel.wakeup(ctx.kernel()) };
+        // So might be the holders of pidfds.
+        self.exit_waitchannel.wakeup(ctx.kernel());
+
+        // SAFETY: the same as above.
+        let parent = ProcRef(self.0.brand(unsafe { &*parent }));
+        let mut guard = parent.lock();
+        if parent.signals.send(SIGCHLD) {
+            self.wakeup_proc(&mut guard);
+        }
+        drop(guard);
 
         let mut guard = ctx.proc().lock();
 
@@ -429,8 +1229,20 @@ unsafe fn forkret() -> ! {
         unsafe { ctx.proc().info.unlock() };
         // File system initialization must be run in the context of a
         // regular process (e.g., because it calls sleep), and thus cannot
-        // be run from main().
-        ctx.kernel().fs().init(ROOTDEV, &ctx);
+        // be run from main(). The first process mounts it before any other
+        // process exists.
+        if ctx.kernel().procs().is_initial(ctx.proc()) {
+            // SAFETY: the first process runs forkret() only once, and it
+            // forks the others only after returning to user space. It
+            // does not return if mounting fails.
+            if unsafe { ctx.kernel().fs().init(ROOTDEV, &ctx) }.is_err() {
+                ctx.kernel()
+                    .as_ref()
+                    .write_str("forkret: the root file system is corrupted\n");
+                TargetArch::power_off(1);
+            }
+            let _ = ctx.kernel().boot_times().end(BootPhase::FsMount);
+        }
         unsafe { ctx.user_trap_ret() }
     };
 
@@ -460,33 +1272,54 @@ impl<'id, 's> KernelRef<'id, 's> {
     /// Per-CPU process scheduler.
     /// Each CPU calls scheduler() after setting itself up.
     /// Scheduler never returns.  It loops, doing:
-    ///  - choose a process to run.
+    ///  - run the softirqs pending on this CPU.
+    ///  - choose a process to run from the run queues, or wait for an interrupt if there is none.
     ///  - swtch to start running that process.
     ///  - eventually that process transfers control
     ///    via swtch back to the scheduler.
     pub unsafe fn scheduler(self) -> ! {
         // SAFETY: this function never moves to another CPU.
         let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
+        let id = cpuid();
         cpu.set_proc(ptr::null_mut());
         loop {
             // Avoid deadlock by ensuring that devices can interrupt.
             unsafe { intr_on() };
 
-            for p in self.procs().process_pool() {
-                let mut guard = p.lock();
-                if guard.state() == Procstate::RUNNABLE {
-                    // Switch to chosen process.  It is the process's job
-                    // to release its lock and then reacquire it
-                    // before jumping back to us.
-                    guard.deref_mut_info().state = Procstate::RUNNING;
-                    cpu.set_proc(p.deref());
-                    unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
-
-                    // Process is done running for now.
-                    // It should have changed its p->state before coming back.
-                    cpu.set_proc(ptr::null_mut());
-                }
+            self.run_softirqs();
+
+            let procs = self.procs();
+            let index = some_or!(procs.runqueues.pop(id), {
+                // Wait for an interrupt, which may make a process runnable, with interrupts
+                // disabled, so that we take it only after checking the queue once more.
+                intr_off();
+                procs.runqueues.idle(id);
+                continue;
+            });
+            let p = procs.proc_at(index);
+            let mut guard = p.lock();
+            assert_eq!(guard.state(), Procstate::RUNNABLE, "scheduler");
+            if guard.deref_info().affinity & (1 << id) == 0 {
+                // The affinity mask has changed since the process was popped.
+                procs.make_runnable(&mut guard);
+                continue;
             }
+
+            // Switch to chosen process.  It is the process's job
+            // to release its lock and then reacquire it
+            // before jumping back to us.
+            let info = guard.deref_mut_info();
+            info.state = Procstate::RUNNING;
+            info.cpu = id;
+            cpu.set_proc(p.deref());
+            let _ = procs.switches.fetch_add(1, Ordering::Relaxed);
+            let pid = guard.deref_info().pid;
+            hal().tracer().record(TRACE_SCHED_SWITCH, [pid as u64, 0]);
+            unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
+
+            // Process is done running for now.
+            // It should have changed its p->state before coming back.
+            cpu.set_proc(ptr::null_mut());
         }
     }
 
@@ -498,7 +1331,10 @@ impl<'id, 's> KernelRef<'id, 's> {
     ///
     /// This method is unsafe and should be used only for debugging.
     pub unsafe fn dump(&self) {
-        self.as_ref().write_str("\n");
+        self.as_ref().write_fmt(format_args!(
+            "\ncontext switches: {}\n",
+            self.procs().switches.load(Ordering::Relaxed)
+        ));
         for p in self.procs().process_pool() {
             let info = p.info.get_mut_raw();
             let state = unsafe { &(*info).state };
@@ -508,7 +1344,8 @@ impl<'id, 's> KernelRef<'id, 's> {
                 // Required since str::from_utf8 cannot recognize interior null characters.
                 let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
                 self.as_ref().write_fmt(format_args!(
-                    "{} {} {}",
+                    "{} {} {} {}",
+                    unsafe { (*info).tgid },
                     unsafe { (*info).pid },
                     Procstate::as_str(state),
                     str::from_utf8(&name[0..length]).unwrap_or("???")
@@ -516,4 +1353,139 @@ impl<'id, 's> KernelRef<'id, 's> {
             }
         }
     }
+
+    /// Print a one-line status to the console for debugging: the number of runnable processes,
+    /// the number of free pages, and the state of the foreground process `fg`, i.e., the process
+    /// that last read from the console.
+    /// Runs when user types ^T on console.
+    /// Doesn't acquire locks in order to avoid wedging a stuck machine further.
+    ///
+    /// # Note
+    ///
+    /// This method is unsafe and should be used only for debugging.
+    pub unsafe fn status(&self, fg: Pid) {
+        let mut runnable = 0;
+        let mut fg_proc = None;
+        for p in self.procs().process_pool() {
+            let info = p.info.get_mut_raw();
+            let state = unsafe { (*info).state };
+            if state == Procstate::RUNNABLE || state == Procstate::RUNNING {
+                runnable += 1;
+            }
+            if state != Procstate::UNUSED && unsafe { (*info).pid } == fg {
+                fg_proc = Some((p, state));
+            }
+        }
+        self.as_ref().write_fmt(format_args!(
+            "\nload: {} free: {} pages",
+            runnable,
+            hal().kmem().free_pages()
+        ));
+        match fg_proc {
+            Some((p, state)) => {
+                let name = unsafe { &(*p.data.get()).name };
+                let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                self.as_ref().write_fmt(format_args!(
+                    " fg: {} {} {}\n",
+                    fg,
+                    str::from_utf8(&name[0..length]).unwrap_or("???"),
+                    Procstate::as_str(&state).trim_end()
+                ));
+            }
+            None => self.as_ref().write_fmt(format_args!(" fg: none\n")),
+        }
+    }
+
+    /// Print the saved registers of process pid and a backtrace of its kernel stack, and where
+    /// it is sleeping if it is, for debugging. Unlike `dump`, holds the lock of the process, so
+    /// that it stays off the CPU while its kernel stack is walked.
+    /// Returns Ok(()) on success, Err(()) if there is no such process.
+    pub fn taskdump(&self, pid: Pid) -> Result<(), ()> {
+        for p in self.procs().process_pool() {
+            let guard = p.lock();
+            let info = guard.deref_info();
+            if info.state == Procstate::UNUSED || info.pid != pid {
+                continue;
+            }
+
+            // SAFETY: we only read the data, which the process does not change while it is off
+            // the CPU, and it cannot get on the CPU while we hold its lock.
+            let data = unsafe { &*p.data.get() };
+            let name = &data.name;
+            let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+            self.as_ref().write_fmt(format_args!(
+                "{} {} {}\n",
+                pid,
+                str::from_utf8(&name[0..length]).unwrap_or("???"),
+                Procstate::as_str(&info.state).trim_end()
+            ));
+            match info.state {
+                // Its saved registers are stale.
+                Procstate::RUNNING => return Ok(()),
+                Procstate::SLEEPING => {
+                    let chan = info.waitchannel;
+                    if ptr::eq(chan, &p.child_waitchannel) {
+                        self.as_ref()
+                            .write_fmt(format_args!("waiting for a child to exit\n"));
+                    } else if ptr::eq(chan, &self.procs().exit_waitchannel) {
+                        self.as_ref()
+                            .write_fmt(format_args!("waiting for a pidfd to be ready\n"));
+                    } else if self.sleep_queue().contains(chan) {
+                        self.as_ref()
+                            .write_fmt(format_args!("sleeping on a timer\n"));
+                    } else {
+                        self.as_ref()
+                            .write_fmt(format_args!("sleeping on {:p}\n", chan));
+                    }
+                }
+                _ => (),
+            }
+
+            let c = &data.context;
+            self.as_ref().write_fmt(format_args!(
+                "ra {:#x} sp {:#x}\ns0 {:#x} s1 {:#x} s2 {:#x} s3 {:#x}\n\
+                 s4 {:#x} s5 {:#x} s6 {:#x} s7 {:#x}\ns8 {:#x} s9 {:#x} s10 {:#x} s11 {:#x}\n",
+                c.ra,
+                c.sp,
+                c.s0,
+                c.s1,
+                c.s2,
+                c.s3,
+                c.s4,
+                c.s5,
+                c.s6,
+                c.s7,
+                c.s8,
+                c.s9,
+                c.s10,
+                c.s11
+            ));
+
+            // Walk the frame pointers, which the kernel keeps, from the frame that called swtch.
+            // The return address of a frame lies just below its frame pointer, and the frame
+            // pointer of its caller below that.
+            self.as_ref().write_fmt(format_args!("backtrace:\n"));
+            let mut fp = c.s0;
+            while fp % mem::size_of::<usize>() == 0
+                && fp > data.kstack + 2 * mem::size_of::<usize>()
+                && fp <= data.kstack + PGSIZE
+            {
+                // SAFETY: fp - 16 and fp - 8 lie within the kernel stack, which is mapped.
+                let (ra, prev) = unsafe {
+                    (
+                        *((fp - mem::size_of::<usize>()) as *const usize),
+                        *((fp - 2 * mem::size_of::<usize>()) as *const usize),
+                    )
+                };
+                self.as_ref().write_fmt(format_args!("{:#x}\n", ra));
+                // The callers' frames lie above.
+                if prev <= fp {
+                    break;
+                }
+                fp = prev;
+            }
+            return Ok(());
+        }
+        Err(())
+    }
 }
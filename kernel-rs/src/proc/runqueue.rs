@@ -0,0 +1,207 @@
+//! Per-CPU run queues.
+//!
+//! Every `RUNNABLE` process is in exactly one run queue, by its index in the process pool. A
+//! process joins the queue of the CPU it last ran on when it becomes runnable, and the scheduler
+//! of each CPU pops from its own queue, so that picking the next process takes no `Proc::info`
+//! lock but that of the picked one.
+//!
+//! The queues are balanced by work stealing. A CPU whose queue is empty steals from another CPU,
+//! and so does a CPU whose queue is shorter than the longest one by more than one process, so that
+//! a few busy CPUs do not keep many processes waiting while the others are idle.
+//!
+//! A process may run only on the CPUs in its affinity mask, which the queue keeps along with its
+//! index. A CPU pops or steals the first process allowed on it, which may not be at the head.
+//!
+//! A CPU with nothing to run waits for an interrupt in `idle`. Putting a process on its queue
+//! interrupts it, and so does putting one on a queue that has another waiting, so that the idle CPU
+//! steals it. Otherwise, the next tick ends the wait.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use array_macro::array;
+use static_assertions::const_assert;
+
+use crate::{
+    arch::{timer::send_ipi, Arch, TargetArch},
+    lock::SpinLock,
+    model::DETERMINISTIC,
+    param::{NCPU, NPROC},
+};
+
+/// The affinity mask that allows every CPU. The bit `1 << id` allows the CPU `id`.
+pub const ALL_CPUS: usize = (1 << NCPU) - 1;
+
+const_assert!(NCPU < usize::BITS as usize);
+
+/// A process on a run queue.
+#[derive(Clone, Copy)]
+struct Queued {
+    /// Index in the process pool.
+    index: usize,
+
+    /// The affinity mask of the process.
+    affinity: usize,
+}
+
+/// FIFO of processes.
+struct Ring {
+    procs: [Queued; NPROC],
+
+    /// Position of the first index.
+    head: usize,
+
+    /// Number of the indices.
+    len: usize,
+}
+
+impl Ring {
+    fn push(&mut self, queued: Queued) {
+        assert!(self.len < NPROC, "Ring::push");
+        self.procs[(self.head + self.len) % NPROC] = queued;
+        self.len += 1;
+    }
+
+    /// Removes the first process allowed to run on `cpu`, and returns its index.
+    fn pop(&mut self, cpu: usize) -> Option<usize> {
+        let pos = (0..self.len)
+            .find(|i| self.procs[(self.head + i) % NPROC].affinity & (1 << cpu) != 0)?;
+        let index = self.procs[(self.head + pos) % NPROC].index;
+        // Move the processes before it one place back, keeping their order.
+        for i in (0..pos).rev() {
+            self.procs[(self.head + i + 1) % NPROC] = self.procs[(self.head + i) % NPROC];
+        }
+        self.head = (self.head + 1) % NPROC;
+        self.len -= 1;
+        Some(index)
+    }
+
+    /// Changes the affinity mask of the process at `index`, if it is in the ring.
+    fn set_affinity(&mut self, index: usize, affinity: usize) {
+        for i in 0..self.len {
+            let queued = &mut self.procs[(self.head + i) % NPROC];
+            if queued.index == index {
+                queued.affinity = affinity;
+            }
+        }
+    }
+}
+
+struct RunQueue {
+    ring: SpinLock<Ring>,
+
+    /// The length of `ring`, read without the lock to choose a CPU to steal from.
+    len: AtomicUsize,
+
+    /// Whether the CPU is waiting for an interrupt in `idle`.
+    idle: AtomicBool,
+}
+
+impl RunQueue {
+    const fn new() -> Self {
+        Self {
+            ring: SpinLock::new(
+                "runqueue",
+                Ring {
+                    procs: [Queued {
+                        index: 0,
+                        affinity: 0,
+                    }; NPROC],
+                    head: 0,
+                    len: 0,
+                },
+            ),
+            len: AtomicUsize::new(0),
+            idle: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, queued: Queued) {
+        let mut ring = self.ring.lock();
+        ring.push(queued);
+        self.len.store(ring.len, Ordering::Relaxed);
+    }
+
+    fn pop(&self, cpu: usize) -> Option<usize> {
+        let mut ring = self.ring.lock();
+        let index = ring.pop(cpu)?;
+        self.len.store(ring.len, Ordering::Relaxed);
+        Some(index)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+}
+
+pub struct RunQueues {
+    queues: [RunQueue; NCPU],
+}
+
+impl RunQueues {
+    pub const fn new() -> Self {
+        Self {
+            queues: array![_ => RunQueue::new(); NCPU],
+        }
+    }
+
+    /// Puts the process at `index`, which may run on the CPUs in `affinity`, on the queue of
+    /// `cpu`. Interrupts `cpu` if it is idle, or another idle CPU if the process has to wait.
+    pub fn push(&self, cpu: usize, index: usize, affinity: usize) {
+        let queue = &self.queues[cpu];
+        queue.push(Queued { index, affinity });
+        // `idle` reads the length after setting its flag, and we read the flag after pushing,
+        // under the lock, so either it finds the process or we find it idle.
+        if queue.idle.load(Ordering::SeqCst) {
+            send_ipi(cpu);
+        } else if queue.len() > 1 {
+            if let Some(id) = (0..NCPU).find(|&id| {
+                affinity & (1 << id) != 0 && self.queues[id].idle.load(Ordering::SeqCst)
+            }) {
+                send_ipi(id);
+            }
+        }
+    }
+
+    /// Waits on `cpu` for an interrupt, unless a process has joined its queue meanwhile.
+    /// Called by the scheduler of `cpu` with interrupts disabled, after `pop` has found nothing.
+    pub fn idle(&self, cpu: usize) {
+        let queue = &self.queues[cpu];
+        queue.idle.store(true, Ordering::SeqCst);
+        if queue.ring.lock().len == 0 {
+            TargetArch::wait_for_interrupt();
+        }
+        queue.idle.store(false, Ordering::SeqCst);
+    }
+
+    /// Changes the affinity mask of the process at `index`, if it is on a queue, so that only the
+    /// CPUs it allows pop it from there on.
+    pub fn set_affinity(&self, index: usize, affinity: usize) {
+        for queue in &self.queues {
+            queue.ring.lock().set_affinity(index, affinity);
+        }
+    }
+
+    /// Pops the index of the next process for `cpu` to run, stealing from another CPU if `cpu`
+    /// has nothing to run or much less to run than the busiest one. Pops only the processes
+    /// allowed to run on `cpu`. Never steals in the deterministic mode of `model-check`.
+    pub fn pop(&self, cpu: usize) -> Option<usize> {
+        if DETERMINISTIC {
+            return self.queues[cpu].pop(cpu);
+        }
+        let (busiest, max) = self
+            .queues
+            .iter()
+            .map(RunQueue::len)
+            .enumerate()
+            .max_by_key(|(_, len)| *len)?;
+        if busiest != cpu && max > self.queues[cpu].len() + 1 {
+            if let Some(index) = self.queues[busiest].pop(cpu) {
+                return Some(index);
+            }
+        }
+        if let Some(index) = self.queues[cpu].pop(cpu) {
+            return Some(index);
+        }
+        (1..NCPU).find_map(|i| self.queues[(cpu + i) % NCPU].pop(cpu))
+    }
+}
@@ -0,0 +1,296 @@
+//! POSIX-style signals.
+//!
+//! A signal sent to a thread is added to its pending set, and the thread is woken up if it is
+//! sleeping, so that an interruptible wait returns early. Nothing else happens until the thread is
+//! about to return to user space, where `handle_signals` takes the pending signals that are not
+//! blocked. The default action of a signal terminates the process, except for `SIGCHLD`, which is
+//! ignored. For a signal with a handler, the user registers and the blocked set are saved in a
+//! `SigFrame` on the user stack, and the thread returns to the handler instead, with the signal
+//! number in `a0` and the restorer of the action in `ra`. The restorer calls `sigreturn`, which
+//! restores the saved registers and blocked set.
+//!
+//! `SIGKILL` can be neither caught, ignored, nor blocked.
+
+use core::{
+    mem, slice,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use rv6_abi::{
+    SigAction, NSIG, SA_NODEFER, SA_RESETHAND, SIGCHLD, SIGKILL, SIG_BLOCK, SIG_DFL, SIG_IGN,
+    SIG_SETMASK, SIG_UNBLOCK,
+};
+
+use super::*;
+
+/// Returns the set of signals that consists of `sig`.
+const fn sigbit(sig: i32) -> u32 {
+    1 << sig
+}
+
+/// Signals whose default action is ignoring them.
+const DEFAULT_IGNORED: u32 = sigbit(SIGCHLD);
+
+/// Signals that cannot be blocked, including the invalid signal 0.
+const UNBLOCKABLE: u32 = sigbit(0) | sigbit(SIGKILL);
+
+/// Signal sets of a thread, which other threads read or update.
+pub struct SignalSets {
+    /// Signals sent but not yet taken.
+    pending: AtomicU32,
+
+    /// Signals left pending until they are unblocked. Updated only by the thread itself.
+    blocked: AtomicU32,
+
+    /// Signals discarded when sent, i.e., those whose action is ignoring them.
+    /// Updated only by the thread itself.
+    ignored: AtomicU32,
+}
+
+/// Saved on the user stack while a signal handler runs, and restored by `sigreturn`.
+#[repr(C)]
+struct SigFrame {
+    trap_frame: TrapFrame,
+
+    /// The blocked set before the handler was entered.
+    blocked: usize,
+}
+
+impl SignalSets {
+    pub const fn new() -> Self {
+        Self {
+            pending: AtomicU32::new(0),
+            blocked: AtomicU32::new(0),
+            ignored: AtomicU32::new(DEFAULT_IGNORED),
+        }
+    }
+
+    /// Adds `sig` to the pending signals unless it is ignored.
+    /// Returns true if it has been added.
+    pub fn send(&self, sig: i32) -> bool {
+        if sig != SIGKILL && self.ignored.load(Ordering::Acquire) & sigbit(sig) != 0 {
+            return false;
+        }
+        let _ = self.pending.fetch_or(sigbit(sig), Ordering::Release);
+        true
+    }
+
+    /// Returns true if a pending signal is not blocked.
+    pub fn has_deliverable(&self) -> bool {
+        self.pending.load(Ordering::Acquire) & !self.blocked.load(Ordering::Relaxed) != 0
+    }
+
+    fn is_pending(&self, sig: i32) -> bool {
+        self.pending.load(Ordering::Acquire) & sigbit(sig) != 0
+    }
+
+    /// Removes a pending signal that is not blocked, `SIGKILL` first, and returns it.
+    /// Must be called by the thread itself.
+    fn take(&self) -> Option<i32> {
+        let deliverable =
+            self.pending.load(Ordering::Acquire) & !self.blocked.load(Ordering::Relaxed);
+        if deliverable == 0 {
+            return None;
+        }
+        let sig = if deliverable & sigbit(SIGKILL) != 0 {
+            SIGKILL
+        } else {
+            deliverable.trailing_zeros() as i32
+        };
+        // Other threads only add signals, so the signal is still pending.
+        let _ = self.pending.fetch_and(!sigbit(sig), Ordering::AcqRel);
+        Some(sig)
+    }
+
+    fn set_blocked(&self, blocked: u32) {
+        self.blocked
+            .store(blocked & !UNBLOCKABLE, Ordering::Relaxed);
+    }
+
+    fn set_ignored(&self, sig: i32, ignored: bool) {
+        if ignored {
+            let _ = self.ignored.fetch_or(sigbit(sig), Ordering::Release);
+            // Discard the signal if it is already pending.
+            let _ = self.pending.fetch_and(!sigbit(sig), Ordering::AcqRel);
+        } else {
+            let _ = self.ignored.fetch_and(!sigbit(sig), Ordering::Release);
+        }
+    }
+
+    /// Makes the sets those of a thread just forked from `parent`.
+    pub fn inherit(&self, parent: &Self) {
+        self.pending.store(0, Ordering::Relaxed);
+        self.blocked
+            .store(parent.blocked.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.ignored
+            .store(parent.ignored.load(Ordering::Relaxed), Ordering::Release);
+    }
+
+    /// Resets the sets to those of a new thread.
+    pub fn reset(&self) {
+        self.pending.store(0, Ordering::Relaxed);
+        self.blocked.store(0, Ordering::Relaxed);
+        self.ignored.store(DEFAULT_IGNORED, Ordering::Release);
+    }
+}
+
+impl SigFrame {
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `SigFrame` consists of `usize`s without padding.
+        unsafe { slice::from_raw_parts(self as *const _ as *const u8, mem::size_of::<SigFrame>()) }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: `SigFrame` consists of `usize`s without padding, and any bytes make a valid one.
+        unsafe { slice::from_raw_parts_mut(self as *mut _ as *mut u8, mem::size_of::<SigFrame>()) }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Exits the current process if it has been sent `SIGKILL`.
+    pub fn exit_if_killed(&mut self) {
+        if self.proc().signals.is_pending(SIGKILL) {
+            self.kernel().procs().exit_current(-1, self);
+        }
+    }
+
+    /// Takes the pending signals that are not blocked, before returning to user space.
+    /// Exits the current process if the action of one of them is terminating it. Stops at the
+    /// first one with a handler, and sets up the trap frame to return to the handler. The rest are
+    /// taken when the handler calls `sigreturn`.
+    pub fn handle_signals(&mut self) {
+        while let Some(sig) = self.proc().signals.take() {
+            let action = self.proc().deref_data().sigactions[sig as usize];
+            match action.handler {
+                SIG_IGN => {}
+                SIG_DFL if DEFAULT_IGNORED & sigbit(sig) != 0 => {}
+                // The initial process takes only the signals it has handlers for.
+                SIG_DFL if self.kernel().procs().is_initial(self.proc()) => {}
+                SIG_DFL => self.kernel().procs().exit_current(-1, self),
+                _ => {
+                    if self.enter_handler(sig, &action).is_err() {
+                        // The user stack cannot hold the frame.
+                        self.kernel().procs().exit_current(-1, self);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Saves the user registers and the blocked set on the user stack, and sets up the trap frame
+    /// to run the handler of `action` for `sig`.
+    /// Returns Ok(()) on success, Err(()) on error.
+    fn enter_handler(&mut self, sig: i32, action: &SigAction) -> Result<(), ()> {
+        let mut frame = SigFrame {
+            trap_frame: *self.proc().trap_frame(),
+            blocked: self.proc().signals.blocked.load(Ordering::Relaxed) as usize,
+        };
+        // Save only the user registers, so that the user cannot see where the kernel is.
+        // `sigreturn` restores the rest from the trap frame.
+        frame.trap_frame.kernel_satp = 0;
+        frame.trap_frame.kernel_sp = 0;
+        frame.trap_frame.kernel_trap = 0;
+        frame.trap_frame.kernel_hartid = 0;
+        let sp = frame
+            .trap_frame
+            .sp
+            .checked_sub(mem::size_of::<SigFrame>())
+            .ok_or(())?
+            & !0xf;
+        self.proc()
+            .memory()
+            .copy_out_bytes(sp.into(), frame.as_bytes())?;
+
+        let trap_frame = self.proc_mut().trap_frame_mut();
+        trap_frame.sp = sp;
+        trap_frame.epc = action.handler;
+        trap_frame.ra = action.restorer;
+        trap_frame.a0 = sig as usize;
+
+        let mut blocked = frame.blocked as u32 | action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            blocked |= sigbit(sig);
+        }
+        self.proc().signals.set_blocked(blocked);
+        if action.flags & SA_RESETHAND != 0 {
+            self.set_sigaction(sig, SigAction::default());
+        }
+        Ok(())
+    }
+
+    /// Returns from a signal handler, restoring the user registers and the blocked set saved by
+    /// `enter_handler` at the user stack pointer.
+    /// Returns Ok(the restored a0) on success, Err(()) on error.
+    pub fn sigreturn(&mut self) -> Result<usize, ()> {
+        let sp = self.proc().trap_frame().sp;
+        let mut frame = SigFrame {
+            trap_frame: *self.proc().trap_frame(),
+            blocked: 0,
+        };
+        self.proc()
+            .memory()
+            .copy_in_bytes(frame.as_bytes_mut(), sp.into())?;
+
+        // Restore only the user registers, as `enter_handler` has saved only them.
+        let trap_frame = self.proc_mut().trap_frame_mut();
+        frame.trap_frame.kernel_satp = trap_frame.kernel_satp;
+        frame.trap_frame.kernel_sp = trap_frame.kernel_sp;
+        frame.trap_frame.kernel_trap = trap_frame.kernel_trap;
+        frame.trap_frame.kernel_hartid = trap_frame.kernel_hartid;
+        *trap_frame = frame.trap_frame;
+        self.proc().signals.set_blocked(frame.blocked as u32);
+        Ok(frame.trap_frame.a0)
+    }
+
+    /// Sets the action of `sig` to `action` if it is `Some`.
+    /// Returns Ok(the previous action) on success, Err(()) on error.
+    pub fn sigaction(&mut self, sig: i32, action: Option<SigAction>) -> Result<SigAction, ()> {
+        if sig <= 0 || sig as usize >= NSIG {
+            return Err(());
+        }
+        let old = self.proc().deref_data().sigactions[sig as usize];
+        if let Some(action) = action {
+            if sig == SIGKILL || (action.handler > SIG_IGN && action.restorer == 0) {
+                return Err(());
+            }
+            self.set_sigaction(sig, action);
+        }
+        Ok(old)
+    }
+
+    fn set_sigaction(&mut self, sig: i32, action: SigAction) {
+        self.proc_mut().deref_mut_data().sigactions[sig as usize] = action;
+        let ignored = action.handler == SIG_IGN
+            || (action.handler == SIG_DFL && DEFAULT_IGNORED & sigbit(sig) != 0);
+        self.proc().signals.set_ignored(sig, ignored);
+    }
+
+    /// Changes the blocked set with `set` by `how`, which is one of `SIG_BLOCK`, `SIG_UNBLOCK`
+    /// and `SIG_SETMASK`, if `set` is `Some`.
+    /// Returns Ok(the previous blocked set) on success, Err(()) on error.
+    pub fn sigprocmask(&mut self, how: i32, set: Option<u32>) -> Result<u32, ()> {
+        let signals = &self.proc().signals;
+        let old = signals.blocked.load(Ordering::Relaxed);
+        if let Some(set) = set {
+            let blocked = match how {
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old & !set,
+                SIG_SETMASK => set,
+                _ => return Err(()),
+            };
+            signals.set_blocked(blocked);
+        }
+        Ok(old)
+    }
+
+    /// Restores the default actions of the signals that have handlers, since the handlers are
+    /// gone with the old user image. Called by `exec`.
+    pub fn reset_signal_handlers(&mut self) {
+        for sig in 1..NSIG as i32 {
+            if self.proc().deref_data().sigactions[sig as usize].handler > SIG_IGN {
+                self.set_sigaction(sig, SigAction::default());
+            }
+        }
+    }
+}
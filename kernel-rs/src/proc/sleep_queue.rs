@@ -0,0 +1,104 @@
+//! Timed sleep.
+//!
+//! A process sleeping until tick `deadline` sleeps on slot `deadline % NSLEEPSLOT` of a timer
+//! wheel, and each clock tick wakes up only the slot of the current tick. Hence, a process is
+//! woken up at its deadline, and before that only once every `NSLEEPSLOT` ticks at most, instead
+//! of on every tick.
+//!
+//! A deadline finer than a tick, given in timer cycles, is served by the one-shot timer of a CPU
+//! instead (see `arch::timer`). A process sleeps on the wheel while its deadline is more than two
+//! ticks away, then arms the one-shot timer of its CPU and sleeps on `oneshot`. Every expiration
+//! of a one-shot timer wakes up all the processes on `oneshot`, which rearm the timer of their
+//! CPU with their own deadlines, as a CPU keeps only the earliest one.
+
+use core::ptr;
+
+use array_macro::array;
+
+use super::{KernelCtx, WaitChannel};
+use crate::{
+    arch::{timer::TICK_INTERVAL, TargetArch, TimeManager},
+    kernel::KernelRef,
+    lock::SleepableLockGuard,
+    param::NSLEEPSLOT,
+};
+
+pub struct SleepQueue {
+    slots: [WaitChannel; NSLEEPSLOT],
+
+    /// Processes waiting for a one-shot timer.
+    oneshot: WaitChannel,
+}
+
+impl SleepQueue {
+    pub const fn new() -> Self {
+        Self {
+            slots: array![_ => WaitChannel::new(); NSLEEPSLOT],
+            oneshot: WaitChannel::new(),
+        }
+    }
+
+    fn slot(&self, tick: u32) -> &WaitChannel {
+        &self.slots[tick as usize % NSLEEPSLOT]
+    }
+
+    /// Atomically releases `ticks` and sleeps until the tick count reaches `deadline` or
+    /// another multiple of `NSLEEPSLOT` ticks away. The caller must recheck the tick count.
+    /// Reacquires `ticks` when awakened.
+    pub fn sleep(
+        &self,
+        deadline: u32,
+        ticks: &mut SleepableLockGuard<'_, u32>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        self.slot(deadline).sleep(ticks, ctx);
+    }
+
+    /// Returns whether `waitchannel` is one of the slots of this queue.
+    pub fn contains(&self, waitchannel: *const WaitChannel) -> bool {
+        ptr::eq(&self.oneshot, waitchannel)
+            || self.slots.iter().any(|slot| ptr::eq(slot, waitchannel))
+    }
+
+    /// Wakes up the processes whose deadline is `ticks`.
+    /// Called by the clock interrupt handler, holding the `ticks` lock.
+    pub fn expire(&self, ticks: u32, kernel: KernelRef<'_, '_>) {
+        self.slot(ticks).wakeup(kernel);
+    }
+
+    /// Wakes up the processes waiting for a one-shot timer.
+    /// Called by the interrupt handler when the one-shot timer of a CPU expires, holding the
+    /// `ticks` lock.
+    pub fn expire_oneshot(&self, kernel: KernelRef<'_, '_>) {
+        self.oneshot.wakeup(kernel);
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Sleeps until the timer reaches `deadline` cycles.
+    /// Returns Ok(()) on success, Err(cycles left) if the current process has been killed.
+    pub fn sleep_until(&self, deadline: u64) -> Result<(), u64> {
+        let mut ticks = self.kernel().ticks().lock();
+        loop {
+            let now = TargetArch::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            if self.proc().killed() {
+                return Err(deadline - now);
+            }
+            let left = deadline - now;
+            if left > 2 * TICK_INTERVAL {
+                // Wakes up at least a tick before the deadline, however the ticks of the CPU
+                // counting them are out of phase with the timer.
+                let tick = ticks.wrapping_add((left / TICK_INTERVAL - 1) as u32);
+                self.kernel().sleep_queue().sleep(tick, &mut ticks, self);
+            } else {
+                // Holding `ticks` keeps interrupts disabled, so that the timer is armed on the
+                // CPU we sleep on, and its expiration is not handled before we sleep.
+                TargetArch::set_next_event(left);
+                self.kernel().sleep_queue().oneshot.sleep(&mut ticks, self);
+            }
+        }
+    }
+}
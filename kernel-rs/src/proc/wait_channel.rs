@@ -1,3 +1,5 @@
+use core::sync::atomic::AtomicUsize;
+
 use super::*;
 use crate::{
     kernel::KernelRef,
@@ -5,14 +7,18 @@ use crate::{
 };
 
 pub struct WaitChannel {
-    /// Required to make this type non-zero-sized. If it were zero-sized, multiple wait channels may
+    /// Ticket given to the next sleeper. Sleepers with smaller tickets have slept longer.
+    ///
+    /// It also makes this type non-zero-sized. If it were zero-sized, multiple wait channels may
     /// have the same address, spuriously waking up more threads.
-    _padding: u8,
+    next_ticket: AtomicUsize,
 }
 
 impl WaitChannel {
     pub const fn new() -> Self {
-        Self { _padding: 0 }
+        Self {
+            next_ticket: AtomicUsize::new(0),
+        }
     }
 
     /// Atomically release lock and sleep on waitchannel.
@@ -31,6 +37,7 @@ impl WaitChannel {
         lock_guard.reacquire_after(move || {
             // Go to sleep.
             guard.deref_mut_info().waitchannel = self;
+            guard.deref_mut_info().wait_ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
             guard.deref_mut_info().state = Procstate::SLEEPING;
             // SAFETY: we hold `p.lock()`, changed the process's state,
             // and device interrupts are disabled by `push_off()` in `p.lock()`.
@@ -51,4 +58,12 @@ impl WaitChannel {
     pub fn wakeup(&self, kernel: KernelRef<'_, '_>) {
         kernel.procs().wakeup_pool(self, kernel);
     }
+
+    /// Wake up the process that has slept on waitchannel the longest, if any.
+    /// Use it instead of `wakeup` only if at most one sleeper can make progress, and the woken
+    /// process passes the wakeup on if it cannot.
+    /// Must be called without any p->lock.
+    pub fn wakeup_one(&self, kernel: KernelRef<'_, '_>) {
+        kernel.procs().wakeup_one_pool(self, kernel);
+    }
 }
@@ -0,0 +1,146 @@
+//! Wait queues keyed by addresses.
+//!
+//! Features that wait for an event on an address, such as futexes, share `WaitQueue` instead of
+//! building their own wait tables. A waiting thread links a `Waiter` on its own stack into the
+//! bucket of the address, and sleeps on the wait channel of the `Waiter`. Hence, a waker wakes up
+//! exactly the waiters of its address, oldest first, however many addresses share the bucket, and
+//! it may wake up only some of them.
+
+use core::{mem, pin::Pin};
+
+use array_macro::array;
+use pin_project::pin_project;
+
+use super::{KernelCtx, WaitChannel};
+use crate::{
+    kernel::KernelRef,
+    lock::SpinLock,
+    util::intrusive_list::{List, ListEntry, ListNode},
+};
+
+/// A hash table of `N` buckets of waiters, keyed by addresses.
+pub struct WaitQueue<const N: usize> {
+    buckets: [SpinLock<List<Waiter>>; N],
+}
+
+/// A thread waiting on a `WaitQueue`. It lives on the stack of the thread, and is linked to the
+/// bucket of its key until a waker wakes it up.
+#[pin_project]
+#[repr(C)]
+struct Waiter {
+    #[pin]
+    list_entry: ListEntry,
+    key: usize,
+    waitchannel: WaitChannel,
+}
+
+impl Waiter {
+    // TODO(https://github.com/kaist-cp/rv6/issues/369)
+    // A workarond for https://github.com/Gilnaa/memoffset/issues/49.
+    // Assumes `list_entry` is located at the beginning of `Waiter`.
+    const LIST_ENTRY_OFFSET: usize = 0;
+
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `Waiter::init`.
+    const unsafe fn new(key: usize) -> Self {
+        Self {
+            list_entry: unsafe { ListEntry::new() },
+            key,
+            waitchannel: WaitChannel::new(),
+        }
+    }
+
+    fn init(self: Pin<&mut Self>) {
+        self.project().list_entry.init();
+    }
+}
+
+// SAFETY: `Waiter` owns a `ListEntry`.
+unsafe impl ListNode for Waiter {
+    fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+        unsafe { Pin::new_unchecked(&self.get_ref().list_entry) }
+    }
+
+    fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+        (list_entry as *const u8).wrapping_sub(Self::LIST_ENTRY_OFFSET) as *const Self
+    }
+}
+
+impl<const N: usize> WaitQueue<N> {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `WaitQueue::init`.
+    pub const unsafe fn new(name: &'static str) -> Self {
+        Self {
+            buckets: array![_ => SpinLock::new(name, unsafe { List::new() }); N],
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
+        // SAFETY: the buckets are not moved.
+        for bucket in &mut unsafe { self.get_unchecked_mut() }.buckets {
+            unsafe { Pin::new_unchecked(bucket) }.get_pin_mut().init();
+        }
+    }
+
+    fn bucket(self: Pin<&Self>, key: usize) -> Pin<&SpinLock<List<Waiter>>> {
+        // Keys are addresses of words, so their lowest bits are mostly the same.
+        let index = (key / mem::size_of::<u32>()) % N;
+        unsafe { Pin::new_unchecked(&self.get_ref().buckets[index]) }
+    }
+
+    /// Sleeps on `key` until a waker wakes it up, or the current process is killed.
+    /// `check` runs holding the lock of the bucket, so that no waker can slip in between it and
+    /// the sleep. Returns its error without sleeping if it fails.
+    pub fn wait<E, F: FnOnce() -> Result<(), E>>(
+        self: Pin<&Self>,
+        key: usize,
+        check: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), E> {
+        let mut guard = self.bucket(key).pinned_lock();
+        check()?;
+
+        // SAFETY: `waiter` is initialized right below.
+        let mut waiter = unsafe { Waiter::new(key) };
+        // SAFETY: `waiter` is shadowed, so it never moves.
+        let mut waiter = unsafe { Pin::new_unchecked(&mut waiter) };
+        waiter.as_mut().init();
+        let waiter = waiter.into_ref();
+        guard.get_pin_mut().as_ref().push_back(waiter);
+
+        // A waker unlinks the waiter before waking it up.
+        while !waiter.get_list_entry().is_unlinked() && !ctx.proc().killed() {
+            waiter.waitchannel.sleep(&mut guard, ctx);
+        }
+        // The waiter must not stay linked after it goes out of scope.
+        waiter.get_list_entry().remove();
+        Ok(())
+    }
+
+    /// Wakes up at most `n` waiters on `key`, the ones that have waited the longest first.
+    /// Returns the number of waiters woken up.
+    pub fn wake(self: Pin<&Self>, key: usize, n: usize, kernel: KernelRef<'_, '_>) -> usize {
+        let mut guard = self.bucket(key).pinned_lock();
+        let mut cursor = guard.get_pin_mut().cursor_front_mut();
+        let mut woken = 0;
+        while woken < n {
+            let waiter = match cursor.current() {
+                Some(waiter) => waiter,
+                None => break,
+            };
+            // SAFETY: a waiter stays alive while it is linked, and it cannot unlink itself since
+            // we hold the lock of the bucket.
+            let waiter = unsafe { &*waiter };
+            if waiter.key == key {
+                let _ = cursor.remove_current();
+                waiter.waitchannel.wakeup(kernel);
+                woken += 1;
+            } else {
+                cursor.move_next();
+            }
+        }
+        woken
+    }
+}
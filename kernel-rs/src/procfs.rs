@@ -0,0 +1,199 @@
+//! The processes and the kernel counters under `/proc`.
+//!
+//! procfs is a read-only pseudo file system (see `mount`), which the kernel boots with mounted on
+//! `/proc`. `/proc/stat` shows the kernel counters, and `/proc/<pid>/status` the status of the
+//! process `pid`: its state, its name, the user pages charged for its memory, and the number of its
+//! open files. They are synthesized from `Procs` on each read, and their size is 0, as on Linux.
+//!
+//! The directory of a process and its `status` are the inodes `PID_INUM + 2 * i` and the next one,
+//! where `i` is the slot of the main thread of the process in the process pool, and their
+//! generation is the PID. Once the process exits, even if another process takes its slot, they
+//! stay empty for those that still refer to them.
+
+use core::{fmt::Write, mem, str};
+
+use arrayvec::ArrayString;
+use rv6_abi::{Dinode, Dirent, DIRSIZ, ROOTINO, T_DIR, T_FILE};
+use static_assertions::const_assert;
+
+use crate::{
+    hal::hal,
+    mount::{PseudoFile, PseudoFs},
+    param::NPROC,
+    proc::{KernelCtx, Pid, Procstate},
+    some_or,
+};
+
+/// The pseudo device of the inodes under `/proc`. No disk has this number.
+pub const PROCFS_DEV: u32 = u32::MAX - 1;
+
+/// Inode number of `/proc/stat`.
+const STAT_INUM: u32 = ROOTINO + 1;
+
+/// Inode number of the directory of the process in the first slot of the process pool.
+const PID_INUM: u32 = ROOTINO + 2;
+
+// A directory entry has 16 bits for an inode number.
+const_assert!(PID_INUM as usize + 2 * NPROC <= u16::MAX as usize);
+
+/// procfs, which the kernel boots with mounted on `/proc`.
+pub const PROCFS: PseudoFs = PseudoFs {
+    name: "procfs",
+    dev: PROCFS_DEV,
+    dinode,
+    dirent,
+    read,
+    read_only: true,
+};
+
+/// An inode of procfs.
+enum Node {
+    Root,
+    Stat,
+    Dir(usize),
+    Status(usize),
+}
+
+impl Node {
+    fn from_inum(inum: u32) -> Option<Self> {
+        match inum {
+            ROOTINO => Some(Self::Root),
+            STAT_INUM => Some(Self::Stat),
+            _ if inum >= PID_INUM && ((inum - PID_INUM) as usize) < 2 * NPROC => {
+                let index = ((inum - PID_INUM) / 2) as usize;
+                if (inum - PID_INUM) % 2 == 0 {
+                    Some(Self::Dir(index))
+                } else {
+                    Some(Self::Status(index))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the inode number of the directory of the process in the `index`th slot.
+fn dir_inum(index: usize) -> u32 {
+    PID_INUM + 2 * index as u32
+}
+
+/// Returns the `i`th entry of the directory `inum` of generation `gen`. `/proc` has ".", "..",
+/// "stat", and then an entry for each slot of the process pool, which is empty unless the slot
+/// holds the main thread of a process. The directory of a process has ".", "..", and "status",
+/// unless the process has exited. Returns None past the last entry, or for an empty one.
+fn dirent(inum: u32, gen: u32, i: u32, ctx: &KernelCtx<'_, '_>) -> Option<Dirent> {
+    let kernel = ctx.kernel();
+    let procs = kernel.procs();
+    let mut pid = ArrayString::<DIRSIZ>::new();
+    let (name, inum) = match (Node::from_inum(inum)?, i) {
+        (Node::Root, 0) => (&b"."[..], ROOTINO),
+        (Node::Root, 1) => (&b".."[..], 0),
+        (Node::Root, 2) => (&b"stat"[..], STAT_INUM),
+        (Node::Root, _) => {
+            let index = i as usize - 3;
+            write!(pid, "{}", procs.pid_at(index)?).ok()?;
+            (pid.as_bytes(), dir_inum(index))
+        }
+        (Node::Dir(index), _) if procs.pid_at(index) == Some(gen as Pid) => {
+            match i {
+                0 => (&b"."[..], dir_inum(index)),
+                1 => (&b".."[..], ROOTINO),
+                2 => (&b"status"[..], dir_inum(index) + 1),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    let mut de = Dirent {
+        inum: inum as u16,
+        ..Default::default()
+    };
+    de.set_name(name);
+    Some(de)
+}
+
+/// Returns the inode `inum` of `PROCFS_DEV` as if it were read from the disk, or a free one if
+/// there is no such inode, e.g., the process has exited.
+fn dinode(inum: u32, ctx: &KernelCtx<'_, '_>) -> Dinode {
+    let kernel = ctx.kernel();
+    let procs = kernel.procs();
+    let mut dip = Dinode::default();
+    match Node::from_inum(inum) {
+        Some(Node::Root) => {
+            dip.typ = T_DIR;
+            dip.size = ((3 + NPROC) * mem::size_of::<Dirent>()) as u32;
+            dip.mode = 0o555;
+        }
+        Some(Node::Stat) => {
+            dip.typ = T_FILE;
+            dip.mode = 0o444;
+        }
+        Some(Node::Dir(index)) => {
+            if let Some(pid) = procs.pid_at(index) {
+                dip.typ = T_DIR;
+                dip.size = (3 * mem::size_of::<Dirent>()) as u32;
+                dip.gen = pid as u32;
+                dip.mode = 0o555;
+            }
+        }
+        Some(Node::Status(index)) => {
+            if let Some(pid) = procs.pid_at(index) {
+                dip.typ = T_FILE;
+                dip.gen = pid as u32;
+                dip.mode = 0o444;
+            }
+        }
+        None => (),
+    }
+    if dip.typ != 0 {
+        dip.nlink = 1;
+    }
+    dip.update_checksum();
+    dip
+}
+
+/// Writes the content of the file `inum` of generation `gen` into `buf`, one "key value" line
+/// for each field.
+fn read(inum: u32, gen: u32, buf: &mut PseudoFile, ctx: &KernelCtx<'_, '_>) {
+    let kernel = ctx.kernel();
+    let procs = kernel.procs();
+    // The fields fit in the buffer.
+    let _ = match Node::from_inum(inum) {
+        Some(Node::Stat) => {
+            let ticks = *kernel.ticks().lock();
+            writeln!(
+                buf,
+                "uptime {}\nfreepages {}\nprocs {}\nswitches {}",
+                ticks,
+                hal().kmem().free_pages(),
+                procs.count(|state| state != Procstate::UNUSED),
+                procs.switches()
+            )
+        }
+        Some(Node::Status(index)) => {
+            let status = some_or!(procs.status_at(index, gen as Pid, ctx), return);
+            let length = status.name.iter().position(|&c| c == 0);
+            let name = &status.name[..length.unwrap_or(status.name.len())];
+            writeln!(
+                buf,
+                "pid {}\nstate {}\nname {}",
+                status.pid,
+                status.state.as_str().trim_end(),
+                str::from_utf8(name).unwrap_or("???")
+            )
+            .and_then(|_| {
+                match status.pages {
+                    Some(pages) => writeln!(buf, "pages {}", pages),
+                    None => Ok(()),
+                }
+            })
+            .and_then(|_| {
+                match status.fds {
+                    Some(fds) => writeln!(buf, "fds {}", fds),
+                    None => Ok(()),
+                }
+            })
+        }
+        _ => Ok(()),
+    };
+}
@@ -0,0 +1,91 @@
+//! Random numbers from an entropy pool fed by interrupt timing.
+//!
+//! Every device and timer interrupt mixes the time it arrived at, which jitters with the state of
+//! the disk, the console and the caches, into the pool. Once `RESEED_SAMPLES` samples have
+//! accumulated, they are folded into the key of a ChaCha20 generator, which serves the requests:
+//! `getrandom`, the bytes of `AT_RANDOM`, and the placement of the stack and the mappings of each
+//! program that `exec` loads.
+//!
+//! The pool does not estimate its entropy, and never blocks. The numbers handed out shortly after
+//! boot, before many interrupts have arrived, are therefore weaker than later ones.
+
+use crate::{arch::riscv::r_time, lock::SpinLock, util::chacha::ChaChaRng};
+
+/// Number of samples accumulated before they are folded into the generator.
+const RESEED_SAMPLES: usize = 64;
+
+/// Multiplier of the mixing function, i.e., the golden ratio in fixed point.
+const GOLDEN: u64 = 0x9e37_79b9_7f4a_7c15;
+
+struct Pool {
+    /// Samples not yet folded into `rng`, mixed together.
+    acc: [u64; 4],
+
+    /// Number of samples in `acc`.
+    samples: usize,
+
+    rng: ChaChaRng,
+}
+
+impl Pool {
+    fn reseed(&mut self) {
+        let mut seed = [0u32; 8];
+        for (i, acc) in self.acc.iter().enumerate() {
+            seed[2 * i] = *acc as u32;
+            seed[2 * i + 1] = (*acc >> 32) as u32;
+        }
+        self.rng.reseed(&seed);
+        self.acc = [0; 4];
+        self.samples = 0;
+    }
+}
+
+pub struct EntropyPool {
+    inner: SpinLock<Pool>,
+}
+
+impl EntropyPool {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(
+                "entropy",
+                Pool {
+                    acc: [0; 4],
+                    samples: 0,
+                    rng: ChaChaRng::new(),
+                },
+            ),
+        }
+    }
+
+    /// Mixes the arrival time of an interrupt from `source`, e.g., an irq number, into the pool.
+    /// Called from interrupt handlers.
+    pub fn add_interrupt(&self, source: usize) {
+        let mut pool = self.inner.lock();
+        let sample = r_time() ^ ((source as u64) << 48);
+        let i = pool.samples % pool.acc.len();
+        pool.acc[i] = (pool.acc[i].rotate_left(23) ^ sample).wrapping_mul(GOLDEN);
+        pool.samples += 1;
+        if pool.samples >= RESEED_SAMPLES {
+            pool.reseed();
+        }
+    }
+
+    /// Fills `buf` with random bytes.
+    pub fn fill_bytes(&self, buf: &mut [u8]) {
+        let mut pool = self.inner.lock();
+        // Fold in what has accumulated so far, and at least the current time.
+        let i = pool.samples % pool.acc.len();
+        pool.acc[i] ^= r_time();
+        pool.reseed();
+        pool.rng.fill_bytes(buf);
+    }
+
+    /// Returns a random number less than `n`, which must be positive.
+    pub fn below(&self, n: usize) -> usize {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        // The bias is negligible for the small ranges used.
+        (u64::from_le_bytes(bytes) % n as u64) as usize
+    }
+}
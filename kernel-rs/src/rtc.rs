@@ -0,0 +1,67 @@
+//! Driver for the goldfish real-time clock of qemu's virt machine.
+//!
+//! The clock counts nanoseconds since the Unix epoch. The kernel reads it only once, at boot, and
+//! keeps the wall-clock time of timer cycle 0, so that reading the wall-clock time later costs
+//! reading the timer, and never jumps against `CLOCK_MONOTONIC`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    arch::{TargetArch, TimeManager},
+    hal::mmio::{MmioRegion, ReadOnly},
+};
+
+#[repr(C)]
+struct RtcRegs {
+    /// Low 32 bits of the time. Reading it latches the high 32 bits into `time_high`.
+    time_low: ReadOnly<u32>,
+    /// High 32 bits of the time.
+    time_high: ReadOnly<u32>,
+}
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+pub struct Rtc {
+    regs: MmioRegion<RtcRegs>,
+
+    /// Nanoseconds since the epoch at timer cycle 0.
+    epoch_offset: AtomicU64,
+}
+
+impl Rtc {
+    /// # Safety
+    ///
+    /// `base` must be the address of the registers of a goldfish RTC.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            regs: unsafe { MmioRegion::new(base) },
+            epoch_offset: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the clock, in nanoseconds since the epoch.
+    fn read(&self) -> u64 {
+        let low = self.regs.time_low.get();
+        let high = self.regs.time_high.get();
+        ((high as u64) << 32) | low as u64
+    }
+
+    /// Reads the clock and takes the wall-clock time of timer cycle 0.
+    /// Must be called while the registers are accessible at their physical address.
+    pub fn init(&self) {
+        let now = self.read();
+        self.epoch_offset.store(
+            now.saturating_sub(cycles_to_ns(TargetArch::now())),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns the wall-clock time, in nanoseconds since the epoch.
+    pub fn now(&self) -> u64 {
+        self.epoch_offset.load(Ordering::Relaxed) + cycles_to_ns(TargetArch::now())
+    }
+}
+
+fn cycles_to_ns(cycles: u64) -> u64 {
+    cycles * (NSEC_PER_SEC / TargetArch::freq())
+}
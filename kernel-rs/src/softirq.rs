@@ -0,0 +1,56 @@
+//! Softirqs, i.e., deferred halves of interrupt handlers.
+//!
+//! An interrupt handler does only what cannot wait, such as draining a device's FIFO, and raises a
+//! softirq on its CPU for the rest. Each CPU runs its pending softirqs with interrupts enabled, in
+//! its scheduler loop and before returning to user space. Hence a long burst of work, such as
+//! echoing a paste into the console, does not keep the CPU from taking interrupts.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{cpu::cpuid, hal::hal, kernel::KernelRef, param::NCPU};
+
+/// Kinds of softirqs.
+#[derive(Clone, Copy)]
+pub enum Softirq {
+    /// Echo and line editing of the bytes received by the console.
+    Console,
+}
+
+/// Pending softirqs of each CPU.
+pub struct Softirqs {
+    /// Bitmap of the pending `Softirq`s, indexed by CPU.
+    pending: [AtomicUsize; NCPU],
+}
+
+impl Softirqs {
+    pub const fn new() -> Self {
+        Self {
+            pending: array![_ => AtomicUsize::new(0); NCPU],
+        }
+    }
+
+    /// Marks `softirq` pending on the current CPU. Called from interrupt handlers, so the CPU
+    /// cannot change under us.
+    pub fn raise(&self, softirq: Softirq) {
+        let _ = self.pending[cpuid()].fetch_or(1 << softirq as usize, Ordering::Release);
+    }
+}
+
+impl KernelRef<'_, '_> {
+    /// Runs the softirqs pending on the current CPU.
+    ///
+    /// Interrupts should be enabled, and no spinlock held, so that the handlers are preemptible by
+    /// interrupts. A softirq raised while they run is left pending for the next call.
+    pub fn run_softirqs(self) {
+        // Disable interrupts so that we take the bits of the CPU we are running on.
+        let intr = hal().cpus().push_off();
+        let pending = self.softirqs().pending[cpuid()].swap(0, Ordering::Acquire);
+        unsafe { hal().cpus().pop_off(intr) };
+
+        if pending & (1 << Softirq::Console as usize) != 0 {
+            hal().console().process_input(self);
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use crate::{
-    arch::memlayout::{clint_mtimecmp, CLINT_MTIME},
     arch::riscv::{
         r_mhartid, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp, Mstatus, MIE,
         SIE,
     },
+    arch::timer,
     kernel::main,
     param::NCPU,
 };
@@ -26,9 +26,6 @@ impl Stack {
 #[no_mangle]
 pub static mut stack0: Stack = Stack::new();
 
-/// A scratch area per CPU for machine-mode timer interrupts.
-static mut TIMER_SCRATCH: [[usize; NCPU]; 5] = [[0; NCPU]; 5];
-
 /// entry.S jumps here in machine mode on stack0.
 #[no_mangle]
 pub unsafe fn start() {
@@ -72,18 +69,9 @@ unsafe fn timerinit() {
     // each CPU has a separate source of timer interrupts.
     let id = r_mhartid();
 
-    // ask the CLINT for a timer interrupt.
-    let interval: usize = 1_000_000; // cycles; about 1/10th second in qemu.
-    unsafe { *(clint_mtimecmp(id) as *mut usize) = (*(CLINT_MTIME as *mut usize)) + interval };
-
-    // prepare information in scratch[] for timervec.
-    // scratch[0..2] : space for timervec to save registers.
-    // scratch[3] : address of CLINT MTIMECMP register.
-    // scratch[4] : desired interval (in cycles) between timer interrupts.
-    let scratch = unsafe { &mut TIMER_SCRATCH[id][..] };
-    *unsafe { scratch.get_unchecked_mut(3) } = clint_mtimecmp(id);
-    *unsafe { scratch.get_unchecked_mut(4) } = interval;
-    unsafe { w_mscratch(&scratch[0] as *const _ as usize) };
+    // ask the CLINT for a timer interrupt, and prepare the scratch area for timervec.
+    let scratch = unsafe { timer::init(id) };
+    unsafe { w_mscratch(scratch) };
 
     // set the machine-mode trap handler.
     unsafe { w_mtvec(timervec as _) };
@@ -93,8 +81,9 @@ unsafe fn timerinit() {
     x.insert(Mstatus::MIE);
     unsafe { x.write() };
 
-    // enable machine-mode timer interrupts.
+    // enable machine-mode timer and software interrupts.
     let mut y = MIE::read();
     y.insert(MIE::MTIE);
+    y.insert(MIE::MSIE);
     unsafe { y.write() };
 }
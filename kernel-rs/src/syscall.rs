@@ -4,24 +4,37 @@
 
 #![allow(clippy::unit_arg)]
 
-use core::{mem, str};
+use core::{cmp, convert::TryFrom, mem, str};
 
 use arrayvec::ArrayVec;
 use cstr_core::CStr;
+use rv6_abi::{
+    syscall::*, CloneFlags, FcountRecord, IrqoffRecord, Itimerval, MapFlags, Meminfo, ProtFlags,
+    SigAction, Sysinfo, Timespec, Timeval, TraceRecord, WaitFlags, BENCH_NULL, BENCH_PIPE,
+    BENCH_YIELD, CLOCK_MONOTONIC, CLOCK_REALTIME, FD_CLOEXEC, FUTEX_WAIT, FUTEX_WAKE, F_GETFD,
+    F_GETFL, F_SETFD, F_SETFL, ITIMER_REAL, MADV_DONTNEED, MADV_FREE, MADV_NORMAL, MADV_WILLNEED,
+    NPAGEOWNER, NSEC_PER_SEC, NSIG, S_IALLUGO, TRACE_SYSCALL_ENTER, TRACE_SYSCALL_EXIT, UTIME_NOW,
+    UTIME_OMIT,
+};
 
 use crate::{
     arch::{
-        addr::{Addr, UVAddr},
-        poweroff,
+        addr::{pgroundup, Addr, UVAddr, PGSIZE},
+        memlayout::TRAPFRAMES,
+        riscv::r_time,
+        timer, Arch, TargetArch, TimeManager,
     },
-    file::RcFile,
-    fs::{FcntlFlags, FileSystem, InodeType, Path},
+    error::{Errno, KernelError},
+    exec::Abi,
+    fcount,
+    file::{FileType, RcFile},
+    fs::{FcntlFlags, FileSystem, FswMask, InodeType, Path, SyncFileRangeFlags},
     hal::hal,
-    ok_or,
+    kalloc::PageOwner,
     page::Page,
-    param::{MAXARG, MAXPATH},
-    proc::{CurrentProc, KernelCtx},
-    some_or,
+    param::{MAXARG, MAXPATH, NCPU, NOFILE},
+    proc::{CurrentProc, Gid, KernelCtx, Procstate, Uid, ALL_CPUS},
+    vm::PteFlags,
 };
 
 impl CurrentProc<'_, '_> {
@@ -30,20 +43,19 @@ impl CurrentProc<'_, '_> {
     pub fn fetchaddr(&mut self, addr: UVAddr) -> Result<usize, ()> {
         let mut ip = 0;
         let sz = mem::size_of::<usize>();
-        if addr.into_usize() >= self.memory().size()
-            || addr.into_usize() + sz > self.memory().size()
-        {
+        let memory = self.memory();
+        if addr.into_usize() >= memory.size() || addr.into_usize() + sz > memory.size() {
             return Err(());
         }
         // SAFETY: usize does not have any internal structure.
-        unsafe { self.memory_mut().copy_in(&mut ip, addr) }?;
+        unsafe { memory.copy_in(&mut ip, addr) }?;
         Ok(ip)
     }
 
     /// Fetch the nul-terminated string at addr from the current process.
     /// Returns reference to the string in the buffer.
     pub fn fetchstr<'a>(&mut self, addr: UVAddr, buf: &'a mut [u8]) -> Result<&'a CStr, ()> {
-        self.memory_mut().copy_in_str(buf, addr)?;
+        self.memory().copy_in_str(buf, addr)?;
 
         // SAFETY: buf contains '\0' as copy_in_str has succeeded.
         Ok(unsafe { CStr::from_ptr(buf.as_ptr()) })
@@ -82,176 +94,1515 @@ impl CurrentProc<'_, '_> {
     }
 
     /// Fetch the nth word-sized system call argument as a file descriptor
-    /// and return both the descriptor and the corresponding struct file.
-    fn argfd(&self, n: usize) -> Result<(i32, &RcFile), ()> {
+    /// and return both the descriptor and a new reference to the corresponding struct file,
+    /// which the caller must free. The file stays open until then, even if another thread
+    /// closes the descriptor.
+    pub fn argfd(&self, n: usize) -> Result<(i32, RcFile), KernelError> {
         let fd = self.argint(n)?;
-        let f = self
-            .deref_data()
-            .open_files
-            .get(fd as usize)
-            .ok_or(())?
-            .as_ref()
-            .ok_or(())?;
+        let f = self.files().get(fd).ok_or(Errno::EBADF)?.clone();
         Ok((fd, f))
     }
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn syscall(&mut self, num: i32) -> Result<usize, ()> {
+    pub fn syscall(&mut self, num: i32) -> Result<usize, KernelError> {
+        hal()
+            .tracer()
+            .record(TRACE_SYSCALL_ENTER, [num as u64, self.proc().pid() as u64]);
+        let ret = if cfg!(feature = "syscall-latency") {
+            let start = r_time();
+            let ret = self.dispatch(num);
+            self.kernel()
+                .syscall_latency()
+                .record(num, r_time().wrapping_sub(start));
+            ret
+        } else {
+            self.dispatch(num)
+        };
+        let value = match ret {
+            Ok(value) => value,
+            Err(err) => err.errno().into_ret(),
+        };
+        self.proc().deref_data().guards.check_none(num);
+        hal()
+            .tracer()
+            .record(TRACE_SYSCALL_EXIT, [num as u64, value as u64]);
+        ret
+    }
+
+    fn dispatch(&mut self, num: i32) -> Result<usize, KernelError> {
+        fcount!(KernelCtx::dispatch);
+        if self.proc().deref_data().abi == Abi::Linux {
+            return self.dispatch_linux(num);
+        }
         match num {
-            1 => self.sys_fork(),
-            2 => self.sys_exit(),
-            3 => self.sys_wait(),
-            4 => self.sys_pipe(),
-            5 => self.sys_read(),
-            6 => self.sys_kill(),
-            7 => self.sys_exec(),
-            8 => self.sys_fstat(),
-            9 => self.sys_chdir(),
-            10 => self.sys_dup(),
-            11 => self.sys_getpid(),
-            12 => self.sys_sbrk(),
-            13 => self.sys_sleep(),
-            14 => self.sys_uptime(),
-            15 => self.sys_open(),
-            16 => self.sys_write(),
-            17 => self.sys_mknod(),
-            18 => self.sys_unlink(),
-            19 => self.sys_link(),
-            20 => self.sys_mkdir(),
-            21 => self.sys_close(),
-            22 => self.sys_poweroff(),
-            _ => {
-                self.kernel().as_ref().write_fmt(format_args!(
-                    "{} {}: unknown sys call {}",
-                    self.proc().pid(),
-                    str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
-                    num
-                ));
-                Err(())
-            }
+            SYS_FORK => self.sys_fork(),
+            SYS_EXIT => self.sys_exit(),
+            SYS_WAIT => self.sys_wait(),
+            SYS_PIPE => self.sys_pipe(),
+            SYS_READ => self.sys_read(),
+            SYS_KILL => self.sys_kill(),
+            SYS_EXEC => self.sys_exec(),
+            SYS_FSTAT => self.sys_fstat(),
+            SYS_CHDIR => self.sys_chdir(),
+            SYS_DUP => self.sys_dup(),
+            SYS_GETPID => self.sys_getpid(),
+            SYS_SBRK => self.sys_sbrk(),
+            SYS_SLEEP => self.sys_sleep(),
+            SYS_UPTIME => self.sys_uptime(),
+            SYS_OPEN => self.sys_open(),
+            SYS_WRITE => self.sys_write(),
+            SYS_MKNOD => self.sys_mknod(),
+            SYS_UNLINK => self.sys_unlink(),
+            SYS_LINK => self.sys_link(),
+            SYS_MKDIR => self.sys_mkdir(),
+            SYS_CLOSE => self.sys_close(),
+            SYS_POWEROFF => self.sys_poweroff(),
+            SYS_REBOOT => self.sys_reboot(),
+            SYS_SYNC_FILE_RANGE => self.sys_sync_file_range(),
+            SYS_IOSTAT => self.sys_iostat(),
+            SYS_RGROUP_SET => self.sys_rgroup_set(),
+            SYS_RGROUP_JOIN => self.sys_rgroup_join(),
+            SYS_GETTID => self.sys_gettid(),
+            SYS_TGKILL => self.sys_tgkill(),
+            SYS_FUTEX => self.sys_futex(),
+            SYS_SET_ROBUST_LIST => self.sys_set_robust_list(),
+            SYS_SPAWN => self.sys_spawn(),
+            SYS_PIDFD_OPEN => self.sys_pidfd_open(),
+            SYS_FSRESIZE => self.sys_fsresize(),
+            SYS_SYSINFO => self.sys_sysinfo(),
+            SYS_GETRANDOM => self.sys_getrandom(),
+            SYS_SYSCALL_LATENCY => self.sys_syscall_latency(),
+            SYS_MMAP => self.sys_mmap(),
+            SYS_MUNMAP => self.sys_munmap(),
+            SYS_BENCH => self.sys_bench(),
+            SYS_TASKDUMP => self.sys_taskdump(),
+            SYS_TRACE_READ => self.sys_trace_read(),
+            SYS_SIGACTION => self.sys_sigaction(),
+            SYS_SIGPROCMASK => self.sys_sigprocmask(),
+            SYS_SIGRETURN => self.sys_sigreturn(),
+            SYS_FCOUNT => self.sys_fcount(),
+            SYS_CLONE => self.sys_clone(),
+            SYS_IRQOFF => self.sys_irqoff(),
+            SYS_BRK => self.sys_brk(),
+            SYS_WAITPID => self.sys_waitpid(),
+            SYS_SETPGID => self.sys_setpgid(),
+            SYS_GETPGID => self.sys_getpgid(),
+            SYS_SETSID => self.sys_setsid(),
+            SYS_GETPPID => self.sys_getppid(),
+            SYS_GETUID => self.sys_getuid(),
+            SYS_GETEUID => self.sys_geteuid(),
+            SYS_MADVISE => self.sys_madvise(),
+            SYS_MEMINFO => self.sys_meminfo(),
+            SYS_NANOSLEEP => self.sys_nanosleep(),
+            SYS_CLOCK_GETTIME => self.sys_clock_gettime(),
+            SYS_GETTIMEOFDAY => self.sys_gettimeofday(),
+            SYS_SETITIMER => self.sys_setitimer(),
+            SYS_ALARM => self.sys_alarm(),
+            SYS_SCHED_SETAFFINITY => self.sys_sched_setaffinity(),
+            SYS_SCHED_GETAFFINITY => self.sys_sched_getaffinity(),
+            SYS_PAGEMAP => self.sys_pagemap(),
+            SYS_RENAME => self.sys_rename(),
+            SYS_LSEEK => self.sys_lseek(),
+            SYS_DUP2 => self.sys_dup2(),
+            SYS_DUP3 => self.sys_dup3(),
+            SYS_PREAD => self.sys_pread(),
+            SYS_PWRITE => self.sys_pwrite(),
+            SYS_FTRUNCATE => self.sys_ftruncate(),
+            SYS_FCNTL => self.sys_fcntl(),
+            SYS_UTIMES => self.sys_utimes(),
+            SYS_FUTIMENS => self.sys_futimens(),
+            SYS_CHMOD => self.sys_chmod(),
+            SYS_CHOWN => self.sys_chown(),
+            SYS_SETUID => self.sys_setuid(),
+            SYS_SETGID => self.sys_setgid(),
+            SYS_GETGID => self.sys_getgid(),
+            SYS_FSYNC => self.sys_fsync(),
+            SYS_FSWATCH => self.sys_fswatch(),
+            SYS_GETDENTS_PLUS => self.sys_getdents_plus(),
+            SYS_FADVISE => self.sys_fadvise(),
+            SYS_MOUNT => self.sys_mount(),
+            SYS_UMOUNT => self.sys_umount(),
+            _ => self.unknown_syscall(num),
         }
     }
 
+    /// Reports the system call `num` that the current process made but the kernel does not know.
+    /// Returns Err(ENOSYS).
+    pub fn unknown_syscall(&self, num: i32) -> Result<usize, KernelError> {
+        self.kernel().as_ref().write_fmt(format_args!(
+            "{} {}: unknown sys call {}",
+            self.proc().pid(),
+            str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
+            num
+        ));
+        Err(Errno::ENOSYS.into())
+    }
+
     /// Terminate the current process; status reported to wait(). No return.
-    pub fn sys_exit(&mut self) -> Result<usize, ()> {
+    pub fn sys_exit(&mut self) -> Result<usize, KernelError> {
         let n = self.proc().argint(0)?;
         self.kernel().procs().exit_current(n, self);
     }
 
     /// Create a process.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_fork(&mut self) -> Result<usize, ()> {
-        Ok(self.kernel().procs().fork(self)? as _)
+    /// Returns Ok(child’s PID) on success, Err(error) on error.
+    pub fn sys_fork(&mut self) -> Result<usize, KernelError> {
+        let pid = self
+            .kernel()
+            .procs()
+            .fork(self)
+            .map_err(|_| Errno::EAGAIN)?;
+        Ok(pid as _)
+    }
+
+    /// Create a thread sharing the memory of the current process, which calls fn(arg) on the
+    /// given stack.
+    /// Returns Ok(new thread’s ID) on success, Err(error) on error.
+    pub fn sys_clone(&mut self) -> Result<usize, KernelError> {
+        let entry = self.proc().argaddr(0)?;
+        let arg = self.proc().argaddr(1)?;
+        let stack = self.proc().argaddr(2)?;
+        let flags = CloneFlags::from_bits(self.proc().argint(3)?).ok_or(Errno::EINVAL)?;
+        let tls = self.proc().argaddr(4)?;
+        if stack % 16 != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let tls = if flags.contains(CloneFlags::SETTLS) {
+            Some(tls)
+        } else {
+            None
+        };
+        let tid = self
+            .kernel()
+            .procs()
+            .clone(entry, arg, stack, tls, self)
+            .map_err(|_| Errno::EAGAIN)?;
+        Ok(tid as _)
     }
 
     /// Wait for a child to exit.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_wait(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(child’s PID) on success, Err(error) on error.
+    pub fn sys_wait(&mut self) -> Result<usize, KernelError> {
         let p = self.proc().argaddr(0)?;
-        Ok(self.kernel().procs().wait(p.into(), self)? as _)
+        let pid = self
+            .kernel()
+            .procs()
+            .waitpid(-1, p.into(), WaitFlags::empty(), self)
+            .map_err(|_| Errno::ECHILD)?;
+        Ok(pid as _)
+    }
+
+    /// Wait for the child with the given pid to exit, or any child if pid is -1.
+    /// Returns Ok(child’s PID) on success, Ok(0) if no such child has exited with WNOHANG,
+    /// Err(error) on error.
+    pub fn sys_waitpid(&mut self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let p = self.proc().argaddr(1)?;
+        let options = WaitFlags::from_bits(self.proc().argint(2)?).ok_or(Errno::EINVAL)?;
+        if pid == 0 || pid < -1 {
+            return Err(Errno::EINVAL.into());
+        }
+        let pid = self
+            .kernel()
+            .procs()
+            .waitpid(pid, p.into(), options, self)
+            .map_err(|_| Errno::ECHILD)?;
+        Ok(pid as _)
     }
 
     /// Return the current process’s PID.
-    pub fn sys_getpid(&self) -> Result<usize, ()> {
+    pub fn sys_getpid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().tgid() as _)
+    }
+
+    /// Return the current thread’s TID.
+    pub fn sys_gettid(&self) -> Result<usize, KernelError> {
         Ok(self.proc().pid() as _)
     }
 
-    /// Grow process’s memory by n bytes.
-    /// Returns Ok(start of new memory) on success, Err(()) on error.
-    pub fn sys_sbrk(&mut self) -> Result<usize, ()> {
+    /// Return the PID of the current process’s parent, or 0 for the initial process.
+    pub fn sys_getppid(&self) -> Result<usize, KernelError> {
+        Ok(self.kernel().procs().getppid(self) as _)
+    }
+
+    /// Return the current process’s real user ID.
+    pub fn sys_getuid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().uid() as _)
+    }
+
+    /// Return the current process’s effective user ID.
+    pub fn sys_geteuid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().euid() as _)
+    }
+
+    /// Return the current process’s group ID.
+    pub fn sys_getgid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().gid() as _)
+    }
+
+    /// Set the real and the effective user IDs of the current process to uid. A process that is
+    /// not the superuser may only set its effective user ID back to its real user ID.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_setuid(&mut self) -> Result<usize, KernelError> {
+        let uid = Uid::try_from(self.proc().argint(0)?).map_err(|_| Errno::EINVAL)?;
+        self.proc_mut().set_uid(uid).map_err(|_| Errno::EPERM)?;
+        Ok(0)
+    }
+
+    /// Set the group ID of the current process to gid. Only the superuser may change it.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_setgid(&mut self) -> Result<usize, KernelError> {
+        let gid = Gid::try_from(self.proc().argint(0)?).map_err(|_| Errno::EINVAL)?;
+        self.proc_mut().set_gid(gid).map_err(|_| Errno::EPERM)?;
+        Ok(0)
+    }
+
+    /// Grow or shrink process’s memory by n bytes.
+    /// Returns Ok(start of new memory) on success, Err(error) on error.
+    pub fn sys_sbrk(&mut self) -> Result<usize, KernelError> {
         let n = self.proc().argint(0)?;
-        self.proc_mut().memory_mut().resize(n, hal().kmem())
+        self.change_layout(|ctx| {
+            let size = ctx.proc().memory().size();
+            let brk = if n >= 0 {
+                size.checked_add(n as usize)
+            } else {
+                size.checked_sub(n.unsigned_abs() as usize)
+            }
+            .ok_or(Errno::ENOMEM)?;
+            ctx.set_break(brk).map_err(|_| Errno::ENOMEM)?;
+            Ok(size)
+        })
+    }
+
+    /// Set the end of process’s memory to addr, unless addr is 0.
+    /// Returns Ok(end of memory) on success, Err(error) on error.
+    pub fn sys_brk(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        self.change_layout(|ctx| {
+            if addr != 0 {
+                ctx.set_break(addr).map_err(|_| Errno::ENOMEM)?;
+            }
+            Ok(ctx.proc().memory().size())
+        })
+    }
+
+    /// Map len bytes with protection prot, at addr if flags has MAP_FIXED, or anywhere otherwise.
+    /// An anonymous mapping is zero-filled, and must be private. Otherwise, the mapping holds the
+    /// file fd from offset, which must be page-aligned, and is zero-filled past the end of the
+    /// file. The changes to a MAP_SHARED mapping are written back to the file when the last
+    /// mapping of the page goes away, and those to a MAP_PRIVATE one are discarded.
+    /// Returns Ok(address of the mapping) on success, Err(error) on error.
+    pub fn sys_mmap(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        let prot = ProtFlags::from_bits(self.proc().argint(2)?).ok_or(Errno::EINVAL)?;
+        let flags = MapFlags::from_bits(self.proc().argint(3)?).ok_or(Errno::EINVAL)?;
+        let shared = flags.contains(MapFlags::MAP_SHARED);
+        if shared == flags.contains(MapFlags::MAP_PRIVATE) {
+            return Err(Errno::EINVAL.into());
+        }
+        let fixed = flags.contains(MapFlags::MAP_FIXED);
+        if len == 0 || (fixed && addr % PGSIZE != 0) {
+            return Err(Errno::EINVAL.into());
+        }
+        // A hint need not be aligned, nor valid.
+        let addr = if fixed {
+            addr
+        } else if addr > TRAPFRAMES {
+            0
+        } else {
+            pgroundup(addr)
+        };
+
+        self.change_layout(|ctx| {
+            let cache = ctx.kernel().page_cache();
+            // Unmap the pages at addr in advance, once they are not pinned.
+            if fixed && len <= TRAPFRAMES && ctx.proc().memory().fits_fixed(addr, pgroundup(len)) {
+                ctx.munmap_pages(addr, len).map_err(|_| Errno::ENOMEM)?;
+            }
+            let start = if flags.contains(MapFlags::MAP_ANONYMOUS) {
+                if shared {
+                    return Err(Errno::EINVAL.into());
+                }
+                ctx.proc()
+                    .memory()
+                    .mmap_anonymous(
+                        addr,
+                        len,
+                        PteFlags::from_prot(prot),
+                        fixed,
+                        cache,
+                        hal().kmem(),
+                    )
+                    .map_err(|_| Errno::ENOMEM)?
+            } else {
+                ctx.mmap_file(addr, len, prot, fixed, shared)?
+            };
+            let footprint = ctx.proc().memory().footprint();
+            if ctx.charge_memory(footprint).is_err() {
+                // Exceeded the page cap of the resource group.
+                let _ = ctx.munmap_pages(start, len);
+                cache.sync(ctx);
+                return Err(Errno::ENOMEM.into());
+            }
+            Ok(start)
+        })
+    }
+
+    /// Map len bytes of the file fd, the fourth argument, from offset, the fifth argument, for
+    /// sys_mmap. The pages are read outside the lock of the memory, which the caller keeps from
+    /// changing its layout meanwhile.
+    /// Returns Ok(address of the mapping) on success, Err(error) on error.
+    fn mmap_file(
+        &self,
+        addr: usize,
+        len: usize,
+        prot: ProtFlags,
+        fixed: bool,
+        shared: bool,
+    ) -> Result<usize, KernelError> {
+        let offset = self.proc().argaddr(5)?;
+        // The offsets of the pages should fit in u32.
+        if offset % PGSIZE != 0
+            || offset
+                .checked_add(len)
+                .map_or(true, |end| end > u32::MAX as _)
+        {
+            return Err(Errno::EINVAL.into());
+        }
+        let (_, f) = self.proc().argfd(4)?;
+        let res = try {
+            let ip = f.mappable_inode().ok_or(Errno::ENODEV)?;
+            if !f.is_readable()
+                || (shared && prot.contains(ProtFlags::PROT_WRITE) && !f.is_writable())
+            {
+                Err(Errno::EACCES)?;
+            }
+
+            let perm = PteFlags::from_prot(prot);
+            let cache = self.kernel().page_cache();
+            let allocator = hal().kmem();
+            let start = self
+                .proc()
+                .memory()
+                .reserve(addr, len, perm, fixed, shared, false, cache, allocator)
+                .map_err(|_| Errno::ENOMEM)?;
+            let pgoff = (offset / PGSIZE) as u32;
+            let mapped: Result<(), ()> = try {
+                for off in num_iter::range_step(0, pgroundup(len), PGSIZE) {
+                    if shared {
+                        let pa = cache.map(ip, pgoff + (off / PGSIZE) as u32, self)?;
+                        self.proc()
+                            .memory()
+                            .map_shared(start, pa, cache, allocator)?;
+                    } else {
+                        let mut page = allocator.alloc(PageOwner::User).ok_or(())?;
+                        page.write_bytes(0);
+                        let filled = ip.lock(self).map(|mut ip| {
+                            let _ =
+                                ip.read_bytes_kernel(&mut page[..], (offset + off) as u32, self);
+                            ip.free(self);
+                        });
+                        if filled.is_err() {
+                            allocator.free(page);
+                            Err(())?;
+                        }
+                        self.proc().memory().map_private(start, page, allocator)?;
+                    }
+                }
+            };
+            if mapped.is_err() {
+                self.proc().memory().cancel(start, cache, allocator);
+            }
+            // Drop the pages cached for the mapping if it failed.
+            cache.sync(self);
+            mapped.map_err(|_| Errno::ENOMEM)?;
+            start
+        };
+        f.free(self);
+        res
+    }
+
+    /// Advise the kernel on the use of the mmap()ed pages from addr to addr + len. addr must be
+    /// page-aligned. MADV_DONTNEED frees the anonymous pages, which are zero-filled again on the
+    /// next access, and leaves the shared pages of files alone. MADV_FREE does the same only for
+    /// anonymous pages. MADV_WILLNEED maps back the freed pages in advance, as the pages of files
+    /// are read in by mmap already.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_madvise(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        let advice = self.proc().argint(2)?;
+        if addr % PGSIZE != 0 || len > TRAPFRAMES {
+            return Err(Errno::EINVAL.into());
+        }
+        let end = addr.checked_add(pgroundup(len)).ok_or(Errno::EINVAL)?;
+        self.change_layout(|ctx| {
+            let anonymous = ctx
+                .proc()
+                .memory()
+                .mapped_anonymous(addr, end)
+                .ok_or(Errno::ENOMEM)?;
+            match advice {
+                MADV_NORMAL => {}
+                MADV_WILLNEED => {
+                    ctx.proc()
+                        .memory()
+                        .populate(addr, end, hal().kmem())
+                        .map_err(|_| Errno::EAGAIN)?
+                }
+                MADV_DONTNEED => ctx.discard_pages(addr, end)?,
+                MADV_FREE if anonymous => ctx.discard_pages(addr, end)?,
+                _ => return Err(Errno::EINVAL.into()),
+            }
+            Ok(0)
+        })
+    }
+
+    /// Unmap the mmap()ed pages from addr to addr + len. addr must be page-aligned.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_munmap(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        if len == 0 || len > TRAPFRAMES || addr % PGSIZE != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        self.change_layout(|ctx| {
+            ctx.munmap_pages(addr, len).map_err(|_| Errno::ENOMEM)?;
+            // Write back the shared pages that are no longer mapped.
+            ctx.kernel().page_cache().sync(ctx);
+            let footprint = ctx.proc().memory().footprint();
+            let _ = ctx.charge_memory(footprint);
+            Ok(0)
+        })
     }
 
     /// Pause for n clock ticks.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_sleep(&self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_sleep(&self) -> Result<usize, KernelError> {
         let n = self.proc().argint(0)?;
         let mut ticks = self.kernel().ticks().lock();
         let ticks0 = *ticks;
+        let deadline = ticks0.wrapping_add(n as u32);
         while ticks.wrapping_sub(ticks0) < n as u32 {
             if self.proc().killed() {
-                return Err(());
+                return Err(Errno::EINTR.into());
             }
-            ticks.sleep(self);
+            self.kernel()
+                .sleep_queue()
+                .sleep(deadline, &mut ticks, self);
         }
         Ok(0)
     }
 
-    /// Terminate process PID.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_kill(&self) -> Result<usize, ()> {
+    /// Sleep for the duration in the timespec at REQ, as accurately as the timer allows.
+    /// If killed before that, writes the time left to the timespec at REM unless REM is 0.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_nanosleep(&mut self) -> Result<usize, KernelError> {
+        let req = self.proc().argaddr(0)?;
+        let rem = self.proc().argaddr(1)?;
+        let mut ts = Timespec::default();
+        // SAFETY: Timespec does not have any internal structure.
+        unsafe { self.proc().memory().copy_in(&mut ts, req.into()) }.map_err(|_| Errno::EFAULT)?;
+        if ts.tv_sec < 0 || ts.tv_nsec < 0 || ts.tv_nsec >= NSEC_PER_SEC {
+            return Err(Errno::EINVAL.into());
+        }
+        let cycles = timer::timespec_to_cycles(ts.tv_sec as u64, ts.tv_nsec as u64);
+        let left = match self.sleep_until(TargetArch::now().saturating_add(cycles)) {
+            Ok(()) => return Ok(0),
+            Err(left) => left,
+        };
+        if rem != 0 {
+            let (sec, nsec) = timer::cycles_to_timespec(left);
+            let ts = Timespec {
+                tv_sec: sec as i64,
+                tv_nsec: nsec as i64,
+            };
+            self.proc()
+                .memory()
+                .copy_out(rem.into(), &ts)
+                .map_err(|_| Errno::EFAULT)?;
+        }
+        Err(Errno::EINTR.into())
+    }
+
+    /// Write the time of clock CLOCKID to the timespec at TP. CLOCK_MONOTONIC counts from the
+    /// boot, and CLOCK_REALTIME from the epoch.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_clock_gettime(&mut self) -> Result<usize, KernelError> {
+        let clockid = self.proc().argint(0)?;
+        let tp = self.proc().argaddr(1)?;
+        let ts = match clockid {
+            CLOCK_REALTIME => {
+                let now = hal().rtc().now();
+                Timespec {
+                    tv_sec: (now / NSEC_PER_SEC as u64) as i64,
+                    tv_nsec: (now % NSEC_PER_SEC as u64) as i64,
+                }
+            }
+            CLOCK_MONOTONIC => {
+                let (sec, nsec) = timer::cycles_to_timespec(TargetArch::now());
+                Timespec {
+                    tv_sec: sec as i64,
+                    tv_nsec: nsec as i64,
+                }
+            }
+            _ => return Err(Errno::EINVAL.into()),
+        };
+        self.proc()
+            .memory()
+            .copy_out(tp.into(), &ts)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(0)
+    }
+
+    /// Write the wall-clock time to the timeval at TV. The time zone argument is ignored.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_gettimeofday(&mut self) -> Result<usize, KernelError> {
+        let tv = self.proc().argaddr(0)?;
+        let now = hal().rtc().now();
+        let timeval = Timeval {
+            tv_sec: (now / NSEC_PER_SEC as u64) as i64,
+            tv_usec: (now % NSEC_PER_SEC as u64 / 1000) as i64,
+        };
+        self.proc()
+            .memory()
+            .copy_out(tv.into(), &timeval)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(0)
+    }
+
+    /// Set the interval timer WHICH, which must be ITIMER_REAL, to the itimerval at NEW, with a
+    /// resolution of a tick. If OLD is not 0, write the previous setting to the itimerval at OLD.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_setitimer(&mut self) -> Result<usize, KernelError> {
+        let which = self.proc().argint(0)?;
+        let new = self.proc().argaddr(1)?;
+        let old = self.proc().argaddr(2)?;
+        if which != ITIMER_REAL {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut itimer = Itimerval::default();
+        // SAFETY: Itimerval does not have any internal structure.
+        unsafe { self.proc().memory().copy_in(&mut itimer, new.into()) }
+            .map_err(|_| Errno::EFAULT)?;
+        let value = timeval_to_ticks(&itimer.it_value)?;
+        let interval = timeval_to_ticks(&itimer.it_interval)?;
+        let now = *self.kernel().ticks().lock();
+        let (left, interval) = self
+            .kernel()
+            .procs()
+            .set_alarm(self.proc().tgid(), value, interval, now)
+            .map_err(|_| Errno::ESRCH)?;
+        if old != 0 {
+            let itimer = Itimerval {
+                it_interval: ticks_to_timeval(interval),
+                it_value: ticks_to_timeval(left),
+            };
+            self.proc()
+                .memory()
+                .copy_out(old.into(), &itimer)
+                .map_err(|_| Errno::EFAULT)?;
+        }
+        Ok(0)
+    }
+
+    /// Send SIGALRM to the current process after SECONDS seconds, or cancel the alarm if SECONDS
+    /// is 0, in place of the previous one.
+    /// Returns Ok(seconds left of the previous alarm, or 0 if none) on success, Err(error) on error.
+    pub fn sys_alarm(&mut self) -> Result<usize, KernelError> {
+        let seconds = self.proc().argint(0)?;
+        if seconds < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let value = timeval_to_ticks(&Timeval {
+            tv_sec: seconds as i64,
+            tv_usec: 0,
+        })?;
+        let now = *self.kernel().ticks().lock();
+        let (left, _) = self
+            .kernel()
+            .procs()
+            .set_alarm(self.proc().tgid(), value, 0, now)
+            .map_err(|_| Errno::ESRCH)?;
+        let ticks_per_sec = 1_000_000 / timer::US_PER_TICK as usize;
+        Ok((left as usize + ticks_per_sec - 1) / ticks_per_sec)
+    }
+
+    /// Let thread pid, or the current thread if pid is 0, run only on the CPUs in the bitmask at
+    /// addr. The current thread moves to an allowed CPU before it returns.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_sched_setaffinity(&mut self) -> Result<usize, KernelError> {
         let pid = self.proc().argint(0)?;
-        self.kernel().procs().kill(pid)?;
+        let addr = self.proc().argaddr(1)?;
+        let mut mask = 0u64;
+        unsafe { self.proc().memory().copy_in(&mut mask, addr.into()) }
+            .map_err(|_| Errno::EFAULT)?;
+        let affinity = mask as usize & ALL_CPUS;
+        if affinity == 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let pid = if pid == 0 { self.proc().pid() } else { pid };
+        self.kernel()
+            .procs()
+            .set_affinity(pid, affinity)
+            .map_err(|_| Errno::ESRCH)?;
+        if pid == self.proc().pid() {
+            // Join the run queue of an allowed CPU.
+            self.yield_cpu();
+        }
         Ok(0)
     }
 
+    /// Get the CPU affinity mask of thread pid, or of the current thread if pid is 0, into addr.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_sched_getaffinity(&mut self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let pid = if pid == 0 { self.proc().pid() } else { pid };
+        let mask = self
+            .kernel()
+            .procs()
+            .get_affinity(pid)
+            .map_err(|_| Errno::ESRCH)? as u64;
+        self.proc()
+            .memory()
+            .copy_out(addr.into(), &mask)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(0)
+    }
+
+    /// Copy up to n ranges of the pages mapped in the current process into the array at addr, and
+    /// the statistics of its page table into stat unless it is 0.
+    /// Returns Ok(number of ranges) on success, Err(error) on error.
+    pub fn sys_pagemap(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        let stat_addr = self.proc().argaddr(2)?;
+        if n < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let memory = self.proc().memory();
+        let (count, stat) = memory
+            .pagemap(addr.into(), n as usize)
+            .map_err(|_| Errno::EFAULT)?;
+        if stat_addr != 0 {
+            memory
+                .copy_out(stat_addr.into(), &stat)
+                .map_err(|_| Errno::EFAULT)?;
+        }
+        Ok(count)
+    }
+
+    /// Set the CPU weight and the page cap (0 for no cap) of a resource group. Only the superuser
+    /// may.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_rgroup_set(&self) -> Result<usize, KernelError> {
+        let group = self.proc().argint(0)?;
+        let weight = self.proc().argint(1)?;
+        let page_cap = self.proc().argint(2)?;
+        if group < 0 || weight < 0 || page_cap < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        if self.proc().euid() != 0 {
+            return Err(Errno::EPERM.into());
+        }
+        self.kernel()
+            .procs()
+            .get_group(group as usize)?
+            .set_limits(weight as usize, page_cap as usize)?;
+        Ok(0)
+    }
+
+    /// Move the current process into a resource group. Only the superuser may.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_rgroup_join(&mut self) -> Result<usize, KernelError> {
+        let group = self.proc().argint(0)?;
+        if group < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        if self.proc().euid() != 0 {
+            return Err(Errno::EPERM.into());
+        }
+        self.join_group(group as usize)?;
+        Ok(0)
+    }
+
+    /// Send signal SIG to process PID, to the process group -PID if PID is negative, or to the
+    /// process group of the current process if PID is 0.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_kill(&self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let sig = self.proc().argint(1)?;
+        if sig < 0 || sig as usize >= NSIG {
+            return Err(Errno::EINVAL.into());
+        }
+        let pid = match pid {
+            // The process group of the current process.
+            0 => -self.proc().lock().pgid(),
+            // Signaling every process is not supported.
+            -1 => return Err(Errno::EINVAL.into()),
+            _ => pid,
+        };
+        self.kernel()
+            .procs()
+            .kill(pid, sig)
+            .map_err(|_| Errno::ESRCH)?;
+        Ok(0)
+    }
+
+    /// Set the process group of the process pid, or of the current process if pid is 0, to pgid,
+    /// or to pid if pgid is 0.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_setpgid(&self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let pgid = self.proc().argint(1)?;
+        if pid < 0 || pgid < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let pid = if pid == 0 { self.proc().tgid() } else { pid };
+        let pgid = if pgid == 0 { pid } else { pgid };
+        self.kernel().procs().setpgid(pid, pgid, self)?;
+        Ok(0)
+    }
+
+    /// Return the process group ID of the process pid, or of the current process if pid is 0.
+    pub fn sys_getpgid(&self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let pid = if pid == 0 { self.proc().tgid() } else { pid };
+        let pgid = self
+            .kernel()
+            .procs()
+            .getpgid(pid)
+            .map_err(|_| Errno::ESRCH)?;
+        Ok(pgid as _)
+    }
+
+    /// Create a new session led by the current process.
+    /// Returns Ok(session ID) on success, Err(error) on error.
+    pub fn sys_setsid(&self) -> Result<usize, KernelError> {
+        let sid = self
+            .kernel()
+            .procs()
+            .setsid(self)
+            .map_err(|_| Errno::EPERM)?;
+        Ok(sid as _)
+    }
+
+    /// Examine and change the action of a signal.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_sigaction(&mut self) -> Result<usize, KernelError> {
+        let sig = self.proc().argint(0)?;
+        let act = self.proc().argaddr(1)?;
+        let oldact = self.proc().argaddr(2)?;
+        let action = if act == 0 {
+            None
+        } else {
+            let mut action = SigAction::default();
+            // SAFETY: SigAction does not have any internal structure.
+            unsafe { self.proc().memory().copy_in(&mut action, act.into()) }
+                .map_err(|_| Errno::EFAULT)?;
+            Some(action)
+        };
+        let old = self.sigaction(sig, action).map_err(|_| Errno::EINVAL)?;
+        if oldact != 0 {
+            self.proc()
+                .memory()
+                .copy_out(oldact.into(), &old)
+                .map_err(|_| Errno::EFAULT)?;
+        }
+        Ok(0)
+    }
+
+    /// Examine and change the blocked signals.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_sigprocmask(&mut self) -> Result<usize, KernelError> {
+        let how = self.proc().argint(0)?;
+        let set = self.proc().argaddr(1)?;
+        let oldset = self.proc().argaddr(2)?;
+        let set = if set == 0 {
+            None
+        } else {
+            let mut mask = 0u32;
+            // SAFETY: u32 does not have any internal structure.
+            unsafe { self.proc().memory().copy_in(&mut mask, set.into()) }
+                .map_err(|_| Errno::EFAULT)?;
+            Some(mask)
+        };
+        let old = self.sigprocmask(how, set).map_err(|_| Errno::EINVAL)?;
+        if oldset != 0 {
+            self.proc()
+                .memory()
+                .copy_out(oldset.into(), &old)
+                .map_err(|_| Errno::EFAULT)?;
+        }
+        Ok(0)
+    }
+
+    /// Return from a signal handler to where the signal interrupted the process.
+    /// Kills the process if the saved registers cannot be read.
+    /// Returns Ok(the interrupted a0) on success, Err(error) on error.
+    pub fn sys_sigreturn(&mut self) -> Result<usize, KernelError> {
+        self.sigreturn().map_err(|_| {
+            self.proc().kill();
+            Errno::EFAULT.into()
+        })
+    }
+
+    /// Terminate thread TID of process TGID.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_tgkill(&self) -> Result<usize, KernelError> {
+        let tgid = self.proc().argint(0)?;
+        let tid = self.proc().argint(1)?;
+        self.kernel()
+            .procs()
+            .tgkill(tgid, tid)
+            .map_err(|_| Errno::ESRCH)?;
+        Ok(0)
+    }
+
+    /// Wait on or wake up the futex at the given address. FUTEX_WAKE wakes up at most the given
+    /// number of waiters.
+    /// Returns Ok(0) after waiting, Ok(number of waiters woken up) after waking up,
+    /// Err(error) on error.
+    pub fn sys_futex(&mut self) -> Result<usize, KernelError> {
+        let uaddr = self.proc().argaddr(0)?;
+        let op = self.proc().argint(1)?;
+        let val = self.proc().argint(2)?;
+        match op {
+            FUTEX_WAIT => {
+                self.futex_wait(uaddr.into(), val as u32)?;
+                Ok(0)
+            }
+            FUTEX_WAKE if val < 0 => Err(Errno::EINVAL.into()),
+            FUTEX_WAKE => self.futex_wake(uaddr.into(), val as usize),
+            _ => Err(Errno::ENOSYS.into()),
+        }
+    }
+
+    /// Register the robust futex list of the current thread.
+    /// Returns Ok(0).
+    pub fn sys_set_robust_list(&mut self) -> Result<usize, KernelError> {
+        let head = self.proc().argaddr(0)?;
+        self.set_robust_list(head.into());
+        Ok(0)
+    }
+
+    /// Open a file descriptor that refers to process PID.
+    /// Reading it blocks until the process exits.
+    /// Returns Ok(fd) on success, Err(error) on error.
+    pub fn sys_pidfd_open(&mut self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        if !self.kernel().procs().is_running(pid) {
+            return Err(Errno::ESRCH.into());
+        }
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Pidfd { pid }, true, false)
+            .map_err(|_| Errno::ENFILE)?;
+        let fd = self.fdalloc(f, false).map_err(|_| Errno::EMFILE)?;
+        Ok(fd as usize)
+    }
+
     /// Return how many clock tick interrupts have occurred
     /// since start.
-    pub fn sys_uptime(&self) -> Result<usize, ()> {
+    pub fn sys_uptime(&self) -> Result<usize, KernelError> {
         Ok(*self.kernel().ticks().lock() as usize)
     }
 
-    /// Shutdowns this machine, discarding all unsaved data. No return.
-    pub fn sys_poweroff(&self) -> Result<usize, ()> {
+    /// Get the I/O statistics of process pid, or of the disk if pid is 0.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_iostat(&mut self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let stat = if pid == 0 {
+            hal().disk().pinned_lock().stat()
+        } else {
+            self.kernel()
+                .procs()
+                .iostat(pid)
+                .map_err(|_| Errno::ESRCH)?
+        };
+        self.proc()
+            .memory()
+            .copy_out(addr.into(), &stat)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(0)
+    }
+
+    /// Get the number of the pages of each owner, and their budgets.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_meminfo(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        let kmem = hal().kmem();
+        let mut info = Meminfo {
+            freepages: kmem.free_pages(),
+            owned: [0; NPAGEOWNER],
+            budgets: [usize::MAX; NPAGEOWNER],
+        };
+        for owner in PageOwner::ALL.iter() {
+            info.owned[owner.index()] = kmem.owned_pages(*owner);
+            info.budgets[owner.index()] = owner.budget().unwrap_or(usize::MAX);
+        }
+        self.proc()
+            .memory()
+            .copy_out(addr.into(), &info)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(0)
+    }
+
+    /// Get the uptime, load averages and other statistics of the system.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_sysinfo(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        let info = Sysinfo {
+            uptime: *self.kernel().ticks().lock() as usize,
+            loads: self.kernel().loadavg().get(),
+            freepages: hal().kmem().free_pages(),
+            procs: self
+                .kernel()
+                .procs()
+                .count(|state| state != Procstate::UNUSED),
+        };
+        self.proc()
+            .memory()
+            .copy_out(addr.into(), &info)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(0)
+    }
+
+    /// Fill the n bytes at addr with random bytes from the entropy pool. flags must be 0.
+    /// Never blocks. Returns Ok(n) on success, Err(error) on error.
+    pub fn sys_getrandom(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        let flags = self.proc().argint(2)?;
+        if n < 0 || flags != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let n = n as usize;
+        let mut buf = [0u8; 256];
+        let mut done = 0;
+        while done < n {
+            let len = cmp::min(n - done, buf.len());
+            self.kernel().entropy().fill_bytes(&mut buf[..len]);
+            self.proc()
+                .memory()
+                .copy_out_bytes((addr + done).into(), &buf[..len])
+                .map_err(|_| Errno::EFAULT)?;
+            done += len;
+        }
+        Ok(n)
+    }
+
+    /// Perform benchmark operation op n times, and measure it in the kernel, free of the noise of
+    /// user-space timers. BENCH_PIPE writes a byte from buf to wfd and reads it back from rfd into
+    /// buf at each round, so another process must pass it from one pipe to the other.
+    /// Returns Ok(elapsed timer cycles) on success, Err(error) on error.
+    pub fn sys_bench(&mut self) -> Result<usize, KernelError> {
+        let op = self.proc().argint(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let start = r_time();
+        match op {
+            BENCH_NULL => {
+                for _ in 0..n {
+                    let _ = self.dispatch(SYS_GETPID)?;
+                }
+            }
+            BENCH_YIELD => {
+                for _ in 0..n {
+                    if self.proc().killed() {
+                        return Err(Errno::EINTR.into());
+                    }
+                    self.yield_cpu();
+                }
+            }
+            BENCH_PIPE => {
+                let buf = self.proc().argaddr(4)?.into();
+                let (_, rf) = self.proc().argfd(2)?;
+                let (_, wf) = match self.proc().argfd(3) {
+                    Ok(wf) => wf,
+                    Err(e) => {
+                        rf.free(self);
+                        return Err(e);
+                    }
+                };
+                let res = try {
+                    for _ in 0..n {
+                        if wf.write(buf, 1, self)? != 1 || rf.read(buf, 1, self)? != 1 {
+                            Err(Errno::EPIPE)?;
+                        }
+                    }
+                };
+                rf.free(self);
+                wf.free(self);
+                res?;
+            }
+            _ => return Err(Errno::EINVAL.into()),
+        }
+        Ok(r_time().wrapping_sub(start) as usize)
+    }
+
+    /// Copy the latency histogram of system call num to addr, as an array of LATENCY_BUCKETS
+    /// counts. Available only with the `syscall-latency` feature.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_syscall_latency(&mut self) -> Result<usize, KernelError> {
+        if !cfg!(feature = "syscall-latency") {
+            return Err(Errno::ENOSYS.into());
+        }
+        let num = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let histogram = self
+            .kernel()
+            .syscall_latency()
+            .histogram(num)
+            .map_err(|_| Errno::EINVAL)?;
+        self.proc()
+            .memory()
+            .copy_out(addr.into(), &histogram)
+            .map_err(|_| Errno::EFAULT)?;
+        Ok(0)
+    }
+
+    /// Print the saved registers and the kernel stack backtrace of process pid to the console.
+    /// Available only in debug builds.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_taskdump(&self) -> Result<usize, KernelError> {
+        if !cfg!(debug_assertions) {
+            return Err(Errno::ENOSYS.into());
+        }
+        let pid = self.proc().argint(0)?;
+        self.kernel().taskdump(pid).map_err(|_| Errno::ESRCH)?;
+        Ok(0)
+    }
+
+    /// Move up to n records out of the trace buffers into the array at addr.
+    /// Available only with the `tracepoints` feature.
+    /// Returns Ok(number of records moved) on success, Err(error) on error.
+    pub fn sys_trace_read(&mut self) -> Result<usize, KernelError> {
+        if !cfg!(feature = "tracepoints") {
+            return Err(Errno::ENOSYS.into());
+        }
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let n = n as usize;
+        let mut records = [TraceRecord::default(); 16];
+        let mut total = 0;
+        while total < n {
+            let count = cmp::min(n - total, records.len());
+            let read = hal().tracer().read(&mut records[..count]);
+            for record in &records[..read] {
+                let dst = addr + total * mem::size_of::<TraceRecord>();
+                self.proc()
+                    .memory()
+                    .copy_out(dst.into(), record)
+                    .map_err(|_| Errno::EFAULT)?;
+                total += 1;
+            }
+            if read < count {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Copy up to n function entry counters into the array at addr.
+    /// Available only with the `fcount` feature.
+    /// Returns Ok(number of counters) on success, Err(error) on error.
+    pub fn sys_fcount(&mut self) -> Result<usize, KernelError> {
+        if !cfg!(feature = "fcount") {
+            return Err(Errno::ENOSYS.into());
+        }
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let counters = fcount::counters();
+        for (i, counter) in counters.iter().take(n as usize).enumerate() {
+            let dst = addr + i * mem::size_of::<FcountRecord>();
+            self.proc()
+                .memory()
+                .copy_out(dst.into(), &counter.record())
+                .map_err(|_| Errno::EFAULT)?;
+        }
+        Ok(counters.len())
+    }
+
+    /// Copy the records of the longest interrupts-disabled window of up to n CPUs into the array
+    /// at addr. Available only with the `irqoff` feature.
+    /// Returns Ok(number of CPUs) on success, Err(error) on error.
+    pub fn sys_irqoff(&mut self) -> Result<usize, KernelError> {
+        if !cfg!(feature = "irqoff") {
+            return Err(Errno::ENOSYS.into());
+        }
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        for id in 0..cmp::min(n as usize, NCPU) {
+            let record = hal().cpus().irqoff_record(id);
+            let dst = addr + id * mem::size_of::<IrqoffRecord>();
+            self.proc()
+                .memory()
+                .copy_out(dst.into(), &record)
+                .map_err(|_| Errno::EFAULT)?;
+        }
+        Ok(NCPU)
+    }
+
+    /// Shutdowns this machine after killing the other processes and writing back the file
+    /// system. No return.
+    pub fn sys_poweroff(&mut self) -> Result<usize, KernelError> {
         let exitcode = self.proc().argint(0)?;
-        poweroff::machine_poweroff(exitcode as _);
+        self.shutdown();
+        TargetArch::power_off(exitcode as _);
+    }
+
+    /// Resets this machine after killing the other processes and writing back the file system.
+    /// No return.
+    pub fn sys_reboot(&mut self) -> Result<usize, KernelError> {
+        self.shutdown();
+        TargetArch::reboot();
+    }
+
+    /// Kills the other processes and writes back the file system, before the machine goes down.
+    fn shutdown(&mut self) {
+        // The killed processes close their files and write back their shared mappings on exit.
+        self.kernel().procs().kill_all(self);
+
+        // Write the bytes staged by the writes to the files of the current process and its shared
+        // mappings back, and commit the log. The buffer cache writes through, so nothing else is
+        // left in memory.
+        for fd in 0..NOFILE as i32 {
+            let f = self.proc().files().get(fd).cloned();
+            if let Some(f) = f {
+                let _ = f.flush(self);
+                f.free(self);
+            }
+        }
+        let cache = self.kernel().page_cache();
+        self.proc().memory().unmap_shared(cache);
+        cache.sync(self);
+        self.kernel().fs().sync(self);
     }
 
     /// Return a new file descriptor referring to the same file as given fd.
-    /// Returns Ok(new file descriptor) on success, Err(()) on error.
-    pub fn sys_dup(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(new file descriptor) on success, Err(error) on error.
+    pub fn sys_dup(&mut self) -> Result<usize, KernelError> {
         let (_, f) = self.proc().argfd(0)?;
-        let newfile = f.clone();
-        let fd = newfile.fdalloc(self)?;
+        let fd = self.fdalloc(f, false).map_err(|_| Errno::EMFILE)?;
         Ok(fd as usize)
     }
 
+    /// Make file descriptor new refer to the file of old, closing the file that new referred to.
+    /// Returns Ok(new) on success, Err(error) on error.
+    pub fn sys_dup2(&mut self) -> Result<usize, KernelError> {
+        let new = self.proc().argint(1)?;
+        let (old, f) = self.proc().argfd(0)?;
+        if old == new {
+            f.free(self);
+            return Ok(new as usize);
+        }
+        self.dup_to(f, new, false)
+    }
+
+    /// Same as dup2, except that old and new must differ. The only flag supported is O_CLOEXEC,
+    /// which marks new close-on-exec.
+    /// Returns Ok(new) on success, Err(error) on error.
+    pub fn sys_dup3(&mut self) -> Result<usize, KernelError> {
+        let new = self.proc().argint(1)?;
+        let flags = FcntlFlags::from_bits(self.proc().argint(2)?).ok_or(())?;
+        let (old, f) = self.proc().argfd(0)?;
+        if old == new || !FcntlFlags::O_CLOEXEC.contains(flags) {
+            f.free(self);
+            return Err(Errno::EINVAL.into());
+        }
+        self.dup_to(f, new, flags.contains(FcntlFlags::O_CLOEXEC))
+    }
+
+    /// Install the reference f to a file at descriptor new, with the close-on-exec flag cloexec,
+    /// and free the file that new referred to, if any.
+    fn dup_to(&mut self, f: RcFile, new: i32, cloexec: bool) -> Result<usize, KernelError> {
+        let old = {
+            let mut files = self.proc().files();
+            match files.open_files.get_mut(new as usize) {
+                Some(slot) => {
+                    let old = slot.replace(f);
+                    files.cloexec[new as usize] = cloexec;
+                    Ok(old)
+                }
+                None => Err(f),
+            }
+        };
+        match old {
+            Ok(old) => {
+                if let Some(old) = old {
+                    old.free(self);
+                }
+                Ok(new as usize)
+            }
+            Err(f) => {
+                f.free(self);
+                Err(Errno::EBADF.into())
+            }
+        }
+    }
+
     /// Read n bytes into buf.
-    /// Returns Ok(number read) on success, Err(()) on error.
-    pub fn sys_read(&mut self) -> Result<usize, ()> {
-        let (_, f) = self.proc().argfd(0)?;
+    /// Returns Ok(number read) on success, Err(error) on error.
+    pub fn sys_read(&mut self) -> Result<usize, KernelError> {
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: read will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).read(p.into(), n, self) }
+        let (_, f) = self.proc().argfd(0)?;
+        let ret = f.read(p.into(), n, self);
+        f.free(self);
+        Ok(ret?)
     }
 
     /// Write n bytes from buf to given file descriptor fd.
-    /// Returns Ok(n) on success, Err(()) on error.
-    pub fn sys_write(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(n) on success, Err(error) on error.
+    pub fn sys_write(&mut self) -> Result<usize, KernelError> {
+        let n = self.proc().argint(2)?;
+        let p = self.proc().argaddr(1)?;
         let (_, f) = self.proc().argfd(0)?;
+        let ret = f.write(p.into(), n, self);
+        f.free(self);
+        Ok(ret?)
+    }
+
+    /// Read n bytes into buf at offset off of file fd, without moving the offset of the file.
+    /// Returns Ok(number read) on success, Err(error) on error.
+    pub fn sys_pread(&mut self) -> Result<usize, KernelError> {
+        self.pio(false)
+    }
+
+    /// Write n bytes from buf at offset off of file fd, without moving the offset of the file.
+    /// Returns Ok(n) on success, Err(error) on error.
+    pub fn sys_pwrite(&mut self) -> Result<usize, KernelError> {
+        self.pio(true)
+    }
+
+    /// The positional I/O of pread and pwrite, whose arguments are fd, buf, n, and off.
+    fn pio(&mut self, write: bool) -> Result<usize, KernelError> {
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: write will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).write(p.into(), n, self) }
+        let off = self.proc().argint(3)?;
+        let (_, f) = self.proc().argfd(0)?;
+        let res = try {
+            if !matches!(f.typ, FileType::Inode { .. }) {
+                Err(Errno::ESPIPE)?;
+            }
+            if (write && !f.is_writable()) || (!write && !f.is_readable()) {
+                Err(Errno::EBADF)?;
+            }
+            if n < 0 || off < 0 {
+                Err(Errno::EINVAL)?;
+            }
+            if write {
+                f.pwrite(p.into(), n as u32, off as u32, self)?
+            } else {
+                f.pread(p.into(), n as u32, off as u32, self)?
+            }
+        };
+        f.free(self);
+        res
+    }
+
+    /// Change the size of file fd to len.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_ftruncate(&mut self) -> Result<usize, KernelError> {
+        let len = self.proc().argint(1)?;
+        let (_, f) = self.proc().argfd(0)?;
+        let res = try {
+            if len < 0 || !matches!(f.typ, FileType::Inode { .. }) {
+                Err(Errno::EINVAL)?;
+            }
+            if !f.is_writable() {
+                Err(Errno::EBADF)?;
+            }
+            f.truncate(len as u32, self)?;
+            0
+        };
+        f.free(self);
+        res
+    }
+
+    /// Set the access time and the modification time of file fd as utimes does.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_futimens(&mut self) -> Result<usize, KernelError> {
+        let (atime, mtime) = self.argtimes(1)?;
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.set_times(atime, mtime, self);
+        f.free(self);
+        res?;
+        Ok(0)
+    }
+
+    /// Fetch the nth argument of utimes and futimens, the address of an array of two timespecs, as
+    /// the access time and the modification time to set. Each is the current time if its tv_nsec
+    /// is UTIME_NOW or the address is 0, and None if its tv_nsec is UTIME_OMIT. The timestamps
+    /// of inodes are in seconds, so tv_nsec is otherwise dropped.
+    fn argtimes(&mut self, n: usize) -> Result<(Option<u32>, Option<u32>), KernelError> {
+        let addr = self.proc().argaddr(n)?;
+        let now = (hal().rtc().now() / NSEC_PER_SEC as u64) as u32;
+        if addr == 0 {
+            return Ok((Some(now), Some(now)));
+        }
+        let mut ts = [Timespec::default(); 2];
+        // SAFETY: Timespec does not have any internal structure.
+        unsafe { self.proc().memory().copy_in(&mut ts, addr.into()) }.map_err(|_| Errno::EFAULT)?;
+        let time = |ts: Timespec| {
+            match ts.tv_nsec {
+                UTIME_NOW => Ok(Some(now)),
+                UTIME_OMIT => Ok(None),
+                nsec if (0..NSEC_PER_SEC).contains(&nsec) => {
+                    u32::try_from(ts.tv_sec)
+                        .map(Some)
+                        .map_err(|_| Errno::EINVAL)
+                }
+                _ => Err(Errno::EINVAL),
+            }
+        };
+        Ok((time(ts[0])?, time(ts[1])?))
     }
 
     /// Release open file fd.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_close(&mut self) -> Result<usize, ()> {
-        let (fd, _) = self.proc().argfd(0)?;
-        if let Some(f) = self.proc_mut().deref_mut_data().open_files[fd as usize].take() {
-            f.free(self);
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_close(&mut self) -> Result<usize, KernelError> {
+        let fd = self.proc().argint(0)?;
+        let f = self.proc().files().take(fd).ok_or(Errno::EBADF)?;
+        f.free(self);
+        Ok(0)
+    }
+
+    /// Force the dirty blocks of the given byte range of file fd to disk.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_sync_file_range(&mut self) -> Result<usize, KernelError> {
+        let off = self.proc().argint(1)?;
+        let len = self.proc().argint(2)?;
+        let flags = SyncFileRangeFlags::from_bits(self.proc().argint(3)?).ok_or(())?;
+        if off < 0 || len < 0 {
+            return Err(Errno::EINVAL.into());
         }
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.sync_range(off as u32, len as u32, flags, self);
+        f.free(self);
+        res?;
         Ok(0)
     }
 
-    /// Place info about an open file into struct stat.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_fstat(&mut self) -> Result<usize, ()> {
+    /// Force file fd, and every other update the log holds, to disk.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_fsync(&mut self) -> Result<usize, KernelError> {
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.fsync(self);
+        f.free(self);
+        res?;
+        Ok(0)
+    }
+
+    /// Watch the events in mask, some of FSW_CREATE, FSW_UNLINK and FSW_WRITE, on the directory
+    /// or the file at path. Reading the returned descriptor waits for events, unless it is set
+    /// O_NONBLOCK.
+    /// Returns Ok(fd) on success, Err(error) on error.
+    pub fn sys_fswatch(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let mask = u16::try_from(self.proc().argint(1)?)
+            .ok()
+            .and_then(FswMask::from_bits)
+            .ok_or(Errno::EINVAL)?;
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().watch(path, mask, &tx, self);
+        tx.end(self);
+        Ok(res?)
+    }
+
+    /// Grow the file system to the given number of blocks, which the disk must hold.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_fsresize(&mut self) -> Result<usize, KernelError> {
+        let size = self.proc().argint(0)?;
+        if size < 0 || size as u32 > hal().disk().pinned_lock().capacity() {
+            return Err(Errno::EINVAL.into());
+        }
+        self.kernel().fs().resize(size as u32, self)?;
+        Ok(0)
+    }
+
+    /// Move the offset of an open file, relative to the origin given by whence.
+    /// Returns Ok(new offset) on success, Err(error) on error.
+    pub fn sys_lseek(&mut self) -> Result<usize, KernelError> {
+        let off = self.proc().argint(1)?;
+        let whence = self.proc().argint(2)?;
         let (_, f) = self.proc().argfd(0)?;
+        let res = match &f.typ {
+            FileType::Inode { inner } => {
+                match inner.seek(off, whence, self) {
+                    Ok(off) => Ok(off as usize),
+                    Err(()) => Err(Errno::EINVAL.into()),
+                }
+            }
+            _ => Err(Errno::ESPIPE.into()),
+        };
+        f.free(self);
+        res
+    }
+
+    /// Place info about an open file into struct stat.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_fstat(&mut self) -> Result<usize, KernelError> {
         // user pointer to struct stat
         let st = self.proc().argaddr(1)?;
-        // SAFETY: stat will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).stat(st.into(), self) }?;
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.stat(st.into(), self);
+        f.free(self);
+        res?;
+        Ok(0)
+    }
+
+    /// Read the entries of directory fd from its offset into the array of struct direntplus at
+    /// addr, each with the status of its inode, as many as fit in n bytes.
+    /// Returns Ok(number of bytes read), or Ok(0) at the end of the directory, on success, and
+    /// Err(error) on error.
+    pub fn sys_getdents_plus(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(1)?;
+        let n = usize::try_from(self.proc().argint(2)?).map_err(|_| Errno::EINVAL)?;
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.getdents_plus(addr.into(), n, self);
+        f.free(self);
+        res
+    }
+
+    /// Advise how the len bytes of file fd at offset off will be read, which len 0 extends to
+    /// the end of file, with advice, one of POSIX_FADV_*.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_fadvise(&mut self) -> Result<usize, KernelError> {
+        let off = self.proc().argint(1)?;
+        let len = self.proc().argint(2)?;
+        let advice = self.proc().argint(3)?;
+        if off < 0 || len < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.fadvise(off as u32, len as u32, advice, self);
+        f.free(self);
+        res?;
         Ok(0)
     }
 
     /// Create the path new as a link to the same inode as old.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_link(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_link(&mut self) -> Result<usize, KernelError> {
         let mut new: [u8; MAXPATH] = [0; MAXPATH];
         let mut old: [u8; MAXPATH] = [0; MAXPATH];
         let old = Path::new(self.proc_mut().argstr(0, &mut old)?);
@@ -267,19 +1618,34 @@ impl KernelCtx<'_, '_> {
     }
 
     /// Remove a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_unlink(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_unlink(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().unlink(path, &tx, self).map(|_| 0);
+        let res = self.kernel().fs().unlink(path, &tx, self);
         tx.end(self);
-        res
+        res?;
+        Ok(0)
+    }
+
+    /// Rename a file or directory, replacing the new path if it exists.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_rename(&mut self) -> Result<usize, KernelError> {
+        let mut old: [u8; MAXPATH] = [0; MAXPATH];
+        let mut new: [u8; MAXPATH] = [0; MAXPATH];
+        let old = Path::new(self.proc_mut().argstr(0, &mut old)?);
+        let new = Path::new(self.proc_mut().argstr(1, &mut new)?);
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().rename(old, new, &tx, self);
+        tx.end(self);
+        res?;
+        Ok(0)
     }
 
     /// Open a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_open(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_open(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let omode = self.proc().argint(1)?;
@@ -287,12 +1653,44 @@ impl KernelCtx<'_, '_> {
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
         let res = self.kernel().fs().open(path, omode, &tx, self);
         tx.end(self);
+        Ok(res?)
+    }
+
+    /// Get or set the descriptor flags or the file status flags of file descriptor fd.
+    /// Returns Ok(flags) for F_GETFD and F_GETFL, Ok(0) for F_SETFD and F_SETFL, and Err(error)
+    /// on error.
+    pub fn sys_fcntl(&mut self) -> Result<usize, KernelError> {
+        let cmd = self.proc().argint(1)?;
+        let arg = self.proc().argint(2)?;
+        let (fd, f) = self.proc().argfd(0)?;
+        let res = match cmd {
+            F_GETFD => {
+                Ok(if self.proc().files().cloexec[fd as usize] {
+                    FD_CLOEXEC as usize
+                } else {
+                    0
+                })
+            }
+            F_SETFD => {
+                self.proc().files().cloexec[fd as usize] = arg & FD_CLOEXEC != 0;
+                Ok(0)
+            }
+            F_GETFL => Ok(f.status_flags().bits() as usize),
+            F_SETFL => {
+                f.set_nonblock(
+                    FcntlFlags::from_bits_truncate(arg).contains(FcntlFlags::O_NONBLOCK),
+                );
+                Ok(0)
+            }
+            _ => Err(Errno::EINVAL.into()),
+        };
+        f.free(self);
         res
     }
 
     /// Create a new directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mkdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_mkdir(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
@@ -305,12 +1703,12 @@ impl KernelCtx<'_, '_> {
                 0
             });
         tx.end(self);
-        res
+        Ok(res?)
     }
 
     /// Create a new device file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mknod(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_mknod(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let major = self.proc().argint(1)? as u16;
@@ -325,12 +1723,119 @@ impl KernelCtx<'_, '_> {
                 0
             });
         tx.end(self);
+        Ok(res?)
+    }
+
+    /// Set the access time and the modification time of the file at path to those in the array of
+    /// two timespecs at times, or to the current time if times is 0. A tv_nsec of UTIME_NOW stands
+    /// for the current time, and UTIME_OMIT leaves the time unchanged.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_utimes(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let (atime, mtime) = self.argtimes(1)?;
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = try {
+            let inode = self
+                .kernel()
+                .fs()
+                .namei(path, &tx, self)
+                .map_err(|e| e.or(Errno::ENOENT))?;
+            self.kernel()
+                .fs()
+                .set_times(inode, atime, mtime, &tx, self)?;
+            0
+        };
+        tx.end(self);
+        res
+    }
+
+    /// Set the permission bits of file path to mode.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_chmod(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let mode = self.proc().argint(1)? as u32;
+        if mode & !S_IALLUGO != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = try {
+            let inode = self
+                .kernel()
+                .fs()
+                .namei(path, &tx, self)
+                .map_err(|e| e.or(Errno::ENOENT))?;
+            self.kernel()
+                .fs()
+                .chmod(inode, mode, &tx, self)
+                .map_err(|e| e.or(Errno::EPERM))?;
+            0
+        };
+        tx.end(self);
+        res
+    }
+
+    /// Set the owner and the group of file path to uid and gid, leaving either unchanged if it
+    /// is -1. Only the superuser may.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_chown(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let id = |id: i32| {
+            match id {
+                -1 => Ok(None),
+                _ => u16::try_from(id).map(Some).map_err(|_| Errno::EINVAL),
+            }
+        };
+        let uid = id(self.proc().argint(1)?)?;
+        let gid = id(self.proc().argint(2)?)?;
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = try {
+            let inode = self
+                .kernel()
+                .fs()
+                .namei(path, &tx, self)
+                .map_err(|e| e.or(Errno::ENOENT))?;
+            self.kernel()
+                .fs()
+                .chown(inode, uid, gid, &tx, self)
+                .map_err(|e| e.or(Errno::EPERM))?;
+            0
+        };
+        tx.end(self);
         res
     }
 
+    /// Mount the pseudo file system fstype, e.g., "devfs", on path. Only the superuser may.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_mount(&mut self) -> Result<usize, KernelError> {
+        let mut fstype: [u8; MAXPATH] = [0; MAXPATH];
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let fstype = self.proc_mut().argstr(0, &mut fstype)?.to_bytes();
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().mount(fstype, path, &tx, self);
+        tx.end(self);
+        res?;
+        Ok(0)
+    }
+
+    /// Unmount the pseudo file system mounted on path. Only the superuser may.
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_umount(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().umount(path, &tx, self);
+        tx.end(self);
+        res?;
+        Ok(0)
+    }
+
     /// Change the current directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_chdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_chdir(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
@@ -343,59 +1848,108 @@ impl KernelCtx<'_, '_> {
         res
     }
 
-    /// Load a file and execute it with arguments.
-    /// Returns Ok(argc argument to user main) on success, Err(()) on error.
-    pub fn sys_exec(&mut self) -> Result<usize, ()> {
-        let mut path: [u8; MAXPATH] = [0; MAXPATH];
-        let mut args = ArrayVec::<Page, MAXARG>::new();
-        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let uargv = self.proc().argaddr(1)?;
+    /// Fetch the null-terminated array of argument strings at user address uargv, and copy each
+    /// string into a new page pushed to args.
+    /// Returns Ok(()) on success, Err(()) on error. The caller must free the pages in args either way.
+    fn fetch_args(&mut self, uargv: usize, args: &mut ArrayVec<Page, MAXARG>) -> Result<(), ()> {
         let allocator = hal().kmem();
-
-        let mut success = false;
         for i in 0..MAXARG {
-            let uarg = ok_or!(
-                self.proc_mut()
-                    .fetchaddr((uargv + mem::size_of::<usize>() * i).into()),
-                break
-            );
-
+            let uarg = self
+                .proc_mut()
+                .fetchaddr((uargv + mem::size_of::<usize>() * i).into())?;
             if uarg == 0 {
-                success = true;
-                break;
+                return Ok(());
             }
 
-            let mut page = some_or!(allocator.alloc(), break);
+            let mut page = allocator.alloc(PageOwner::Exec).ok_or(())?;
             if self
                 .proc_mut()
                 .fetchstr(uarg.into(), &mut page[..])
                 .is_err()
             {
                 allocator.free(page);
-                break;
+                return Err(());
             }
             args.push(page);
         }
+        Err(())
+    }
 
-        let ret = if success {
-            self.exec(path, &args)
-        } else {
-            Err(())
+    /// Load a file and execute it with arguments.
+    /// Returns Ok(argc argument to user main) on success, Err(error) on error.
+    pub fn sys_exec(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let mut args = ArrayVec::<Page, MAXARG>::new();
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let uargv = self.proc().argaddr(1)?;
+
+        let ret = match self.fetch_args(uargv, &mut args) {
+            Ok(()) => self.exec(path, &args).map_err(|e| e.or(Errno::ENOEXEC)),
+            Err(()) => Err(Errno::EFAULT.into()),
         };
 
         for page in args.drain(..) {
-            allocator.free(page);
+            hal().kmem().free(page);
         }
 
-        ret
+        Ok(ret?)
+    }
+
+    /// Create a child process running the program at path with arguments argv,
+    /// without copying the memory of the current process.
+    /// Returns Ok(child's PID) on success, Err(error) on error.
+    pub fn sys_spawn(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let mut args = ArrayVec::<Page, MAXARG>::new();
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let uargv = self.proc().argaddr(1)?;
+
+        let ret = match self.fetch_args(uargv, &mut args) {
+            Ok(()) => {
+                self.kernel()
+                    .procs()
+                    .spawn(path, &args, self)
+                    .map_err(|e| e.or(Errno::ENOEXEC))
+            }
+            Err(()) => Err(Errno::EFAULT.into()),
+        };
+
+        for page in args.drain(..) {
+            hal().kmem().free(page);
+        }
+
+        Ok(ret? as _)
     }
 
     /// Create a pipe.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_pipe(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(error) on error.
+    pub fn sys_pipe(&mut self) -> Result<usize, KernelError> {
         // user pointer to array of two integers
         let fdarray = self.proc().argaddr(0)?.into();
         self.pipe(fdarray)?;
         Ok(0)
     }
 }
+
+/// Converts the duration `tv` to ticks, rounding up.
+/// Returns Ok(ticks) on success, Err(EINVAL) if `tv` is not a valid duration.
+fn timeval_to_ticks(tv: &Timeval) -> Result<u32, KernelError> {
+    if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) {
+        return Err(Errno::EINVAL.into());
+    }
+    let us = (tv.tv_sec as u64)
+        .saturating_mul(1_000_000)
+        .saturating_add(tv.tv_usec as u64);
+    let ticks = us / timer::US_PER_TICK + (us % timer::US_PER_TICK != 0) as u64;
+    // Tick counts are compared by their difference as an i32.
+    Ok(cmp::min(ticks, i32::MAX as u64 - 1) as u32)
+}
+
+/// Converts a number of ticks to a duration.
+fn ticks_to_timeval(ticks: u32) -> Timeval {
+    let us = ticks as u64 * timer::US_PER_TICK;
+    Timeval {
+        tv_sec: (us / 1_000_000) as i64,
+        tv_usec: (us % 1_000_000) as i64,
+    }
+}
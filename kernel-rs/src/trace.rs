@@ -0,0 +1,130 @@
+//! Static tracepoints, recorded with the `tracepoints` feature.
+//!
+//! A tracepoint writes a fixed-size `TraceRecord` into the ring buffer of the CPU that hit it,
+//! with interrupts disabled and without taking any lock, so that it can be placed anywhere,
+//! including in the spinlock itself. `sys_trace_read` drains the buffers. The records of each CPU
+//! are in order, and those of different CPUs can be merged by their timestamps offline.
+//!
+//! If a buffer is full, new records are dropped rather than overwriting the undrained ones, and
+//! counted in `dropped`.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+use rv6_abi::TraceRecord;
+
+use crate::{
+    arch::riscv::r_time,
+    cpu::cpuid,
+    hal::hal,
+    lock::SpinLock,
+    param::{NCPU, NTRACE},
+};
+
+/// Ring buffer of the records of a CPU. Only the CPU writes to it, and the readers serialize
+/// through `Tracer::read_lock`.
+struct TraceBuf {
+    records: [UnsafeCell<TraceRecord>; NTRACE],
+
+    /// Number of the records read.
+    head: AtomicUsize,
+
+    /// Number of the records written.
+    tail: AtomicUsize,
+
+    /// Number of the records dropped since the buffer was full.
+    dropped: AtomicUsize,
+}
+
+// SAFETY: a record is written only by the CPU owning the buffer before publishing it through
+// `tail`, and read only by the holder of `read_lock` before releasing it through `head`.
+unsafe impl Sync for TraceBuf {}
+
+impl TraceBuf {
+    const fn new() -> Self {
+        Self {
+            records: array![_ => UnsafeCell::new(TraceRecord {
+                time: 0,
+                event: 0,
+                cpu: 0,
+                args: [0; 2],
+            }); NTRACE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+}
+
+pub struct Tracer {
+    bufs: [TraceBuf; NCPU],
+
+    /// Serializes the readers.
+    read_lock: SpinLock<()>,
+}
+
+impl Tracer {
+    pub const fn new() -> Self {
+        Self {
+            bufs: array![_ => TraceBuf::new(); NCPU],
+            read_lock: SpinLock::new("trace", ()),
+        }
+    }
+
+    /// Records `event` with `args` in the buffer of the current CPU.
+    pub fn record(&self, event: u32, args: [u64; 2]) {
+        if !cfg!(feature = "tracepoints") {
+            return;
+        }
+        // Stay on this CPU, and keep interrupt handlers off the buffer meanwhile.
+        let intr = hal().cpus().push_off();
+        let cpu = cpuid();
+        let buf = &self.bufs[cpu];
+        let tail = buf.tail.load(Ordering::Relaxed);
+        if tail - buf.head.load(Ordering::Acquire) >= NTRACE {
+            let _ = buf.dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            // SAFETY: the slot is not visible to the readers until tail is advanced.
+            unsafe {
+                *buf.records[tail % NTRACE].get() = TraceRecord {
+                    time: r_time() as u64,
+                    event,
+                    cpu: cpu as u32,
+                    args,
+                }
+            };
+            buf.tail.store(tail + 1, Ordering::Release);
+        }
+        unsafe { hal().cpus().pop_off(intr) };
+    }
+
+    /// Moves the oldest records of the CPUs, one CPU after another, into `out`.
+    /// Returns the number of the records moved.
+    pub fn read(&self, out: &mut [TraceRecord]) -> usize {
+        let _guard = self.read_lock.lock();
+        let mut n = 0;
+        for buf in &self.bufs {
+            let mut head = buf.head.load(Ordering::Relaxed);
+            let tail = buf.tail.load(Ordering::Acquire);
+            while head != tail && n < out.len() {
+                // SAFETY: the record was published by advancing tail, and we hold read_lock.
+                out[n] = unsafe { *buf.records[head % NTRACE].get() };
+                head += 1;
+                n += 1;
+            }
+            buf.head.store(head, Ordering::Release);
+        }
+        n
+    }
+
+    /// Returns the number of the records dropped since the buffers were full.
+    pub fn dropped(&self) -> usize {
+        self.bufs
+            .iter()
+            .map(|buf| buf.dropped.load(Ordering::Relaxed))
+            .sum()
+    }
+}
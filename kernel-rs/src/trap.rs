@@ -1,18 +1,30 @@
-use core::mem;
+use core::{
+    fmt::{self, Write},
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use crate::{
     arch::addr::PGSIZE,
-    arch::memlayout::{TRAMPOLINE, TRAPFRAME, UART0_IRQ, VIRTIO0_IRQ},
+    arch::memlayout::{TRAMPOLINE, UART0, UART0_IRQ, VIRTIO0_IRQ},
     arch::plic::{plic_claim, plic_complete},
     arch::riscv::{
-        intr_get, intr_off, intr_on, r_satp, r_scause, r_sepc, r_sip, r_stval, r_tp, w_sepc, w_sip,
-        w_stvec, Sstatus,
+        intr_get, intr_off, intr_on, r_satp, r_scause, r_sepc, r_sip, r_stval, r_tp,
+        sfence_vma_page, w_sepc, w_sip, w_sscratch, w_stvec, Sstatus,
     },
+    arch::timer,
+    arch::TargetArch,
     cpu::cpuid,
+    fcount,
     hal::hal,
     kernel::{kernel_ref, KernelRef},
-    ok_or,
+    loadavg::LOAD_FREQ,
+    model::DETERMINISTIC,
+    param::NCPU,
     proc::{kernel_ctx, KernelCtx, Procstate},
+    uart::Uart,
+    util::spin_loop,
+    vm::PteFlags,
 };
 
 extern "C" {
@@ -25,13 +37,91 @@ extern "C" {
 
     // In kernelvec.S, calls kerneltrap().
     fn kernelvec();
+
+    // In kernelvec.S, calls earlytrap().
+    fn earlyvec();
 }
 
-pub fn trapinit() {}
+/// Size of the emergency stack of a CPU.
+const EMERGENCY_STACK_SIZE: usize = 4096;
+
+/// Stacks that kernelvec switches to when a kernel stack overflows into its guard page, one per
+/// CPU, so that the overflow can be reported instead of faulting again and again.
+#[repr(C, align(16))]
+struct EmergencyStacks([[u8; EMERGENCY_STACK_SIZE]; NCPU]);
+
+static mut EMERGENCY_STACKS: EmergencyStacks = EmergencyStacks([[0; EMERGENCY_STACK_SIZE]; NCPU]);
+
+/// Sends traps from the kernel to kernelvec, and tells it the emergency stack of this CPU.
+///
+/// # Safety
+///
+/// Interrupts must be disabled.
+unsafe fn set_kernelvec() {
+    // SAFETY: only kernelvec on this CPU uses the stack, and only through sscratch.
+    let top = unsafe { EMERGENCY_STACKS.0[cpuid()].as_ptr_range().end };
+    unsafe { w_sscratch(top as _) };
+    unsafe { w_stvec(kernelvec as _) };
+}
 
 /// Set up to take exceptions and traps while in the kernel.
 pub unsafe fn trapinithart() {
-    unsafe { w_stvec(kernelvec as _) };
+    unsafe { set_kernelvec() };
+}
+
+/// Set up to report traps that this CPU takes before `trapinithart`, when neither the kernel nor
+/// the console may be initialized yet.
+///
+/// # Safety
+///
+/// Interrupts must be disabled.
+pub unsafe fn earlytrapinithart() {
+    // SAFETY: only earlyvec on this CPU uses the stack, and only through sscratch.
+    let top = unsafe { EMERGENCY_STACKS.0[cpuid()].as_ptr_range().end };
+    unsafe { w_sscratch(top as _) };
+    unsafe { w_stvec(earlyvec as _) };
+}
+
+/// Writes to the UART directly, spinning, so that it works before the console is initialized.
+struct EarlyWriter(Uart);
+
+impl Write for EarlyWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            while self.0.is_full() {}
+            self.0.putc(c);
+        }
+        Ok(())
+    }
+}
+
+/// A CPU took a trap before `trapinithart`. earlyvec calls this on the emergency stack of the
+/// CPU. Reports the trap and parks the CPU, since nothing is ready to handle it.
+#[no_mangle]
+pub unsafe extern "C" fn earlytrap() -> ! {
+    /// Keeps reports of different CPUs from interleaving.
+    static PRINTING: AtomicBool = AtomicBool::new(false);
+
+    while PRINTING
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        ::core::hint::spin_loop();
+    }
+    // SAFETY: the UART is mapped at UART0 both before and after paging is on.
+    let mut writer = EarlyWriter(unsafe { Uart::new(UART0) });
+    let _ = writer.write_fmt(format_args!(
+        "hart {}: trap before initialization: scause={:#x} sepc={:018p} stval={:018p}\n\
+         hart {}: parked\n",
+        cpuid(),
+        r_scause(),
+        r_sepc() as *const u8,
+        r_stval() as *const u8,
+        cpuid()
+    ));
+    PRINTING.store(false, Ordering::Release);
+
+    spin_loop()
 }
 
 /// Handle an interrupt, exception, or system call from user space.
@@ -52,9 +142,28 @@ pub unsafe fn kerneltrap() {
     unsafe { kernel_ref(|kref| kref.kernel_trap()) };
 }
 
+/// A kernel stack has overflowed into its guard page at `sp`. kernelvec calls this on the
+/// emergency stack of the CPU.
+#[no_mangle]
+pub unsafe extern "C" fn kernelstackoverflow(sp: usize) -> ! {
+    // SAFETY: kernelstackoverflow can be reached only after the initialization of the kernel.
+    unsafe {
+        kernel_ref(|kref| {
+            kref.as_ref().write_fmt(format_args!(
+                "kernel stack overflow: sp={:018p} sepc={:018p} stval={:018p}\n",
+                sp as *const u8,
+                r_sepc() as *const u8,
+                r_stval() as *const u8
+            ))
+        })
+    };
+    panic!("kernel stack overflow");
+}
+
 impl KernelCtx<'_, '_> {
     /// `user_trap` can be reached only from the user mode, so it is a method of `KernelCtx`.
     unsafe fn user_trap(mut self) -> ! {
+        fcount!(KernelCtx::user_trap);
         assert!(
             !Sstatus::read().contains(Sstatus::SPP),
             "usertrap: not from user mode"
@@ -62,7 +171,11 @@ impl KernelCtx<'_, '_> {
 
         // Send interrupts and exceptions to kerneltrap(),
         // since we're now in the kernel.
-        unsafe { w_stvec(kernelvec as _) };
+        unsafe { set_kernelvec() };
+
+        // The kernel does not use the user page table, so other CPUs need not interrupt us to
+        // flush it any more.
+        TargetArch::leave_user();
 
         let mut which_dev: i32 = 0;
 
@@ -71,9 +184,7 @@ impl KernelCtx<'_, '_> {
         if r_scause() == 8 {
             // system call
 
-            if self.proc().killed() {
-                self.kernel().procs().exit_current(-1, &mut self);
-            }
+            self.exit_if_killed();
 
             // sepc points to the ecall instruction,
             // but we want to return to the next instruction.
@@ -83,13 +194,20 @@ impl KernelCtx<'_, '_> {
             // so don't enable until done with those registers.
             unsafe { intr_on() };
             let syscall_no = self.proc_mut().trap_frame_mut().a7 as i32;
-            self.proc_mut().trap_frame_mut().a0 = ok_or!(self.syscall(syscall_no), usize::MAX);
+            // A failed system call returns -errno.
+            self.proc_mut().trap_frame_mut().a0 = match self.syscall(syscall_no) {
+                Ok(ret) => ret,
+                Err(err) => err.errno().into_ret(),
+            };
+        } else if self.map_faulted_page() {
+            // Retry the access on the page mapped back.
         } else {
             which_dev = unsafe { self.kernel().dev_intr() };
             if which_dev == 0 {
                 self.kernel().as_ref().write_fmt(format_args!(
-                    "usertrap(): unexpected scause {:018p} pid={}\n",
+                    "usertrap(): unexpected scause {:018p} pid={} tid={}\n",
                     r_scause() as *const u8,
+                    self.proc().tgid(),
                     self.proc().pid()
                 ));
                 self.kernel().as_ref().write_fmt(format_args!(
@@ -101,18 +219,40 @@ impl KernelCtx<'_, '_> {
             }
         }
 
-        if self.proc().killed() {
-            self.kernel().procs().exit_current(-1, &mut self);
-        }
-
-        // Give up the CPU if this is a timer interrupt.
-        if which_dev == 2 {
+        // Give up the CPU if this is a timer interrupt and the process has used up its time slice.
+        if which_dev == 2 && !DETERMINISTIC && self.tick_expired() {
             self.yield_cpu();
         }
 
+        // Finish the work deferred by interrupt handlers, with interrupts enabled.
+        unsafe { intr_on() };
+        self.kernel().run_softirqs();
+
+        // Take the signals, which may make us return to a signal handler, or exit.
+        self.handle_signals();
+
         unsafe { self.user_trap_ret() }
     }
 
+    /// Maps back the page that the user has faulted on, if it has been discarded, or copies it if
+    /// the user has written to a page lent by the page cache.
+    /// Returns true if it has been mapped, and false if the trap is not such a page fault.
+    fn map_faulted_page(&mut self) -> bool {
+        let perm = match r_scause() {
+            12 => PteFlags::X,
+            13 => PteFlags::R,
+            15 => PteFlags::W,
+            _ => return false,
+        };
+        // The TLB may still hold the invalid translation, while another thread has mapped the page.
+        // SAFETY: flushing the TLB only makes the CPU walk the page tables again.
+        unsafe { sfence_vma_page(r_stval()) };
+        self.proc()
+            .memory()
+            .fault(r_stval(), perm, hal().kmem())
+            .is_ok()
+    }
+
     /// Return to user space.
     pub unsafe fn user_trap_ret(mut self) -> ! {
         // We're about to switch the destination of traps from
@@ -159,22 +299,22 @@ impl KernelCtx<'_, '_> {
         // Set S Exception Program Counter to the saved user pc.
         unsafe { w_sepc(self.proc().trap_frame().epc) };
 
-        // Tell trampoline.S the user page table to switch to.
-        let satp: usize = self.proc().memory().satp();
-
         // Jump to trampoline.S at the top of memory, which
         // switches to the user page table, restores user registers,
         // and switches to user mode with sret.
         let fn_0: usize =
             TRAMPOLINE + unsafe { userret.as_ptr().offset_from(trampoline.as_ptr()) } as usize;
         let fn_0 = unsafe { mem::transmute::<_, unsafe extern "C" fn(usize, usize) -> !>(fn_0) };
-        unsafe { fn_0(TRAPFRAME, satp) }
+        // Tell trampoline.S the user page table to switch to.
+        let satp: usize = self.proc().memory().enter_user();
+        unsafe { fn_0(self.proc().trap_frame_va(), satp) }
     }
 }
 
 impl KernelRef<'_, '_> {
     /// `kernel_trap` can be reached from the kernel mode, so it is a method of `Kernel`.
     unsafe fn kernel_trap(self) {
+        fcount!(KernelRef::kernel_trap);
         let sepc = r_sepc();
         let sstatus = Sstatus::read();
         let scause = r_scause();
@@ -198,7 +338,7 @@ impl KernelRef<'_, '_> {
         }
 
         // Give up the CPU if this is a timer interrupt.
-        if which_dev == 2 {
+        if which_dev == 2 && !DETERMINISTIC {
             // TODO(https://github.com/kaist-cp/rv6/issues/517): safety?
             if let Some(ctx) = unsafe { self.get_ctx() } {
                 // SAFETY:
@@ -219,7 +359,19 @@ impl KernelRef<'_, '_> {
     fn clock_intr(self) {
         let mut ticks = self.ticks().lock();
         *ticks = ticks.wrapping_add(1);
-        ticks.wakeup(self);
+        self.sleep_queue().expire(*ticks, self);
+        let now = *ticks;
+        let sample = now % LOAD_FREQ == 0;
+        drop(ticks);
+
+        self.procs().expire_alarms(now);
+
+        if sample {
+            let active = self
+                .procs()
+                .count(|state| state == Procstate::RUNNABLE || state == Procstate::RUNNING);
+            self.loadavg().update(active);
+        }
     }
 
     /// Check if it's an external interrupt or software interrupt,
@@ -235,12 +387,15 @@ impl KernelRef<'_, '_> {
 
             // irq indicates which device interrupted.
             let irq = unsafe { plic_claim() };
+            if irq != 0 {
+                self.entropy().add_interrupt(irq as usize);
+            }
 
             if irq as usize == UART0_IRQ {
-                // SAFETY: it's unsafe only when ctrl+p is pressed.
+                // SAFETY: it's unsafe only when ctrl+p or ctrl+t is pressed.
                 unsafe { hal().console().intr(self) };
             } else if irq as usize == VIRTIO0_IRQ {
-                hal().disk().pinned_lock().get_pin_mut().intr(self);
+                hal().disk().intr(self);
             } else if irq != 0 {
                 // Use `panic!` instead of `println` to prevent stack overflow.
                 // https://github.com/kaist-cp/rv6/issues/311
@@ -257,16 +412,33 @@ impl KernelRef<'_, '_> {
             1
         } else if scause == 0x8000000000000001 {
             // Software interrupt from a machine-mode timer interrupt,
-            // forwarded by timervec in selfvec.S.
+            // forwarded by timervec in kernelvec.S.
+
+            // Acknowledge the software interrupt by clearing
+            // the SSIP bit in sip, before taking what expired, so that
+            // an expiration flagged after that raises it again.
+            unsafe { w_sip(r_sip() & !2) };
+            if timer::take_ipi() {
+                TargetArch::handle_shootdown();
+            }
+            let (ticked, oneshot) = timer::take_expired();
+
+            // 0 is not an irq number, so it stands for the timer.
+            self.entropy().add_interrupt(0);
 
+            if oneshot {
+                let ticks = self.ticks().lock();
+                self.sleep_queue().expire_oneshot(self);
+                drop(ticks);
+            }
+
+            if !ticked {
+                return 1;
+            }
             if cpuid() == 0 {
                 self.clock_intr();
             }
 
-            // Acknowledge the software interrupt by clearing
-            // the SSIP bit in sip.
-            unsafe { w_sip(r_sip() & !2) };
-
             2
         } else {
             0
@@ -3,9 +3,7 @@
 // Dead code is allowed in this file because not all components are used in the kernel.
 #![allow(dead_code)]
 
-use core::ptr;
-
-use self::UartCtrlRegs::{FCR, IER, ISR, LCR, LSR, RBR, THR};
+use crate::hal::mmio::{MmioRegion, ReadOnly, ReadWrite, Reserved};
 
 enum UartRegBits {
     IERTxEnable,
@@ -40,44 +38,24 @@ impl UartRegBits {
 /// Some have different meanings for
 /// read vs write.
 /// see http://byterunner.com/16550.html
-#[repr(usize)]
-enum UartCtrlRegs {
-    /// Recieve Buffer Register.
-    RBR,
-    /// Transmit Holding Register (for output bytes).
-    THR,
+#[repr(C)]
+struct UartCtrlRegs {
+    /// Recieve Buffer Register on read, Transmit Holding Register (for output bytes) on write.
+    rbr_thr: ReadWrite<u8>,
     /// Interrupt Enable Register.
-    IER,
-    /// FIFO Control Register.
-    FCR,
-    /// Interrupt Status Register.
-    ISR,
+    ier: ReadWrite<u8>,
+    /// Interrupt Status Register on read, FIFO Control Register on write.
+    isr_fcr: ReadWrite<u8>,
     /// Line Control Register.
-    LCR,
+    lcr: ReadWrite<u8>,
+    /// Modem Control Register.
+    _mcr: Reserved<1>,
     /// Line Status Register.
-    LSR,
-}
-
-impl UartCtrlRegs {
-    /// The UART control registers are memory-mapped
-    /// at address uart. This macro returns the
-    /// address of one of the registers.
-    fn addr(self, uart: usize) -> *mut u8 {
-        match self {
-            THR | RBR => uart as *mut u8,
-            IER => (uart + 1) as *mut u8,
-            FCR | ISR => (uart + 2) as *mut u8,
-            LCR => (uart + 3) as *mut u8,
-            LSR => (uart + 5) as *mut u8,
-        }
-    }
+    lsr: ReadOnly<u8>,
 }
 
-/// # Safety
-///
-/// uart..(uart + 5) are owned addresses.
 pub struct Uart {
-    uart: usize,
+    regs: MmioRegion<UartCtrlRegs>,
 }
 
 impl Uart {
@@ -85,44 +63,44 @@ impl Uart {
     ///
     /// uart..(uart + 5) are owned addresses.
     pub const unsafe fn new(uart: usize) -> Self {
-        Self { uart }
+        Self {
+            regs: unsafe { MmioRegion::new(uart) },
+        }
     }
 
     pub fn init(&self) {
         // Disable interrupts.
-        self.write(IER, 0x00);
+        self.regs.ier.set(0x00);
 
         // Special mode to set baud rate.
-        self.write(LCR, UartRegBits::LCRBaudLatch.bits());
+        self.regs.lcr.set(UartRegBits::LCRBaudLatch.bits());
 
         // LSB for baud rate of 38.4K.
-        self.write(RBR, 0x03);
+        self.regs.rbr_thr.set(0x03);
 
         // MSB for baud rate of 38.4K.
-        self.write(IER, 0x00);
+        self.regs.ier.set(0x00);
 
         // Leave set-baud mode,
         // and set word length to 8 bits, no parity.
-        self.write(LCR, UartRegBits::LCREightBits.bits());
+        self.regs.lcr.set(UartRegBits::LCREightBits.bits());
 
         // Reset and enable FIFOs.
-        self.write(
-            FCR,
-            UartRegBits::FCRFifoEnable.bits() | UartRegBits::FCRFifoClear.bits(),
-        );
+        self.regs
+            .isr_fcr
+            .set(UartRegBits::FCRFifoEnable.bits() | UartRegBits::FCRFifoClear.bits());
 
         // Enable transmit and receive interrupts.
-        self.write(
-            IER,
-            UartRegBits::IERTxEnable.bits() | UartRegBits::IERRxEnable.bits(),
-        );
+        self.regs
+            .ier
+            .set(UartRegBits::IERTxEnable.bits() | UartRegBits::IERRxEnable.bits());
     }
 
     /// Read one input character from the UART. Return Err(()) if none is waiting.
     pub fn getc(&self) -> Result<i32, ()> {
-        if self.read(LSR) & 0x01 != 0 {
+        if self.regs.lsr.get() & 0x01 != 0 {
             // Input data is ready.
-            Ok(self.read(RBR) as i32)
+            Ok(self.regs.rbr_thr.get() as i32)
         } else {
             Err(())
         }
@@ -130,27 +108,11 @@ impl Uart {
 
     /// Write one output character to the UART.
     pub fn putc(&self, c: u8) {
-        self.write(THR, c);
+        self.regs.rbr_thr.set(c);
     }
 
     /// Check whether the UART transmit holding register is full.
     pub fn is_full(&self) -> bool {
-        (self.read(LSR) & UartRegBits::LSRTxIdle.bits()) == 0
-    }
-
-    fn read(&self, reg: UartCtrlRegs) -> u8 {
-        // SAFETY:
-        // * the address is valid because of the invariant of self.
-        // * volatile concurrent accesses are safe.
-        //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::read_volatile(reg.addr(self.uart)) }
-    }
-
-    fn write(&self, reg: UartCtrlRegs, v: u8) {
-        // SAFETY:
-        // * the address is valid because of the invariant of self.
-        // * volatile concurrent accesses are safe.
-        //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::write_volatile(reg.addr(self.uart), v) }
+        (self.regs.lsr.get() & UartRegBits::LSRTxIdle.bits()) == 0
     }
 }
@@ -0,0 +1,90 @@
+//! The ChaCha20 stream cipher, used as a random number generator.
+
+/// "expand 32-byte k" in little endian.
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// Returns the block of the keystream of `key` at `counter`, with a zero nonce.
+pub fn block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut state = [0; 16];
+    state[..4].copy_from_slice(&CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+
+    let mut x = state;
+    for _ in 0..10 {
+        // Column rounds.
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        // Diagonal rounds.
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    for (x, s) in x.iter_mut().zip(state.iter()) {
+        *x = x.wrapping_add(*s);
+    }
+    x
+}
+
+/// A deterministic random bit generator that outputs the keystream of ChaCha20.
+///
+/// After each request, the key is replaced with fresh keystream, so that the outputs already
+/// handed out cannot be recovered from the state.
+pub struct ChaChaRng {
+    key: [u32; 8],
+    counter: u64,
+}
+
+impl ChaChaRng {
+    pub const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            counter: 0,
+        }
+    }
+
+    /// Mixes `seed` into the key.
+    pub fn reseed(&mut self, seed: &[u32; 8]) {
+        for (k, s) in self.key.iter_mut().zip(seed.iter()) {
+            *k ^= *s;
+        }
+        self.rekey();
+    }
+
+    fn next_block(&mut self) -> [u32; 16] {
+        let block = block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        block
+    }
+
+    fn rekey(&mut self) {
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[..8]);
+    }
+
+    /// Fills `buf` with random bytes.
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(64) {
+            let block = self.next_block();
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (block[i / 4] >> (8 * (i % 4))) as u8;
+            }
+        }
+        self.rekey();
+    }
+}
@@ -4,11 +4,10 @@
 #![allow(dead_code)]
 
 pub mod branded;
+pub mod chacha;
 pub mod etrace;
-pub mod intrusive_list;
-pub mod pinned_array;
-pub mod static_arc;
-pub mod strong_pin;
+
+pub use kernel_rs_lib::{intrusive_list, pinned_array, static_arc, strong_pin};
 
 pub fn spin_loop() -> ! {
     loop {
@@ -12,11 +12,15 @@
 // virtio mmio control registers, mapped starting at 0x10001000.
 // from qemu virtio_mmio.h
 
-use core::ptr;
+use core::mem;
 
 use bitflags::bitflags;
+use static_assertions::const_assert_eq;
 
-use crate::arch::memlayout::VIRTIO0;
+use crate::{
+    arch::memlayout::VIRTIO0,
+    hal::mmio::{MmioRegion, ReadOnly, ReadWrite, Reserved, WriteOnly},
+};
 
 mod virtio_disk;
 
@@ -29,99 +33,85 @@ pub use virtio_disk::VirtioDisk;
 ///
 /// * The `GuestPageSize` should be set to the page size of the guest architecture.
 /// * All queues should be correctly initialized.
-#[repr(usize)]
-enum MmioRegs {
+#[repr(C)]
+struct MmioRegs {
     /// 0x74726976
-    MagicValue = 0x000,
+    magic_value: ReadOnly<u32>,
     /// version; 1 is legacy
-    Version = 0x004,
+    version: ReadOnly<u32>,
     /// device type; 1 is net, 2 is disk
-    DeviceId = 0x008,
+    device_id: ReadOnly<u32>,
     /// 0x554d4551
-    VendorId = 0x00c,
-    DeviceFeatures = 0x010,
-    DriverFeatures = 0x020,
-    /// page size for PFN, write-only
-    GuestPageSize = 0x028,
-    /// select queue, write-only
-    QueueSel = 0x030,
-    /// max size of current queue, read-only
-    QueueNumMax = 0x034,
-    /// size of current queue, write-only
-    QueueNum = 0x038,
-    /// physical page number for queue, read/write
-    QueuePfn = 0x040,
+    vendor_id: ReadOnly<u32>,
+    device_features: ReadOnly<u32>,
+    _reserved0: Reserved<0xc>,
+    driver_features: WriteOnly<u32>,
+    _reserved1: Reserved<0x4>,
+    /// page size for PFN
+    guest_page_size: WriteOnly<u32>,
+    _reserved2: Reserved<0x4>,
+    /// select queue
+    queue_sel: WriteOnly<u32>,
+    /// max size of current queue
+    queue_num_max: ReadOnly<u32>,
+    /// size of current queue
+    queue_num: WriteOnly<u32>,
+    _reserved3: Reserved<0x4>,
+    /// physical page number for queue
+    queue_pfn: ReadWrite<u32>,
     /// ready bit
-    QueueReady = 0x044,
-    /// write-only
-    QueueNotify = 0x050,
-    /// read-only
-    InterruptStatus = 0x060,
-    /// write-only
-    InterruptAck = 0x064,
-    /// read/write
-    Status = 0x070,
+    queue_ready: ReadWrite<u32>,
+    _reserved4: Reserved<0x8>,
+    queue_notify: WriteOnly<u32>,
+    _reserved5: Reserved<0xc>,
+    interrupt_status: ReadOnly<u32>,
+    interrupt_ack: WriteOnly<u32>,
+    _reserved6: Reserved<0x8>,
+    status: ReadWrite<u32>,
+    _reserved7: Reserved<0x8c>,
+    /// device-specific configuration; for a disk, the low half of its capacity in 512-byte sectors
+    capacity_low: ReadOnly<u32>,
+    /// the high half of the capacity
+    capacity_high: ReadOnly<u32>,
+    _reserved8: Reserved<0x1c>,
+    /// the maximum number of sectors in a discard request
+    max_discard_sectors: ReadOnly<u32>,
 }
 
-impl MmioRegs {
-    fn read(self) -> u32 {
-        // SAFETY:
-        // * `src` is valid, as the kernel can access [VIRTIO0..VIRTIO0+PGSIZE).
-        // * `src` is properly aligned, as self % 4 == 0.
-        // * `src` points to a properly initialized value, as u32 does not have
-        //   any internal structure to be initialized.
-        // * volatile concurrent accesses are safe.
-        //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::read_volatile((VIRTIO0 as *mut u8).add(self as _) as _) }
-    }
+const_assert_eq!(mem::size_of::<MmioRegs>(), 0x128);
 
-    /// # Safety
-    ///
-    /// Writing at memory mapped registers may cause hardware side effects.
-    /// For example, after writing at `QueueNotify`, the virtio driver reads/writes the address given by the kernel.
-    /// If a wrong address was given, this could lead to undefined behavior.
-    unsafe fn write(self, dst: u32) {
-        // SAFETY:
-        // * `dst` is valid, as the kernel can access [VIRTIO0..VIRTIO0+PGSIZE).
-        // * `dst` is properly aligned, as self % 4 == 0.
-        // * volatile concurrent accesses are safe.
-        //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::write_volatile((VIRTIO0 as *mut u8).add(self as _) as _, dst) }
-    }
+/// The registers of the virtio disk, mapped starting at VIRTIO0.
+// SAFETY: the kernel can access [VIRTIO0..VIRTIO0+PGSIZE), which holds the registers.
+static REGS: MmioRegion<MmioRegs> = unsafe { MmioRegion::new(VIRTIO0) };
 
+impl MmioRegs {
     /// Checks the virtio disk's properties.
     fn check_virtio_disk() {
         assert!(
-            MmioRegs::MagicValue.read() == 0x74726976,
+            REGS.magic_value.get() == 0x74726976,
             "could not find virtio disk"
         );
-        assert!(MmioRegs::Version.read() == 1, "could not find virtio disk");
-        assert!(MmioRegs::DeviceId.read() == 2, "could not find virtio disk");
+        assert!(REGS.version.get() == 1, "could not find virtio disk");
+        assert!(REGS.device_id.get() == 2, "could not find virtio disk");
         assert!(
-            MmioRegs::VendorId.read() == 0x554d4551,
+            REGS.vendor_id.get() == 0x554d4551,
             "could not find virtio disk"
         );
     }
 
     /// Sets the virtio status.
     fn set_status(status: &VirtIOStatus) {
-        // SAFETY: simply setting status bits does not cause side effects.
-        unsafe {
-            MmioRegs::Status.write(status.bits());
-        }
+        REGS.status.set(status.bits());
     }
 
     /// Returns the device's virtio features.
     fn get_features() -> VirtIOFeatures {
-        VirtIOFeatures::from_bits_truncate(MmioRegs::DeviceFeatures.read())
+        VirtIOFeatures::from_bits_truncate(REGS.device_features.get())
     }
 
     /// Sets the device's virtio features.
     fn set_features(features: &VirtIOFeatures) {
-        // SAFETY: simply setting features bits does not cause side effects.
-        unsafe {
-            MmioRegs::DriverFeatures.write(features.bits());
-        }
+        REGS.driver_features.set(features.bits());
     }
 
     /// Sets the page size for PFN.
@@ -131,10 +121,7 @@ impl MmioRegs {
     /// The virtio driver will uses this info to calculate addresses.
     /// Hence, the caller must give the correct page size. Otherwise, the driver may read/write at wrong addresses.
     unsafe fn set_pg_size(size: u32) {
-        // SAFETY: simply telling the page size does not cause side effects.
-        unsafe {
-            MmioRegs::GuestPageSize.write(size);
-        }
+        REGS.guest_page_size.set(size);
     }
 
     /// Selects the queue `queue_num`, and initializes it with `queue_size` and `queue_addr`.
@@ -144,18 +131,13 @@ impl MmioRegs {
     /// The virtio driver will later use this info to read/write descriptors.
     /// Hence, the caller must give correct info.
     unsafe fn select_and_init_queue(queue_num: u32, queue_size: u32, queue_pg_num: u32) {
-        // SAFETY: simply selecting and initializing the queue does not cause side effects.
-        unsafe {
-            MmioRegs::QueueSel.write(queue_num);
-        }
-        let max = MmioRegs::QueueNumMax.read();
+        REGS.queue_sel.set(queue_num);
+        let max = REGS.queue_num_max.get();
         assert!(max != 0, "virtio disk has no queue {}", queue_num);
         assert!(max >= NUM as u32, "virtio disk max queue too short");
 
-        unsafe {
-            MmioRegs::QueueNum.write(queue_size);
-            MmioRegs::QueuePfn.write(queue_pg_num);
-        }
+        REGS.queue_num.set(queue_size);
+        REGS.queue_pfn.set(queue_pg_num);
     }
 
     /// Notifies the given queue number.
@@ -165,18 +147,30 @@ impl MmioRegs {
     /// After notifying the queue, the driver will try to access the queue and read/write at the addresses given through descriptors.
     /// This may cause undefined behavior if the descriptors were not well set or contains wrong addresses.
     unsafe fn notify_queue(num: u32) {
-        unsafe {
-            MmioRegs::QueueNotify.write(num);
+        REGS.queue_notify.set(num);
+    }
+
+    /// Returns the capacity of the disk in 512-byte sectors.
+    fn capacity() -> u64 {
+        // The configuration may change between the two reads, e.g., if the disk is resized.
+        loop {
+            let high = REGS.capacity_high.get();
+            let low = REGS.capacity_low.get();
+            if REGS.capacity_high.get() == high {
+                return (high as u64) << 32 | low as u64;
+            }
         }
     }
 
+    /// Returns the maximum number of sectors in a discard request.
+    fn max_discard_sectors() -> u32 {
+        REGS.max_discard_sectors.get()
+    }
+
     /// Acknowledges all interrupts.
     fn intr_ack_all() {
-        let intr_status = MmioRegs::InterruptStatus.read() & 0x3;
-        // SAFETY: simply acknowledging interrupts does not cause undefined behavior.
-        unsafe {
-            MmioRegs::InterruptAck.write(intr_status);
-        }
+        let intr_status = REGS.interrupt_status.get() & 0x3;
+        REGS.interrupt_ack.set(intr_status);
     }
 }
 
@@ -205,6 +199,9 @@ bitflags! {
         /// support more than one vq
         const BLK_F_MQ = 1 << 12;
 
+        /// Supports discard requests
+        const BLK_F_DISCARD = 1 << 13;
+
         const F_ANY_LAYOUT = 1 << 27;
         const RING_F_INDIRECT_DESC = 1 << 28;
         const RING_F_EVENT_IDX = 1 << 29;
@@ -214,6 +211,7 @@ bitflags! {
             !Self::BLK_F_SCSI.bits &
             !Self::BLK_F_CONFIG_WCE.bits &
             !Self::BLK_F_MQ.bits &
+            !Self::BLK_F_DISCARD.bits &
             !Self::F_ANY_LAYOUT.bits &
             !Self::RING_F_INDIRECT_DESC.bits &
             !Self::RING_F_EVENT_IDX.bits;
@@ -300,6 +298,9 @@ const VIRTIO_BLK_T_IN: u32 = 0;
 /// write the disk
 const VIRTIO_BLK_T_OUT: u32 = 1;
 
+/// discard sectors of the disk
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+
 impl VirtqDesc {
     const fn new() -> Self {
         Self {
@@ -4,6 +4,7 @@
 ///
 /// qemu ... -drive file=fs.img,if=none,format=raw,id=x0 -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0
 use core::array::IntoIter;
+use core::cmp;
 use core::marker::PhantomPinned;
 use core::mem;
 use core::pin::Pin;
@@ -14,18 +15,26 @@ use arrayvec::ArrayVec;
 use bitmaps::Bitmap;
 use const_zero::const_zero;
 use pin_project::pin_project;
+use rv6_abi::{TRACE_DISK_COMPLETE, TRACE_DISK_SUBMIT};
 
 use super::{
     MmioRegs, VirtIOFeatures, VirtIOStatus, VirtqAvail, VirtqDesc, VirtqDescFlags, VirtqUsed, NUM,
-    VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+    VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
 };
 use crate::{
-    arch::addr::{PGSHIFT, PGSIZE},
+    arch::{
+        addr::{PAddr, PGSHIFT, PGSIZE},
+        riscv::r_time,
+    },
     bio::Buf,
+    fcount,
+    hal::hal,
+    iostat::IoStat,
     kernel::KernelRef,
     lock::{SleepableLock, SleepableLockGuard},
     param::BSIZE,
     proc::KernelCtx,
+    some_or,
 };
 
 // It must be page-aligned.
@@ -52,6 +61,15 @@ pub struct VirtioDisk {
 
     #[pin]
     info: DiskInfo,
+
+    /// I/O statistics of this device.
+    stat: IoStat,
+
+    /// Whether the device accepts discard requests.
+    discard: bool,
+
+    /// The maximum number of sectors in a discard request.
+    max_discard_sectors: u32,
 }
 
 // It must be page-aligned because a virtqueue (desc + avail + used) occupies
@@ -72,17 +90,36 @@ struct DiskInfo {
     /// Disk command headers. One-for-one with descriptors, for convenience.
     ops: [VirtIOBlockOutHeader; NUM],
 
+    /// Ranges of discard requests. One-for-one with descriptors, for convenience.
+    discards: [VirtIOBlockDiscard; NUM],
+
     #[pin]
     _marker: PhantomPinned,
 }
 
 /// # Safety
 ///
-/// `b` refers to a valid `Buf` unless it is null.
+/// `b` refers to a valid `Buf` unless it is null. It is null for discard requests and direct
+/// transfers.
 #[derive(Copy, Clone)]
 struct InflightInfo {
     b: *mut Buf,
-    status: bool,
+    /// Written by the device: 0 on success. `STATUS_PENDING` until then.
+    status: u8,
+}
+
+/// The status of a request that the device has not completed.
+const STATUS_PENDING: u8 = 0xff;
+
+/// The data of a discard request.
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtIOBlockDiscard {
+    sector: u64,
+    num_sectors: u32,
+    flags: u32,
 }
 
 /// The format of the first descriptor in a disk request. To be followed by two
@@ -107,6 +144,9 @@ impl VirtioDisk {
             avail: VirtqAvail::new(),
             used: VirtqUsed::new(),
             info: DiskInfo::new(),
+            stat: IoStat::new(),
+            discard: false,
+            max_discard_sectors: 0,
         }
     }
 }
@@ -119,6 +159,7 @@ impl DiskInfo {
             used_idx: 0,
             inflight: [InflightInfo::new(); NUM],
             ops: [VirtIOBlockOutHeader::default(); NUM],
+            discards: [VirtIOBlockDiscard::new(); NUM],
             _marker: PhantomPinned,
         }
     }
@@ -128,12 +169,30 @@ impl InflightInfo {
     const fn new() -> Self {
         Self {
             b: ptr::null_mut(),
-            status: false,
+            status: 0,
+        }
+    }
+}
+
+impl VirtIOBlockDiscard {
+    const fn new() -> Self {
+        Self {
+            sector: 0,
+            num_sectors: 0,
+            flags: 0,
         }
     }
 }
 
 impl VirtIOBlockOutHeader {
+    fn discard() -> Self {
+        Self {
+            typ: VIRTIO_BLK_T_DISCARD,
+            reserved: 0,
+            sector: 0,
+        }
+    }
+
     fn new(write: bool, sector: usize) -> Self {
         let typ = if write {
             VIRTIO_BLK_T_OUT
@@ -194,10 +253,106 @@ impl SleepableLock<VirtioDisk> {
     pub fn write(self: Pin<&Self>, b: &mut Buf, ctx: &KernelCtx<'_, '_>) {
         VirtioDisk::rw(&mut self.pinned_lock(), b, true, ctx)
     }
+
+    /// Reads the indicated blocks of device `dev` into the buffer cache, unless they are cached
+    /// already, with as many requests in flight at once as there are descriptors for. It skips
+    /// the blocks for which no buffer or descriptor is free, as they are only read in advance.
+    pub fn read_ahead(self: Pin<&Self>, dev: u32, blocknos: &[u32], ctx: &KernelCtx<'_, '_>) {
+        let bcache = ctx.kernel().bcache();
+        for blocknos in blocknos.chunks(NUM / 3) {
+            let mut bufs = ArrayVec::<Buf, { NUM / 3 }>::new();
+            for &blockno in blocknos {
+                if let Some(buf) = bcache.lookup(dev, blockno, ctx) {
+                    buf.free(ctx);
+                    continue;
+                }
+                let buf = some_or!(bcache.try_get_buf(dev, blockno), break).lock(ctx);
+                if buf.deref_inner().valid {
+                    buf.free(ctx);
+                } else {
+                    bufs.push(buf);
+                }
+            }
+
+            let mut guard = self.pinned_lock();
+            let start = r_time();
+            let mut requests = ArrayVec::<_, { NUM / 3 }>::new();
+            // Never wait for descriptors while holding some, which would wait for ourselves.
+            for buf in bufs.iter_mut() {
+                let desc = some_or!(guard.get_pin_mut().alloc_three_descriptors(), break);
+                VirtioDisk::submit(&mut guard, &desc, buf, false);
+                requests.push(desc);
+            }
+            for (buf, desc) in bufs.iter_mut().zip(requests) {
+                while buf.deref_inner().disk {
+                    buf.vdisk_request_waitchannel.sleep(&mut guard, ctx);
+                }
+                VirtioDisk::complete(&mut guard, desc, false, start, ctx);
+                buf.deref_inner_mut().valid = true;
+            }
+            drop(guard);
+            for buf in bufs {
+                buf.free(ctx);
+            }
+        }
+    }
+
+    pub fn intr(self: Pin<&Self>, kernel: KernelRef<'_, '_>) {
+        let mut guard = self.pinned_lock();
+        if guard.get_pin_mut().intr(kernel) {
+            guard.wakeup(kernel);
+        }
+    }
+
+    /// Transfers block `blockno` between the disk and the `BSIZE` bytes at physical address `pa`,
+    /// which the device reads or writes directly, bypassing the buffer cache. The bytes must not
+    /// cross a page boundary.
+    /// Returns Err(()) if the device fails the request.
+    pub fn rw_direct(
+        self: Pin<&Self>,
+        blockno: u32,
+        pa: PAddr,
+        write: bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        VirtioDisk::rw_direct(&mut self.pinned_lock(), blockno, pa, write, ctx)
+    }
+
+    /// Tells the device that the `n` blocks from `blockno` hold no data, if it accepts discard
+    /// requests. Returns after the device completes the request, so that it cannot overtake a
+    /// later write to the blocks.
+    pub fn discard(self: Pin<&Self>, blockno: u32, n: u32, ctx: &KernelCtx<'_, '_>) {
+        let mut guard = self.pinned_lock();
+        if !guard.discard {
+            return;
+        }
+        let sectors_per_block = (BSIZE / 512) as u32;
+        let max = cmp::max(guard.max_discard_sectors / sectors_per_block, 1);
+        for start in num_iter::range_step(blockno, blockno + n, max) {
+            let len = cmp::min(max, blockno + n - start);
+            VirtioDisk::discard(
+                &mut guard,
+                start as u64 * sectors_per_block as u64,
+                len * sectors_per_block,
+                ctx,
+            );
+        }
+    }
 }
 
 impl VirtioDisk {
-    pub fn init(self: Pin<&Self>) {
+    /// Returns the I/O statistics of this device.
+    pub fn stat(&self) -> IoStat {
+        self.stat
+    }
+
+    /// Returns the number of blocks the disk holds. It grows if the host enlarges the disk.
+    pub fn capacity(&self) -> u32 {
+        let blocks = MmioRegs::capacity() / (BSIZE / 512) as u64;
+        cmp::min(blocks, u32::MAX as u64) as u32
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
         let mut status: VirtIOStatus = VirtIOStatus::empty();
 
         // MMIO registers are located below KERNBASE, while kernel text and data
@@ -219,6 +374,9 @@ impl VirtioDisk {
                 | VirtIOFeatures::RING_F_INDIRECT_DESC);
 
         MmioRegs::set_features(&features);
+        let this = self.as_mut().project();
+        *this.discard = features.contains(VirtIOFeatures::BLK_F_DISCARD);
+        *this.max_discard_sectors = MmioRegs::max_discard_sectors();
 
         // Tell device that feature negotiation is complete.
         status.insert(VirtIOStatus::FEATURES_OK);
@@ -254,7 +412,8 @@ impl VirtioDisk {
         write: bool,
         ctx: &KernelCtx<'_, '_>,
     ) {
-        let sector: usize = (*b).blockno as usize * (BSIZE / 512);
+        fcount!(VirtioDisk::rw);
+        let start = r_time();
 
         // The spec's Section 5.2 says that legacy block operations use
         // three descriptors: one for type/reserved/sector, one for the
@@ -277,6 +436,23 @@ impl VirtioDisk {
             }
         };
 
+        Self::submit(guard, &desc, b, write);
+
+        // Wait for virtio_disk_intr() to say request has finished.
+        b.vdisk_request_waitchannel.sleep(guard, ctx);
+
+        Self::complete(guard, desc, write, start, ctx);
+    }
+
+    /// Formats the three descriptors `desc` for a request to transfer `b`, and tells the device
+    /// about it. virtio_disk_intr() clears `b.disk` once the device completes it.
+    fn submit(
+        guard: &mut SleepableLockGuard<'_, Self>,
+        desc: &[Descriptor; 3],
+        b: &mut Buf,
+        write: bool,
+    ) {
+        let sector: usize = (*b).blockno as usize * (BSIZE / 512);
         let mut this = guard.get_pin_mut().project();
         let mut info = this.info.project();
 
@@ -309,7 +485,7 @@ impl VirtioDisk {
 
         // 3. Set the third descriptor.
         // device writes 0 on success
-        info.inflight[desc[0].idx].status = true;
+        info.inflight[desc[0].idx].status = STATUS_PENDING;
 
         // Device writes the status
         this.desc[desc[2].idx] = VirtqDesc {
@@ -336,23 +512,107 @@ impl VirtioDisk {
 
         fence(Ordering::SeqCst);
 
+        hal()
+            .tracer()
+            .record(TRACE_DISK_SUBMIT, [b.blockno as u64, write as u64]);
+
         // SAFETY: the all three descriptors' fields are well set.
         // Value is queue number.
         unsafe {
             MmioRegs::notify_queue(0);
         }
+    }
 
-        // Wait for virtio_disk_intr() to say request has finished.
-        b.vdisk_request_waitchannel.sleep(guard, ctx);
-
+    /// Frees the descriptors `desc` of a completed request, which was submitted at `start`, and
+    /// charges it to the device and the current process.
+    fn complete(
+        guard: &mut SleepableLockGuard<'_, Self>,
+        desc: [Descriptor; 3],
+        write: bool,
+        start: u64,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
         // As it assigns null, the invariant of inflight is maintained even if
         // b: &mut Buf becomes invalid after this method returns.
         guard.get_pin_mut().project().info.project().inflight[desc[0].idx].b = ptr::null_mut();
         IntoIter::new(desc).for_each(|desc| guard.get_pin_mut().free(desc));
+
+        // Charge the request to the device and the current process.
+        let wait_time = (r_time() - start) as usize;
+        guard
+            .get_pin_mut()
+            .project()
+            .stat
+            .charge(write, BSIZE, wait_time);
+        ctx.proc().io().charge_wait(wait_time);
+
         guard.wakeup(ctx.kernel());
     }
 
-    pub fn intr(self: Pin<&mut Self>, kernel: KernelRef<'_, '_>) {
+    /// Transfers block `blockno` between the disk and physical address `pa`, and waits for the
+    /// device to complete it.
+    fn rw_direct(
+        guard: &mut SleepableLockGuard<'_, Self>,
+        blockno: u32,
+        pa: PAddr,
+        write: bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        fcount!(VirtioDisk::rw_direct);
+        let start = r_time();
+        let desc = loop {
+            match guard.get_pin_mut().alloc_three_descriptors() {
+                Some(idx) => break idx,
+                None => guard.sleep(ctx),
+            }
+        };
+        let head = desc[0].idx;
+
+        let mut this = guard.get_pin_mut().project();
+        let mut info = this.info.project();
+
+        let header = &mut info.ops[head];
+        *header = VirtIOBlockOutHeader::new(write, blockno as usize * (BSIZE / 512));
+        this.desc[head] = VirtqDesc {
+            addr: header as *const _ as _,
+            len: mem::size_of::<VirtIOBlockOutHeader>() as _,
+            flags: VirtqDescFlags::NEXT,
+            next: desc[1].idx as _,
+        };
+        this.desc[desc[1].idx] = VirtqDesc {
+            addr: pa.into_usize() as _,
+            len: BSIZE as _,
+            flags: if write {
+                VirtqDescFlags::NEXT
+            } else {
+                VirtqDescFlags::NEXT | VirtqDescFlags::WRITE
+            },
+            next: desc[2].idx as _,
+        };
+        hal()
+            .tracer()
+            .record(TRACE_DISK_SUBMIT, [blockno as u64, write as u64]);
+
+        let status = Self::submit_unbuffered(guard, desc, ctx);
+
+        let wait_time = (r_time() - start) as usize;
+        guard
+            .get_pin_mut()
+            .project()
+            .stat
+            .charge(write, BSIZE, wait_time);
+        ctx.proc().io().charge_wait(wait_time);
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Handles the completed requests.
+    /// Returns true if a request without a `Buf`, i.e., a discard request or a direct transfer,
+    /// has completed, whose issuer waits for the lock.
+    fn intr(self: Pin<&mut Self>, kernel: KernelRef<'_, '_>) -> bool {
         // The device won't raise another interrupt until we tell it
         // we've seen this interrupt, which the following line does.
         // This may race with the device writing new entries to
@@ -368,23 +628,120 @@ impl VirtioDisk {
 
         let this = self.project();
         let info = this.info.project();
+        let mut unbuffered = false;
 
         while *info.used_idx != this.used.id {
             fence(Ordering::SeqCst);
             let id = this.used.ring[(*info.used_idx as usize) % NUM].id as usize;
 
-            assert!(!info.inflight[id].status, "Disk::intr status");
+            if info.inflight[id].b.is_null() {
+                // The issuer checks the status, since discards are only hints, which the device
+                // may fail.
+                unbuffered = true;
+            } else {
+                assert_eq!(info.inflight[id].status, 0, "Disk::intr status");
+
+                // SAFETY: from the invariant, b refers to a valid
+                // buffer unless it is null.
+                let buf = unsafe { &mut *info.inflight[id].b };
+                hal()
+                    .tracer()
+                    .record(TRACE_DISK_COMPLETE, [buf.blockno as u64, 0]);
+
+                // disk is done with buf
+                buf.deref_inner_mut().disk = false;
+                buf.vdisk_request_waitchannel.wakeup(kernel);
+            }
 
-            // SAFETY: from the invariant, b refers to a valid
-            // buffer unless it is null.
-            let buf = unsafe { &mut *info.inflight[id].b };
+            *info.used_idx += 1;
+        }
+        unbuffered
+    }
 
-            // disk is done with buf
-            buf.deref_inner_mut().disk = false;
-            buf.vdisk_request_waitchannel.wakeup(kernel);
+    /// Discards `num_sectors` sectors from `sector`, and waits for the device to complete it.
+    fn discard(
+        guard: &mut SleepableLockGuard<'_, Self>,
+        sector: u64,
+        num_sectors: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let desc = loop {
+            match guard.get_pin_mut().alloc_three_descriptors() {
+                Some(idx) => break idx,
+                None => guard.sleep(ctx),
+            }
+        };
+        let head = desc[0].idx;
 
-            *info.used_idx += 1;
+        let mut this = guard.get_pin_mut().project();
+        let mut info = this.info.project();
+
+        let header = &mut info.ops[head];
+        *header = VirtIOBlockOutHeader::discard();
+        this.desc[head] = VirtqDesc {
+            addr: header as *const _ as _,
+            len: mem::size_of::<VirtIOBlockOutHeader>() as _,
+            flags: VirtqDescFlags::NEXT,
+            next: desc[1].idx as _,
+        };
+
+        let range = &mut info.discards[head];
+        *range = VirtIOBlockDiscard {
+            sector,
+            num_sectors,
+            flags: 0,
+        };
+        this.desc[desc[1].idx] = VirtqDesc {
+            addr: range as *const _ as _,
+            len: mem::size_of::<VirtIOBlockDiscard>() as _,
+            flags: VirtqDescFlags::NEXT,
+            next: desc[2].idx as _,
+        };
+
+        let _ = Self::submit_unbuffered(guard, desc, ctx);
+    }
+
+    /// Submits the request of the three descriptors `desc`, of which the first two are set and
+    /// the last receives the status, and waits for the device to complete it. The request has no
+    /// `Buf` to wait on, so its issuer waits for the lock instead.
+    /// Returns the status that the device wrote: 0 on success.
+    fn submit_unbuffered(
+        guard: &mut SleepableLockGuard<'_, Self>,
+        desc: [Descriptor; 3],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> u8 {
+        let head = desc[0].idx;
+        let mut this = guard.get_pin_mut().project();
+        let mut info = this.info.project();
+
+        info.inflight[head].status = STATUS_PENDING;
+        this.desc[desc[2].idx] = VirtqDesc {
+            addr: &info.inflight[head].status as *const _ as _,
+            len: 1,
+            flags: VirtqDescFlags::WRITE,
+            next: 0,
+        };
+        info.inflight[head].b = ptr::null_mut();
+
+        let ring_idx = this.avail.idx as usize % NUM;
+        this.avail.ring[ring_idx] = head as _;
+        fence(Ordering::SeqCst);
+        this.avail.idx += 1;
+        fence(Ordering::SeqCst);
+
+        // SAFETY: the all three descriptors' fields are well set.
+        unsafe {
+            MmioRegs::notify_queue(0);
         }
+
+        // `intr` wakes up the waiters of the lock when a request without a `Buf` completes.
+        while guard.info.inflight[head].status == STATUS_PENDING {
+            guard.sleep(ctx);
+        }
+        let status = guard.info.inflight[head].status;
+        IntoIter::new(desc).for_each(|desc| guard.get_pin_mut().free(desc));
+        guard.wakeup(ctx.kernel());
+        status
     }
 
     /// Find a free descriptor, mark it non-free, return its index.
@@ -1,6 +1,18 @@
-use core::{cmp, marker::PhantomData, mem, pin::Pin, slice};
+use core::{
+    cmp,
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use arrayvec::ArrayVec;
 use bitflags::bitflags;
+use rv6_abi::{
+    PagemapEntry, PagemapStat, ProtFlags, PAGEMAP_ANONYMOUS, PAGEMAP_FILE, PAGEMAP_HEAP,
+    PAGEMAP_SHARED, PAGEMAP_TRAPFRAME,
+};
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
@@ -8,14 +20,20 @@ use crate::{
         pa2pte, pgrounddown, pgroundup, pte2pa, Addr, KVAddr, PAddr, UVAddr, VAddr, MAXVA, PGSIZE,
     },
     arch::memlayout::{
-        kstack, FINISHER, KERNBASE, PHYSTOP, PLIC, TRAMPOLINE, TRAPFRAME, UART0, VIRTIO0,
+        kstack, CLINT, FINISHER, KERNBASE, PHYSTOP, PLIC, TRAMPOLINE, TRAPFRAME, TRAPFRAMES, UART0,
+        VIRTIO0, VMAP_BASE, VMAP_END,
     },
     arch::riscv::{make_satp, sfence_vma, w_satp},
+    arch::tlb::{init_asids, Asid},
+    arch::{Arch, TargetArch},
+    fcount,
     fs::{FileSystem, InodeGuard, Ufs},
-    kalloc::Kmem,
+    hal::hal,
+    kalloc::{Kmem, PageOwner},
     lock::SpinLock,
     page::Page,
-    param::NPROC,
+    pagecache::PageCache,
+    param::{NKMAP, NPROC, NTHREAD, NVMA},
     proc::KernelCtx,
 };
 
@@ -39,6 +57,56 @@ bitflags! {
         const X = 1 << 3;
         /// user-accessible
         const U = 1 << 4;
+        /// accessed, set by the hardware
+        const A = 1 << 6;
+        /// dirty, i.e., written to, set by the hardware
+        const D = 1 << 7;
+        /// copy-on-write, i.e., a page that the page cache has lent to a private, writable
+        /// mapping, which is mapped without W until it is copied on the first write
+        const COW = 1 << 8;
+    }
+}
+
+impl PteFlags {
+    /// Returns the flags of a user page mapped with `prot`.
+    ///
+    /// The riscv reserves writable pages that are not readable, so PROT_WRITE implies PROT_READ.
+    /// A leaf PTE needs one of R, W and X, so a PROT_NONE page is mapped readable without U,
+    /// which only the kernel can access.
+    pub fn from_prot(prot: ProtFlags) -> Self {
+        if prot.is_empty() {
+            return Self::R;
+        }
+        let mut flags = Self::U;
+        if prot.intersects(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE) {
+            flags |= Self::R;
+        }
+        if prot.contains(ProtFlags::PROT_WRITE) {
+            flags |= Self::W;
+        }
+        if prot.contains(ProtFlags::PROT_EXEC) {
+            flags |= Self::X;
+        }
+        flags
+    }
+
+    /// Returns the protection of a user page mapped with these flags, which is `PROT_NONE` if the
+    /// user cannot access it.
+    pub fn to_prot(self) -> ProtFlags {
+        let mut prot = ProtFlags::empty();
+        if !self.contains(Self::U) {
+            return prot;
+        }
+        if self.contains(Self::R) {
+            prot |= ProtFlags::PROT_READ;
+        }
+        if self.intersects(Self::W | Self::COW) {
+            prot |= ProtFlags::PROT_WRITE;
+        }
+        if self.contains(Self::X) {
+            prot |= ProtFlags::PROT_EXEC;
+        }
+        prot
     }
 }
 
@@ -73,6 +141,14 @@ impl PageTableEntry {
         self.flag_intersects(PteFlags::V | PteFlags::U)
     }
 
+    fn is_writable(&self) -> bool {
+        self.flag_intersects(PteFlags::W)
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.flag_intersects(PteFlags::D)
+    }
+
     fn is_table(&self) -> bool {
         self.is_valid() && !self.flag_intersects(PteFlags::R | PteFlags::W | PteFlags::X)
     }
@@ -94,6 +170,46 @@ impl PageTableEntry {
         self.inner = pa2pte(pa) | (perm | PteFlags::V).bits();
     }
 
+    /// Make the entry refer to a given address with a given permission as `set_entry` does, if it
+    /// is still invalid. Another thread sharing the page table may be setting it at the same time.
+    /// Returns true if this call has set the entry.
+    fn set_entry_if_invalid(&mut self, pa: PAddr, perm: PteFlags) -> bool {
+        assert!(perm.intersects(PteFlags::R | PteFlags::W | PteFlags::X));
+        // SAFETY: usize and AtomicUsize have the same in-memory representation.
+        let inner = unsafe { &*(&mut self.inner as *mut usize as *const AtomicUsize) };
+        inner
+            .compare_exchange(
+                0,
+                pa2pte(pa) | (perm | PteFlags::V).bits(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+    }
+
+    /// Make the entry refer to a given address with a given permission as `set_entry` does, if it
+    /// still has the bits `old`. Another thread sharing the page table may be setting it at the
+    /// same time, or the hardware may be setting its A and D bits.
+    /// Returns true if this call has set the entry.
+    fn replace_entry_if(&mut self, old: usize, pa: PAddr, perm: PteFlags) -> bool {
+        assert!(perm.intersects(PteFlags::R | PteFlags::W | PteFlags::X));
+        // SAFETY: usize and AtomicUsize have the same in-memory representation.
+        let inner = unsafe { &*(&mut self.inner as *mut usize as *const AtomicUsize) };
+        inner
+            .compare_exchange(
+                old,
+                pa2pte(pa) | (perm | PteFlags::V).bits(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+    }
+
+    /// Mark the page as written to, as the hardware does on a store through the entry.
+    fn set_dirty(&mut self) {
+        self.inner |= PteFlags::D.bits();
+    }
+
     /// Make the entry inaccessible by user processes by clearing PteFlags::U.
     fn clear_user(&mut self) {
         self.inner &= !(PteFlags::U.bits());
@@ -132,7 +248,7 @@ impl RawPageTable {
     /// Return `Ok(..)` if the allocation has succeeded.
     /// Return `None` if the allocation has failed.
     fn new(allocator: Pin<&SpinLock<Kmem>>) -> Option<*mut RawPageTable> {
-        let mut page = allocator.alloc()?;
+        let mut page = allocator.alloc(PageOwner::PageTable)?;
         page.write_bytes(0);
         // This line guarantees the invariant.
         Some(page.into_usize() as *mut RawPageTable)
@@ -166,6 +282,16 @@ impl RawPageTable {
         pte
     }
 
+    /// Returns the number of page-table pages in this page table, including itself.
+    fn count_tables(&mut self) -> usize {
+        1 + self
+            .inner
+            .iter_mut()
+            .filter_map(PageTableEntry::as_table_mut)
+            .map(|table| table.count_tables())
+            .sum::<usize>()
+    }
+
     /// Recursively free page-table pages.
     /// All leaf mappings must already have been removed.
     ///
@@ -193,6 +319,10 @@ impl RawPageTable {
 /// ptr uniquely refers to a valid 3-level RawPageTable.
 struct PageTable<A: VAddr> {
     ptr: *mut RawPageTable,
+
+    /// The ASID to run the page table with. The kernel page table keeps ASID 0.
+    asid: Asid,
+
     _marker: PhantomData<A>,
 }
 
@@ -203,6 +333,7 @@ impl<A: VAddr> PageTable<A> {
     fn new(allocator: Pin<&SpinLock<Kmem>>) -> Option<Self> {
         Some(Self {
             ptr: RawPageTable::new(allocator)?,
+            asid: Asid::new(),
             _marker: PhantomData,
         })
     }
@@ -211,6 +342,17 @@ impl<A: VAddr> PageTable<A> {
         self.ptr as usize
     }
 
+    /// Returns the number of page-table pages in this page table.
+    fn count_tables(&mut self) -> usize {
+        // SAFETY: self.ptr uniquely refers to a valid RawPageTable according to the invariant.
+        unsafe { &mut *self.ptr }.count_tables()
+    }
+
+    /// Returns the `satp` for the page table, with its current ASID.
+    fn satp(&self) -> usize {
+        make_satp(self.as_usize(), self.asid.get())
+    }
+
     /// Return the reference of the PTE in this page table
     /// that corresponds to virtual address `va`. If `allocator` is `Some`,
     /// create any required page-table pages.
@@ -249,6 +391,9 @@ impl<A: VAddr> PageTable<A> {
         let pte = self.get_mut(A::from(a), Some(allocator)).ok_or(())?;
         assert!(!pte.is_valid(), "PageTable::insert");
         pte.set_entry(pa, perm);
+        // No TLB caches a valid translation of the page, so no flush is needed. A CPU that has
+        // faulted on the page may still hold the invalid one, but then it faults again, and the
+        // kernel flushes the page from its TLB and finds it mapped (see `map_faulted_page`).
         Ok(())
     }
 
@@ -277,6 +422,7 @@ impl<A: VAddr> PageTable<A> {
         assert!(pte.is_data(), "PageTable::remove");
         let pa = pte.get_pa();
         pte.invalidate();
+        TargetArch::flush_tlb_page(self.satp(), va.into_usize());
         Some(pa)
     }
 
@@ -301,7 +447,7 @@ impl<A: VAddr> Drop for PageTable<A> {
 
 /// UserMemory manages the page table and allocated pages of a process. Its
 /// invariant guarantees that every PAddr mapped to VAddr except TRAMPOLINE and
-/// TRAPFRAME is from Page. This property is crucial for safety of methods that
+/// the trap frames is from Page. This property is crucial for safety of methods that
 /// read or write on memory, such as copy_in. Also, it is essential for safety
 /// of freeing a page created from each PAddr as well.
 ///
@@ -310,18 +456,45 @@ impl<A: VAddr> Drop for PageTable<A> {
 /// For brevity, pt := page_table, and we treat pt as a function from va to pa.
 /// - If va ∈ dom(pt), va mod PGSIZE = 0 ∧ pt(va) mod PGSIZE = 0.
 /// - pt(TRAMPOLINE) = trampoline.
-/// - TRAPFRAME ∈ dom(pt).
-/// - If va ∈ dom(pt) ∧ va ∉ { TRAMPOLINE, TRAPFRAME },
+/// - If va ∈ dom(pt) ∧ TRAPFRAMES ≤ va ≤ TRAPFRAME, then pt(va) is the trap frame of a thread
+///   using this memory, which owns the page.
+/// - If va ∈ dom(pt) ∧ va < TRAPFRAMES,
 ///   then Page::from_usize(pt(va)) succeeds without breaking the invariant of Page.
-/// - If va ∈ dom(pt) where va ∉ { 0, TRAMPOLINE, TRAPFRAME },
+/// - If va ∈ dom(pt) where va < pgroundup(size) ∧ va ≠ 0,
 ///   then va - PGSIZE ∈ dom(pt).
-/// - pgroundup(size) ∉ dom(pt).
 /// - If size > 0, then pgroundup(size) - PGSIZE ∈ dom(pt).
+/// - vmas are sorted by start, and disjoint. Every vma lies within [pgroundup(size), TRAPFRAMES),
+///   and its start and end are multiples of PGSIZE.
+/// - If pgroundup(size) ≤ va < TRAPFRAMES, then va ∈ dom(pt) only if va lies within a vma, and
+///   va ∉ dom(pt) only if va lies within an anonymous vma, whose page at va has been discarded.
+/// - If va lies within a shared vma, then pt(va) is the address of a page in the page cache,
+///   which counts the mapping, rather than a page owned by this memory.
+/// - If va ∈ dom(pt) is mapped with COW, then pt(va) is a page that the page cache has lent,
+///   and this memory holds a reference to it taken by `Kmem::share`. It is mapped without W, and
+///   va lies below pgroundup(size), or within a private vma that allows writes.
 pub struct UserMemory {
     /// Page table of process.
     page_table: PageTable<UVAddr>,
     /// Size of process memory (bytes).
     size: usize,
+    /// Regions mapped by mmap, above the heap.
+    vmas: ArrayVec<Vma, NVMA>,
+    /// The address below which mmap places the regions without a fixed address, if it can.
+    mmap_base: usize,
+}
+
+/// A region of pages mapped by mmap, from start to end.
+#[derive(Clone, Copy)]
+struct Vma {
+    start: usize,
+    end: usize,
+    perm: PteFlags,
+    /// Whether the pages are shared pages of a file in the page cache. Otherwise, they are
+    /// private to this memory.
+    shared: bool,
+    /// Whether the pages are zero-filled private pages, which may be discarded and zero-filled
+    /// again on the next access.
+    anonymous: bool,
 }
 
 impl UserMemory {
@@ -369,11 +542,13 @@ impl UserMemory {
         let mut memory = Self {
             page_table: scopeguard::ScopeGuard::into_inner(page_table),
             size: 0,
+            vmas: ArrayVec::new(),
+            mmap_base: TRAPFRAMES,
         };
 
         if let Some(src) = src_opt {
             assert!(src.len() < PGSIZE, "new: more than a page");
-            let mut page = allocator.alloc()?;
+            let mut page = allocator.alloc(PageOwner::User)?;
             page.write_bytes(0);
             (&mut page[..src.len()]).copy_from_slice(src);
             memory
@@ -390,34 +565,103 @@ impl UserMemory {
     }
 
     /// Makes a new memory by copying a given memory. Copies both the page
-    /// table and the physical memory. Returns Some(memory) on success, None on
+    /// table and the physical memory, except the shared pages of files, which
+    /// are mapped in both memories. Returns Some(memory) on success, None on
     /// failure. Frees any allocated pages on failure.
-    pub fn clone(&mut self, trap_frame: PAddr, allocator: Pin<&SpinLock<Kmem>>) -> Option<Self> {
+    pub fn clone(
+        &mut self,
+        trap_frame: PAddr,
+        cache: &PageCache,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Option<Self> {
         let new = Self::new(trap_frame, None, allocator)?;
         let mut new = scopeguard::guard(new, |mut new| {
-            let _ = new.dealloc(0, allocator);
+            new.unmap_shared(cache);
+            new.free(allocator)
         });
         for i in num_iter::range_step(0, self.size, PGSIZE) {
-            let pte = self
-                .page_table
-                .get_mut(i.into(), None)
-                .expect("clone_into: pte not found");
-            assert!(pte.is_valid(), "clone_into: invalid page");
-
-            let pa = pte.get_pa();
-            let flags = pte.get_flags();
-            let mut page = allocator.alloc()?;
-            // SAFETY: pa is an address in page_table,
-            // and thus it is the address of a page by the invariant.
-            let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
-            page.copy_from_slice(src);
+            let (page, flags) = self.copy_page(i, allocator)?;
             new.push_page(page, flags, allocator)
                 .map_err(|page| allocator.free(page))
                 .ok()?;
         }
-        let mut new = scopeguard::ScopeGuard::into_inner(new);
         new.size = self.size;
-        Some(new)
+        new.mmap_base = self.mmap_base;
+        for vma in self.vmas.clone() {
+            new.vmas.push(Vma {
+                end: vma.start,
+                ..vma
+            });
+            for va in num_iter::range_step(vma.start, vma.end, PGSIZE) {
+                if vma.shared {
+                    let pte = self.page_table.get_mut(va.into(), None).expect("clone");
+                    let pa = pte.get_pa();
+                    new.page_table
+                        .insert(va.into(), pa, vma.perm, allocator)
+                        .ok()?;
+                    cache.share(pa);
+                } else if self.is_mapped(va) {
+                    let (page, flags) = self.copy_page(va, allocator)?;
+                    new.insert_page(va, page, flags, allocator).ok()?;
+                }
+                new.vmas.last_mut().expect("clone").end = va + PGSIZE;
+            }
+        }
+        Some(scopeguard::ScopeGuard::into_inner(new))
+    }
+
+    /// Maps `trap_frame` at a free trap frame slot, for a new thread using this memory.
+    /// Returns Ok(address of the slot) on success, Err(()) if every slot is in use.
+    pub fn map_trap_frame(
+        &mut self,
+        trap_frame: PAddr,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, ()> {
+        for slot in 0..NTHREAD {
+            let va = crate::arch::memlayout::trap_frame(slot);
+            if !self.is_mapped(va) {
+                self.page_table.insert(
+                    va.into(),
+                    trap_frame,
+                    PteFlags::R | PteFlags::W,
+                    allocator,
+                )?;
+                return Ok(va);
+            }
+        }
+        Err(())
+    }
+
+    /// Unmaps the trap frame of a thread that no longer uses this memory from `va`.
+    pub fn unmap_trap_frame(&mut self, va: usize) {
+        assert!((TRAPFRAMES..=TRAPFRAME).contains(&va), "unmap_trap_frame");
+        let _ = self.page_table.remove(va.into()).expect("unmap_trap_frame");
+    }
+
+    /// Returns a copy of the page at va, and its flags.
+    fn copy_page(
+        &mut self,
+        va: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Option<(Page, PteFlags)> {
+        let pte = self
+            .page_table
+            .get_mut(va.into(), None)
+            .expect("copy_page: pte not found");
+        assert!(pte.is_valid(), "copy_page: invalid page");
+
+        let pa = pte.get_pa();
+        let mut flags = pte.get_flags();
+        if flags.contains(PteFlags::COW) {
+            // The copy is private to the new memory.
+            flags = (flags - PteFlags::COW) | PteFlags::W;
+        }
+        let mut page = allocator.alloc(PageOwner::User)?;
+        // SAFETY: pa is an address in page_table,
+        // and thus it is the address of a page by the invariant.
+        let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
+        page.copy_from_slice(src);
+        Some((page, flags))
     }
 
     /// Get the size of this memory.
@@ -425,8 +669,628 @@ impl UserMemory {
         self.size
     }
 
-    /// Load data from a file into memory at virtual address va. va must be
-    /// page-aligned, and the pages from va to va + sz must already be mapped.
+    /// Lowers the addresses that mmap chooses by gap bytes, which must be page-aligned.
+    pub fn set_mmap_gap(&mut self, gap: usize) {
+        assert!(gap % PGSIZE == 0 && gap < TRAPFRAMES, "set_mmap_gap");
+        self.mmap_base = TRAPFRAMES - gap;
+    }
+
+    /// Returns the number of bytes of memory in use, including the mmap()ed pages.
+    pub fn footprint(&self) -> usize {
+        pgroundup(self.size)
+            + self
+                .vmas
+                .iter()
+                .map(|vma| vma.end - vma.start)
+                .sum::<usize>()
+    }
+
+    /// Copies the ranges of the resident pages mapped alike, i.e., with the same protection and
+    /// backing, into the array of n `PagemapEntry`s at addr, in the order of their addresses. The
+    /// pages of the heap, of the regions mapped by mmap, and of the trap frames are considered.
+    /// Returns Ok((number of the ranges, statistics)) on success, Err(()) if the array is not
+    /// writable.
+    pub fn pagemap(&mut self, addr: UVAddr, n: usize) -> Result<(usize, PagemapStat), ()> {
+        let mut regions = ArrayVec::<(usize, usize, u32), { NVMA + 2 }>::new();
+        regions.push((0, pgroundup(self.size), PAGEMAP_HEAP));
+        for vma in &self.vmas {
+            let kind = if vma.shared {
+                PAGEMAP_SHARED
+            } else if vma.anonymous {
+                PAGEMAP_ANONYMOUS
+            } else {
+                PAGEMAP_FILE
+            };
+            regions.push((vma.start, vma.end, kind));
+        }
+        regions.push((TRAPFRAMES, TRAMPOLINE, PAGEMAP_TRAPFRAME));
+
+        let mut stat = PagemapStat {
+            page_table_pages: self.page_table.count_tables() as u64,
+            resident_pages: 0,
+            virtual_pages: 0,
+        };
+        let mut count = 0;
+        for (start, end, kind) in regions {
+            stat.virtual_pages += ((end - start) / PGSIZE) as u64;
+            let mut range: Option<PagemapEntry> = None;
+            // Visit one more page past the end, to put the last range.
+            for va in (start..=end).step_by(PGSIZE) {
+                let prot = if va < end {
+                    self.page_table
+                        .get_mut(va.into(), None)
+                        .filter(|pte| pte.is_data())
+                        .map(|pte| pte.get_flags().to_prot().bits())
+                } else {
+                    None
+                };
+                if prot.is_some() {
+                    stat.resident_pages += 1;
+                }
+                match (&mut range, prot) {
+                    (Some(range), Some(prot)) if range.prot == prot => {
+                        range.end += PGSIZE as u64;
+                        continue;
+                    }
+                    (Some(range), _) => {
+                        if count < n {
+                            let dst = addr.into_usize() + count * mem::size_of::<PagemapEntry>();
+                            self.copy_out(dst.into(), range)?;
+                        }
+                        count += 1;
+                    }
+                    (None, _) => (),
+                }
+                range = prot.map(|prot| {
+                    PagemapEntry {
+                        start: va as u64,
+                        end: (va + PGSIZE) as u64,
+                        prot,
+                        kind,
+                    }
+                });
+            }
+        }
+        Ok((count, stat))
+    }
+
+    /// Returns the address that the heap cannot grow beyond.
+    fn heap_limit(&self) -> usize {
+        self.vmas.first().map_or(TRAPFRAMES, |vma| vma.start)
+    }
+
+    /// Maps len bytes of anonymous pages with perm, where len need not be page-aligned. Each page
+    /// is zero-filled. See `reserve` for addr and fixed.
+    /// Returns Ok(address of the pages) on success, Err(()) on error.
+    pub fn mmap_anonymous(
+        &mut self,
+        addr: usize,
+        len: usize,
+        perm: PteFlags,
+        fixed: bool,
+        cache: &PageCache,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, ()> {
+        let start = self.reserve(addr, len, perm, fixed, false, true, cache, allocator)?;
+        for _ in num_iter::range_step(0, pgroundup(len), PGSIZE) {
+            let res = allocator
+                .alloc(PageOwner::User)
+                .ok_or(())
+                .and_then(|mut page| {
+                    page.write_bytes(0);
+                    self.map_private(start, page, allocator)
+                });
+            if res.is_err() {
+                self.cancel(start, cache, allocator);
+                return Err(());
+            }
+        }
+        Ok(start)
+    }
+
+    /// Adds an empty vma for len bytes of pages with perm, where len need not be page-aligned.
+    /// `map_private` or `map_shared` then maps its pages in order, depending on shared, and
+    /// `cancel` removes it. No other vma may be added or removed until then. If fixed, the vma is
+    /// placed at addr, replacing the mappings there. Otherwise, addr is a hint, and the vma is
+    /// placed at the highest free addresses below the mmap base, or above it if there is no room
+    /// below, if it can't be honored.
+    /// Returns Ok(start of the vma) on success, Err(()) on error.
+    #[allow(clippy::too_many_arguments)]
+    pub fn reserve(
+        &mut self,
+        addr: usize,
+        len: usize,
+        perm: PteFlags,
+        fixed: bool,
+        shared: bool,
+        anonymous: bool,
+        cache: &PageCache,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, ()> {
+        if len == 0 || len > TRAPFRAMES || addr % PGSIZE != 0 {
+            return Err(());
+        }
+        let len = pgroundup(len);
+        let start = if fixed {
+            if !self.fits_fixed(addr, len) {
+                return Err(());
+            }
+            self.munmap(addr, len, cache, allocator)?;
+            addr
+        } else if addr != 0 && self.is_free(addr, len) {
+            addr
+        } else {
+            self.find_free(len).ok_or(())?
+        };
+        if self.vmas.is_full() {
+            return Err(());
+        }
+
+        let index = self.vmas.iter().position(|vma| vma.start > start);
+        let index = index.unwrap_or_else(|| self.vmas.len());
+        self.vmas.insert(
+            index,
+            Vma {
+                start,
+                end: start,
+                perm,
+                shared,
+                anonymous,
+            },
+        );
+        Ok(start)
+    }
+
+    /// Returns true if a vma of len bytes, which must be page-aligned, may be placed at addr by
+    /// `reserve` with fixed.
+    pub fn fits_fixed(&self, addr: usize, len: usize) -> bool {
+        len <= TRAPFRAMES
+            && addr % PGSIZE == 0
+            && addr >= pgroundup(self.size)
+            && addr <= TRAPFRAMES - len
+    }
+
+    /// Maps page at the end of the private vma at start, which `reserve` has added, and extends
+    /// the vma over it.
+    /// Returns Ok(()) on success, Err(()) on failure, in which case the page is freed.
+    pub fn map_private(
+        &mut self,
+        start: usize,
+        page: Page,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let (index, va) = self.next_page(start);
+        let vma = self.vmas[index];
+        assert!(!vma.shared, "map_private");
+        self.insert_page(va, page, vma.perm, allocator)?;
+        self.vmas[index].end = va + PGSIZE;
+        if vma.perm.contains(PteFlags::X) {
+            TargetArch::sync_icache_range(va, va + PGSIZE);
+        }
+        Ok(())
+    }
+
+    /// Maps pa, the address of a page in cache that counts the mapping, at the end of the shared
+    /// vma at start, which `reserve` has added, and extends the vma over it.
+    /// Returns Ok(()) on success, Err(()) on failure, in which case the mapping is returned to
+    /// cache.
+    pub fn map_shared(
+        &mut self,
+        start: usize,
+        pa: PAddr,
+        cache: &PageCache,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let (index, va) = self.next_page(start);
+        let vma = self.vmas[index];
+        assert!(vma.shared, "map_shared");
+        self.page_table
+            .insert(va.into(), pa, vma.perm, allocator)
+            .map_err(|_| cache.unmap(pa, false))?;
+        self.vmas[index].end = va + PGSIZE;
+        Ok(())
+    }
+
+    /// Removes the vma at start, which `reserve` has added, and unmaps its pages.
+    pub fn cancel(&mut self, start: usize, cache: &PageCache, allocator: Pin<&SpinLock<Kmem>>) {
+        let vma = self.vmas.remove(self.vma_index(start));
+        self.unmap_vma(&vma, vma.start, vma.end, cache, allocator);
+    }
+
+    /// Returns the index of the vma at start.
+    fn vma_index(&self, start: usize) -> usize {
+        self.vmas
+            .iter()
+            .position(|vma| vma.start == start)
+            .expect("vma_index")
+    }
+
+    /// Returns the index of the vma at start, and the address just past its pages, which must not
+    /// reach the next vma.
+    fn next_page(&self, start: usize) -> (usize, usize) {
+        let index = self.vma_index(start);
+        let va = self.vmas[index].end;
+        let limit = self.vmas.get(index + 1).map_or(TRAPFRAMES, |vma| vma.start);
+        assert!(va < limit, "next_page: no room");
+        (index, va)
+    }
+
+    /// Unmaps the mmap()ed pages from addr to addr + len, where len need not be page-aligned.
+    /// Addresses that are not mapped are skipped.
+    /// Returns Ok(()) on success, Err(()) if it would split a region but no more can be added.
+    pub fn munmap(
+        &mut self,
+        addr: usize,
+        len: usize,
+        cache: &PageCache,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let end = addr.checked_add(pgroundup(len)).ok_or(())?;
+        if self.vmas.is_full()
+            && self
+                .vmas
+                .iter()
+                .any(|vma| vma.start < addr && end < vma.end)
+        {
+            return Err(());
+        }
+
+        let mut i = 0;
+        while i < self.vmas.len() {
+            let vma = self.vmas[i];
+            let (lo, hi) = (cmp::max(vma.start, addr), cmp::min(vma.end, end));
+            if lo >= hi {
+                i += 1;
+                continue;
+            }
+            self.unmap_vma(&vma, lo, hi, cache, allocator);
+            match (vma.start < lo, hi < vma.end) {
+                (false, false) => {
+                    let _ = self.vmas.remove(i);
+                    continue;
+                }
+                (false, true) => self.vmas[i].start = hi,
+                (true, false) => self.vmas[i].end = lo,
+                (true, true) => {
+                    self.vmas[i].end = lo;
+                    self.vmas.insert(i + 1, Vma { start: hi, ..vma });
+                }
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// Unmaps every shared mapping of files. Must be called before the memory is freed, since
+    /// `free` does not return the shared pages to the page cache.
+    pub fn unmap_shared(&mut self, cache: &PageCache) {
+        let mut i = 0;
+        while i < self.vmas.len() {
+            if self.vmas[i].shared {
+                let vma = self.vmas.remove(i);
+                self.release_pages(vma.start, vma.end, cache);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Returns Some(whether they are all anonymous) if the pages from start to end, which must be
+    /// page-aligned, lie within the regions mapped by mmap, or None otherwise.
+    pub fn mapped_anonymous(&self, start: usize, end: usize) -> Option<bool> {
+        let mut addr = start;
+        let mut anonymous = true;
+        for vma in self
+            .vmas
+            .iter()
+            .filter(|vma| vma.end > start && vma.start < end)
+        {
+            if vma.start > addr {
+                return None;
+            }
+            anonymous &= vma.anonymous;
+            addr = vma.end;
+        }
+        if addr >= end {
+            Some(anonymous)
+        } else {
+            None
+        }
+    }
+
+    /// Frees the anonymous pages from start to end, which must be page-aligned and lie within the
+    /// regions mapped by mmap. They are zero-filled again on the next access. The shared pages of
+    /// files are left alone, as the page cache keeps their contents anyway.
+    /// Returns Ok(()) on success, Err(()) if the range has private pages of a file, which could
+    /// not be read back.
+    pub fn discard(
+        &mut self,
+        start: usize,
+        end: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let overlaps = |vma: &Vma| vma.end > start && vma.start < end;
+        if self
+            .vmas
+            .iter()
+            .any(|vma| overlaps(vma) && !vma.shared && !vma.anonymous)
+        {
+            return Err(());
+        }
+        for vma in self.vmas.clone() {
+            if overlaps(&vma) && vma.anonymous {
+                let (lo, hi) = (cmp::max(vma.start, start), cmp::min(vma.end, end));
+                self.unmap_pages(lo, hi, allocator);
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps the discarded pages from start to end back in advance, which must be page-aligned.
+    /// Returns Ok(()) on success, Err(()) if the memory is full.
+    pub fn populate(
+        &mut self,
+        start: usize,
+        end: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        for va in num_iter::range_step(start, end, PGSIZE) {
+            if !self.is_mapped(va) {
+                self.fault(va, PteFlags::empty(), allocator)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps a zero-filled page at va if it lies within an anonymous region whose page has been
+    /// discarded, and the region allows perm. Another thread may be doing the same, in which case
+    /// the page mapped first wins. If perm has W and the page at va has been lent by the page
+    /// cache, copies it instead.
+    /// Returns Ok(()) if the page at va is mapped with perm now, Err(()) otherwise.
+    pub fn fault(
+        &mut self,
+        va: usize,
+        perm: PteFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let va = pgrounddown(va);
+        if let Some(pte) = self.page_table.get_mut(va.into(), None) {
+            if pte.is_valid() && pte.flag_intersects(PteFlags::COW) && perm.contains(PteFlags::W) {
+                return self.copy_on_write(va, allocator);
+            }
+            if pte.is_valid() && pte.get_flags().contains(perm | PteFlags::U) {
+                // Another thread has mapped or copied it.
+                return Ok(());
+            }
+        }
+        let vma = *self
+            .vmas
+            .iter()
+            .find(|vma| vma.start <= va && va < vma.end)
+            .ok_or(())?;
+        if !vma.anonymous || !vma.perm.contains(perm) {
+            return Err(());
+        }
+        if self.is_mapped(va) {
+            // Another thread has mapped it.
+            return Ok(());
+        }
+        let mut page = allocator.alloc(PageOwner::User).ok_or(())?;
+        page.write_bytes(0);
+        let pa = page.into_usize();
+        let pte = self.page_table.get_mut(va.into(), Some(allocator));
+        if !matches!(pte, Some(pte) if pte.set_entry_if_invalid(pa.into(), vma.perm)) {
+            // SAFETY: pa is the address of the page, which has not been mapped.
+            allocator.free(unsafe { Page::from_usize(pa) });
+        }
+        Ok(())
+    }
+
+    /// Maps page, a page that the page cache lends, at va in place of the page there, as if the
+    /// user had written the same content into it. The page is copied on the first write to it.
+    /// va must be page-aligned, and lie below pgroundup(size), or within a private vma that
+    /// allows writes.
+    /// Returns Ok(()) on success, Err(()) on failure, in which case the page is freed.
+    pub fn map_lent(
+        &mut self,
+        va: usize,
+        page: Page,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let page = scopeguard::guard(page, |page| allocator.free(page));
+        if va >= TRAPFRAMES || va % PGSIZE != 0 {
+            return Err(());
+        }
+        let vma = self
+            .vmas
+            .iter()
+            .find(|vma| vma.start <= va && va < vma.end)
+            .copied();
+        match vma {
+            Some(vma) if vma.shared => return Err(()),
+            None if va >= pgroundup(self.size) => return Err(()),
+            _ => (),
+        }
+        let pte = self
+            .page_table
+            .get_mut(va.into(), Some(allocator))
+            .ok_or(())?;
+        let old = pte.inner;
+        let perm = if pte.is_valid() {
+            if !pte.is_data() {
+                return Err(());
+            }
+            pte.get_flags() - PteFlags::V - PteFlags::A - PteFlags::D
+        } else {
+            // A discarded page of an anonymous region.
+            vma.filter(|vma| vma.anonymous).ok_or(())?.perm
+        };
+        if !perm.contains(PteFlags::U | PteFlags::R)
+            || !perm.intersects(PteFlags::W | PteFlags::COW)
+        {
+            return Err(());
+        }
+        let pa = page.addr();
+        if !pte.replace_entry_if(old, pa, (perm - PteFlags::W) | PteFlags::COW) {
+            // The hardware or another thread has changed the entry meanwhile.
+            return Err(());
+        }
+        mem::forget(scopeguard::ScopeGuard::into_inner(page));
+        TargetArch::flush_tlb_page(self.page_table.satp(), va);
+        if old & PteFlags::V.bits() != 0 {
+            // SAFETY: pte2pa(old) was an address in page_table, and, thus, it is the address of a
+            // page by the invariant. No CPU can access it any longer.
+            allocator.free(unsafe { Page::from_usize(pte2pa(old).into_usize()) });
+        }
+        Ok(())
+    }
+
+    /// Maps a writable copy of the page at va, which the page cache has lent, in its place.
+    /// Another thread may be doing the same, in which case the copy mapped first wins.
+    /// Returns Ok(()) on success, Err(()) if the memory is full.
+    fn copy_on_write(&mut self, va: usize, allocator: Pin<&SpinLock<Kmem>>) -> Result<(), ()> {
+        let page = allocator.alloc(PageOwner::User).ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+        loop {
+            let pte = self.page_table.get_mut(va.into(), None).ok_or(())?;
+            let old = pte.inner;
+            let flags = PteFlags::from_bits_truncate(old);
+            if !flags.contains(PteFlags::V | PteFlags::COW) {
+                // Another thread has copied it.
+                return Ok(());
+            }
+            let pa = pte2pa(old);
+            // SAFETY: the page at pa is lent, and no holder of it writes to it. If another thread
+            // has copied it meanwhile, and the page has been freed, then what is read is discarded
+            // below.
+            let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
+            page.copy_from_slice(src);
+            let perm =
+                (flags - PteFlags::V - PteFlags::A - PteFlags::D - PteFlags::COW) | PteFlags::W;
+            if pte.replace_entry_if(old, page.addr(), perm) {
+                mem::forget(scopeguard::ScopeGuard::into_inner(page));
+                TargetArch::flush_tlb_page(self.page_table.satp(), va);
+                // SAFETY: pa was an address in page_table, and, thus, it is the address of a page
+                // by the invariant. No CPU can access it any longer.
+                allocator.free(unsafe { Page::from_usize(pa.into_usize()) });
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns whether the page at va is mapped.
+    fn is_mapped(&mut self, va: usize) -> bool {
+        matches!(self.page_table.get_mut(va.into(), None), Some(pte) if pte.is_valid())
+    }
+
+    /// Returns whether the page at va is a page that the page cache has lent.
+    fn is_lent(&mut self, va: usize) -> bool {
+        matches!(
+            self.page_table.get_mut(va.into(), None),
+            Some(pte) if pte.is_valid() && pte.flag_intersects(PteFlags::COW)
+        )
+    }
+
+    /// Returns whether len bytes from addr can be mapped without replacing any mapping.
+    fn is_free(&self, addr: usize, len: usize) -> bool {
+        addr >= pgroundup(self.size)
+            && addr <= TRAPFRAMES - len
+            && self
+                .vmas
+                .iter()
+                .all(|vma| vma.end <= addr || addr + len <= vma.start)
+    }
+
+    /// Returns the highest address from which len bytes can be mapped without replacing any
+    /// mapping, below the mmap base if possible, if any.
+    fn find_free(&self, len: usize) -> Option<usize> {
+        self.find_free_below(self.mmap_base, len)
+            .or_else(|| self.find_free_below(TRAPFRAMES, len))
+    }
+
+    /// Returns the highest address from which len bytes can be mapped below limit without
+    /// replacing any mapping, if any.
+    fn find_free_below(&self, limit: usize, len: usize) -> Option<usize> {
+        let mut top = limit;
+        for vma in self.vmas.iter().rev().skip_while(|vma| vma.start >= limit) {
+            if vma.end <= top && top - vma.end >= len {
+                return Some(top - len);
+            }
+            top = vma.start;
+        }
+        if top.checked_sub(pgroundup(self.size))? >= len {
+            Some(top - len)
+        } else {
+            None
+        }
+    }
+
+    /// Maps page at va, which must not be mapped, with perm.
+    /// Ok(()) on success, Err(()) on failure, in which case the page is freed.
+    fn insert_page(
+        &mut self,
+        va: usize,
+        page: Page,
+        perm: PteFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let pa = page.into_usize();
+        self.page_table
+            .insert(va.into(), pa.into(), perm, allocator)
+            // SAFETY: pa is the address of the given page.
+            .map_err(|_| allocator.free(unsafe { Page::from_usize(pa) }))
+    }
+
+    /// Unmaps the pages of vma from start to end, which must be page-aligned.
+    fn unmap_vma(
+        &mut self,
+        vma: &Vma,
+        start: usize,
+        end: usize,
+        cache: &PageCache,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) {
+        if vma.shared {
+            self.release_pages(start, end, cache);
+        } else {
+            self.unmap_pages(start, end, allocator);
+        }
+    }
+
+    /// Unmaps and frees the pages from start to end, which must be page-aligned and mapped, or
+    /// discarded.
+    fn unmap_pages(&mut self, start: usize, end: usize, allocator: Pin<&SpinLock<Kmem>>) {
+        for va in num_iter::range_step(start, end, PGSIZE) {
+            if !self.is_mapped(va) {
+                continue;
+            }
+            let pa = self
+                .page_table
+                .remove(va.into())
+                .expect("unmap_pages")
+                .into_usize();
+            // SAFETY: pa is an address in page_table,
+            // and, thus, it is the address of a page by the invariant.
+            allocator.free(unsafe { Page::from_usize(pa) });
+        }
+    }
+
+    /// Unmaps the shared pages from start to end, which must be page-aligned and mapped, and
+    /// removes their mappings from cache.
+    fn release_pages(&mut self, start: usize, end: usize, cache: &PageCache) {
+        for va in num_iter::range_step(start, end, PGSIZE) {
+            let dirty = self
+                .page_table
+                .get_mut(va.into(), None)
+                .expect("release_pages")
+                .is_dirty();
+            let pa = self.page_table.remove(va.into()).expect("release_pages");
+            cache.unmap(pa, dirty);
+        }
+    }
+
+    /// Load data from a file into memory at virtual address va. The pages
+    /// from va to va + sz must already be mapped.
     ///
     /// Returns Ok(()) on success, Err(()) on failure.
     pub fn load_file(
@@ -437,16 +1301,19 @@ impl UserMemory {
         sz: u32,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()> {
-        assert!(va.is_page_aligned(), "load_file: va must be page aligned");
-        for i in num_iter::range_step(0, sz, PGSIZE as _) {
+        let mut i = 0;
+        while i < sz {
+            let va = va + i as usize;
             let dst = self
-                .get_slice(va + i as usize)
+                .get_slice(pgrounddown(va.into_usize()).into(), true)
                 .expect("load_file: address should exist");
-            let n = cmp::min((sz - i) as usize, PGSIZE);
-            let bytes_read = ip.read_bytes_kernel(&mut dst[..n], offset + i, ctx);
+            let begin = va.into_usize() % PGSIZE;
+            let n = cmp::min((sz - i) as usize, PGSIZE - begin);
+            let bytes_read = ip.read_bytes_kernel(&mut dst[begin..begin + n], offset + i, ctx);
             if bytes_read != n {
                 return Err(());
             }
+            i += n as u32;
         }
         Ok(())
     }
@@ -457,13 +1324,16 @@ impl UserMemory {
         if newsz <= self.size {
             return Ok(self.size);
         }
+        if pgroundup(newsz) > self.heap_limit() {
+            return Err(());
+        }
 
         let oldsz = self.size;
         let mut this = scopeguard::guard(self, |this| {
             let _ = this.dealloc(oldsz, allocator);
         });
         while pgroundup(this.size) < pgroundup(newsz) {
-            let mut page = allocator.alloc().ok_or(())?;
+            let mut page = allocator.alloc(PageOwner::User).ok_or(())?;
             page.write_bytes(0);
             this.push_page(
                 page,
@@ -493,22 +1363,6 @@ impl UserMemory {
         newsz
     }
 
-    /// Grow or shrink process size by n bytes.
-    /// Return Ok(old size) on success, Err(()) on failure.
-    pub fn resize(&mut self, n: i32, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
-        let size = self.size;
-        match n.cmp(&0) {
-            cmp::Ordering::Equal => (),
-            cmp::Ordering::Greater => {
-                let _ = self.alloc(size + n as usize, allocator)?;
-            }
-            cmp::Ordering::Less => {
-                let _ = self.dealloc(size - (-n as usize), allocator);
-            }
-        };
-        Ok(size)
-    }
-
     /// Mark a PTE invalid for user access.
     /// Used by exec for the user stack guard page.
     pub fn clear(&mut self, va: UVAddr) {
@@ -522,13 +1376,14 @@ impl UserMemory {
     /// Copy len bytes from src to virtual address dstva in a given page table.
     /// Return Ok(()) on success, Err(()) on error.
     pub fn copy_out_bytes(&mut self, dstva: UVAddr, src: &[u8]) -> Result<(), ()> {
+        fcount!(UserMemory::copy_out_bytes);
         let mut dst = dstva.into_usize();
         let mut len = src.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(dst);
             let poffset = dst - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice(va.into(), true).ok_or(())?;
             let n = cmp::min(PGSIZE - poffset, len);
             page[poffset..poffset + n].copy_from_slice(&src[offset..offset + n]);
             len -= n;
@@ -549,13 +1404,14 @@ impl UserMemory {
     /// Copy len bytes to dst from virtual address srcva in a given page table.
     /// Return Ok(()) on success, Err(()) on error.
     pub fn copy_in_bytes(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
+        fcount!(UserMemory::copy_in_bytes);
         let mut src = srcva.into_usize();
         let mut len = dst.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice(va.into(), false).ok_or(())?;
             let n = cmp::min(PGSIZE - poffset, len);
             dst[offset..offset + n].copy_from_slice(&page[poffset..poffset + n]);
             len -= n;
@@ -587,7 +1443,7 @@ impl UserMemory {
         while max > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice(va.into(), false).ok_or(())?;
             let n = cmp::min(PGSIZE - poffset, max);
 
             let from = &page[poffset..poffset + n];
@@ -607,22 +1463,50 @@ impl UserMemory {
         Err(())
     }
 
-    /// Return the address of the page table for this memory in the riscv's sv39
-    /// page table scheme.
-    pub fn satp(&self) -> usize {
-        make_satp(self.page_table.as_usize())
+    /// Prepares the current CPU to run user code with this memory, and returns the `satp` for
+    /// the trampoline to switch to. Called with interrupts disabled right before switching to
+    /// user mode.
+    pub fn enter_user(&self) -> usize {
+        TargetArch::enter_user(&self.page_table.asid, self.page_table.as_usize())
     }
 
-    /// Return a page at va as a slice. Some(page) on success, None on failure.
-    fn get_slice(&mut self, va: UVAddr) -> Option<&mut [u8]> {
-        if va.into_usize() >= TRAPFRAME {
+    /// Returns the physical address that `va` is mapped to, or None if `va` is not mapped for the
+    /// user.
+    pub fn translate(&mut self, va: UVAddr) -> Option<PAddr> {
+        let page = self.get_slice(pgrounddown(va.into_usize()).into(), false)?;
+        Some((page.as_ptr() as usize + va.into_usize() % PGSIZE).into())
+    }
+
+    /// Returns the physical address that `va` is mapped to, or None if the user cannot write to
+    /// `va`. A page that is shared copy-on-write is copied first, so that writing to the address
+    /// changes only this memory.
+    pub fn translate_mut(&mut self, va: UVAddr) -> Option<PAddr> {
+        let page = self.get_slice(pgrounddown(va.into_usize()).into(), true)?;
+        Some((page.as_ptr() as usize + va.into_usize() % PGSIZE).into())
+    }
+
+    /// Return a page at va as a slice, if the user can access it, and write to it if `write`.
+    fn get_slice(&mut self, va: UVAddr, write: bool) -> Option<&mut [u8]> {
+        if va.into_usize() >= TRAPFRAMES {
             return None;
         }
+        if !self.is_mapped(va.into_usize()) {
+            // Map back a discarded page, as an access by the user would.
+            let perm = if write { PteFlags::W } else { PteFlags::R };
+            self.fault(va.into_usize(), perm, hal().kmem()).ok()?;
+        } else if write && self.is_lent(va.into_usize()) {
+            // Copy the page, as a write by the user would.
+            self.copy_on_write(va.into_usize(), hal().kmem()).ok()?;
+        }
         let pte = self.page_table.get_mut(va, None)?;
-        if !pte.is_user() {
+        if !pte.is_user() || (write && !pte.is_writable()) {
             return None;
         }
-        // SAFETY: va < TRAPFRAME, so pte.get_pa() is the address of a page.
+        if write {
+            // The kernel writes through its own mapping, which the hardware does not track.
+            pte.set_dirty();
+        }
+        // SAFETY: va < TRAPFRAMES, so pte.get_pa() is the address of a page.
         Some(unsafe { slice::from_raw_parts_mut(pte.get_pa().into_usize() as _, PGSIZE) })
     }
 
@@ -664,6 +1548,10 @@ impl UserMemory {
 
     pub fn free(mut self, allocator: Pin<&SpinLock<Kmem>>) {
         let _ = self.dealloc(0, allocator);
+        while let Some(vma) = self.vmas.pop() {
+            assert!(!vma.shared, "free: shared mapping");
+            self.unmap_pages(vma.start, vma.end, allocator);
+        }
         // SAFETY: self will be dropped.
         unsafe { self.page_table.free(allocator) };
         mem::forget(self);
@@ -687,8 +1575,61 @@ impl Drop for UserMemory {
 // what would be the proper invariant for KernelMemory and whether we can
 // combine UserMemory and KernelMemory to form a single type.
 pub struct KernelMemory {
-    /// Page table of kernel.
+    /// Address of the page table, for `satp`.
+    root: usize,
+
+    /// Page table of kernel, with the areas mapped at run time.
+    mappings: SpinLock<KernelMappings>,
+}
+
+/// The kernel page table, which maps everything at boot but the areas that `ioremap` and
+/// `kmap_page` map at run time, between `VMAP_BASE` and `VMAP_END`.
+struct KernelMappings {
     page_table: PageTable<KVAddr>,
+
+    /// The areas mapped at run time, from start to end, sorted by their starts. Each is followed
+    /// by an invalid guard page.
+    areas: ArrayVec<(usize, usize), NKMAP>,
+}
+
+impl KernelMappings {
+    /// Finds `size` bytes of free addresses, and takes them as an area.
+    /// Returns Ok(start of the area) on success, Err(()) if there is no room.
+    fn alloc(&mut self, size: usize) -> Result<usize, ()> {
+        if self.areas.is_full() {
+            return Err(());
+        }
+        let mut start = VMAP_BASE;
+        let mut index = self.areas.len();
+        for (i, &(area_start, area_end)) in self.areas.iter().enumerate() {
+            if start + size + PGSIZE <= area_start {
+                index = i;
+                break;
+            }
+            start = area_end + PGSIZE;
+        }
+        if start + size > VMAP_END {
+            return Err(());
+        }
+        self.areas.insert(index, (start, start + size));
+        Ok(start)
+    }
+
+    /// Unmaps the pages of the area that contains `va`, flushing them only from the TLB of this
+    /// CPU, and returns the area.
+    fn unmap(&mut self, va: usize) -> (usize, usize) {
+        let &(start, end) = self
+            .areas
+            .iter()
+            .find(|(start, end)| *start <= va && va < *end)
+            .expect("KernelMappings::unmap");
+        for va in num_iter::range_step(start, end, PGSIZE) {
+            if matches!(self.page_table.get_mut(va.into(), None), Some(pte) if pte.is_data()) {
+                let _ = self.page_table.remove(va.into());
+            }
+        }
+        (start, end)
+    }
 }
 
 impl KernelMemory {
@@ -733,6 +1674,17 @@ impl KernelMemory {
             )
             .ok()?;
 
+        // CLINT, whose comparators arm the one-shot timers
+        page_table
+            .insert_range(
+                CLINT.into(),
+                0x10000,
+                CLINT.into(),
+                PteFlags::R | PteFlags::W,
+                allocator,
+            )
+            .ok()?;
+
         // PLIC
         page_table
             .insert_range(
@@ -785,7 +1737,7 @@ impl KernelMemory {
         // Map it high in memory, followed by an invalid
         // guard page.
         for i in 0..NPROC {
-            let pa = allocator.alloc()?.into_usize();
+            let pa = allocator.alloc(PageOwner::KernelStack)?.into_usize();
             let va: usize = kstack(i);
             page_table
                 .insert_range(
@@ -798,16 +1750,106 @@ impl KernelMemory {
                 .ok()?;
         }
 
+        let page_table = scopeguard::ScopeGuard::into_inner(page_table);
         Some(Self {
-            page_table: scopeguard::ScopeGuard::into_inner(page_table),
+            root: page_table.as_usize(),
+            mappings: SpinLock::new(
+                "kernel_mappings",
+                KernelMappings {
+                    page_table,
+                    areas: ArrayVec::new(),
+                },
+            ),
         })
     }
 
     /// Switch h/w page table register to the kernel's page table, and enable paging.
     pub unsafe fn init_hart(&self) {
         unsafe {
-            w_satp(make_satp(self.page_table.as_usize()));
+            w_satp(make_satp(self.root, 0));
             sfence_vma();
+            init_asids();
+        }
+    }
+}
+
+impl KernelMemory {
+    /// Maps `size` bytes from the physical address `pa` at free kernel addresses with `perm`.
+    /// Returns Ok(the address `pa` is mapped at) on success, Err(()) if there is no room or a page
+    /// table cannot be allocated.
+    fn map(
+        &self,
+        pa: usize,
+        size: usize,
+        perm: PteFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, ()> {
+        let offset = pa % PGSIZE;
+        let size = pgroundup(offset + size);
+        let mut mappings = self.mappings.lock();
+        let start = mappings.alloc(size)?;
+        if mappings
+            .page_table
+            .insert_range(start.into(), size, (pa - offset).into(), perm, allocator)
+            .is_err()
+        {
+            // No other CPU has used the pages mapped so far.
+            let _ = mappings.unmap(start);
+            mappings.areas.retain(|area| area.0 != start);
+            return Err(());
         }
+        // No TLB caches a valid translation of the pages, as `unmap` has flushed them everywhere.
+        Ok(start + offset)
+    }
+
+    /// Unmaps the area mapped by `map` that contains `va`, and frees its addresses once no CPU
+    /// caches their translations. The caller must not hold a spin lock.
+    fn unmap(&self, va: usize) {
+        let (start, end) = self.mappings.lock().unmap(va);
+        for va in num_iter::range_step(start, end, PGSIZE) {
+            TargetArch::flush_kernel_tlb_page(va);
+        }
+        self.mappings.lock().areas.retain(|area| area.0 != start);
+    }
+
+    /// Maps `size` bytes of device registers from the physical address `pa`, e.g., a PCI BAR
+    /// found at run time, into the kernel, readable and writable. Sv39 has no memory types, so
+    /// the platform's physical memory attributes keep the registers uncached.
+    /// Returns Ok(the address of the registers) on success, Err(()) on failure.
+    // No driver maps registers found at run time yet.
+    #[allow(dead_code)]
+    pub fn ioremap(
+        &self,
+        pa: usize,
+        size: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, ()> {
+        assert!(size > 0, "ioremap");
+        self.map(pa, size, PteFlags::R | PteFlags::W, allocator)
+    }
+
+    /// Unmaps the registers mapped at `va` by `ioremap`. The caller must not hold a spin lock.
+    #[allow(dead_code)]
+    pub fn iounmap(&self, va: usize) {
+        self.unmap(va);
+    }
+
+    /// Maps the physical page at `pa`, e.g., a user page, at a kernel address of its own,
+    /// readable and writable, until `kunmap_page`. Unlike the direct mapping of the RAM, the
+    /// address stops translating once unmapped, which catches stale uses.
+    /// Returns Ok(the address of the page) on success, Err(()) on failure.
+    pub fn kmap_page(&self, pa: PAddr, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
+        assert!(pa.into_usize() % PGSIZE == 0, "kmap_page");
+        self.map(
+            pa.into_usize(),
+            PGSIZE,
+            PteFlags::R | PteFlags::W,
+            allocator,
+        )
+    }
+
+    /// Unmaps the page mapped at `va` by `kmap_page`. The caller must not hold a spin lock.
+    pub fn kunmap_page(&self, va: usize) {
+        self.unmap(va);
     }
 }
@@ -40,3 +40,13 @@ struct proghdr {
 #define ELF_PROG_FLAG_EXEC      1
 #define ELF_PROG_FLAG_WRITE     2
 #define ELF_PROG_FLAG_READ      4
+
+// Types of auxiliary vector entries, which follow the null
+// terminating argv[] on the initial user stack as (type, value) pairs.
+#define AT_NULL                 0
+#define AT_PHDR                 3   // Address of the program headers
+#define AT_PHENT                4   // Size of a program header
+#define AT_PHNUM                5   // Number of program headers
+#define AT_PAGESZ               6   // Page size
+#define AT_ENTRY                9   // Entry point of the program
+#define AT_RANDOM               25  // Address of 16 random bytes
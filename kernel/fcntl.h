@@ -3,3 +3,28 @@
 #define O_RDWR    0x002
 #define O_CREATE  0x200
 #define O_TRUNC   0x400
+#define O_APPEND  0x800
+#define O_NONBLOCK 0x1000
+#define O_CLOEXEC 0x2000
+#define O_DIRECT  0x4000
+
+#define F_GETFD 1
+#define F_SETFD 2
+#define F_GETFL 3
+#define F_SETFL 4
+
+#define FD_CLOEXEC 1
+
+#define SEEK_SET 0
+#define SEEK_CUR 1
+#define SEEK_END 2
+
+#define POSIX_FADV_NORMAL     0  // No special treatment
+#define POSIX_FADV_RANDOM     1  // Read at random offsets: nothing is read ahead
+#define POSIX_FADV_SEQUENTIAL 2  // Read sequentially: twice as much is read ahead
+#define POSIX_FADV_WILLNEED   3  // The range will be read soon
+#define POSIX_FADV_DONTNEED   4  // The range will not be read soon
+
+#define SYNC_FILE_RANGE_WAIT_BEFORE 0x1
+#define SYNC_FILE_RANGE_WRITE       0x2
+#define SYNC_FILE_RANGE_WAIT_AFTER  0x4
@@ -20,13 +20,20 @@ struct superblock {
   uint logstart;     // Block number of first log block
   uint inodestart;   // Block number of first inode block
   uint bmapstart;    // Block number of first free map block
+  uint flags;        // Optional features, e.g., FS_CHECKSUM
+  uint checksum;     // CRC-32 of the fields above, if FS_CHECKSUM is set
 };
 
 #define FSMAGIC 0x10203040
 
-#define NDIRECT 12
+#define FS_CHECKSUM 0x1  // Metadata is checksummed
+#define FS_EXTENTS  0x2  // Files are mapped by extents instead of addrs
+#define FS_DISCARD  0x4  // Freed blocks are discarded
+
+#define NDIRECT 11
 #define NINDIRECT (BSIZE / sizeof(uint))
-#define MAXFILE (NDIRECT + NINDIRECT)
+#define NDINDIRECT (NINDIRECT * NINDIRECT)
+#define MAXFILE (NDIRECT + NINDIRECT + NDINDIRECT)
 
 // On-disk inode structure
 struct dinode {
@@ -35,7 +42,15 @@ struct dinode {
   ushort minor;         // Minor device number (T_DEVICE only)
   short nlink;          // Number of links to inode in file system
   uint size;            // Size of file (bytes)
-  uint addrs[NDIRECT+1];   // Data block addresses
+  uint addrs[NDIRECT+2];   // Data block addresses
+  uint gen;             // Generation number, incremented on allocation
+  uint atime;           // Time of the last access, in seconds since the epoch
+  uint mtime;           // Time of the last modification of the content
+  uint ctime;           // Time of the last change of the inode
+  uint mode;            // Permission bits
+  ushort uid;           // User ID of the owner
+  ushort gid;           // Group ID of the owner
+  uint checksum;        // CRC-32 of the fields above, if FS_CHECKSUM is set
 };
 
 // Inodes per block.
@@ -58,3 +73,23 @@ struct dirent {
   char name[DIRSIZ];
 };
 
+// A directory entry with the status of its inode, read by
+// getdents_plus(). Needs kernel/stat.h.
+struct direntplus {
+  struct stat st;
+  struct dirent de;
+};
+
+// Events of fswatch(). Must match rv6-abi/src/lib.rs.
+#define FSW_CREATE   0x1 // An entry was added to the watched directory
+#define FSW_UNLINK   0x2 // An entry was removed from the watched directory
+#define FSW_WRITE    0x4 // The watched file was written
+#define FSW_OVERFLOW 0x8 // Events were dropped since the reader fell behind
+
+// An event read from a descriptor returned by fswatch().
+struct fsevent {
+  ushort mask;
+  ushort inum;
+  char name[DIRSIZ];
+};
+
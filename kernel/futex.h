@@ -0,0 +1,17 @@
+// futex() operations and robust futex words. Must match rv6-abi/src/lib.rs.
+#define FUTEX_WAIT        0
+#define FUTEX_WAKE        1
+
+#define FUTEX_WAITERS     0x80000000
+#define FUTEX_OWNER_DIED  0x40000000
+#define FUTEX_TID_MASK    0x3fffffff
+
+struct robust_list {
+  struct robust_list *next;
+};
+
+struct robust_list_head {
+  struct robust_list list;
+  long futex_offset;
+  struct robust_list *list_op_pending;
+};
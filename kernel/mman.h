@@ -0,0 +1,42 @@
+// Arguments of mmap and madvise. Must match rv6-abi/src/lib.rs.
+
+#define PROT_NONE     0x0
+#define PROT_READ     0x1
+#define PROT_WRITE    0x2
+#define PROT_EXEC     0x4
+
+#define MAP_SHARED    0x01  // Changes are written back to the file
+#define MAP_PRIVATE   0x02  // Changes are private to the process
+#define MAP_FIXED     0x10  // Map exactly at the given address
+#define MAP_ANONYMOUS 0x20  // Zero-filled, not backed by a file
+
+#define MAP_FAILED    ((void*)-1)
+
+// Advice of madvise.
+
+#define MADV_NORMAL   0  // No special treatment
+#define MADV_WILLNEED 3  // The pages will be accessed soon
+#define MADV_DONTNEED 4  // Free the pages, which read as zeros afterwards
+#define MADV_FREE     8  // The anonymous pages are no longer needed
+
+// Backings of the pages reported by pagemap.
+
+#define PAGEMAP_HEAP      0  // The program and its heap
+#define PAGEMAP_ANONYMOUS 1  // Zero-filled private pages mapped by mmap
+#define PAGEMAP_FILE      2  // Private copies of the pages of a file
+#define PAGEMAP_SHARED    3  // Pages of a file mapped with MAP_SHARED
+#define PAGEMAP_COW       4  // Shared copy-on-write, not reported yet
+#define PAGEMAP_TRAPFRAME 5  // Trap frames of the threads
+
+struct pagemapentry {
+  uint64 start;  // Address of the first page
+  uint64 end;    // Address past the last page
+  int prot;      // PROT_* for the user
+  uint kind;     // PAGEMAP_*
+};
+
+struct pagemapstat {
+  uint64 page_table_pages;  // Number of page-table pages
+  uint64 resident_pages;    // Number of the pages mapped in the regions
+  uint64 virtual_pages;     // Number of the pages in the regions
+};
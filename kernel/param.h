@@ -1,13 +1,33 @@
+// The resource limits chosen by CONFIG in the Makefile.
+// Must match param::Config in kernel-rs/src/param.rs.
+#if defined(CONFIG_SMALL)
+#define NPROC        16  // maximum number of processes
+#define NOFILE        8  // open files per process
+#define NFILE        32  // open files per system
+#define NINODE       24  // maximum number of active i-nodes
+#define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
+#elif defined(CONFIG_LARGE)
+#define NPROC       256  // maximum number of processes
+#define NOFILE       64  // open files per process
+#define NFILE       400  // open files per system
+#define NINODE      200  // maximum number of active i-nodes
+#define NBUF         (MAXOPBLOCKS*12)  // size of disk block cache
+#else
 #define NPROC        64  // maximum number of processes
-#define NCPU          8  // maximum number of CPUs
 #define NOFILE       16  // open files per process
 #define NFILE       100  // open files per system
 #define NINODE       50  // maximum number of active i-nodes
+#define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
+#endif
+
+#define NCPU          8  // maximum number of CPUs
 #define NDEV         10  // maximum major device number
 #define ROOTDEV       1  // device number of file system root disk
 #define MAXARG       32  // max exec arguments
 #define MAXOPBLOCKS  10  // max # of blocks any FS op writes
 #define LOGSIZE      (MAXOPBLOCKS*3)  // max data blocks in on-disk log
-#define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
-#define FSSIZE       2000  // size of file system in blocks
+#define FSSIZE       20000  // size of file system in blocks
 #define MAXPATH      128   // maximum file path name
+#define NGROUP        8  // maximum number of resource groups
+#define NFUTEX        16  // number of futex wait queues
+#define ROBUST_LIST_LIMIT 2048  // maximum number of entries walked in a robust list
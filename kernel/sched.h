@@ -0,0 +1,2 @@
+// Flags of clone(). Must match rv6-abi/src/lib.rs.
+#define CLONE_SETTLS  0x80000  // Set tp of the new thread to the given TLS block
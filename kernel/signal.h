@@ -0,0 +1,36 @@
+// Signals. Must match rv6-abi/src/lib.rs.
+
+#define NSIG     32  // Signals are numbered from 1 to NSIG - 1
+
+#define SIGHUP    1
+#define SIGINT    2
+#define SIGQUIT   3
+#define SIGKILL   9
+#define SIGUSR1  10
+#define SIGSEGV  11
+#define SIGUSR2  12
+#define SIGPIPE  13
+#define SIGALRM  14
+#define SIGTERM  15
+#define SIGCHLD  17
+
+#define SIG_DFL  ((void (*)(int))0)  // Default action: ignore SIGCHLD, terminate otherwise
+#define SIG_IGN  ((void (*)(int))1)  // Ignore the signal
+
+#define SA_NODEFER    0x40000000  // Do not block the signal while its handler runs
+#define SA_RESETHAND  0x80000000  // Restore the default action once the handler is called
+
+// sigprocmask() operations.
+#define SIG_BLOCK     0
+#define SIG_UNBLOCK   1
+#define SIG_SETMASK   2
+
+// A set of signals, with bit sig set for each signal sig in it.
+typedef uint sigset_t;
+
+struct sigaction {
+  void (*sa_handler)(int);   // SIG_DFL, SIG_IGN, or a handler
+  sigset_t sa_mask;          // Signals blocked while the handler runs
+  uint sa_flags;             // SA_*
+  void (*sa_restorer)(void); // Where the handler returns to; must call sigreturn()
+};
@@ -7,5 +7,93 @@ struct stat {
   uint ino;    // Inode number
   short type;  // Type of file
   short nlink; // Number of links to file
+  uint gen;    // Generation number of the inode
   uint64 size; // Size of file in bytes
+  uint64 atime; // Time of the last access, in seconds since the epoch
+  uint64 mtime; // Time of the last modification of the content
+  uint64 ctime; // Time of the last change of the inode
+  uint mode;    // Permission bits
+  ushort uid;   // User ID of the owner
+  ushort gid;   // Group ID of the owner
+};
+
+// Permission bits of stat.mode.
+#define S_IRUSR 0400 // Read by the owner
+#define S_IWUSR 0200 // Write by the owner
+#define S_IXUSR 0100 // Execute or search by the owner
+#define S_IRGRP 0040 // Read by the group
+#define S_IWGRP 0020 // Write by the group
+#define S_IXGRP 0010 // Execute or search by the group
+#define S_IROTH 0004 // Read by the others
+#define S_IWOTH 0002 // Write by the others
+#define S_IXOTH 0001 // Execute or search by the others
+
+// Operations of bench.
+#define BENCH_NULL  0 // Dispatch a no-op system call
+#define BENCH_YIELD 1 // Yield the CPU
+#define BENCH_PIPE  2 // Pass a byte through a pair of pipes
+
+#define LATENCY_BUCKETS 32 // Buckets of a syscall_latency histogram
+
+// Events of a tracerecord, with the meaning of their args.
+#define TRACE_SCHED_SWITCH    1 // A CPU switched to a process: pid
+#define TRACE_SYSCALL_ENTER   2 // A process entered a system call: number, pid
+#define TRACE_SYSCALL_EXIT    3 // A process returned from a system call: number, return value
+#define TRACE_DISK_SUBMIT     4 // A disk request was submitted: block number, 1 if a write
+#define TRACE_DISK_COMPLETE   5 // The disk completed a request: block number
+#define TRACE_LOCK_CONTEND    6 // A CPU found a spinlock held: address of the lock
+
+struct tracerecord {
+  uint64 time;    // Value of the time CSR when the tracepoint was hit
+  uint event;     // One of TRACE_*
+  uint cpu;       // The CPU that hit the tracepoint
+  uint64 args[2]; // Arguments of the event
+};
+
+#define FCOUNT_NAME_LEN 56 // Length of a fcountrecord name, including the NUL
+
+struct fcountrecord {
+  uint64 count;               // Number of calls to the function
+  char name[FCOUNT_NAME_LEN]; // Path of the function in the kernel
+};
+
+#define IRQOFF_SITE_LEN 48 // Length of an irqoffrecord site, including the NUL
+
+struct irqoffrecord {
+  uint64 cycles;              // Length of the longest interrupts-disabled window, in timer cycles
+  uint64 line;                // Line of site, or 0 if site is a spin lock
+  char site[IRQOFF_SITE_LEN]; // The spin lock or the source file that disabled interrupts
+};
+
+// Owners of the kernel pages, which index the arrays of struct meminfo.
+#define PAGE_OWNER_USER      0 // User memory
+#define PAGE_OWNER_PAGETABLE 1 // Page-table pages
+#define PAGE_OWNER_KSTACK    2 // Kernel stacks
+#define PAGE_OWNER_TRAPFRAME 3 // Trap frames
+#define PAGE_OWNER_PIPE      4 // Pipe buffers
+#define PAGE_OWNER_PAGECACHE 5 // Pages of the files mapped with MAP_SHARED
+#define PAGE_OWNER_EXEC      6 // Arguments of exec
+#define NPAGEOWNER           7
+
+struct meminfo {
+  uint64 freepages;           // Number of free pages
+  uint64 owned[NPAGEOWNER];   // Number of the pages of each owner
+  uint64 budgets[NPAGEOWNER]; // Number of the pages each owner may hold, or -1 if unbounded
+};
+
+#define FSHIFT 11 // Number of fractional bits of the load averages
+
+struct sysinfo {
+  uint64 uptime;    // Number of timer ticks since boot
+  uint64 loads[3];  // 1, 5 and 15 minute load averages, in fixed point
+  uint64 freepages; // Number of free pages
+  uint64 procs;     // Number of processes
+};
+
+struct iostat {
+  uint64 read_ops;    // Number of read operations
+  uint64 write_ops;   // Number of write operations
+  uint64 read_bytes;  // Number of bytes read
+  uint64 write_bytes; // Number of bytes written
+  uint64 wait_time;   // Time spent waiting for the disk, in timer cycles
 };
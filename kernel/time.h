@@ -0,0 +1,25 @@
+// Clocks of clock_gettime(). Must match rv6-abi/src/lib.rs.
+#define CLOCK_REALTIME  0  // Wall-clock time
+#define CLOCK_MONOTONIC 1  // Time since the boot, which never jumps
+
+// Values of tv_nsec taken by utimes() and futimens().
+#define UTIME_NOW  ((1l << 30) - 1)  // Set the time to the current time
+#define UTIME_OMIT ((1l << 30) - 2)  // Leave the time unchanged
+
+struct timespec {
+  long tv_sec;
+  long tv_nsec;  // Less than 1000000000
+};
+
+struct timeval {
+  long tv_sec;
+  long tv_usec;  // Less than 1000000
+};
+
+// Interval timers of setitimer().
+#define ITIMER_REAL 0  // Counts real time, and sends SIGALRM
+
+struct itimerval {
+  struct timeval it_interval;  // Time between the later expirations, or 0
+  struct timeval it_value;     // Time until the next expiration, or 0 to disarm
+};
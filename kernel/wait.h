@@ -0,0 +1,8 @@
+// Options of waitpid(). Must match rv6-abi/src/lib.rs.
+#define WNOHANG 0x1  // Return 0 instead of blocking if no child has exited
+
+// The status reported by wait() and waitpid() is the argument of
+// exit(), or -1 if the process was killed by a signal.
+#define WIFEXITED(status)   ((status) != -1)
+#define WEXITSTATUS(status) (status)
+#define WIFSIGNALED(status) ((status) == -1)
@@ -0,0 +1,68 @@
+//! Adds random files to an image one transaction at a time through the log, and checks every
+//! image that a crash in the middle could leave behind.
+//!
+//! This runs the log protocol of `rv6_abi::log`, which the kernel shares, but none of the file
+//! system code of the kernel: `fs::ufs` (inodes, directories, `bmap`, and truncation) is built on
+//! `KernelCtx`, the disk of the HAL, and the kernel locks, and does not build for the host, so it
+//! is not fuzzed yet.
+//!
+//! Run with `cargo fuzz run crash` in the `mkfs` directory.
+
+#![no_main]
+
+use std::collections::HashSet;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rv6_abi::{BSIZE, DIRSIZ};
+use rv6_mkfs::{crash, Config, Mkfs};
+
+/// A small file system, so that each crash image is cheap to build.
+const CONFIG: Config = Config {
+    size: 200,
+    ninodes: 32,
+    nlog: 10,
+    max_size: 200,
+    checksums: true,
+    extents: true,
+    discard: false,
+    time: 0,
+};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    files: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut mkfs = Mkfs::new(CONFIG);
+    let mut disk = crash::RecordingDisk::new(mkfs.clone().finish());
+    let mut names = HashSet::new();
+    for (name, data) in input.files.iter().take(CONFIG.ninodes as usize - 2) {
+        if name.is_empty()
+            || name.len() > DIRSIZ
+            || name.contains(&0)
+            || name.contains(&b'/')
+            || name == b"."
+            || name == b".."
+            || data.len() > 4 * BSIZE
+            || !names.insert(name)
+        {
+            continue;
+        }
+        let before = mkfs.clone().finish();
+        let _ = mkfs.add_file(name, data);
+        let after = mkfs.clone().finish();
+
+        // The blocks that the transaction changes.
+        let blocks = before
+            .chunks_exact(BSIZE)
+            .zip(after.chunks_exact(BSIZE))
+            .enumerate()
+            .filter(|(_, (b, a))| b != a)
+            .map(|(bn, (_, a))| (bn as u32, a.to_vec()))
+            .collect::<Vec<_>>();
+        crash::commit(&mut disk, &blocks);
+    }
+    crash::check(&disk).unwrap();
+});
@@ -0,0 +1,59 @@
+//! Builds an image from random files, and checks it before and after corrupting random bytes.
+//! The first check must pass, and the second must not panic.
+//!
+//! Run with `cargo fuzz run mkfs` in the `mkfs` directory.
+
+#![no_main]
+
+use std::collections::HashSet;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rv6_abi::{BSIZE, DIRSIZ, MAXFILE};
+use rv6_mkfs::{fsck, Config, Mkfs};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    files: Vec<(Vec<u8>, Vec<u8>)>,
+    corruptions: Vec<(u32, u8)>,
+    checksums: bool,
+    extents: bool,
+}
+
+fuzz_target!(|input: Input| {
+    let mut mkfs = Mkfs::new(Config {
+        checksums: input.checksums,
+        extents: input.extents,
+        ..Config::default()
+    });
+    let sb = *mkfs.superblock();
+
+    // Leave room for the indirect blocks, so that mkfs does not run out of blocks.
+    let mut budget = sb.nblocks as usize / 2 * BSIZE;
+    let mut names = HashSet::new();
+    for (name, data) in input.files.iter().take(sb.ninodes as usize - 2) {
+        if name.is_empty()
+            || name.len() > DIRSIZ
+            || name.contains(&0)
+            || name.contains(&b'/')
+            || name == b"."
+            || name == b".."
+            || data.len() > MAXFILE * BSIZE
+            || data.len() > budget
+            || !names.insert(name)
+        {
+            continue;
+        }
+        budget -= data.len();
+        let _ = mkfs.add_file(name, data);
+    }
+
+    let mut image = mkfs.finish();
+    fsck::check(&image).unwrap();
+
+    for (off, byte) in input.corruptions {
+        let off = off as usize % image.len();
+        image[off] = byte;
+    }
+    let _ = fsck::check(&image);
+});
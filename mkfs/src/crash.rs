@@ -0,0 +1,200 @@
+//! Crash-consistency checks at block granularity.
+//!
+//! `RecordingDisk` records the writes issued to an image, and the flushes between them. A crash
+//! may lose any of the writes issued since the last flush, so `crash_images` returns the images
+//! that a power loss could leave behind. `check` recovers each of them as the kernel does when it
+//! mounts the file system, and checks the result with `fsck::check`.
+//!
+//! `commit` and `recover` run `rv6_abi::log`, the log protocol of the kernel, over a model of the
+//! buffer cache of the kernel.
+
+use std::collections::HashMap;
+use std::iter;
+
+use rv6_abi::log::{self, LogDisk, LogError, MAXLOGGED};
+use rv6_abi::BSIZE;
+
+use crate::fsck::{self, Image};
+
+/// Crash points with at most this many unflushed writes are checked for every subset of the
+/// writes. Otherwise, only the prefixes and the prefixes missing one write are checked.
+const EXHAUSTIVE: usize = 8;
+
+/// An image with the history of writes to it.
+#[derive(Clone)]
+pub struct RecordingDisk {
+    /// The image before any of the writes.
+    initial: Vec<u8>,
+    /// The image after all of the writes.
+    current: Vec<u8>,
+    writes: Vec<(u32, Vec<u8>)>,
+    /// The number of writes issued before each flush.
+    flushes: Vec<usize>,
+}
+
+impl RecordingDisk {
+    pub fn new(image: Vec<u8>) -> Self {
+        Self {
+            current: image.clone(),
+            initial: image,
+            writes: Vec::new(),
+            flushes: Vec::new(),
+        }
+    }
+
+    pub fn read(&self, bn: u32) -> &[u8] {
+        let off = bn as usize * BSIZE;
+        &self.current[off..off + BSIZE]
+    }
+
+    pub fn write(&mut self, bn: u32, data: &[u8]) {
+        assert_eq!(data.len(), BSIZE);
+        let off = bn as usize * BSIZE;
+        self.current[off..off + BSIZE].copy_from_slice(data);
+        self.writes.push((bn, data.to_vec()));
+    }
+
+    /// Makes the writes issued so far durable.
+    pub fn flush(&mut self) {
+        self.flushes.push(self.writes.len());
+    }
+
+    /// Returns the image after all of the writes.
+    pub fn image(&self) -> &[u8] {
+        &self.current
+    }
+
+    /// Returns the image with the first `durable` writes, and then the writes in `pending`.
+    fn image_with(&self, durable: usize, pending: &[usize]) -> Vec<u8> {
+        let mut image = self.initial.clone();
+        let writes = (0..durable).chain(pending.iter().copied());
+        for (bn, data) in writes.map(|i| &self.writes[i]) {
+            let off = *bn as usize * BSIZE;
+            image[off..off + BSIZE].copy_from_slice(data);
+        }
+        image
+    }
+
+    /// Returns the crash points, each of which is the number of durable writes and the indices of
+    /// the unflushed writes that made it to the disk.
+    fn crash_points(&self) -> Vec<(usize, Vec<usize>)> {
+        let mut points = Vec::new();
+        let mut durable = 0;
+        for end in self
+            .flushes
+            .iter()
+            .copied()
+            .chain(iter::once(self.writes.len()))
+        {
+            let unflushed = (durable..end).collect::<Vec<_>>();
+            if unflushed.len() <= EXHAUSTIVE {
+                for subset in 0..1usize << unflushed.len() {
+                    let pending = unflushed
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| subset & (1 << i) != 0)
+                        .map(|(_, w)| *w)
+                        .collect::<Vec<_>>();
+                    points.push((durable, pending));
+                }
+            } else {
+                for len in 0..=unflushed.len() {
+                    points.push((durable, unflushed[..len].to_vec()));
+                    for skip in 0..len {
+                        let mut pending = unflushed[..len].to_vec();
+                        let _ = pending.remove(skip);
+                        points.push((durable, pending));
+                    }
+                }
+            }
+            durable = end;
+        }
+        points
+    }
+
+    /// Returns the images that a crash could leave behind.
+    pub fn crash_images(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.crash_points()
+            .into_iter()
+            .map(move |(durable, pending)| self.image_with(durable, &pending))
+    }
+}
+
+/// `disk` seen through a write-back cache holding `dirty`, the new contents of blocks that are not
+/// written to `disk` yet, as the buffer cache of the kernel holds them during a transaction.
+struct Cached<'a> {
+    disk: &'a mut RecordingDisk,
+    dirty: HashMap<u32, Vec<u8>>,
+}
+
+impl Cached<'_> {
+    fn block(&self, bn: u32) -> &[u8] {
+        match self.dirty.get(&bn) {
+            Some(data) => data,
+            None => self.disk.read(bn),
+        }
+    }
+}
+
+impl LogDisk for Cached<'_> {
+    fn copy(&mut self, from: u32, to: u32) {
+        let data = self.block(from).to_vec();
+        self.disk.write(to, &data);
+        let _ = self.dirty.remove(&to);
+    }
+
+    fn read(&mut self, bn: u32, f: impl FnOnce(&[u8])) {
+        f(self.block(bn));
+    }
+
+    fn update(&mut self, bn: u32, f: impl FnOnce(&mut [u8])) {
+        let mut data = self.block(bn).to_vec();
+        f(&mut data);
+        self.disk.write(bn, &data);
+        let _ = self.dirty.remove(&bn);
+    }
+
+    fn flush(&mut self) {
+        self.disk.flush();
+    }
+}
+
+/// Writes `blocks` to `disk` in a single transaction through the log of the file system, with
+/// `log::commit` as the kernel does.
+///
+/// # Panics
+///
+/// Panics if `disk` does not hold a file system, or the transaction does not fit in the log.
+pub fn commit(disk: &mut RecordingDisk, blocks: &[(u32, Vec<u8>)]) {
+    let sb = *Image::new(disk.image()).unwrap().superblock();
+    let bns = blocks.iter().map(|(bn, _)| *bn).collect::<Vec<_>>();
+    let mut cached = Cached {
+        disk,
+        dirty: blocks.iter().cloned().collect(),
+    };
+    log::commit(&mut cached, &sb, &bns);
+}
+
+/// Installs the committed transaction in the log of `image`, if any, with `log::recover` as the
+/// kernel does when it mounts the file system. Returns the writes of the recovery.
+pub fn recover(image: Vec<u8>) -> Result<RecordingDisk, fsck::Error> {
+    let sb = *Image::new(&image)?.superblock();
+    let mut disk = RecordingDisk::new(image);
+    let mut cached = Cached {
+        disk: &mut disk,
+        dirty: HashMap::new(),
+    };
+    log::recover::<_, MAXLOGGED>(&mut cached, &sb).map_err(|err| match err {
+        LogError::BadChecksum => fsck::Error::BadChecksum { bn: sb.logstart },
+        err => fsck::Error::BadLog(err),
+    })?;
+    Ok(disk)
+}
+
+/// Checks that every image a crash could leave behind on `disk` is consistent once recovered.
+pub fn check(disk: &RecordingDisk) -> Result<(), fsck::Error> {
+    for image in disk.crash_images() {
+        fsck::check(recover(image)?.image())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,278 @@
+//! Consistency checks of file system images.
+//!
+//! `check` verifies the invariants that the kernel keeps across every committed transaction:
+//! every block is owned by at most one inode and marked in the bitmap, every directory entry
+//! refers to an allocated inode, and link counts match the directory tree. If the file system is
+//! checksummed, every inode must also match its checksum. If it maps files by extents, the blocks
+//! of the extents and the extent blocks are checked as above. An image that a crash left behind
+//! must pass it once its log is installed by `crash::recover`.
+
+use std::{cmp, collections::HashSet, mem};
+
+use rv6_abi::extent::{ExtentMap, EXTENT_BLOCK};
+use rv6_abi::log::LogError;
+use rv6_abi::{
+    Dinode, Dirent, Superblock, BSIZE, IPB, MAXFILE, NDIRECT, NINDIRECT, ROOTINO, T_DEVICE, T_DIR,
+    T_FILE,
+};
+use zerocopy::FromBytes;
+
+/// An inconsistency found by `check`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The image does not start with a valid super block.
+    BadSuperblock,
+    /// The log header holds too many blocks, or refers to a block outside the image.
+    BadLog(LogError),
+    /// A block of metadata does not match its checksum.
+    BadChecksum { bn: u32 },
+    /// The inode has an unknown type, or a size or extents beyond `MAXFILE`.
+    BadInode { inum: u32 },
+    /// The inode refers to a block outside the data blocks.
+    BadBlock { inum: u32, bn: u32 },
+    /// The block is referred to more than once.
+    DupBlock { bn: u32 },
+    /// The block is in use, but not marked in the bitmap.
+    Unmarked { bn: u32 },
+    /// The block is marked in the bitmap, but not in use.
+    Leaked { bn: u32 },
+    /// The root inode is not a directory.
+    BadRoot,
+    /// A directory entry refers to a free or out-of-range inode.
+    BadDirent { dir: u32, inum: u32 },
+    /// A directory has two entries of the same name.
+    DupName { dir: u32 },
+    /// The link count of an inode disagrees with the number of entries referring to it.
+    BadNlink { inum: u32, nlink: i16, refs: usize },
+}
+
+/// A read-only view of a file system image.
+pub struct Image<'a> {
+    image: &'a [u8],
+    sb: Superblock,
+}
+
+impl<'a> Image<'a> {
+    /// Returns Err(Error::BadSuperblock) if `image` does not hold a file system.
+    pub fn new(image: &'a [u8]) -> Result<Self, Error> {
+        let sb = image
+            .get(BSIZE..)
+            .and_then(Superblock::read_from_prefix)
+            .filter(|sb| {
+                // Checked in this order so that none of them overflows.
+                sb.is_valid()
+                    && sb.size as usize * BSIZE <= image.len()
+                    && sb.logstart == 2
+                    && sb.nlog < sb.size
+                    && sb.inodestart == sb.logstart + sb.nlog
+                    && sb.bmapstart > sb.inodestart
+                    && sb.bmapstart < sb.size
+                    && sb.nblocks < sb.size
+                    && sb.ninodes > ROOTINO
+                    && sb.iblock(sb.ninodes - 1) < sb.bmapstart
+                    && sb.bblock(sb.size - 1) < sb.size - sb.nblocks
+            })
+            .ok_or(Error::BadSuperblock)?;
+        Ok(Self { image, sb })
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    pub fn block(&self, bn: u32) -> &'a [u8] {
+        let off = bn as usize * BSIZE;
+        &self.image[off..off + BSIZE]
+    }
+
+    pub fn dinode(&self, inum: u32) -> Dinode {
+        let off = inum as usize % IPB * mem::size_of::<Dinode>();
+        Dinode::read_from_prefix(&self.block(self.sb.iblock(inum))[off..]).unwrap()
+    }
+
+    /// Returns whether block `bn` is marked in the bitmap.
+    pub fn is_marked(&self, bn: u32) -> bool {
+        let bi = bn as usize % (BSIZE * 8);
+        self.block(self.sb.bblock(bn))[bi / 8] & (1 << (bi % 8)) != 0
+    }
+
+    /// Returns the contents of inode `inum`.
+    pub fn read(&self, inum: u32) -> Vec<u8> {
+        let din = self.dinode(inum);
+        let mut data = Vec::new();
+        for bn in self.data_blocks(&din) {
+            data.extend_from_slice(self.block(bn));
+        }
+        data.truncate(din.size as usize);
+        data
+    }
+
+    /// Returns the i-number of the entry named `name` in directory `dir`.
+    pub fn lookup(&self, dir: u32, name: &[u8]) -> Option<u32> {
+        self.dirents(&self.dinode(dir))
+            .iter()
+            .find(|de| de.name() == name)
+            .map(|de| de.inum as u32)
+    }
+
+    /// Returns the first block of the data blocks.
+    fn datastart(&self) -> u32 {
+        self.sb.size - self.sb.nblocks
+    }
+
+    /// Returns the addresses listed in the indirect block `bn`, or none if it does not lie within
+    /// the data blocks.
+    fn indirect(&self, bn: u32) -> Vec<u32> {
+        if !(self.datastart()..self.sb.size).contains(&bn) {
+            return Vec::new();
+        }
+        let addrs = self.block(bn);
+        (0..NINDIRECT)
+            .map(|i| u32::read_from_prefix(&addrs[i * 4..]).unwrap())
+            .collect()
+    }
+
+    /// Returns the extent map of `din`, without the extent block if it does not lie within the
+    /// data blocks.
+    fn extents<'b>(&self, din: &'b Dinode) -> ExtentMap<&'b [u32], &'a [u8]> {
+        let block = din.addrs[EXTENT_BLOCK];
+        let extents = if (self.datastart()..self.sb.size).contains(&block) {
+            Some(self.block(block))
+        } else {
+            None
+        };
+        ExtentMap::new(&din.addrs[..], extents)
+    }
+
+    /// Returns the data blocks of `din` in file order.
+    fn data_blocks(&self, din: &Dinode) -> Vec<u32> {
+        if self.sb.has_extents() {
+            // Up to `MAXFILE` blocks, even if the extents run beyond.
+            let mut blocks = Vec::new();
+            for (fbn, e) in self.extents(din).iter() {
+                let len = cmp::min(e.len, (MAXFILE as u32).saturating_sub(fbn));
+                blocks.extend(e.blocks().take(len as usize));
+            }
+            return blocks;
+        }
+        let mut blocks = din.addrs[..NDIRECT].to_vec();
+        blocks.extend(self.indirect(din.addrs[NDIRECT]));
+        for indirect in self.indirect(din.addrs[NDIRECT + 1]) {
+            blocks.extend(self.indirect(indirect));
+        }
+        blocks.retain(|bn| *bn != 0);
+        blocks
+    }
+
+    /// Returns the indirect blocks of `din`, including the double-indirect block and the indirect
+    /// blocks that it lists, or its extent block if the file system has `FS_EXTENTS` set.
+    fn indirect_blocks(&self, din: &Dinode) -> Vec<u32> {
+        if self.sb.has_extents() {
+            return din.addrs[EXTENT_BLOCK..]
+                .iter()
+                .copied()
+                .filter(|bn| *bn != 0)
+                .collect();
+        }
+        let mut blocks = din.addrs[NDIRECT..].to_vec();
+        blocks.extend(self.indirect(din.addrs[NDIRECT + 1]));
+        blocks.retain(|bn| *bn != 0);
+        blocks
+    }
+
+    /// Returns the entries of directory `din`.
+    fn dirents(&self, din: &Dinode) -> Vec<Dirent> {
+        let mut data = Vec::new();
+        for bn in self.data_blocks(din) {
+            data.extend_from_slice(self.block(bn));
+        }
+        data.truncate(din.size as usize);
+        data.chunks_exact(mem::size_of::<Dirent>())
+            .map(|de| Dirent::read_from(de).unwrap())
+            .filter(|de| de.inum != 0)
+            .collect()
+    }
+}
+
+/// Checks the consistency of `image`.
+pub fn check(image: &[u8]) -> Result<(), Error> {
+    let image = Image::new(image)?;
+    let sb = *image.superblock();
+    let datastart = image.datastart();
+
+    // Blocks and types of the inodes.
+    let mut used = HashSet::new();
+    let mut dirs = Vec::new();
+    for inum in 1..sb.ninodes {
+        let din = image.dinode(inum);
+        if sb.has_checksums() && !din.is_checksum_valid() {
+            return Err(Error::BadChecksum {
+                bn: sb.iblock(inum),
+            });
+        }
+        match din.typ {
+            0 => continue,
+            T_DIR => dirs.push(inum),
+            T_FILE | T_DEVICE => (),
+            _ => return Err(Error::BadInode { inum }),
+        }
+        if din.size as usize > MAXFILE * BSIZE
+            || (sb.has_extents() && image.extents(&din).nblocks() as usize > MAXFILE)
+        {
+            return Err(Error::BadInode { inum });
+        }
+        let mut blocks = image.data_blocks(&din);
+        blocks.extend(image.indirect_blocks(&din));
+        for bn in blocks {
+            if !(datastart..sb.size).contains(&bn) {
+                return Err(Error::BadBlock { inum, bn });
+            }
+            if !used.insert(bn) {
+                return Err(Error::DupBlock { bn });
+            }
+        }
+    }
+
+    // The bitmap.
+    for bn in datastart..sb.size {
+        match (used.contains(&bn), image.is_marked(bn)) {
+            (true, false) => return Err(Error::Unmarked { bn }),
+            (false, true) => return Err(Error::Leaked { bn }),
+            _ => (),
+        }
+    }
+
+    // Directory entries and link counts.
+    if image.dinode(ROOTINO).typ != T_DIR {
+        return Err(Error::BadRoot);
+    }
+    let mut refs = vec![0; sb.ninodes as usize];
+    for dir in dirs {
+        let mut names = HashSet::new();
+        for de in image.dirents(&image.dinode(dir)) {
+            let inum = de.inum as u32;
+            if inum >= sb.ninodes || image.dinode(inum).typ == 0 {
+                return Err(Error::BadDirent { dir, inum });
+            }
+            if !names.insert(de.name().to_vec()) {
+                return Err(Error::DupName { dir });
+            }
+            // "." does not count as a link, to avoid a cyclic reference.
+            if de.name() != b"." {
+                refs[inum as usize] += 1;
+            }
+        }
+    }
+    for inum in 1..sb.ninodes {
+        let din = image.dinode(inum);
+        // An unlinked inode may still be allocated if it was open at a crash.
+        if din.typ != 0 && din.nlink != 0 && din.nlink as usize != refs[inum as usize] {
+            return Err(Error::BadNlink {
+                inum,
+                nlink: din.nlink,
+                refs: refs[inum as usize],
+            });
+        }
+    }
+    Ok(())
+}
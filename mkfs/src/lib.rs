@@ -0,0 +1,340 @@
+//! Builds rv6 file system images.
+//!
+//! The image is built in memory with the on-disk types of `rv6_abi`, which the kernel also uses
+//! to read it, so the two cannot disagree on the layout.
+//!
+//! Disk layout:
+//! [ boot block | sb block | log | inode blocks | free bit map | data blocks ]
+
+pub mod crash;
+pub mod fsck;
+
+use std::{
+    cmp, mem,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rv6_abi::extent::{ExtentMap, EXTENT_BLOCK};
+use rv6_abi::{
+    log, Dinode, Dirent, Superblock, BPB, BSIZE, FSMAGIC, FS_CHECKSUM, FS_DISCARD, FS_EXTENTS, IPB,
+    MAXFILE, NDIRECT, NINDIRECT, ROOTINO, T_DIR, T_FILE,
+};
+use zerocopy::{AsBytes, FromBytes};
+
+/// Size of file system in blocks. Must agree with `FSSIZE` of kernel/param.h.
+pub const FSSIZE: u32 = 20000;
+
+/// Max data blocks in on-disk log. Must not exceed `LOGSIZE` of the kernel.
+pub const LOGSIZE: u32 = 30;
+
+/// Number of inodes.
+pub const NINODES: u32 = 200;
+
+/// Parameters of the disk layout.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Size of file system image (blocks).
+    pub size: u32,
+
+    /// Number of inodes.
+    pub ninodes: u32,
+
+    /// Number of log blocks.
+    pub nlog: u32,
+
+    /// Size that the bitmap can track without taking over data blocks (blocks). The file system
+    /// can grow online up to this size cheaply, even if it is full.
+    pub max_size: u32,
+
+    /// Whether the metadata is checksummed, i.e., `FS_CHECKSUM` is set.
+    pub checksums: bool,
+
+    /// Whether files are mapped by extents, i.e., `FS_EXTENTS` is set.
+    pub extents: bool,
+
+    /// Whether the kernel discards the blocks freed, i.e., `FS_DISCARD` is set.
+    pub discard: bool,
+
+    /// Time of the inodes, in seconds since the epoch. The current time by default, but fixed
+    /// for a reproducible image.
+    pub time: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            size: FSSIZE,
+            ninodes: NINODES,
+            nlog: LOGSIZE,
+            max_size: FSSIZE,
+            checksums: false,
+            extents: false,
+            discard: false,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as u32),
+        }
+    }
+}
+
+impl Config {
+    /// Computes the super block of an image with this layout.
+    pub fn superblock(&self) -> Superblock {
+        let nbitmap = cmp::max(self.size, self.max_size) / BPB as u32 + 1;
+        let ninodeblocks = self.ninodes / IPB as u32 + 1;
+        let nmeta = 2 + self.nlog + ninodeblocks + nbitmap;
+        let mut sb = Superblock {
+            magic: FSMAGIC,
+            size: self.size,
+            nblocks: self.size - nmeta,
+            ninodes: self.ninodes,
+            nlog: self.nlog,
+            logstart: 2,
+            inodestart: 2 + self.nlog,
+            bmapstart: 2 + self.nlog + ninodeblocks,
+            flags: if self.checksums { FS_CHECKSUM } else { 0 }
+                | if self.extents { FS_EXTENTS } else { 0 }
+                | if self.discard { FS_DISCARD } else { 0 },
+            checksum: 0,
+        };
+        sb.update_checksum();
+        sb
+    }
+}
+
+/// A file system image under construction, which contains only the root directory initially.
+#[derive(Clone)]
+pub struct Mkfs {
+    image: Vec<u8>,
+    sb: Superblock,
+    freeinode: u32,
+    freeblock: u32,
+    time: u32,
+}
+
+impl Mkfs {
+    /// Returns an empty file system with the given layout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the layout does not fit in `config.size` blocks, or the bitmap takes more than
+    /// one block.
+    pub fn new(config: Config) -> Self {
+        assert_eq!(BSIZE % mem::size_of::<Dirent>(), 0);
+
+        let sb = config.superblock();
+        assert!(sb.bmapstart < sb.size, "mkfs: image too small");
+        let mut fs = Self {
+            image: vec![0; sb.size as usize * BSIZE],
+            sb,
+            // The first free block that we can allocate.
+            freeblock: sb.size - sb.nblocks,
+            freeinode: 1,
+            time: config.time,
+        };
+        fs.block_mut(1)[..mem::size_of::<Superblock>()].copy_from_slice(sb.as_bytes());
+
+        if sb.has_checksums() {
+            // An empty log header, and free inodes, also carry checksums.
+            log::write_header(fs.block_mut(sb.logstart), &[], true);
+            for inum in 0..sb.ninodes {
+                fs.winode(inum, &Dinode::default());
+            }
+        }
+
+        let rootino = fs.ialloc(T_DIR);
+        assert_eq!(rootino, ROOTINO);
+        fs.add_dirent(rootino, b".", rootino);
+        fs.add_dirent(rootino, b"..", rootino);
+        fs
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    /// Adds a regular file named `name` with contents `data` to the root directory.
+    /// Returns the i-number of the file.
+    pub fn add_file(&mut self, name: &[u8], data: &[u8]) -> u32 {
+        let inum = self.ialloc(T_FILE);
+        self.add_dirent(ROOTINO, name, inum);
+        self.iappend(inum, data);
+        inum
+    }
+
+    /// Writes the bitmap of the allocated blocks, and returns the image.
+    pub fn finish(mut self) -> Vec<u8> {
+        // Fix size of root inode dir.
+        let mut din = self.rinode(ROOTINO);
+        din.size = (din.size / BSIZE as u32 + 1) * BSIZE as u32;
+        self.winode(ROOTINO, &din);
+
+        let used = self.freeblock as usize;
+        assert!(used < BPB, "mkfs: bitmap overflow");
+        let bmapstart = self.sb.bmapstart;
+        let bitmap = self.block_mut(bmapstart);
+        for i in 0..used {
+            bitmap[i / 8] |= 1 << (i % 8);
+        }
+        self.image
+    }
+
+    /// Returns the number of blocks allocated so far, including the metadata blocks.
+    pub fn used_blocks(&self) -> u32 {
+        self.freeblock
+    }
+
+    fn block(&self, bn: u32) -> &[u8] {
+        let off = bn as usize * BSIZE;
+        &self.image[off..off + BSIZE]
+    }
+
+    fn block_mut(&mut self, bn: u32) -> &mut [u8] {
+        let off = bn as usize * BSIZE;
+        &mut self.image[off..off + BSIZE]
+    }
+
+    fn balloc(&mut self) -> u32 {
+        assert!(self.freeblock < self.sb.size, "mkfs: out of blocks");
+        self.freeblock += 1;
+        self.freeblock - 1
+    }
+
+    fn ialloc(&mut self, typ: u16) -> u32 {
+        assert!(self.freeinode < self.sb.ninodes, "mkfs: out of inodes");
+        let inum = self.freeinode;
+        self.freeinode += 1;
+        let now = self.time;
+        let din = Dinode {
+            typ,
+            nlink: 1,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            // Owned by root, and readable and executable by everyone, as most of them are
+            // programs.
+            mode: 0o755,
+            ..Default::default()
+        };
+        self.winode(inum, &din);
+        inum
+    }
+
+    fn dinode_offset(inum: u32) -> usize {
+        inum as usize % IPB * mem::size_of::<Dinode>()
+    }
+
+    fn rinode(&self, inum: u32) -> Dinode {
+        let off = Self::dinode_offset(inum);
+        Dinode::read_from_prefix(&self.block(self.sb.iblock(inum))[off..]).unwrap()
+    }
+
+    fn winode(&mut self, inum: u32, din: &Dinode) {
+        let off = Self::dinode_offset(inum);
+        let bn = self.sb.iblock(inum);
+        let mut din = *din;
+        if self.sb.has_checksums() {
+            din.update_checksum();
+        }
+        din.write_to_prefix(&mut self.block_mut(bn)[off..]).unwrap();
+    }
+
+    fn add_dirent(&mut self, dir: u32, name: &[u8], inum: u32) {
+        let mut de = Dirent {
+            inum: inum as u16,
+            ..Default::default()
+        };
+        de.set_name(&name[..cmp::min(name.len(), de.name.len())]);
+        self.iappend(dir, de.as_bytes());
+    }
+
+    /// Returns the block that holds the `fbn`th block of `din`, allocating it if needed.
+    fn bmap(&mut self, din: &mut Dinode, fbn: usize) -> u32 {
+        assert!(fbn < MAXFILE, "mkfs: file too large");
+        if self.sb.has_extents() {
+            return self.extent_bmap(din, fbn as u32);
+        }
+        if fbn < NDIRECT {
+            if din.addrs[fbn] == 0 {
+                din.addrs[fbn] = self.balloc();
+            }
+            return din.addrs[fbn];
+        }
+
+        let fbn = fbn - NDIRECT;
+        if fbn < NINDIRECT {
+            if din.addrs[NDIRECT] == 0 {
+                din.addrs[NDIRECT] = self.balloc();
+            }
+            return self.indirect(din.addrs[NDIRECT], fbn);
+        }
+
+        let fbn = fbn - NINDIRECT;
+        if din.addrs[NDIRECT + 1] == 0 {
+            din.addrs[NDIRECT + 1] = self.balloc();
+        }
+        let indirect = self.indirect(din.addrs[NDIRECT + 1], fbn / NINDIRECT);
+        self.indirect(indirect, fbn % NINDIRECT)
+    }
+
+    /// Returns the block that holds the `fbn`th block of `din` by its extents, allocating it if
+    /// needed. Files are appended to one at a time, so each takes a single extent mostly.
+    fn extent_bmap(&mut self, din: &mut Dinode, fbn: u32) -> u32 {
+        let block = din.addrs[EXTENT_BLOCK];
+        let extents = if block == 0 {
+            None
+        } else {
+            Some(self.block(block))
+        };
+        let map = ExtentMap::new(&din.addrs[..], extents);
+        let addr = map.lookup(fbn);
+        if addr != 0 {
+            return addr;
+        }
+
+        // Take the extent block first, so that it does not split the extent of the new block.
+        if block == 0 && !map.fits() {
+            din.addrs[EXTENT_BLOCK] = self.balloc();
+        }
+        let addr = self.balloc();
+        let block = din.addrs[EXTENT_BLOCK];
+        let extents = if block == 0 {
+            None
+        } else {
+            Some(self.block_mut(block))
+        };
+        ExtentMap::new(&mut din.addrs[..], extents)
+            .map(fbn, addr)
+            .expect("mkfs: too many extents");
+        addr
+    }
+
+    /// Returns the `index`th block listed in the indirect block `indirect`, allocating it if
+    /// needed.
+    fn indirect(&mut self, indirect: u32, index: usize) -> u32 {
+        let off = index * mem::size_of::<u32>();
+        let mut addr = u32::read_from_prefix(&self.block(indirect)[off..]).unwrap();
+        if addr == 0 {
+            addr = self.balloc();
+            addr.write_to_prefix(&mut self.block_mut(indirect)[off..])
+                .unwrap();
+        }
+        addr
+    }
+
+    fn iappend(&mut self, inum: u32, mut data: &[u8]) {
+        let mut din = self.rinode(inum);
+        let mut off = din.size as usize;
+        while !data.is_empty() {
+            let fbn = off / BSIZE;
+            let bn = self.bmap(&mut din, fbn);
+            let n = cmp::min(data.len(), (fbn + 1) * BSIZE - off);
+            let boff = off - fbn * BSIZE;
+            self.block_mut(bn)[boff..boff + n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            off += n;
+        }
+        din.size = off as u32;
+        self.winode(inum, &din);
+    }
+}
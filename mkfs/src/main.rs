@@ -0,0 +1,72 @@
+use std::{env, fs, process};
+
+use rv6_mkfs::{Config, Mkfs};
+
+fn usage() -> ! {
+    eprintln!("Usage: mkfs [-c] [-e] [-d] [-g size] fs.img files...");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().collect::<Vec<_>>();
+    let mut config = Config::default();
+
+    // Options come before the image, in any order.
+    while args.len() >= 2 && args[1].starts_with('-') {
+        match args.remove(1).as_str() {
+            // Checksum the metadata.
+            "-c" => config.checksums = true,
+            // Map the files by extents.
+            "-e" => config.extents = true,
+            // Have the kernel discard the blocks freed.
+            "-d" => config.discard = true,
+            // Size the bitmap so that the file system can grow online to `size` blocks.
+            "-g" if args.len() >= 2 => {
+                config.max_size = args.remove(1).parse().unwrap_or_else(|_| usage())
+            }
+            _ => usage(),
+        }
+    }
+    if args.len() < 2 {
+        usage();
+    }
+
+    let mut mkfs = Mkfs::new(config);
+    let sb = *mkfs.superblock();
+    println!(
+        "nmeta {} (boot, super, log blocks {} inode blocks {}, bitmap blocks {}) blocks {} total {}",
+        sb.size - sb.nblocks,
+        sb.nlog,
+        sb.bmapstart - sb.inodestart,
+        sb.size - sb.nblocks - sb.bmapstart,
+        sb.nblocks,
+        sb.size
+    );
+
+    for path in &args[2..] {
+        // Get rid of "user/".
+        let name = path.strip_prefix("user/").unwrap_or(path);
+        assert!(!name.contains('/'));
+
+        // Skip leading _ in name when writing to file system.
+        // The binaries are named _rm, _cat, etc. to keep the
+        // build operating system from trying to execute them
+        // in place of system binaries like rm and cat.
+        let name = name.strip_prefix('_').unwrap_or(name);
+
+        let data = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        });
+        let _ = mkfs.add_file(name.as_bytes(), &data);
+    }
+
+    println!(
+        "balloc: first {} blocks have been allocated",
+        mkfs.used_blocks()
+    );
+    if let Err(e) = fs::write(&args[1], mkfs.finish()) {
+        eprintln!("{}: {}", args[1], e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,132 @@
+//! Tests of committing transactions through the log with `crash`, which runs the log protocol of
+//! the kernel, and of recovering the images that a crash leaves behind.
+
+use rv6_abi::log::{self, LogError};
+use rv6_abi::BSIZE;
+use rv6_mkfs::crash::{self, RecordingDisk};
+use rv6_mkfs::{fsck, Config, Mkfs};
+
+const CONFIG: Config = Config {
+    size: 200,
+    ninodes: 32,
+    nlog: 10,
+    max_size: 200,
+    checksums: true,
+    extents: false,
+    discard: false,
+    time: 0,
+};
+
+/// Returns the blocks that differ between `before` and `after`, with their contents in `after`.
+fn diff(before: &[u8], after: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    before
+        .chunks_exact(BSIZE)
+        .zip(after.chunks_exact(BSIZE))
+        .enumerate()
+        .filter(|(_, (b, a))| b != a)
+        .map(|(bn, (_, a))| (bn as u32, a.to_vec()))
+        .collect()
+}
+
+/// Returns the image without its log.
+fn without_log(image: &[u8], config: Config) -> Vec<u8> {
+    let logstart = Mkfs::new(config).superblock().logstart as usize;
+    let mut image = image.to_vec();
+    for b in &mut image[logstart * BSIZE..(logstart + config.nlog as usize) * BSIZE] {
+        *b = 0;
+    }
+    image
+}
+
+/// Commits adding a file, and returns the disk with the images before and after.
+fn add_file(config: Config) -> (RecordingDisk, Vec<u8>, Vec<u8>) {
+    let mut mkfs = Mkfs::new(config);
+    let before = mkfs.clone().finish();
+    let _ = mkfs.add_file(b"file", &[7; 2 * BSIZE]);
+    let after = mkfs.finish();
+    let mut disk = RecordingDisk::new(before.clone());
+    crash::commit(&mut disk, &diff(&before, &after));
+    (disk, before, after)
+}
+
+/// Returns the log header of `disk` holding `blocks`, overwritten by `f`.
+fn corrupt_header(disk: &RecordingDisk, blocks: &[u32], f: impl FnOnce(&mut [u8])) -> Vec<u8> {
+    let logstart = Mkfs::new(CONFIG).superblock().logstart as usize;
+    let mut image = disk.image().to_vec();
+    let header = &mut image[logstart * BSIZE..(logstart + 1) * BSIZE];
+    log::write_header(header, blocks, true);
+    f(header);
+    image
+}
+
+/// Every crash during a commit leaves the file system either before or after the transaction,
+/// once recovered.
+#[test]
+fn commit_is_atomic() {
+    for &checksums in &[false, true] {
+        let config = Config {
+            checksums,
+            ..CONFIG
+        };
+        let (disk, before, after) = add_file(config);
+        let (before, after) = (without_log(&before, config), without_log(&after, config));
+        assert!(without_log(disk.image(), config) == after);
+
+        let mut afters = 0;
+        for image in disk.crash_images() {
+            let recovered = crash::recover(image).unwrap();
+            fsck::check(recovered.image()).unwrap();
+            let recovered = without_log(recovered.image(), config);
+            assert!(recovered == before || recovered == after);
+            afters += (recovered == after) as usize;
+        }
+        assert!(afters > 0);
+        crash::check(&disk).unwrap();
+    }
+}
+
+/// A crash during recovery leaves the log to be recovered again at the next mount, with the same
+/// result.
+#[test]
+fn recovery_is_idempotent() {
+    let (disk, _, _) = add_file(CONFIG);
+    for image in disk.crash_images() {
+        let recovery = crash::recover(image).unwrap();
+        let expected = without_log(recovery.image(), CONFIG);
+        for image in recovery.crash_images() {
+            let recovered = crash::recover(image).unwrap();
+            assert!(without_log(recovered.image(), CONFIG) == expected);
+        }
+    }
+}
+
+#[test]
+fn bad_checksum() {
+    let (disk, _, _) = add_file(CONFIG);
+    let logstart = Mkfs::new(CONFIG).superblock().logstart;
+    let image = corrupt_header(&disk, &[logstart + 1], |header| header[4] ^= 1);
+    assert_eq!(
+        crash::recover(image).err(),
+        Some(fsck::Error::BadChecksum { bn: logstart })
+    );
+}
+
+#[test]
+fn too_long() {
+    let (disk, _, _) = add_file(CONFIG);
+    let image = corrupt_header(&disk, &vec![1; CONFIG.nlog as usize], |_| ());
+    assert_eq!(
+        crash::recover(image).err(),
+        Some(fsck::Error::BadLog(LogError::TooLong { n: CONFIG.nlog }))
+    );
+}
+
+#[test]
+fn bad_block() {
+    let (disk, _, _) = add_file(CONFIG);
+    let image = corrupt_header(&disk, &[1, CONFIG.size], |_| ());
+    assert_eq!(
+        crash::recover(image).err(),
+        Some(fsck::Error::BadLog(LogError::BadBlock { bn: CONFIG.size }))
+    );
+}
@@ -0,0 +1,262 @@
+//! Tests of `ExtentMap` against a model that lists the address of every block, and of images whose
+//! files are mapped by extents.
+
+use std::ops::Range;
+
+use rv6_abi::extent::{ExtentMap, EXTENT_BLOCK, MAXEXTENT, NEXTENT_INODE};
+use rv6_abi::{Dinode, BSIZE, NDIRECT, ROOTINO};
+use rv6_mkfs::{fsck, Config, Mkfs};
+use zerocopy::AsBytes;
+
+/// The addresses of an inode and its extent block.
+struct Inode {
+    addrs: [u32; NDIRECT + 2],
+    block: Vec<u8>,
+}
+
+impl Inode {
+    fn new() -> Self {
+        Self {
+            addrs: [0; NDIRECT + 2],
+            block: vec![0; BSIZE],
+        }
+    }
+
+    fn map(&mut self) -> ExtentMap<&mut [u32], &mut [u8]> {
+        ExtentMap::new(&mut self.addrs[..], Some(&mut self.block[..]))
+    }
+}
+
+/// Returns a permutation of `0..n`.
+fn shuffle(n: u32, mut seed: u32) -> Vec<u32> {
+    let mut v = (0..n).collect::<Vec<_>>();
+    for i in (1..v.len()).rev() {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        v.swap(i, (seed >> 16) as usize % (i + 1));
+    }
+    v
+}
+
+/// Checks `map` against `model`, the address of each block or 0, and checks that the extents are
+/// as few as they can be.
+fn check(map: &ExtentMap<&mut [u32], &mut [u8]>, model: &[u32]) {
+    for (bn, addr) in model.iter().enumerate() {
+        assert_eq!(map.lookup(bn as u32), *addr, "block {}", bn);
+    }
+    assert_eq!(map.lookup(model.len() as u32), 0);
+    // No hole is left at the end.
+    let end = model
+        .iter()
+        .rposition(|addr| *addr != 0)
+        .map_or(0, |bn| bn + 1);
+    assert_eq!(map.nblocks() as usize, end);
+
+    let extents = map.iter().map(|(_, e)| e).collect::<Vec<_>>();
+    for pair in extents.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        assert!(!(a.is_hole() && b.is_hole()), "two holes");
+        assert!(
+            a.is_hole() || a.start + a.len != b.start,
+            "contiguous extents"
+        );
+    }
+}
+
+/// Maps `bns` in order, each `bn` to `addr(bn)`, checking the map after each.
+fn map_all(inode: &mut Inode, model: &mut Vec<u32>, bns: &[u32], addr: impl Fn(u32) -> u32) {
+    for &bn in bns {
+        inode.map().map(bn, addr(bn)).unwrap();
+        if model.len() <= bn as usize {
+            model.resize(bn as usize + 1, 0);
+        }
+        model[bn as usize] = addr(bn);
+        check(&inode.map(), model);
+    }
+}
+
+#[test]
+fn sequential() {
+    let mut inode = Inode::new();
+    let mut model = Vec::new();
+    let bns = (0..1000).collect::<Vec<_>>();
+    map_all(&mut inode, &mut model, &bns, |bn| 100 + bn);
+    assert_eq!(inode.map().len(), 1);
+}
+
+#[test]
+fn any_order() {
+    for seed in 0..20 {
+        let mut inode = Inode::new();
+        let mut model = Vec::new();
+        map_all(&mut inode, &mut model, &shuffle(120, seed), |bn| 100 + bn);
+        // Contiguous in the end, whatever the order.
+        assert_eq!(inode.map().len(), 1);
+    }
+}
+
+#[test]
+fn holes() {
+    let mut inode = Inode::new();
+    let mut model = Vec::new();
+    // Every other block, and then a block far beyond, in another run.
+    let bns = (0..40).map(|i| 2 * i).collect::<Vec<_>>();
+    map_all(&mut inode, &mut model, &bns, |bn| 100 + bn);
+    map_all(&mut inode, &mut model, &[500], |_| 7);
+    assert_eq!(inode.map().len(), 2 * 40 + 1);
+
+    // Filling the holes joins the extents on both sides.
+    let bns = (0..39).map(|i| 2 * i + 1).collect::<Vec<_>>();
+    map_all(&mut inode, &mut model, &bns, |bn| 100 + bn);
+    assert_eq!(inode.map().len(), 3);
+}
+
+#[test]
+fn full() {
+    let mut inode = Inode::new();
+    let mut model = Vec::new();
+    // No block is contiguous with another, so each takes an extent.
+    let mut bn = 0;
+    while inode.map().fits() {
+        map_all(&mut inode, &mut model, &[bn], |bn| 100 + 2 * bn);
+        bn += 1;
+    }
+    assert!(inode.map().len() >= MAXEXTENT - 1);
+
+    // A block that needs a new extent does not fit, and changes nothing.
+    let (addrs, block) = (inode.addrs, inode.block.clone());
+    assert!(inode.map().map(bn + 1, 1).is_err());
+    assert_eq!(inode.addrs, addrs);
+    assert!(inode.block == block);
+
+    // One that joins the last extent still fits.
+    let last = 100 + 2 * (bn - 1);
+    map_all(&mut inode, &mut model, &[bn], |_| last + 1);
+}
+
+#[test]
+fn inode_only() {
+    let mut addrs = [0; NDIRECT + 2];
+    let mut map = ExtentMap::new(&mut addrs[..], None::<&mut [u8]>);
+    let mut bn = 0;
+    while map.fits() {
+        map.map(bn, 100 + 2 * bn).unwrap();
+        bn += 1;
+    }
+    assert_eq!(map.len(), NEXTENT_INODE - 1);
+    assert!(map.map(bn, 1).is_ok());
+    assert!(map.map(bn + 1, 3).is_err());
+    assert_eq!(addrs[EXTENT_BLOCK], 0);
+}
+
+#[test]
+fn truncate() {
+    for seed in 0..20 {
+        let mut inode = Inode::new();
+        let mut model = Vec::new();
+        // Two runs, with holes.
+        let bns = shuffle(120, seed)
+            .into_iter()
+            .filter(|bn| bn % 7 != 3)
+            .collect::<Vec<_>>();
+        map_all(&mut inode, &mut model, &bns, |bn| {
+            if bn < 60 {
+                1000 + bn
+            } else {
+                5000 + bn
+            }
+        });
+
+        for &nblocks in &[200, 119, 100, 61, 60, 57, 4, 1, 0] {
+            let mut freed = Vec::new();
+            inode
+                .map()
+                .truncate(nblocks, |blocks: Range<u32>| freed.extend(blocks));
+            freed.sort_unstable();
+            let mut dropped = model
+                .drain(model.len().min(nblocks as usize)..)
+                .filter(|addr| *addr != 0)
+                .collect::<Vec<_>>();
+            dropped.sort_unstable();
+            assert_eq!(freed, dropped);
+            check(&inode.map(), &model);
+        }
+        assert!(inode.map().is_empty());
+        assert!(inode.addrs == [0; NDIRECT + 2]);
+        assert!(inode.block.iter().all(|b| *b == 0));
+    }
+}
+
+/// Config of a file system that maps files by extents.
+const EXTENTS: Config = Config {
+    size: 2000,
+    ninodes: 32,
+    nlog: 10,
+    max_size: 2000,
+    checksums: false,
+    extents: true,
+    discard: false,
+    time: 0,
+};
+
+/// Returns the image with a file of `len` bytes, and the i-number and the inode of the file.
+fn image_with_file(len: usize) -> (Vec<u8>, u32) {
+    let mut mkfs = Mkfs::new(EXTENTS);
+    let inum = mkfs.add_file(b"file", &vec![7; len]);
+    (mkfs.finish(), inum)
+}
+
+/// Overwrites inode `inum` of `image` with `din`.
+fn write_dinode(image: &mut [u8], inum: u32, din: &Dinode) {
+    let sb = *fsck::Image::new(image).unwrap().superblock();
+    let off = sb.iblock(inum) as usize * BSIZE
+        + inum as usize % rv6_abi::IPB * std::mem::size_of::<Dinode>();
+    image[off..off + std::mem::size_of::<Dinode>()].copy_from_slice(din.as_bytes());
+}
+
+#[test]
+fn files_take_one_extent() {
+    let (image, inum) = image_with_file(1000 * BSIZE + 3);
+    fsck::check(&image).unwrap();
+    let fs = fsck::Image::new(&image).unwrap();
+    assert!(fs.superblock().has_extents());
+    let din = fs.dinode(inum);
+    assert_eq!(din.addrs[1], 1001);
+    assert_eq!(din.addrs[2..], [0; NDIRECT]);
+    assert!(fs.read(inum) == vec![7; 1000 * BSIZE + 3]);
+    assert_eq!(fs.lookup(ROOTINO, b"file"), Some(inum));
+}
+
+#[test]
+fn bad_extents() {
+    let (image, inum) = image_with_file(10 * BSIZE);
+    let din = fsck::Image::new(&image).unwrap().dinode(inum);
+
+    // Beyond the data blocks.
+    let mut bad = image.clone();
+    let mut d = din;
+    d.addrs[1] = EXTENTS.size;
+    write_dinode(&mut bad, inum, &d);
+    assert!(matches!(
+        fsck::check(&bad),
+        Err(fsck::Error::BadBlock { .. })
+    ));
+
+    // Longer than a file can be.
+    let mut bad = image.clone();
+    let mut d = din;
+    d.addrs[2] = 0;
+    d.addrs[3] = u32::MAX;
+    write_dinode(&mut bad, inum, &d);
+    assert_eq!(fsck::check(&bad), Err(fsck::Error::BadInode { inum }));
+
+    // Overlapping another extent.
+    let mut bad = image;
+    let mut d = din;
+    d.addrs[2] = d.addrs[0] + 1;
+    d.addrs[3] = 1;
+    write_dinode(&mut bad, inum, &d);
+    assert_eq!(
+        fsck::check(&bad),
+        Err(fsck::Error::DupBlock { bn: d.addrs[0] + 1 })
+    );
+}
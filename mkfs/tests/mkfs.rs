@@ -0,0 +1,223 @@
+//! Tests of `Mkfs` against a golden image, and of reading back the files that it writes.
+//!
+//! The images are read back by `fsck`, and `tests/crash.rs` recovers them by the log protocol that
+//! the kernel shares. No test mounts them through the kernel itself: its mount path
+//! (`read_superblock`, `Log::new`, and `Inode::lock`) runs on the buffer cache and the disk of the
+//! HAL, and does not build for the host.
+
+use rv6_abi::{
+    Dinode, Dirent, Errno, Superblock, BSIZE, FSMAGIC, IPB, MAXFILE, NDIRECT, NINDIRECT, ROOTINO,
+    T_DIR, T_FILE,
+};
+use rv6_mkfs::{fsck, Config, Mkfs};
+use zerocopy::AsBytes;
+
+const TIME: u32 = 1_000_000_000;
+
+/// A file system small enough to spell out block by block.
+const SMALL: Config = Config {
+    size: 64,
+    ninodes: 16,
+    nlog: 4,
+    max_size: 64,
+    checksums: false,
+    extents: false,
+    discard: false,
+    time: TIME,
+};
+
+fn write(image: &mut [u8], bn: u32, off: usize, data: &[u8]) {
+    let off = bn as usize * BSIZE + off;
+    image[off..off + data.len()].copy_from_slice(data);
+}
+
+fn dirent(inum: u16, name: &[u8]) -> Dirent {
+    let mut de = Dirent {
+        inum,
+        ..Default::default()
+    };
+    de.set_name(name);
+    de
+}
+
+fn dinode(typ: u16, size: u32, addr: u32) -> Dinode {
+    let mut addrs = [0; NDIRECT + 2];
+    addrs[0] = addr;
+    Dinode {
+        typ,
+        nlink: 1,
+        size,
+        addrs,
+        atime: TIME,
+        mtime: TIME,
+        ctime: TIME,
+        mode: 0o755,
+        ..Default::default()
+    }
+}
+
+/// Returns `len` bytes that differ from block to block.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251 + i / BSIZE) as u8).collect()
+}
+
+#[test]
+fn golden_image() {
+    let mut mkfs = Mkfs::new(SMALL);
+    assert_eq!(mkfs.add_file(b"hello", b"hello, world\n"), 2);
+    let image = mkfs.finish();
+
+    // [ boot | sb | log 2..6 | inodes 6..8 | bitmap 8 | data 9..64 ], as laid out by xv6's mkfs.
+    let sb = Superblock {
+        magic: FSMAGIC,
+        size: 64,
+        nblocks: 55,
+        ninodes: 16,
+        nlog: 4,
+        logstart: 2,
+        inodestart: 6,
+        bmapstart: 8,
+        flags: 0,
+        checksum: 0,
+    };
+    let mut golden = vec![0; 64 * BSIZE];
+    write(&mut golden, 1, 0, sb.as_bytes());
+
+    // The root directory takes the first data block, and "hello" the next.
+    let isize = std::mem::size_of::<Dinode>();
+    write(
+        &mut golden,
+        6,
+        isize,
+        dinode(T_DIR, BSIZE as u32, 9).as_bytes(),
+    );
+    write(&mut golden, 6, 2 * isize, dinode(T_FILE, 13, 10).as_bytes());
+    let desize = std::mem::size_of::<Dirent>();
+    write(&mut golden, 9, 0, dirent(1, b".").as_bytes());
+    write(&mut golden, 9, desize, dirent(1, b"..").as_bytes());
+    write(&mut golden, 9, 2 * desize, dirent(2, b"hello").as_bytes());
+    write(&mut golden, 10, 0, b"hello, world\n");
+
+    // Blocks 0..11 are in use.
+    write(&mut golden, 8, 0, &[0xff, 0x07]);
+
+    for (bn, (block, expected)) in image
+        .chunks_exact(BSIZE)
+        .zip(golden.chunks_exact(BSIZE))
+        .enumerate()
+    {
+        assert!(block == expected, "block {} differs", bn);
+    }
+    assert_eq!(image.len(), golden.len());
+}
+
+#[test]
+fn reproducible() {
+    let build = || {
+        let mut mkfs = Mkfs::new(Config {
+            time: TIME,
+            ..Config::default()
+        });
+        let _ = mkfs.add_file(b"a", &pattern(3 * BSIZE));
+        mkfs.finish()
+    };
+    assert!(build() == build());
+}
+
+#[test]
+fn round_trip() {
+    // Sizes across the direct, indirect, and double-indirect blocks.
+    let files = [
+        (&b"empty"[..], 0),
+        (b"small", 1),
+        (b"block", BSIZE),
+        (b"direct", NDIRECT * BSIZE),
+        (b"indirect", (NDIRECT + 1) * BSIZE + 7),
+        (b"dindirect", (NDIRECT + NINDIRECT + 3) * BSIZE + 1),
+        (b"fourteenchars!", 100),
+    ];
+    for &(checksums, extents, discard) in &[
+        (false, false, false),
+        (true, false, true),
+        (false, true, false),
+        (true, true, true),
+    ] {
+        let mut mkfs = Mkfs::new(Config {
+            checksums,
+            extents,
+            discard,
+            time: TIME,
+            ..Config::default()
+        });
+        let inums = files
+            .iter()
+            .map(|(name, len)| mkfs.add_file(name, &pattern(*len)))
+            .collect::<Vec<_>>();
+        let image = mkfs.finish();
+        fsck::check(&image).unwrap();
+
+        let fs = fsck::Image::new(&image).unwrap();
+        assert_eq!(fs.superblock().has_checksums(), checksums);
+        assert_eq!(fs.superblock().has_extents(), extents);
+        assert_eq!(fs.superblock().has_discard(), discard);
+        assert_eq!(fs.lookup(ROOTINO, b"."), Some(ROOTINO));
+        assert_eq!(fs.lookup(ROOTINO, b".."), Some(ROOTINO));
+        for ((name, len), inum) in files.iter().zip(inums) {
+            assert_eq!(fs.lookup(ROOTINO, name), Some(inum));
+            let din = fs.dinode(inum);
+            assert_eq!(din.typ, T_FILE);
+            assert_eq!(din.nlink, 1);
+            assert_eq!(din.mtime, TIME);
+            assert!(fs.read(inum) == pattern(*len));
+        }
+        assert_eq!(fs.lookup(ROOTINO, b"missing"), None);
+    }
+}
+
+#[test]
+fn corrupt_inode() {
+    let mut mkfs = Mkfs::new(Config {
+        checksums: true,
+        time: TIME,
+        ..Config::default()
+    });
+    let inum = mkfs.add_file(b"victim", b"data");
+    let mut image = mkfs.finish();
+    let fs = fsck::Image::new(&image).unwrap();
+    let sb = *fs.superblock();
+    assert_eq!(fs.dinode(inum).check(true), Ok(()));
+
+    // Flip a bit of `gen`, which follows `addrs`, so that the checksum no longer matches.
+    let off = sb.iblock(inum) as usize * BSIZE
+        + inum as usize % IPB * std::mem::size_of::<Dinode>()
+        + 12
+        + 4 * (NDIRECT + 2);
+    image[off] ^= 1;
+    let fs = fsck::Image::new(&image).unwrap();
+    assert_eq!(fs.dinode(inum).check(true), Err(Errno::EIO));
+    assert_eq!(fs.dinode(inum).check(false), Ok(()));
+    assert_eq!(
+        fsck::check(&image),
+        Err(fsck::Error::BadChecksum {
+            bn: sb.iblock(inum)
+        })
+    );
+
+    // An unknown type is corrupted even if the checksum matches.
+    let mut din = fs.dinode(inum);
+    din.typ = 7;
+    din.update_checksum();
+    assert_eq!(din.check(true), Err(Errno::EIO));
+    assert_eq!(din.check(false), Err(Errno::EIO));
+}
+
+#[test]
+#[should_panic(expected = "mkfs: file too large")]
+fn too_large() {
+    let mut mkfs = Mkfs::new(Config {
+        size: 70000,
+        max_size: 70000,
+        ..Config::default()
+    });
+    let _ = mkfs.add_file(b"big", &vec![0; (MAXFILE + 1) * BSIZE]);
+}
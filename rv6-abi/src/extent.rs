@@ -0,0 +1,293 @@
+//! Extent maps, with which a file system that has `FS_EXTENTS` set maps the blocks of its files.
+//!
+//! An extent is a run of blocks that are contiguous on the disk. Instead of the address of every
+//! block, an inode lists the extents of its file in file order: the first `NEXTENT_INODE` of them
+//! in `Dinode::addrs`, and the rest in the extent block that `Dinode::addrs[EXTENT_BLOCK]` refers
+//! to, if any. An extent that starts at block 0 is a hole. The list ends at the first extent of
+//! length 0, or where it is full, and the blocks past its end are holes too. A file that is
+//! written sequentially takes a few extents, so mapping its blocks reads the extent block at
+//! most.
+//!
+//! The kernel and `mkfs` edit extent maps with `ExtentMap`, and `fsck` reads them with it.
+
+use core::{cmp, mem, ops::Range};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{BSIZE, NDIRECT};
+
+/// A run of `len` blocks from block `start`, or a hole of `len` blocks if `start` is 0.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, AsBytes, FromBytes)]
+pub struct Extent {
+    pub start: u32,
+    pub len: u32,
+}
+
+impl Extent {
+    const fn hole(len: u32) -> Self {
+        Self { start: 0, len }
+    }
+
+    pub const fn is_hole(&self) -> bool {
+        self.start == 0
+    }
+
+    /// Returns the blocks of the extent, which are none if it is a hole.
+    pub fn blocks(&self) -> Range<u32> {
+        if self.is_hole() {
+            0..0
+        } else {
+            self.start..self.start.saturating_add(self.len)
+        }
+    }
+
+    /// Returns true if block `addr` comes right after the extent.
+    fn is_followed_by(&self, addr: u32) -> bool {
+        !self.is_hole() && self.start.wrapping_add(self.len) == addr
+    }
+}
+
+/// Index of the address of the extent block in `Dinode::addrs`, after the extents there.
+pub const EXTENT_BLOCK: usize = NDIRECT + 1;
+
+/// Number of extents in `Dinode::addrs`.
+pub const NEXTENT_INODE: usize = EXTENT_BLOCK / 2;
+
+/// Number of extents in an extent block.
+pub const NEXTENT_BLOCK: usize = BSIZE / mem::size_of::<Extent>();
+
+/// Maximum number of extents of a file.
+pub const MAXEXTENT: usize = NEXTENT_INODE + NEXTENT_BLOCK;
+
+/// The extent map of a file: `Dinode::addrs` of its inode, and the contents of its extent block
+/// if it has one. An `ExtentMap` without the extent block sees the extents in the inode only.
+pub struct ExtentMap<A, B> {
+    addrs: A,
+    block: Option<B>,
+}
+
+impl<A: AsRef<[u32]>, B: AsRef<[u8]>> ExtentMap<A, B> {
+    pub fn new(addrs: A, block: Option<B>) -> Self {
+        assert_eq!(
+            addrs.as_ref().len(),
+            NDIRECT + 2,
+            "ExtentMap::new: not addrs"
+        );
+        Self { addrs, block }
+    }
+
+    fn capacity(&self) -> usize {
+        if self.block.is_some() {
+            MAXEXTENT
+        } else {
+            NEXTENT_INODE
+        }
+    }
+
+    /// Returns the `i`th slot of the list, which must be less than the capacity.
+    fn get(&self, i: usize) -> Extent {
+        match &self.block {
+            Some(block) if i >= NEXTENT_INODE => {
+                let off = (i - NEXTENT_INODE) * mem::size_of::<Extent>();
+                Extent::read_from_prefix(&block.as_ref()[off..]).unwrap()
+            }
+            _ => {
+                let addrs = self.addrs.as_ref();
+                Extent {
+                    start: addrs[2 * i],
+                    len: addrs[2 * i + 1],
+                }
+            }
+        }
+    }
+
+    /// Returns the extents in file order, each with the number of its first block in the file.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, Extent)> + '_ {
+        (0..self.capacity())
+            .map(move |i| self.get(i))
+            .take_while(|e| e.len != 0)
+            .scan(0u32, |fbn, e| {
+                let first = *fbn;
+                *fbn = fbn.saturating_add(e.len);
+                Some((first, e))
+            })
+    }
+
+    /// Returns the number of extents.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of blocks that the extents cover, including the holes.
+    pub fn nblocks(&self) -> u32 {
+        self.iter()
+            .last()
+            .map_or(0, |(fbn, e)| fbn.saturating_add(e.len))
+    }
+
+    /// Returns the extent that covers the `bn`th block of the file, with the number of its first
+    /// block in the file.
+    pub fn find(&self, bn: u32) -> Option<(u32, Extent)> {
+        self.iter().find(|(fbn, e)| bn >= *fbn && bn - fbn < e.len)
+    }
+
+    /// Returns the address of the `bn`th block of the file, or 0 if it is in a hole.
+    pub fn lookup(&self, bn: u32) -> u32 {
+        match self.find(bn) {
+            Some((fbn, e)) if !e.is_hole() => e.start + (bn - fbn),
+            _ => 0,
+        }
+    }
+
+    /// Returns true if there is room for mapping any block, which takes up to two more extents.
+    pub fn fits(&self) -> bool {
+        self.len() + 2 <= self.capacity()
+    }
+}
+
+impl<A: AsRef<[u32]> + AsMut<[u32]>, B: AsRef<[u8]> + AsMut<[u8]>> ExtentMap<A, B> {
+    fn set(&mut self, i: usize, e: Extent) {
+        match &mut self.block {
+            Some(block) if i >= NEXTENT_INODE => {
+                let off = (i - NEXTENT_INODE) * mem::size_of::<Extent>();
+                e.write_to_prefix(&mut block.as_mut()[off..]).unwrap();
+            }
+            _ => {
+                let addrs = self.addrs.as_mut();
+                addrs[2 * i] = e.start;
+                addrs[2 * i + 1] = e.len;
+            }
+        }
+    }
+
+    /// Replaces the `remove` extents from the `i`th one with `insert`, which must fit.
+    fn splice(&mut self, i: usize, remove: usize, insert: &[Extent]) {
+        let n = self.len();
+        let m = n - remove + insert.len();
+        debug_assert!(m <= self.capacity());
+        if insert.len() > remove {
+            for j in (i + remove..n).rev() {
+                self.set(j + insert.len() - remove, self.get(j));
+            }
+        } else {
+            for j in i + remove..n {
+                self.set(j + insert.len() - remove, self.get(j));
+            }
+        }
+        for (j, e) in insert.iter().enumerate() {
+            self.set(i + j, *e);
+        }
+        for j in m..n {
+            self.set(j, Extent::default());
+        }
+    }
+
+    /// Maps the `bn`th block of the file, which must be in a hole, to block `addr`. The block joins
+    /// the extent before or after it if they are contiguous on the disk.
+    /// Returns Err(()), changing nothing, if the extents do not fit (see `fits`).
+    pub fn map(&mut self, bn: u32, addr: u32) -> Result<(), ()> {
+        assert_ne!(addr, 0, "ExtentMap::map: no block");
+        let n = self.len();
+        let end = self.nblocks();
+        // The hole of `bn`: the `i`th extent, or one past the end that is not in the list yet.
+        let (i, fbn, len, remove) = match self
+            .iter()
+            .enumerate()
+            .find(|(_, (fbn, e))| bn >= *fbn && bn - fbn < e.len)
+        {
+            Some((i, (fbn, e))) => {
+                assert!(e.is_hole(), "ExtentMap::map: mapped");
+                (i, fbn, e.len, 1)
+            }
+            None => (n, end, bn - end + 1, 0),
+        };
+        let before = bn - fbn;
+        let after = fbn + len - 1 - bn;
+        let join_prev = before == 0 && i > 0 && self.get(i - 1).is_followed_by(addr);
+        let join_next = after == 0
+            && i + 1 < n
+            && !self.get(i + 1).is_hole()
+            && self.get(i + 1).start == addr + 1;
+
+        let block = Extent {
+            start: addr,
+            len: 1,
+        };
+        let mut insert = [Extent::default(); 3];
+        let mut k = 0;
+        for &(cond, e) in &[
+            (before > 0, Extent::hole(before)),
+            (!join_prev && !join_next, block),
+            (after > 0, Extent::hole(after)),
+        ] {
+            if cond {
+                insert[k] = e;
+                k += 1;
+            }
+        }
+        let remove = remove + (join_prev && join_next) as usize;
+        if n - remove + k > self.capacity() {
+            return Err(());
+        }
+
+        if join_prev {
+            let mut prev = self.get(i - 1);
+            prev.len += 1;
+            if join_next {
+                prev.len += self.get(i + 1).len;
+            }
+            self.set(i - 1, prev);
+        } else if join_next {
+            let next = self.get(i + 1);
+            self.set(
+                i + 1,
+                Extent {
+                    start: addr,
+                    len: next.len + 1,
+                },
+            );
+        }
+        self.splice(i, remove, &insert[..k]);
+        Ok(())
+    }
+
+    /// Unmaps the blocks of the file from the `nblocks`th one, calling `free` with each run of
+    /// blocks that were mapped. The holes at the end are dropped too.
+    pub fn truncate(&mut self, nblocks: u32, mut free: impl FnMut(Range<u32>)) {
+        let mut fbn = 0u32;
+        for i in 0..self.capacity() {
+            let e = self.get(i);
+            if e.len == 0 {
+                break;
+            }
+            let keep = cmp::min(nblocks.saturating_sub(fbn), e.len);
+            fbn = fbn.saturating_add(e.len);
+            if keep == e.len {
+                continue;
+            }
+            let blocks = e.blocks();
+            if !blocks.is_empty() {
+                free(blocks.start + keep..blocks.end);
+            }
+            let e = if keep == 0 {
+                Extent::default()
+            } else {
+                Extent {
+                    start: e.start,
+                    len: keep,
+                }
+            };
+            self.set(i, e);
+        }
+        let mut n = self.len();
+        while n > 0 && self.get(n - 1).is_hole() {
+            n -= 1;
+            self.set(n, Extent::default());
+        }
+    }
+}
@@ -0,0 +1,1111 @@
+//! The system call ABI of rv6.
+//!
+//! Everything that crosses the boundary between the kernel and user programs is defined here, so
+//! that both sides agree on the numbers and the layouts. Types copied in or out of user memory
+//! are `#[repr(C)]` and derive zerocopy traits.
+//!
+//! The on-disk layout of the file system is also defined here, since `mkfs` builds disk images on
+//! the host with the same types that the kernel reads them with. Likewise, `log` runs the log
+//! protocol for both the kernel and the crash checks of `mkfs`, and `extent` edits the extent maps
+//! of files for both the kernel and `mkfs`.
+//!
+//! The system call stubs of the user programs and `kernel/syscall.h` are generated from
+//! `syscall::SYSCALLS` at build time. The other C headers (`kernel/errno.h`, `kernel/sched.h`,
+//! `kernel/stat.h`, `kernel/fcntl.h`, `kernel/signal.h` and `kernel/fs.h`) mirror this crate, and
+//! must be kept in sync with it.
+
+#![no_std]
+
+pub mod extent;
+pub mod log;
+
+use core::mem;
+
+use bitflags::bitflags;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Declares the system call numbers, and `SYSCALLS`, the table of them.
+macro_rules! syscalls {
+    ($($name:ident = $number:literal,)*) => {
+        $(pub const $name: i32 = $number;)*
+
+        /// Every system call, by the name of its constant. The user-space stubs (`user/usys.S`)
+        /// and `kernel/syscall.h` are generated from it by `usys`.
+        pub const SYSCALLS: &[(&str, i32)] = &[$((stringify!($name), $name)),*];
+    };
+}
+
+/// System call numbers.
+pub mod syscall {
+    syscalls! {
+        SYS_FORK = 1,
+        SYS_EXIT = 2,
+        SYS_WAIT = 3,
+        SYS_PIPE = 4,
+        SYS_READ = 5,
+        SYS_KILL = 6,
+        SYS_EXEC = 7,
+        SYS_FSTAT = 8,
+        SYS_CHDIR = 9,
+        SYS_DUP = 10,
+        SYS_GETPID = 11,
+        SYS_SBRK = 12,
+        SYS_SLEEP = 13,
+        SYS_UPTIME = 14,
+        SYS_OPEN = 15,
+        SYS_WRITE = 16,
+        SYS_MKNOD = 17,
+        SYS_UNLINK = 18,
+        SYS_LINK = 19,
+        SYS_MKDIR = 20,
+        SYS_CLOSE = 21,
+        SYS_POWEROFF = 22,
+        SYS_SYNC_FILE_RANGE = 23,
+        SYS_IOSTAT = 24,
+        SYS_RGROUP_SET = 25,
+        SYS_RGROUP_JOIN = 26,
+        SYS_GETTID = 27,
+        SYS_TGKILL = 28,
+        SYS_FUTEX = 29,
+        SYS_SET_ROBUST_LIST = 30,
+        SYS_SPAWN = 31,
+        SYS_PIDFD_OPEN = 32,
+        SYS_FSRESIZE = 33,
+        SYS_SYSINFO = 34,
+        SYS_GETRANDOM = 35,
+        SYS_SYSCALL_LATENCY = 36,
+        SYS_MMAP = 37,
+        SYS_MUNMAP = 38,
+        SYS_BENCH = 39,
+        SYS_TASKDUMP = 40,
+        SYS_TRACE_READ = 41,
+        SYS_SIGACTION = 42,
+        SYS_SIGPROCMASK = 43,
+        SYS_SIGRETURN = 44,
+        SYS_FCOUNT = 45,
+        SYS_CLONE = 46,
+        SYS_IRQOFF = 47,
+        SYS_BRK = 48,
+        SYS_WAITPID = 49,
+        SYS_SETPGID = 50,
+        SYS_GETPGID = 51,
+        SYS_SETSID = 52,
+        SYS_GETPPID = 53,
+        SYS_GETUID = 54,
+        SYS_GETEUID = 55,
+        SYS_MADVISE = 56,
+        SYS_MEMINFO = 57,
+        SYS_NANOSLEEP = 58,
+        SYS_CLOCK_GETTIME = 59,
+        SYS_GETTIMEOFDAY = 60,
+        SYS_SETITIMER = 61,
+        SYS_ALARM = 62,
+        SYS_SCHED_SETAFFINITY = 63,
+        SYS_SCHED_GETAFFINITY = 64,
+        SYS_PAGEMAP = 65,
+        SYS_RENAME = 66,
+        SYS_LSEEK = 67,
+        SYS_DUP2 = 68,
+        SYS_DUP3 = 69,
+        SYS_PREAD = 70,
+        SYS_PWRITE = 71,
+        SYS_FTRUNCATE = 72,
+        SYS_REBOOT = 73,
+        SYS_FCNTL = 74,
+        SYS_UTIMES = 75,
+        SYS_FUTIMENS = 76,
+        SYS_CHMOD = 77,
+        SYS_CHOWN = 78,
+        SYS_SETUID = 79,
+        SYS_SETGID = 80,
+        SYS_GETGID = 81,
+        SYS_FSYNC = 82,
+        SYS_FSWATCH = 83,
+        SYS_GETDENTS_PLUS = 84,
+        SYS_FADVISE = 85,
+        SYS_MOUNT = 86,
+        SYS_UMOUNT = 87,
+    }
+}
+
+/// System call numbers of Linux on riscv64, which the programs linked against a Linux libc, e.g.,
+/// a static musl, use. Only the calls that rv6 implements are listed. See `exec::Abi` of the
+/// kernel for how such programs are told apart.
+pub mod linux {
+    pub const SYS_IOCTL: i32 = 29;
+    pub const SYS_CLOSE: i32 = 57;
+    pub const SYS_LSEEK: i32 = 62;
+    pub const SYS_READ: i32 = 63;
+    pub const SYS_WRITE: i32 = 64;
+    pub const SYS_READV: i32 = 65;
+    pub const SYS_WRITEV: i32 = 66;
+    pub const SYS_PREAD64: i32 = 67;
+    pub const SYS_PWRITE64: i32 = 68;
+    pub const SYS_FSYNC: i32 = 82;
+    pub const SYS_EXIT: i32 = 93;
+    pub const SYS_EXIT_GROUP: i32 = 94;
+    pub const SYS_SET_TID_ADDRESS: i32 = 96;
+    pub const SYS_FUTEX: i32 = 98;
+    pub const SYS_SET_ROBUST_LIST: i32 = 99;
+    pub const SYS_NANOSLEEP: i32 = 101;
+    pub const SYS_CLOCK_GETTIME: i32 = 113;
+    pub const SYS_KILL: i32 = 129;
+    pub const SYS_TGKILL: i32 = 131;
+    pub const SYS_SETGID: i32 = 144;
+    pub const SYS_SETUID: i32 = 146;
+    pub const SYS_GETPID: i32 = 172;
+    pub const SYS_GETPPID: i32 = 173;
+    pub const SYS_GETUID: i32 = 174;
+    pub const SYS_GETEUID: i32 = 175;
+    pub const SYS_GETGID: i32 = 176;
+    pub const SYS_GETTID: i32 = 178;
+    pub const SYS_BRK: i32 = 214;
+    pub const SYS_MUNMAP: i32 = 215;
+    pub const SYS_MMAP: i32 = 222;
+    pub const SYS_FADVISE64: i32 = 223;
+    pub const SYS_MADVISE: i32 = 233;
+}
+
+/// Error numbers, which are the same as those of Linux.
+///
+/// A system call returns a value in `a0`. A value in [-`MAX_ERRNO`, -1], read as a signed integer,
+/// is the negation of an `Errno`, and any other value is the result of a successful call.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(i32)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Permission denied
+    EACCES = 13,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// Cross-device link
+    EXDEV = 18,
+    /// No such device
+    ENODEV = 19,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// File table overflow
+    ENFILE = 23,
+    /// Too many open files
+    EMFILE = 24,
+    /// Not a typewriter
+    ENOTTY = 25,
+    /// File too large
+    EFBIG = 27,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Illegal seek
+    ESPIPE = 29,
+    /// Read-only file system
+    EROFS = 30,
+    /// Broken pipe
+    EPIPE = 32,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+}
+
+/// Largest error number that can be returned by a system call.
+pub const MAX_ERRNO: usize = 4095;
+
+impl Errno {
+    /// Returns the value of `a0` that reports this error.
+    pub const fn into_ret(self) -> usize {
+        (-(self as isize)) as usize
+    }
+}
+
+bitflags! {
+    /// Flags of `open`.
+    pub struct FcntlFlags: i32 {
+        const O_RDONLY = 0;
+        const O_WRONLY = 0x1;
+        const O_RDWR = 0x2;
+        const O_CREATE = 0x200;
+        const O_TRUNC = 0x400;
+        /// Every write appends to the end of file.
+        const O_APPEND = 0x800;
+        /// Reads from a pipe or a device return `EAGAIN` instead of waiting for input.
+        const O_NONBLOCK = 0x1000;
+        /// The descriptor is closed by `exec`.
+        const O_CLOEXEC = 0x2000;
+        /// Reads and writes of a regular file move data between the disk and the user buffer
+        /// directly, bypassing the buffer cache. The buffer, the offset, and the length must be
+        /// multiples of the block size.
+        const O_DIRECT = 0x4000;
+    }
+}
+
+/// Commands of `fcntl`.
+/// Returns the descriptor flags.
+pub const F_GETFD: i32 = 1;
+/// Sets the descriptor flags.
+pub const F_SETFD: i32 = 2;
+/// Returns the file status flags.
+pub const F_GETFL: i32 = 3;
+/// Sets the file status flags. Only `O_NONBLOCK` can be changed.
+pub const F_SETFL: i32 = 4;
+
+/// Descriptor flag of `fcntl`: the descriptor is closed by `exec`.
+pub const FD_CLOEXEC: i32 = 1;
+
+/// Origins of `lseek`.
+/// The offset is absolute.
+pub const SEEK_SET: i32 = 0;
+/// The offset is relative to the current offset.
+pub const SEEK_CUR: i32 = 1;
+/// The offset is relative to the end of file.
+pub const SEEK_END: i32 = 2;
+
+/// Advice of `fadvise`.
+/// No special treatment.
+pub const POSIX_FADV_NORMAL: i32 = 0;
+/// The file will be read at random offsets, so that nothing is read ahead.
+pub const POSIX_FADV_RANDOM: i32 = 1;
+/// The file will be read sequentially, so that twice as much is read ahead.
+pub const POSIX_FADV_SEQUENTIAL: i32 = 2;
+/// The range will be read soon, so that it is read into the buffer cache.
+pub const POSIX_FADV_WILLNEED: i32 = 3;
+/// The range will not be read soon, so that it is dropped from the caches.
+pub const POSIX_FADV_DONTNEED: i32 = 4;
+
+bitflags! {
+    /// Flags of `sync_file_range`.
+    pub struct SyncFileRangeFlags: i32 {
+        /// Wait for write-out of the range that was already in progress.
+        const WAIT_BEFORE = 0x1;
+        /// Start write-out of the dirty blocks in the range.
+        const WRITE = 0x2;
+        /// Wait for the write-out of the range to finish.
+        const WAIT_AFTER = 0x4;
+    }
+}
+
+bitflags! {
+    /// Protection of the pages mapped by `mmap`. The empty set is `PROT_NONE`.
+    pub struct ProtFlags: i32 {
+        const PROT_READ = 0x1;
+        const PROT_WRITE = 0x2;
+        const PROT_EXEC = 0x4;
+    }
+}
+
+bitflags! {
+    /// Flags of `mmap`.
+    pub struct MapFlags: i32 {
+        /// Changes are seen by the other mappings of the file, and written back to it.
+        const MAP_SHARED = 0x01;
+        /// Changes are private to the process.
+        const MAP_PRIVATE = 0x02;
+        /// Map exactly at the given address, replacing any mapping there.
+        const MAP_FIXED = 0x10;
+        /// The mapping is not backed by a file, and is zero-filled.
+        const MAP_ANONYMOUS = 0x20;
+    }
+}
+
+bitflags! {
+    /// Options of `waitpid`.
+    pub struct WaitFlags: i32 {
+        /// Return 0 instead of waiting if no child has exited.
+        const WNOHANG = 0x1;
+    }
+}
+
+bitflags! {
+    /// Flags of `clone`.
+    pub struct CloneFlags: i32 {
+        /// Set the thread pointer (`tp`) of the new thread to the given TLS block.
+        const SETTLS = 0x80000;
+    }
+}
+
+/// Advice of `madvise`.
+/// No special treatment.
+pub const MADV_NORMAL: i32 = 0;
+/// The pages will be accessed soon.
+pub const MADV_WILLNEED: i32 = 3;
+/// The pages will not be accessed soon, so that they can be freed.
+pub const MADV_DONTNEED: i32 = 4;
+/// The contents of the anonymous pages are no longer needed, so that they can be freed.
+pub const MADV_FREE: i32 = 8;
+
+/// Operations of `futex`.
+pub const FUTEX_WAIT: i32 = 0;
+pub const FUTEX_WAKE: i32 = 1;
+
+/// Bits of a robust futex word. The rest of the word holds the thread ID of the owner.
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+/// Head of a robust list, registered by `set_robust_list`.
+/// The list is a circular singly linked list of user-space locks. Each entry is a pointer to the
+/// next entry, and its futex word lies `futex_offset` bytes away from it.
+#[repr(C)]
+#[derive(Default, Clone, Copy, AsBytes, FromBytes)]
+pub struct RobustListHead {
+    /// The first entry, or the address of the head itself if the list is empty.
+    pub next: usize,
+
+    /// Offset from an entry to its futex word.
+    pub futex_offset: isize,
+
+    /// The entry that the thread is about to acquire or release, or 0.
+    pub list_op_pending: usize,
+}
+
+/// Number of signals. Signals are numbered from 1 to `NSIG - 1`, and a set of signals is a `u32`
+/// with bit `sig` set for each signal `sig` in it.
+pub const NSIG: usize = 32;
+
+/// Signal numbers.
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+
+/// Values of `SigAction::handler` other than the address of a handler.
+/// Takes the default action, i.e., ignores `SIGCHLD` and terminates the process otherwise.
+pub const SIG_DFL: usize = 0;
+/// Ignores the signal.
+pub const SIG_IGN: usize = 1;
+
+/// Flags of `SigAction`.
+/// Does not block the signal while its handler runs.
+pub const SA_NODEFER: u32 = 0x4000_0000;
+/// Restores the default action once the handler is called.
+pub const SA_RESETHAND: u32 = 0x8000_0000;
+
+/// Operations of `sigprocmask`.
+pub const SIG_BLOCK: i32 = 0;
+pub const SIG_UNBLOCK: i32 = 1;
+pub const SIG_SETMASK: i32 = 2;
+
+/// Action taken on a signal, set by `sigaction`.
+#[repr(C)]
+#[derive(Default, Clone, Copy, AsBytes, FromBytes)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN`, or the address of a handler, called with the signal number.
+    pub handler: usize,
+
+    /// Signals blocked while the handler runs, in addition to the signal itself.
+    pub mask: u32,
+
+    /// `SA_*` flags.
+    pub flags: u32,
+
+    /// Address the handler returns to, which must call `sigreturn`.
+    pub restorer: usize,
+}
+
+/// Types of the entries of the auxiliary vector, which follows the NULL terminating argv[] on the
+/// initial user stack. Each entry is a pair of a type and a value, and `AT_NULL` ends the vector.
+pub const AT_NULL: usize = 0;
+/// The value is the address of the program headers of the program.
+pub const AT_PHDR: usize = 3;
+/// The value is the size of a program header.
+pub const AT_PHENT: usize = 4;
+/// The value is the number of the program headers.
+pub const AT_PHNUM: usize = 5;
+/// The value is the size of a page.
+pub const AT_PAGESZ: usize = 6;
+/// The value is the entry point of the program.
+pub const AT_ENTRY: usize = 9;
+/// The value is the address of 16 random bytes on the stack.
+pub const AT_RANDOM: usize = 25;
+
+/// Values of `Stat::typ`.
+pub const T_DIR: u16 = 1;
+pub const T_FILE: u16 = 2;
+pub const T_DEVICE: u16 = 3;
+
+/// Permission bits of `Stat::mode`, for the owner, the group, and the others.
+pub const S_IRUSR: u32 = 0o400;
+pub const S_IWUSR: u32 = 0o200;
+pub const S_IXUSR: u32 = 0o100;
+pub const S_IRGRP: u32 = 0o040;
+pub const S_IWGRP: u32 = 0o020;
+pub const S_IXGRP: u32 = 0o010;
+pub const S_IROTH: u32 = 0o004;
+pub const S_IWOTH: u32 = 0o002;
+pub const S_IXOTH: u32 = 0o001;
+/// All the permission bits, which `chmod` takes.
+pub const S_IALLUGO: u32 = 0o777;
+
+/// File status returned by `fstat`.
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Stat {
+    /// File system's disk device
+    pub dev: i32,
+
+    /// Inode number
+    pub ino: u32,
+
+    /// Type of file
+    pub typ: u16,
+
+    /// Number of links to file
+    pub nlink: i16,
+
+    /// Generation number of the inode. An inode number that is reused for a new file gets a new
+    /// generation number.
+    pub gen: u32,
+
+    /// Size of file in bytes
+    pub size: usize,
+
+    /// Time of the last access, in seconds since the epoch
+    pub atime: u64,
+
+    /// Time of the last modification of the content, in seconds since the epoch
+    pub mtime: u64,
+
+    /// Time of the last change of the inode, in seconds since the epoch
+    pub ctime: u64,
+
+    /// Permission bits, e.g., `S_IRUSR`
+    pub mode: u32,
+
+    /// User ID of the owner
+    pub uid: u16,
+
+    /// Group ID of the owner
+    pub gid: u16,
+}
+
+/// Block size.
+pub const BSIZE: usize = 1024;
+
+/// Root i-number.
+pub const ROOTINO: u32 = 1;
+
+/// Must be the first field of the super block.
+pub const FSMAGIC: u32 = 0x10203040;
+
+/// Bit of `Superblock::flags`, set if the metadata of the file system is checksummed: the super
+/// block, the log header and every inode carry a CRC-32 of their contents.
+pub const FS_CHECKSUM: u32 = 0x1;
+
+/// Bit of `Superblock::flags`, set if the inodes map the blocks of their files by extents (see
+/// `extent`) instead of by block addresses.
+pub const FS_EXTENTS: u32 = 0x2;
+
+/// Bit of `Superblock::flags`, set if the kernel should discard the blocks that the file system
+/// frees, e.g., so that a sparse image stays sparse.
+pub const FS_DISCARD: u32 = 0x4;
+
+/// Number of direct block addresses in an inode.
+pub const NDIRECT: usize = 11;
+
+/// Number of block addresses in an indirect block.
+pub const NINDIRECT: usize = BSIZE / mem::size_of::<u32>();
+
+/// Number of block addresses reachable through a double-indirect block.
+pub const NDINDIRECT: usize = NINDIRECT * NINDIRECT;
+
+/// Maximum size of a file in blocks.
+pub const MAXFILE: usize = NDIRECT + NINDIRECT + NDINDIRECT;
+
+/// Disk layout:
+/// [ boot block | super block | log | inode blocks |
+///                                          free bit map | data blocks]
+///
+/// mkfs computes the super block and builds an initial file system. The
+/// super block describes the disk layout:
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Superblock {
+    /// Must be FSMAGIC
+    pub magic: u32,
+
+    /// Size of file system image (blocks)
+    pub size: u32,
+
+    /// Number of data blocks
+    pub nblocks: u32,
+
+    /// Number of inodes
+    pub ninodes: u32,
+
+    /// Number of log blocks
+    pub nlog: u32,
+
+    /// Block number of first log block
+    pub logstart: u32,
+
+    /// Block number of first inode block
+    pub inodestart: u32,
+
+    /// Block number of first free map block
+    pub bmapstart: u32,
+
+    /// Optional features, e.g., `FS_CHECKSUM`. Images that predate this field have zero here.
+    pub flags: u32,
+
+    /// CRC-32 of the fields above, if `FS_CHECKSUM` is set.
+    pub checksum: u32,
+}
+
+impl Superblock {
+    pub fn is_valid(&self) -> bool {
+        self.magic == FSMAGIC && (!self.has_checksums() || self.checksum == self.compute_checksum())
+    }
+
+    pub const fn has_checksums(&self) -> bool {
+        self.flags & FS_CHECKSUM != 0
+    }
+
+    pub const fn has_extents(&self) -> bool {
+        self.flags & FS_EXTENTS != 0
+    }
+
+    pub const fn has_discard(&self) -> bool {
+        self.flags & FS_DISCARD != 0
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        crc32(&self.as_bytes()[..mem::size_of::<Self>() - mem::size_of::<u32>()])
+    }
+
+    /// Updates `checksum` to match the other fields, if `FS_CHECKSUM` is set.
+    pub fn update_checksum(&mut self) {
+        if self.has_checksums() {
+            self.checksum = self.compute_checksum();
+        }
+    }
+
+    /// Block containing inode i
+    pub const fn iblock(self, i: u32) -> u32 {
+        i / IPB as u32 + self.inodestart
+    }
+
+    /// Block of free map containing bit for block b
+    pub const fn bblock(self, b: u32) -> u32 {
+        b / BPB as u32 + self.bmapstart
+    }
+}
+
+/// On-disk inode structure.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Dinode {
+    /// File type, one of `T_DIR`, `T_FILE` and `T_DEVICE`, or 0 if the inode is free.
+    pub typ: u16,
+
+    /// Major device number (T_DEVICE only)
+    pub major: u16,
+
+    /// Minor device number (T_DEVICE only)
+    pub minor: u16,
+
+    /// Number of links to inode in file system
+    pub nlink: i16,
+
+    /// Size of file (bytes)
+    pub size: u32,
+
+    /// Direct data block addresses, followed by the addresses of the indirect block and the
+    /// double-indirect block. If the file system has `FS_EXTENTS` set, the extents of the file
+    /// instead, followed by the address of the extent block (see `extent`).
+    pub addrs: [u32; NDIRECT + 2],
+
+    /// Generation number, incremented whenever the inode is allocated.
+    pub gen: u32,
+
+    /// Time of the last access, in seconds since the epoch.
+    pub atime: u32,
+
+    /// Time of the last modification of the content, in seconds since the epoch.
+    pub mtime: u32,
+
+    /// Time of the last change of the inode, in seconds since the epoch.
+    pub ctime: u32,
+
+    /// Permission bits, e.g., `S_IRUSR`.
+    pub mode: u32,
+
+    /// User ID of the owner.
+    pub uid: u16,
+
+    /// Group ID of the owner.
+    pub gid: u16,
+
+    /// CRC-32 of the fields above, if the file system has `FS_CHECKSUM` set.
+    pub checksum: u32,
+}
+
+impl Dinode {
+    fn compute_checksum(&self) -> u32 {
+        crc32(&self.as_bytes()[..mem::size_of::<Self>() - mem::size_of::<u32>()])
+    }
+
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum == self.compute_checksum()
+    }
+
+    pub fn update_checksum(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Returns Err(EIO) if the inode has an unknown type, or does not match its checksum while
+    /// `checksums` is set. The kernel reports such an inode to user space as is.
+    pub fn check(&self, checksums: bool) -> Result<(), Errno> {
+        match self.typ {
+            0 | T_DIR | T_FILE | T_DEVICE if !checksums || self.is_checksum_valid() => Ok(()),
+            _ => Err(Errno::EIO),
+        }
+    }
+}
+
+/// Inodes per block.
+pub const IPB: usize = BSIZE / mem::size_of::<Dinode>();
+
+/// Bitmap bits per block
+pub const BPB: usize = BSIZE * 8;
+
+/// Returns the checksum of a log header that holds `blocks`, if the file system has `FS_CHECKSUM`
+/// set.
+///
+/// The log header consists of the number of logged blocks, the checksum, and the block numbers of
+/// the logged blocks. The checksum covers the number and the block numbers in use.
+pub fn log_header_checksum(blocks: &[u32]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update((blocks.len() as u32).as_bytes());
+    crc.update(blocks.as_bytes());
+    crc.finish()
+}
+
+/// Lookup table of `Crc32`, indexed by the low byte of the remainder.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 of IEEE 802.3, computed incrementally.
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.0 = CRC32_TABLE[((self.0 ^ *b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub const fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+/// Returns the CRC-32 of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Maximum length of a file name in a directory entry.
+pub const DIRSIZ: usize = 14;
+
+/// A directory entry. A directory is a file containing a sequence of them, and user programs read
+/// them directly with `read`.
+#[repr(C)]
+#[derive(Default, AsBytes, FromBytes)]
+pub struct Dirent {
+    /// Inode number, or 0 if the entry is free.
+    pub inum: u16,
+
+    /// File name, terminated by NUL if shorter than `DIRSIZ`.
+    pub name: [u8; DIRSIZ],
+}
+
+impl Dirent {
+    /// Returns the name without the NUL terminator.
+    pub fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
+        &self.name[..len]
+    }
+
+    /// Fills in the name. If `name` is shorter than `DIRSIZ`, NUL character is appended as
+    /// terminator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is longer than `DIRSIZ`.
+    pub fn set_name(&mut self, name: &[u8]) {
+        if name.len() == DIRSIZ {
+            self.name.copy_from_slice(name);
+        } else {
+            self.name[..name.len()].copy_from_slice(name);
+            self.name[name.len()] = 0;
+        }
+    }
+}
+
+/// A directory entry with the status of its inode, read by `getdents_plus`.
+#[repr(C)]
+#[derive(AsBytes, FromBytes)]
+pub struct DirentPlus {
+    pub stat: Stat,
+    pub dirent: Dirent,
+}
+
+bitflags! {
+    /// Events of `fswatch`.
+    pub struct FswMask: u16 {
+        /// An entry was added to the watched directory.
+        const FSW_CREATE = 0x1;
+        /// An entry was removed from the watched directory.
+        const FSW_UNLINK = 0x2;
+        /// The watched file was written.
+        const FSW_WRITE = 0x4;
+        /// Events were dropped since the reader fell behind. It is always reported.
+        const FSW_OVERFLOW = 0x8;
+    }
+}
+
+/// An event read from a descriptor returned by `fswatch`.
+#[repr(C)]
+#[derive(Default, Clone, Copy, AsBytes, FromBytes)]
+pub struct FsEvent {
+    /// The event, one of `FswMask`.
+    pub mask: u16,
+
+    /// Inode number of the entry created or removed, or of the file written.
+    pub inum: u16,
+
+    /// Name of the entry created or removed, terminated by NUL if shorter than `DIRSIZ`, or empty
+    /// for a write.
+    pub name: [u8; DIRSIZ],
+}
+
+/// Number of buckets of a latency histogram returned by `syscall_latency`. Bucket `i` counts the
+/// calls that took less than 2^i timer cycles but not less than 2^(i-1), and the last bucket also
+/// counts the longer ones.
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// Operations of `bench`.
+/// Dispatches a no-op system call.
+pub const BENCH_NULL: i32 = 0;
+/// Yields the CPU.
+pub const BENCH_YIELD: i32 = 1;
+/// Writes a byte to a pipe, and reads a byte back from another.
+pub const BENCH_PIPE: i32 = 2;
+
+/// Events of `TraceRecord`, with the meaning of their arguments.
+/// A CPU switched to a process: [pid, 0].
+pub const TRACE_SCHED_SWITCH: u32 = 1;
+/// A process entered a system call: [syscall number, pid].
+pub const TRACE_SYSCALL_ENTER: u32 = 2;
+/// A process returned from a system call: [syscall number, return value].
+pub const TRACE_SYSCALL_EXIT: u32 = 3;
+/// A request was submitted to the disk: [block number, 1 if a write, or 0].
+pub const TRACE_DISK_SUBMIT: u32 = 4;
+/// The disk completed a request: [block number, 0].
+pub const TRACE_DISK_COMPLETE: u32 = 5;
+/// A CPU found a spinlock held by another CPU: [address of the lock, 0].
+pub const TRACE_LOCK_CONTEND: u32 = 6;
+
+/// A record of a tracepoint, returned by `trace_read`.
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+pub struct TraceRecord {
+    /// Value of the `time` CSR when the tracepoint was hit.
+    pub time: u64,
+
+    /// One of the `TRACE_*` events.
+    pub event: u32,
+
+    /// The CPU that hit the tracepoint.
+    pub cpu: u32,
+
+    /// Arguments of the event.
+    pub args: [u64; 2],
+}
+
+/// Length of `FcountRecord::name`, including the terminating NUL.
+pub const FCOUNT_NAME_LEN: usize = 56;
+
+/// A function entry counter, returned by `fcount`.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct FcountRecord {
+    /// Number of calls to the function.
+    pub count: u64,
+
+    /// Path of the function in the kernel, NUL-terminated and truncated if too long.
+    pub name: [u8; FCOUNT_NAME_LEN],
+}
+
+/// Length of `IrqoffRecord::site`, including the terminating NUL.
+pub const IRQOFF_SITE_LEN: usize = 48;
+
+/// The longest interrupts-disabled window of a CPU, returned by `irqoff`.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct IrqoffRecord {
+    /// Length of the window in timer cycles, or 0 if none has been recorded.
+    pub cycles: u64,
+
+    /// Line of `site` if it is a source file, or 0 if it is the name of a spin lock.
+    pub line: u64,
+
+    /// The spin lock or the source file that disabled interrupts, NUL-terminated. A long path
+    /// is truncated at the front.
+    pub site: [u8; IRQOFF_SITE_LEN],
+}
+
+/// Owners of the kernel pages, which index the arrays of `Meminfo`.
+/// User memory.
+pub const PAGE_OWNER_USER: usize = 0;
+/// Page-table pages.
+pub const PAGE_OWNER_PAGETABLE: usize = 1;
+/// Kernel stacks.
+pub const PAGE_OWNER_KSTACK: usize = 2;
+/// Trap frames.
+pub const PAGE_OWNER_TRAPFRAME: usize = 3;
+/// Pipe buffers.
+pub const PAGE_OWNER_PIPE: usize = 4;
+/// Pages of the files mapped with MAP_SHARED.
+pub const PAGE_OWNER_PAGECACHE: usize = 5;
+/// Arguments of `exec`.
+pub const PAGE_OWNER_EXEC: usize = 6;
+pub const NPAGEOWNER: usize = 7;
+
+/// Usage of the physical pages returned by `meminfo`.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct Meminfo {
+    /// Number of free pages.
+    pub freepages: usize,
+
+    /// Number of the pages of each owner.
+    pub owned: [usize; NPAGEOWNER],
+
+    /// Number of the pages each owner may hold, or `usize::MAX` if it is not bounded.
+    pub budgets: [usize; NPAGEOWNER],
+}
+
+/// Backings of the pages of a `PagemapEntry`.
+/// The program and its heap below the program break, private to the memory.
+pub const PAGEMAP_HEAP: u32 = 0;
+/// Zero-filled private pages mapped by `mmap`.
+pub const PAGEMAP_ANONYMOUS: u32 = 1;
+/// Private copies of the pages of a file mapped by `mmap`.
+pub const PAGEMAP_FILE: u32 = 2;
+/// Pages of a file in the page cache, mapped by `mmap` with `MAP_SHARED`.
+pub const PAGEMAP_SHARED: u32 = 3;
+/// Pages shared copy-on-write with another memory. Not reported yet, as fork copies every page.
+pub const PAGEMAP_COW: u32 = 4;
+/// The trap frames of the threads, which only the kernel accesses.
+pub const PAGEMAP_TRAPFRAME: u32 = 5;
+
+/// A range of resident pages mapped alike, returned by `pagemap`.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct PagemapEntry {
+    /// Address of the first page.
+    pub start: u64,
+
+    /// Address past the last page.
+    pub end: u64,
+
+    /// Protection of the pages for the user, as `ProtFlags` bits.
+    pub prot: i32,
+
+    /// Backing of the pages, one of the `PAGEMAP_*` constants.
+    pub kind: u32,
+}
+
+/// Statistics of the page table of a memory, returned by `pagemap`.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct PagemapStat {
+    /// Number of the page-table pages.
+    pub page_table_pages: u64,
+
+    /// Number of the pages mapped in the heap, the regions mapped by `mmap`, and the trap frames.
+    pub resident_pages: u64,
+
+    /// Number of the pages in those regions, mapped or not.
+    pub virtual_pages: u64,
+}
+
+/// Clocks of `clock_gettime`.
+/// Wall-clock time.
+pub const CLOCK_REALTIME: i32 = 0;
+/// Time since the boot, which never jumps.
+pub const CLOCK_MONOTONIC: i32 = 1;
+
+/// Values of `Timespec::tv_nsec` taken by `utimes` and `futimens`.
+/// Set the time to the current time.
+pub const UTIME_NOW: i64 = (1 << 30) - 1;
+/// Leave the time unchanged.
+pub const UTIME_OMIT: i64 = (1 << 30) - 2;
+
+/// Nanoseconds per second, which `Timespec::tv_nsec` is less than.
+pub const NSEC_PER_SEC: i64 = 1_000_000_000;
+
+/// A time or a duration, taken by `nanosleep` and returned by `clock_gettime`.
+#[repr(C)]
+#[derive(Default, Clone, Copy, AsBytes, FromBytes)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+/// A buffer of `readv` and `writev`.
+#[repr(C)]
+#[derive(Default, Clone, Copy, AsBytes, FromBytes)]
+pub struct Iovec {
+    pub iov_base: usize,
+    pub iov_len: usize,
+}
+
+/// Maximum number of buffers of a `readv` or `writev`.
+pub const IOV_MAX: usize = 1024;
+
+/// Wall-clock time returned by `gettimeofday`.
+#[repr(C)]
+#[derive(Default, Clone, Copy, AsBytes, FromBytes)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+/// The interval timer of `setitimer` that counts real time and sends `SIGALRM`.
+pub const ITIMER_REAL: i32 = 0;
+
+/// Setting of an interval timer, taken and returned by `setitimer`.
+#[repr(C)]
+#[derive(Default, Clone, Copy, AsBytes, FromBytes)]
+pub struct Itimerval {
+    /// Time between the later expirations, or 0 if the timer expires only once.
+    pub it_interval: Timeval,
+
+    /// Time until the next expiration, or 0 to disarm the timer.
+    pub it_value: Timeval,
+}
+
+/// Number of fractional bits of the load averages in `Sysinfo`.
+pub const FSHIFT: usize = 11;
+
+/// System statistics returned by `sysinfo`.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct Sysinfo {
+    /// Number of timer ticks since boot.
+    pub uptime: usize,
+
+    /// 1, 5 and 15 minute load averages, in fixed point with `FSHIFT` fractional bits.
+    pub loads: [usize; 3],
+
+    /// Number of free pages.
+    pub freepages: usize,
+
+    /// Number of processes.
+    pub procs: usize,
+}
+
+/// I/O statistics returned by `iostat`.
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub struct IoStat {
+    /// Number of read operations.
+    pub read_ops: usize,
+
+    /// Number of write operations.
+    pub write_ops: usize,
+
+    /// Number of bytes read.
+    pub read_bytes: usize,
+
+    /// Number of bytes written.
+    pub write_bytes: usize,
+
+    /// Time spent waiting for disk requests to complete, in timer cycles.
+    pub wait_time: usize,
+}
+
+impl IoStat {
+    pub const fn new() -> Self {
+        Self {
+            read_ops: 0,
+            write_ops: 0,
+            read_bytes: 0,
+            write_bytes: 0,
+            wait_time: 0,
+        }
+    }
+
+    /// Charges a read or write of `bytes` bytes that waited `wait_time` cycles for the disk.
+    pub fn charge(&mut self, write: bool, bytes: usize, wait_time: usize) {
+        if write {
+            self.write_ops += 1;
+            self.write_bytes += bytes;
+        } else {
+            self.read_ops += 1;
+            self.read_bytes += bytes;
+        }
+        self.wait_time += wait_time;
+    }
+}
@@ -0,0 +1,159 @@
+//! The on-disk log of the file system.
+//!
+//! The log takes `Superblock::nlog` blocks from `Superblock::logstart`: a header block, and then
+//! the logged blocks in order. The header holds the number of logged blocks, the checksum if the
+//! file system has `FS_CHECKSUM` set, and the block numbers of the logged blocks. Writing a header
+//! with logged blocks commits them, and writing an empty one ends the transaction.
+//!
+//! The kernel commits and recovers the log with `commit` and `recover`, and so do the crash checks
+//! of `mkfs`, each over its own `LogDisk`, so that `mkfs` checks the protocol that the kernel runs.
+
+use core::mem;
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{log_header_checksum, Superblock, BSIZE};
+
+/// Offset of the block numbers in the header, which follow the number of blocks and the checksum.
+const BLOCKS_OFFSET: usize = 2 * mem::size_of::<u32>();
+
+/// Maximum number of block numbers that a header holds.
+pub const MAXLOGGED: usize = (BSIZE - BLOCKS_OFFSET) / mem::size_of::<u32>();
+
+/// The disk under a log, seen through the block cache of its user, if any.
+pub trait LogDisk {
+    /// Copies the contents of block `from` to block `to`, and writes `to` to the disk.
+    fn copy(&mut self, from: u32, to: u32);
+
+    /// Calls `f` with the contents of block `bn`.
+    fn read(&mut self, bn: u32, f: impl FnOnce(&[u8]));
+
+    /// Calls `f` to modify the contents of block `bn`, and writes it to the disk.
+    fn update(&mut self, bn: u32, f: impl FnOnce(&mut [u8]));
+
+    /// Waits until the writes issued so far are durable.
+    fn flush(&mut self);
+}
+
+/// Why a log cannot be recovered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogError {
+    /// The header does not match its checksum.
+    BadChecksum,
+    /// The header holds more blocks than the log does.
+    TooLong { n: u32 },
+    /// A logged block lies outside the file system.
+    BadBlock { bn: u32 },
+}
+
+/// Writes a header holding `blocks` to `header`, with its checksum if `checksums` is true.
+///
+/// # Panics
+///
+/// Panics if `blocks` does not fit in a header.
+pub fn write_header(header: &mut [u8], blocks: &[u32], checksums: bool) {
+    assert!(blocks.len() <= MAXLOGGED, "write_header: too many blocks");
+    (blocks.len() as u32)
+        .write_to_prefix(&mut header[..])
+        .unwrap();
+    if checksums {
+        log_header_checksum(blocks)
+            .write_to_prefix(&mut header[mem::size_of::<u32>()..])
+            .unwrap();
+    }
+    blocks
+        .write_to_prefix(&mut header[BLOCKS_OFFSET..])
+        .unwrap();
+}
+
+/// Reads the header in `header` into `blocks`, and returns the number of the logged blocks, after
+/// checking them against the file system of `sb`.
+pub fn read_header(header: &[u8], sb: &Superblock, blocks: &mut [u32]) -> Result<usize, LogError> {
+    let n = u32::read_from_prefix(header).unwrap();
+    if n as usize > blocks.len() || n as usize > MAXLOGGED || n >= sb.nlog {
+        return Err(LogError::TooLong { n });
+    }
+    let blocks = &mut blocks[..n as usize];
+    blocks.as_bytes_mut().copy_from_slice(
+        &header[BLOCKS_OFFSET..BLOCKS_OFFSET + n as usize * mem::size_of::<u32>()],
+    );
+
+    // A header is written in a single block, so a bad checksum means that the log is corrupted,
+    // and installing it would spread the corruption.
+    let checksum = u32::read_from_prefix(&header[mem::size_of::<u32>()..]).unwrap();
+    if sb.has_checksums() && checksum != log_header_checksum(blocks) {
+        return Err(LogError::BadChecksum);
+    }
+    if let Some(bn) = blocks.iter().find(|bn| **bn >= sb.size) {
+        return Err(LogError::BadBlock { bn: *bn });
+    }
+    Ok(blocks.len())
+}
+
+/// Writes a header holding `blocks` to the log of `sb`.
+fn write_head<D: LogDisk>(disk: &mut D, sb: &Superblock, blocks: &[u32]) {
+    disk.update(sb.logstart, |header| {
+        write_header(header, blocks, sb.has_checksums())
+    });
+}
+
+/// Copies the logged `blocks` from the log of `sb` to their home locations.
+fn install_trans<D: LogDisk>(disk: &mut D, sb: &Superblock, blocks: &[u32]) {
+    for (tail, bn) in blocks.iter().enumerate() {
+        disk.copy(sb.logstart + tail as u32 + 1, *bn);
+    }
+}
+
+/// Writes `blocks` to the disk in a single transaction through the log of `sb`. The new contents
+/// of `blocks` are read through `disk`, and may not be on the disk yet.
+///
+/// # Panics
+///
+/// Panics if the transaction does not fit in the log.
+pub fn commit<D: LogDisk>(disk: &mut D, sb: &Superblock, blocks: &[u32]) {
+    assert!(
+        blocks.len() < sb.nlog as usize && blocks.len() <= MAXLOGGED,
+        "commit: too big a transaction"
+    );
+    if blocks.is_empty() {
+        return;
+    }
+
+    // Write modified blocks to the log.
+    for (tail, bn) in blocks.iter().enumerate() {
+        disk.copy(*bn, sb.logstart + tail as u32 + 1);
+    }
+    disk.flush();
+
+    // Write header to disk -- the real commit.
+    write_head(disk, sb, blocks);
+    disk.flush();
+
+    // Now install writes to home locations.
+    install_trans(disk, sb, blocks);
+    disk.flush();
+
+    // Erase the transaction from the log.
+    write_head(disk, sb, &[]);
+    disk.flush();
+}
+
+/// Installs the committed transaction in the log of `sb`, if any, and clears the log. Reads at
+/// most `N` logged blocks.
+pub fn recover<D: LogDisk, const N: usize>(disk: &mut D, sb: &Superblock) -> Result<(), LogError> {
+    let mut blocks = [0; N];
+    let mut res = Ok(0);
+    disk.read(sb.logstart, |header| {
+        res = read_header(header, sb, &mut blocks)
+    });
+    let blocks = &blocks[..res?];
+
+    // If committed, copy from log to disk.
+    install_trans(disk, sb, blocks);
+    disk.flush();
+
+    // Clear the log.
+    write_head(disk, sb, &[]);
+    disk.flush();
+    Ok(())
+}
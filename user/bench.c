@@ -0,0 +1,139 @@
+// Microbenchmarks of the system call path, the scheduler and pipes,
+// timed by the kernel with bench(), and of reading a file as cat
+// does.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/time.h"
+#include "kernel/fcntl.h"
+#include "kernel/riscv.h"
+#include "user/user.h"
+
+#define N 1000
+
+// Pages of the file that catfile() reads.
+#define NPAGES 64
+
+static char page[2*PGSIZE] __attribute__((aligned(PGSIZE)));
+
+static void
+report(char *name, long cycles)
+{
+  if(cycles < 0){
+    fprintf(2, "bench: %s failed\n", name);
+    exit(1);
+  }
+  printf("%s: %d cycles/op\n", name, (int)(cycles / N));
+}
+
+static long
+ns(struct timespec *start, struct timespec *end)
+{
+  return (end->tv_sec - start->tv_sec) * 1000000000L + end->tv_nsec - start->tv_nsec;
+}
+
+// Read the file "catfile" a page at a time into buf, and return the
+// time it takes in ns per page.
+static long
+catfile(char *buf)
+{
+  struct timespec start, end;
+  int fd, n, pages;
+
+  fd = open("catfile", O_RDONLY);
+  if(fd < 0){
+    fprintf(2, "bench: open catfile failed\n");
+    exit(1);
+  }
+  pages = 0;
+  clock_gettime(CLOCK_MONOTONIC, &start);
+  while((n = read(fd, buf, PGSIZE)) > 0)
+    pages++;
+  clock_gettime(CLOCK_MONOTONIC, &end);
+  close(fd);
+  if(n < 0 || pages != NPAGES){
+    fprintf(2, "bench: read catfile failed\n");
+    exit(1);
+  }
+  return ns(&start, &end) / NPAGES;
+}
+
+int
+main(void)
+{
+  int to[2], from[2], pid, fd;
+  char buf[1];
+
+  int i;
+  struct timespec start, end;
+
+  report("null", bench(BENCH_NULL, N, 0, 0, 0));
+
+  // Trap from user space and back, switching page tables each way,
+  // which costs refilling the TLB on a CPU without ASIDs.
+  clock_gettime(CLOCK_MONOTONIC, &start);
+  for(i = 0; i < N; i++)
+    getpid();
+  clock_gettime(CLOCK_MONOTONIC, &end);
+  printf("getpid: %d ns/op\n", (int)(ns(&start, &end) / N));
+
+  // Yield against a child doing the same, so that each round is a
+  // pair of context switches.
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "bench: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    bench(BENCH_YIELD, N, 0, 0, 0);
+    exit(0);
+  }
+  report("yield", bench(BENCH_YIELD, N, 0, 0, 0));
+  wait(0);
+
+  // Ping-pong a byte with a child echoing it back.
+  if(pipe(to) < 0 || pipe(from) < 0){
+    fprintf(2, "bench: pipe failed\n");
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "bench: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    close(to[1]);
+    close(from[0]);
+    while(read(to[0], buf, 1) == 1)
+      write(from[1], buf, 1);
+    exit(0);
+  }
+  close(to[0]);
+  close(from[1]);
+  report("pipe", bench(BENCH_PIPE, N, from[0], to[1], buf));
+  close(to[1]);
+  close(from[0]);
+  wait(0);
+
+  // Read a file as cat does, into a page-aligned buffer, which the
+  // kernel maps the cached pages at, and into one that is not, which
+  // it copies into. The first pass reads the file into the caches.
+  fd = open("catfile", O_CREATE|O_WRONLY|O_TRUNC);
+  if(fd < 0){
+    fprintf(2, "bench: create catfile failed\n");
+    exit(1);
+  }
+  for(i = 0; i < NPAGES; i++){
+    memset(page, 'a' + i % 26, PGSIZE);
+    if(write(fd, page, PGSIZE) != PGSIZE){
+      fprintf(2, "bench: write catfile failed\n");
+      exit(1);
+    }
+  }
+  close(fd);
+  catfile(page);
+  printf("cat aligned: %d ns/page\n", (int)catfile(page));
+  printf("cat unaligned: %d ns/page\n", (int)catfile(page + 1));
+  unlink("catfile");
+  exit(0);
+}
@@ -2,7 +2,9 @@
 #include "kernel/stat.h"
 #include "user/user.h"
 
-char buf[512];
+// A page-aligned page, into which read() maps the cached pages of
+// a file instead of copying them.
+char buf[4096] __attribute__((aligned(4096)));
 
 void
 cat(int fd)
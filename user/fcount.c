@@ -0,0 +1,35 @@
+// Print the function entry counters of a kernel built with
+// FCOUNT=yes, one "count name" line per function called so far.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/errno.h"
+#include "user/user.h"
+
+#define NCOUNTER 64
+
+struct fcountrecord counters[NCOUNTER];
+
+int
+main(void)
+{
+  int n, i;
+
+  n = fcount(counters, NCOUNTER);
+  if(n < 0){
+    if(errno == ENOSYS)
+      fprintf(2, "fcount: kernel built without FCOUNT=yes\n");
+    else
+      fprintf(2, "fcount: fcount failed\n");
+    exit(1);
+  }
+  if(n > NCOUNTER){
+    fprintf(2, "fcount: showing %d of %d counters\n", NCOUNTER, n);
+    n = NCOUNTER;
+  }
+  for(i = 0; i < n; i++){
+    if(counters[i].count)
+      printf("%d %s\n", (int)counters[i].count, counters[i].name);
+  }
+  exit(0);
+}
@@ -22,10 +22,7 @@ main(void)
   // Add xstate to immediately run usertests and poweroff.
   int pid, wpid, xstate;
 
-  if(open("console", O_RDWR) < 0){
-    mknod("console", CONSOLE, 0);
-    open("console", O_RDWR);
-  }
+  open("/dev/console", O_RDWR);
   dup(0);  // stdout
   dup(0);  // stderr
 
@@ -33,8 +30,10 @@ main(void)
     printf("init: starting %s\n", argv[0]);
     pid = fork();
     if(pid < 0){
+      // Exiting would panic the kernel; try again later.
       printf("init: fork failed\n");
-      exit(1);
+      sleep(10);
+      continue;
     }
     if(pid == 0){
       exec(argv[0], argv);
@@ -47,11 +46,12 @@ main(void)
       // or if a parentless process exits.
       wpid = wait(&xstate);
       if(wpid == pid){
-        // the shell exited; restart it.
+        // the shell exited or crashed; restart it.
+        printf("init: %s exited with status %d\n", argv[0], xstate);
         break;
       } else if(wpid < 0){
         printf("init: wait returned an error\n");
-        exit(1);
+        sleep(10);
       } else {
         // it was a parentless process; do nothing.
       }
@@ -59,5 +59,7 @@ main(void)
 #ifdef USERTEST
     poweroff(xstate);
 #endif
+    // Keep a shell that cannot start from spinning.
+    sleep(10);
   }
 }
@@ -0,0 +1,43 @@
+// Print the longest interrupts-disabled window of each CPU of a
+// kernel built with IRQOFF=yes, and the spin lock or the source
+// line that disabled interrupts.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/errno.h"
+#include "user/user.h"
+
+#define MAXCPU 8
+
+// Timer cycles per microsecond, i.e., the 10MHz timer of qemu.
+#define CYCLES_PER_US 10
+
+struct irqoffrecord records[MAXCPU];
+
+int
+main(void)
+{
+  int n, i;
+
+  n = irqoff(records, MAXCPU);
+  if(n < 0){
+    if(errno == ENOSYS)
+      fprintf(2, "irqoff: kernel built without IRQOFF=yes\n");
+    else
+      fprintf(2, "irqoff: irqoff failed\n");
+    exit(1);
+  }
+  if(n > MAXCPU)
+    n = MAXCPU;
+  for(i = 0; i < n; i++){
+    if(records[i].cycles == 0)
+      continue;
+    if(records[i].line)
+      printf("cpu %d: %d us at %s:%d\n", i, (int)(records[i].cycles / CYCLES_PER_US),
+             records[i].site, (int)records[i].line);
+    else
+      printf("cpu %d: %d us holding %s\n", i, (int)(records[i].cycles / CYCLES_PER_US),
+             records[i].site);
+  }
+  exit(0);
+}
@@ -1,17 +1,24 @@
 #include "kernel/types.h"
 #include "kernel/stat.h"
+#include "kernel/signal.h"
 #include "user/user.h"
 
 int
 main(int argc, char **argv)
 {
-  int i;
+  int i, sig;
 
-  if(argc < 2){
-    fprintf(2, "usage: kill pid...\n");
+  sig = SIGTERM;
+  i = 1;
+  if(argc > 1 && argv[1][0] == '-'){
+    sig = atoi(argv[1] + 1);
+    i++;
+  }
+  if(i >= argc){
+    fprintf(2, "usage: kill [-sig] pid...\n");
     exit(1);
   }
-  for(i=1; i<argc; i++)
-    kill(atoi(argv[i]));
+  for(; i<argc; i++)
+    kill(atoi(argv[i]), sig);
   exit(0);
 }
@@ -0,0 +1,40 @@
+// Print the latency histograms of the system calls, recorded by
+// a kernel built with SYSCALL_LATENCY=yes.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/errno.h"
+#include "user/user.h"
+
+#define NSYSCALL 64
+
+int
+main(void)
+{
+  uint64 histogram[LATENCY_BUCKETS];
+  uint64 calls;
+  int num, i;
+
+  for(num = 1; num < NSYSCALL; num++){
+    if(syscall_latency(num, histogram) < 0){
+      if(errno == ENOSYS){
+        fprintf(2, "latency: kernel built without SYSCALL_LATENCY=yes\n");
+        exit(1);
+      }
+      break;
+    }
+    calls = 0;
+    for(i = 0; i < LATENCY_BUCKETS; i++)
+      calls += histogram[i];
+    if(calls == 0)
+      continue;
+    // Bucket i counts the calls shorter than 2^i cycles.
+    printf("syscall %d: %d calls,", num, (int)calls);
+    for(i = 0; i < LATENCY_BUCKETS; i++){
+      if(histogram[i])
+        printf(" <2^%d:%d", i, (int)histogram[i]);
+    }
+    printf("\n");
+  }
+  exit(0);
+}
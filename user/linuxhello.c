@@ -0,0 +1,114 @@
+// A "hello, world" built as a static Linux program would be:
+// it is linked above the first page, starts at _start with argc,
+// argv[], envp[] and the auxiliary vector on the stack, and makes
+// the system calls that a libc such as musl makes, by their Linux
+// numbers. It exits with 0 if the kernel behaved as Linux does.
+
+#include "kernel/types.h"
+#include "kernel/elf.h"
+#include "kernel/errno.h"
+
+// System call numbers of Linux on riscv64.
+#define LINUX_IOCTL           29
+#define LINUX_WRITEV          66
+#define LINUX_EXIT_GROUP      94
+#define LINUX_SET_TID_ADDRESS 96
+#define LINUX_CLOCK_GETTIME   113
+#define LINUX_GETTID          178
+#define LINUX_BRK             214
+#define LINUX_MMAP            222
+
+#define TIOCGWINSZ 0x5413
+
+struct iovec {
+  void *base;
+  uint64 len;
+};
+
+struct timespec {
+  long sec;
+  long nsec;
+};
+
+asm(".text\n"
+    ".global _start\n"
+    "_start:\n"
+    "  mv a0, sp\n"
+    "  andi sp, sp, -16\n"
+    "  call cstart\n");
+
+static long
+syscall6(long n, long a, long b, long c, long d, long e, long f)
+{
+  register long a0 asm("a0") = a;
+  register long a1 asm("a1") = b;
+  register long a2 asm("a2") = c;
+  register long a3 asm("a3") = d;
+  register long a4 asm("a4") = e;
+  register long a5 asm("a5") = f;
+  register long a7 asm("a7") = n;
+  asm volatile("ecall"
+               : "+r"(a0)
+               : "r"(a1), "r"(a2), "r"(a3), "r"(a4), "r"(a5), "r"(a7)
+               : "memory");
+  return a0;
+}
+
+static long
+syscall3(long n, long a, long b, long c)
+{
+  return syscall6(n, a, b, c, 0, 0, 0);
+}
+
+static void
+check(int ok, int code)
+{
+  if(!ok)
+    syscall3(LINUX_EXIT_GROUP, code, 0, 0);
+}
+
+void
+cstart(uint64 *sp)
+{
+  uint64 argc = sp[0];
+  char **argv = (char**)(sp + 1);
+  char **envp = argv + argc + 1;
+  uint64 *auxv = (uint64*)(envp + 1);
+  uint64 pagesz = 0, random = 0, phdr = 0, phnum = 0;
+  struct timespec ts;
+  struct iovec iov[2];
+  char *p;
+  long brk;
+
+  check(argc >= 1 && argv[argc] == 0 && envp[0] == 0, 2);
+  for(; auxv[0] != AT_NULL; auxv += 2){
+    if(auxv[0] == AT_PAGESZ)
+      pagesz = auxv[1];
+    if(auxv[0] == AT_RANDOM)
+      random = auxv[1];
+    if(auxv[0] == AT_PHDR)
+      phdr = auxv[1];
+    if(auxv[0] == AT_PHNUM)
+      phnum = auxv[1];
+  }
+  check(pagesz == 4096 && random != 0, 3);
+  check(phnum == 0 || ((struct proghdr*)phdr)->type == ELF_PROG_LOAD, 4);
+
+  check(syscall3(LINUX_SET_TID_ADDRESS, 0, 0, 0) == syscall3(LINUX_GETTID, 0, 0, 0), 5);
+  check(syscall3(LINUX_IOCTL, 1, TIOCGWINSZ, (long)&ts) == -ENOTTY, 6);
+  check(syscall3(LINUX_CLOCK_GETTIME, 1, (long)&ts, 0) == 0, 7);
+
+  // Map a page, as musl does for the TLS and malloc().
+  p = (char*)syscall6(LINUX_MMAP, 0, 4096, 3, 0x22, -1, 0);
+  check((unsigned long)p < -4096UL, 8);
+  p[0] = 'h';
+  brk = syscall3(LINUX_BRK, 0, 0, 0);
+  check(syscall3(LINUX_BRK, brk + 4096, 0, 0) == brk + 4096, 9);
+
+  iov[0].base = p;
+  iov[0].len = 1;
+  iov[1].base = "ello, world\n";
+  iov[1].len = 12;
+  check(syscall3(LINUX_WRITEV, 1, (long)iov, 2) == 13, 10);
+  syscall3(LINUX_EXIT_GROUP, 0, 0, 0);
+}
@@ -26,8 +26,8 @@ void
 ls(char *path)
 {
   char buf[512], *p;
-  int fd;
-  struct dirent de;
+  int fd, n, i;
+  struct direntplus des[8];
   struct stat st;
 
   if((fd = open(path, 0)) < 0){
@@ -54,16 +54,13 @@ ls(char *path)
     strcpy(buf, path);
     p = buf+strlen(buf);
     *p++ = '/';
-    while(read(fd, &de, sizeof(de)) == sizeof(de)){
-      if(de.inum == 0)
-        continue;
-      memmove(p, de.name, DIRSIZ);
-      p[DIRSIZ] = 0;
-      if(stat(buf, &st) < 0){
-        printf("ls: cannot stat %s\n", buf);
-        continue;
+    // Read the entries with their status, instead of stat()ing each.
+    while((n = getdents_plus(fd, des, sizeof(des))) > 0){
+      for(i = 0; i < n / sizeof(des[0]); i++){
+        memmove(p, des[i].de.name, DIRSIZ);
+        p[DIRSIZ] = 0;
+        printf("%s %d %d %d\n", fmtname(buf), des[i].st.type, des[i].st.ino, des[i].st.size);
       }
-      printf("%s %d %d %d\n", fmtname(buf), st.type, st.ino, st.size);
     }
     break;
   }
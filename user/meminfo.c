@@ -0,0 +1,36 @@
+// Print the number of the kernel pages held by each owner, with its
+// budget, to attribute running out of pages to a subsystem.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+char *owners[NPAGEOWNER] = {
+  [PAGE_OWNER_USER]      "user",
+  [PAGE_OWNER_PAGETABLE] "pagetable",
+  [PAGE_OWNER_KSTACK]    "kstack",
+  [PAGE_OWNER_TRAPFRAME] "trapframe",
+  [PAGE_OWNER_PIPE]      "pipe",
+  [PAGE_OWNER_PAGECACHE] "pagecache",
+  [PAGE_OWNER_EXEC]      "exec",
+};
+
+int
+main(void)
+{
+  struct meminfo mi;
+  int i;
+
+  if(meminfo(&mi) < 0){
+    fprintf(2, "meminfo: meminfo failed\n");
+    exit(1);
+  }
+  printf("free %d\n", (int)mi.freepages);
+  for(i = 0; i < NPAGEOWNER; i++){
+    if(mi.budgets[i] == (uint64)-1)
+      printf("%s %d\n", owners[i], (int)mi.owned[i]);
+    else
+      printf("%s %d/%d\n", owners[i], (int)mi.owned[i], (int)mi.budgets[i]);
+  }
+  exit(0);
+}
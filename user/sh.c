@@ -57,7 +57,7 @@ struct cmd *parsecmd(char*);
 void
 runcmd(struct cmd *cmd)
 {
-  int p[2];
+  int fd, p[2];
   struct backcmd *bcmd;
   struct execcmd *ecmd;
   struct listcmd *lcmd;
@@ -81,11 +81,14 @@ runcmd(struct cmd *cmd)
 
   case REDIR:
     rcmd = (struct redircmd*)cmd;
-    close(rcmd->fd);
-    if(open(rcmd->file, rcmd->mode) < 0){
+    if((fd = open(rcmd->file, rcmd->mode)) < 0){
       fprintf(2, "open %s failed\n", rcmd->file);
       exit(1);
     }
+    if(fd != rcmd->fd){
+      dup2(fd, rcmd->fd);
+      close(fd);
+    }
     runcmd(rcmd->cmd);
     break;
 
@@ -102,15 +105,13 @@ runcmd(struct cmd *cmd)
     if(pipe(p) < 0)
       panic("pipe");
     if(fork1() == 0){
-      close(1);
-      dup(p[1]);
+      dup2(p[1], 1);
       close(p[0]);
       close(p[1]);
       runcmd(pcmd->left);
     }
     if(fork1() == 0){
-      close(0);
-      dup(p[0]);
+      dup2(p[0], 0);
       close(p[0]);
       close(p[1]);
       runcmd(pcmd->right);
@@ -148,7 +149,7 @@ main(void)
   int fd;
 
   // Ensure that three file descriptors are open.
-  while((fd = open("console", O_RDWR)) >= 0){
+  while((fd = open("/dev/console", O_RDWR)) >= 0){
     if(fd >= 3){
       close(fd);
       break;
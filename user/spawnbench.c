@@ -0,0 +1,77 @@
+// Compare the cost of creating processes with fork()+exec() and with spawn().
+// Usage: spawnbench [n]
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/fcntl.h"
+#include "user/user.h"
+
+// Grow the parent, so that fork() has something to copy, as a shell would.
+#define HEAP (64 * 4096)
+
+char *echoargv[] = { "echo", 0 };
+
+int
+forkexec(void)
+{
+  int pid = fork();
+  if(pid == 0){
+    exec(echoargv[0], echoargv);
+    exit(1);
+  }
+  return pid;
+}
+
+int
+spawnecho(void)
+{
+  return spawn(echoargv[0], echoargv);
+}
+
+// Returns the ticks taken to start n processes with start() and wait for them.
+int
+bench(int (*start)(void), int n)
+{
+  int i, t0;
+
+  t0 = uptime();
+  for(i = 0; i < n; i++){
+    if(start() < 0)
+      return -1;
+    wait(0);
+  }
+  return uptime() - t0;
+}
+
+int
+main(int argc, char *argv[])
+{
+  int n = 100, fd, tfork, tspawn;
+
+  if(argc > 1)
+    n = atoi(argv[1]);
+  if(sbrk(HEAP) == (char*)-1){
+    fprintf(2, "spawnbench: sbrk failed\n");
+    exit(1);
+  }
+
+  // Keep the output of echo out of the way.
+  fd = open("spawnbench.out", O_CREATE|O_WRONLY);
+  if(fd < 0){
+    fprintf(2, "spawnbench: create failed\n");
+    exit(1);
+  }
+  close(1);
+  dup(fd);
+  close(fd);
+
+  tfork = bench(forkexec, n);
+  tspawn = bench(spawnecho, n);
+  unlink("spawnbench.out");
+  if(tfork < 0 || tspawn < 0){
+    fprintf(2, "spawnbench: failed to start a process\n");
+    exit(1);
+  }
+  fprintf(2, "%d processes: fork+exec %d ticks, spawn %d ticks\n", n, tfork, tspawn);
+  exit(0);
+}
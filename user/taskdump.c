@@ -0,0 +1,19 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+int
+main(int argc, char **argv)
+{
+  int i;
+
+  if(argc < 2){
+    fprintf(2, "usage: taskdump pid...\n");
+    exit(1);
+  }
+  for(i=1; i<argc; i++){
+    if(taskdump(atoi(argv[i])) < 0)
+      fprintf(2, "taskdump: %s failed\n", argv[i]);
+  }
+  exit(0);
+}
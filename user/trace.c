@@ -0,0 +1,48 @@
+// Drain the trace buffers of a kernel built with TRACE=yes, printing
+// one record per line: time cpu event arg0 arg1.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/errno.h"
+#include "user/user.h"
+
+#define NRECORD 64
+
+static char *events[] = {
+[TRACE_SCHED_SWITCH]  "sched_switch",
+[TRACE_SYSCALL_ENTER] "syscall_enter",
+[TRACE_SYSCALL_EXIT]  "syscall_exit",
+[TRACE_DISK_SUBMIT]   "disk_submit",
+[TRACE_DISK_COMPLETE] "disk_complete",
+[TRACE_LOCK_CONTEND]  "lock_contend",
+};
+
+int
+main(void)
+{
+  struct tracerecord records[NRECORD];
+  struct tracerecord *r;
+  char *event;
+  int n, i;
+
+  for(;;){
+    n = trace_read(records, NRECORD);
+    if(n < 0){
+      if(errno == ENOSYS)
+        fprintf(2, "trace: kernel built without TRACE=yes\n");
+      else
+        fprintf(2, "trace: trace_read failed\n");
+      exit(1);
+    }
+    for(i = 0; i < n; i++){
+      r = &records[i];
+      event = "unknown";
+      if(r->event < sizeof(events)/sizeof(events[0]) && events[r->event])
+        event = events[r->event];
+      printf("%p %d %s %p %p\n", r->time, r->cpu, event, r->args[0], r->args[1]);
+    }
+    if(n < NRECORD)
+      break;
+  }
+  exit(0);
+}
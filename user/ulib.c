@@ -1,8 +1,29 @@
 #include "kernel/types.h"
 #include "kernel/stat.h"
 #include "kernel/fcntl.h"
+#include "kernel/errno.h"
+#include "kernel/signal.h"
 #include "user/user.h"
 
+// Error number of the last failed system call of the thread.
+// It lives in the TLS block that tp points to, so threads made by
+// clone() with CLONE_SETTLS have their own. The others share the
+// block, and so errno, of the thread that made them.
+__thread int errno;
+
+// Every system call stub in usys.S jumps here with the value the
+// kernel returned in a0. A value in [-MAX_ERRNO, -1] is -errno:
+// record it and return -1, as the callers expect.
+long
+__syscall_ret(long r)
+{
+  if(r < 0 && r >= -MAX_ERRNO){
+    errno = -r;
+    return -1;
+  }
+  return r;
+}
+
 char*
 strcpy(char *s, const char *t)
 {
@@ -134,3 +155,15 @@ memcpy(void *dst, const void *src, uint n)
 {
   return memmove(dst, src, n);
 }
+
+// Set the handler of signal sig, which returns through sigreturn().
+int
+signal(int sig, void (*handler)(int))
+{
+  struct sigaction act;
+
+  memset(&act, 0, sizeof(act));
+  act.sa_handler = handler;
+  act.sa_restorer = (void (*)(void))sigreturn;
+  return sigaction(sig, &act, 0);
+}
@@ -0,0 +1,36 @@
+// Print the uptime, the number of processes and the load averages.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// Ticks per second, as the timer interrupts about every 1/10th second.
+#define HZ 10
+
+static void
+printload(uint64 load)
+{
+  uint64 frac = ((load & ((1 << FSHIFT) - 1)) * 100) >> FSHIFT;
+
+  printf(" %d.%s%d", (int)(load >> FSHIFT), frac < 10 ? "0" : "", (int)frac);
+}
+
+int
+main(void)
+{
+  struct sysinfo si;
+  uint64 secs;
+  int i;
+
+  if(sysinfo(&si) < 0){
+    fprintf(2, "uptime: sysinfo failed\n");
+    exit(1);
+  }
+  secs = si.uptime / HZ;
+  printf("up %d:%s%d, %d procs, load average:", (int)(secs / 60),
+         secs % 60 < 10 ? "0" : "", (int)(secs % 60), (int)si.procs);
+  for(i = 0; i < 3; i++)
+    printload(si.loads[i]);
+  printf("\n");
+  exit(0);
+}
@@ -1,5 +1,18 @@
 struct stat;
 struct rtcdate;
+struct iostat;
+struct sysinfo;
+struct tracerecord;
+struct sigaction;
+struct fcountrecord;
+struct irqoffrecord;
+struct meminfo;
+struct timespec;
+struct timeval;
+struct itimerval;
+struct pagemapentry;
+struct pagemapstat;
+struct direntplus;
 
 // system calls
 int fork(void);
@@ -9,7 +22,7 @@ int pipe(int*);
 int write(int, const void*, int);
 int read(int, void*, int);
 int close(int);
-int kill(int);
+int kill(int, int);
 int exec(char*, char**);
 int open(const char*, int);
 int mknod(const char*, short, short);
@@ -24,8 +37,74 @@ char* sbrk(int);
 int sleep(int);
 int uptime(void);
 int poweroff(int) __attribute__((noreturn));
+int sync_file_range(int, int, int, int);
+int fsync(int);
+int iostat(int, struct iostat*);
+int rgroup_set(int, int, int);
+int rgroup_join(int);
+int gettid(void);
+int tgkill(int, int);
+int futex(int*, int, int);
+int set_robust_list(void*);
+int spawn(char*, char**);
+int pidfd_open(int);
+int fsresize(int);
+int sysinfo(struct sysinfo*);
+int getrandom(void*, int, int);
+int syscall_latency(int, uint64*);
+void* mmap(void*, uint64, int, int, int, int);
+int munmap(void*, uint64);
+int madvise(void*, uint64, int);
+long bench(int, int, int, int, char*);
+int taskdump(int);
+int trace_read(struct tracerecord*, int);
+int sigaction(int, const struct sigaction*, struct sigaction*);
+int sigprocmask(int, const uint*, uint*);
+int sigreturn(void);
+int fcount(struct fcountrecord*, int);
+int clone(void (*)(void*), void*, void*, int, void*);
+int irqoff(struct irqoffrecord*, int);
+char* brk(void*);
+int waitpid(int, int*, int);
+int setpgid(int, int);
+int getpgid(int);
+int setsid(void);
+int getppid(void);
+int getuid(void);
+int geteuid(void);
+int meminfo(struct meminfo*);
+int nanosleep(const struct timespec*, struct timespec*);
+int clock_gettime(int, struct timespec*);
+int gettimeofday(struct timeval*, void*);
+int setitimer(int, const struct itimerval*, struct itimerval*);
+int alarm(int);
+int sched_setaffinity(int, const uint64*);
+int sched_getaffinity(int, uint64*);
+int pagemap(struct pagemapentry*, int, struct pagemapstat*);
+int rename(const char*, const char*);
+int lseek(int, int, int);
+int dup2(int, int);
+int dup3(int, int, int);
+int pread(int, void*, int, int);
+int pwrite(int, const void*, int, int);
+int ftruncate(int, int);
+int reboot(void) __attribute__((noreturn));
+int fcntl(int, int, int);
+int utimes(const char*, const struct timespec*);
+int futimens(int, const struct timespec*);
+int chmod(const char*, int);
+int chown(const char*, int, int);
+int setuid(int);
+int setgid(int);
+int getgid(void);
+int fswatch(const char*, int);
+int getdents_plus(int, struct direntplus*, int);
+int fadvise(int, int, int, int);
+int mount(const char*, const char*);
+int umount(const char*);
 
 // ulib.c
+extern __thread int errno;
 int stat(const char*, struct stat*);
 char* strcpy(char*, const char*);
 void *memmove(void*, const void*, int);
@@ -39,5 +118,6 @@ void* memset(void*, int, uint);
 void* malloc(uint);
 void free(void*);
 int atoi(const char*);
+int signal(int, void (*)(int));
 int memcmp(const void *, const void *, uint);
 void *memcpy(void *, const void *, uint);
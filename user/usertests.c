@@ -7,6 +7,14 @@
 #include "kernel/syscall.h"
 #include "kernel/memlayout.h"
 #include "kernel/riscv.h"
+#include "kernel/elf.h"
+#include "kernel/errno.h"
+#include "kernel/mman.h"
+#include "kernel/signal.h"
+#include "kernel/wait.h"
+#include "kernel/time.h"
+#include "kernel/futex.h"
+#include "kernel/sched.h"
 
 //
 // Tests xv6 system calls.  usertests without arguments runs them all
@@ -580,7 +588,8 @@ writebig(char *s)
     exit(1);
   }
 
-  for(i = 0; i < MAXFILE; i++){
+  // Fill the direct and indirect blocks; hugefile tests the double-indirect ones.
+  for(i = 0; i < NDIRECT + NINDIRECT; i++){
     ((int*)buf)[0] = i;
     if(write(fd, buf, BSIZE) != BSIZE){
       printf("%s: error: write big file failed\n", s, i);
@@ -600,7 +609,7 @@ writebig(char *s)
   for(;;){
     i = read(fd, buf, BSIZE);
     if(i == 0){
-      if(n == MAXFILE - 1){
+      if(n != NDIRECT + NINDIRECT){
         printf("%s: read only %d blocks from big", s, n);
         exit(1);
       }
@@ -724,6 +733,3131 @@ exectest(char *s)
 
 }
 
+// spawn() runs the program in a child that inherits the open files,
+// without copying the parent's memory.
+void
+spawntest(char *s)
+{
+  int fd, xstatus, pid;
+  char *echoargv[] = { "echo", "OK", 0 };
+  char *badargv[] = { "nonexistent", 0 };
+  char buf[3];
+
+  if(spawn("nonexistent", badargv) >= 0){
+    printf("%s: spawn of nonexistent program succeeded\n", s);
+    exit(1);
+  }
+
+  unlink("spawn-ok");
+  fd = open("spawn-ok", O_CREATE|O_WRONLY);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  // Redirect stdout of the child, and restore it in the parent.
+  int out = dup(1);
+  close(1);
+  if(dup(fd) != 1){
+    printf("%s: wrong fd\n", s);
+    exit(1);
+  }
+  pid = spawn("echo", echoargv);
+  close(1);
+  dup(out);
+  close(out);
+  close(fd);
+  if(pid < 0){
+    printf("%s: spawn echo failed\n", s);
+    exit(1);
+  }
+  if(wait(&xstatus) != pid || xstatus != 0){
+    printf("%s: wait failed\n", s);
+    exit(1);
+  }
+
+  fd = open("spawn-ok", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, 2) != 2){
+    printf("%s: read failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("spawn-ok");
+  if(buf[0] != 'O' || buf[1] != 'K'){
+    printf("%s: wrong output\n", s);
+    exit(1);
+  }
+}
+
+// reading a pidfd blocks until the process exits.
+void
+pidfdtest(char *s)
+{
+  int fd, pid, xstatus;
+  char buf[1];
+
+  if(pidfd_open(1000000) >= 0){
+    printf("%s: pidfd_open of nonexistent pid succeeded\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(5);
+    exit(7);
+  }
+
+  fd = pidfd_open(pid);
+  if(fd < 0){
+    printf("%s: pidfd_open failed\n", s);
+    exit(1);
+  }
+  if(write(fd, buf, 1) >= 0){
+    printf("%s: write to pidfd succeeded\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, 1) != 0){
+    printf("%s: read of pidfd failed\n", s);
+    exit(1);
+  }
+  // The child has exited, but has not been reaped.
+  if(wait(&xstatus) != pid || xstatus != 7){
+    printf("%s: wait failed\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// fsresize must refuse to shrink the file system or to grow it beyond the disk.
+void
+fsresizetest(char *s)
+{
+  if(fsresize(-1) >= 0 || fsresize(0) >= 0 || fsresize(FSSIZE) >= 0){
+    printf("%s: fsresize did not grow, but succeeded\n", s);
+    exit(1);
+  }
+  // fs.img is exactly FSSIZE blocks long.
+  if(fsresize(FSSIZE + 1) >= 0){
+    printf("%s: fsresize beyond the disk succeeded\n", s);
+    exit(1);
+  }
+}
+
+// an inode number reused for a new file gets a new generation number.
+void
+inodegentest(char *s)
+{
+  struct stat st1, st2;
+  int fd;
+
+  unlink("gen");
+  fd = open("gen", O_CREATE|O_RDWR);
+  if(fd < 0 || fstat(fd, &st1) < 0){
+    printf("%s: create gen failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("gen");
+
+  fd = open("gen", O_CREATE|O_RDWR);
+  if(fd < 0 || fstat(fd, &st2) < 0){
+    printf("%s: create gen again failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("gen");
+
+  // The lowest free inode is allocated, so the second file reuses the inode of the first.
+  if(st1.ino != st2.ino){
+    printf("%s: inode %d not reused, got %d\n", s, st1.ino, st2.ino);
+    exit(1);
+  }
+  if(st1.gen == st2.gen){
+    printf("%s: inode %d reused with the same generation %d\n", s, st1.ino, st1.gen);
+    exit(1);
+  }
+}
+
+// sysinfo reports a running uptime and at least this process.
+void
+sysinfotest(char *s)
+{
+  struct sysinfo si1, si2;
+
+  if(sysinfo(&si1) < 0){
+    printf("%s: sysinfo failed\n", s);
+    exit(1);
+  }
+  if(si1.procs < 1 || si1.freepages == 0){
+    printf("%s: sysinfo reports %d procs, %d free pages\n", s, si1.procs, si1.freepages);
+    exit(1);
+  }
+  sleep(2);
+  if(sysinfo(&si2) < 0 || si2.uptime < si1.uptime + 2){
+    printf("%s: uptime did not advance\n", s);
+    exit(1);
+  }
+  if(sysinfo((struct sysinfo*)0xffffffffffffffff) >= 0){
+    printf("%s: sysinfo to a bad address succeeded\n", s);
+    exit(1);
+  }
+}
+
+// argv[] of main, for auxvtest.
+char **mainargv;
+
+// exec leaves the address of 16 random bytes in the auxiliary
+// vector that follows argv[].
+void
+auxvtest(char *s)
+{
+  uint64 *auxv;
+  uchar *random = 0;
+  int i, zeros;
+
+  for(i = 0; mainargv[i]; i++)
+    ;
+  for(auxv = (uint64*)&mainargv[i+1]; auxv[0] != AT_NULL; auxv += 2){
+    if(auxv[0] == AT_RANDOM)
+      random = (uchar*)auxv[1];
+  }
+  if(random == 0){
+    printf("%s: no AT_RANDOM\n", s);
+    exit(1);
+  }
+  zeros = 0;
+  for(i = 0; i < 16; i++)
+    zeros += random[i] == 0;
+  if(zeros == 16){
+    printf("%s: AT_RANDOM bytes are all zero\n", s);
+    exit(1);
+  }
+}
+
+// getrandom fills the whole buffer, with different bytes each time.
+void
+getrandomtest(char *s)
+{
+  char buf1[300], buf2[300];
+
+  memset(buf1, 0, sizeof(buf1));
+  memset(buf2, 0, sizeof(buf2));
+  if(getrandom(buf1, sizeof(buf1), 0) != sizeof(buf1) ||
+     getrandom(buf2, sizeof(buf2), 0) != sizeof(buf2)){
+    printf("%s: getrandom failed\n", s);
+    exit(1);
+  }
+  if(memcmp(buf1, buf2, sizeof(buf1)) == 0){
+    printf("%s: getrandom returned the same bytes twice\n", s);
+    exit(1);
+  }
+  if(getrandom(buf1, sizeof(buf1), 1) >= 0 || getrandom(buf1, -1, 0) >= 0){
+    printf("%s: getrandom accepted bad arguments\n", s);
+    exit(1);
+  }
+  if(getrandom((char*)0xffffffffffffffff, 16, 0) >= 0){
+    printf("%s: getrandom to a bad address succeeded\n", s);
+    exit(1);
+  }
+}
+
+// syscall_latency counts the calls to getpid, unless the kernel
+// records no latencies.
+void
+latencytest(char *s)
+{
+  uint64 before[LATENCY_BUCKETS], after[LATENCY_BUCKETS];
+  uint64 n;
+  int i;
+
+  if(syscall_latency(SYS_getpid, before) < 0){
+    if(errno != ENOSYS){
+      printf("%s: syscall_latency failed with errno %d\n", s, errno);
+      exit(1);
+    }
+    return;
+  }
+  for(i = 0; i < 10; i++)
+    getpid();
+  if(syscall_latency(SYS_getpid, after) < 0){
+    printf("%s: syscall_latency failed\n", s);
+    exit(1);
+  }
+  n = 0;
+  for(i = 0; i < LATENCY_BUCKETS; i++)
+    n += after[i] - before[i];
+  if(n < 10){
+    printf("%s: %d getpid calls recorded, expected 10\n", s, (int)n);
+    exit(1);
+  }
+  if(syscall_latency(1000, after) >= 0){
+    printf("%s: syscall_latency of a bad number succeeded\n", s);
+    exit(1);
+  }
+}
+
+// anonymous mmap gives zeroed private pages that fork copies,
+// and munmap takes them away, even from the middle of a mapping.
+void
+mmaptest(char *s)
+{
+  char *p, *q;
+  int i, pid, xstatus, fd;
+
+  p = mmap(0, 3*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  if(p == MAP_FAILED || (uint64)p % PGSIZE != 0){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 3*PGSIZE; i++){
+    if(p[i] != 0){
+      printf("%s: mmap page not zeroed\n", s);
+      exit(1);
+    }
+  }
+  memset(p, 'a', 3*PGSIZE);
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(p[PGSIZE] != 'a')
+      exit(1);
+    p[PGSIZE] = 'b';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || p[PGSIZE] != 'a'){
+    printf("%s: mapping not copied by fork\n", s);
+    exit(1);
+  }
+
+  // Punch a hole in the middle.
+  if(munmap(p + PGSIZE, PGSIZE) < 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+  if(p[0] != 'a' || p[2*PGSIZE] != 'a'){
+    printf("%s: munmap took the neighbors\n", s);
+    exit(1);
+  }
+  fd = open("README", 0);
+  if(fd < 0){
+    printf("%s: open README failed\n", s);
+    exit(1);
+  }
+  if(write(1, p + PGSIZE, 1) >= 0 || read(fd, p + PGSIZE, 1) >= 0){
+    printf("%s: kernel accessed an unmapped page\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid == 0){
+    p[PGSIZE] = 'c';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: write to an unmapped page did not kill\n", s);
+    exit(1);
+  }
+
+  // Map the hole again at a fixed address, read-only.
+  q = mmap(p + PGSIZE, PGSIZE, PROT_READ, MAP_PRIVATE|MAP_ANONYMOUS|MAP_FIXED, -1, 0);
+  if(q != p + PGSIZE || q[0] != 0){
+    printf("%s: MAP_FIXED failed\n", s);
+    exit(1);
+  }
+  if(read(fd, q, 1) >= 0){
+    printf("%s: kernel wrote to a read-only page\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(munmap(p, 3*PGSIZE) < 0){
+    printf("%s: munmap all failed\n", s);
+    exit(1);
+  }
+  if(mmap(0, PGSIZE, PROT_READ, MAP_PRIVATE, -1, 0) != MAP_FAILED ||
+     mmap(0, 0, PROT_READ, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0) != MAP_FAILED){
+    printf("%s: mmap accepted bad arguments\n", s);
+    exit(1);
+  }
+}
+
+// bench measures each operation, and a byte round-trips through
+// a pipe echoed by a child.
+void
+benchtest(char *s)
+{
+  int to[2], from[2], pid, xstatus;
+  char buf[1];
+
+  if(bench(BENCH_NULL, 10, 0, 0, 0) < 0 || bench(BENCH_YIELD, 10, 0, 0, 0) < 0){
+    printf("%s: bench failed\n", s);
+    exit(1);
+  }
+  if(bench(-1, 10, 0, 0, 0) >= 0 || bench(BENCH_NULL, -1, 0, 0, 0) >= 0){
+    printf("%s: bench accepted bad arguments\n", s);
+    exit(1);
+  }
+  if(pipe(to) < 0 || pipe(from) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(to[1]);
+    close(from[0]);
+    while(read(to[0], buf, 1) == 1)
+      write(from[1], buf, 1);
+    exit(0);
+  }
+  close(to[0]);
+  close(from[1]);
+  if(bench(BENCH_PIPE, 10, from[0], to[1], buf) < 0){
+    printf("%s: pipe bench failed\n", s);
+    exit(1);
+  }
+  close(to[1]);
+  close(from[0]);
+  wait(&xstatus);
+}
+
+// a file mapped with MAP_PRIVATE keeps its changes to itself, and one
+// mapped with MAP_SHARED shares them with fork()ed children and writes
+// them back to the file on munmap, without growing it.
+void
+mmapfiletest(char *s)
+{
+  enum { SZ = 2*PGSIZE + 100 };
+  char *p;
+  int i, fd, pid, xstatus;
+  struct stat st;
+
+  fd = open("mmapfile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create mmapfile failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < SZ; i++)
+    buf[i] = 'a' + i % 26;
+  if(write(fd, buf, SZ) != SZ){
+    printf("%s: write mmapfile failed\n", s);
+    exit(1);
+  }
+
+  p = mmap(0, 3*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE, fd, 0);
+  if(p == MAP_FAILED){
+    printf("%s: private mmap failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 3*PGSIZE; i++){
+    if(p[i] != (i < SZ ? 'a' + i % 26 : 0)){
+      printf("%s: wrong byte %d in private mapping\n", s, i);
+      exit(1);
+    }
+  }
+  p[0] = 'X';
+  munmap(p, 3*PGSIZE);
+
+  p = mmap(0, 3*PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0);
+  if(p == MAP_FAILED || p[0] != 'a' || p[PGSIZE] != 'a' + PGSIZE % 26){
+    printf("%s: shared mmap failed, or saw a private change\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    p[1] = 'Y';
+    exit(0);
+  }
+  wait(&xstatus);
+  p[PGSIZE] = 'Z';
+  p[SZ] = 'W';
+  if(xstatus != 0 || p[1] != 'Y'){
+    printf("%s: change of the child not shared\n", s);
+    exit(1);
+  }
+  // write() reaches the mapping, and writing the mapping back does
+  // not undo it.
+  if(lseek(fd, 2, SEEK_SET) != 2 || write(fd, "V", 1) != 1 || p[2] != 'V'){
+    printf("%s: write not seen by the mapping\n", s);
+    exit(1);
+  }
+  if(munmap(p, 3*PGSIZE) < 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("mmapfile", O_RDONLY);
+  if(fd < 0 || read(fd, buf, BUFSZ) != SZ || fstat(fd, &st) < 0 || st.size != SZ){
+    printf("%s: mmapfile has a wrong size\n", s);
+    exit(1);
+  }
+  if(buf[0] != 'a' || buf[1] != 'Y' || buf[2] != 'V' || buf[PGSIZE] != 'Z'){
+    printf("%s: shared changes not written back\n", s);
+    exit(1);
+  }
+  if(mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0) != MAP_FAILED ||
+     mmap(0, PGSIZE, PROT_READ, MAP_SHARED, fd, 1) != MAP_FAILED ||
+     mmap(0, PGSIZE, PROT_READ, MAP_SHARED|MAP_PRIVATE, fd, 0) != MAP_FAILED){
+    printf("%s: mmap accepted bad arguments\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("mmapfile");
+}
+
+// taskdump shows a child blocked reading a pipe, and fails for
+// a process that does not exist.
+void
+taskdumptest(char *s)
+{
+  int fds[2], pid, xstatus;
+  char c;
+
+  if(taskdump(getpid()) < 0){
+    if(errno != ENOSYS){
+      printf("%s: taskdump failed with errno %d\n", s, errno);
+      exit(1);
+    }
+    return;
+  }
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[1]);
+    read(fds[0], &c, 1);
+    exit(0);
+  }
+  close(fds[0]);
+  sleep(1);
+  if(taskdump(pid) < 0){
+    printf("%s: taskdump of the child failed\n", s);
+    exit(1);
+  }
+  close(fds[1]);
+  wait(&xstatus);
+  if(taskdump(pid) >= 0 || errno != ESRCH){
+    printf("%s: taskdump of a dead process succeeded\n", s);
+    exit(1);
+  }
+}
+
+// a getpid() shows up in the trace buffers as a syscall_enter
+// record, followed by the matching syscall_exit record.
+void
+tracetest(char *s)
+{
+  struct tracerecord records[16];
+  int n, i, pid, entered, exited;
+
+  // Drain what has been recorded so far.
+  while((n = trace_read(records, 16)) == 16)
+    ;
+  if(n < 0){
+    if(errno != ENOSYS){
+      printf("%s: trace_read failed with errno %d\n", s, errno);
+      exit(1);
+    }
+    return;
+  }
+
+  pid = getpid();
+  entered = exited = 0;
+  while((n = trace_read(records, 16)) > 0){
+    for(i = 0; i < n; i++){
+      if(records[i].event == TRACE_SYSCALL_ENTER &&
+         records[i].args[0] == SYS_getpid && records[i].args[1] == pid)
+        entered = 1;
+      if(entered && records[i].event == TRACE_SYSCALL_EXIT &&
+         records[i].args[0] == SYS_getpid && records[i].args[1] == pid)
+        exited = 1;
+    }
+  }
+  if(n < 0){
+    printf("%s: trace_read failed\n", s);
+    exit(1);
+  }
+  if(!entered || !exited){
+    printf("%s: getpid() not traced\n", s);
+    exit(1);
+  }
+}
+
+volatile int signalled;
+volatile uint64 sigleaked;
+
+void
+sigusr1(int sig)
+{
+  // The frame the kernel saved starts at sp on entry, with the
+  // trap frame, whose kernel_satp, kernel_sp, kernel_trap and
+  // kernel_hartid must not be shown to the user.
+  uint64 *frame = __builtin_frame_address(0);
+
+  sigleaked |= frame[0] | frame[1] | frame[2] | frame[4];
+  if(sig == SIGUSR1)
+    signalled++;
+}
+
+// a handler runs when a signal is sent, or once it is unblocked,
+// and returns to where the signal interrupted the process. an
+// ignored signal does nothing, the default action terminates the
+// process, and a signal interrupts a sleep.
+void
+signaltest(char *s)
+{
+  int fds[2], pid, xstatus, r;
+  uint set;
+  struct sigaction act;
+  char c;
+
+  signalled = 0;
+  sigleaked = 0;
+  if(signal(SIGUSR1, sigusr1) < 0){
+    printf("%s: signal failed\n", s);
+    exit(1);
+  }
+  r = kill(getpid(), SIGUSR1);
+  if(r != 0 || signalled != 1){
+    printf("%s: handler did not run, or kill returned %d\n", s, r);
+    exit(1);
+  }
+  if(sigleaked){
+    printf("%s: signal frame shows the kernel\n", s);
+    exit(1);
+  }
+
+  set = 1 << SIGUSR1;
+  if(sigprocmask(SIG_BLOCK, &set, 0) < 0){
+    printf("%s: sigprocmask failed\n", s);
+    exit(1);
+  }
+  kill(getpid(), SIGUSR1);
+  if(signalled != 1){
+    printf("%s: blocked signal delivered\n", s);
+    exit(1);
+  }
+  sigprocmask(SIG_UNBLOCK, &set, 0);
+  if(signalled != 2){
+    printf("%s: unblocked signal not delivered\n", s);
+    exit(1);
+  }
+
+  signal(SIGUSR2, SIG_IGN);
+  kill(getpid(), SIGUSR2);
+  signal(SIGUSR2, SIG_DFL);
+
+  memset(&act, 0, sizeof(act));
+  act.sa_handler = SIG_IGN;
+  if(sigaction(SIGKILL, &act, 0) >= 0 || errno != EINVAL){
+    printf("%s: SIGKILL ignored\n", s);
+    exit(1);
+  }
+
+  // The default action of SIGTERM.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(;;)
+      sleep(1);
+  }
+  kill(pid, SIGTERM);
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: SIGTERM did not terminate the child\n", s);
+    exit(1);
+  }
+
+  // A handled signal interrupts a sleep.
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    signalled = 0;
+    signal(SIGUSR1, sigusr1);
+    write(fds[1], "x", 1);
+    r = sleep(1000);
+    exit(r < 0 && errno == EINTR && signalled == 1 ? 0 : 1);
+  }
+  read(fds[0], &c, 1);
+  // Give the child time to fall asleep.
+  sleep(2);
+  kill(pid, SIGUSR1);
+  wait(&xstatus);
+  close(fds[0]);
+  close(fds[1]);
+  if(xstatus != 0){
+    printf("%s: sleep not interrupted by the signal\n", s);
+    exit(1);
+  }
+  signal(SIGUSR1, SIG_DFL);
+}
+
+// fcount counts every system call in KernelCtx::dispatch.
+uint64
+dispatchcount(struct fcountrecord *counters, int n)
+{
+  int i;
+
+  for(i = 0; i < n; i++){
+    if(strcmp(counters[i].name, "syscall::KernelCtx::dispatch") == 0)
+      return counters[i].count;
+  }
+  return 0;
+}
+
+void
+fcounttest(char *s)
+{
+  static struct fcountrecord counters[64];
+  uint64 before, after;
+  int n, i;
+
+  n = fcount(counters, 64);
+  if(n < 0){
+    if(errno != ENOSYS){
+      printf("%s: fcount failed with errno %d\n", s, errno);
+      exit(1);
+    }
+    return;
+  }
+  if(n > 64)
+    n = 64;
+  before = dispatchcount(counters, n);
+  for(i = 0; i < 10; i++)
+    getpid();
+  n = fcount(counters, 64);
+  if(n > 64)
+    n = 64;
+  after = dispatchcount(counters, n);
+  if(after < before + 10){
+    printf("%s: dispatch counted %d calls, expected at least 10\n", s, (int)(after - before));
+    exit(1);
+  }
+}
+
+// init must survive signals that would terminate any other process.
+void
+killinittest(char *s)
+{
+  if(kill(1, SIGTERM) < 0 || kill(1, SIGKILL) < 0){
+    printf("%s: kill(1) failed\n", s);
+    exit(1);
+  }
+  sleep(1);
+  if(kill(1, 0) < 0){
+    printf("%s: init is gone\n", s);
+    exit(1);
+  }
+}
+
+// threads made by clone() share the memory, and are reaped by wait().
+int clonesum;
+char clonestacks[4][1024] __attribute__((aligned(16)));
+
+void
+clonechild(void *arg)
+{
+  __sync_fetch_and_add(&clonesum, (int)(uint64)arg);
+  exit(0);
+}
+
+void
+clonetest(char *s)
+{
+  int i;
+
+  clonesum = 0;
+  for(i = 0; i < 4; i++){
+    if(clone(clonechild, (void*)(uint64)(i + 1), clonestacks[i] + sizeof(clonestacks[i]), 0, 0) < 0){
+      printf("%s: clone failed\n", s);
+      exit(1);
+    }
+  }
+  for(i = 0; i < 4; i++){
+    if(wait(0) < 0){
+      printf("%s: wait failed\n", s);
+      exit(1);
+    }
+  }
+  if(clonesum != 1 + 2 + 3 + 4){
+    printf("%s: threads did not share memory, sum %d\n", s, clonesum);
+    exit(1);
+  }
+}
+
+// errno is per thread: a thread with its own TLS block does not
+// see the errors of the others.
+char errnotls[64] __attribute__((aligned(16)));
+int errnoseen;
+
+void
+errnochild(void *arg)
+{
+  if(close(-1) >= 0)
+    exit(1);
+  errnoseen = errno;
+  exit(0);
+}
+
+void
+errnotest(char *s)
+{
+  int xstatus;
+
+  // errno is in .tbss, so a zeroed block is a fresh one.
+  memset(errnotls, 0, sizeof(errnotls));
+  errno = 0;
+  errnoseen = 0;
+  if(clone(errnochild, 0, clonestacks[0] + sizeof(clonestacks[0]), CLONE_SETTLS, errnotls) < 0){
+    printf("%s: clone failed\n", s);
+    exit(1);
+  }
+  if(wait(&xstatus) < 0 || xstatus != 0){
+    printf("%s: thread failed\n", s);
+    exit(1);
+  }
+  if(errnoseen != EBADF){
+    printf("%s: thread saw errno %d\n", s, errnoseen);
+    exit(1);
+  }
+  if(errno != 0){
+    printf("%s: errno of another thread leaked, %d\n", s, errno);
+    exit(1);
+  }
+}
+
+// the kernel records an interrupts-disabled window on some CPU,
+// if it is built with IRQOFF=yes.
+void
+irqofftest(char *s)
+{
+  static struct irqoffrecord records[8];
+  int n, i;
+
+  n = irqoff(records, 8);
+  if(n < 0){
+    if(errno != ENOSYS){
+      printf("%s: irqoff failed with errno %d\n", s, errno);
+      exit(1);
+    }
+    return;
+  }
+  if(n > 8)
+    n = 8;
+  for(i = 0; i < n; i++){
+    if(records[i].cycles > 0 && records[i].site[0] != 0)
+      return;
+  }
+  printf("%s: no window recorded\n", s);
+  exit(1);
+}
+
+// brk moves the end of memory within bounds, and shrinking the heap
+// waits for a thread reading into it.
+int brkread;
+
+void
+brkreader(void *arg)
+{
+  int *fds = arg;
+  char *top = sbrk(0);
+
+  brkread = read(fds[0], top - 1, 1);
+  exit(0);
+}
+
+void
+brktest(char *s)
+{
+  char *cur, *top;
+  int fds[2], pid, i;
+
+  cur = brk(0);
+  if(brk(cur + 3*PGSIZE) != cur + 3*PGSIZE || sbrk(0) != cur + 3*PGSIZE){
+    printf("%s: brk grow failed\n", s);
+    exit(1);
+  }
+  cur[3*PGSIZE - 1] = 1;
+  if(brk(cur) != cur || brk(0) != cur){
+    printf("%s: brk shrink failed\n", s);
+    exit(1);
+  }
+  if(brk((char*)MAXVA) != (char*)-1){
+    printf("%s: brk beyond the user memory succeeded\n", s);
+    exit(1);
+  }
+  if(sbrk(-((uint64)cur + PGSIZE)) != (char*)-1){
+    printf("%s: sbrk below 0 succeeded\n", s);
+    exit(1);
+  }
+
+  // a thread blocks reading a pipe into the last heap page,
+  // while this thread shrinks the heap under it.
+  top = sbrk(PGSIZE);
+  if(top == (char*)-1 || pipe(fds) < 0){
+    printf("%s: sbrk or pipe failed\n", s);
+    exit(1);
+  }
+  brkread = 0;
+  if(clone(brkreader, fds, clonestacks[0] + sizeof(clonestacks[0]), 0, 0) < 0){
+    printf("%s: clone failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(5);
+    write(fds[1], "x", 1);
+    exit(0);
+  }
+  sleep(1);
+  if(sbrk(-PGSIZE) == (char*)-1){
+    printf("%s: sbrk shrink failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 2; i++){
+    if(wait(0) < 0){
+      printf("%s: wait failed\n", s);
+      exit(1);
+    }
+  }
+  close(fds[0]);
+  close(fds[1]);
+  if(brkread != 1){
+    printf("%s: read into the heap returned %d\n", s, brkread);
+    exit(1);
+  }
+}
+
+// waitpid waits for the given child, and WNOHANG polls.
+void
+waitpidtest(char *s)
+{
+  int pid1, pid2, xstatus;
+
+  pid1 = fork();
+  if(pid1 < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid1 == 0){
+    sleep(5);
+    exit(3);
+  }
+  pid2 = fork();
+  if(pid2 < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid2 == 0)
+    exit(4);
+
+  if(waitpid(pid1, &xstatus, WNOHANG) != 0){
+    printf("%s: WNOHANG reaped a running child\n", s);
+    exit(1);
+  }
+  if(waitpid(pid1, &xstatus, 0) != pid1 || !WIFEXITED(xstatus) || WEXITSTATUS(xstatus) != 3){
+    printf("%s: waitpid(pid1) failed\n", s);
+    exit(1);
+  }
+  if(waitpid(-1, &xstatus, 0) != pid2 || WEXITSTATUS(xstatus) != 4){
+    printf("%s: waitpid(-1) failed\n", s);
+    exit(1);
+  }
+  if(waitpid(-1, &xstatus, WNOHANG) != -1 || errno != ECHILD){
+    printf("%s: waitpid without children succeeded\n", s);
+    exit(1);
+  }
+}
+
+// process groups, and kill() of a whole group.
+void
+pgrptest(char *s)
+{
+  int pgid, leader, member, i, xstatus;
+
+  pgid = getpgid(0);
+  leader = fork();
+  if(leader < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(leader == 0){
+    for(;;)
+      sleep(1);
+  }
+  member = fork();
+  if(member < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(member == 0){
+    for(;;)
+      sleep(1);
+  }
+
+  if(setpgid(leader, 0) < 0 || getpgid(leader) != leader){
+    printf("%s: setpgid(leader) failed\n", s);
+    exit(1);
+  }
+  if(setpgid(member, leader) < 0 || getpgid(member) != leader){
+    printf("%s: setpgid(member) failed\n", s);
+    exit(1);
+  }
+  if(setpgid(member, 0x7fff) >= 0 || errno != EPERM){
+    printf("%s: joined a group that does not exist\n", s);
+    exit(1);
+  }
+  if(setpgid(1, 0) >= 0 || errno != ESRCH){
+    printf("%s: moved a process that is not a child\n", s);
+    exit(1);
+  }
+  if(getpgid(0) != pgid){
+    printf("%s: the caller changed its group\n", s);
+    exit(1);
+  }
+
+  if(kill(-leader, SIGKILL) < 0){
+    printf("%s: kill(-pgid) failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 2; i++){
+    if(wait(&xstatus) < 0 || !WIFSIGNALED(xstatus)){
+      printf("%s: a member of the group survived\n", s);
+      exit(1);
+    }
+  }
+  if(kill(-leader, SIGKILL) >= 0 || errno != ESRCH){
+    printf("%s: killed an empty group\n", s);
+    exit(1);
+  }
+}
+
+// setsid() starts a session, which its leader cannot leave.
+void
+setsidtest(char *s)
+{
+  int pid, xstatus;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    pid = getpid();
+    if(setsid() != pid || getpgid(0) != pid){
+      printf("%s: setsid failed\n", s);
+      exit(1);
+    }
+    if(setsid() >= 0 || errno != EPERM){
+      printf("%s: setsid in a group leader succeeded\n", s);
+      exit(1);
+    }
+    if(setpgid(0, 1) >= 0 || errno != EPERM){
+      printf("%s: a session leader changed its group\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  exit(xstatus);
+}
+
+// getppid() returns the parent of the process, also in a thread,
+// and every process runs as the superuser.
+int ppidthread;
+
+void
+ppidchild(void *arg)
+{
+  ppidthread = getppid();
+  exit(0);
+}
+
+void
+getppidtest(char *s)
+{
+  int parent, pid, xstatus;
+
+  parent = getpid();
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(getppid() == parent ? 0 : 1);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: getppid in the child is not the parent\n", s);
+    exit(1);
+  }
+
+  ppidthread = -1;
+  if(clone(ppidchild, 0, clonestacks[0] + sizeof(clonestacks[0]), 0, 0) < 0){
+    printf("%s: clone failed\n", s);
+    exit(1);
+  }
+  wait(0);
+  if(ppidthread != getppid()){
+    printf("%s: getppid in a thread %d, in the process %d\n", s, ppidthread, getppid());
+    exit(1);
+  }
+
+  if(getuid() != 0 || geteuid() != 0){
+    printf("%s: not the superuser\n", s);
+    exit(1);
+  }
+}
+
+// madvise(MADV_DONTNEED) frees anonymous pages, which read as zeros
+// again afterwards, also in the kernel and in a fork()ed child.
+void
+madvisetest(char *s)
+{
+  enum { N = 16 };
+  char *p, *f;
+  int i, fd, fds[2], pid, xstatus;
+  struct sysinfo si1, si2;
+
+  p = mmap(0, N*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  if(p == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++)
+    p[i*PGSIZE] = 'a' + i;
+  if(sysinfo(&si1) < 0 || madvise(p, N*PGSIZE, MADV_DONTNEED) < 0 || sysinfo(&si2) < 0){
+    printf("%s: madvise(MADV_DONTNEED) failed\n", s);
+    exit(1);
+  }
+  if(si2.freepages < si1.freepages + N/2){
+    printf("%s: %d free pages before, %d after\n", s, si1.freepages, si2.freepages);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(p[PGSIZE] == 0 ? 0 : 1);
+  wait(&xstatus);
+  if(xstatus != 0 || p[0] != 0){
+    printf("%s: discarded page not zero-filled\n", s);
+    exit(1);
+  }
+  p[0] = 'A';
+  if(pipe(fds) < 0 || write(fds[1], "xyz", 3) != 3 || read(fds[0], p + 2*PGSIZE, 3) != 3){
+    printf("%s: read into a discarded page failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  if(p[0] != 'A' || p[2*PGSIZE] != 'x'){
+    printf("%s: write after madvise lost\n", s);
+    exit(1);
+  }
+  if(madvise(p, N*PGSIZE, MADV_FREE) < 0 || madvise(p, N*PGSIZE, MADV_WILLNEED) < 0 ||
+     p[0] != 0){
+    printf("%s: madvise(MADV_FREE) failed\n", s);
+    exit(1);
+  }
+  munmap(p, N*PGSIZE);
+  if(madvise(p, PGSIZE, MADV_DONTNEED) >= 0 || errno != ENOMEM){
+    printf("%s: madvise on unmapped pages succeeded\n", s);
+    exit(1);
+  }
+
+  fd = open("madvisefile", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "hello", 5) != 5){
+    printf("%s: create madvisefile failed\n", s);
+    exit(1);
+  }
+  f = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0);
+  if(f == MAP_FAILED){
+    printf("%s: shared mmap failed\n", s);
+    exit(1);
+  }
+  f[0] = 'j';
+  if(madvise(f, PGSIZE, MADV_DONTNEED) < 0 || madvise(f, PGSIZE, MADV_WILLNEED) < 0 ||
+     f[0] != 'j'){
+    printf("%s: madvise lost a shared page\n", s);
+    exit(1);
+  }
+  if(madvise(f, PGSIZE, MADV_FREE) >= 0 || errno != EINVAL){
+    printf("%s: madvise(MADV_FREE) on a file succeeded\n", s);
+    exit(1);
+  }
+  munmap(f, PGSIZE);
+  close(fd);
+  unlink("madvisefile");
+}
+
+// meminfo counts the buffer of a pipe until it is closed.
+void
+meminfotest(char *s)
+{
+  struct meminfo mi1, mi2, mi3;
+  int fds[2];
+
+  if(meminfo(&mi1) < 0 || pipe(fds) < 0 || meminfo(&mi2) < 0){
+    printf("%s: meminfo or pipe failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  if(meminfo(&mi3) < 0){
+    printf("%s: meminfo failed\n", s);
+    exit(1);
+  }
+  if(mi2.owned[PAGE_OWNER_PIPE] != mi1.owned[PAGE_OWNER_PIPE] + 1 ||
+     mi3.owned[PAGE_OWNER_PIPE] != mi1.owned[PAGE_OWNER_PIPE]){
+    printf("%s: pipe pages %d, %d, %d\n", s, (int)mi1.owned[PAGE_OWNER_PIPE],
+           (int)mi2.owned[PAGE_OWNER_PIPE], (int)mi3.owned[PAGE_OWNER_PIPE]);
+    exit(1);
+  }
+  if(mi1.owned[PAGE_OWNER_KSTACK] != mi1.budgets[PAGE_OWNER_KSTACK] ||
+     mi1.budgets[PAGE_OWNER_USER] != (uint64)-1 || mi1.owned[PAGE_OWNER_USER] == 0){
+    printf("%s: wrong kernel stack or user pages\n", s);
+    exit(1);
+  }
+  if(meminfo((struct meminfo*)0xffffffffffffffff) >= 0){
+    printf("%s: meminfo to a bad address succeeded\n", s);
+    exit(1);
+  }
+}
+
+// nanosleep wakes up well before the next tick, and clock_gettime
+// does not go backwards.
+void
+nanosleeptest(char *s)
+{
+  struct timespec t0, t1, req;
+  long ns;
+  int i;
+
+  for(i = 0; i < 5; i++){
+    req.tv_sec = 0;
+    req.tv_nsec = 1000000;
+    if(clock_gettime(CLOCK_MONOTONIC, &t0) < 0 || nanosleep(&req, 0) < 0 ||
+       clock_gettime(CLOCK_MONOTONIC, &t1) < 0){
+      printf("%s: clock_gettime or nanosleep failed\n", s);
+      exit(1);
+    }
+    ns = (t1.tv_sec - t0.tv_sec) * 1000000000 + (t1.tv_nsec - t0.tv_nsec);
+    if(ns < 1000000){
+      printf("%s: slept %d ns for 1 ms\n", s, (int)ns);
+      exit(1);
+    }
+    if(ns < 50000000)
+      break;
+  }
+  if(i == 5){
+    printf("%s: sleeping for 1 ms took a tick\n", s);
+    exit(1);
+  }
+
+  req.tv_sec = 0;
+  req.tv_nsec = 1000000000;
+  if(nanosleep(&req, 0) >= 0 || errno != EINVAL){
+    printf("%s: nanosleep with tv_nsec of 1 s succeeded\n", s);
+    exit(1);
+  }
+  if(clock_gettime(2, &t0) >= 0 || errno != EINVAL){
+    printf("%s: clock_gettime of a bad clock succeeded\n", s);
+    exit(1);
+  }
+  if(clock_gettime(CLOCK_REALTIME, &t0) < 0 || t0.tv_nsec < 0 || t0.tv_nsec >= 1000000000){
+    printf("%s: clock_gettime(CLOCK_REALTIME) failed\n", s);
+    exit(1);
+  }
+}
+
+// gettimeofday reads the real-time clock, and agrees with
+// clock_gettime(CLOCK_REALTIME).
+void
+gettimeofdaytest(char *s)
+{
+  struct timeval tv;
+  struct timespec ts;
+
+  if(gettimeofday(&tv, 0) < 0 || clock_gettime(CLOCK_REALTIME, &ts) < 0){
+    printf("%s: gettimeofday or clock_gettime failed\n", s);
+    exit(1);
+  }
+  // 2021-01-01
+  if(tv.tv_sec < 1609459200 || tv.tv_usec < 0 || tv.tv_usec >= 1000000){
+    printf("%s: bad time %d.%d\n", s, (int)tv.tv_sec, (int)tv.tv_usec);
+    exit(1);
+  }
+  if(ts.tv_sec < tv.tv_sec || ts.tv_sec > tv.tv_sec + 1){
+    printf("%s: clocks disagree\n", s);
+    exit(1);
+  }
+  if(gettimeofday((struct timeval*)0xffffffffffffffff, 0) >= 0){
+    printf("%s: gettimeofday to a bad address succeeded\n", s);
+    exit(1);
+  }
+}
+
+volatile int alarms;
+
+void
+sigalrm(int sig)
+{
+  if(sig == SIGALRM)
+    alarms++;
+}
+
+// setitimer sends SIGALRM periodically until disarmed, alarm
+// replaces a pending alarm and returns the seconds it had left,
+// and the default action of SIGALRM terminates the process.
+void
+alarmtest(char *s)
+{
+  struct itimerval it, old;
+  int i, pid, xstatus;
+
+  alarms = 0;
+  signal(SIGALRM, sigalrm);
+  memset(&it, 0, sizeof(it));
+  it.it_value.tv_usec = 100000;
+  it.it_interval.tv_usec = 100000;
+  if(setitimer(ITIMER_REAL, &it, 0) < 0){
+    printf("%s: setitimer failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 100 && alarms < 3; i++)
+    sleep(1);
+  memset(&it, 0, sizeof(it));
+  if(setitimer(ITIMER_REAL, &it, &old) < 0 || old.it_interval.tv_usec != 100000){
+    printf("%s: disarming setitimer failed\n", s);
+    exit(1);
+  }
+  if(alarms < 3){
+    printf("%s: %d alarms\n", s, alarms);
+    exit(1);
+  }
+  i = alarms;
+  sleep(5);
+  if(alarms != i){
+    printf("%s: disarmed timer expired\n", s);
+    exit(1);
+  }
+  signal(SIGALRM, SIG_DFL);
+
+  if(alarm(10) != 0 || alarm(0) != 10){
+    printf("%s: alarm did not return the seconds left\n", s);
+    exit(1);
+  }
+  if(setitimer(ITIMER_REAL + 1, &it, 0) >= 0 || errno != EINVAL){
+    printf("%s: setitimer of a bad timer succeeded\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    alarm(1);
+    for(;;)
+      sleep(1);
+  }
+  wait(&xstatus);
+  if(!WIFSIGNALED(xstatus)){
+    printf("%s: SIGALRM did not terminate the process\n", s);
+    exit(1);
+  }
+}
+
+// a thread pinned to a CPU stays there, its children inherit the
+// mask, and an empty mask or an unknown thread fails.
+void
+affinitytest(char *s)
+{
+  uint64 mask, all;
+  int i, pid, xstatus;
+
+  if(sched_getaffinity(0, &all) < 0 || (all & 1) == 0){
+    printf("%s: sched_getaffinity failed\n", s);
+    exit(1);
+  }
+  mask = 1;
+  if(sched_setaffinity(0, &mask) < 0){
+    printf("%s: sched_setaffinity failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 10; i++){
+    sleep(0);
+    mask = 0;
+    if(sched_getaffinity(getpid(), &mask) < 0 || mask != 1){
+      printf("%s: mask changed to %p\n", s, mask);
+      exit(1);
+    }
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    mask = 0;
+    exit(sched_getaffinity(0, &mask) < 0 || mask != 1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: the child did not inherit the mask\n", s);
+    exit(1);
+  }
+
+  mask = 0;
+  if(sched_setaffinity(0, &mask) >= 0 || errno != EINVAL){
+    printf("%s: setting an empty mask succeeded\n", s);
+    exit(1);
+  }
+  if(sched_getaffinity(1000000, &mask) >= 0 || errno != ESRCH){
+    printf("%s: getting the mask of no thread succeeded\n", s);
+    exit(1);
+  }
+  if(sched_setaffinity(0, &all) < 0){
+    printf("%s: restoring the mask failed\n", s);
+    exit(1);
+  }
+}
+
+// pagemap reports the heap and an anonymous mapping with their
+// protections, and counts the ranges that did not fit.
+void
+pagemaptest(char *s)
+{
+  struct pagemapentry entries[16];
+  struct pagemapstat stat;
+  char *p;
+  int i, n, found;
+
+  p = mmap(0, 2 * PGSIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+  if(p == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  p[0] = 1;
+  n = pagemap(entries, 16, &stat);
+  if(n < 2 || n > 16){
+    printf("%s: pagemap returned %d\n", s, n);
+    exit(1);
+  }
+  if(entries[0].start != 0 || entries[0].kind != PAGEMAP_HEAP){
+    printf("%s: no heap at 0\n", s);
+    exit(1);
+  }
+  found = 0;
+  for(i = 0; i < n; i++){
+    if(entries[i].kind == PAGEMAP_ANONYMOUS && entries[i].start <= (uint64)p &&
+       (uint64)p < entries[i].end)
+      found = entries[i].prot == (PROT_READ | PROT_WRITE);
+  }
+  if(!found){
+    printf("%s: the mapping is missing\n", s);
+    exit(1);
+  }
+  if(stat.page_table_pages < 3 || stat.resident_pages > stat.virtual_pages){
+    printf("%s: bad statistics\n", s);
+    exit(1);
+  }
+  if(pagemap(entries, 0, 0) != n){
+    printf("%s: pagemap into no entries returned another count\n", s);
+    exit(1);
+  }
+  if(pagemap((struct pagemapentry*)0xffffffffffffffff, 1, 0) >= 0){
+    printf("%s: pagemap to a bad address succeeded\n", s);
+    exit(1);
+  }
+  munmap(p, 2 * PGSIZE);
+}
+
+// rename files and directories, within and across directories.
+void
+renametest(char *s)
+{
+  int fd;
+  char c;
+
+  unlink("rn1");
+  unlink("rn2");
+  fd = open("rn1", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create rn1 failed\n", s);
+    exit(1);
+  }
+  write(fd, "a", 1);
+  close(fd);
+  if(rename("rn1", "rn2") != 0){
+    printf("%s: rename rn1 rn2 failed\n", s);
+    exit(1);
+  }
+  if(open("rn1", 0) >= 0){
+    printf("%s: rn1 exists after rename\n", s);
+    exit(1);
+  }
+  fd = open("rn2", 0);
+  if(fd < 0 || read(fd, &c, 1) != 1 || c != 'a'){
+    printf("%s: rn2 has wrong contents\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // Replace an existing file.
+  fd = open("rn1", O_CREATE|O_RDWR);
+  write(fd, "b", 1);
+  close(fd);
+  if(rename("rn1", "rn2") != 0){
+    printf("%s: rename onto a file failed\n", s);
+    exit(1);
+  }
+  fd = open("rn2", 0);
+  if(fd < 0 || read(fd, &c, 1) != 1 || c != 'b'){
+    printf("%s: rn2 not replaced\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // Move a directory into another one, and check its "..".
+  if(mkdir("rnd1") != 0 || mkdir("rnd2") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(rename("rn2", "rnd1/f") != 0){
+    printf("%s: rename into a directory failed\n", s);
+    exit(1);
+  }
+  if(rename("rnd1", "rnd2/d") != 0){
+    printf("%s: rename of a directory failed\n", s);
+    exit(1);
+  }
+  if(rename("rnd2", "rnd2/d/x") == 0){
+    printf("%s: moved a directory into itself\n", s);
+    exit(1);
+  }
+  if(rename("rnd2/d/f", "rnd2") == 0){
+    printf("%s: replaced a directory with a file\n", s);
+    exit(1);
+  }
+  if(chdir("rnd2/d") != 0){
+    printf("%s: chdir rnd2/d failed\n", s);
+    exit(1);
+  }
+  fd = open("../d/f", 0);
+  if(fd < 0){
+    printf("%s: .. of the moved directory is wrong\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(chdir("../..") != 0 || unlink("rnd2/d/f") != 0 || unlink("rnd2/d") != 0
+     || unlink("rnd2") != 0){
+    printf("%s: cleanup failed\n", s);
+    exit(1);
+  }
+}
+
+// lseek, and O_APPEND writes that always go to the end of file.
+void
+lseektest(char *s)
+{
+  int fd, fd2, fds[2];
+  char b[8];
+
+  unlink("lseekf");
+  fd = open("lseekf", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create lseekf failed\n", s);
+    exit(1);
+  }
+  write(fd, "abcdef", 6);
+  if(lseek(fd, 2, SEEK_SET) != 2 || read(fd, b, 2) != 2 || b[0] != 'c' || b[1] != 'd'){
+    printf("%s: SEEK_SET failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, -3, SEEK_CUR) != 1 || read(fd, b, 1) != 1 || b[0] != 'b'){
+    printf("%s: SEEK_CUR failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, -1, SEEK_END) != 5 || read(fd, b, 1) != 1 || b[0] != 'f'){
+    printf("%s: SEEK_END failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, -7, SEEK_END) >= 0 || lseek(fd, 0, 3) >= 0){
+    printf("%s: bad lseek succeeded\n", s);
+    exit(1);
+  }
+  if(lseek(fd, 10, SEEK_SET) != 10 || read(fd, b, 1) != 0){
+    printf("%s: read past the end of file returned data\n", s);
+    exit(1);
+  }
+
+  fd2 = open("lseekf", O_WRONLY|O_APPEND);
+  if(fd2 < 0){
+    printf("%s: open with O_APPEND failed\n", s);
+    exit(1);
+  }
+  lseek(fd, 0, SEEK_SET);
+  write(fd, "x", 1);
+  write(fd2, "gh", 2);
+  write(fd, "y", 1);
+  write(fd2, "i", 1);
+  close(fd2);
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, b, sizeof(b)) != 8 || memcmp(b, "xycdefgh", 8) != 0){
+    printf("%s: O_APPEND wrote at the wrong place\n", s);
+    exit(1);
+  }
+  if(read(fd, b, 1) != 1 || b[0] != 'i'){
+    printf("%s: O_APPEND did not append\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(lseek(fds[0], 0, SEEK_SET) >= 0){
+    printf("%s: lseek on a pipe succeeded\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  unlink("lseekf");
+}
+
+// dup2 and dup3 install the file at the given descriptor, closing the one there.
+void
+dup2test(char *s)
+{
+  int fd, fds[2];
+  char b[4];
+
+  unlink("dup2f");
+  fd = open("dup2f", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create dup2f failed\n", s);
+    exit(1);
+  }
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+
+  // Replacing the write end of the pipe closes it.
+  if(dup2(fd, fds[1]) != fds[1]){
+    printf("%s: dup2 failed\n", s);
+    exit(1);
+  }
+  if(read(fds[0], b, 1) != 0){
+    printf("%s: dup2 did not close the write end\n", s);
+    exit(1);
+  }
+  if(write(fds[1], "ab", 2) != 2 || lseek(fd, 0, SEEK_CUR) != 2){
+    printf("%s: dup2 did not share the offset\n", s);
+    exit(1);
+  }
+
+  if(dup2(fd, fd) != fd || dup2(fd, 1000) >= 0 || dup2(1000, fd) >= 0){
+    printf("%s: dup2 with bad descriptors\n", s);
+    exit(1);
+  }
+  if(dup3(fd, fd, 0) >= 0 || dup3(fd, fds[0], 1) >= 0){
+    printf("%s: dup3 with bad arguments succeeded\n", s);
+    exit(1);
+  }
+  if(dup3(fds[1], fds[0], 0) != fds[0] || read(fds[0], b, sizeof(b)) != 0){
+    printf("%s: dup3 failed\n", s);
+    exit(1);
+  }
+  if(lseek(fds[0], 0, SEEK_SET) != 0 || read(fds[0], b, sizeof(b)) != 2 || b[0] != 'a'){
+    printf("%s: dup3 installed the wrong file\n", s);
+    exit(1);
+  }
+
+  close(fd);
+  close(fds[0]);
+  close(fds[1]);
+  unlink("dup2f");
+}
+
+// pread and pwrite use the given offset and leave the offset of the file alone.
+void
+preadtest(char *s)
+{
+  int fd, fd2, fds[2], i, pid, xstatus;
+  char b[8];
+
+  unlink("preadf");
+  fd = open("preadf", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create preadf failed\n", s);
+    exit(1);
+  }
+  write(fd, "abcdef", 6);
+  lseek(fd, 1, SEEK_SET);
+  if(pread(fd, b, 3, 2) != 3 || memcmp(b, "cde", 3) != 0 || lseek(fd, 0, SEEK_CUR) != 1){
+    printf("%s: pread failed\n", s);
+    exit(1);
+  }
+  if(pwrite(fd, "XY", 2, 4) != 2 || lseek(fd, 0, SEEK_CUR) != 1){
+    printf("%s: pwrite failed\n", s);
+    exit(1);
+  }
+  if(pread(fd, b, sizeof(b), 0) != 6 || memcmp(b, "abcdXY", 6) != 0){
+    printf("%s: pwrite wrote at the wrong place\n", s);
+    exit(1);
+  }
+  if(pread(fd, b, 1, 10) != 0 || pread(fd, b, 1, -1) >= 0 || pwrite(fd, b, 1, -1) >= 0){
+    printf("%s: pread or pwrite with bad offsets\n", s);
+    exit(1);
+  }
+
+  // pwrite ignores O_APPEND.
+  fd2 = open("preadf", O_WRONLY|O_APPEND);
+  if(fd2 < 0 || pwrite(fd2, "Z", 1, 0) != 1 || pread(fd2, b, 1, 0) >= 0){
+    printf("%s: pwrite with O_APPEND failed\n", s);
+    exit(1);
+  }
+  close(fd2);
+  if(pread(fd, b, 1, 0) != 1 || b[0] != 'Z'){
+    printf("%s: pwrite with O_APPEND appended\n", s);
+    exit(1);
+  }
+
+  // Processes sharing the file write their own bytes without racing for the offset.
+  for(i = 0; i < 4; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      b[0] = '0' + i;
+      exit(pwrite(fd, b, 1, 2 + i) != 1);
+    }
+  }
+  for(i = 0; i < 4; i++){
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(1);
+  }
+  if(pread(fd, b, sizeof(b), 0) != 6 || memcmp(b, "Zb0123", 6) != 0 || lseek(fd, 0, SEEK_CUR) != 1){
+    printf("%s: concurrent pwrite failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(pwrite(fds[1], "a", 1, 0) >= 0 || pread(fds[0], b, 1, 0) >= 0){
+    printf("%s: pread or pwrite on a pipe succeeded\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  unlink("preadf");
+}
+
+// ftruncate shrinks a file, freeing its tail, and grows it with zeros.
+void
+ftruncatetest(char *s)
+{
+  int fd, fds[2], i;
+  struct stat st;
+
+  unlink("ftruncf");
+  fd = open("ftruncf", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create ftruncf failed\n", s);
+    exit(1);
+  }
+  memset(buf, 'a', 3*BSIZE);
+  if(write(fd, buf, 3*BSIZE) != 3*BSIZE){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+
+  if(ftruncate(fd, BSIZE+10) != 0 || fstat(fd, &st) != 0 || st.size != BSIZE+10){
+    printf("%s: shrinking failed\n", s);
+    exit(1);
+  }
+  // The offset stays past the end of file.
+  if(lseek(fd, 0, SEEK_CUR) != 3*BSIZE || read(fd, buf, 1) != 0){
+    printf("%s: read past the end of file\n", s);
+    exit(1);
+  }
+  if(pread(fd, buf, BSIZE, BSIZE) != 10){
+    printf("%s: pread after shrinking failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 10; i++){
+    if(buf[i] != 'a'){
+      printf("%s: shrinking lost data\n", s);
+      exit(1);
+    }
+  }
+
+  // Growing brings back zeros, not the old content.
+  if(ftruncate(fd, 3*BSIZE) != 0 || fstat(fd, &st) != 0 || st.size != 3*BSIZE){
+    printf("%s: growing failed\n", s);
+    exit(1);
+  }
+  if(pread(fd, buf, 2*BSIZE, BSIZE) != 2*BSIZE){
+    printf("%s: pread after growing failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 2*BSIZE; i++){
+    if(buf[i] != (i < 10 ? 'a' : 0)){
+      printf("%s: growing did not zero byte %d\n", s, i);
+      exit(1);
+    }
+  }
+
+  if(ftruncate(fd, -1) >= 0 || ftruncate(fd, 0) != 0 || fstat(fd, &st) != 0 || st.size != 0){
+    printf("%s: truncating to zero failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("ftruncf", O_RDONLY);
+  if(fd < 0 || ftruncate(fd, 1) >= 0){
+    printf("%s: ftruncate on a read-only file succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(ftruncate(fds[1], 0) >= 0){
+    printf("%s: ftruncate on a pipe succeeded\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  unlink("ftruncf");
+}
+
+// futex wakes up only as many waiters as asked for, and FUTEX_WAIT sleeps only on the
+// expected value.
+void
+futextest(char *s)
+{
+  int word = 1;
+
+  if(futex(&word, FUTEX_WAIT, 0) >= 0){
+    printf("%s: FUTEX_WAIT on a changed word succeeded\n", s);
+    exit(1);
+  }
+  if(futex(&word, FUTEX_WAKE, 1) != 0){
+    printf("%s: FUTEX_WAKE woke up a waiter of nothing\n", s);
+    exit(1);
+  }
+  if(futex(&word, FUTEX_WAKE, -1) >= 0 || futex((int*)((char*)&word + 1), FUTEX_WAKE, 1) >= 0){
+    printf("%s: FUTEX_WAKE with bad arguments succeeded\n", s);
+    exit(1);
+  }
+}
+
+// fcntl gets and sets the close-on-exec flag of a descriptor and O_NONBLOCK of its file.
+void
+fcntltest(char *s)
+{
+  int fds[2], fd, pid, xstatus;
+  char c;
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fds[0], F_GETFL, 0) != O_RDONLY || fcntl(fds[1], F_GETFL, 0) != O_WRONLY){
+    printf("%s: F_GETFL returned wrong flags\n", s);
+    exit(1);
+  }
+
+  // A nonblocking read from an empty pipe fails with EAGAIN, and succeeds once there is data.
+  if(fcntl(fds[0], F_SETFL, O_NONBLOCK) != 0 || fcntl(fds[0], F_GETFL, 0) != (O_RDONLY|O_NONBLOCK)){
+    printf("%s: F_SETFL failed\n", s);
+    exit(1);
+  }
+  if(read(fds[0], &c, 1) >= 0 || errno != EAGAIN){
+    printf("%s: nonblocking read from an empty pipe did not fail with EAGAIN\n", s);
+    exit(1);
+  }
+  if(write(fds[1], "x", 1) != 1 || read(fds[0], &c, 1) != 1 || c != 'x'){
+    printf("%s: nonblocking read with data failed\n", s);
+    exit(1);
+  }
+
+  // O_NONBLOCK belongs to the file, shared by duplicated descriptors.
+  fd = dup(fds[0]);
+  if(fd < 0 || (fcntl(fd, F_GETFL, 0) & O_NONBLOCK) == 0){
+    printf("%s: dup lost O_NONBLOCK\n", s);
+    exit(1);
+  }
+
+  // The close-on-exec flag belongs to the descriptor, and is inherited by fork.
+  if(fcntl(fds[0], F_GETFD, 0) != 0 || fcntl(fds[0], F_SETFD, FD_CLOEXEC) != 0 ||
+     fcntl(fds[0], F_GETFD, 0) != FD_CLOEXEC || fcntl(fd, F_GETFD, 0) != 0){
+    printf("%s: F_SETFD failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(fcntl(fds[0], F_GETFD, 0) != FD_CLOEXEC);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: fork lost FD_CLOEXEC\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // dup3 with O_CLOEXEC marks the new descriptor; dup2 clears the mark.
+  fd = dup3(fds[1], NOFILE-1, O_CLOEXEC);
+  if(fd != NOFILE-1 || fcntl(fd, F_GETFD, 0) != FD_CLOEXEC || dup2(fds[1], fd) != fd || fcntl(fd, F_GETFD, 0) != 0){
+    printf("%s: dup3 with O_CLOEXEC failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fd, 100, 0) >= 0){
+    printf("%s: fcntl with a bad command succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(fds[0]);
+  close(fds[1]);
+}
+
+// files grow past the indirect block into the double-indirect one, up to MAXFILE blocks.
+void
+hugefile(char *s)
+{
+  enum { N = NDIRECT + 4*NINDIRECT };
+  int fd, i;
+  struct stat st;
+
+  unlink("hugef");
+  fd = open("hugef", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create hugef failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    ((int*)buf)[0] = i;
+    if(write(fd, buf, BSIZE) != BSIZE){
+      printf("%s: write of block %d failed\n", s, i);
+      exit(1);
+    }
+  }
+  for(i = 0; i < N; i++){
+    if(pread(fd, buf, BSIZE, i*BSIZE) != BSIZE || ((int*)buf)[0] != i){
+      printf("%s: read of block %d failed\n", s, i);
+      exit(1);
+    }
+  }
+
+  // The last block of the largest file, and no further.
+  ((int*)buf)[0] = MAXFILE - 1;
+  if(pwrite(fd, buf, BSIZE, (MAXFILE-1)*BSIZE) != BSIZE){
+    printf("%s: write of the last block failed\n", s);
+    exit(1);
+  }
+  if(pwrite(fd, buf, 1, MAXFILE*BSIZE) >= 0 || ftruncate(fd, MAXFILE*BSIZE+1) >= 0){
+    printf("%s: file grew past MAXFILE\n", s);
+    exit(1);
+  }
+  ((int*)buf)[0] = 0;
+  if(fstat(fd, &st) != 0 || st.size != MAXFILE*BSIZE ||
+     pread(fd, buf, BSIZE, (MAXFILE-1)*BSIZE) != BSIZE || ((int*)buf)[0] != MAXFILE - 1){
+    printf("%s: read of the last block failed\n", s);
+    exit(1);
+  }
+
+  // Shrinking into the indirect block frees the double-indirect one; the rest stays.
+  if(ftruncate(fd, (NDIRECT+10)*BSIZE) != 0 || ftruncate(fd, MAXFILE*BSIZE) != 0){
+    printf("%s: ftruncate failed\n", s);
+    exit(1);
+  }
+  if(pread(fd, buf, BSIZE, (NDIRECT+9)*BSIZE) != BSIZE || ((int*)buf)[0] != NDIRECT+9){
+    printf("%s: shrinking lost data\n", s);
+    exit(1);
+  }
+  for(i = NDIRECT+10; i < N; i += NINDIRECT/2){
+    if(pread(fd, buf, BSIZE, i*BSIZE) != BSIZE || ((int*)buf)[0] != 0){
+      printf("%s: block %d not zero after shrinking\n", s, i);
+      exit(1);
+    }
+  }
+  close(fd);
+  unlink("hugef");
+}
+
+// files written at the same time, overwritten in the middle, and truncated and regrown
+// read back what was written, however their blocks are laid out.
+void
+extenttest(char *s)
+{
+  enum { N = NDIRECT + 40 };
+  int fds[2], i, j;
+  char *names[2] = { "extf0", "extf1" };
+
+  for(j = 0; j < 2; j++){
+    unlink(names[j]);
+    fds[j] = open(names[j], O_CREATE|O_RDWR);
+    if(fds[j] < 0){
+      printf("%s: create %s failed\n", s, names[j]);
+      exit(1);
+    }
+  }
+  // Interleave the allocations of the two files.
+  for(i = 0; i < N; i++){
+    for(j = 0; j < 2; j++){
+      ((int*)buf)[0] = i;
+      ((int*)buf)[1] = j;
+      if(write(fds[j], buf, BSIZE) != BSIZE){
+        printf("%s: write failed\n", s);
+        exit(1);
+      }
+    }
+  }
+  ((int*)buf)[0] = -1;
+  ((int*)buf)[1] = 0;
+  if(pwrite(fds[0], buf, BSIZE, (N/2)*BSIZE) != BSIZE){
+    printf("%s: overwrite failed\n", s);
+    exit(1);
+  }
+  if(ftruncate(fds[1], (NDIRECT+3)*BSIZE) != 0 || lseek(fds[1], 0, SEEK_END) < 0){
+    printf("%s: ftruncate failed\n", s);
+    exit(1);
+  }
+  for(i = NDIRECT+3; i < N; i++){
+    ((int*)buf)[0] = 2*i;
+    ((int*)buf)[1] = 1;
+    if(write(fds[1], buf, BSIZE) != BSIZE){
+      printf("%s: rewrite failed\n", s);
+      exit(1);
+    }
+  }
+
+  for(j = 0; j < 2; j++){
+    for(i = 0; i < N; i++){
+      int want = i;
+      if(j == 0 && i == N/2)
+        want = -1;
+      if(j == 1 && i >= NDIRECT+3)
+        want = 2*i;
+      if(pread(fds[j], buf, BSIZE, i*BSIZE) != BSIZE ||
+         ((int*)buf)[0] != want || ((int*)buf)[1] != (want == -1 ? 0 : j)){
+        printf("%s: block %d of %s is wrong\n", s, i, names[j]);
+        exit(1);
+      }
+    }
+    close(fds[j]);
+    unlink(names[j]);
+  }
+}
+
+// names in a directory big enough to be indexed are found,
+// and freed entries are reused.
+void
+dirhashtest(char *s)
+{
+  enum { N = 3 * BSIZE / sizeof(struct dirent) };
+  int i, fd;
+  char name[DIRSIZ];
+  struct stat st;
+
+  if(mkdir("dh") != 0 || chdir("dh") != 0){
+    printf("%s: mkdir dh failed\n", s);
+    exit(1);
+  }
+  fd = open("f", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create f failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  name[0] = 'h';
+  name[3] = '\0';
+  for(i = 0; i < N; i++){
+    name[1] = '0' + i / 64;
+    name[2] = '0' + i % 64;
+    if(link("f", name) != 0){
+      printf("%s: link %s failed\n", s, name);
+      exit(1);
+    }
+  }
+  for(i = 0; i < N; i += 2){
+    name[1] = '0' + i / 64;
+    name[2] = '0' + i % 64;
+    if(link("f", name) == 0){
+      printf("%s: link to existing %s succeeded\n", s, name);
+      exit(1);
+    }
+    if(unlink(name) != 0){
+      printf("%s: unlink %s failed\n", s, name);
+      exit(1);
+    }
+  }
+  for(i = 0; i < N; i++){
+    name[1] = '0' + i / 64;
+    name[2] = '0' + i % 64;
+    if((stat(name, &st) == 0) != (i % 2 == 1)){
+      printf("%s: stat %s wrong\n", s, name);
+      exit(1);
+    }
+  }
+
+  // The removed entries are reused, so the directory does not grow.
+  for(i = 0; i < N; i += 2){
+    name[1] = '0' + i / 64;
+    name[2] = '0' + i % 64;
+    if(link("f", name) != 0){
+      printf("%s: relink %s failed\n", s, name);
+      exit(1);
+    }
+  }
+  fd = open(".", O_RDONLY);
+  if(fd < 0 || fstat(fd, &st) != 0 || st.size > (N + 3) * sizeof(struct dirent)){
+    printf("%s: directory grew\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  for(i = 0; i < N; i++){
+    name[1] = '0' + i / 64;
+    name[2] = '0' + i % 64;
+    if(unlink(name) != 0){
+      printf("%s: final unlink %s failed\n", s, name);
+      exit(1);
+    }
+  }
+  if(unlink("f") != 0 || chdir("..") != 0 || unlink("dh") != 0){
+    printf("%s: cleanup failed\n", s);
+    exit(1);
+  }
+}
+
+// a program built as a static Linux program runs, and its
+// output through writev() reaches the pipe.
+void
+linuxabitest(char *s)
+{
+  int fds[2], pid, xstatus, n, tot;
+  char out[32];
+  char *args[] = { "linuxhello", "arg", 0 };
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(1);
+    dup(fds[1]);
+    close(fds[0]);
+    close(fds[1]);
+    exec("linuxhello", args);
+    exit(100);
+  }
+  close(fds[1]);
+  tot = 0;
+  while((n = read(fds[0], out + tot, sizeof(out) - 1 - tot)) > 0)
+    tot += n;
+  out[tot] = '\0';
+  close(fds[0]);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: linuxhello exited with %d\n", s, xstatus);
+    exit(1);
+  }
+  if(strcmp(out, "hello, world\n") != 0){
+    printf("%s: wrong output %s\n", s, out);
+    exit(1);
+  }
+}
+
+// creating, writing and reading a file set its timestamps,
+// and utimes() and futimens() set them as asked.
+void
+timestest(char *s)
+{
+  struct stat st;
+  struct timespec now, times[2];
+  int fd;
+  char c;
+
+  unlink("ut");
+  if(clock_gettime(CLOCK_REALTIME, &now) < 0){
+    printf("%s: clock_gettime failed\n", s);
+    exit(1);
+  }
+  fd = open("ut", O_CREATE|O_RDWR);
+  if(fd < 0 || fstat(fd, &st) < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  if(st.atime < now.tv_sec || st.mtime < now.tv_sec || st.ctime < now.tv_sec){
+    printf("%s: bad times of a new file\n", s);
+    exit(1);
+  }
+
+  times[0].tv_sec = 1000;
+  times[0].tv_nsec = 0;
+  times[1].tv_sec = 2000;
+  times[1].tv_nsec = 0;
+  if(utimes("ut", times) < 0 || stat("ut", &st) < 0){
+    printf("%s: utimes failed\n", s);
+    exit(1);
+  }
+  if(st.atime != 1000 || st.mtime != 2000 || st.ctime < now.tv_sec){
+    printf("%s: utimes set wrong times\n", s);
+    exit(1);
+  }
+
+  times[0].tv_nsec = UTIME_OMIT;
+  times[1].tv_sec = 3000;
+  if(futimens(fd, times) < 0 || fstat(fd, &st) < 0){
+    printf("%s: futimens failed\n", s);
+    exit(1);
+  }
+  if(st.atime != 1000 || st.mtime != 3000){
+    printf("%s: futimens set wrong times\n", s);
+    exit(1);
+  }
+
+  if(write(fd, "x", 1) != 1 || fstat(fd, &st) < 0 || st.mtime < now.tv_sec || st.atime != 1000){
+    printf("%s: write did not set mtime\n", s);
+    exit(1);
+  }
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, &c, 1) != 1 || fstat(fd, &st) < 0 || st.atime < now.tv_sec){
+    printf("%s: read did not set atime\n", s);
+    exit(1);
+  }
+
+  times[0].tv_sec = 1;
+  times[0].tv_nsec = 1000000000;
+  if(utimes("ut", times) >= 0){
+    printf("%s: utimes took a bad timespec\n", s);
+    exit(1);
+  }
+  if(utimes("ut-missing", 0) >= 0){
+    printf("%s: utimes of a missing file succeeded\n", s);
+    exit(1);
+  }
+  if(futimens(fd, 0) < 0 || fstat(fd, &st) < 0 || st.atime < now.tv_sec || st.mtime < now.tv_sec){
+    printf("%s: futimens(fd, 0) failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("ut");
+}
+
+// read() into a page-aligned buffer maps the cached pages of the
+// file, which are copied on the first write by the user or the
+// kernel, and keep their content while the file changes.
+void
+zerocopytest(char *s)
+{
+  enum { SZ = 2*PGSIZE + 100 };
+  char *p, *q, *m;
+  int i, fd, pid, xstatus, fds[2];
+
+  fd = open("zc", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create zc failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < SZ; i++)
+    buf[i] = 'a' + i / PGSIZE;
+  if(write(fd, buf, SZ) != SZ){
+    printf("%s: write zc failed\n", s);
+    exit(1);
+  }
+  p = sbrk(7*PGSIZE);
+  if(p == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  p += PGSIZE - (uint64)p % PGSIZE;
+  q = p + 3*PGSIZE;
+
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, p, SZ) != SZ){
+    printf("%s: read zc failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < SZ; i++){
+    if(p[i] != 'a' + i / PGSIZE){
+      printf("%s: wrong byte %d read\n", s, i);
+      exit(1);
+    }
+  }
+  p[0] = 'X';
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, q, SZ) != SZ || q[0] != 'a' || p[1] != 'a'){
+    printf("%s: a write to the buffer reached the file\n", s);
+    exit(1);
+  }
+
+  // The kernel copies the page too.
+  if(pipe(fds) < 0 || write(fds[1], "Y", 1) != 1 || read(fds[0], q + PGSIZE, 1) != 1){
+    printf("%s: read from a pipe failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  if(q[PGSIZE] != 'Y' || q[PGSIZE+1] != 'b' || p[PGSIZE] != 'b'){
+    printf("%s: a read from a pipe went wrong\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    q[1] = 'Z';
+    exit(q[2] == 'a' ? 0 : 1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || q[1] != 'a'){
+    printf("%s: a write of the child reached the parent\n", s);
+    exit(1);
+  }
+
+  // A write() to the file is not seen by the buffers read before.
+  if(lseek(fd, PGSIZE, SEEK_SET) != PGSIZE || write(fd, "W", 1) != 1){
+    printf("%s: write zc failed\n", s);
+    exit(1);
+  }
+  if(p[PGSIZE] != 'b' || q[PGSIZE+1] != 'b'){
+    printf("%s: a write to the file reached the buffer\n", s);
+    exit(1);
+  }
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, p, SZ) != SZ || p[PGSIZE] != 'W'){
+    printf("%s: read after write saw old content\n", s);
+    exit(1);
+  }
+
+  // Nor is a write through a shared mapping, which is written back.
+  m = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0);
+  if(m == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  m[0] = 'M';
+  if(p[0] != 'a'){
+    printf("%s: a write to a mapping reached the buffer\n", s);
+    exit(1);
+  }
+  munmap(m, PGSIZE);
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, q, PGSIZE) != PGSIZE || q[0] != 'M'){
+    printf("%s: read after munmap saw old content\n", s);
+    exit(1);
+  }
+
+  // An unaligned buffer is copied into.
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, p + 1, PGSIZE) != PGSIZE ||
+     p[1] != 'M' || p[PGSIZE] != 'a'){
+    printf("%s: read into an unaligned buffer failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("zc");
+  sbrk(-7*PGSIZE);
+}
+
+// files are owned by their creator, and the permission bits
+// decide who may open, create, unlink and execute them; only the
+// superuser and the owner may chmod(), and only the superuser chown().
+void
+permtest(char *s)
+{
+  struct stat st;
+  char *args[] = { "echo", 0 };
+  int fd, fd2, n, pid, xstatus;
+
+  if(mkdir("permdir") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  fd = open("permdir/f", O_CREATE|O_WRONLY);
+  if(fd < 0 || write(fd, "secret", 6) != 6){
+    printf("%s: create permdir/f failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(stat("permdir/f", &st) < 0 || st.mode != 0644 || st.uid != 0 || st.gid != 0){
+    printf("%s: new file has mode %x, owner %d:%d\n", s, st.mode, st.uid, st.gid);
+    exit(1);
+  }
+  if(chmod("permdir/f", 01600) != -EINVAL || chmod("permdir/f", 0600) < 0 ||
+     stat("permdir/f", &st) < 0 || st.mode != 0600){
+    printf("%s: chmod failed\n", s);
+    exit(1);
+  }
+
+  // A copy of echo can be executed only while it has an execute bit,
+  // even by the superuser.
+  fd = open("echo", O_RDONLY);
+  fd2 = open("permdir/echo", O_CREATE|O_WRONLY);
+  if(fd < 0 || fd2 < 0){
+    printf("%s: open echo failed\n", s);
+    exit(1);
+  }
+  while((n = read(fd, buf, BUFSZ)) > 0){
+    if(write(fd2, buf, n) != n){
+      printf("%s: copy echo failed\n", s);
+      exit(1);
+    }
+  }
+  close(fd);
+  close(fd2);
+  pid = fork();
+  if(pid == 0){
+    exec("permdir/echo", args);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: executed a file without an execute bit\n", s);
+    exit(1);
+  }
+  if(chmod("permdir/echo", 0755) < 0){
+    printf("%s: chmod echo failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid == 0){
+    exec("permdir/echo", args);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: exec of an executable file failed\n", s);
+    exit(1);
+  }
+
+  // Another user may not read the file, nor change the directory.
+  pid = fork();
+  if(pid == 0){
+    if(setuid(1) < 0 || getuid() != 1 || geteuid() != 1 || setuid(0) != -EPERM ||
+       setgid(1) != -EPERM){
+      printf("%s: setuid failed\n", s);
+      exit(1);
+    }
+    if(open("permdir/f", O_RDONLY) >= 0 || open("permdir/g", O_CREATE|O_RDWR) >= 0 ||
+       unlink("permdir/f") == 0 || rename("permdir/f", "permdir/g") == 0){
+      printf("%s: another user accessed permdir\n", s);
+      exit(1);
+    }
+    if(chmod("permdir/f", 0666) != -EPERM || chown("permdir/f", 1, 1) != -EPERM){
+      printf("%s: another user changed permdir/f\n", s);
+      exit(1);
+    }
+    if(rgroup_set(1, 1, 0) != -EPERM || rgroup_join(1) != -EPERM){
+      printf("%s: another user changed a resource group\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+
+  // Once it owns them, it may.
+  if(chown("permdir", 1, -1) < 0 || chown("permdir/f", 1, 1) < 0 ||
+     stat("permdir/f", &st) < 0 || st.uid != 1 || st.gid != 1 || st.mode != 0600){
+    printf("%s: chown failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid == 0){
+    if(setuid(1) < 0)
+      exit(1);
+    fd = open("permdir/f", O_RDWR);
+    if(fd < 0 || read(fd, buf, 6) != 6 || memcmp(buf, "secret", 6) != 0){
+      printf("%s: the owner could not read permdir/f\n", s);
+      exit(1);
+    }
+    close(fd);
+    fd = open("permdir/g", O_CREATE|O_RDWR);
+    if(fd < 0 || fstat(fd, &st) < 0 || st.uid != 1 || st.gid != 0){
+      printf("%s: the owner could not create permdir/g\n", s);
+      exit(1);
+    }
+    close(fd);
+    if(unlink("permdir/g") < 0 || chmod("permdir/f", 0) < 0 || open("permdir/f", O_RDONLY) >= 0){
+      printf("%s: the owner could not change permdir\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+
+  // The superuser may read anything.
+  fd = open("permdir/f", O_RDONLY);
+  if(fd < 0){
+    printf("%s: the superuser could not open permdir/f\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(unlink("permdir/f") < 0 || unlink("permdir/echo") < 0 || unlink("permdir") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+}
+
+// small writes are gathered in memory until they leave the block,
+// but every descriptor of the file, in any process, sees them at once,
+// and ftruncate() and close() write them to the disk.
+void
+coalescetest(char *s)
+{
+  struct stat st;
+  char c;
+  int fd, fd2, i, pid, xstatus;
+  int n = 2*BSIZE + 100;
+
+  unlink("coalesce");
+  fd = open("coalesce", O_CREATE|O_RDWR);
+  fd2 = open("coalesce", O_RDONLY);
+  if(fd < 0 || fd2 < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < n; i++){
+    c = 'a' + i % 26;
+    if(write(fd, &c, 1) != 1){
+      printf("%s: write %d failed\n", s, i);
+      exit(1);
+    }
+    if(fstat(fd2, &st) < 0 || st.size != i + 1 || pread(fd2, buf, 1, i) != 1 || buf[0] != c){
+      printf("%s: byte %d is not visible\n", s, i);
+      exit(1);
+    }
+  }
+
+  // Another process reads the staged bytes through its own descriptor.
+  pid = fork();
+  if(pid == 0){
+    fd = open("coalesce", O_RDONLY);
+    if(fd < 0 || read(fd, buf, BUFSZ) != n){
+      printf("%s: child read failed\n", s);
+      exit(1);
+    }
+    for(i = 0; i < n; i++){
+      if(buf[i] != 'a' + i % 26){
+        printf("%s: child read wrong byte %d\n", s, i);
+        exit(1);
+      }
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+
+  // Appends through another descriptor follow the staged bytes.
+  if(lseek(fd, 0, SEEK_END) != n || write(fd, "xy", 2) != 2){
+    printf("%s: lseek failed\n", s);
+    exit(1);
+  }
+  close(fd2);
+  fd2 = open("coalesce", O_WRONLY|O_APPEND);
+  if(fd2 < 0 || write(fd2, "z", 1) != 1 || pread(fd, buf, 3, n) != 3 ||
+     memcmp(buf, "xyz", 3) != 0){
+    printf("%s: append failed\n", s);
+    exit(1);
+  }
+  close(fd2);
+
+  // Truncating drops the staged bytes past the new end.
+  if(write(fd, "w", 1) != 1 || ftruncate(fd, n + 1) < 0 ||
+     fstat(fd, &st) < 0 || st.size != n + 1){
+    printf("%s: ftruncate failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, n + 1, SEEK_SET) != n + 1 || write(fd, "q", 1) != 1){
+    printf("%s: write after ftruncate failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("coalesce", O_RDONLY);
+  if(fd < 0 || read(fd, buf, BUFSZ) != n + 2 || buf[n] != 'x' || buf[n + 1] != 'q'){
+    printf("%s: reopened file is wrong\n", s);
+    exit(1);
+  }
+  for(i = 0; i < n; i++){
+    if(buf[i] != 'a' + i % 26){
+      printf("%s: reopened file has wrong byte %d\n", s, i);
+      exit(1);
+    }
+  }
+  close(fd);
+  unlink("coalesce");
+}
+
+// fsync() writes out the staged bytes of a file and commits the log;
+// it refuses pipes.
+void
+fsynctest(char *s)
+{
+  struct stat st;
+  int fd, fds[2];
+
+  fd = open("fsync", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "durable", 7) != 7){
+    printf("%s: create fsync failed\n", s);
+    exit(1);
+  }
+  if(fsync(fd) != 0 || fstat(fd, &st) < 0 || st.size != 7){
+    printf("%s: fsync failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(fsync(fd) != -EBADF || fsync(1) != 0){
+    printf("%s: fsync of a closed fd or the console failed\n", s);
+    exit(1);
+  }
+  if(pipe(fds) < 0 || fsync(fds[0]) != -EINVAL){
+    printf("%s: fsync of a pipe succeeded\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  unlink("fsync");
+}
+
+// O_DIRECT reads and writes move whole blocks between the disk and
+// the user buffer, which must be aligned, yet agree with buffered
+// reads and writes of the same file.
+void
+directtest(char *s)
+{
+  char *p = (char*)(((uint64)buf + BSIZE - 1) & ~(uint64)(BSIZE - 1));
+  int fd, fd2, i;
+
+  fd = open("direct", O_CREATE|O_RDWR|O_DIRECT);
+  fd2 = open("direct", O_RDWR);
+  if(fd < 0 || fd2 < 0 || !(fcntl(fd, F_GETFL, 0) & O_DIRECT)){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  if(write(fd, p + 1, BSIZE) != -EINVAL || write(fd, p, 100) != -EINVAL){
+    printf("%s: unaligned write succeeded\n", s);
+    exit(1);
+  }
+
+  for(i = 0; i < 2*BSIZE; i++)
+    p[i] = i % 251;
+  if(write(fd, p, 2*BSIZE) != 2*BSIZE){
+    printf("%s: direct write failed\n", s);
+    exit(1);
+  }
+  memset(p, 0, 2*BSIZE);
+  if(read(fd2, p, 2*BSIZE) != 2*BSIZE){
+    printf("%s: buffered read failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 2*BSIZE; i++){
+    if(p[i] != (char)(i % 251)){
+      printf("%s: buffered read saw wrong byte %d\n", s, i);
+      exit(1);
+    }
+  }
+
+  // A direct read sees buffered writes, even ones still in memory.
+  if(pwrite(fd2, "hello", 5, BSIZE) != 5 || write(fd2, "tail", 4) != 4 || write(fd2, "!", 1) != 1){
+    printf("%s: buffered write failed\n", s);
+    exit(1);
+  }
+  if(lseek(fd, 1, SEEK_SET) != 1 || read(fd, p, BSIZE) != -EINVAL){
+    printf("%s: unaligned read succeeded\n", s);
+    exit(1);
+  }
+  memset(p, 0, 3*BSIZE);
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, p, 3*BSIZE) != 2*BSIZE + 5){
+    printf("%s: direct read failed\n", s);
+    exit(1);
+  }
+  if(p[0] != 0 || memcmp(p + BSIZE, "hello", 5) != 0 || p[BSIZE + 5] != (char)((BSIZE + 5) % 251) ||
+     memcmp(p + 2*BSIZE, "tail!", 5) != 0){
+    printf("%s: direct read saw wrong data\n", s);
+    exit(1);
+  }
+  if(read(fd, p, BSIZE) != 0){
+    printf("%s: direct read past the end of file\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(fd2);
+  unlink("direct");
+}
+
+// fswatch() reports the entries created in and removed from a watched
+// directory, and the writes to a watched file.
+void
+fswatchtest(char *s)
+{
+  struct fsevent ev[4];
+  struct stat st;
+  int w, w2, fd;
+
+  if(mkdir("fswatchdir") < 0){
+    printf("%s: mkdir fswatchdir failed\n", s);
+    exit(1);
+  }
+  if(fswatch("fswatchnone", FSW_CREATE) >= 0){
+    printf("%s: watched a missing file\n", s);
+    exit(1);
+  }
+  w = fswatch("fswatchdir", FSW_CREATE|FSW_UNLINK);
+  if(w < 0){
+    printf("%s: fswatch fswatchdir failed\n", s);
+    exit(1);
+  }
+  fd = open("fswatchdir/f", O_CREATE|O_RDWR);
+  if(fd < 0 || fstat(fd, &st) < 0){
+    printf("%s: create fswatchdir/f failed\n", s);
+    exit(1);
+  }
+  if(read(w, ev, sizeof(ev)) != sizeof(ev[0]) || ev[0].mask != FSW_CREATE ||
+     ev[0].inum != st.ino || strcmp(ev[0].name, "f") != 0){
+    printf("%s: no create event\n", s);
+    exit(1);
+  }
+  w2 = fswatch("fswatchdir/f", FSW_WRITE);
+  if(w2 < 0){
+    printf("%s: fswatch fswatchdir/f failed\n", s);
+    exit(1);
+  }
+  // The directory's watch does not take writes, nor the file's unlinks.
+  if(write(fd, "ab", 2) != 2 || write(fd, "cd", 2) != 2 || unlink("fswatchdir/f") < 0){
+    printf("%s: write or unlink fswatchdir/f failed\n", s);
+    exit(1);
+  }
+  if(read(w, ev, sizeof(ev)) != sizeof(ev[0]) || ev[0].mask != FSW_UNLINK ||
+     ev[0].inum != st.ino || strcmp(ev[0].name, "f") != 0){
+    printf("%s: no unlink event\n", s);
+    exit(1);
+  }
+  if(fcntl(w, F_SETFL, O_NONBLOCK) < 0 || read(w, ev, sizeof(ev)) != -EAGAIN){
+    printf("%s: nonblocking read of no event did not fail\n", s);
+    exit(1);
+  }
+  if(read(w2, ev, 1) != -EINVAL){
+    printf("%s: read of part of an event did not fail\n", s);
+    exit(1);
+  }
+  if(read(w2, ev, sizeof(ev)) != 2 * sizeof(ev[0]) || ev[0].mask != FSW_WRITE ||
+     ev[1].mask != FSW_WRITE || ev[0].inum != st.ino || ev[0].name[0] != 0){
+    printf("%s: no write events\n", s);
+    exit(1);
+  }
+  close(w2);
+  close(w);
+  close(fd);
+  if(unlink("fswatchdir") < 0){
+    printf("%s: unlink fswatchdir failed\n", s);
+    exit(1);
+  }
+}
+
+// getdents_plus() reads the entries in use of a directory, each with
+// the status that stat() would return, however few fit in the buffer.
+void
+getdentstest(char *s)
+{
+  struct direntplus des[2];
+  struct stat ast, dst;
+  int dfd, fd, n, seen = 0;
+
+  if(mkdir("gdpdir") < 0 || mkdir("gdpdir/c") < 0){
+    printf("%s: mkdir gdpdir failed\n", s);
+    exit(1);
+  }
+  fd = open("gdpdir/a", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "hello", 5) != 5){
+    printf("%s: create gdpdir/a failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  fd = open("gdpdir/b", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create gdpdir/b failed\n", s);
+    exit(1);
+  }
+  if(getdents_plus(fd, des, sizeof(des)) != -ENOTDIR){
+    printf("%s: getdents_plus of a file did not fail\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(unlink("gdpdir/b") < 0){
+    printf("%s: unlink gdpdir/b failed\n", s);
+    exit(1);
+  }
+
+  if(stat("gdpdir", &dst) < 0 || stat("gdpdir/a", &ast) < 0){
+    printf("%s: stat gdpdir failed\n", s);
+    exit(1);
+  }
+  dfd = open("gdpdir", O_RDONLY);
+  if(dfd < 0 || getdents_plus(dfd, des, sizeof(des[0]) - 1) != -EINVAL){
+    printf("%s: getdents_plus into a short buffer did not fail\n", s);
+    exit(1);
+  }
+  // One entry at a time, so that the offset moves past the free entry.
+  while((n = getdents_plus(dfd, des, sizeof(des[0]))) > 0){
+    if(n != sizeof(des[0]) || des[0].de.inum != des[0].st.ino){
+      printf("%s: getdents_plus returned %d\n", s, n);
+      exit(1);
+    }
+    if(strcmp(des[0].de.name, "a") == 0){
+      if(des[0].st.type != T_FILE || des[0].st.size != 5 || des[0].st.ino != ast.ino ||
+         des[0].st.mtime != ast.mtime){
+        printf("%s: wrong status of gdpdir/a\n", s);
+        exit(1);
+      }
+      seen |= 1;
+    } else if(strcmp(des[0].de.name, "c") == 0){
+      if(des[0].st.type != T_DIR){
+        printf("%s: wrong status of gdpdir/c\n", s);
+        exit(1);
+      }
+      seen |= 2;
+    } else if(strcmp(des[0].de.name, ".") == 0){
+      if(des[0].st.ino != dst.ino || des[0].st.nlink != dst.nlink){
+        printf("%s: wrong status of gdpdir\n", s);
+        exit(1);
+      }
+      seen |= 4;
+    } else if(strcmp(des[0].de.name, "..") != 0){
+      printf("%s: unexpected entry %s\n", s, des[0].de.name);
+      exit(1);
+    }
+  }
+  if(n != 0 || seen != 7){
+    printf("%s: getdents_plus missed entries\n", s);
+    exit(1);
+  }
+  close(dfd);
+  if(unlink("gdpdir/a") < 0 || unlink("gdpdir/c") < 0 || unlink("gdpdir") < 0){
+    printf("%s: unlink gdpdir failed\n", s);
+    exit(1);
+  }
+}
+
+// fadvise() changes how much is read ahead of sequential reads, reads
+// a range in advance, and drops a range from the caches, none of
+// which changes what the file reads.
+void
+fadvisetest(char *s)
+{
+  enum { N=12 };
+  int advice[] = { POSIX_FADV_SEQUENTIAL, POSIX_FADV_RANDOM, POSIX_FADV_NORMAL };
+  int fd, fds[2], i, j, n;
+  char c;
+
+  fd = open("fadvise", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create fadvise failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    memset(buf, 'a' + i, BSIZE);
+    if(write(fd, buf, BSIZE) != BSIZE){
+      printf("%s: write fadvise failed\n", s);
+      exit(1);
+    }
+  }
+  if(fadvise(fd, 0, 0, 5) != -EINVAL || fadvise(fd, -1, 0, POSIX_FADV_NORMAL) != -EINVAL){
+    printf("%s: invalid fadvise succeeded\n", s);
+    exit(1);
+  }
+  if(pipe(fds) < 0 || fadvise(fds[0], 0, 0, POSIX_FADV_NORMAL) != -ESPIPE){
+    printf("%s: fadvise of a pipe did not fail\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+
+  for(j = 0; j < 3; j++){
+    if(fadvise(fd, 0, 0, advice[j]) < 0 || lseek(fd, 0, SEEK_SET) != 0){
+      printf("%s: fadvise %d failed\n", s, advice[j]);
+      exit(1);
+    }
+    // Reads that do not line up with the blocks.
+    for(i = 0; (n = read(fd, buf, 100)) > 0; i += n){
+      if(buf[0] != 'a' + i / BSIZE || buf[n - 1] != 'a' + (i + n - 1) / BSIZE){
+        printf("%s: read at %d saw wrong data after fadvise %d\n", s, i, advice[j]);
+        exit(1);
+      }
+    }
+    if(n != 0 || i != N*BSIZE){
+      printf("%s: read %d bytes after fadvise %d\n", s, i, advice[j]);
+      exit(1);
+    }
+  }
+
+  // A write that the log still holds is not lost by dropping it.
+  if(fadvise(fd, BSIZE, 2*BSIZE, POSIX_FADV_WILLNEED) < 0 ||
+     pwrite(fd, "z", 1, 3*BSIZE / 2) != 1 ||
+     fadvise(fd, 0, 0, POSIX_FADV_DONTNEED) < 0){
+    printf("%s: fadvise of a range failed\n", s);
+    exit(1);
+  }
+  if(pread(fd, &c, 1, 3*BSIZE / 2) != 1 || c != 'z' || pread(fd, &c, 1, BSIZE) != 1 || c != 'b'){
+    printf("%s: read after POSIX_FADV_DONTNEED saw wrong data\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("fadvise");
+}
+
+// The kernel provides /dev/console, /dev/tty, /dev/null and /dev/zero
+// in memory, without mknod(), and they cannot be changed.
+void
+devfstest(char *s)
+{
+  char *names[] = { "/dev/console", "/dev/tty", "/dev/null", "/dev/zero" };
+  struct stat st;
+  struct dirent de;
+  int fd, i, found;
+
+  for(i = 0; i < 4; i++){
+    if(stat(names[i], &st) < 0 || st.type != T_DEVICE){
+      printf("%s: no device %s\n", s, names[i]);
+      exit(1);
+    }
+  }
+
+  fd = open("/dev/null", O_RDWR);
+  if(fd < 0 || write(fd, "hello", 5) != 5 || read(fd, buf, sizeof(buf)) != 0){
+    printf("%s: /dev/null is not empty\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("/dev/zero", O_RDWR);
+  memset(buf, 'x', 1000);
+  if(fd < 0 || read(fd, buf, 1000) != 1000 || write(fd, "hello", 5) != 5){
+    printf("%s: /dev/zero read or write failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 1000; i++){
+    if(buf[i] != 0){
+      printf("%s: /dev/zero read %d at %d\n", s, buf[i], i);
+      exit(1);
+    }
+  }
+  close(fd);
+
+  fd = open("/dev", O_RDONLY);
+  found = 0;
+  while(fd >= 0 && read(fd, &de, sizeof(de)) == sizeof(de)){
+    if(de.inum != 0 && strcmp(de.name, "null") == 0)
+      found = 1;
+  }
+  if(!found){
+    printf("%s: /dev does not list null\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(open("/dev/foo", O_CREATE|O_RDWR) >= 0 || mkdir("/dev/foo") == 0 ||
+     unlink("/dev/null") == 0 || link("/dev/null", "/dev/foo") == 0 ||
+     rename("/dev/null", "/dev/foo") == 0 || mkdir("/dev") == 0){
+    printf("%s: changed /dev\n", s);
+    exit(1);
+  }
+}
+
+// devfs moves from /dev to another mount point, and back.
+void
+mounttest(char *s)
+{
+  struct stat st;
+  int pid, xstatus;
+
+  if(mount("devfs", "/mnt") != -EBUSY || mount("nofs", "/mnt") != -ENODEV ||
+     umount("/mnt") != -EINVAL || mount("devfs", "/dev/mnt") != -EINVAL){
+    printf("%s: mount of a mounted or unknown file system succeeded\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(1) < 0 || umount("/dev") != -EPERM || mount("devfs", "/mnt") != -EPERM)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: an ordinary user mounted or unmounted\n", s);
+    exit(1);
+  }
+
+  if(umount("/dev") != 0 || stat("/dev/null", &st) == 0){
+    printf("%s: umount /dev failed\n", s);
+    exit(1);
+  }
+  if(mount("devfs", "/mnt") != 0 || stat("/mnt/null", &st) < 0 || st.type != T_DEVICE ||
+     stat("/mnt/..", &st) < 0 || st.ino != ROOTINO){
+    printf("%s: devfs is not at /mnt\n", s);
+    exit(1);
+  }
+  if(unlink("/mnt") == 0 || rename("/mnt", "/mnt2") == 0 || mkdir("/mnt") == 0){
+    printf("%s: changed the mount point\n", s);
+    exit(1);
+  }
+  if(umount("/mnt") != 0 || mount("devfs", "/dev") != 0 || stat("/dev/null", &st) < 0){
+    printf("%s: mount /dev failed\n", s);
+    exit(1);
+  }
+}
+
+// Writes "/proc/<pid>" followed by file into path.
+static void
+procpath(char *path, int pid, char *file)
+{
+  char num[16];
+  int i;
+
+  i = sizeof(num) - 1;
+  num[i] = 0;
+  do {
+    num[--i] = '0' + pid % 10;
+    pid /= 10;
+  } while(pid > 0);
+  strcpy(path, "/proc/");
+  strcpy(path + strlen(path), num + i);
+  strcpy(path + strlen(path), file);
+}
+
+// /proc shows the status of the processes and the kernel counters, and nothing is written to it.
+void
+proctest(char *s)
+{
+  char path[32], want[64];
+  struct stat st;
+  int fd, n, pid, xstatus;
+
+  pid = getpid();
+  procpath(path, pid, "");
+  if(stat(path, &st) < 0 || st.type != T_DIR){
+    printf("%s: no directory %s\n", s, path);
+    exit(1);
+  }
+
+  // "pid <pid>\nstate run\nname usertests\n", since we are running, and then the pages.
+  strcpy(want, "pid ");
+  strcpy(want + strlen(want), path + strlen("/proc/"));
+  strcpy(want + strlen(want), "\nstate run\nname usertests\npages ");
+  procpath(path, pid, "/status");
+  fd = open(path, O_RDONLY);
+  n = fd < 0 ? -1 : read(fd, buf, sizeof(buf));
+  if(n < (int)strlen(want) || memcmp(buf, want, strlen(want)) != 0){
+    printf("%s: %s does not show the process\n", s, path);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("/proc/stat", O_RDONLY);
+  n = fd < 0 ? -1 : read(fd, buf, sizeof(buf));
+  if(n < 7 || memcmp(buf, "uptime ", 7) != 0){
+    printf("%s: /proc/stat does not show the uptime\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(open(path, O_RDWR) != -EROFS || open("/proc/stat", O_WRONLY|O_TRUNC) != -EROFS ||
+     chmod("/proc/stat", 0777) != -EROFS || mkdir("/proc/foo") == 0 || unlink(path) == 0){
+    printf("%s: changed /proc\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(0);
+  wait(&xstatus);
+  procpath(path, pid, "/status");
+  if(stat(path, &st) == 0){
+    printf("%s: %s outlives the process\n", s, path);
+    exit(1);
+  }
+}
+
 // simple fork and pipe read/write
 
 void
@@ -799,7 +3933,7 @@ killstatus(char *s)
       exit(0);
     }
     sleep(1);
-    kill(pid1);
+    kill(pid1, SIGKILL);
     wait(&xst);
     if(xst != -1) {
        printf("%s: status should be -1\n", s);
@@ -856,9 +3990,9 @@ preempt(char *s)
   }
   close(pfds[0]);
   printf("kill... ");
-  kill(pid1);
-  kill(pid2);
-  kill(pid3);
+  kill(pid1, SIGKILL);
+  kill(pid2, SIGKILL);
+  kill(pid3, SIGKILL);
   printf("wait... ");
   wait(0);
   wait(0);
@@ -914,7 +4048,7 @@ reparent(char *s)
     } else {
       int pid2 = fork();
       if(pid2 < 0){
-        kill(master_pid);
+        kill(master_pid, SIGKILL);
         exit(1);
       }
       exit(0);
@@ -2263,7 +5397,7 @@ sbrkfail(char *s)
   for(i = 0; i < sizeof(pids)/sizeof(pids[0]); i++){
     if(pids[i] == -1)
       continue;
-    kill(pids[i]);
+    kill(pids[i], SIGKILL);
     wait(0);
   }
   if(c == (char*)0xffffffffffffffffL){
@@ -2751,6 +5885,7 @@ main(int argc, char *argv[])
   int continuous = 0;
   char *justone = 0;
 
+  mainargv = argv;
   if(argc == 2 && strcmp(argv[1], "-c") == 0){
     continuous = 1;
   } else if(argc == 2 && strcmp(argv[1], "-C") == 0){
@@ -2797,6 +5932,44 @@ main(int argc, char *argv[])
     {sharedfd, "sharedfd"},
     {dirtest, "dirtest"},
     {exectest, "exectest"},
+    {spawntest, "spawntest"},
+    {pidfdtest, "pidfdtest"},
+    {fsresizetest, "fsresizetest"},
+    {inodegentest, "inodegentest"},
+    {sysinfotest, "sysinfotest"},
+    {auxvtest, "auxvtest"},
+    {getrandomtest, "getrandomtest"},
+    {latencytest, "latencytest"},
+    {mmaptest, "mmaptest"},
+    {benchtest, "benchtest"},
+    {mmapfiletest, "mmapfiletest"},
+    {taskdumptest, "taskdumptest"},
+    {tracetest, "tracetest"},
+    {signaltest, "signaltest"},
+    {fcounttest, "fcounttest"},
+    {killinittest, "killinittest"},
+    {clonetest, "clonetest"},
+    {errnotest, "errnotest"},
+    {irqofftest, "irqofftest"},
+    {brktest, "brktest"},
+    {waitpidtest, "waitpidtest"},
+    {pgrptest, "pgrptest"},
+    {setsidtest, "setsidtest"},
+    {getppidtest, "getppidtest"},
+    {madvisetest, "madvisetest"},
+    {meminfotest, "meminfotest"},
+    {nanosleeptest, "nanosleeptest"},
+    {gettimeofdaytest, "gettimeofdaytest"},
+    {alarmtest, "alarmtest"},
+    {affinitytest, "affinitytest"},
+    {pagemaptest, "pagemaptest"},
+    {renametest, "renametest"},
+    {lseektest, "lseektest"},
+    {dup2test, "dup2test"},
+    {preadtest, "preadtest"},
+    {ftruncatetest, "ftruncatetest"},
+    {futextest, "futextest"},
+    {fcntltest, "fcntltest"},
     {bigargtest, "bigargtest"},
     {bigwrite, "bigwrite"},
     {bsstest, "bsstest"},
@@ -2810,6 +5983,22 @@ main(int argc, char *argv[])
     {opentest, "opentest"},
     {writetest, "writetest"},
     {writebig, "writebig"},
+    {hugefile, "hugefile"},
+    {extenttest, "extenttest"},
+    {dirhashtest, "dirhashtest"},
+    {linuxabitest, "linuxabitest"},
+    {timestest, "timestest"},
+    {zerocopytest, "zerocopytest"},
+    {permtest, "permtest"},
+    {coalescetest, "coalescetest"},
+    {fsynctest, "fsynctest"},
+    {directtest, "directtest"},
+    {fswatchtest, "fswatchtest"},
+    {getdentstest, "getdentstest"},
+    {fadvisetest, "fadvisetest"},
+    {devfstest, "devfstest"},
+    {mounttest, "mounttest"},
+    {proctest, "proctest"},
     {createtest, "createtest"},
     {openiputtest, "openiput"},
     {exitiputtest, "exitiput"},
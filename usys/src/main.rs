@@ -0,0 +1,52 @@
+//! Generates the system call stubs of the user programs (`user/usys.S`) and the system call
+//! numbers of C programs (`kernel/syscall.h`) from `rv6_abi::syscall::SYSCALLS`, the table that
+//! the kernel dispatches with. Hence, adding a system call to the table is enough for user
+//! programs to call it, and the numbers cannot disagree.
+
+use std::{collections::HashSet, env, process};
+
+use rv6_abi::syscall::SYSCALLS;
+
+fn usage() -> ! {
+    eprintln!("Usage: usys asm|header");
+    process::exit(1);
+}
+
+/// Returns the name of the system call whose constant is `constant`, e.g., `fork` for
+/// `SYS_FORK`. The user-space stub of the system call has the same name.
+fn name(constant: &str) -> String {
+    constant.trim_start_matches("SYS_").to_lowercase()
+}
+
+fn main() {
+    let mut numbers = HashSet::new();
+    for (constant, number) in SYSCALLS {
+        if !numbers.insert(number) {
+            eprintln!("usys: {} reuses system call number {}", constant, number);
+            process::exit(1);
+        }
+    }
+
+    match env::args().nth(1).as_deref() {
+        Some("asm") => {
+            println!("# generated by usys from rv6-abi - do not edit");
+            println!("#include \"kernel/syscall.h\"");
+            for (constant, _) in SYSCALLS {
+                let name = name(constant);
+                println!(".global {}", name);
+                println!("{}:", name);
+                println!(" li a7, SYS_{}", name);
+                println!(" ecall");
+                println!(" j __syscall_ret");
+            }
+        }
+        Some("header") => {
+            println!("// generated by usys from rv6-abi - do not edit");
+            println!("// System call numbers.");
+            for (constant, number) in SYSCALLS {
+                println!("#define SYS_{} {}", name(constant), number);
+            }
+        }
+        _ => usage(),
+    }
+}